@@ -32,10 +32,14 @@ use webauthn_rs::proto::{
 };
 // use users::{get_current_uid, get_effective_uid};
 
+use kanidm_proto::oauth2::Jwk;
 use kanidm_proto::v1::{
-    AccountUnixExtend, AuthAllowed, AuthCredential, AuthMech, AuthRequest, AuthResponse, AuthState,
-    AuthStep, CreateRequest, DeleteRequest, Entry, Filter, GroupUnixExtend, ModifyList,
-    ModifyRequest, OperationError, OperationResponse, RadiusAuthToken, SearchRequest,
+    AccountUnixExtend, ApiToken, ApiTokenGenerate, ApiTokenPurpose, AppPassword,
+    AppPasswordGenerate, AppPasswordScope, AuthAllowed, AuthAuditQuery, AuthAuditRecord,
+    AuthCredential, AuthMech, AuthRequest, AuthResponse, AuthState, AuthStep, CreateRequest,
+    CredentialResetIntent, CredentialResetIntentRequest, CredentialResetRequest,
+    CredentialResetSession, CredentialSoftLock, DeleteRequest, Entry, Filter, GroupUnixExtend,
+    ModifyList, ModifyRequest, OperationError, OperationResponse, RadiusAuthToken, SearchRequest,
     SearchResponse, SetCredentialRequest, SetCredentialResponse, SingleStringRequest, TOTPSecret,
     UnixGroupToken, UnixUserToken, UserAuthToken, WhoamiResponse,
 };
@@ -66,6 +70,9 @@ struct KanidmClientConfig {
     verify_ca: Option<bool>,
     verify_hostnames: Option<bool>,
     ca_path: Option<String>,
+    // A pkcs12 bundle of a client certificate and its key, presented to the server.
+    client_identity_path: Option<String>,
+    client_identity_password: Option<String>,
     // Should we add username/pw later? They could be part of the builder
     // process ...
 }
@@ -76,6 +83,7 @@ pub struct KanidmClientBuilder {
     verify_ca: bool,
     verify_hostnames: bool,
     ca: Option<reqwest::Certificate>,
+    identity: Option<reqwest::Identity>,
     connect_timeout: Option<u64>,
 }
 
@@ -98,6 +106,7 @@ impl KanidmClientBuilder {
             verify_ca: true,
             verify_hostnames: true,
             ca: None,
+            identity: None,
             connect_timeout: None,
         }
     }
@@ -125,12 +134,33 @@ impl KanidmClientBuilder {
         reqwest::Certificate::from_pem(&buf).map_err(|_| ())
     }
 
+    fn parse_identity(identity_path: &str, password: &str) -> Result<reqwest::Identity, ()> {
+        let mut buf = Vec::new();
+        // The bundle holds a private key, so it must not be readable by others.
+        let path = Path::new(identity_path);
+        let identity_meta = read_file_metadata(&path)?;
+
+        if identity_meta.mode() & 0o007 != 0 {
+            warn!(
+                "{} has 'everyone' permission bits in the mode. This could be a security risk ...",
+                identity_path
+            );
+        }
+
+        let mut f = File::open(identity_path).map_err(|_| ())?;
+        f.read_to_end(&mut buf).map_err(|_| ())?;
+        reqwest::Identity::from_pkcs12_der(&buf, password).map_err(|e| {
+            error!("Unable to read client identity {} - {:?}", identity_path, e);
+        })
+    }
+
     fn apply_config_options(self, kcc: KanidmClientConfig) -> Result<Self, ()> {
         let KanidmClientBuilder {
             address,
             verify_ca,
             verify_hostnames,
             ca,
+            identity,
             connect_timeout,
         } = self;
         // Process and apply all our options if they exist.
@@ -144,12 +174,20 @@ impl KanidmClientBuilder {
             Some(ca_path) => Some(Self::parse_certificate(ca_path.as_str())?),
             None => ca,
         };
+        let identity = match kcc.client_identity_path {
+            Some(identity_path) => Some(Self::parse_identity(
+                identity_path.as_str(),
+                kcc.client_identity_password.as_deref().unwrap_or(""),
+            )?),
+            None => identity,
+        };
 
         Ok(KanidmClientBuilder {
             address,
             verify_ca,
             verify_hostnames,
             ca,
+            identity,
             connect_timeout,
         })
     }
@@ -183,6 +221,7 @@ impl KanidmClientBuilder {
             verify_ca: self.verify_ca,
             verify_hostnames: self.verify_hostnames,
             ca: self.ca,
+            identity: self.identity,
             connect_timeout: self.connect_timeout,
         }
     }
@@ -194,6 +233,7 @@ impl KanidmClientBuilder {
             // We have to flip the bool state here due to english language.
             verify_hostnames: !accept_invalid_hostnames,
             ca: self.ca,
+            identity: self.identity,
             connect_timeout: self.connect_timeout,
         }
     }
@@ -205,6 +245,7 @@ impl KanidmClientBuilder {
             verify_ca: !accept_invalid_certs,
             verify_hostnames: self.verify_hostnames,
             ca: self.ca,
+            identity: self.identity,
             connect_timeout: self.connect_timeout,
         }
     }
@@ -215,6 +256,7 @@ impl KanidmClientBuilder {
             verify_ca: self.verify_ca,
             verify_hostnames: self.verify_hostnames,
             ca: self.ca,
+            identity: self.identity,
             connect_timeout: Some(secs),
         }
     }
@@ -228,6 +270,26 @@ impl KanidmClientBuilder {
             verify_ca: self.verify_ca,
             verify_hostnames: self.verify_hostnames,
             ca: Some(ca),
+            identity: self.identity,
+            connect_timeout: self.connect_timeout,
+        })
+    }
+
+    /// Present the certificate of a pkcs12 bundle to the server, so that the account it's
+    /// bound to can authenticate with it.
+    pub fn add_client_identity_filepath(
+        self,
+        identity_path: &str,
+        password: &str,
+    ) -> Result<Self, ()> {
+        let identity = Self::parse_identity(identity_path, password)?;
+
+        Ok(KanidmClientBuilder {
+            address: self.address,
+            verify_ca: self.verify_ca,
+            verify_hostnames: self.verify_hostnames,
+            ca: self.ca,
+            identity: Some(identity),
             connect_timeout: self.connect_timeout,
         })
     }
@@ -272,6 +334,11 @@ impl KanidmClientBuilder {
             None => client_builder,
         };
 
+        let client_builder = match &self.identity {
+            Some(identity) => client_builder.identity(identity.clone()),
+            None => client_builder,
+        };
+
         let client_builder = match &self.connect_timeout {
             Some(secs) => client_builder
                 .connect_timeout(Duration::from_secs(*secs))
@@ -322,6 +389,11 @@ impl KanidmClientBuilder {
             None => client_builder,
         };
 
+        let client_builder = match &self.identity {
+            Some(identity) => client_builder.identity(identity.clone()),
+            None => client_builder,
+        };
+
         let client_builder = match &self.connect_timeout {
             Some(secs) => client_builder
                 .connect_timeout(Duration::from_secs(*secs))
@@ -557,6 +629,12 @@ impl KanidmClient {
         Ok(Some((r.youare, r.uat)))
     }
 
+    /// Get the public key that the server signs user auth tokens with. This can be
+    /// used to build a `JwsValidator` to verify tokens offline.
+    pub fn get_public_jwk(&self) -> Result<Jwk, ClientError> {
+        self.perform_get_request("/v1/jwk")
+    }
+
     // auth
     pub fn auth_step_anonymous(&mut self) -> Result<AuthResponse, ClientError> {
         let auth_anon = AuthRequest {
@@ -586,6 +664,23 @@ impl KanidmClient {
         })
     }
 
+    pub fn auth_step_password_change(
+        &mut self,
+        password: &str,
+    ) -> Result<AuthResponse, ClientError> {
+        let auth_req = AuthRequest {
+            step: AuthStep::Cred(AuthCredential::PasswordChange(password.to_string())),
+        };
+        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_req);
+
+        r.map(|ar| {
+            if let AuthState::Success(token) = &ar.state {
+                self.bearer_token = Some(token.clone());
+            };
+            ar
+        })
+    }
+
     pub fn auth_step_totp(&mut self, totp: u32) -> Result<AuthResponse, ClientError> {
         let auth_req = AuthRequest {
             step: AuthStep::Cred(AuthCredential::TOTP(totp)),
@@ -600,6 +695,34 @@ impl KanidmClient {
         })
     }
 
+    pub fn auth_step_backup_code(&mut self, code: &str) -> Result<AuthResponse, ClientError> {
+        let auth_req = AuthRequest {
+            step: AuthStep::Cred(AuthCredential::BackupCode(code.to_string())),
+        };
+        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_req);
+
+        r.map(|ar| {
+            if let AuthState::Success(token) = &ar.state {
+                self.bearer_token = Some(token.clone());
+            };
+            ar
+        })
+    }
+
+    pub fn auth_step_client_certificate(&mut self) -> Result<AuthResponse, ClientError> {
+        let auth_req = AuthRequest {
+            step: AuthStep::Cred(AuthCredential::ClientCertificate),
+        };
+        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_req);
+
+        r.map(|ar| {
+            if let AuthState::Success(token) = &ar.state {
+                self.bearer_token = Some(token.clone());
+            };
+            ar
+        })
+    }
+
     pub fn auth_step_webauthn_complete(
         &mut self,
         pkc: PublicKeyCredential,
@@ -641,6 +764,30 @@ impl KanidmClient {
         }
     }
 
+    pub fn auth_client_certificate(&mut self, ident: &str) -> Result<(), ClientError> {
+        let mechs = match self.auth_step_init(ident) {
+            Ok(s) => s,
+            Err(e) => return Err(e),
+        };
+
+        if !mechs.contains(&AuthMech::ClientCertificate) {
+            debug!("ClientCertificate mech not presented");
+            return Err(ClientError::AuthenticationFailed);
+        }
+
+        let _state = match self.auth_step_begin(AuthMech::ClientCertificate) {
+            Ok(s) => s,
+            Err(e) => return Err(e),
+        };
+
+        let r = self.auth_step_client_certificate()?;
+
+        match r.state {
+            AuthState::Success(_token) => Ok(()),
+            _ => Err(ClientError::AuthenticationFailed),
+        }
+    }
+
     pub fn auth_simple_password(&mut self, ident: &str, password: &str) -> Result<(), ClientError> {
         let mechs = match self.auth_step_init(ident) {
             Ok(s) => s,
@@ -741,6 +888,27 @@ impl KanidmClient {
         }
     }
 
+    /// Begin a webauthn authentication without a name. The account is determined by
+    /// the discoverable credential on the token, and the session is completed with
+    /// `auth_webauthn_complete`.
+    pub fn auth_webauthn_discoverable_begin(
+        &mut self,
+    ) -> Result<RequestChallengeResponse, ClientError> {
+        let mechs = self.auth_step_init_discoverable()?;
+
+        if !mechs.contains(&AuthMech::WebauthnDiscoverable) {
+            debug!("Discoverable webauthn mech not presented");
+            return Err(ClientError::AuthenticationFailed);
+        }
+
+        let mut state = self.auth_step_begin(AuthMech::WebauthnDiscoverable)?;
+
+        match state.pop() {
+            Some(AuthAllowed::Webauthn(r)) => Ok(r),
+            _ => Err(ClientError::AuthenticationFailed),
+        }
+    }
+
     pub fn auth_webauthn_complete(&mut self, pkc: PublicKeyCredential) -> Result<(), ClientError> {
         let r = self.auth_step_webauthn_complete(pkc)?;
         match r.state {
@@ -786,6 +954,15 @@ impl KanidmClient {
         r.map(|_| true)
     }
 
+    /// Request a short lived OpenSSH certificate for one of the ssh public keys of the
+    /// current account. The certificate expires with the current session.
+    pub fn idm_account_ssh_sign(&self, publickey: &str) -> Result<String, ClientError> {
+        let s = SingleStringRequest {
+            value: publickey.to_string(),
+        };
+        self.perform_post_request("/v1/self/_ssh_cert", s)
+    }
+
     pub fn auth_step_init(&self, ident: &str) -> Result<Set<AuthMech>, ClientError> {
         let auth_init = AuthRequest {
             step: AuthStep::Init(ident.to_string()),
@@ -803,6 +980,23 @@ impl KanidmClient {
         .map(|mechs| mechs.into_iter().collect())
     }
 
+    pub fn auth_step_init_discoverable(&self) -> Result<Set<AuthMech>, ClientError> {
+        let auth_init = AuthRequest {
+            step: AuthStep::InitDiscoverable,
+        };
+
+        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_init);
+        r.map(|v| {
+            debug!("Authentication Session ID -> {:?}", v.sessionid);
+            v.state
+        })
+        .and_then(|state| match state {
+            AuthState::Choose(mechs) => Ok(mechs),
+            _ => Err(ClientError::AuthenticationFailed),
+        })
+        .map(|mechs| mechs.into_iter().collect())
+    }
+
     pub fn auth_step_begin(&self, mech: AuthMech) -> Result<Vec<AuthAllowed>, ClientError> {
         let auth_begin = AuthRequest {
             step: AuthStep::Begin(mech),
@@ -1020,8 +1214,9 @@ impl KanidmClient {
     pub fn idm_account_primary_credential_remove_totp(
         &self,
         id: &str,
+        label: &str,
     ) -> Result<bool, ClientError> {
-        let r = SetCredentialRequest::TOTPRemove;
+        let r = SetCredentialRequest::TOTPRemove(label.to_string());
         let res: Result<SetCredentialResponse, ClientError> = self.perform_put_request(
             format!("/v1/account/{}/_credential/primary", id).as_str(),
             r,
@@ -1033,6 +1228,44 @@ impl KanidmClient {
         }
     }
 
+    /// Add an existing TOTP secret to the account, such as one moved from another
+    /// system. No verification step is needed.
+    pub fn idm_account_primary_credential_import_totp(
+        &self,
+        id: &str,
+        label: &str,
+        secret: TOTPSecret,
+    ) -> Result<bool, ClientError> {
+        let r = SetCredentialRequest::TOTPImport(label.to_string(), secret);
+        let res: Result<SetCredentialResponse, ClientError> = self.perform_put_request(
+            format!("/v1/account/{}/_credential/primary", id).as_str(),
+            r,
+        );
+        match res {
+            Ok(SetCredentialResponse::Success) => Ok(true),
+            Ok(_) => Err(ClientError::EmptyResponse),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replace the backup codes of the account. The returned codes are only available
+    /// at this point, and each may be used once in place of a TOTP or webauthn token.
+    pub fn idm_account_primary_credential_generate_backup_codes(
+        &self,
+        id: &str,
+    ) -> Result<Vec<String>, ClientError> {
+        let r = SetCredentialRequest::BackupCodeGenerate;
+        let res: Result<SetCredentialResponse, ClientError> = self.perform_put_request(
+            format!("/v1/account/{}/_credential/primary", id).as_str(),
+            r,
+        );
+        match res {
+            Ok(SetCredentialResponse::BackupCodes(codes)) => Ok(codes),
+            Ok(_) => Err(ClientError::EmptyResponse),
+            Err(e) => Err(e),
+        }
+    }
+
     pub fn idm_account_primary_credential_register_webauthn(
         &self,
         id: &str,
@@ -1107,6 +1340,120 @@ impl KanidmClient {
         self.perform_get_request(format!("/v1/account/{}/_radius/_token", id).as_str())
     }
 
+    pub fn idm_account_radius_cred_verify(
+        &self,
+        id: &str,
+        cred: &str,
+    ) -> Result<Option<RadiusAuthToken>, ClientError> {
+        let req = SingleStringRequest {
+            value: cred.to_string(),
+        };
+        self.perform_post_request(format!("/v1/account/{}/_radius/_auth", id).as_str(), req)
+    }
+
+    pub fn idm_account_app_password_list(&self, id: &str) -> Result<Vec<AppPassword>, ClientError> {
+        self.perform_get_request(format!("/v1/account/{}/_app_password", id).as_str())
+    }
+
+    /// Generate a new app password for the account. The returned password is only
+    /// available at this point, and is accepted only within the requested scope.
+    pub fn idm_account_app_password_generate(
+        &self,
+        id: &str,
+        label: &str,
+        scope: AppPasswordScope,
+    ) -> Result<String, ClientError> {
+        let gen = AppPasswordGenerate {
+            label: label.to_string(),
+            scope,
+        };
+        self.perform_post_request(format!("/v1/account/{}/_app_password", id).as_str(), gen)
+    }
+
+    pub fn idm_account_app_password_remove(
+        &self,
+        id: &str,
+        label: &str,
+    ) -> Result<bool, ClientError> {
+        self.perform_delete_request(format!("/v1/account/{}/_app_password/{}", id, label).as_str())
+    }
+
+    /// List the credentials of the account that are currently softlocked after
+    /// repeated authentication failures.
+    pub fn idm_account_softlock_status(
+        &self,
+        id: &str,
+    ) -> Result<Vec<CredentialSoftLock>, ClientError> {
+        self.perform_get_request(format!("/v1/account/{}/_lock", id).as_str())
+    }
+
+    pub fn idm_account_softlock_clear(&self, id: &str) -> Result<bool, ClientError> {
+        self.perform_delete_request(format!("/v1/account/{}/_lock", id).as_str())
+    }
+
+    /// Search the stored authentication attempts, newest first.
+    pub fn idm_auth_audit_search(
+        &self,
+        query: &AuthAuditQuery,
+    ) -> Result<Vec<AuthAuditRecord>, ClientError> {
+        let mut qs = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(account) = &query.account {
+            qs.append_pair("account", account);
+        }
+        if let Some(success) = query.success {
+            qs.append_pair("success", if success { "true" } else { "false" });
+        }
+        if let Some(since) = &query.since {
+            qs.append_pair("since", since);
+        }
+        if let Some(until) = &query.until {
+            qs.append_pair("until", until);
+        }
+        if let Some(limit) = query.limit {
+            qs.append_pair("limit", limit.to_string().as_str());
+        }
+        self.perform_get_request(format!("/v1/audit/auth?{}", qs.finish()).as_str())
+    }
+
+    /// Issue a single use credential reset token for the account. If no ttl is
+    /// provided the server default is used.
+    pub fn idm_account_credential_reset_intent(
+        &self,
+        id: &str,
+        ttl: Option<u64>,
+    ) -> Result<CredentialResetIntent, ClientError> {
+        let r = CredentialResetIntentRequest { ttl };
+        self.perform_post_request(
+            format!("/v1/account/{}/_credential/_reset_intent", id).as_str(),
+            r,
+        )
+    }
+
+    /// Redeem a credential reset token. This does not require authentication, and the
+    /// token can not be redeemed again after this call.
+    pub fn idm_credential_reset_begin(
+        &self,
+        token: &str,
+    ) -> Result<CredentialResetSession, ClientError> {
+        let r = SingleStringRequest {
+            value: token.to_string(),
+        };
+        self.perform_post_request("/v1/credential/_reset", r)
+    }
+
+    pub fn idm_credential_reset_step(
+        &self,
+        sessionid: Uuid,
+        token: &str,
+        sac: SetCredentialRequest,
+    ) -> Result<SetCredentialResponse, ClientError> {
+        let r = CredentialResetRequest {
+            token: token.to_string(),
+            sac,
+        };
+        self.perform_post_request(format!("/v1/credential/_reset/{}", sessionid).as_str(), r)
+    }
+
     pub fn idm_account_unix_extend(
         &self,
         id: &str,
@@ -1185,6 +1532,118 @@ impl KanidmClient {
         self.perform_delete_request(format!("/v1/account/{}/_ssh_pubkeys/{}", id, tag).as_str())
     }
 
+    // ==== oauth2 resource server configuration
+    pub fn idm_oauth2_rs_list(&self) -> Result<Vec<Entry>, ClientError> {
+        self.perform_get_request("/v1/oauth2")
+    }
+
+    pub fn idm_oauth2_rs_get(&self, id: &str) -> Result<Option<Entry>, ClientError> {
+        self.perform_get_request(format!("/v1/oauth2/{}", id).as_str())
+    }
+
+    pub fn idm_oauth2_rs_basic_create(
+        &self,
+        name: &str,
+        displayname: &str,
+        origin: &str,
+        redirect_uri: &str,
+    ) -> Result<bool, ClientError> {
+        let mut new_oauth2_rs = Entry {
+            attrs: BTreeMap::new(),
+        };
+        new_oauth2_rs
+            .attrs
+            .insert("oauth2_rs_name".to_string(), vec![name.to_string()]);
+        new_oauth2_rs
+            .attrs
+            .insert("displayname".to_string(), vec![displayname.to_string()]);
+        new_oauth2_rs
+            .attrs
+            .insert("oauth2_rs_origin".to_string(), vec![origin.to_string()]);
+        new_oauth2_rs.attrs.insert(
+            "oauth2_rs_redirect_uri".to_string(),
+            vec![redirect_uri.to_string()],
+        );
+        self.perform_post_request("/v1/oauth2/_basic", new_oauth2_rs)
+            .map(|_: OperationResponse| true)
+    }
+
+    pub fn idm_oauth2_rs_delete(&self, id: &str) -> Result<bool, ClientError> {
+        self.perform_delete_request(format!("/v1/oauth2/{}", id).as_str())
+    }
+
+    // ==== service accounts
+    pub fn idm_service_account_list(&self) -> Result<Vec<Entry>, ClientError> {
+        self.perform_get_request("/v1/service_account")
+    }
+
+    pub fn idm_service_account_get(&self, id: &str) -> Result<Option<Entry>, ClientError> {
+        self.perform_get_request(format!("/v1/service_account/{}", id).as_str())
+    }
+
+    pub fn idm_service_account_create(
+        &self,
+        name: &str,
+        displayname: &str,
+    ) -> Result<bool, ClientError> {
+        let mut new_acct = Entry {
+            attrs: BTreeMap::new(),
+        };
+        new_acct
+            .attrs
+            .insert("name".to_string(), vec![name.to_string()]);
+        new_acct
+            .attrs
+            .insert("displayname".to_string(), vec![displayname.to_string()]);
+        self.perform_post_request("/v1/service_account", new_acct)
+            .map(|_: OperationResponse| true)
+    }
+
+    pub fn idm_service_account_delete(&self, id: &str) -> Result<bool, ClientError> {
+        self.perform_delete_request(format!("/v1/service_account/{}", id).as_str())
+    }
+
+    pub fn idm_service_account_api_token_list(
+        &self,
+        id: &str,
+    ) -> Result<Vec<ApiToken>, ClientError> {
+        self.perform_get_request(format!("/v1/service_account/{}/_api_token", id).as_str())
+    }
+
+    /// Generate a new api token for the service account. The returned token is only
+    /// available at this point, and can be used with `set_token`.
+    pub fn idm_service_account_api_token_generate(
+        &self,
+        id: &str,
+        label: &str,
+        expiry: Option<&str>,
+        read_only: bool,
+    ) -> Result<String, ClientError> {
+        let gen = ApiTokenGenerate {
+            label: label.to_string(),
+            expiry: expiry.map(str::to_string),
+            purpose: if read_only {
+                ApiTokenPurpose::ReadOnly
+            } else {
+                ApiTokenPurpose::ReadWrite
+            },
+        };
+        self.perform_post_request(
+            format!("/v1/service_account/{}/_api_token", id).as_str(),
+            gen,
+        )
+    }
+
+    pub fn idm_service_account_api_token_destroy(
+        &self,
+        id: &str,
+        token_id: &str,
+    ) -> Result<bool, ClientError> {
+        self.perform_delete_request(
+            format!("/v1/service_account/{}/_api_token/{}", id, token_id).as_str(),
+        )
+    }
+
     // ==== domain_info (aka domain)
     pub fn idm_domain_list(&self) -> Result<Vec<Entry>, ClientError> {
         self.perform_get_request("/v1/domain")
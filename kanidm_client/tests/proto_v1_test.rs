@@ -1,11 +1,15 @@
 #![deny(warnings)]
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use log::debug;
 
 use kanidm::credential::totp::TOTP;
 use kanidm_client::KanidmClient;
-use kanidm_proto::v1::{Entry, Filter, Modify, ModifyList};
+use kanidm_proto::jws::{JwsError, JwsValidator};
+use kanidm_proto::v1::{
+    AppPasswordScope, Entry, Filter, Modify, ModifyList, SetCredentialRequest,
+    SetCredentialResponse,
+};
 
 mod common;
 use crate::common::{run_test, ADMIN_TEST_PASSWORD};
@@ -108,6 +112,32 @@ fn test_server_whoami_admin_simple_password() {
     });
 }
 
+#[test]
+fn test_server_uat_offline_validation() {
+    run_test(|mut rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // Verify the token we were issued with only the published public key.
+        let jwk = rsclient.get_public_jwk().unwrap();
+        let validator = JwsValidator::from_jwk(&jwk).unwrap();
+        let token = rsclient.get_token().unwrap();
+        let ct = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+        let uat = validator.validate_uat(token, ct).unwrap();
+        assert!(uat.name == "admin");
+
+        // A tampered token must be rejected.
+        let mut parts: Vec<&str> = token.split('.').collect();
+        parts[1] = "e30";
+        assert!(validator.validate_uat(&parts.join("."), ct) == Err(JwsError::InvalidSignature));
+
+        // As must an expired one.
+        let future = ct + Duration::from_secs(7200);
+        assert!(validator.validate_uat(token, future) == Err(JwsError::Expired));
+    });
+}
+
 #[test]
 fn test_server_search() {
     run_test(|mut rsclient: KanidmClient| {
@@ -494,6 +524,55 @@ fn test_server_rest_domain_lifecycle() {
     });
 }
 
+#[test]
+fn test_server_rest_oauth2_basic_lifecycle() {
+    run_test(|mut rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // List, there are none.
+        let final_configs = rsclient
+            .idm_oauth2_rs_list()
+            .expect("Failed to retrieve oauth2 configs");
+        assert!(final_configs.is_empty());
+
+        // Create some new oauth2 configurations.
+        rsclient
+            .idm_oauth2_rs_basic_create(
+                "test_integration",
+                "Test Integration",
+                "https://demo.example.com",
+                "https://demo.example.com/oauth2/result",
+            )
+            .expect("Failed to create oauth2 config");
+
+        // List, there is what we created.
+        let final_configs = rsclient
+            .idm_oauth2_rs_list()
+            .expect("Failed to retrieve oauth2 configs");
+        assert!(final_configs.len() == 1);
+
+        // Get the value. Assert we have oauth2_rs_basic_secret generated for us.
+        let oauth2_config = rsclient
+            .idm_oauth2_rs_get("test_integration")
+            .ok()
+            .flatten()
+            .expect("Failed to retrieve test_integration config");
+        assert!(oauth2_config.attrs.contains_key("oauth2_rs_basic_secret"));
+
+        // Delete the config
+        rsclient
+            .idm_oauth2_rs_delete("test_integration")
+            .expect("Failed to delete test_integration");
+
+        // List, there are none.
+        let final_configs = rsclient
+            .idm_oauth2_rs_list()
+            .expect("Failed to retrieve oauth2 configs");
+        assert!(final_configs.is_empty());
+    });
+}
+
 #[test]
 fn test_server_rest_posix_lifecycle() {
     run_test(|mut rsclient: KanidmClient| {
@@ -776,7 +855,7 @@ fn test_server_rest_totp_auth_lifecycle() {
 
         // Remove TOTP on the account.
         rsclient
-            .idm_account_primary_credential_remove_totp("demo_account")
+            .idm_account_primary_credential_remove_totp("demo_account", "demo")
             .unwrap();
         // Check password auth.
         let mut rsclient_good = rsclient.new_session().unwrap();
@@ -953,6 +1032,216 @@ fn test_server_rest_webauthn_mfa_auth_lifecycle() {
     });
 }
 
+#[test]
+fn test_server_rest_service_account_api_token_lifecycle() {
+    run_test(|mut rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        rsclient
+            .idm_service_account_create("test_service", "Test Service")
+            .unwrap();
+
+        // A service account can not be given a password.
+        assert!(rsclient
+            .idm_account_primary_credential_set_password("test_service", "eicieY7ahchaoCh0eeTa")
+            .is_err());
+
+        // An expiry in the past is rejected.
+        assert!(rsclient
+            .idm_service_account_api_token_generate(
+                "test_service",
+                "expired",
+                Some("2020-09-25T11:22:02+10:00"),
+                false
+            )
+            .is_err());
+
+        let rw_token = rsclient
+            .idm_service_account_api_token_generate("test_service", "rw", None, false)
+            .unwrap();
+        let ro_token = rsclient
+            .idm_service_account_api_token_generate("test_service", "ro", None, true)
+            .unwrap();
+
+        let tokens = rsclient
+            .idm_service_account_api_token_list("test_service")
+            .unwrap();
+        assert!(tokens.len() == 2);
+        let ro_token_id = tokens
+            .iter()
+            .find(|t| t.label == "ro")
+            .map(|t| t.token_id.clone())
+            .unwrap();
+
+        // Use the read write token to act as the service account.
+        let mut sa_client = rsclient.new_session().unwrap();
+        sa_client.set_token(rw_token);
+        let (_, uat) = sa_client.whoami().unwrap().unwrap();
+        assert!(uat.name == "test_service");
+        assert!(sa_client
+            .idm_account_set_displayname("test_service", "Renamed Service")
+            .is_ok());
+
+        // The read only token can search, but not write.
+        let mut ro_client = rsclient.new_session().unwrap();
+        ro_client.set_token(ro_token);
+        assert!(ro_client
+            .search(Filter::Eq("name".to_string(), "admin".to_string()))
+            .is_ok());
+        assert!(ro_client
+            .idm_account_set_displayname("test_service", "Read Only")
+            .is_err());
+
+        // Once revoked the token can no longer be used.
+        rsclient
+            .idm_service_account_api_token_destroy("test_service", ro_token_id.as_str())
+            .unwrap();
+        assert!(!matches!(ro_client.whoami(), Ok(Some(_))));
+        assert!(ro_client
+            .search(Filter::Eq("name".to_string(), "admin".to_string()))
+            .is_err());
+
+        let tokens = rsclient
+            .idm_service_account_api_token_list("test_service")
+            .unwrap();
+        assert!(tokens.len() == 1);
+    });
+}
+
+#[test]
+fn test_server_rest_app_password_lifecycle() {
+    run_test(|mut rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+        // Get an anon connection
+        let mut anon_rsclient = rsclient.new_session().unwrap();
+        assert!(anon_rsclient.auth_anonymous().is_ok());
+
+        // Not recommended in production!
+        rsclient
+            .idm_group_add_members("idm_admins", &["admin"])
+            .unwrap();
+
+        rsclient
+            .idm_account_create("posix_account", "Posix Demo Account")
+            .unwrap();
+        rsclient
+            .idm_account_unix_extend("posix_account", None, None)
+            .unwrap();
+
+        let unix_pw = rsclient
+            .idm_account_app_password_generate("posix_account", "laptop", AppPasswordScope::Unix)
+            .unwrap();
+        let ldap_pw = rsclient
+            .idm_account_app_password_generate("posix_account", "mail", AppPasswordScope::Ldap)
+            .unwrap();
+        // Labels must be unique.
+        assert!(rsclient
+            .idm_account_app_password_generate("posix_account", "mail", AppPasswordScope::Unix)
+            .is_err());
+
+        let app_pws = rsclient
+            .idm_account_app_password_list("posix_account")
+            .unwrap();
+        assert!(app_pws.len() == 2);
+
+        // Only the unix scoped password is accepted by unix auth.
+        let r1 = anon_rsclient.idm_account_unix_cred_verify("posix_account", unix_pw.as_str());
+        assert!(matches!(r1, Ok(Some(_))));
+        let r2 = anon_rsclient.idm_account_unix_cred_verify("posix_account", ldap_pw.as_str());
+        assert!(matches!(r2, Ok(None)));
+
+        // Once removed, it is rejected.
+        assert!(rsclient
+            .idm_account_app_password_remove("posix_account", "laptop")
+            .is_ok());
+        let r3 = anon_rsclient.idm_account_unix_cred_verify("posix_account", unix_pw.as_str());
+        assert!(matches!(r3, Ok(None)));
+
+        let app_pws = rsclient
+            .idm_account_app_password_list("posix_account")
+            .unwrap();
+        assert!(app_pws.len() == 1);
+        assert!(app_pws[0].label == "mail");
+    });
+}
+
+#[test]
+fn test_server_rest_credential_reset_lifecycle() {
+    run_test(|mut rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // Not recommended in production!
+        rsclient
+            .idm_group_add_members("idm_admins", &["admin"])
+            .unwrap();
+
+        rsclient
+            .idm_account_create("demo_account", "Deeeeemo")
+            .unwrap();
+
+        let intent = rsclient
+            .idm_account_credential_reset_intent("demo_account", None)
+            .unwrap();
+
+        // The token holder does not need to be authenticated.
+        let token_rsclient = rsclient.new_session().unwrap();
+        let session = token_rsclient
+            .idm_credential_reset_begin(intent.token.as_str())
+            .unwrap();
+        assert!(session.spn == "demo_account@example.com");
+        // It can only be redeemed once.
+        assert!(token_rsclient
+            .idm_credential_reset_begin(intent.token.as_str())
+            .is_err());
+
+        let r = token_rsclient.idm_credential_reset_step(
+            session.sessionid,
+            intent.token.as_str(),
+            SetCredentialRequest::Password("sohdi3iuHo6mai7noh0a".to_string()),
+        );
+        assert!(matches!(r, Ok(SetCredentialResponse::Success)));
+
+        let (totp_session, tok) = match token_rsclient.idm_credential_reset_step(
+            session.sessionid,
+            intent.token.as_str(),
+            SetCredentialRequest::TOTPGenerate("demo".to_string()),
+        ) {
+            Ok(SetCredentialResponse::TOTPCheck(s, t)) => (s, t),
+            _ => panic!("Failed to generate totp"),
+        };
+        let r_tok: TOTP = tok.into();
+        let totp = r_tok
+            .do_totp_duration_from_epoch(
+                &SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap(),
+            )
+            .expect("Failed to do totp?");
+        let r = token_rsclient.idm_credential_reset_step(
+            session.sessionid,
+            intent.token.as_str(),
+            SetCredentialRequest::TOTPVerify(totp_session, totp),
+        );
+        assert!(matches!(r, Ok(SetCredentialResponse::Success)));
+
+        // The new credentials work.
+        let mut rsclient_good = rsclient.new_session().unwrap();
+        let totp = r_tok
+            .do_totp_duration_from_epoch(
+                &SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap(),
+            )
+            .expect("Failed to do totp?");
+        assert!(rsclient_good
+            .auth_password_totp("demo_account", "sohdi3iuHo6mai7noh0a", totp)
+            .is_ok());
+    });
+}
+
 // Test setting account expiry
 
 // Test the self version of the radius path.
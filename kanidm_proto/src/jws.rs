@@ -0,0 +1,156 @@
+//! Verification of the compact JWS tokens that kanidm issues. Kanidm signs user auth
+//! tokens and oauth2 tokens with ES256. The public key is published as a JWK, and
+//! clients can use a [`JwsValidator`] created from that key to check the tokens
+//! offline.
+
+use crate::oauth2::{AccessTokenClaims, Jwk};
+use crate::v1::UserAuthToken;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::nid::Nid;
+use openssl::pkey::Public;
+use openssl::sha::sha256;
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum JwsError {
+    #[error("The token is not a valid compact JWS")]
+    InvalidFormat,
+    #[error("The key is not a valid ES256 public key")]
+    InvalidKey,
+    #[error("The token was signed by a different key")]
+    KeyMismatch,
+    #[error("The token uses an unsupported algorithm")]
+    UnsupportedAlgorithm,
+    #[error("The token signature is invalid")]
+    InvalidSignature,
+    #[error("The token has expired")]
+    Expired,
+    #[error("The token was not issued for this purpose")]
+    InvalidPurpose,
+}
+
+/// What a token signed by the domain key was issued for. The same key signs every kind
+/// of token, so each carries this as its `purpose` claim, and a validator only accepts
+/// tokens of its own purpose.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    UserAuth,
+    Oauth2Access,
+    Oauth2Id,
+    CredentialReset,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwsHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+fn b64_decode(input: &str) -> Result<Vec<u8>, JwsError> {
+    base64::decode_config(input, base64::URL_SAFE_NO_PAD).map_err(|_| JwsError::InvalidFormat)
+}
+
+/// Verifies tokens signed by a single kanidm ES256 key.
+#[derive(Clone)]
+pub struct JwsValidator {
+    key: EcKey<Public>,
+    kid: String,
+}
+
+impl JwsValidator {
+    pub fn from_jwk(jwk: &Jwk) -> Result<Self, JwsError> {
+        if jwk.kty != "EC" || jwk.crv != "P-256" || jwk.alg != "ES256" {
+            return Err(JwsError::UnsupportedAlgorithm);
+        }
+
+        let x = b64_decode(&jwk.x)
+            .and_then(|x| BigNum::from_slice(&x).map_err(|_| JwsError::InvalidKey))?;
+        let y = b64_decode(&jwk.y)
+            .and_then(|y| BigNum::from_slice(&y).map_err(|_| JwsError::InvalidKey))?;
+
+        let key = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)
+            .and_then(|group| EcKey::from_public_key_affine_coordinates(&group, &x, &y))
+            .map_err(|_| JwsError::InvalidKey)?;
+        key.check_key().map_err(|_| JwsError::InvalidKey)?;
+
+        Ok(JwsValidator {
+            key,
+            kid: jwk.kid.clone(),
+        })
+    }
+
+    /// Check the signature of the token, and return the deserialised claims.
+    /// This does not check any time related claims, as they differ per token type.
+    pub fn validate<T: DeserializeOwned>(&self, jws: &str) -> Result<T, JwsError> {
+        let mut parts = jws.split('.');
+        let (header, payload, sig) = match (parts.next(), parts.next(), parts.next(), parts.next())
+        {
+            (Some(h), Some(p), Some(s), None) => (h, p, s),
+            _ => return Err(JwsError::InvalidFormat),
+        };
+
+        let hdr: JwsHeader = b64_decode(header)
+            .and_then(|h| serde_json::from_slice(&h).map_err(|_| JwsError::InvalidFormat))?;
+        if hdr.alg != "ES256" {
+            return Err(JwsError::UnsupportedAlgorithm);
+        }
+        if let Some(kid) = hdr.kid {
+            if kid != self.kid {
+                return Err(JwsError::KeyMismatch);
+            }
+        }
+
+        // JWS uses the raw r || s form, not DER.
+        let sig = b64_decode(sig)?;
+        if sig.len() != 64 {
+            return Err(JwsError::InvalidSignature);
+        }
+        let sig = BigNum::from_slice(&sig[..32])
+            .and_then(|r| BigNum::from_slice(&sig[32..]).map(|s| (r, s)))
+            .and_then(|(r, s)| EcdsaSig::from_private_components(r, s))
+            .map_err(|_| JwsError::InvalidSignature)?;
+
+        let digest = sha256(format!("{}.{}", header, payload).as_bytes());
+        match sig.verify(&digest, &self.key) {
+            Ok(true) => {}
+            _ => return Err(JwsError::InvalidSignature),
+        }
+
+        b64_decode(payload)
+            .and_then(|p| serde_json::from_slice(&p).map_err(|_| JwsError::InvalidFormat))
+    }
+
+    /// Validate a user auth token, as issued by `/v1/auth`. `ct` is the current time
+    /// as a duration since the unix epoch.
+    pub fn validate_uat(&self, jws: &str, ct: Duration) -> Result<UserAuthToken, JwsError> {
+        let uat: UserAuthToken = self.validate(jws)?;
+        if uat.purpose != TokenPurpose::UserAuth {
+            Err(JwsError::InvalidPurpose)
+        } else if (ct.as_secs() as i64) < uat.exp {
+            Ok(uat)
+        } else {
+            Err(JwsError::Expired)
+        }
+    }
+
+    /// Validate an oauth2 access token, as issued by `/oauth2/token`. The audience
+    /// and scopes are left for the resource server to check.
+    pub fn validate_access_token(
+        &self,
+        jws: &str,
+        ct: Duration,
+    ) -> Result<AccessTokenClaims, JwsError> {
+        let claims: AccessTokenClaims = self.validate(jws)?;
+        if claims.purpose != TokenPurpose::Oauth2Access {
+            Err(JwsError::InvalidPurpose)
+        } else if (ct.as_secs() as i64) < claims.exp {
+            Ok(claims)
+        } else {
+            Err(JwsError::Expired)
+        }
+    }
+}
@@ -11,4 +11,6 @@
 #[macro_use]
 extern crate serde_derive;
 
+pub mod jws;
+pub mod oauth2;
 pub mod v1;
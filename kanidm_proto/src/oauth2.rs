@@ -0,0 +1,225 @@
+use crate::jws::TokenPurpose;
+use std::fmt;
+
+/// The only PKCE code challenge method we support. Plain is not accepted as it
+/// provides no protection over simply not using PKCE at all.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum CodeChallengeMethod {
+    S256,
+}
+
+/// The parameters an Oauth2 client (the resource server) sends via the user agent to
+/// the authorisation endpoint to begin an authorisation code grant.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthorisationRequest {
+    // Must be "code". (or token, see 4.2.1)
+    pub response_type: String,
+    pub client_id: String,
+    pub state: String,
+    pub redirect_uri: String,
+    // Space seperated list of requested scopes.
+    pub scope: String,
+    pub code_challenge: String,
+    pub code_challenge_method: CodeChallengeMethod,
+    // OpenID Connect replay protection for the id_token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+}
+
+/// The result of an authorisation request. If the user has previously consented to
+/// the requested scopes, they are immediately redirected back to the resource server
+/// with a code. Otherwise the user must be prompted to consent.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AuthorisationResponse {
+    ConsentRequested {
+        // A pretty-name of the client
+        client_name: String,
+        // A list of scopes requested / to be issued.
+        scopes: Vec<String>,
+        // The users displayname (?)
+        // pub display_name: String,
+        // The token we need to be given back to allow this to proceed
+        consent_token: String,
+    },
+    Permitted {
+        // The full uri, including the code and state parameters, that
+        // the user agent should now be redirected to.
+        redirect_uri: String,
+    },
+}
+
+/// Sent by the user agent once the user has consented to the scopes of a
+/// `ConsentRequested` response.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConsentRequest {
+    pub consent_token: String,
+}
+
+/// The form parameters of a request to the token endpoint. Depending on the grant_type
+/// different fields are required.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AccessTokenRequest {
+    pub grant_type: String,
+    // authorization_code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_verifier: Option<String>,
+    // client_credentials
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    // Client authentication when http basic is not used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+/// The response to a successful token exchange.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccessTokenResponse {
+    // Signed JWS of the access token claims.
+    pub access_token: String,
+    pub token_type: String,
+    // seconds.
+    pub expires_in: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    // Only present when the openid scope was granted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// The claims of an access token issued by kanidm. These are signed by the domain's
+/// ES256 key, and can be verified with the key published at the jwks endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccessTokenClaims {
+    // Always oauth2_access.
+    pub purpose: TokenPurpose,
+    pub iss: String,
+    // The account (or for client credentials, the resource server) uuid.
+    pub sub: String,
+    // The client_id of the resource server.
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    // Space seperated list of granted scopes.
+    pub scope: String,
+}
+
+/// The claims of an OpenID Connect id_token.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OidcToken {
+    // Always oauth2_id.
+    pub purpose: TokenPurpose,
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub groups: Vec<String>,
+}
+
+/// The OpenID Connect discovery document, served from
+/// `/oauth2/openid/:client_id/.well-known/openid-configuration`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OidcDiscoveryResponse {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub scopes_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
+
+/// A single public key in JWK format (RFC 7517). Only EC P-256 keys are issued.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    // base64 url safe, no padding.
+    pub x: String,
+    pub y: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub use_: String,
+    pub kid: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JwkKeySet {
+    pub keys: Vec<Jwk>,
+}
+
+/// Errors as defined by RFC 6749 section 4.1.2.1 and 5.2.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Oauth2Error {
+    // Non-standard - the user is not authenticated.
+    AuthenticationRequired,
+    InvalidClient,
+    InvalidGrant,
+    InvalidRequest,
+    UnauthorizedClient,
+    AccessDenied,
+    UnsupportedGrantType,
+    UnsupportedResponseType,
+    InvalidScope,
+    ServerError,
+    TemporarilyUnavailable,
+}
+
+impl Oauth2Error {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Oauth2Error::AuthenticationRequired => "access_denied",
+            Oauth2Error::InvalidClient => "invalid_client",
+            Oauth2Error::InvalidGrant => "invalid_grant",
+            Oauth2Error::InvalidRequest => "invalid_request",
+            Oauth2Error::UnauthorizedClient => "unauthorized_client",
+            Oauth2Error::AccessDenied => "access_denied",
+            Oauth2Error::UnsupportedGrantType => "unsupported_grant_type",
+            Oauth2Error::UnsupportedResponseType => "unsupported_response_type",
+            Oauth2Error::InvalidScope => "invalid_scope",
+            Oauth2Error::ServerError => "server_error",
+            Oauth2Error::TemporarilyUnavailable => "temporarily_unavailable",
+        }
+    }
+}
+
+impl fmt::Display for Oauth2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The body of an error returned from the token endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorResponse {
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_description: Option<String>,
+}
+
+impl From<Oauth2Error> for ErrorResponse {
+    fn from(e: Oauth2Error) -> Self {
+        ErrorResponse {
+            error: e.as_str().to_string(),
+            error_description: None,
+        }
+    }
+}
@@ -1,5 +1,7 @@
+use crate::jws::TokenPurpose;
 use std::collections::BTreeMap;
 use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 // use zxcvbn::feedback;
 use std::cmp::Ordering;
@@ -42,6 +44,8 @@ pub enum PluginError {
     Base(String),
     ReferentialIntegrity(String),
     PasswordImport(String),
+    ServiceAccount(String),
+    Trust(String),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -101,10 +105,18 @@ pub enum OperationError {
     InvalidSessionState,
     SystemProtectedObject,
     SystemProtectedAttribute,
-    PasswordTooWeak,
+    // zxcvbn only gives feedback for the weakest passwords.
+    PasswordTooWeak(Option<zxcvbn::feedback::Feedback>),
     PasswordTooShort(usize),
+    // The number of character classes the password must contain.
+    PasswordTooFewCharClasses(u32),
     PasswordEmpty,
     PasswordBadListed,
+    // The password is in the corpus of breached passwords.
+    PasswordBreached,
+    PasswordInHistory,
+    // The minimum credential type that a group policy requires.
+    CredentialTooWeak(String),
     CryptographyError,
     ResourceLimit,
     QueueDisconnected,
@@ -158,9 +170,9 @@ pub struct Application {
 
 // The currently authenticated user, and any required metadata for them
 // to properly authorise them. This is similar in nature to oauth and the krb
-// PAC/PAD structures. Currently we only use this internally, but we should
-// consider making it "parseable" by the client so they can have per-session
-// group/authorisation data.
+// PAC/PAD structures. The server issues this as an ES256 signed JWS, so clients
+// and resource servers can verify it offline with the published key, see
+// `jws::JwsValidator`.
 //
 // This structure and how it works will *very much* change over time from this
 // point onward!
@@ -169,9 +181,12 @@ pub struct Application {
 // and to the Entry so that filters or access controls can be applied.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UserAuthToken {
-    // When this data should be considered invalid. Interpretation
-    // may depend on the client application.
-    // pub expiry: DateTime,
+    // When this data should be considered invalid, as seconds since the unix
+    // epoch. This matches the jwt exp claim.
+    pub exp: i64,
+    // Always user_auth, so that other tokens signed by the domain key are not accepted
+    // in place of this one.
+    pub purpose: TokenPurpose,
     pub name: String,
     pub spn: String,
     pub displayname: String,
@@ -185,6 +200,17 @@ pub struct UserAuthToken {
     pub lim_rmax: usize,
     pub lim_pmax: usize,
     pub lim_fmax: usize,
+    // If this token is a service account api token, this is the id of the token.
+    // The server checks that it has not been revoked on each use.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_token_id: Option<String>,
+    // The default value of bool is false.
+    #[serde(default)]
+    pub read_only: bool,
+    // When the password of the account expires, as seconds since the unix epoch.
+    // Only present if a maximum password age applies to the account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_expiry: Option<i64>,
 }
 
 impl fmt::Display for UserAuthToken {
@@ -194,6 +220,9 @@ impl fmt::Display for UserAuthToken {
         writeln!(f, "display: {}", self.displayname)?;
         writeln!(f, "uuid: {}", self.uuid)?;
         writeln!(f, "groups: {:?}", self.groups)?;
+        if let Some(pe) = self.password_expiry {
+            writeln!(f, "password_expiry: {}", pe)?;
+        }
         writeln!(f, "claims: {:?}", self.claims)
     }
 }
@@ -260,6 +289,10 @@ pub struct UnixUserToken {
     // The default value of bool is false.
     #[serde(default)]
     pub valid: bool,
+    // When the unix password expires, as seconds since the unix epoch. Only present
+    // if a maximum password age applies to the account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_expiry: Option<i64>,
 }
 
 impl fmt::Display for UnixUserToken {
@@ -273,6 +306,9 @@ impl fmt::Display for UnixUserToken {
             Some(s) => writeln!(f, "shell: {}", s)?,
             None => writeln!(f, "shell: <none>")?,
         }
+        if let Some(pe) = self.password_expiry {
+            writeln!(f, "password_expiry: {}", pe)?;
+        }
         self.sshkeys
             .iter()
             .try_for_each(|s| writeln!(f, "ssh_publickey: {}", s))?;
@@ -288,6 +324,187 @@ pub struct AccountUnixExtend {
     pub shell: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiTokenPurpose {
+    ReadOnly,
+    ReadWrite,
+}
+
+// An api token of a service account, as displayed to an administrator. The token
+// itself is only ever returned once, when it is generated.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiToken {
+    pub token_id: String,
+    pub label: String,
+    // rfc3339 datetimes
+    pub expiry: Option<String>,
+    pub purpose: ApiTokenPurpose,
+    pub last_used: Option<String>,
+}
+
+impl fmt::Display for ApiToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "---")?;
+        writeln!(f, "token_id: {}", self.token_id)?;
+        writeln!(f, "label: {}", self.label)?;
+        match &self.expiry {
+            Some(e) => writeln!(f, "expiry: {}", e)?,
+            None => writeln!(f, "expiry: never")?,
+        }
+        writeln!(f, "purpose: {:?}", self.purpose)?;
+        match &self.last_used {
+            Some(l) => writeln!(f, "last_used: {}", l),
+            None => writeln!(f, "last_used: never"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiTokenGenerate {
+    pub label: String,
+    // rfc3339 datetime, or none for a token that does not expire.
+    pub expiry: Option<String>,
+    pub purpose: ApiTokenPurpose,
+}
+
+// The only purpose an application password may be used for. This is stored as a
+// claim on the credential.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AppPasswordScope {
+    Ldap,
+    Unix,
+    Radius,
+}
+
+impl AppPasswordScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AppPasswordScope::Ldap => "ldap",
+            AppPasswordScope::Unix => "unix",
+            AppPasswordScope::Radius => "radius",
+        }
+    }
+}
+
+impl std::str::FromStr for AppPasswordScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ldap" => Ok(AppPasswordScope::Ldap),
+            "unix" => Ok(AppPasswordScope::Unix),
+            "radius" => Ok(AppPasswordScope::Radius),
+            _ => Err(format!(
+                "invalid app password scope {} - expected ldap, unix or radius",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AppPassword {
+    pub label: String,
+    pub scope: AppPasswordScope,
+}
+
+impl fmt::Display for AppPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.label, self.scope.as_str())
+    }
+}
+
+// The softlock of one of an account's credentials, as displayed to an administrator.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CredentialSoftLock {
+    // primary or unix
+    pub credential: String,
+    pub failures: u32,
+    // rfc3339 datetimes. unlock_at is none if the credential may be used now.
+    pub unlock_at: Option<String>,
+    pub reset_at: String,
+}
+
+impl fmt::Display for CredentialSoftLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "---")?;
+        writeln!(f, "credential: {}", self.credential)?;
+        writeln!(f, "failures: {}", self.failures)?;
+        match &self.unlock_at {
+            Some(u) => writeln!(f, "locked until: {}", u)?,
+            None => writeln!(f, "locked until: unlocked")?,
+        }
+        writeln!(f, "failures reset at: {}", self.reset_at)
+    }
+}
+
+// An authentication attempt from the audit store.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuthAuditRecord {
+    // rfc3339 datetime
+    pub time: String,
+    // The account name, if the attempt could be tied to an account.
+    pub account: Option<String>,
+    pub account_uuid: Option<String>,
+    pub success: bool,
+    // password, passwordmfa or webauthn
+    pub mechanism: Option<String>,
+    // https, ldap or unix
+    pub protocol: String,
+    pub source: Option<String>,
+    // If the credential was softlocked after this attempt.
+    pub softlocked: bool,
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for AuthAuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "---")?;
+        writeln!(f, "time: {}", self.time)?;
+        writeln!(
+            f,
+            "account: {}",
+            self.account.as_deref().unwrap_or("unknown")
+        )?;
+        writeln!(
+            f,
+            "result: {}",
+            if self.success { "success" } else { "failure" }
+        )?;
+        if let Some(reason) = &self.reason {
+            writeln!(f, "reason: {}", reason)?;
+        }
+        if let Some(mechanism) = &self.mechanism {
+            writeln!(f, "mechanism: {}", mechanism)?;
+        }
+        writeln!(f, "protocol: {}", self.protocol)?;
+        if let Some(source) = &self.source {
+            writeln!(f, "source: {}", source)?;
+        }
+        writeln!(f, "softlocked: {}", self.softlocked)
+    }
+}
+
+// The filter of a search of the authentication audit store. Records are returned newest
+// first.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AuthAuditQuery {
+    pub account: Option<String>,
+    pub success: Option<bool>,
+    // rfc3339 datetimes
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AppPasswordGenerate {
+    pub label: String,
+    pub scope: AppPasswordScope,
+}
+
 /* ===== low level proto types ===== */
 
 // ProtoEntry vs Entry
@@ -433,6 +650,12 @@ pub enum AuthCredential {
     Password(String),
     TOTP(u32),
     Webauthn(PublicKeyCredential),
+    BackupCode(String),
+    // The new password, when the current one has expired.
+    PasswordChange(String),
+    // The client certificate of the connection. It carries nothing as the certificate
+    // was already verified by the TLS layer.
+    ClientCertificate,
 }
 
 impl fmt::Debug for AuthCredential {
@@ -442,6 +665,9 @@ impl fmt::Debug for AuthCredential {
             AuthCredential::Password(_) => write!(fmt, "Password(_)"),
             AuthCredential::TOTP(_) => write!(fmt, "TOTP(_)"),
             AuthCredential::Webauthn(_) => write!(fmt, "Webauthn(_)"),
+            AuthCredential::BackupCode(_) => write!(fmt, "BackupCode(_)"),
+            AuthCredential::PasswordChange(_) => write!(fmt, "PasswordChange(_)"),
+            AuthCredential::ClientCertificate => write!(fmt, "ClientCertificate"),
         }
     }
 }
@@ -453,6 +679,10 @@ pub enum AuthMech {
     Password,
     PasswordMFA,
     Webauthn,
+    // A webauthn token that names the account, so no name was given at init.
+    WebauthnDiscoverable,
+    // A TLS client certificate bound to the account.
+    ClientCertificate,
     // WebauthnVerified,
     // PasswordWebauthnVerified
 }
@@ -470,6 +700,8 @@ impl fmt::Display for AuthMech {
             AuthMech::Password => write!(f, "Passwold Only"),
             AuthMech::PasswordMFA => write!(f, "TOTP or Token, and Password"),
             AuthMech::Webauthn => write!(f, "Webauthn Token"),
+            AuthMech::WebauthnDiscoverable => write!(f, "Discoverable Webauthn Token"),
+            AuthMech::ClientCertificate => write!(f, "TLS Client Certificate"),
         }
     }
 }
@@ -479,6 +711,8 @@ impl fmt::Display for AuthMech {
 pub enum AuthStep {
     // name
     Init(String),
+    // No name, the account is found from the discoverable webauthn credential.
+    InitDiscoverable,
     // We want to talk to you like this.
     Begin(AuthMech),
     // Step
@@ -501,6 +735,8 @@ pub enum AuthAllowed {
     Anonymous,
     Password,
     TOTP,
+    BackupCode,
+    ClientCertificate,
     Webauthn(RequestChallengeResponse),
 }
 
@@ -525,6 +761,10 @@ impl Ord for AuthAllowed {
                 (_, AuthAllowed::Password) => Ordering::Greater,
                 (AuthAllowed::TOTP, _) => Ordering::Less,
                 (_, AuthAllowed::TOTP) => Ordering::Greater,
+                (AuthAllowed::BackupCode, _) => Ordering::Less,
+                (_, AuthAllowed::BackupCode) => Ordering::Greater,
+                (AuthAllowed::ClientCertificate, _) => Ordering::Less,
+                (_, AuthAllowed::ClientCertificate) => Ordering::Greater,
                 (AuthAllowed::Webauthn(_), _) => Ordering::Less,
                 // Unreachable
                 // (_, AuthAllowed::Webauthn(_)) => Ordering::Greater,
@@ -545,6 +785,8 @@ impl fmt::Display for AuthAllowed {
             AuthAllowed::Anonymous => write!(f, "Anonymous (no credentials)"),
             AuthAllowed::Password => write!(f, "Password"),
             AuthAllowed::TOTP => write!(f, "TOTP"),
+            AuthAllowed::BackupCode => write!(f, "Backup Code"),
+            AuthAllowed::ClientCertificate => write!(f, "TLS Client Certificate"),
             AuthAllowed::Webauthn(_) => write!(f, "Webauthn Token"),
         }
     }
@@ -562,6 +804,9 @@ pub enum AuthState {
     // Everything is good, your bearer header has been issued and is within
     // the result.
     Success(String),
+    // Your credentials are correct, but your password has expired. The only
+    // step allowed is to provide a new password with PasswordChange.
+    PasswordExpired,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -578,16 +823,21 @@ pub enum SetCredentialRequest {
     GeneratePassword,
     TOTPGenerate(String),
     TOTPVerify(Uuid, u32),
-    TOTPRemove,
+    // Remove the TOTP with this label.
+    TOTPRemove(String),
+    // Import an existing TOTP secret under this label.
+    TOTPImport(String, TOTPSecret),
     // Start the rego.
     WebauthnBegin(String),
     // Finish it.
     WebauthnRegister(Uuid, RegisterPublicKeyCredential),
     // Remove
     WebauthnRemove(String),
+    // Replace any existing backup codes with a new set.
+    BackupCodeGenerate,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TOTPAlgo {
     Sha1,
@@ -605,6 +855,23 @@ impl fmt::Display for TOTPAlgo {
     }
 }
 
+impl FromStr for TOTPAlgo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha1" => Ok(TOTPAlgo::Sha1),
+            "sha256" => Ok(TOTPAlgo::Sha256),
+            "sha512" => Ok(TOTPAlgo::Sha512),
+            _ => Err(format!("unknown totp algorithm {}", s)),
+        }
+    }
+}
+
+fn default_totp_digits() -> u8 {
+    6
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TOTPSecret {
     pub accountname: String,
@@ -612,6 +879,29 @@ pub struct TOTPSecret {
     pub secret: Vec<u8>,
     pub algo: TOTPAlgo,
     pub step: u64,
+    #[serde(default = "default_totp_digits")]
+    pub digits: u8,
+}
+
+// Decode the %XX escapes of an otpauth uri component.
+fn percent_decode(s: &str) -> Result<String, String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s
+                .get(i + 1..i + 3)
+                .ok_or_else(|| format!("invalid escape in {}", s))?;
+            let b = u8::from_str_radix(hex, 16).map_err(|_| format!("invalid escape in {}", s))?;
+            out.push(b);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| format!("invalid utf8 in {}", s))
 }
 
 impl TOTPSecret {
@@ -634,11 +924,74 @@ impl TOTPSecret {
         let secret = self.get_secret();
         let period = self.step;
         format!(
-            "otpauth://totp/{}?secret={}&issuer={}&algorithm={}&digits=6&period={}",
-            label, secret, issuer, algo, period
+            "otpauth://totp/{}?secret={}&issuer={}&algorithm={}&digits={}&period={}",
+            label, secret, issuer, algo, self.digits, period
         )
     }
 
+    /// Parse an existing otpauth://totp/ uri, such as one exported from another system.
+    /// Parameters that are not present take the defaults of the key uri format.
+    pub fn from_uri(uri: &str) -> Result<Self, String> {
+        let rest = uri
+            .trim()
+            .strip_prefix("otpauth://totp/")
+            .ok_or_else(|| "only otpauth://totp/ uris are supported".to_string())?;
+        let (label, query) = match rest.find('?') {
+            Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+            None => (rest, ""),
+        };
+        let label = percent_decode(label)?;
+        let (mut issuer, accountname) = match label.find(':') {
+            Some(idx) => (
+                label[..idx].to_string(),
+                label[idx + 1..].trim().to_string(),
+            ),
+            None => (String::new(), label.clone()),
+        };
+
+        let mut secret = None;
+        let mut algo = TOTPAlgo::Sha1;
+        let mut digits = default_totp_digits();
+        let mut step = 30;
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (k, v) = match pair.find('=') {
+                Some(idx) => (&pair[..idx], percent_decode(&pair[idx + 1..])?),
+                None => (pair, String::new()),
+            };
+            match k.to_lowercase().as_str() {
+                "secret" => secret = Some(Self::decode_secret(&v)?),
+                "issuer" => issuer = v,
+                "algorithm" => algo = TOTPAlgo::from_str(&v)?,
+                "digits" => digits = v.parse().map_err(|_| format!("invalid digits {}", v))?,
+                "period" => step = v.parse().map_err(|_| format!("invalid period {}", v))?,
+                // Ignore parameters such as image that we have no use for.
+                _ => {}
+            }
+        }
+
+        Ok(TOTPSecret {
+            accountname,
+            issuer,
+            secret: secret.ok_or_else(|| "uri has no secret".to_string())?,
+            algo,
+            step,
+            digits,
+        })
+    }
+
+    /// Decode a base32 secret as shown by most authenticator enrolment pages, allowing
+    /// spaces, lower case and padding.
+    pub fn decode_secret(secret: &str) -> Result<Vec<u8>, String> {
+        let secret: String = secret
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .collect::<String>()
+            .to_uppercase();
+        base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret)
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| "secret is not valid base32".to_string())
+    }
+
     pub fn get_secret(&self) -> String {
         base32::encode(base32::Alphabet::RFC4648 { padding: false }, &self.secret)
     }
@@ -651,6 +1004,31 @@ pub enum SetCredentialResponse {
     Token(String),
     TOTPCheck(Uuid, TOTPSecret),
     WebauthnCreateChallenge(Uuid, CreationChallengeResponse),
+    BackupCodes(Vec<String>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CredentialResetIntentRequest {
+    /// How long the token is valid for in seconds. The server default is used if not set.
+    pub ttl: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CredentialResetIntent {
+    pub token: String,
+    pub expiry: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CredentialResetSession {
+    pub sessionid: Uuid,
+    pub spn: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CredentialResetRequest {
+    pub token: String,
+    pub sac: SetCredentialRequest,
 }
 
 /* Recycle Requests area */
@@ -741,6 +1119,7 @@ mod tests {
             secret: vec![0xaa, 0xbb, 0xcc, 0xdd],
             step: 30,
             algo: TOTPAlgo::Sha256,
+            digits: 6,
         };
         let s = totp.to_uri();
         assert!(s == "otpauth://totp/blackhats:william?secret=VK54ZXI&issuer=blackhats&algorithm=SHA256&digits=6&period=30");
@@ -752,8 +1131,34 @@ mod tests {
             secret: vec![0xaa, 0xbb, 0xcc, 0xdd],
             step: 30,
             algo: TOTPAlgo::Sha256,
+            digits: 6,
         };
         let s = totp.to_uri();
         assert!(s == "otpauth://totp/blackhats%20australia:william?secret=VK54ZXI&issuer=blackhats%20australia&algorithm=SHA256&digits=6&period=30");
     }
+
+    #[test]
+    fn totp_from_uri() {
+        let totp = TOTPSecret::from_uri("otpauth://totp/blackhats%20australia:william?secret=VK54ZXI&issuer=blackhats%20australia&algorithm=SHA256&digits=8&period=60").unwrap();
+        assert!(totp.accountname == "william");
+        assert!(totp.issuer == "blackhats australia");
+        assert!(totp.secret == vec![0xaa, 0xbb, 0xcc, 0xdd]);
+        assert!(totp.algo == TOTPAlgo::Sha256);
+        assert!(totp.digits == 8);
+        assert!(totp.step == 60);
+
+        // Defaults from the key uri format, and a padded lower case secret.
+        let totp = TOTPSecret::from_uri("otpauth://totp/william?secret=vk54zxi=").unwrap();
+        assert!(totp.accountname == "william");
+        assert!(totp.secret == vec![0xaa, 0xbb, 0xcc, 0xdd]);
+        assert!(totp.algo == TOTPAlgo::Sha1);
+        assert!(totp.digits == 6);
+        assert!(totp.step == 30);
+
+        assert!(TOTPSecret::from_uri("otpauth://hotp/william?secret=VK54ZXI&counter=0").is_err());
+        assert!(TOTPSecret::from_uri("otpauth://totp/william?issuer=blackhats").is_err());
+        assert!(
+            TOTPSecret::from_uri("otpauth://totp/william?secret=VK54ZXI&algorithm=md5").is_err()
+        );
+    }
 }
@@ -10,8 +10,8 @@
 
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::BufWriter;
-use std::path::PathBuf;
+use std::io::{self, BufReader, BufWriter, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use kanidm_proto::v1::Modify;
@@ -22,6 +22,71 @@ use structopt::StructOpt;
 
 include!("opt/badlist_preprocess.rs");
 
+// This must match the breach corpus of kanidmd.
+const BREACH_CORPUS_MAGIC: &[u8; 8] = b"KANIBC01";
+const PREFIX_BUCKETS: usize = 65536;
+
+// Dump lines are "<hex sha1>:<count>".
+fn parse_sha1(line: &str) -> Option<[u8; 20]> {
+    let hex = line.split(':').next()?.trim();
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut hash = [0; 20];
+    for (i, b) in hash.iter_mut().enumerate() {
+        *b = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+// The dumps are too large to sort in memory, so they must already be ordered by hash. The
+// hashes are streamed out, and the index of where each prefix starts is written last.
+fn write_breach_corpus(outfile: &Path, dumps: &[PathBuf]) -> io::Result<u64> {
+    let mut bwrite = BufWriter::new(File::create(outfile)?);
+    bwrite.write_all(BREACH_CORPUS_MAGIC)?;
+    // Space for the index, which is only known once all the hashes are written.
+    (0..=PREFIX_BUCKETS).try_for_each(|_| bwrite.write_all(&0u64.to_le_bytes()))?;
+
+    let mut counts = vec![0u64; PREFIX_BUCKETS];
+    let mut last: Option<[u8; 20]> = None;
+    for f in dumps.iter() {
+        info!("Reading {:?} ...", f);
+        for line in BufReader::new(File::open(f)?).lines() {
+            let line = line?;
+            let hash = match parse_sha1(line.as_str()) {
+                Some(h) => h,
+                None => {
+                    debug!("Skipping invalid line -> {}", line);
+                    continue;
+                }
+            };
+            match last {
+                Some(l) if l == hash => continue,
+                Some(l) if l > hash => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "dumps are not ordered by hash",
+                    ))
+                }
+                _ => {}
+            }
+            counts[usize::from(u16::from_be_bytes([hash[0], hash[1]]))] += 1;
+            bwrite.write_all(&hash)?;
+            last = Some(hash);
+        }
+    }
+
+    bwrite.seek(SeekFrom::Start(BREACH_CORPUS_MAGIC.len() as u64))?;
+    let mut start: u64 = 0;
+    for count in counts.iter() {
+        bwrite.write_all(&start.to_le_bytes())?;
+        start += count;
+    }
+    bwrite.write_all(&start.to_le_bytes())?;
+    bwrite.flush()?;
+    Ok(start)
+}
+
 fn main() {
     let opt = BadlistProcOpt::from_args();
     if opt.debug {
@@ -38,6 +103,24 @@ fn main() {
     }
     info!("Kanidm badlist preprocessor - this may take a long time ...");
 
+    if opt.hibp {
+        // Write alongside the output and then replace it, so that a running server never
+        // reads a partial corpus.
+        let mut tmpfile = opt.outfile.clone().into_os_string();
+        tmpfile.push(".tmp");
+        let tmpfile = PathBuf::from(tmpfile);
+        match write_breach_corpus(&tmpfile, &opt.password_list)
+            .and_then(|count| std::fs::rename(&tmpfile, &opt.outfile).map(|_| count))
+        {
+            Ok(count) => info!(
+                "Wrote {} hashes. next step: set breach_corpus_path in server.toml to {:?}",
+                count, opt.outfile
+            ),
+            Err(e) => error!("Failed to write breach corpus - {:?}", e),
+        }
+        return;
+    }
+
     // We open the file early to find out if we can create it or not.
     let fileout = match File::create(opt.outfile) {
         Ok(f) => f,
@@ -1,7 +1,9 @@
-use crate::password_prompt;
+use crate::{password_error, password_prompt};
 use crate::{
-    AccountCredential, AccountOpt, AccountPosix, AccountRadius, AccountSsh, AccountValidity,
+    AccountAppPassword, AccountCredential, AccountOpt, AccountPosix, AccountRadius, AccountSsh,
+    AccountValidity,
 };
+use kanidm_proto::v1::{AppPasswordScope, TOTPSecret};
 use qrcode::render::unicode;
 use qrcode::QrCode;
 use std::io;
@@ -19,12 +21,21 @@ impl AccountOpt {
                 AccountCredential::RemoveWebauthn(acs) => acs.copt.debug,
                 AccountCredential::RegisterTOTP(acs) => acs.copt.debug,
                 AccountCredential::RemoveTOTP(acs) => acs.copt.debug,
+                AccountCredential::ImportTOTP(acs) => acs.copt.debug,
+                AccountCredential::ResetIntent(acs) => acs.copt.debug,
+                AccountCredential::LockStatus(acs) => acs.copt.debug,
+                AccountCredential::Unlock(acs) => acs.copt.debug,
             },
             AccountOpt::Radius(acopt) => match acopt {
                 AccountRadius::Show(aro) => aro.copt.debug,
                 AccountRadius::Generate(aro) => aro.copt.debug,
                 AccountRadius::Delete(aro) => aro.copt.debug,
             },
+            AccountOpt::AppPassword(aaopt) => match aaopt {
+                AccountAppPassword::List(aao) => aao.copt.debug,
+                AccountAppPassword::Generate(aao) => aao.copt.debug,
+                AccountAppPassword::Remove(aao) => aao.copt.debug,
+            },
             AccountOpt::Posix(apopt) => match apopt {
                 AccountPosix::Show(apo) => apo.copt.debug,
                 AccountPosix::Set(apo) => apo.copt.debug,
@@ -67,7 +78,7 @@ impl AccountOpt {
                         acsopt.aopts.account_id.as_str(),
                         password.as_str(),
                     ) {
-                        eprintln!("Error -> {:?}", e);
+                        password_error(&e);
                     }
                 }
                 AccountCredential::GeneratePassword(acsopt) => {
@@ -216,6 +227,7 @@ impl AccountOpt {
                     let client = acsopt.copt.to_client();
                     match client.idm_account_primary_credential_remove_totp(
                         acsopt.aopts.account_id.as_str(),
+                        acsopt.tag.as_str(),
                     ) {
                         Ok(_) => {
                             println!("TOTP removal success.");
@@ -225,6 +237,87 @@ impl AccountOpt {
                         }
                     }
                 }
+                AccountCredential::ImportTOTP(acsopt) => {
+                    let tok = match (&acsopt.uri, &acsopt.secret) {
+                        (Some(uri), _) => TOTPSecret::from_uri(uri.as_str()),
+                        (None, Some(secret)) => TOTPSecret::decode_secret(secret.as_str())
+                            .and_then(|secret| {
+                                acsopt.algo.parse().map(|algo| TOTPSecret {
+                                    accountname: acsopt.aopts.account_id.clone(),
+                                    issuer: String::new(),
+                                    secret,
+                                    algo,
+                                    step: acsopt.step,
+                                    digits: acsopt.digits,
+                                })
+                            }),
+                        (None, None) => Err("one of --uri or --secret is required".to_string()),
+                    };
+                    let tok = match tok {
+                        Ok(t) => t,
+                        Err(e) => {
+                            eprintln!("Error -> {}", e);
+                            return;
+                        }
+                    };
+                    let client = acsopt.copt.to_client();
+                    match client.idm_account_primary_credential_import_totp(
+                        acsopt.aopts.account_id.as_str(),
+                        acsopt.tag.as_str(),
+                        tok,
+                    ) {
+                        Ok(_) => {
+                            println!("TOTP import success.");
+                        }
+                        Err(e) => {
+                            eprintln!("Error Importing TOTP to account -> {:?}", e);
+                        }
+                    }
+                }
+                AccountCredential::ResetIntent(acsopt) => {
+                    let client = acsopt.copt.to_client();
+                    match client.idm_account_credential_reset_intent(
+                        acsopt.aopts.account_id.as_str(),
+                        acsopt.ttl,
+                    ) {
+                        Ok(intent) => {
+                            println!(
+                                "Credential reset token for {} (valid until {}):",
+                                acsopt.aopts.account_id, intent.expiry
+                            );
+                            println!("{}", intent.token);
+                            eprintln!(
+                                "The account holder can redeem this with: kanidm self credential reset --token <token>"
+                            );
+                        }
+                        Err(e) => {
+                            eprintln!("Error -> {:?}", e);
+                        }
+                    }
+                }
+                AccountCredential::LockStatus(acsopt) => {
+                    let client = acsopt.copt.to_client();
+                    match client.idm_account_softlock_status(acsopt.aopts.account_id.as_str()) {
+                        Ok(locks) if locks.is_empty() => {
+                            println!("No credentials are softlocked.");
+                        }
+                        Ok(locks) => locks.iter().for_each(|l| println!("{}", l)),
+                        Err(e) => {
+                            eprintln!("Error -> {:?}", e);
+                        }
+                    }
+                }
+                AccountCredential::Unlock(acsopt) => {
+                    let client = acsopt.copt.to_client();
+                    match client.idm_account_softlock_clear(acsopt.aopts.account_id.as_str()) {
+                        Ok(_) => {
+                            println!("Credential softlocks cleared.");
+                        }
+                        Err(e) => {
+                            eprintln!("Error -> {:?}", e);
+                        }
+                    }
+                }
             }, // end AccountOpt::Credential
             AccountOpt::Radius(aropt) => match aropt {
                 AccountRadius::Show(aopt) => {
@@ -258,6 +351,49 @@ impl AccountOpt {
                     }
                 }
             }, // end AccountOpt::Radius
+            AccountOpt::AppPassword(aaopt) => match aaopt {
+                AccountAppPassword::List(aopt) => {
+                    let client = aopt.copt.to_client();
+                    match client.idm_account_app_password_list(aopt.aopts.account_id.as_str()) {
+                        Ok(app_pws) => app_pws.iter().for_each(|a| println!("{}", a)),
+                        Err(e) => {
+                            eprintln!("Error -> {:?}", e);
+                        }
+                    }
+                }
+                AccountAppPassword::Generate(aopt) => {
+                    let scope: AppPasswordScope = match aopt.scope.parse() {
+                        Ok(s) => s,
+                        Err(e) => {
+                            eprintln!("Error -> {}", e);
+                            return;
+                        }
+                    };
+                    let client = aopt.copt.to_client();
+                    match client.idm_account_app_password_generate(
+                        aopt.aopts.account_id.as_str(),
+                        aopt.label.as_str(),
+                        scope,
+                    ) {
+                        Ok(pw) => {
+                            println!("Success: {}", pw);
+                            println!("This password will not be displayed again.");
+                        }
+                        Err(e) => {
+                            eprintln!("Error -> {:?}", e);
+                        }
+                    }
+                }
+                AccountAppPassword::Remove(aopt) => {
+                    let client = aopt.copt.to_client();
+                    if let Err(e) = client.idm_account_app_password_remove(
+                        aopt.aopts.account_id.as_str(),
+                        aopt.tag.as_str(),
+                    ) {
+                        eprintln!("Error -> {:?}", e);
+                    }
+                }
+            }, // end AccountOpt::AppPassword
             AccountOpt::Posix(apopt) => match apopt {
                 AccountPosix::Show(aopt) => {
                     let client = aopt.copt.to_client();
@@ -292,7 +428,7 @@ impl AccountOpt {
                         aopt.aopts.account_id.as_str(),
                         password.as_str(),
                     ) {
-                        eprintln!("Error -> {:?}", e);
+                        password_error(&e);
                     }
                 }
             }, // end AccountOpt::Posix
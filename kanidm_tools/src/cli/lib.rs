@@ -10,8 +10,14 @@
 
 #[macro_use]
 extern crate log;
+use kanidm_client::ClientError;
+use kanidm_proto::v1::{OperationError, SetCredentialRequest, SetCredentialResponse};
+use qrcode::render::unicode;
+use qrcode::QrCode;
+use std::io;
 use std::path::PathBuf;
 use structopt::StructOpt;
+use webauthn_authenticator_rs::{u2fhid::U2FHid, WebauthnAuthenticator};
 
 include!("../opt/kanidm.rs");
 
@@ -19,14 +25,21 @@ pub mod account;
 pub mod common;
 pub mod group;
 pub mod login;
+pub mod oauth2;
 pub mod raw;
 pub mod recycle;
+pub mod serviceaccount;
 
 impl SelfOpt {
     pub fn debug(&self) -> bool {
         match self {
             SelfOpt::Whoami(copt) => copt.debug,
             SelfOpt::SetPassword(copt) => copt.debug,
+            SelfOpt::Credential(scopt) => match scopt {
+                SelfCredentialOpt::Reset(scro) => scro.copt.debug,
+                SelfCredentialOpt::BackupCodes(copt) => copt.debug,
+            },
+            SelfOpt::Ssh(SelfSshOpt::Sign(ssso)) => ssso.copt.debug,
         }
     }
 
@@ -59,13 +72,261 @@ impl SelfOpt {
                 };
 
                 if let Err(e) = client.idm_account_set_password(password) {
-                    eprintln!("Error -> {:?}", e);
+                    password_error(&e);
                 }
             }
+
+            SelfOpt::Credential(scopt) => match scopt {
+                SelfCredentialOpt::Reset(scro) => credential_reset(scro),
+                SelfCredentialOpt::BackupCodes(copt) => backup_codes(copt),
+            },
+            SelfOpt::Ssh(SelfSshOpt::Sign(ssso)) => ssh_sign(ssso),
         }
     }
 }
 
+fn credential_reset(scro: &SelfCredentialResetOpt) {
+    // The token is the credential here, so we don't need a logged in session.
+    let client = scro.copt.to_unauth_client();
+    let token = scro.token.as_str();
+
+    let session = match client.idm_credential_reset_begin(token) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error redeeming reset token -> {:?}", e);
+            return;
+        }
+    };
+    let sessionid = session.sessionid;
+
+    eprintln!("Resetting the credentials of {}", session.spn);
+
+    let password = match password_prompt("Enter new password: ") {
+        Some(v) => v,
+        None => {
+            eprintln!("Passwords do not match");
+            return;
+        }
+    };
+
+    if let Err(e) =
+        client.idm_credential_reset_step(sessionid, token, SetCredentialRequest::Password(password))
+    {
+        eprintln!("Error setting password -> {:?}", e);
+        return;
+    }
+    println!("Password set.");
+
+    let mut mfa_registered = false;
+
+    if yes_no_prompt("Do you want to register a TOTP? [y/N] ") {
+        let (totp_session, tok) = match client.idm_credential_reset_step(
+            sessionid,
+            token,
+            SetCredentialRequest::TOTPGenerate(session.spn.clone()),
+        ) {
+            Ok(SetCredentialResponse::TOTPCheck(s, t)) => (s, t),
+            Ok(_) => {
+                eprintln!("Error Starting Registration -> unexpected response");
+                return;
+            }
+            Err(e) => {
+                eprintln!("Error Starting Registration -> {:?}", e);
+                return;
+            }
+        };
+
+        eprintln!("Scan the following QR code with your OTP app.");
+        match QrCode::new(tok.to_uri().as_str()) {
+            Ok(code) => {
+                let image = code
+                    .render::<unicode::Dense1x2>()
+                    .dark_color(unicode::Dense1x2::Light)
+                    .light_color(unicode::Dense1x2::Dark)
+                    .build();
+                eprintln!("{}", image);
+            }
+            Err(e) => eprintln!("Failed to generate QR code -> {:?}", e),
+        };
+        eprintln!("Alternatively, you can manually enter the following OTP details:");
+        println!("Account Name: {}", tok.accountname);
+        println!("Issuer: {}", tok.issuer);
+        println!("Algorithm: {}", tok.algo.to_string());
+        println!("Period/Step: {}", tok.step);
+        println!("Secret: {}", tok.get_secret());
+
+        eprintln!("--------------------------------------------------------------");
+        eprint!("Enter a TOTP from your authenticator to complete registration: \nTOTP: ");
+
+        let mut totp_input = String::new();
+        if let Err(e) = io::stdin().read_line(&mut totp_input) {
+            eprintln!("Failed to read from stdin -> {:?}", e);
+            return;
+        };
+
+        let totp = match u32::from_str_radix(totp_input.trim(), 10) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Invalid TOTP -> {:?}", e);
+                return;
+            }
+        };
+
+        match client.idm_credential_reset_step(
+            sessionid,
+            token,
+            SetCredentialRequest::TOTPVerify(totp_session, totp),
+        ) {
+            Ok(SetCredentialResponse::Success) => {
+                println!("TOTP registration success.");
+                mfa_registered = true;
+            }
+            Ok(_) => {
+                eprintln!("TOTP verification failed.");
+                return;
+            }
+            Err(e) => {
+                eprintln!("Error Completing -> {:?}", e);
+                return;
+            }
+        }
+    }
+
+    if yes_no_prompt("Do you want to register a webauthn token? [y/N] ") {
+        eprint!("Enter a label for the token: ");
+        let mut label = String::new();
+        if let Err(e) = io::stdin().read_line(&mut label) {
+            eprintln!("Failed to read from stdin -> {:?}", e);
+            return;
+        };
+
+        let (wa_session, chal) = match client.idm_credential_reset_step(
+            sessionid,
+            token,
+            SetCredentialRequest::WebauthnBegin(label.trim().to_string()),
+        ) {
+            Ok(SetCredentialResponse::WebauthnCreateChallenge(s, c)) => (s, c),
+            Ok(_) => {
+                eprintln!("Error Starting Registration -> unexpected response");
+                return;
+            }
+            Err(e) => {
+                eprintln!("Error Starting Registration -> {:?}", e);
+                return;
+            }
+        };
+
+        let mut wa = WebauthnAuthenticator::new(U2FHid::new());
+        eprintln!("Your authenticator will now flash for you to interact with.");
+
+        let rego = match wa.do_registration(client.get_origin(), chal) {
+            Ok(rego) => rego,
+            Err(e) => {
+                eprintln!("Error Signing -> {:?}", e);
+                return;
+            }
+        };
+
+        match client.idm_credential_reset_step(
+            sessionid,
+            token,
+            SetCredentialRequest::WebauthnRegister(wa_session, rego),
+        ) {
+            Ok(_) => {
+                println!("Webauthn token registration success.");
+                mfa_registered = true;
+            }
+            Err(e) => eprintln!("Error Completing -> {:?}", e),
+        }
+    }
+
+    if mfa_registered
+        && yes_no_prompt(
+            "Do you want to generate backup codes in case you lose your TOTP or token? [y/N] ",
+        )
+    {
+        match client.idm_credential_reset_step(
+            sessionid,
+            token,
+            SetCredentialRequest::BackupCodeGenerate,
+        ) {
+            Ok(SetCredentialResponse::BackupCodes(codes)) => print_backup_codes(&codes),
+            Ok(_) => eprintln!("Error Generating Backup Codes -> unexpected response"),
+            Err(e) => eprintln!("Error Generating Backup Codes -> {:?}", e),
+        }
+    }
+}
+
+fn backup_codes(copt: &CommonOpt) {
+    let client = copt.to_client();
+
+    let uat = match client.whoami() {
+        Ok(Some((_, uat))) => uat,
+        Ok(None) => {
+            eprintln!("Unauthenticated");
+            return;
+        }
+        Err(e) => {
+            eprintln!("Error -> {:?}", e);
+            return;
+        }
+    };
+
+    match client.idm_account_primary_credential_generate_backup_codes(uat.uuid.as_str()) {
+        Ok(codes) => print_backup_codes(&codes),
+        Err(e) => eprintln!("Error Generating Backup Codes -> {:?}", e),
+    }
+}
+
+fn ssh_sign(ssso: &SelfSshSignOpt) {
+    let publickey = match std::fs::read_to_string(&ssso.publickey) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Unable to read {:?} -> {:?}", ssso.publickey, e);
+            return;
+        }
+    };
+
+    // ssh looks for id_ed25519-cert.pub beside id_ed25519 and id_ed25519.pub.
+    let output = match &ssso.output {
+        Some(o) => o.clone(),
+        None => {
+            let stem = ssso
+                .publickey
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            ssso.publickey.with_file_name(format!("{}-cert.pub", stem))
+        }
+    };
+
+    let client = ssso.copt.to_client();
+    match client.idm_account_ssh_sign(publickey.trim()) {
+        Ok(cert) => match std::fs::write(&output, format!("{}\n", cert)) {
+            Ok(_) => eprintln!("Wrote certificate to {:?}", output),
+            Err(e) => eprintln!("Unable to write {:?} -> {:?}", output, e),
+        },
+        Err(e) => eprintln!("Error Signing Key -> {:?}", e),
+    }
+}
+
+fn print_backup_codes(codes: &[String]) {
+    eprintln!("Store these backup codes somewhere safe. Each can be used once in place of your");
+    eprintln!("TOTP or webauthn token, and any previous backup codes no longer work.");
+    for code in codes {
+        println!("{}", code);
+    }
+}
+
+fn yes_no_prompt(prompt: &str) -> bool {
+    eprint!("{}", prompt);
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input).is_err() {
+        return false;
+    }
+    matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
 impl KanidmClientOpt {
     pub fn debug(&self) -> bool {
         match self {
@@ -74,6 +335,8 @@ impl KanidmClientOpt {
             KanidmClientOpt::CSelf(csopt) => csopt.debug(),
             KanidmClientOpt::Account(aopt) => aopt.debug(),
             KanidmClientOpt::Group(gopt) => gopt.debug(),
+            KanidmClientOpt::Oauth2(oopt) => oopt.debug(),
+            KanidmClientOpt::ServiceAccount(sopt) => sopt.debug(),
             KanidmClientOpt::Recycle(ropt) => ropt.debug(),
         }
     }
@@ -85,6 +348,8 @@ impl KanidmClientOpt {
             KanidmClientOpt::CSelf(csopt) => csopt.exec(),
             KanidmClientOpt::Account(aopt) => aopt.exec(),
             KanidmClientOpt::Group(gopt) => gopt.exec(),
+            KanidmClientOpt::Oauth2(oopt) => oopt.exec(),
+            KanidmClientOpt::ServiceAccount(sopt) => sopt.exec(),
             KanidmClientOpt::Recycle(ropt) => ropt.exec(),
         }
     }
@@ -111,3 +376,41 @@ pub(crate) fn password_prompt(prompt: &str) -> Option<String> {
     }
     None
 }
+
+/// Explain why the server rejected a new password, or show the error if it wasn't the
+/// password itself.
+pub(crate) fn password_error(e: &ClientError) {
+    match e {
+        ClientError::Http(_, Some(OperationError::PasswordTooShort(len)), _) => {
+            eprintln!("The password must be at least {} characters long", len)
+        }
+        ClientError::Http(_, Some(OperationError::PasswordTooFewCharClasses(n)), _) => {
+            eprintln!(
+                "The password must contain at least {} of lower case letters, upper case letters, digits and symbols",
+                n
+            )
+        }
+        ClientError::Http(_, Some(OperationError::PasswordTooWeak(feedback)), _) => {
+            eprintln!("The password is too easy to guess");
+            if let Some(feedback) = feedback {
+                if let Some(warning) = feedback.warning() {
+                    eprintln!("{}", warning);
+                }
+                feedback
+                    .suggestions()
+                    .iter()
+                    .for_each(|s| eprintln!(" * {}", s));
+            }
+        }
+        ClientError::Http(_, Some(OperationError::PasswordBadListed), _) => {
+            eprintln!("The password, or a word in it, is not allowed")
+        }
+        ClientError::Http(_, Some(OperationError::PasswordBreached), _) => {
+            eprintln!("The password has appeared in a known data breach")
+        }
+        ClientError::Http(_, Some(OperationError::PasswordInHistory), _) => {
+            eprintln!("The password has been used recently")
+        }
+        _ => eprintln!("Error -> {:?}", e),
+    }
+}
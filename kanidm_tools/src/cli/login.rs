@@ -1,4 +1,4 @@
-use crate::LoginOpt;
+use crate::{password_error, password_prompt, LoginOpt};
 use kanidm_client::{ClientError, KanidmClient};
 use kanidm_proto::v1::{AuthAllowed, AuthResponse, AuthState};
 use libc::umask;
@@ -6,10 +6,13 @@ use std::collections::BTreeMap;
 use std::fs::{create_dir, File};
 use std::io::{self, BufReader, BufWriter};
 use std::path::PathBuf;
+use std::time::SystemTime;
 use webauthn_authenticator_rs::{u2fhid::U2FHid, RequestChallengeResponse, WebauthnAuthenticator};
 
 static TOKEN_DIR: &str = "~/.cache";
 static TOKEN_PATH: &str = "~/.cache/kanidm_tokens";
+// Warn about a password that expires within this many seconds.
+const PASSWORD_EXPIRY_WARN_SECS: i64 = 14 * 86400;
 
 pub fn read_tokens() -> Result<BTreeMap<String, String>, ()> {
     let token_path = PathBuf::from(shellexpand::tilde(TOKEN_PATH).into_owned());
@@ -117,6 +120,54 @@ impl LoginOpt {
         client.auth_step_password(password.as_str())
     }
 
+    fn do_password_change(&self, client: &mut KanidmClient) -> Result<(), ClientError> {
+        println!("Your password has expired and must be changed.");
+        for _ in 0..3 {
+            let password = match password_prompt("Enter new password: ") {
+                Some(v) => v,
+                None => {
+                    println!("Passwords do not match");
+                    continue;
+                }
+            };
+
+            // A rejected password leaves the session waiting for a change, so
+            // we can try again.
+            match client.auth_step_password_change(password.as_str()) {
+                Ok(r) => match r.state {
+                    AuthState::Success(_token) => return Ok(()),
+                    AuthState::Denied(reason) => {
+                        error!("Authentication Denied: {:?}", reason);
+                        std::process::exit(1);
+                    }
+                    _ => return Err(ClientError::AuthenticationFailed),
+                },
+                Err(e) => password_error(&e),
+            }
+        }
+        Err(ClientError::AuthenticationFailed)
+    }
+
+    fn warn_password_expiry(&self, client: &KanidmClient) {
+        let expiry = match client.whoami() {
+            Ok(Some((_e, uat))) => uat.password_expiry,
+            _ => None,
+        };
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64);
+
+        if let (Some(expiry), Ok(now)) = (expiry, now) {
+            let remain = expiry - now;
+            if remain <= PASSWORD_EXPIRY_WARN_SECS {
+                println!(
+                    "Your password will expire in {} days. Change it with `kanidm self set_password`.",
+                    remain.max(0) / 86400
+                );
+            }
+        }
+    }
+
     fn do_totp(&self, client: &mut KanidmClient) -> Result<AuthResponse, ClientError> {
         let totp = loop {
             println!("Enter TOTP: ");
@@ -135,6 +186,16 @@ impl LoginOpt {
         client.auth_step_totp(totp)
     }
 
+    fn do_backup_code(&self, client: &mut KanidmClient) -> Result<AuthResponse, ClientError> {
+        println!("Enter Backup Code: ");
+        let mut buffer = String::new();
+        if let Err(e) = io::stdin().read_line(&mut buffer) {
+            eprintln!("Failed to read from stdin -> {:?}", e);
+            return Err(ClientError::SystemError);
+        };
+        client.auth_step_backup_code(buffer.trim())
+    }
+
     fn do_webauthn(
         &self,
         client: &mut KanidmClient,
@@ -247,6 +308,8 @@ impl LoginOpt {
                 AuthAllowed::Anonymous => client.auth_step_anonymous(),
                 AuthAllowed::Password => self.do_password(&mut client),
                 AuthAllowed::TOTP => self.do_totp(&mut client),
+                AuthAllowed::BackupCode => self.do_backup_code(&mut client),
+                AuthAllowed::ClientCertificate => client.auth_step_client_certificate(),
                 AuthAllowed::Webauthn(chal) => self.do_webauthn(&mut client, chal.clone()),
             };
 
@@ -263,6 +326,13 @@ impl LoginOpt {
             allowed = match &state {
                 AuthState::Continue(allowed) => allowed.to_vec(),
                 AuthState::Success(_token) => break,
+                AuthState::PasswordExpired => match self.do_password_change(&mut client) {
+                    Ok(()) => break,
+                    Err(e) => {
+                        error!("Error in authentication phase: {:?}", e);
+                        std::process::exit(1);
+                    }
+                },
                 AuthState::Denied(reason) => {
                     error!("Authentication Denied: {:?}", reason);
                     std::process::exit(1);
@@ -300,5 +370,6 @@ impl LoginOpt {
 
         // Success!
         println!("Login Success for {}", username);
+        self.warn_password_expiry(&client);
     }
 }
@@ -0,0 +1,87 @@
+use crate::{ApiTokenOpt, ServiceAccountOpt};
+
+impl ServiceAccountOpt {
+    pub fn debug(&self) -> bool {
+        match self {
+            ServiceAccountOpt::List(copt) => copt.debug,
+            ServiceAccountOpt::Get(nopt) => nopt.copt.debug,
+            ServiceAccountOpt::Create(copt) => copt.nopt.copt.debug,
+            ServiceAccountOpt::Delete(nopt) => nopt.copt.debug,
+            ServiceAccountOpt::ApiToken(atopt) => match atopt {
+                ApiTokenOpt::List(nopt) => nopt.copt.debug,
+                ApiTokenOpt::Generate(gopt) => gopt.nopt.copt.debug,
+                ApiTokenOpt::Destroy(dopt) => dopt.nopt.copt.debug,
+            },
+        }
+    }
+
+    pub fn exec(&self) {
+        match self {
+            ServiceAccountOpt::List(copt) => {
+                let client = copt.to_client();
+                match client.idm_service_account_list() {
+                    Ok(r) => r.iter().for_each(|e| println!("{}", e)),
+                    Err(e) => eprintln!("Error -> {:?}", e),
+                }
+            }
+            ServiceAccountOpt::Get(nopt) => {
+                let client = nopt.copt.to_client();
+                match client.idm_service_account_get(nopt.name.as_str()) {
+                    Ok(Some(e)) => println!("{}", e),
+                    Ok(None) => println!("No matching entries"),
+                    Err(e) => eprintln!("Error -> {:?}", e),
+                }
+            }
+            ServiceAccountOpt::Create(copt) => {
+                let client = copt.nopt.copt.to_client();
+                match client
+                    .idm_service_account_create(copt.nopt.name.as_str(), copt.displayname.as_str())
+                {
+                    Ok(_) => println!("Success"),
+                    Err(e) => eprintln!("Error -> {:?}", e),
+                }
+            }
+            ServiceAccountOpt::Delete(nopt) => {
+                let client = nopt.copt.to_client();
+                match client.idm_service_account_delete(nopt.name.as_str()) {
+                    Ok(_) => println!("Success"),
+                    Err(e) => eprintln!("Error -> {:?}", e),
+                }
+            }
+            ServiceAccountOpt::ApiToken(atopt) => match atopt {
+                ApiTokenOpt::List(nopt) => {
+                    let client = nopt.copt.to_client();
+                    match client.idm_service_account_api_token_list(nopt.name.as_str()) {
+                        Ok(r) => r.iter().for_each(|t| println!("{}", t)),
+                        Err(e) => eprintln!("Error -> {:?}", e),
+                    }
+                }
+                ApiTokenOpt::Generate(gopt) => {
+                    let client = gopt.nopt.copt.to_client();
+                    match client.idm_service_account_api_token_generate(
+                        gopt.nopt.name.as_str(),
+                        gopt.label.as_str(),
+                        gopt.expiry.as_deref(),
+                        gopt.read_only,
+                    ) {
+                        Ok(token) => {
+                            println!("Success: This token will only be displayed ONCE");
+                            println!("{}", token)
+                        }
+                        Err(e) => eprintln!("Error -> {:?}", e),
+                    }
+                }
+                ApiTokenOpt::Destroy(dopt) => {
+                    let client = dopt.nopt.copt.to_client();
+                    match client.idm_service_account_api_token_destroy(
+                        dopt.nopt.name.as_str(),
+                        dopt.token_id.as_str(),
+                    ) {
+                        Ok(_) => println!("Success"),
+                        Err(e) => eprintln!("Error -> {:?}", e),
+                    }
+                }
+            },
+        }
+    }
+}
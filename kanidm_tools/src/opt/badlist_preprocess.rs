@@ -5,6 +5,10 @@ struct BadlistProcOpt {
     debug: bool,
     #[structopt(short = "m", long = "modlist")]
     modlist: bool,
+    /// Generate a breach corpus for kanidmd from Have I Been Pwned SHA1 dumps that are
+    /// ordered by hash.
+    #[structopt(long = "hibp")]
+    hibp: bool,
     #[structopt(short = "o", long = "output")]
     outfile: PathBuf,
     #[structopt(parse(from_os_str))]
@@ -92,6 +92,42 @@ pub struct AccountNamedOpt {
     copt: CommonOpt,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct AccountCredentialResetIntentOpt {
+    #[structopt(flatten)]
+    aopts: AccountCommonOpt,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+    /// The number of seconds the reset token is valid for. Defaults to one hour.
+    #[structopt(long = "ttl")]
+    ttl: Option<u64>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AccountCredentialImportTOTPOpt {
+    #[structopt(flatten)]
+    aopts: AccountCommonOpt,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+    #[structopt(name = "tag")]
+    tag: String,
+    /// An otpauth://totp/ uri. The other options are ignored if this is given.
+    #[structopt(long = "uri")]
+    uri: Option<String>,
+    /// The base32 encoded secret.
+    #[structopt(long = "secret")]
+    secret: Option<String>,
+    /// One of "sha1", "sha256" or "sha512".
+    #[structopt(long = "algo", default_value = "sha1")]
+    algo: String,
+    /// The number of digits in each code, 6 or 8.
+    #[structopt(long = "digits", default_value = "6")]
+    digits: u8,
+    /// The number of seconds each code is valid for.
+    #[structopt(long = "step", default_value = "30")]
+    step: u64,
+}
+
 #[derive(Debug, StructOpt)]
 pub struct AccountNamedExpireDateTimeOpt {
     #[structopt(flatten)]
@@ -158,13 +194,29 @@ pub enum AccountCredential {
     RegisterWebauthn(AccountNamedTagOpt),
     #[structopt(name = "remove_webauthn")]
     RemoveWebauthn(AccountNamedTagOpt),
-    /// Set the TOTP credential of the account. If a TOTP already exists, on a successful
-    /// registration, this will replace it.
+    /// Add a TOTP credential to the account. If a TOTP with the same tag already exists,
+    /// on a successful registration, this will replace it.
     #[structopt(name = "set_totp")]
     RegisterTOTP(AccountNamedTagOpt),
-    /// Remove TOTP from the account. If no TOTP exists, no action is taken.
+    /// Remove the TOTP with this tag from the account.
     #[structopt(name = "remove_totp")]
-    RemoveTOTP(AccountNamedOpt),
+    RemoveTOTP(AccountNamedTagOpt),
+    /// Import an existing TOTP secret, such as from a hardware token or another system,
+    /// from either an otpauth:// uri or a base32 secret.
+    #[structopt(name = "import_totp")]
+    ImportTOTP(AccountCredentialImportTOTPOpt),
+    /// Issue a single use, time limited token that allows the account holder to
+    /// reset their own credentials with `kanidm self credential reset`.
+    #[structopt(name = "reset_intent")]
+    ResetIntent(AccountCredentialResetIntentOpt),
+    /// Show the credentials of the account that are softlocked after repeated
+    /// authentication failures.
+    #[structopt(name = "lock_status")]
+    LockStatus(AccountNamedOpt),
+    /// Clear any softlocks on the credentials of the account, allowing authentication
+    /// to be attempted again immediately.
+    #[structopt(name = "unlock")]
+    Unlock(AccountNamedOpt),
 }
 
 #[derive(Debug, StructOpt)]
@@ -177,6 +229,32 @@ pub enum AccountRadius {
     Delete(AccountNamedOpt),
 }
 
+#[derive(Debug, StructOpt)]
+pub struct AccountAppPasswordGenerateOpt {
+    #[structopt(flatten)]
+    aopts: AccountCommonOpt,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+    #[structopt(name = "label")]
+    label: String,
+    #[structopt(name = "scope")]
+    /// Where the password may be used, one of "ldap", "unix" or "radius".
+    scope: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum AccountAppPassword {
+    #[structopt(name = "list")]
+    /// List the app passwords of an account
+    List(AccountNamedOpt),
+    #[structopt(name = "generate")]
+    /// Generate a new app password. It is only displayed once.
+    Generate(AccountAppPasswordGenerateOpt),
+    #[structopt(name = "remove")]
+    /// Remove an app password by label
+    Remove(AccountNamedTagOpt),
+}
+
 #[derive(Debug, StructOpt)]
 pub struct AccountPosixOpt {
     #[structopt(flatten)]
@@ -225,6 +303,8 @@ pub enum AccountOpt {
     Credential(AccountCredential),
     #[structopt(name = "radius")]
     Radius(AccountRadius),
+    #[structopt(name = "app_password")]
+    AppPassword(AccountAppPassword),
     #[structopt(name = "posix")]
     Posix(AccountPosix),
     #[structopt(name = "ssh")]
@@ -254,6 +334,97 @@ pub enum RecycleOpt {
     Revive(Named),
 }
 
+#[derive(Debug, StructOpt)]
+pub struct Oauth2BasicCreateOpt {
+    #[structopt(flatten)]
+    nopt: Named,
+    #[structopt(name = "displayname")]
+    displayname: String,
+    #[structopt(name = "origin")]
+    origin: String,
+    #[structopt(name = "redirect_uri")]
+    redirect_uri: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Oauth2Opt {
+    #[structopt(name = "list")]
+    /// List all configured oauth2 resource servers
+    List(CommonOpt),
+    #[structopt(name = "get")]
+    /// Display a selected oauth2 resource server
+    Get(Named),
+    #[structopt(name = "create")]
+    /// Create a new oauth2 resource server that authenticates with a basic secret
+    CreateBasic(Oauth2BasicCreateOpt),
+    #[structopt(name = "delete")]
+    /// Delete a oauth2 resource server
+    Delete(Named),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ServiceAccountCreateOpt {
+    #[structopt(flatten)]
+    nopt: Named,
+    #[structopt(name = "displayname")]
+    displayname: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ApiTokenGenerateOpt {
+    #[structopt(flatten)]
+    nopt: Named,
+    #[structopt(name = "label")]
+    label: String,
+    #[structopt(long = "expiry")]
+    /// An rfc3339 time of the format "YYYY-MM-DDTHH:MM:SS+TZ", "2020-09-25T11:22:02+10:00"
+    /// after which the token is no longer valid. If not set the token does not expire.
+    expiry: Option<String>,
+    #[structopt(long = "read-only")]
+    /// Only allow the token to be used for read operations.
+    read_only: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ApiTokenDestroyOpt {
+    #[structopt(flatten)]
+    nopt: Named,
+    #[structopt(name = "token_id")]
+    token_id: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ApiTokenOpt {
+    #[structopt(name = "list")]
+    /// List the api tokens of a service account
+    List(Named),
+    #[structopt(name = "generate")]
+    /// Generate a new api token for a service account
+    Generate(ApiTokenGenerateOpt),
+    #[structopt(name = "destroy")]
+    /// Revoke an api token of a service account
+    Destroy(ApiTokenDestroyOpt),
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ServiceAccountOpt {
+    #[structopt(name = "list")]
+    /// List all service accounts
+    List(CommonOpt),
+    #[structopt(name = "get")]
+    /// Display a selected service account
+    Get(Named),
+    #[structopt(name = "create")]
+    /// Create a new service account
+    Create(ServiceAccountCreateOpt),
+    #[structopt(name = "delete")]
+    /// Delete a service account
+    Delete(Named),
+    #[structopt(name = "api_token")]
+    /// Manage the api tokens of a service account
+    ApiToken(ApiTokenOpt),
+}
+
 #[derive(Debug, StructOpt)]
 pub struct LoginOpt {
     #[structopt(flatten)]
@@ -300,6 +471,47 @@ pub enum RawOpt {
     Delete(FilterOpt),
 }
 
+#[derive(Debug, StructOpt)]
+pub struct SelfCredentialResetOpt {
+    #[structopt(flatten)]
+    copt: CommonOpt,
+    /// The reset token that was issued by an administrator
+    #[structopt(long = "token")]
+    token: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum SelfCredentialOpt {
+    #[structopt(name = "reset")]
+    /// Redeem a credential reset token to set a new password, TOTP and webauthn
+    /// token. This does not require you to be logged in.
+    Reset(SelfCredentialResetOpt),
+    #[structopt(name = "backup-codes")]
+    /// Generate a new set of single use backup codes, replacing any existing codes.
+    /// A TOTP or webauthn token must already be registered.
+    BackupCodes(CommonOpt),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct SelfSshSignOpt {
+    #[structopt(flatten)]
+    copt: CommonOpt,
+    /// The ssh public key file to sign. It must be registered to your account.
+    #[structopt(parse(from_os_str))]
+    publickey: PathBuf,
+    /// Where to write the certificate. Defaults to the -cert.pub file beside the key,
+    /// where ssh will find it.
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum SelfSshOpt {
+    #[structopt(name = "sign")]
+    /// Sign an ssh public key, creating a certificate that expires with your session
+    Sign(SelfSshSignOpt),
+}
+
 #[derive(Debug, StructOpt)]
 pub enum SelfOpt {
     #[structopt(name = "whoami")]
@@ -308,6 +520,12 @@ pub enum SelfOpt {
     #[structopt(name = "set_password")]
     /// Set the current user's password
     SetPassword(CommonOpt),
+    #[structopt(name = "credential")]
+    /// Manage the current user's credentials
+    Credential(SelfCredentialOpt),
+    #[structopt(name = "ssh")]
+    /// Request ssh certificates for the current user's keys
+    Ssh(SelfSshOpt),
 }
 
 #[derive(Debug, StructOpt)]
@@ -325,6 +543,12 @@ pub enum KanidmClientOpt {
     #[structopt(name = "group")]
     /// Group operations
     Group(GroupOpt),
+    #[structopt(name = "oauth2")]
+    /// Configure oauth2 resource servers
+    Oauth2(Oauth2Opt),
+    #[structopt(name = "service_account")]
+    /// Service account and api token operations
+    ServiceAccount(ServiceAccountOpt),
     #[structopt(name = "recycle_bin")]
     /// Recycle Bin operations
     Recycle(RecycleOpt),
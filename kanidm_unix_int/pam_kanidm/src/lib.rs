@@ -19,6 +19,7 @@ use crate::pam::module::{PamHandle, PamHooks};
 use std::collections::BTreeSet;
 use std::convert::TryFrom;
 use std::ffi::CStr;
+use std::time::SystemTime;
 // use std::os::raw::c_char;
 use async_std::task;
 use kanidm_unix_common::client::call_daemon;
@@ -59,6 +60,47 @@ fn get_cfg() -> Result<KanidmUnixdConfig, PamResultCode> {
         .map_err(|_| PamResultCode::PAM_SERVICE_ERR)
 }
 
+// Warn the user about a password that expires within this many seconds.
+const PASSWORD_EXPIRY_WARN_SECS: i64 = 14 * 86400;
+
+fn warn_password_expiry(
+    pamh: &PamHandle,
+    cfg: &KanidmUnixdConfig,
+    account_id: String,
+    opts: &Options,
+) {
+    let req = ClientRequest::PamAccountPasswordExpiry(account_id);
+    let expiry = match task::block_on(call_daemon(cfg.sock_path.as_str(), req)) {
+        Ok(ClientResponse::PamPasswordExpiry(Some(expiry))) => expiry,
+        r => {
+            if opts.debug {
+                println!("No password expiry -> {:?}", r);
+            }
+            return;
+        }
+    };
+
+    let now = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(_) => return,
+    };
+
+    let remain = expiry - now;
+    if remain > PASSWORD_EXPIRY_WARN_SECS {
+        return;
+    }
+
+    // Expired passwords are denied at authentication, and can only be changed
+    // with the kanidm cli, so this is advisory only.
+    let msg = format!(
+        "Your password will expire in {} days. Change it with `kanidm account posix set_password`.",
+        remain.max(0) / 86400
+    );
+    if let Ok(conv) = pamh.get_item::<PamConv>() {
+        let _ = conv.send(PAM_TEXT_INFO, msg.as_str());
+    }
+}
+
 struct PamKanidm;
 pam_hooks!(PamKanidm);
 
@@ -89,13 +131,14 @@ impl PamHooks for PamKanidm {
             Ok(cfg) => cfg,
             Err(e) => return e,
         };
-        let req = ClientRequest::PamAccountAllowed(account_id);
+        let req = ClientRequest::PamAccountAllowed(account_id.clone());
         // PamResultCode::PAM_IGNORE
 
         match task::block_on(call_daemon(cfg.sock_path.as_str(), req)) {
             Ok(r) => match r {
                 ClientResponse::PamStatus(Some(true)) => {
                     // println!("PAM_SUCCESS");
+                    warn_password_expiry(pamh, &cfg, account_id, &opts);
                     PamResultCode::PAM_SUCCESS
                 }
                 ClientResponse::PamStatus(Some(false)) => {
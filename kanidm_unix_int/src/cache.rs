@@ -51,6 +51,16 @@ impl ToString for Id {
     }
 }
 
+fn password_expired(tok: &UnixUserToken) -> bool {
+    match (
+        tok.password_expiry,
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH),
+    ) {
+        (Some(expiry), Ok(now)) => now.as_secs() as i64 >= expiry,
+        _ => false,
+    }
+}
+
 impl CacheLayer {
     // TODO: Could consider refactoring this to be better ...
     #[allow(clippy::too_many_arguments)]
@@ -789,9 +799,11 @@ impl CacheLayer {
         debug!("Attempt offline password check");
         match token.as_ref() {
             Some(t) => {
-                if t.valid {
+                if t.valid && !password_expired(t) {
                     self.check_cache_userpassword(&t.uuid, cred).await.map(Some)
                 } else {
+                    // An expired password can only be changed online, so the cached
+                    // copy is refused here just as the server would.
                     Ok(Some(false))
                 }
             }
@@ -825,6 +837,11 @@ impl CacheLayer {
         }))
     }
 
+    pub async fn pam_account_password_expiry(&self, account_id: &str) -> Result<Option<i64>, ()> {
+        let token = self.get_usertoken(Id::Name(account_id.to_string())).await?;
+        Ok(token.and_then(|tok| tok.password_expiry))
+    }
+
     pub async fn pam_account_authenticate(
         &self,
         account_id: &str,
@@ -183,6 +183,14 @@ async fn handle_client(
                     .map(ClientResponse::PamStatus)
                     .unwrap_or(ClientResponse::Error)
             }
+            ClientRequest::PamAccountPasswordExpiry(account_id) => {
+                debug!("pam account password expiry");
+                cachelayer
+                    .pam_account_password_expiry(account_id.as_str())
+                    .await
+                    .map(ClientResponse::PamPasswordExpiry)
+                    .unwrap_or(ClientResponse::Error)
+            }
             ClientRequest::InvalidateCache => {
                 debug!("invalidate cache");
                 cachelayer
@@ -719,6 +719,7 @@ mod tests {
             groups: Vec::new(),
             sshkeys: vec!["key-a".to_string()],
             valid: true,
+            password_expiry: None,
         };
 
         let id_name = Id::Name("testuser".to_string());
@@ -892,6 +893,7 @@ mod tests {
             groups: vec![gt1.clone(), gt2],
             sshkeys: vec!["key-a".to_string()],
             valid: true,
+            password_expiry: None,
         };
 
         // First, add the groups.
@@ -947,6 +949,7 @@ mod tests {
             groups: Vec::new(),
             sshkeys: vec!["key-a".to_string()],
             valid: true,
+            password_expiry: None,
         };
 
         // Test that with no account, is false
@@ -1050,6 +1053,7 @@ mod tests {
             groups: Vec::new(),
             sshkeys: vec!["key-a".to_string()],
             valid: true,
+            password_expiry: None,
         };
 
         let ut2 = UnixUserToken {
@@ -1062,6 +1066,7 @@ mod tests {
             groups: Vec::new(),
             sshkeys: vec!["key-a".to_string()],
             valid: true,
+            password_expiry: None,
         };
 
         let id_name = Id::Name("testuser".to_string());
@@ -25,6 +25,7 @@ pub enum ClientRequest {
     NssGroupByName(String),
     PamAuthenticate(String, String),
     PamAccountAllowed(String),
+    PamAccountPasswordExpiry(String),
     InvalidateCache,
     ClearCache,
     Status,
@@ -38,6 +39,7 @@ pub enum ClientResponse {
     NssGroups(Vec<NssGroup>),
     NssGroup(Option<NssGroup>),
     PamStatus(Option<bool>),
+    PamPasswordExpiry(Option<i64>),
     Ok,
     Error,
 }
@@ -1,28 +1,36 @@
 use tokio::sync::mpsc::UnboundedSender as Sender;
 
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use crate::audit::AuditScope;
+use crate::crypto::ClientCertificate;
 
 use crate::event::{AuthEvent, AuthResult, SearchEvent, SearchResult, WhoamiResult};
 use crate::idm::event::{
-    RadiusAuthTokenEvent, UnixGroupTokenEvent, UnixUserAuthEvent, UnixUserTokenEvent,
+    AuthAuditSearchEvent, ListApiTokenEvent, ListAppPasswordEvent, RadiusAuthEvent,
+    RadiusAuthTokenEvent, ReadSoftLockEvent, SshCertSignEvent, UnixGroupTokenEvent,
+    UnixUserAuthEvent, UnixUserTokenEvent,
 };
 use crate::value::PartialValue;
-use kanidm_proto::v1::{OperationError, RadiusAuthToken};
+use kanidm_proto::v1::{
+    ApiToken, AppPassword, AuthAuditQuery, AuthAuditRecord, CredentialSoftLock, OperationError,
+    RadiusAuthToken,
+};
 
 use crate::filter::{Filter, FilterInvalid};
 use crate::idm::server::IdmServer;
-use crate::ldap::{LdapBoundToken, LdapResponseState, LdapServer};
+use crate::ldap::{LdapBoundToken, LdapResponseState, LdapServer, LdapWriteRequest};
 use crate::server::{QueryServer, QueryServerTransaction};
 
+use kanidm_proto::oauth2::{JwkKeySet, OidcDiscoveryResponse};
 use kanidm_proto::v1::Entry as ProtoEntry;
 use kanidm_proto::v1::{
     AuthRequest, SearchRequest, SearchResponse, UnixGroupToken, UnixUserToken, UserAuthToken,
     WhoamiResponse,
 };
 
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
 use ldap3_server::simple::*;
@@ -44,14 +52,24 @@ pub struct WhoamiMessage {
 pub struct AuthMessage {
     pub sessionid: Option<Uuid>,
     pub req: AuthRequest,
+    pub source: Option<IpAddr>,
+    pub client_cert: Option<ClientCertificate>,
     pub eventid: Uuid,
 }
 
 impl AuthMessage {
-    pub fn new(req: AuthRequest, sessionid: Option<Uuid>, eventid: Uuid) -> Self {
+    pub fn new(
+        req: AuthRequest,
+        sessionid: Option<Uuid>,
+        source: Option<IpAddr>,
+        client_cert: Option<ClientCertificate>,
+        eventid: Uuid,
+    ) -> Self {
         AuthMessage {
             sessionid,
             req,
+            source,
+            client_cert,
             eventid,
         }
     }
@@ -107,6 +125,40 @@ pub struct InternalUnixGroupTokenReadMessage {
     pub eventid: Uuid,
 }
 
+pub struct IdmServiceAccountApiTokenListMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub eventid: Uuid,
+}
+
+pub struct IdmAccountAppPasswordListMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub eventid: Uuid,
+}
+
+pub struct IdmAccountSoftLockReadMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub eventid: Uuid,
+}
+
+pub struct AuthAuditSearchMessage {
+    pub uat: Option<UserAuthToken>,
+    pub query: AuthAuditQuery,
+    pub eventid: Uuid,
+}
+
+pub struct IdmAccountSshCertSignMessage {
+    pub uat: Option<UserAuthToken>,
+    pub publickey: String,
+    pub eventid: Uuid,
+}
+
+pub struct SshCaPublicKeyMessage {
+    pub eventid: Uuid,
+}
+
 pub struct InternalSshKeyReadMessage {
     pub uat: Option<UserAuthToken>,
     pub uuid_or_name: String,
@@ -127,10 +179,37 @@ pub struct IdmAccountUnixAuthMessage {
     pub eventid: Uuid,
 }
 
+pub struct IdmAccountRadiusAuthMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub cred: String,
+    pub eventid: Uuid,
+}
+
+pub struct Oauth2OpenIdDiscoveryMessage {
+    pub client_id: String,
+    pub eventid: Uuid,
+}
+
+pub struct Oauth2OpenIdPublicKeyMessage {
+    pub client_id: String,
+    pub eventid: Uuid,
+}
+
 pub struct LdapRequestMessage {
     pub eventid: Uuid,
     pub protomsg: LdapMsg,
     pub uat: Option<LdapBoundToken>,
+    pub source: Option<IpAddr>,
+    // The connection is plaintext and may still be upgraded, so binds with
+    // credentials are refused.
+    pub bind_requires_tls: bool,
+}
+
+pub struct LdapCertBindMessage {
+    pub eventid: Uuid,
+    pub source: Option<IpAddr>,
+    pub client_cert: ClientCertificate,
 }
 
 // ===========================================================
@@ -185,6 +264,11 @@ impl QueryServerReadV1 {
     // required complete. We still need to do certain validation steps, but
     // at this point our just is just to route to do_<action>
 
+    // This is not dispatched to a transaction, as the trust keys are held in memory.
+    pub(crate) fn validate_trusted_uat(&self, jws: &str, ct: Duration) -> Option<UserAuthToken> {
+        self.qs.validate_trusted_uat(jws, ct)
+    }
+
     pub async fn handle_search(
         &self,
         msg: SearchMessage,
@@ -249,12 +333,16 @@ impl QueryServerReadV1 {
         // the session are enforced.
         idm_write.expire_auth_sessions(ct).await;
 
-        // Generally things like auth denied are in Ok() msgs
-        // so true errors should always trigger a rollback.
-        let res = idm_write
-            .auth(&mut audit, &ae, ct)
-            .await
-            .and_then(|r| idm_write.commit(&mut audit).map(|_| r));
+        // An expired password is replaced and committed before the session can complete.
+        let res = match self.idms.change_expired_password(&mut audit, &ae, ct).await {
+            // Generally things like auth denied are in Ok() msgs
+            // so true errors should always trigger a rollback.
+            Ok(()) => idm_write
+                .auth(&mut audit, &ae, ct)
+                .await
+                .and_then(|r| idm_write.commit(&mut audit).map(|_| r)),
+            Err(e) => Err(e),
+        };
 
         lsecurity!(audit, "Sending auth result -> {:?}", res);
         // Build the result.
@@ -625,6 +713,182 @@ impl QueryServerReadV1 {
         res
     }
 
+    pub async fn handle_serviceaccountapitokenlist(
+        &self,
+        msg: IdmServiceAccountApiTokenListMessage,
+    ) -> Result<Vec<ApiToken>, OperationError> {
+        let mut audit = AuditScope::new(
+            "idm_service_account_api_token_list",
+            msg.eventid,
+            self.log_level,
+        );
+        let mut idm_read = self.idms.proxy_read_async().await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_read::handle<IdmServiceAccountApiTokenListMessage>",
+            || {
+                let target_uuid = idm_read
+                    .qs_read
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Error resolving id to target");
+                        e
+                    })?;
+
+                let lte = ListApiTokenEvent::from_parts(
+                    &mut audit,
+                    &idm_read.qs_read,
+                    msg.uat.as_ref(),
+                    target_uuid,
+                )
+                .map_err(|e| {
+                    ladmin_error!(audit, "Failed to begin api token list: {:?}", e);
+                    e
+                })?;
+
+                ltrace!(audit, "Begin event {:?}", lte);
+
+                idm_read.service_account_list_api_token(&mut audit, &lte)
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_accountapppasswordlist(
+        &self,
+        msg: IdmAccountAppPasswordListMessage,
+    ) -> Result<Vec<AppPassword>, OperationError> {
+        let mut audit =
+            AuditScope::new("idm_account_app_password_list", msg.eventid, self.log_level);
+        let mut idm_read = self.idms.proxy_read_async().await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_read::handle<IdmAccountAppPasswordListMessage>",
+            || {
+                let target_uuid = idm_read
+                    .qs_read
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Error resolving id to target");
+                        e
+                    })?;
+
+                let lape = ListAppPasswordEvent::from_parts(
+                    &mut audit,
+                    &idm_read.qs_read,
+                    msg.uat.as_ref(),
+                    target_uuid,
+                )
+                .map_err(|e| {
+                    ladmin_error!(audit, "Failed to begin app password list: {:?}", e);
+                    e
+                })?;
+
+                ltrace!(audit, "Begin event {:?}", lape);
+
+                idm_read.account_list_app_password(&mut audit, &lape)
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_accountsoftlockread(
+        &self,
+        msg: IdmAccountSoftLockReadMessage,
+    ) -> Result<Vec<CredentialSoftLock>, OperationError> {
+        let mut audit = AuditScope::new("idm_account_softlock_read", msg.eventid, self.log_level);
+        let mut idm_read = self.idms.proxy_read_async().await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_read::handle<IdmAccountSoftLockReadMessage>",
+            || {
+                let target_uuid = idm_read
+                    .qs_read
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Error resolving id to target");
+                        e
+                    })?;
+
+                let rsle = ReadSoftLockEvent::from_parts(
+                    &mut audit,
+                    &idm_read.qs_read,
+                    msg.uat.as_ref(),
+                    target_uuid,
+                )
+                .map_err(|e| {
+                    ladmin_error!(audit, "Failed to begin softlock read: {:?}", e);
+                    e
+                })?;
+
+                let ct = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Clock Error -> {:?}", e);
+                        OperationError::InvalidState
+                    })?;
+
+                ltrace!(audit, "Begin event {:?}", rsle);
+
+                idm_read.account_softlock_status(&mut audit, &rsle, ct)
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_authauditsearch(
+        &self,
+        msg: AuthAuditSearchMessage,
+    ) -> Result<Vec<AuthAuditRecord>, OperationError> {
+        let mut audit = AuditScope::new("auth_audit_search", msg.eventid, self.log_level);
+        let mut idm_read = self.idms.proxy_read_async().await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_read::handle<AuthAuditSearchMessage>",
+            || {
+                let ct = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Clock Error -> {:?}", e);
+                        OperationError::InvalidState
+                    })?;
+
+                let aase = AuthAuditSearchEvent::from_parts(
+                    &mut audit,
+                    &idm_read.qs_read,
+                    msg.uat.as_ref(),
+                    msg.query,
+                    ct,
+                )
+                .map_err(|e| {
+                    ladmin_error!(audit, "Failed to begin auth audit search: {:?}", e);
+                    e
+                })?;
+
+                ltrace!(audit, "Begin event {:?}", aase);
+
+                idm_read.search_auth_records(&mut audit, &aase)
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
     pub async fn handle_internalsshkeyread(
         &self,
         msg: InternalSshKeyReadMessage,
@@ -759,6 +1023,64 @@ impl QueryServerReadV1 {
         res
     }
 
+    pub async fn handle_idmaccountsshcertsign(
+        &self,
+        msg: IdmAccountSshCertSignMessage,
+    ) -> Result<String, OperationError> {
+        let mut audit = AuditScope::new("idm_account_ssh_cert_sign", msg.eventid, self.log_level);
+        let mut idm_read = self.idms.proxy_read_async().await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_read::handle<IdmAccountSshCertSignMessage>",
+            || {
+                let ssce = SshCertSignEvent::from_parts(
+                    &mut audit,
+                    &idm_read.qs_read,
+                    msg.uat.as_ref(),
+                    msg.publickey,
+                )
+                .map_err(|e| {
+                    ladmin_error!(audit, "Failed to begin ssh cert sign: {:?}", e);
+                    e
+                })?;
+
+                let ct = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Clock Error -> {:?}", e);
+                        OperationError::InvalidState
+                    })?;
+
+                ltrace!(audit, "Begin event {:?}", ssce);
+
+                idm_read.account_sign_ssh_key(&mut audit, &ssce, ct)
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_sshcapublickey(
+        &self,
+        msg: SshCaPublicKeyMessage,
+    ) -> Result<String, OperationError> {
+        let mut audit = AuditScope::new("ssh_ca_public_key", msg.eventid, self.log_level);
+        let idm_read = self.idms.proxy_read_async().await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_read::handle<SshCaPublicKeyMessage>",
+            || idm_read.ssh_ca_public_key(&mut audit)
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
     pub async fn handle_idmaccountunixauth(
         &self,
         msg: IdmAccountUnixAuthMessage,
@@ -813,11 +1135,114 @@ impl QueryServerReadV1 {
         res
     }
 
+    pub async fn handle_idmaccountradiusauth(
+        &self,
+        msg: IdmAccountRadiusAuthMessage,
+    ) -> Result<Option<RadiusAuthToken>, OperationError> {
+        let mut audit = AuditScope::new("idm_account_radius_auth", msg.eventid, self.log_level);
+        let mut idm_write = self.idms.write_async().await;
+        let target_uuid = idm_write
+            .qs_read
+            .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+            .map_err(|e| {
+                ladmin_info!(&mut audit, "Error resolving id to target");
+                e
+            })?;
+        let rae = match RadiusAuthEvent::from_parts(
+            &mut audit,
+            &idm_write.qs_read,
+            msg.uat.as_ref(),
+            target_uuid,
+            msg.cred,
+        ) {
+            Ok(s) => s,
+            Err(e) => {
+                ladmin_error!(audit, "Failed to begin radius auth: {:?}", e);
+                return Err(e);
+            }
+        };
+
+        lsecurity!(audit, "Begin event {:?}", rae);
+
+        let ct = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|e| {
+                ladmin_error!(audit, "Clock Error -> {:?}", e);
+                OperationError::InvalidState
+            })?;
+
+        let res = idm_write
+            .auth_radius(&mut audit, &rae, ct)
+            .await
+            .and_then(|r| idm_write.commit(&mut audit).map(|_| r));
+
+        lsecurity!(audit, "Sending result -> {:?}", res);
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_oauth2_openid_discovery(
+        &self,
+        msg: Oauth2OpenIdDiscoveryMessage,
+    ) -> Result<OidcDiscoveryResponse, OperationError> {
+        let mut audit = AuditScope::new("oauth2_openid_discovery", msg.eventid, self.log_level);
+        let idm_read = self.idms.proxy_read_async().await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_read::handle<Oauth2OpenIdDiscoveryMessage>",
+            || idm_read.oauth2_openid_discovery(&mut audit, msg.client_id.as_str())
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_oauth2_openid_publickey(
+        &self,
+        msg: Oauth2OpenIdPublicKeyMessage,
+    ) -> Result<JwkKeySet, OperationError> {
+        let mut audit = AuditScope::new("oauth2_openid_publickey", msg.eventid, self.log_level);
+        let idm_read = self.idms.proxy_read_async().await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_read::handle<Oauth2OpenIdPublicKeyMessage>",
+            || idm_read.oauth2_openid_publickey(&mut audit, msg.client_id.as_str())
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_ldapcertbind(&self, msg: LdapCertBindMessage) -> Option<LdapBoundToken> {
+        let mut audit = AuditScope::new("ldap_cert_bind_message", msg.eventid, self.log_level);
+        let res = self
+            .ldap
+            .do_certificate_bind(&mut audit, &self.idms, msg.source, &msg.client_cert)
+            .await
+            .unwrap_or_else(|e| {
+                ladmin_error!(&mut audit, "do_certificate_bind failed -> {:?}", e);
+                None
+            });
+        if self.log.send(audit).is_err() {
+            error!("Unable to commit log -> {:?}", &msg.eventid);
+        }
+        res
+    }
+
     pub async fn handle_ldaprequest(&self, msg: LdapRequestMessage) -> Option<LdapResponseState> {
         let LdapRequestMessage {
             eventid,
             protomsg,
             uat,
+            source,
+            bind_requires_tls,
         } = msg;
         let mut audit = AuditScope::new("ldap_request_message", eventid, self.log_level);
 
@@ -827,23 +1252,39 @@ impl QueryServerReadV1 {
             "actors::v1_read::handle<LdapRequestMessage>",
             || {
         */
-        let res = match ServerOps::try_from(protomsg) {
-            Ok(server_op) => self
-                .ldap
-                .do_op(&mut audit, &self.idms, server_op, uat, &eventid)
-                .await
-                .unwrap_or_else(|e| {
-                    ladmin_error!(&mut audit, "do_op failed -> {:?}", e);
-                    LdapResponseState::Disconnect(DisconnectionNotice::gen(
-                        LdapResultCode::Other,
-                        format!("Internal Server Error {:?}", &eventid).as_str(),
-                    ))
-                }),
-            Err(_) => LdapResponseState::Disconnect(DisconnectionNotice::gen(
-                LdapResultCode::ProtocolError,
-                format!("Invalid Request {:?}", &eventid).as_str(),
-            )),
-        };
+        let res = match LdapWriteRequest::try_from(protomsg) {
+            Ok(write_req) => {
+                self.ldap
+                    .do_write_op(&mut audit, &self.idms, write_req, uat, source)
+                    .await
+            }
+            Err(protomsg) => match ServerOps::try_from(protomsg) {
+                Ok(server_op) => {
+                    self.ldap
+                        .do_op(
+                            &mut audit,
+                            &self.idms,
+                            server_op,
+                            uat,
+                            source,
+                            bind_requires_tls,
+                            &eventid,
+                        )
+                        .await
+                }
+                Err(_) => Ok(LdapResponseState::Disconnect(DisconnectionNotice::gen(
+                    LdapResultCode::ProtocolError,
+                    format!("Invalid Request {:?}", &eventid).as_str(),
+                ))),
+            },
+        }
+        .unwrap_or_else(|e| {
+            ladmin_error!(&mut audit, "do_op failed -> {:?}", e);
+            LdapResponseState::Disconnect(DisconnectionNotice::gen(
+                LdapResultCode::Other,
+                format!("Internal Server Error {:?}", &eventid).as_str(),
+            ))
+        });
         /*
             }
         );
@@ -1,14 +1,19 @@
 use crate::audit::AuditScope;
+use std::collections::BTreeMap;
 use std::iter;
 use std::sync::Arc;
 use tokio::sync::mpsc::UnboundedSender as Sender;
 
+use crate::be::dbvalue::{DbAuthRecordV1, DbSoftLockV1};
 use crate::event::{
     CreateEvent, DeleteEvent, ModifyEvent, PurgeRecycledEvent, PurgeTombstoneEvent,
     ReviveRecycledEvent,
 };
 use crate::idm::event::{
-    GeneratePasswordEvent, GenerateTOTPEvent, PasswordChangeEvent, RegenerateRadiusSecretEvent,
+    ClearSoftLockEvent, CredentialResetBeginEvent, CredentialResetIntentEvent,
+    CredentialResetStepEvent, DestroyApiTokenEvent, GenerateApiTokenEvent,
+    GenerateAppPasswordEvent, GenerateBackupCodeEvent, GeneratePasswordEvent, GenerateTOTPEvent,
+    ImportTOTPEvent, PasswordChangeEvent, RegenerateRadiusSecretEvent, RemoveAppPasswordEvent,
     RemoveTOTPEvent, RemoveWebauthnEvent, UnixPasswordChangeEvent, VerifyTOTPEvent,
     WebauthnDoRegisterEvent, WebauthnInitRegisterEvent,
 };
@@ -22,11 +27,16 @@ use crate::idm::server::IdmServer;
 use crate::server::{QueryServer, QueryServerTransaction};
 use crate::utils::duration_from_epoch_now;
 
+use kanidm_proto::oauth2::{
+    AccessTokenRequest, AccessTokenResponse, AuthorisationRequest, AuthorisationResponse,
+    ConsentRequest, Oauth2Error,
+};
 use kanidm_proto::v1::Entry as ProtoEntry;
 use kanidm_proto::v1::Modify as ProtoModify;
 use kanidm_proto::v1::ModifyList as ProtoModifyList;
 use kanidm_proto::v1::{
-    AccountUnixExtend, CreateRequest, DeleteRequest, GroupUnixExtend, ModifyRequest,
+    AccountUnixExtend, ApiTokenGenerate, AppPasswordGenerate, CreateRequest, CredentialResetIntent,
+    CredentialResetRequest, CredentialResetSession, DeleteRequest, GroupUnixExtend, ModifyRequest,
     OperationResponse, SetCredentialRequest, SetCredentialResponse, SingleStringRequest,
     UserAuthToken,
 };
@@ -137,6 +147,25 @@ impl IdmAccountUnixExtendMessage {
     }
 }
 
+pub struct Oauth2AuthoriseMessage {
+    pub uat: Option<UserAuthToken>,
+    pub auth_req: AuthorisationRequest,
+    pub eventid: Uuid,
+}
+
+pub struct Oauth2AuthorisePermitMessage {
+    pub uat: Option<UserAuthToken>,
+    pub consent_req: ConsentRequest,
+    pub eventid: Uuid,
+}
+
+pub struct Oauth2TokenExchangeMessage {
+    // The http basic authorisation header, if the client provided one.
+    pub client_authz: Option<String>,
+    pub token_req: AccessTokenRequest,
+    pub eventid: Uuid,
+}
+
 pub struct IdmGroupUnixExtendMessage {
     pub uat: Option<UserAuthToken>,
     pub uuid_or_name: String,
@@ -192,6 +221,58 @@ impl InternalRegenerateRadiusMessage {
     }
 }
 
+pub struct IdmServiceAccountApiTokenGenerateMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub gen: ApiTokenGenerate,
+    pub eventid: Uuid,
+}
+
+pub struct IdmServiceAccountApiTokenDestroyMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub token_id: Uuid,
+    pub eventid: Uuid,
+}
+
+pub struct IdmAccountAppPasswordGenerateMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub gen: AppPasswordGenerate,
+    pub eventid: Uuid,
+}
+
+pub struct IdmAccountAppPasswordRemoveMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub label: String,
+    pub eventid: Uuid,
+}
+
+pub struct IdmAccountSoftLockClearMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub eventid: Uuid,
+}
+
+pub struct IdmAccountCredentialResetIntentMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub ttl: Option<u64>,
+    pub eventid: Uuid,
+}
+
+pub struct IdmCredentialResetBeginMessage {
+    pub token: String,
+    pub eventid: Uuid,
+}
+
+pub struct IdmCredentialResetStepMessage {
+    pub sessionid: Uuid,
+    pub req: CredentialResetRequest,
+    pub eventid: Uuid,
+}
+
 pub struct InternalSshKeyCreateMessage {
     pub uat: Option<UserAuthToken>,
     pub uuid_or_name: String,
@@ -643,12 +724,13 @@ impl QueryServerWriteV1 {
                             .verify_account_totp(&mut audit, &vte, ct)
                             .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
                     }
-                    SetCredentialRequest::TOTPRemove => {
+                    SetCredentialRequest::TOTPRemove(label) => {
                         let rte = RemoveTOTPEvent::from_parts(
                             &mut audit,
                             &idms_prox_write.qs_write,
                             msg.uat.as_ref(),
                             target_uuid,
+                            label,
                         )
                         .map_err(|e| {
                             ladmin_error!(
@@ -662,6 +744,27 @@ impl QueryServerWriteV1 {
                             .remove_account_totp(&mut audit, &rte)
                             .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
                     }
+                    SetCredentialRequest::TOTPImport(label, secret) => {
+                        let ite = ImportTOTPEvent::from_parts(
+                            &mut audit,
+                            &idms_prox_write.qs_write,
+                            msg.uat.as_ref(),
+                            target_uuid,
+                            label,
+                            secret,
+                        )
+                        .map_err(|e| {
+                            ladmin_error!(
+                                audit,
+                                "Failed to begin internal_credential_set_message: {:?}",
+                                e
+                            );
+                            e
+                        })?;
+                        idms_prox_write
+                            .import_account_totp(&mut audit, &ite)
+                            .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+                    }
                     SetCredentialRequest::WebauthnBegin(label) => {
                         let wre = WebauthnInitRegisterEvent::from_parts(
                             &mut audit,
@@ -723,6 +826,25 @@ impl QueryServerWriteV1 {
                             .remove_account_webauthn(&mut audit, &rwe)
                             .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
                     }
+                    SetCredentialRequest::BackupCodeGenerate => {
+                        let gbe = GenerateBackupCodeEvent::from_parts(
+                            &mut audit,
+                            &idms_prox_write.qs_write,
+                            msg.uat.as_ref(),
+                            target_uuid,
+                        )
+                        .map_err(|e| {
+                            ladmin_error!(
+                                audit,
+                                "Failed to begin internal_credential_set_message: {:?}",
+                                e
+                            );
+                            e
+                        })?;
+                        idms_prox_write
+                            .generate_backup_codes(&mut audit, &gbe)
+                            .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+                    }
                 }
             }
         );
@@ -818,6 +940,359 @@ impl QueryServerWriteV1 {
         res
     }
 
+    pub async fn handle_serviceaccountapitokengenerate(
+        &self,
+        msg: IdmServiceAccountApiTokenGenerateMessage,
+    ) -> Result<String, OperationError> {
+        let mut audit = AuditScope::new(
+            "idm_service_account_api_token_generate",
+            msg.eventid,
+            self.log_level,
+        );
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<IdmServiceAccountApiTokenGenerateMessage>",
+            || {
+                let target_uuid = idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Error resolving id to target");
+                        e
+                    })?;
+
+                let gte = GenerateApiTokenEvent::from_parts(
+                    &mut audit,
+                    &idms_prox_write.qs_write,
+                    msg.uat.as_ref(),
+                    target_uuid,
+                    msg.gen,
+                )
+                .map_err(|e| {
+                    ladmin_error!(
+                        audit,
+                        "Failed to begin idm_service_account_api_token_generate: {:?}",
+                        e
+                    );
+                    e
+                })?;
+
+                idms_prox_write
+                    .service_account_generate_api_token(&mut audit, &gte, ct)
+                    .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_serviceaccountapitokendestroy(
+        &self,
+        msg: IdmServiceAccountApiTokenDestroyMessage,
+    ) -> Result<(), OperationError> {
+        let mut audit = AuditScope::new(
+            "idm_service_account_api_token_destroy",
+            msg.eventid,
+            self.log_level,
+        );
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<IdmServiceAccountApiTokenDestroyMessage>",
+            || {
+                let target_uuid = idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Error resolving id to target");
+                        e
+                    })?;
+
+                let dte = DestroyApiTokenEvent::from_parts(
+                    &mut audit,
+                    &idms_prox_write.qs_write,
+                    msg.uat.as_ref(),
+                    target_uuid,
+                    msg.token_id,
+                )
+                .map_err(|e| {
+                    ladmin_error!(
+                        audit,
+                        "Failed to begin idm_service_account_api_token_destroy: {:?}",
+                        e
+                    );
+                    e
+                })?;
+
+                idms_prox_write
+                    .service_account_destroy_api_token(&mut audit, &dte)
+                    .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_accountapppasswordgenerate(
+        &self,
+        msg: IdmAccountAppPasswordGenerateMessage,
+    ) -> Result<String, OperationError> {
+        let mut audit = AuditScope::new(
+            "idm_account_app_password_generate",
+            msg.eventid,
+            self.log_level,
+        );
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<IdmAccountAppPasswordGenerateMessage>",
+            || {
+                let target_uuid = idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Error resolving id to target");
+                        e
+                    })?;
+
+                let gape = GenerateAppPasswordEvent::from_parts(
+                    &mut audit,
+                    &idms_prox_write.qs_write,
+                    msg.uat.as_ref(),
+                    target_uuid,
+                    msg.gen.label,
+                    msg.gen.scope,
+                )
+                .map_err(|e| {
+                    ladmin_error!(
+                        audit,
+                        "Failed to begin idm_account_app_password_generate: {:?}",
+                        e
+                    );
+                    e
+                })?;
+
+                idms_prox_write
+                    .generate_account_app_password(&mut audit, &gape)
+                    .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_accountsoftlockclear(
+        &self,
+        msg: IdmAccountSoftLockClearMessage,
+    ) -> Result<(), OperationError> {
+        let mut audit = AuditScope::new("idm_account_softlock_clear", msg.eventid, self.log_level);
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<IdmAccountSoftLockClearMessage>",
+            || {
+                let target_uuid = idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Error resolving id to target");
+                        e
+                    })?;
+
+                let csle = ClearSoftLockEvent::from_parts(
+                    &mut audit,
+                    &idms_prox_write.qs_write,
+                    msg.uat.as_ref(),
+                    target_uuid,
+                )
+                .map_err(|e| {
+                    ladmin_error!(audit, "Failed to begin idm_account_softlock_clear: {:?}", e);
+                    e
+                })?;
+
+                idms_prox_write
+                    .clear_account_softlocks(&mut audit, &csle)
+                    .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_accountapppasswordremove(
+        &self,
+        msg: IdmAccountAppPasswordRemoveMessage,
+    ) -> Result<(), OperationError> {
+        let mut audit = AuditScope::new(
+            "idm_account_app_password_remove",
+            msg.eventid,
+            self.log_level,
+        );
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<IdmAccountAppPasswordRemoveMessage>",
+            || {
+                let target_uuid = idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Error resolving id to target");
+                        e
+                    })?;
+
+                let rape = RemoveAppPasswordEvent::from_parts(
+                    &mut audit,
+                    &idms_prox_write.qs_write,
+                    msg.uat.as_ref(),
+                    target_uuid,
+                    msg.label,
+                )
+                .map_err(|e| {
+                    ladmin_error!(
+                        audit,
+                        "Failed to begin idm_account_app_password_remove: {:?}",
+                        e
+                    );
+                    e
+                })?;
+
+                idms_prox_write
+                    .remove_account_app_password(&mut audit, &rape)
+                    .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_idmaccountcredentialresetintent(
+        &self,
+        msg: IdmAccountCredentialResetIntentMessage,
+    ) -> Result<CredentialResetIntent, OperationError> {
+        let mut audit = AuditScope::new(
+            "idm_account_credential_reset_intent",
+            msg.eventid,
+            self.log_level,
+        );
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<IdmAccountCredentialResetIntentMessage>",
+            || {
+                let target_uuid = idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        ladmin_error!(audit, "Error resolving id to target");
+                        e
+                    })?;
+
+                let crie = CredentialResetIntentEvent::from_parts(
+                    &mut audit,
+                    &idms_prox_write.qs_write,
+                    msg.uat.as_ref(),
+                    target_uuid,
+                    msg.ttl,
+                )
+                .map_err(|e| {
+                    ladmin_error!(
+                        audit,
+                        "Failed to begin idm_account_credential_reset_intent: {:?}",
+                        e
+                    );
+                    e
+                })?;
+
+                idms_prox_write
+                    .credential_reset_intent(&mut audit, &crie, ct)
+                    .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_idmcredentialresetbegin(
+        &self,
+        msg: IdmCredentialResetBeginMessage,
+    ) -> Result<CredentialResetSession, OperationError> {
+        let mut audit = AuditScope::new("idm_credential_reset_begin", msg.eventid, self.log_level);
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<IdmCredentialResetBeginMessage>",
+            || {
+                idms_prox_write.expire_credreset_sessions(ct);
+
+                let crbe = CredentialResetBeginEvent::new(msg.token);
+                idms_prox_write
+                    .credential_reset_begin(&mut audit, &crbe, ct)
+                    .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
+    pub async fn handle_idmcredentialresetstep(
+        &self,
+        msg: IdmCredentialResetStepMessage,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        let mut audit = AuditScope::new("idm_credential_reset_step", msg.eventid, self.log_level);
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<IdmCredentialResetStepMessage>",
+            || {
+                // As with credential set, expire sessions before taking any steps so
+                // that the timeouts are enforced.
+                idms_prox_write.expire_credreset_sessions(ct);
+                idms_prox_write.expire_mfareg_sessions(ct);
+
+                let crse = CredentialResetStepEvent::new(msg.sessionid, msg.req.token, msg.req.sac);
+                idms_prox_write
+                    .credential_reset_step(&mut audit, &crse, ct)
+                    .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            OperationError::InvalidState
+        })?;
+        res
+    }
+
     pub async fn handle_purgeattribute(
         &self,
         msg: PurgeAttributeMessage,
@@ -1206,7 +1681,115 @@ impl QueryServerWriteV1 {
         res
     }
 
+    pub async fn handle_oauth2_authorise(
+        &self,
+        msg: Oauth2AuthoriseMessage,
+    ) -> Result<AuthorisationResponse, Oauth2Error> {
+        let mut audit = AuditScope::new("oauth2_authorise", msg.eventid, self.log_level);
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<Oauth2AuthoriseMessage>",
+            || {
+                idms_prox_write.expire_oauth2_sessions(ct);
+                idms_prox_write
+                    .check_oauth2_authorisation(&mut audit, msg.uat.as_ref(), &msg.auth_req, ct)
+                    .and_then(|r| {
+                        idms_prox_write
+                            .commit(&mut audit)
+                            .map(|_| r)
+                            .map_err(|_| Oauth2Error::ServerError)
+                    })
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            Oauth2Error::ServerError
+        })?;
+        res
+    }
+
+    pub async fn handle_oauth2_authorise_permit(
+        &self,
+        msg: Oauth2AuthorisePermitMessage,
+    ) -> Result<AuthorisationResponse, Oauth2Error> {
+        let mut audit = AuditScope::new("oauth2_authorise_permit", msg.eventid, self.log_level);
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<Oauth2AuthorisePermitMessage>",
+            || {
+                idms_prox_write.expire_oauth2_sessions(ct);
+                idms_prox_write
+                    .check_oauth2_authorise_permit(
+                        &mut audit,
+                        msg.uat.as_ref(),
+                        msg.consent_req.consent_token.as_str(),
+                        ct,
+                    )
+                    .and_then(|r| {
+                        idms_prox_write
+                            .commit(&mut audit)
+                            .map(|_| r)
+                            .map_err(|_| Oauth2Error::ServerError)
+                    })
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            Oauth2Error::ServerError
+        })?;
+        res
+    }
+
+    pub async fn handle_oauth2_token_exchange(
+        &self,
+        msg: Oauth2TokenExchangeMessage,
+    ) -> Result<AccessTokenResponse, Oauth2Error> {
+        let mut audit = AuditScope::new("oauth2_token_exchange", msg.eventid, self.log_level);
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        let res = lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<Oauth2TokenExchangeMessage>",
+            || {
+                idms_prox_write.expire_oauth2_sessions(ct);
+                let res = idms_prox_write.check_oauth2_token_exchange(
+                    &mut audit,
+                    msg.client_authz.as_deref(),
+                    &msg.token_req,
+                    ct,
+                );
+                // A code is consumed by any attempt to exchange it, so this must be
+                // committed even when the exchange failed.
+                idms_prox_write
+                    .commit(&mut audit)
+                    .map_err(|_| Oauth2Error::ServerError)
+                    .and(res)
+            }
+        );
+        self.log.send(audit).map_err(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            Oauth2Error::ServerError
+        })?;
+        res
+    }
+
     // ===== These below are internal only event types. =====
+    /// Queue an update of the last used time of the api token that issued this uat, if any.
+    pub(crate) fn handle_apitokenused(&self, uat: &UserAuthToken) {
+        if uat.api_token_id.is_some() {
+            let mut audit = AuditScope::new("api_token_used", Uuid::new_v4(), self.log_level);
+            self.idms
+                .note_api_token_use(&mut audit, uat, duration_from_epoch_now());
+            self.log.send(audit).unwrap_or_else(|_| {
+                error!("CRITICAL: UNABLE TO COMMIT LOGS");
+            });
+        }
+    }
+
     pub(crate) async fn handle_purgetombstoneevent(&self, msg: PurgeTombstoneEvent) {
         let mut audit = AuditScope::new("purge tombstones", msg.eventid, self.log_level);
 
@@ -1253,6 +1836,43 @@ impl QueryServerWriteV1 {
         });
     }
 
+    pub(crate) async fn handle_expireinactiveaccounts(&self) {
+        let eventid = Uuid::new_v4();
+        let mut audit = AuditScope::new("expire inactive accounts", eventid, self.log_level);
+        ltrace!(audit, "Begin expire inactive accounts event");
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<ExpireInactiveAccounts>",
+            || {
+                let res = idms_prox_write
+                    .expire_inactive_accounts(&mut audit, ct)
+                    .and_then(|n| idms_prox_write.commit(&mut audit).map(|_| n));
+                ladmin_info!(audit, "Expire inactive accounts result: {:?}", res);
+            }
+        );
+        self.log.send(audit).unwrap_or_else(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+        });
+    }
+
+    pub(crate) async fn handle_purgeauthsources(&self) {
+        let eventid = Uuid::new_v4();
+        let mut audit = AuditScope::new("purge auth sources", eventid, self.log_level);
+        ltrace!(audit, "Begin purge auth sources event");
+        let idm_write = self.idms.write_async().await;
+        if let Err(res) = idm_write
+            .purge_auth_sources(&mut audit, duration_from_epoch_now())
+            .await
+        {
+            ladmin_info!(audit, "Purge auth sources error: {:?}", res);
+        }
+        self.log.send(audit).unwrap_or_else(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+        });
+    }
+
     pub(crate) async fn handle_delayedaction(&self, da: DelayedAction) {
         let eventid = Uuid::new_v4();
         let mut audit = AuditScope::new("delayed action", eventid, self.log_level);
@@ -1275,4 +1895,47 @@ impl QueryServerWriteV1 {
             error!("CRITICAL: UNABLE TO COMMIT LOGS");
         });
     }
+
+    pub(crate) async fn handle_softlockupdate(
+        &self,
+        updates: BTreeMap<Uuid, Option<DbSoftLockV1>>,
+    ) {
+        let eventid = Uuid::new_v4();
+        let mut audit = AuditScope::new("softlock update", eventid, self.log_level);
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        lperf_op_segment!(
+            &mut audit,
+            "actors::v1_write::handle<SoftLockUpdate>",
+            || {
+                if let Err(res) = idms_prox_write
+                    .process_softlockupdate(&mut audit, updates, ct)
+                    .and_then(|_| idms_prox_write.commit(&mut audit))
+                {
+                    ladmin_info!(audit, "softlock update error: {:?}", res);
+                }
+            }
+        );
+        self.log.send(audit).unwrap_or_else(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+        });
+    }
+
+    pub(crate) async fn handle_authrecords(&self, records: Vec<DbAuthRecordV1>) {
+        let eventid = Uuid::new_v4();
+        let mut audit = AuditScope::new("auth records", eventid, self.log_level);
+        let ct = duration_from_epoch_now();
+        let mut idms_prox_write = self.idms.proxy_write_async(ct).await;
+        lperf_op_segment!(&mut audit, "actors::v1_write::handle<AuthRecords>", || {
+            if let Err(res) = idms_prox_write
+                .process_authrecords(&mut audit, records.as_slice(), ct)
+                .and_then(|_| idms_prox_write.commit(&mut audit))
+            {
+                ladmin_error!(audit, "auth audit record error: {:?}", res);
+            }
+        });
+        self.log.send(audit).unwrap_or_else(|_| {
+            error!("CRITICAL: UNABLE TO COMMIT LOGS");
+        });
+    }
 }
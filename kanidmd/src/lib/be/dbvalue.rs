@@ -1,3 +1,4 @@
+use std::net::IpAddr;
 use std::time::Duration;
 use uuid::Uuid;
 use webauthn_rs::crypto::COSEKey;
@@ -13,6 +14,12 @@ pub struct DbCidV1 {
 pub enum DbPasswordV1 {
     PBKDF2(usize, Vec<u8>, Vec<u8>),
     SSHA512(Vec<u8>, Vec<u8>),
+    ARGON2ID(u32, u32, u32, Vec<u8>, Vec<u8>),
+    SSHA(Vec<u8>, Vec<u8>),
+    SSHA256(Vec<u8>, Vec<u8>),
+    CRYPT(String),
+    BCRYPT(String),
+    ARGON2PHC(String),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -28,6 +35,18 @@ pub struct DbTotpV1 {
     pub k: Vec<u8>,
     pub s: u64,
     pub a: DbTotpAlgoV1,
+    #[serde(default = "default_totp_digits")]
+    pub d: u8,
+}
+
+fn default_totp_digits() -> u8 {
+    6
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbBackupCodeV1 {
+    // The sha256 of each unused code.
+    pub h: Vec<Vec<u8>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -37,6 +56,9 @@ pub struct DbWebauthnV1 {
     pub c: COSEKey,
     pub t: u32,
     pub v: bool,
+    // The aaguid of the authenticator model, if it was attested at registration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub m: Option<Uuid>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -63,9 +85,18 @@ pub struct DbCredV1 {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub webauthn: Option<Vec<DbWebauthnV1>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    // Only read, for credentials written before multiple totps were supported.
     pub totp: Option<DbTotpV1>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub totps: Vec<DbTotpV1>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_code: Option<DbBackupCodeV1>,
     pub claims: Vec<String>,
     pub uuid: Uuid,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<DbPasswordV1>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_set: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -80,6 +111,21 @@ pub struct DbValueTaggedStringV1 {
     pub d: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbValueOauthScopeMapV1 {
+    pub u: Uuid,
+    pub m: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbValueApiTokenV1 {
+    pub u: Uuid,
+    pub l: String,
+    pub e: Option<String>,
+    pub r: bool,
+    pub lu: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DbValueV1 {
     U8(String),
@@ -99,6 +145,39 @@ pub enum DbValueV1 {
     CI(DbCidV1),
     NU(String),
     DT(String),
+    PB(Vec<u8>),
+    OS(DbValueOauthScopeMapV1),
+    AT(DbValueApiTokenV1),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum DbSoftLockPolicyV1 {
+    Password,
+    TOTP(u64),
+    Webauthn,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbSoftLockV1 {
+    pub p: DbSoftLockPolicyV1,
+    pub c: usize,
+    pub r: Duration,
+    pub u: Duration,
+}
+
+// An authentication attempt in the audit store. The account is none when the attempt
+// could not be tied to one, such as an unknown discoverable credential.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbAuthRecordV1 {
+    pub t: Duration,
+    pub n: Option<String>,
+    pub u: Option<Uuid>,
+    pub s: bool,
+    pub m: Option<String>,
+    pub p: String,
+    pub a: Option<IpAddr>,
+    pub l: bool,
+    pub r: Option<String>,
 }
 
 #[cfg(test)]
@@ -1,9 +1,10 @@
 use crate::audit::AuditScope;
+use crate::be::dbvalue::{DbAuthRecordV1, DbSoftLockV1};
 use crate::be::idl_sqlite::{
     FsType, IdlSqlite, IdlSqliteReadTransaction, IdlSqliteTransaction, IdlSqliteWriteTransaction,
 };
 use crate::be::idxkey::{IdlCacheKey, IdlCacheKeyRef, IdlCacheKeyToRef};
-use crate::be::{IdRawEntry, IDL};
+use crate::be::{AuthRecordFilter, IdRawEntry, IDL};
 use crate::entry::{Entry, EntryCommitted, EntrySealed};
 use crate::value::IndexType;
 use crate::value::Value;
@@ -332,6 +333,12 @@ pub trait IdlArcSqliteTransaction {
         audit: &mut AuditScope,
         uuid: &Uuid,
     ) -> Result<Option<String>, OperationError>;
+
+    fn get_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        filter: &AuthRecordFilter,
+    ) -> Result<Vec<DbAuthRecordV1>, OperationError>;
 }
 
 impl<'a> IdlArcSqliteTransaction for IdlArcSqliteReadTransaction<'a> {
@@ -405,6 +412,14 @@ impl<'a> IdlArcSqliteTransaction for IdlArcSqliteReadTransaction<'a> {
     ) -> Result<Option<String>, OperationError> {
         uuid2rdn!(self, audit, uuid)
     }
+
+    fn get_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        filter: &AuthRecordFilter,
+    ) -> Result<Vec<DbAuthRecordV1>, OperationError> {
+        self.db.get_auth_records(audit, filter)
+    }
 }
 
 impl<'a> IdlArcSqliteTransaction for IdlArcSqliteWriteTransaction<'a> {
@@ -478,6 +493,14 @@ impl<'a> IdlArcSqliteTransaction for IdlArcSqliteWriteTransaction<'a> {
     ) -> Result<Option<String>, OperationError> {
         uuid2rdn!(self, audit, uuid)
     }
+
+    fn get_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        filter: &AuthRecordFilter,
+    ) -> Result<Vec<DbAuthRecordV1>, OperationError> {
+        self.db.get_auth_records(audit, filter)
+    }
 }
 
 impl<'a> IdlArcSqliteWriteTransaction<'a> {
@@ -818,6 +841,47 @@ impl<'a> IdlArcSqliteWriteTransaction<'a> {
         }
     }
 
+    pub fn write_softlock(
+        &self,
+        audit: &mut AuditScope,
+        uuid: &Uuid,
+        k: Option<&DbSoftLockV1>,
+    ) -> Result<(), OperationError> {
+        self.db.write_softlock(audit, uuid, k)
+    }
+
+    pub fn purge_softlocks(
+        &self,
+        audit: &mut AuditScope,
+        ct: &Duration,
+    ) -> Result<(), OperationError> {
+        self.db.purge_softlocks(audit, ct)
+    }
+
+    pub fn get_softlocks(
+        &self,
+        audit: &mut AuditScope,
+    ) -> Result<Vec<(Uuid, DbSoftLockV1)>, OperationError> {
+        self.db.get_softlocks(audit)
+    }
+
+    pub fn write_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        records: &[DbAuthRecordV1],
+    ) -> Result<(), OperationError> {
+        self.db.write_auth_records(audit, records)
+    }
+
+    pub fn purge_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        before: &Duration,
+        max_records: u32,
+    ) -> Result<(), OperationError> {
+        self.db.purge_auth_records(audit, before, max_records)
+    }
+
     pub(crate) fn get_db_index_version(&self) -> i64 {
         self.db.get_db_index_version()
     }
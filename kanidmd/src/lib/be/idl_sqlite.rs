@@ -1,5 +1,6 @@
 use crate::audit::AuditScope;
-use crate::be::{IdRawEntry, IDL};
+use crate::be::dbvalue::{DbAuthRecordV1, DbSoftLockV1};
+use crate::be::{AuthRecordFilter, IdRawEntry, IDL};
 use crate::entry::{Entry, EntryCommitted, EntrySealed};
 use crate::value::{IndexType, Value};
 use idlset::IDLBitRange;
@@ -446,6 +447,52 @@ pub trait IdlSqliteTransaction {
         };
         r
     }
+
+    fn get_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        filter: &AuthRecordFilter,
+    ) -> Result<Vec<DbAuthRecordV1>, OperationError> {
+        let since = filter.since.as_secs() as i64;
+        let until = filter.until.as_secs() as i64;
+        let limit = i64::from(filter.limit);
+        let mut stmt = self
+            .get_conn()
+            .prepare(
+                "SELECT data FROM db_auth_audit
+                WHERE ts >= :since AND ts <= :until
+                AND (:account IS NULL OR account = :account)
+                AND (:success IS NULL OR success = :success)
+                ORDER BY id DESC LIMIT :limit",
+            )
+            .map_err(|e| {
+                ladmin_error!(audit, "SQLite Error {:?}", e);
+                OperationError::SQLiteError
+            })?;
+        let rows: Result<Vec<Vec<u8>>, _> = stmt
+            .query_map_named(
+                &[
+                    (":since", &since),
+                    (":until", &until),
+                    (":account", &filter.account),
+                    (":success", &filter.success),
+                    (":limit", &limit),
+                ],
+                |row| row.get(0),
+            )
+            .and_then(|iter| iter.collect())
+            .map_err(|e| {
+                ladmin_error!(audit, "SQLite Error {:?}", e);
+                OperationError::SQLiteError
+            });
+
+        rows?
+            .into_iter()
+            .map(|d| {
+                serde_cbor::from_slice(d.as_slice()).map_err(|_| OperationError::SerdeCborError)
+            })
+            .collect()
+    }
 }
 
 impl IdlSqliteTransaction for IdlSqliteReadTransaction {
@@ -846,6 +893,197 @@ impl IdlSqliteWriteTransaction {
         }
     }
 
+    pub fn create_softlock(&self, audit: &mut AuditScope) -> Result<(), OperationError> {
+        self.conn
+            .execute(
+                "CREATE TABLE IF NOT EXISTS db_softlock (
+                    uuid TEXT PRIMARY KEY,
+                    reset_at INTEGER NOT NULL,
+                    data BLOB NOT NULL
+                )
+                ",
+                NO_PARAMS,
+            )
+            .map(|_| ())
+            .map_err(|e| {
+                ladmin_error!(audit, "SQLite Error {:?}", e);
+                OperationError::SQLiteError
+            })
+    }
+
+    pub fn write_softlock(
+        &self,
+        audit: &mut AuditScope,
+        uuid: &Uuid,
+        k: Option<&DbSoftLockV1>,
+    ) -> Result<(), OperationError> {
+        let uuids = uuid.to_hyphenated_ref().to_string();
+        match k {
+            Some(k) => {
+                let reset_at = k.r.as_secs() as i64;
+                let data = serde_cbor::to_vec(k).map_err(|_e| OperationError::SerdeCborError)?;
+                self.conn
+                    .prepare(
+                        "INSERT OR REPLACE INTO db_softlock (uuid, reset_at, data) VALUES(:uuid, :reset_at, :data)",
+                    )
+                    .and_then(|mut stmt| {
+                        stmt.execute_named(&[
+                            (":uuid", &uuids),
+                            (":reset_at", &reset_at),
+                            (":data", &data),
+                        ])
+                    })
+                    .map(|_| ())
+                    .map_err(|e| {
+                        ladmin_error!(audit, "SQLite Error {:?}", e);
+                        OperationError::SQLiteError
+                    })
+            }
+            None => self
+                .conn
+                .prepare("DELETE FROM db_softlock WHERE uuid = :uuid")
+                .and_then(|mut stmt| stmt.execute_named(&[(":uuid", &uuids)]))
+                .map(|_| ())
+                .map_err(|e| {
+                    ladmin_error!(audit, "SQLite Error {:?}", e);
+                    OperationError::SQLiteError
+                }),
+        }
+    }
+
+    /// Remove softlocks that have passed their reset time, as they no longer
+    /// have any effect on authentication.
+    pub fn purge_softlocks(
+        &self,
+        audit: &mut AuditScope,
+        ct: &Duration,
+    ) -> Result<(), OperationError> {
+        let ct = ct.as_secs() as i64;
+        self.conn
+            .prepare("DELETE FROM db_softlock WHERE reset_at < :ct")
+            .and_then(|mut stmt| stmt.execute_named(&[(":ct", &ct)]))
+            .map(|_| ())
+            .map_err(|e| {
+                ladmin_error!(audit, "SQLite Error {:?}", e);
+                OperationError::SQLiteError
+            })
+    }
+
+    pub fn get_softlocks(
+        &self,
+        audit: &mut AuditScope,
+    ) -> Result<Vec<(Uuid, DbSoftLockV1)>, OperationError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT uuid, data FROM db_softlock")
+            .map_err(|e| {
+                ladmin_error!(audit, "SQLite Error {:?}", e);
+                OperationError::SQLiteError
+            })?;
+        let rows: Result<Vec<(String, Vec<u8>)>, _> = stmt
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+            .and_then(|iter| iter.collect())
+            .map_err(|e| {
+                ladmin_error!(audit, "SQLite Error {:?}", e);
+                OperationError::SQLiteError
+            });
+
+        rows?
+            .into_iter()
+            .map(|(u, d)| {
+                let uuid = Uuid::parse_str(u.as_str()).map_err(|_| OperationError::InvalidUuid)?;
+                let dbs = serde_cbor::from_slice(d.as_slice())
+                    .map_err(|_| OperationError::SerdeCborError)?;
+                Ok((uuid, dbs))
+            })
+            .collect()
+    }
+
+    pub fn create_auth_audit(&self, audit: &mut AuditScope) -> Result<(), OperationError> {
+        self.conn
+            .execute(
+                "CREATE TABLE IF NOT EXISTS db_auth_audit (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    ts INTEGER NOT NULL,
+                    account TEXT,
+                    success INTEGER NOT NULL,
+                    data BLOB NOT NULL
+                )
+                ",
+                NO_PARAMS,
+            )
+            .and_then(|_| {
+                self.conn.execute(
+                    "CREATE INDEX IF NOT EXISTS db_auth_audit_account_idx ON db_auth_audit (account, ts)",
+                    NO_PARAMS,
+                )
+            })
+            .map(|_| ())
+            .map_err(|e| {
+                ladmin_error!(audit, "SQLite Error {:?}", e);
+                OperationError::SQLiteError
+            })
+    }
+
+    pub fn write_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        records: &[DbAuthRecordV1],
+    ) -> Result<(), OperationError> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "INSERT INTO db_auth_audit (ts, account, success, data) VALUES(:ts, :account, :success, :data)",
+            )
+            .map_err(|e| {
+                ladmin_error!(audit, "SQLite Error {:?}", e);
+                OperationError::SQLiteError
+            })?;
+
+        records.iter().try_for_each(|record| {
+            let ts = record.t.as_secs() as i64;
+            let data = serde_cbor::to_vec(record).map_err(|_e| OperationError::SerdeCborError)?;
+            stmt.execute_named(&[
+                (":ts", &ts),
+                (":account", &record.n),
+                (":success", &record.s),
+                (":data", &data),
+            ])
+            .map(|_| ())
+            .map_err(|e| {
+                ladmin_error!(audit, "SQLite Error {:?}", e);
+                OperationError::SQLiteError
+            })
+        })
+    }
+
+    /// Remove the authentication records older than `before`, and then the oldest records
+    /// beyond the newest `max_records`, so that the store stays bounded.
+    pub fn purge_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        before: &Duration,
+        max_records: u32,
+    ) -> Result<(), OperationError> {
+        let before = before.as_secs() as i64;
+        let max_records = i64::from(max_records);
+        self.conn
+            .prepare("DELETE FROM db_auth_audit WHERE ts < :before")
+            .and_then(|mut stmt| stmt.execute_named(&[(":before", &before)]))
+            .and_then(|_| {
+                self.conn
+                    .prepare(
+                        "DELETE FROM db_auth_audit WHERE id <= (SELECT MAX(id) FROM db_auth_audit) - :max",
+                    )
+                    .and_then(|mut stmt| stmt.execute_named(&[(":max", &max_records)]))
+            })
+            .map(|_| ())
+            .map_err(|e| {
+                ladmin_error!(audit, "SQLite Error {:?}", e);
+                OperationError::SQLiteError
+            })
+    }
+
     pub fn create_uuid2rdn(&self, audit: &mut AuditScope) -> Result<(), OperationError> {
         self.conn
             .execute(
@@ -1216,7 +1454,27 @@ impl IdlSqliteWriteTransaction {
                 dbv_id2entry
             );
         }
-        //   * if v4 -> complete.
+        //   * if v4 -> add the softlock table.
+        if dbv_id2entry == 4 {
+            self.create_softlock(audit)?;
+            dbv_id2entry = 5;
+            ladmin_info!(
+                audit,
+                "dbv_id2entry migrated (db_softlock) -> {}",
+                dbv_id2entry
+            );
+        }
+        //   * if v5 -> add the authentication audit table.
+        if dbv_id2entry == 5 {
+            self.create_auth_audit(audit)?;
+            dbv_id2entry = 6;
+            ladmin_info!(
+                audit,
+                "dbv_id2entry migrated (db_auth_audit) -> {}",
+                dbv_id2entry
+            );
+        }
+        //   * if v6 -> complete.
 
         self.set_db_version_key(DBV_ID2ENTRY, dbv_id2entry)
             .map_err(|e| {
@@ -7,6 +7,7 @@ use std::sync::Arc;
 
 use crate::audit::AuditScope;
 use crate::be::dbentry::DbEntry;
+use crate::be::dbvalue::{DbAuthRecordV1, DbSoftLockV1};
 use crate::entry::{Entry, EntryCommitted, EntryNew, EntrySealed};
 use crate::event::EventLimits;
 use crate::filter::{Filter, FilterPlan, FilterResolved, FilterValidResolved};
@@ -47,6 +48,16 @@ pub enum IDL {
     Indexed(IDLBitRange),
 }
 
+/// The records to return from the authentication audit store, newest first.
+#[derive(Debug)]
+pub struct AuthRecordFilter {
+    pub account: Option<String>,
+    pub success: Option<bool>,
+    pub since: Duration,
+    pub until: Duration,
+    pub limit: u32,
+}
+
 #[derive(Debug)]
 pub struct IdRawEntry {
     id: u64,
@@ -684,6 +695,14 @@ pub trait BackendTransaction {
     ) -> Result<Option<String>, OperationError> {
         self.get_idlayer().uuid2rdn(audit, uuid)
     }
+
+    fn get_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        filter: &AuthRecordFilter,
+    ) -> Result<Vec<DbAuthRecordV1>, OperationError> {
+        self.get_idlayer().get_auth_records(audit, filter)
+    }
 }
 
 impl<'a> BackendTransaction for BackendReadTransaction<'a> {
@@ -1295,6 +1314,48 @@ impl<'a> BackendWriteTransaction<'a> {
         }
     }
 
+    pub fn write_softlock(
+        &self,
+        audit: &mut AuditScope,
+        uuid: &Uuid,
+        k: Option<&DbSoftLockV1>,
+    ) -> Result<(), OperationError> {
+        self.get_idlayer().write_softlock(audit, uuid, k)
+    }
+
+    pub fn purge_softlocks(
+        &self,
+        audit: &mut AuditScope,
+        ct: &Duration,
+    ) -> Result<(), OperationError> {
+        self.get_idlayer().purge_softlocks(audit, ct)
+    }
+
+    pub fn get_softlocks(
+        &self,
+        audit: &mut AuditScope,
+    ) -> Result<Vec<(Uuid, DbSoftLockV1)>, OperationError> {
+        self.get_idlayer().get_softlocks(audit)
+    }
+
+    pub fn write_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        records: &[DbAuthRecordV1],
+    ) -> Result<(), OperationError> {
+        self.get_idlayer().write_auth_records(audit, records)
+    }
+
+    pub fn purge_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        before: &Duration,
+        max_records: u32,
+    ) -> Result<(), OperationError> {
+        self.get_idlayer()
+            .purge_auth_records(audit, before, max_records)
+    }
+
     fn get_db_index_version(&self) -> i64 {
         self.get_idlayer().get_db_index_version()
     }
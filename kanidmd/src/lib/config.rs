@@ -10,12 +10,19 @@ pub struct IntegrationTestConfig {
 pub struct TlsConfiguration {
     pub chain: String,
     pub key: String,
+    // The CA bundle that client certificates are verified against. Without it, client
+    // certificates are not requested.
+    pub client_ca: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Configuration {
     pub address: String,
     pub ldapaddress: Option<String>,
+    // A plaintext ldap listener that offers StartTLS with the tls_config.
+    pub ldapstarttlsaddress: Option<String>,
+    // Refuse binds with credentials on the StartTLS listener until TLS is established.
+    pub ldap_bind_requires_tls: bool,
     pub threads: usize,
     // db type later
     pub db_path: String,
@@ -27,6 +34,7 @@ pub struct Configuration {
     pub integration_test_config: Option<Box<IntegrationTestConfig>>,
     pub log_level: Option<u32>,
     pub origin: String,
+    pub breach_corpus_path: Option<String>,
 }
 
 impl fmt::Display for Configuration {
@@ -36,11 +44,33 @@ impl fmt::Display for Configuration {
                 Some(la) => write!(f, "ldap address: {}, ", la),
                 None => write!(f, "ldap address: disabled, "),
             })
+            .and_then(|_| match &self.ldapstarttlsaddress {
+                Some(la) => write!(
+                    f,
+                    "ldap starttls address: {} (bind requires tls: {}), ",
+                    la, self.ldap_bind_requires_tls
+                ),
+                None => write!(f, "ldap starttls address: disabled, "),
+            })
             .and_then(|_| write!(f, "thread count: {}, ", self.threads))
             .and_then(|_| write!(f, "dbpath: {}, ", self.db_path))
             .and_then(|_| write!(f, "max request size: {}b, ", self.maximum_request))
             .and_then(|_| write!(f, "secure cookies: {}, ", self.secure_cookies))
             .and_then(|_| write!(f, "with TLS: {}, ", self.tls_config.is_some()))
+            .and_then(|_| {
+                write!(
+                    f,
+                    "with client certificates: {}, ",
+                    self.tls_config
+                        .as_ref()
+                        .map(|tls| tls.client_ca.is_some())
+                        .unwrap_or(false)
+                )
+            })
+            .and_then(|_| match &self.breach_corpus_path {
+                Some(p) => write!(f, "breach corpus: {}, ", p),
+                None => write!(f, "breach corpus: disabled, "),
+            })
             .and_then(|_| match self.log_level {
                 Some(u) => write!(f, "with log_level: {:x}, ", u),
                 None => write!(f, "with log_level: default, "),
@@ -60,6 +90,8 @@ impl Configuration {
         let mut c = Configuration {
             address: String::from("127.0.0.1:8080"),
             ldapaddress: None,
+            ldapstarttlsaddress: None,
+            ldap_bind_requires_tls: false,
             threads: num_cpus::get(),
             db_path: String::from(""),
             db_fs_type: None,
@@ -73,6 +105,7 @@ impl Configuration {
             integration_test_config: None,
             log_level: None,
             origin: "https://idm.example.com".to_string(),
+            breach_corpus_path: None,
         };
         let mut rng = StdRng::from_entropy();
         rng.fill(&mut c.cookie_key);
@@ -102,17 +135,43 @@ impl Configuration {
         self.ldapaddress = l.clone();
     }
 
+    pub fn update_ldapstarttlsbind(&mut self, l: &Option<String>, bind_requires_tls: Option<bool>) {
+        self.ldapstarttlsaddress = l.clone();
+        self.ldap_bind_requires_tls = bind_requires_tls.unwrap_or(false);
+    }
+
     pub fn update_origin(&mut self, o: &str) {
         self.origin = o.to_string();
     }
 
-    pub fn update_tls(&mut self, chain: &Option<String>, key: &Option<String>) {
+    pub fn update_breach_corpus_path(&mut self, p: &Option<String>) {
+        self.breach_corpus_path = p.clone();
+    }
+
+    pub fn update_tls(
+        &mut self,
+        chain: &Option<String>,
+        key: &Option<String>,
+        client_ca: &Option<String>,
+    ) {
         match (chain, key) {
-            (None, None) => {}
+            (None, None) => {
+                if client_ca.is_some() {
+                    eprintln!(
+                        "ERROR: Invalid TLS configuration - client ca requires chain and key!"
+                    );
+                    std::process::exit(1);
+                }
+            }
             (Some(chainp), Some(keyp)) => {
                 let chain = chainp.to_string();
                 let key = keyp.to_string();
-                self.tls_config = Some(TlsConfiguration { chain, key })
+                let client_ca = client_ca.clone();
+                self.tls_config = Some(TlsConfiguration {
+                    chain,
+                    key,
+                    client_ca,
+                })
             }
             _ => {
                 eprintln!("ERROR: Invalid TLS configuration - must provide chain and key!");
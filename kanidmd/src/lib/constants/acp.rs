@@ -95,11 +95,15 @@ pub const JSON_IDM_SELF_ACP_READ_V1: &str = r#"{
             "class",
             "memberof",
             "radius_secret",
+            "app_password",
             "gidnumber",
             "loginshell",
             "uuid",
             "account_expire",
-            "account_valid_from"
+            "account_valid_from",
+            "last_login",
+            "last_login_mechanism",
+            "oauth2_consent_scope_map"
         ]
     }
 }"#;
@@ -117,10 +121,10 @@ pub const JSON_IDM_SELF_ACP_WRITE_V1: &str = r#"{
             "\"self\""
         ],
         "acp_modify_removedattr": [
-            "name", "displayname", "legalname", "radius_secret", "primary_credential", "ssh_publickey", "unix_password"
+            "name", "displayname", "legalname", "radius_secret", "primary_credential", "ssh_publickey", "unix_password", "oauth2_consent_scope_map", "app_password"
         ],
         "acp_modify_presentattr": [
-            "name", "displayname", "legalname", "radius_secret", "primary_credential", "ssh_publickey", "unix_password"
+            "name", "displayname", "legalname", "radius_secret", "primary_credential", "ssh_publickey", "unix_password", "app_password"
         ]
     }
 }"#;
@@ -305,13 +309,13 @@ pub const JSON_IDM_ACP_GROUP_WRITE_PRIV_V1: &str = r#"{
             "{\"and\": [{\"eq\": [\"class\",\"group\"]}, {\"andnot\": {\"or\": [{\"eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"eq\": [\"class\", \"tombstone\"]}, {\"eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_search_attr": [
-            "class", "name", "spn", "uuid", "description", "member"
+            "class", "name", "spn", "uuid", "description", "member", "password_max_age", "credential_type_minimum", "password_min_length", "password_min_score", "password_min_char_classes"
         ],
         "acp_modify_removedattr": [
-            "name", "description", "member"
+            "name", "description", "member", "password_max_age", "credential_type_minimum", "password_min_length", "password_min_score", "password_min_char_classes"
         ],
         "acp_modify_presentattr": [
-            "name", "description", "member"
+            "name", "description", "member", "password_max_age", "credential_type_minimum", "password_min_length", "password_min_score", "password_min_char_classes"
         ]
     }
 }"#;
@@ -333,7 +337,7 @@ pub const JSON_IDM_ACP_ACCOUNT_READ_PRIV_V1: &str = r#"{
             "{\"and\": [{\"eq\": [\"class\",\"account\"]}, {\"andnot\": {\"or\": [{\"eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"eq\": [\"class\", \"tombstone\"]}, {\"eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_search_attr": [
-            "class", "name", "spn", "uuid", "displayname", "ssh_publickey", "primary_credential", "memberof", "mail", "gidnumber", "account_expire", "account_valid_from"
+            "class", "name", "spn", "uuid", "displayname", "ssh_publickey", "primary_credential", "memberof", "mail", "gidnumber", "account_expire", "account_valid_from", "api_token_session", "app_password", "last_login", "last_login_mechanism", "x509_fingerprint"
         ]
     }
 }"#;
@@ -355,10 +359,10 @@ pub const JSON_IDM_ACP_ACCOUNT_WRITE_PRIV_V1: &str = r#"{
             "{\"and\": [{\"eq\": [\"class\",\"account\"]}, {\"andnot\": {\"or\": [{\"eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"eq\": [\"class\", \"tombstone\"]}, {\"eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_modify_removedattr": [
-            "name", "displayname", "ssh_publickey", "primary_credential", "mail", "account_expire", "account_valid_from"
+            "name", "displayname", "ssh_publickey", "primary_credential", "mail", "account_expire", "account_valid_from", "api_token_session", "app_password", "credential_reset_intent", "x509_fingerprint"
         ],
         "acp_modify_presentattr": [
-            "name", "displayname", "ssh_publickey", "primary_credential", "mail", "account_expire", "account_valid_from"
+            "name", "displayname", "ssh_publickey", "primary_credential", "mail", "account_expire", "account_valid_from", "api_token_session", "credential_reset_intent", "x509_fingerprint"
         ]
     }
 }"#;
@@ -389,10 +393,11 @@ pub const JSON_IDM_ACP_ACCOUNT_MANAGE_PRIV_V1: &str = r#"{
             "ssh_publickey",
             "mail",
             "account_expire",
-            "account_valid_from"
+            "account_valid_from",
+            "x509_fingerprint"
         ],
         "acp_create_class": [
-            "object", "account"
+            "object", "account", "service_account"
         ]
     }
 }"#;
@@ -438,7 +443,7 @@ pub const JSON_IDM_ACP_HP_ACCOUNT_READ_PRIV_V1: &str = r#"{
             "{\"and\": [{\"eq\": [\"class\",\"account\"]}, {\"eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"andnot\": {\"or\": [{\"eq\": [\"class\", \"tombstone\"]}, {\"eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_search_attr": [
-            "class", "name", "spn", "uuid", "displayname", "ssh_publickey", "primary_credential", "memberof", "account_expire", "account_valid_from"
+            "class", "name", "spn", "uuid", "displayname", "ssh_publickey", "primary_credential", "memberof", "account_expire", "account_valid_from", "api_token_session", "app_password", "last_login", "last_login_mechanism", "x509_fingerprint"
         ]
     }
 }"#;
@@ -460,10 +465,10 @@ pub const JSON_IDM_ACP_HP_ACCOUNT_WRITE_PRIV_V1: &str = r#"{
             "{\"and\": [{\"eq\": [\"class\",\"account\"]}, {\"eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"andnot\": {\"or\": [{\"eq\": [\"class\", \"tombstone\"]}, {\"eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_modify_removedattr": [
-            "name", "displayname", "ssh_publickey", "primary_credential", "account_expire", "account_valid_from"
+            "name", "displayname", "ssh_publickey", "primary_credential", "account_expire", "account_valid_from", "api_token_session", "app_password", "credential_reset_intent", "x509_fingerprint"
         ],
         "acp_modify_presentattr": [
-            "name", "displayname", "ssh_publickey", "primary_credential", "account_expire", "account_valid_from"
+            "name", "displayname", "ssh_publickey", "primary_credential", "account_expire", "account_valid_from", "api_token_session", "credential_reset_intent", "x509_fingerprint"
         ]
     }
 }"#;
@@ -487,13 +492,13 @@ pub const JSON_IDM_ACP_HP_GROUP_WRITE_PRIV_V1: &str = r#"{
             "{\"and\": [{\"eq\": [\"class\",\"group\"]}, {\"eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"andnot\": {\"or\": [{\"eq\": [\"class\", \"tombstone\"]}, {\"eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_search_attr": [
-            "class", "name", "uuid", "description", "member"
+            "class", "name", "uuid", "description", "member", "password_max_age", "credential_type_minimum", "password_min_length", "password_min_score", "password_min_char_classes"
         ],
         "acp_modify_removedattr": [
-            "name", "description", "member"
+            "name", "description", "member", "password_max_age", "credential_type_minimum", "password_min_length", "password_min_score", "password_min_char_classes"
         ],
         "acp_modify_presentattr": [
-            "name", "description", "member"
+            "name", "description", "member", "password_max_age", "credential_type_minimum", "password_min_length", "password_min_score", "password_min_char_classes"
         ]
     }
 }"#;
@@ -764,10 +769,11 @@ pub const JSON_IDM_ACP_HP_ACCOUNT_MANAGE_PRIV_V1: &str = r#"{
             "primary_credential",
             "ssh_publickey",
             "account_expire",
-            "account_valid_from"
+            "account_valid_from",
+            "x509_fingerprint"
         ],
         "acp_create_class": [
-            "object", "account"
+            "object", "account", "service_account"
         ]
     }
 }"#;
@@ -858,10 +864,66 @@ pub const JSON_IDM_ACP_SYSTEM_CONFIG_PRIV_V1: &str = r#"{
             "name",
             "uuid",
             "description",
-            "badlist_password"
+            "badlist_password",
+            "password_history_length",
+            "softlock_max_failures",
+            "softlock_max_delay",
+            "softlock_reset_window",
+            "webauthn_user_verification",
+            "webauthn_attestation_ca",
+            "webauthn_attestation_aaguid",
+            "password_min_length",
+            "password_min_score",
+            "password_min_char_classes",
+            "password_badlist_words",
+            "auth_ratelimit_address_failures",
+            "auth_ratelimit_subnet_failures",
+            "auth_ratelimit_window",
+            "account_inactivity_days",
+            "account_inactivity_exclude_group",
+            "auth_audit_retention_days",
+            "auth_audit_max_records"
+        ],
+        "acp_modify_removedattr": [
+            "password_history_length",
+            "softlock_max_failures",
+            "softlock_max_delay",
+            "softlock_reset_window",
+            "webauthn_user_verification",
+            "webauthn_attestation_ca",
+            "webauthn_attestation_aaguid",
+            "password_min_length",
+            "password_min_score",
+            "password_min_char_classes",
+            "password_badlist_words",
+            "auth_ratelimit_address_failures",
+            "auth_ratelimit_subnet_failures",
+            "auth_ratelimit_window",
+            "account_inactivity_days",
+            "account_inactivity_exclude_group",
+            "auth_audit_retention_days",
+            "auth_audit_max_records"
         ],
         "acp_modify_presentattr": [
-            "badlist_password"
+            "badlist_password",
+            "password_history_length",
+            "softlock_max_failures",
+            "softlock_max_delay",
+            "softlock_reset_window",
+            "webauthn_user_verification",
+            "webauthn_attestation_ca",
+            "webauthn_attestation_aaguid",
+            "password_min_length",
+            "password_min_score",
+            "password_min_char_classes",
+            "password_badlist_words",
+            "auth_ratelimit_address_failures",
+            "auth_ratelimit_subnet_failures",
+            "auth_ratelimit_window",
+            "account_inactivity_days",
+            "account_inactivity_exclude_group",
+            "auth_audit_retention_days",
+            "auth_audit_max_records"
         ]
     }
 }"#;
@@ -926,3 +988,125 @@ pub const JSON_IDM_ACP_GROUP_UNIX_EXTEND_PRIV_V1: &str = r#"{
         "acp_modify_class": ["posixgroup"]
     }
 }"#;
+
+// 33 - oauth2 resource server manage
+pub const JSON_IDM_ACP_HP_OAUTH2_MANAGE_PRIV_V1: &str = r#"{
+    "attrs": {
+        "class": [
+            "object",
+            "access_control_profile",
+            "access_control_search",
+            "access_control_modify",
+            "access_control_create",
+            "access_control_delete"
+        ],
+        "name": ["idm_acp_hp_oauth2_manage_priv"],
+        "uuid": ["00000000-0000-0000-0000-ffffff000033"],
+        "description": ["Builtin IDM Control for managing oauth2 resource server integrations."],
+        "acp_receiver": [
+            "{\"eq\":[\"memberof\",\"00000000-0000-0000-0000-000000000025\"]}"
+        ],
+        "acp_targetscope": [
+            "{\"and\": [{\"eq\": [\"class\",\"oauth2_resource_server\"]}, {\"andnot\": {\"or\": [{\"eq\": [\"class\", \"tombstone\"]}, {\"eq\": [\"class\", \"recycled\"]}]}}]}"
+        ],
+        "acp_search_attr": [
+            "class",
+            "description",
+            "displayname",
+            "oauth2_rs_name",
+            "oauth2_rs_origin",
+            "oauth2_rs_redirect_uri",
+            "oauth2_rs_implicit_scopes",
+            "oauth2_rs_basic_secret"
+        ],
+        "acp_modify_removedattr": [
+            "description",
+            "displayname",
+            "oauth2_rs_name",
+            "oauth2_rs_origin",
+            "oauth2_rs_redirect_uri",
+            "oauth2_rs_implicit_scopes",
+            "oauth2_rs_basic_secret"
+        ],
+        "acp_modify_presentattr": [
+            "description",
+            "displayname",
+            "oauth2_rs_name",
+            "oauth2_rs_origin",
+            "oauth2_rs_redirect_uri",
+            "oauth2_rs_implicit_scopes"
+        ],
+        "acp_create_attr": [
+            "class",
+            "description",
+            "displayname",
+            "oauth2_rs_name",
+            "oauth2_rs_origin",
+            "oauth2_rs_redirect_uri",
+            "oauth2_rs_implicit_scopes"
+        ],
+        "acp_create_class": [
+            "oauth2_resource_server",
+            "oauth2_resource_server_basic",
+            "object"
+        ]
+    }
+}"#;
+
+// 34 - domain trust manage
+pub const JSON_IDM_ACP_DOMAIN_TRUST_MANAGE_PRIV_V1: &str = r#"{
+    "attrs": {
+        "class": [
+            "object",
+            "access_control_profile",
+            "access_control_search",
+            "access_control_modify",
+            "access_control_create",
+            "access_control_delete"
+        ],
+        "name": ["idm_acp_domain_trust_manage_priv"],
+        "uuid": ["00000000-0000-0000-0000-ffffff000034"],
+        "description": ["Builtin IDM Control for managing domain trusts and the foreign principals of trusted domains."],
+        "acp_receiver": [
+            "{\"eq\":[\"memberof\",\"00000000-0000-0000-0000-000000000020\"]}"
+        ],
+        "acp_targetscope": [
+            "{\"and\": [{\"or\": [{\"eq\": [\"class\",\"domain_trust\"]}, {\"eq\": [\"class\",\"foreign_principal\"]}]}, {\"andnot\": {\"or\": [{\"eq\": [\"class\", \"tombstone\"]}, {\"eq\": [\"class\", \"recycled\"]}]}}]}"
+        ],
+        "acp_search_attr": [
+            "class",
+            "name",
+            "uuid",
+            "spn",
+            "description",
+            "displayname",
+            "domain_name",
+            "trust_key"
+        ],
+        "acp_modify_removedattr": [
+            "description",
+            "displayname",
+            "trust_key"
+        ],
+        "acp_modify_presentattr": [
+            "description",
+            "displayname",
+            "trust_key"
+        ],
+        "acp_create_attr": [
+            "class",
+            "name",
+            "uuid",
+            "spn",
+            "description",
+            "displayname",
+            "domain_name",
+            "trust_key"
+        ],
+        "acp_create_class": [
+            "domain_trust",
+            "foreign_principal",
+            "object"
+        ]
+    }
+}"#;
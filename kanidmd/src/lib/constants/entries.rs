@@ -260,6 +260,28 @@ pub const JSON_IDM_HP_GROUP_WRITE_PRIV_V1: &str = r#"{
         ]
     }
 }"#;
+pub const JSON_IDM_HP_OAUTH2_MANAGE_PRIV_V1: &str = r#"{
+    "attrs": {
+        "class": ["group", "object"],
+        "name": ["idm_hp_oauth2_manage_priv"],
+        "uuid": ["00000000-0000-0000-0000-000000000025"],
+        "description": ["Builtin IDM Group for managing oauth2 resource server integrations to this authentication domain."],
+        "member": [
+            "00000000-0000-0000-0000-000000000019"
+        ]
+    }
+}"#;
+pub const JSON_IDM_AUTH_AUDIT_READ_PRIV_V1: &str = r#"{
+    "attrs": {
+        "class": ["group", "object"],
+        "name": ["idm_auth_audit_read_priv"],
+        "uuid": ["00000000-0000-0000-0000-000000000026"],
+        "description": ["Builtin IDM Group for granting read access to the authentication audit store."],
+        "member": [
+            "00000000-0000-0000-0000-000000000019"
+        ]
+    }
+}"#;
 pub const JSON_DOMAIN_ADMINS: &str = r#"{
     "attrs": {
         "class": ["group", "object"],
@@ -301,6 +323,8 @@ pub const JSON_IDM_HIGH_PRIVILEGE_V1: &str = r#"{
             "00000000-0000-0000-0000-000000000020",
             "00000000-0000-0000-0000-000000000023",
             "00000000-0000-0000-0000-000000000024",
+            "00000000-0000-0000-0000-000000000025",
+            "00000000-0000-0000-0000-000000000026",
             "00000000-0000-0000-0000-000000001000"
         ]
     }
@@ -311,7 +335,7 @@ pub const JSON_SYSTEM_INFO_V1: &str = r#"{
         "class": ["object", "system_info", "system"],
         "uuid": ["00000000-0000-0000-0000-ffffff000001"],
         "description": ["System (local) info and metadata object."],
-        "version": ["3"]
+        "version": ["5"]
     }
 }"#;
 
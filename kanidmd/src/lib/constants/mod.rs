@@ -13,7 +13,7 @@ pub use crate::constants::system_config::*;
 pub use crate::constants::uuids::*;
 
 // Increment this as we add new schema types and values!!!
-pub const SYSTEM_INDEX_VERSION: i64 = 12;
+pub const SYSTEM_INDEX_VERSION: i64 = 14;
 // On test builds, define to 60 seconds
 #[cfg(test)]
 pub const PURGE_FREQUENCY: u64 = 60;
@@ -39,4 +39,21 @@ pub const RECYCLEBIN_MAX_AGE: u64 = 604_800;
 pub const AUTH_SESSION_TIMEOUT: u64 = 300;
 // 5 minute mfa reg window
 pub const MFAREG_SESSION_TIMEOUT: u64 = 300;
+// 15 minute window to complete a credential reset once the token is redeemed
+pub const CREDRESET_SESSION_TIMEOUT: u64 = 900;
+// 1 hour default lifetime of a credential reset token, and 1 week at most
+pub const CREDRESET_INTENT_DEFAULT_TTL: u64 = 3600;
+pub const CREDRESET_INTENT_MAX_TTL: u64 = 604_800;
+// 5 minute window for oauth2 consent and code exchange
+pub const OAUTH2_SESSION_TIMEOUT: u64 = 300;
+// 15 minute lifetime of oauth2 access and id tokens
+pub const OAUTH2_ACCESS_TOKEN_EXPIRY: u32 = 900;
+// 1 hour lifetime of a signed user auth token
+pub const UAT_EXPIRY: u64 = 3600;
 pub const PW_MIN_LENGTH: usize = 10;
+// How long hashing a password should take on the server, which tunes the argon2id iterations
+pub const PW_HASH_TIME_TARGET_MS: u64 = 10;
+// The most app passwords an account may hold, as each one is a password hash to check
+pub const APP_PASSWORD_MAX: usize = 8;
+// The default minimum zxcvbn score of a password
+pub const PW_MIN_SCORE: u32 = 3;
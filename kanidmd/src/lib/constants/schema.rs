@@ -484,6 +484,1060 @@ pub const JSON_SCHEMA_ATTR_ACCOUNT_VALID_FROM: &str = r#"{
     }
 }"#;
 
+pub const JSON_SCHEMA_ATTR_ES256_PRIVATE_KEY_DER: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "An es256 private key"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "es256_private_key_der"
+      ],
+      "syntax": [
+        "PRIVATE_BINARY"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000074"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_SSH_CA_PRIVATE_KEY_DER: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The private key of the ssh certificate authority"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "ssh_ca_private_key_der"
+      ],
+      "syntax": [
+        "PRIVATE_BINARY"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000110"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_TRUST_KEY: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "A public key in JWK format that a trusted domain signs its user auth tokens with"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "trust_key"
+      ],
+      "syntax": [
+        "UTF8STRING"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000111"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_OAUTH2_RS_NAME: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The unique name of an external Oauth2 resource"
+      ],
+      "index": [
+        "EQUALITY"
+      ],
+      "unique": [
+        "true"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "oauth2_rs_name"
+      ],
+      "syntax": [
+        "UTF8STRING_INAME"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000075"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_OAUTH2_RS_ORIGIN: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The origin domain of an oauth2 resource server"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "oauth2_rs_origin"
+      ],
+      "syntax": [
+        "UTF8STRING"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000076"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_OAUTH2_RS_REDIRECT_URI: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "A redirect uri that an oauth2 resource server has registered"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "oauth2_rs_redirect_uri"
+      ],
+      "syntax": [
+        "UTF8STRING"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000114"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_OAUTH2_RS_IMPLICIT_SCOPES: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "An oauth2 scope that is implicitly granted to all users"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "oauth2_rs_implicit_scopes"
+      ],
+      "syntax": [
+        "UTF8STRING_INSENSITIVE"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000077"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_OAUTH2_RS_BASIC_SECRET: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "When using oauth2 basic authentication, the secret string of the resource server"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "oauth2_rs_basic_secret"
+      ],
+      "syntax": [
+        "UTF8STRING"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000078"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_OAUTH2_CONSENT_SCOPE_MAP: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "A set of scopes mapped from a relying server to a user, where the user has previously consented to the following. If changed or deleted, consent will be re-sought."
+      ],
+      "index": [
+        "EQUALITY"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "oauth2_consent_scope_map"
+      ],
+      "syntax": [
+        "OAUTH_SCOPE_MAP"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000079"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_API_TOKEN_SESSION: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The api tokens issued to a service account. A token that is removed from here is revoked."
+      ],
+      "index": [
+        "EQUALITY"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "api_token_session"
+      ],
+      "syntax": [
+        "API_TOKEN"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000082"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_APP_PASSWORD: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "Application specific passwords of an account, each limited by a claim to a single purpose such as ldap bind."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "app_password"
+      ],
+      "syntax": [
+        "CREDENTIAL"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000084"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_CREDENTIAL_RESET_INTENT: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The id of the outstanding credential reset token of an account. It is removed when the token is redeemed."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "credential_reset_intent"
+      ],
+      "syntax": [
+        "UUID"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000085"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PASSWORD_HISTORY_LENGTH: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The number of most recent passwords, including the current one, that an account may not reuse. Zero or absent disables the history."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "password_history_length"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000086"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PASSWORD_MAX_AGE: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The maximum age in seconds of the passwords of members of this group, after which they must be changed."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "password_max_age"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000087"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_CREDENTIAL_TYPE_MINIMUM: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The weakest type of credential that members of this group may authenticate with."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "credential_type_minimum"
+      ],
+      "syntax": [
+        "UTF8STRING_INSENSITIVE"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000088"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_SOFTLOCK_MAX_FAILURES: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The number of failed password authentications within the softlock reset window after which a credential is locked until the window ends."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "softlock_max_failures"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000089"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_SOFTLOCK_MAX_DELAY: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The longest delay in seconds applied between failed password authentications."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "softlock_max_delay"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000090"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_SOFTLOCK_RESET_WINDOW: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The length in seconds of the window in which failed password authentications are counted."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "softlock_reset_window"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000091"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_WEBAUTHN_USER_VERIFICATION: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "If webauthn tokens must verify the user, such as with a pin or biometric."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "webauthn_user_verification"
+      ],
+      "syntax": [
+        "BOOLEAN"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000092"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_WEBAUTHN_ATTESTATION_CA: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "A PEM certificate authority that webauthn token attestations must chain to."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "webauthn_attestation_ca"
+      ],
+      "syntax": [
+        "UTF8STRING"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000093"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_WEBAUTHN_ATTESTATION_AAGUID: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The AAGUIDs of the webauthn token models that may be registered."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "webauthn_attestation_aaguid"
+      ],
+      "syntax": [
+        "UUID"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000094"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_WEBAUTHN_CREDENTIAL_ID: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The ids of the webauthn tokens of the primary credential, to find the account of a discoverable credential."
+      ],
+      "index": [
+        "EQUALITY"
+      ],
+      "unique": [
+        "true"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "webauthn_credential_id"
+      ],
+      "syntax": [
+        "UTF8STRING"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000095"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PASSWORD_MIN_LENGTH: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The minimum length of new passwords."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "password_min_length"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000096"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PASSWORD_MIN_SCORE: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The minimum zxcvbn score, from 0 to 4, of new passwords."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "password_min_score"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000097"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PASSWORD_MIN_CHAR_CLASSES: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The number of character classes (lower case, upper case, digits and symbols), from 0 to 4, that new passwords must contain."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "password_min_char_classes"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000098"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PASSWORD_BADLIST_WORDS: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "Words, such as the domain or organisation name, that new passwords may not contain."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "password_badlist_words"
+      ],
+      "syntax": [
+        "UTF8STRING_INSENSITIVE"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000099"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_AUTH_RATELIMIT_ADDRESS_FAILURES: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The number of failed authentications from one client address within the rate limit window after which the address is blocked until the window ends. 0 disables the limit."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "auth_ratelimit_address_failures"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000100"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_AUTH_RATELIMIT_SUBNET_FAILURES: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The number of failed authentications from one client subnet (/24 for ipv4, /64 for ipv6) within the rate limit window after which the subnet is blocked until the window ends. 0 disables the limit."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "auth_ratelimit_subnet_failures"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000101"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_AUTH_RATELIMIT_WINDOW: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The length in seconds of the window in which failed authentications from a client address or subnet are counted."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "auth_ratelimit_window"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000102"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_LAST_LOGIN: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The time of the most recent successful authentication of this account."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "last_login"
+      ],
+      "syntax": [
+        "DATETIME"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000103"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_LAST_LOGIN_MECHANISM: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The mechanism, such as password or webauthn, of the most recent successful authentication of this account."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "last_login_mechanism"
+      ],
+      "syntax": [
+        "UTF8STRING"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000104"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_X509_FINGERPRINT: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The sha256 fingerprints, in hex, of the TLS client certificates that authenticate this account."
+      ],
+      "index": [
+        "EQUALITY"
+      ],
+      "unique": [
+        "true"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "x509_fingerprint"
+      ],
+      "syntax": [
+        "UTF8STRING_INSENSITIVE"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000109"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_ACCOUNT_INACTIVITY_DAYS: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The number of days without a successful authentication after which an account is expired. 0 disables this."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "account_inactivity_days"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000105"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_ACCOUNT_INACTIVITY_EXCLUDE_GROUP: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "Members of this group are never expired for inactivity."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "account_inactivity_exclude_group"
+      ],
+      "syntax": [
+        "REFERENCE_UUID"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000106"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_AUTH_AUDIT_RETENTION_DAYS: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The number of days that authentication attempts are kept in the audit store."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "auth_audit_retention_days"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000107"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_AUTH_AUDIT_MAX_RECORDS: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The greatest number of authentication attempts kept in the audit store."
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "auth_audit_max_records"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000108"
+      ]
+    }
+}"#;
+
 // === classes ===
 
 pub const JSON_SCHEMA_CLASS_PERSON: &str = r#"
@@ -530,7 +1584,12 @@ pub const JSON_SCHEMA_CLASS_GROUP: &str = r#"
         "group"
       ],
       "systemmay": [
-        "member"
+        "member",
+        "password_max_age",
+        "credential_type_minimum",
+        "password_min_length",
+        "password_min_score",
+        "password_min_char_classes"
       ],
       "systemmust": [
         "name",
@@ -561,7 +1620,14 @@ pub const JSON_SCHEMA_CLASS_ACCOUNT: &str = r#"
         "ssh_publickey",
         "radius_secret",
         "account_expire",
-        "account_valid_from"
+        "account_valid_from",
+        "oauth2_consent_scope_map",
+        "app_password",
+        "credential_reset_intent",
+        "webauthn_credential_id",
+        "last_login",
+        "last_login_mechanism",
+        "x509_fingerprint"
       ],
       "systemmust": [
         "displayname",
@@ -595,7 +1661,9 @@ pub const JSON_SCHEMA_CLASS_DOMAIN_INFO: &str = r#"
         "domain_info"
       ],
       "systemmay": [
-        "domain_ssid"
+        "domain_ssid",
+        "es256_private_key_der",
+        "ssh_ca_private_key_der"
       ],
       "systemmust": [
         "name",
@@ -677,7 +1745,25 @@ pub const JSON_SCHEMA_CLASS_SYSTEM_CONFIG: &str = r#"
       ],
       "systemmay": [
         "description",
-        "badlist_password"
+        "badlist_password",
+        "password_history_length",
+        "softlock_max_failures",
+        "softlock_max_delay",
+        "softlock_reset_window",
+        "webauthn_user_verification",
+        "webauthn_attestation_ca",
+        "webauthn_attestation_aaguid",
+        "password_min_length",
+        "password_min_score",
+        "password_min_char_classes",
+        "password_badlist_words",
+        "auth_ratelimit_address_failures",
+        "auth_ratelimit_subnet_failures",
+        "auth_ratelimit_window",
+        "account_inactivity_days",
+        "account_inactivity_exclude_group",
+        "auth_audit_retention_days",
+        "auth_audit_max_records"
       ],
       "uuid": [
         "00000000-0000-0000-0000-ffff00000060"
@@ -685,3 +1771,140 @@ pub const JSON_SCHEMA_CLASS_SYSTEM_CONFIG: &str = r#"
     }
   }
 "#;
+
+pub const JSON_SCHEMA_CLASS_OAUTH2_RS: &str = r#"
+  {
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "classtype"
+      ],
+      "description": [
+        "The class representing a configured Oauth2 Resource Server"
+      ],
+      "classname": [
+        "oauth2_resource_server"
+      ],
+      "systemmay": [
+        "description",
+        "oauth2_rs_redirect_uri",
+        "oauth2_rs_implicit_scopes"
+      ],
+      "systemmust": [
+        "oauth2_rs_name",
+        "displayname",
+        "oauth2_rs_origin"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000080"
+      ]
+    }
+  }
+"#;
+
+pub const JSON_SCHEMA_CLASS_OAUTH2_RS_BASIC: &str = r#"
+  {
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "classtype"
+      ],
+      "description": [
+        "The class representing a configured Oauth2 Resource Server authenticated with http basic authentication"
+      ],
+      "classname": [
+        "oauth2_resource_server_basic"
+      ],
+      "systemmust": [
+        "oauth2_rs_basic_secret"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000081"
+      ]
+    }
+  }
+"#;
+
+pub const JSON_SCHEMA_CLASS_SERVICE_ACCOUNT: &str = r#"
+  {
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "classtype"
+      ],
+      "description": [
+        "Object representation of a service account, requires account. A service account can not hold a password credential and authenticates with api tokens."
+      ],
+      "classname": [
+        "service_account"
+      ],
+      "systemmay": [
+        "description",
+        "api_token_session"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000083"
+      ]
+    }
+  }
+"#;
+
+pub const JSON_SCHEMA_CLASS_DOMAIN_TRUST: &str = r#"
+  {
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "classtype"
+      ],
+      "description": [
+        "A one way trust of another kanidm domain. Users of the trusted domain may be granted access to this domain as foreign principals."
+      ],
+      "classname": [
+        "domain_trust"
+      ],
+      "systemmay": [
+        "description"
+      ],
+      "systemmust": [
+        "name",
+        "domain_name",
+        "trust_key"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000112"
+      ]
+    }
+  }
+"#;
+
+pub const JSON_SCHEMA_CLASS_FOREIGN_PRINCIPAL: &str = r#"
+  {
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "classtype"
+      ],
+      "description": [
+        "A reference to an account of a trusted domain, sharing its uuid and spn, that local groups may contain."
+      ],
+      "classname": [
+        "foreign_principal"
+      ],
+      "systemmay": [
+        "description",
+        "displayname"
+      ],
+      "systemmust": [
+        "spn"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000113"
+      ]
+    }
+  }
+"#;
@@ -21,7 +21,7 @@ pub const _STR_UUID_IDM_ACCOUNT_MANAGE_PRIV: &str = "00000000-0000-0000-0000-000
 pub const _STR_UUID_IDM_GROUP_MANAGE_PRIV: &str = "00000000-0000-0000-0000-000000000015";
 pub const _STR_UUID_IDM_HP_ACCOUNT_MANAGE_PRIV: &str = "00000000-0000-0000-0000-000000000016";
 pub const _STR_UUID_IDM_HP_GROUP_MANAGE_PRIV: &str = "00000000-0000-0000-0000-000000000017";
-pub const _STR_UUID_IDM_ADMIN_V1: &str = "00000000-0000-0000-0000-000000000018";
+pub const STR_UUID_IDM_ADMIN_V1: &str = "00000000-0000-0000-0000-000000000018";
 pub const _STR_UUID_SYSTEM_ADMINS: &str = "00000000-0000-0000-0000-000000000019";
 pub const STR_UUID_DOMAIN_ADMINS: &str = "00000000-0000-0000-0000-000000000020";
 pub const _STR_UUID_IDM_ACCOUNT_UNIX_EXTEND_PRIV: &str = "00000000-0000-0000-0000-000000000021";
@@ -29,6 +29,8 @@ pub const _STR_UUID_IDM_GROUP_UNIX_EXTEND_PRIV: &str = "00000000-0000-0000-0000-
 pub const _STR_UUID_IDM_PEOPLE_ACCOUNT_PASSWORD_IMPORT_PRIV: &str =
     "00000000-0000-0000-0000-000000000023";
 pub const _STR_UUID_IDM_PEOPLE_EXTEND_PRIV: &str = "00000000-0000-0000-0000-000000000024";
+pub const _STR_UUID_IDM_HP_OAUTH2_MANAGE_PRIV: &str = "00000000-0000-0000-0000-000000000025";
+pub const STR_UUID_IDM_AUTH_AUDIT_READ_PRIV: &str = "00000000-0000-0000-0000-000000000026";
 //
 pub const _STR_UUID_IDM_HIGH_PRIVILEGE: &str = "00000000-0000-0000-0000-000000001000";
 
@@ -118,6 +120,71 @@ pub const STR_UUID_SCHEMA_ATTR_OBJECTCLASS: &str = "00000000-0000-0000-0000-ffff
 
 pub const _STR_UUID_SCHEMA_ATTR_ACCOUNT_EXPIRE: &str = "00000000-0000-0000-0000-ffff00000072";
 pub const _STR_UUID_SCHEMA_ATTR_ACCOUNT_VALID_FROM: &str = "00000000-0000-0000-0000-ffff00000073";
+pub const _STR_UUID_SCHEMA_ATTR_ES256_PRIVATE_KEY_DER: &str =
+    "00000000-0000-0000-0000-ffff00000074";
+pub const _STR_UUID_SCHEMA_ATTR_OAUTH2_RS_NAME: &str = "00000000-0000-0000-0000-ffff00000075";
+pub const _STR_UUID_SCHEMA_ATTR_OAUTH2_RS_ORIGIN: &str = "00000000-0000-0000-0000-ffff00000076";
+pub const _STR_UUID_SCHEMA_ATTR_OAUTH2_RS_IMPLICIT_SCOPES: &str =
+    "00000000-0000-0000-0000-ffff00000077";
+pub const _STR_UUID_SCHEMA_ATTR_OAUTH2_RS_BASIC_SECRET: &str =
+    "00000000-0000-0000-0000-ffff00000078";
+pub const _STR_UUID_SCHEMA_ATTR_OAUTH2_CONSENT_SCOPE_MAP: &str =
+    "00000000-0000-0000-0000-ffff00000079";
+pub const _STR_UUID_SCHEMA_CLASS_OAUTH2_RS: &str = "00000000-0000-0000-0000-ffff00000080";
+pub const _STR_UUID_SCHEMA_CLASS_OAUTH2_RS_BASIC: &str = "00000000-0000-0000-0000-ffff00000081";
+pub const _STR_UUID_SCHEMA_ATTR_API_TOKEN_SESSION: &str = "00000000-0000-0000-0000-ffff00000082";
+pub const _STR_UUID_SCHEMA_CLASS_SERVICE_ACCOUNT: &str = "00000000-0000-0000-0000-ffff00000083";
+pub const _STR_UUID_SCHEMA_ATTR_APP_PASSWORD: &str = "00000000-0000-0000-0000-ffff00000084";
+pub const _STR_UUID_SCHEMA_ATTR_CREDENTIAL_RESET_INTENT: &str =
+    "00000000-0000-0000-0000-ffff00000085";
+pub const _STR_UUID_SCHEMA_ATTR_PASSWORD_HISTORY_LENGTH: &str =
+    "00000000-0000-0000-0000-ffff00000086";
+pub const _STR_UUID_SCHEMA_ATTR_PASSWORD_MAX_AGE: &str = "00000000-0000-0000-0000-ffff00000087";
+pub const _STR_UUID_SCHEMA_ATTR_CREDENTIAL_TYPE_MINIMUM: &str =
+    "00000000-0000-0000-0000-ffff00000088";
+pub const _STR_UUID_SCHEMA_ATTR_SOFTLOCK_MAX_FAILURES: &str =
+    "00000000-0000-0000-0000-ffff00000089";
+pub const _STR_UUID_SCHEMA_ATTR_SOFTLOCK_MAX_DELAY: &str = "00000000-0000-0000-0000-ffff00000090";
+pub const _STR_UUID_SCHEMA_ATTR_SOFTLOCK_RESET_WINDOW: &str =
+    "00000000-0000-0000-0000-ffff00000091";
+pub const _STR_UUID_SCHEMA_ATTR_WEBAUTHN_USER_VERIFICATION: &str =
+    "00000000-0000-0000-0000-ffff00000092";
+pub const _STR_UUID_SCHEMA_ATTR_WEBAUTHN_ATTESTATION_CA: &str =
+    "00000000-0000-0000-0000-ffff00000093";
+pub const _STR_UUID_SCHEMA_ATTR_WEBAUTHN_ATTESTATION_AAGUID: &str =
+    "00000000-0000-0000-0000-ffff00000094";
+pub const _STR_UUID_SCHEMA_ATTR_WEBAUTHN_CREDENTIAL_ID: &str =
+    "00000000-0000-0000-0000-ffff00000095";
+pub const _STR_UUID_SCHEMA_ATTR_PASSWORD_MIN_LENGTH: &str = "00000000-0000-0000-0000-ffff00000096";
+pub const _STR_UUID_SCHEMA_ATTR_PASSWORD_MIN_SCORE: &str = "00000000-0000-0000-0000-ffff00000097";
+pub const _STR_UUID_SCHEMA_ATTR_PASSWORD_MIN_CHAR_CLASSES: &str =
+    "00000000-0000-0000-0000-ffff00000098";
+pub const _STR_UUID_SCHEMA_ATTR_PASSWORD_BADLIST_WORDS: &str =
+    "00000000-0000-0000-0000-ffff00000099";
+pub const _STR_UUID_SCHEMA_ATTR_AUTH_RATELIMIT_ADDRESS_FAILURES: &str =
+    "00000000-0000-0000-0000-ffff00000100";
+pub const _STR_UUID_SCHEMA_ATTR_AUTH_RATELIMIT_SUBNET_FAILURES: &str =
+    "00000000-0000-0000-0000-ffff00000101";
+pub const _STR_UUID_SCHEMA_ATTR_AUTH_RATELIMIT_WINDOW: &str =
+    "00000000-0000-0000-0000-ffff00000102";
+pub const _STR_UUID_SCHEMA_ATTR_LAST_LOGIN: &str = "00000000-0000-0000-0000-ffff00000103";
+pub const _STR_UUID_SCHEMA_ATTR_LAST_LOGIN_MECHANISM: &str = "00000000-0000-0000-0000-ffff00000104";
+pub const _STR_UUID_SCHEMA_ATTR_ACCOUNT_INACTIVITY_DAYS: &str =
+    "00000000-0000-0000-0000-ffff00000105";
+pub const _STR_UUID_SCHEMA_ATTR_ACCOUNT_INACTIVITY_EXCLUDE_GROUP: &str =
+    "00000000-0000-0000-0000-ffff00000106";
+pub const _STR_UUID_SCHEMA_ATTR_AUTH_AUDIT_RETENTION_DAYS: &str =
+    "00000000-0000-0000-0000-ffff00000107";
+pub const _STR_UUID_SCHEMA_ATTR_AUTH_AUDIT_MAX_RECORDS: &str =
+    "00000000-0000-0000-0000-ffff00000108";
+pub const _STR_UUID_SCHEMA_ATTR_X509_FINGERPRINT: &str = "00000000-0000-0000-0000-ffff00000109";
+pub const _STR_UUID_SCHEMA_ATTR_SSH_CA_PRIVATE_KEY_DER: &str =
+    "00000000-0000-0000-0000-ffff00000110";
+pub const _STR_UUID_SCHEMA_ATTR_TRUST_KEY: &str = "00000000-0000-0000-0000-ffff00000111";
+pub const _STR_UUID_SCHEMA_CLASS_DOMAIN_TRUST: &str = "00000000-0000-0000-0000-ffff00000112";
+pub const _STR_UUID_SCHEMA_CLASS_FOREIGN_PRINCIPAL: &str = "00000000-0000-0000-0000-ffff00000113";
+pub const _STR_UUID_SCHEMA_ATTR_OAUTH2_RS_REDIRECT_URI: &str =
+    "00000000-0000-0000-0000-ffff00000114";
 
 // System and domain infos
 // I'd like to strongly criticise william of the past for fucking up these allocations.
@@ -163,6 +230,9 @@ pub const _STR_UUID_IDM_ACP_GROUP_UNIX_EXTEND_PRIV_V1: &str =
 pub const _STR_UUID_IDM_ACP_PEOPLE_ACCOUNT_PASSWORD_IMPORT_PRIV_V1: &str =
     "00000000-0000-0000-0000-ffffff000031";
 pub const _STR_UUID_IDM_ACP_PEOPLE_EXTEND_PRIV_V1: &str = "00000000-0000-0000-0000-ffffff000032";
+pub const _STR_UUID_IDM_ACP_HP_OAUTH2_MANAGE_PRIV_V1: &str = "00000000-0000-0000-0000-ffffff000033";
+pub const _STR_UUID_IDM_ACP_DOMAIN_TRUST_MANAGE_PRIV_V1: &str =
+    "00000000-0000-0000-0000-ffffff000034";
 
 // End of system ranges
 pub const STR_UUID_DOES_NOT_EXIST: &str = "00000000-0000-0000-0000-fffffffffffe";
@@ -170,6 +240,9 @@ pub const STR_UUID_ANONYMOUS: &str = "00000000-0000-0000-0000-ffffffffffff";
 
 lazy_static! {
     pub static ref UUID_ADMIN: Uuid = Uuid::parse_str(STR_UUID_ADMIN).unwrap();
+    pub static ref UUID_IDM_ADMIN: Uuid = Uuid::parse_str(STR_UUID_IDM_ADMIN_V1).unwrap();
+    pub static ref UUID_IDM_AUTH_AUDIT_READ_PRIV: Uuid =
+        Uuid::parse_str(STR_UUID_IDM_AUTH_AUDIT_READ_PRIV).unwrap();
     pub static ref UUID_DOES_NOT_EXIST: Uuid = Uuid::parse_str(STR_UUID_DOES_NOT_EXIST).unwrap();
     pub static ref UUID_ANONYMOUS: Uuid = Uuid::parse_str(STR_UUID_ANONYMOUS).unwrap();
     pub static ref UUID_SYSTEM_CONFIG: Uuid = Uuid::parse_str(STR_UUID_SYSTEM_CONFIG).unwrap();
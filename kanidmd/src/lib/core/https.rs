@@ -1,31 +1,46 @@
 use crate::actors::v1_read::QueryServerReadV1;
 use crate::actors::v1_read::{
-    AuthMessage, IdmAccountUnixAuthMessage, InternalRadiusReadMessage,
+    AuthAuditSearchMessage, AuthMessage, IdmAccountAppPasswordListMessage,
+    IdmAccountRadiusAuthMessage, IdmAccountSoftLockReadMessage, IdmAccountSshCertSignMessage,
+    IdmAccountUnixAuthMessage, IdmServiceAccountApiTokenListMessage, InternalRadiusReadMessage,
     InternalRadiusTokenReadMessage, InternalSearchMessage, InternalSearchRecycledMessage,
     InternalSshKeyReadMessage, InternalSshKeyTagReadMessage, InternalUnixGroupTokenReadMessage,
-    InternalUnixUserTokenReadMessage, SearchMessage, WhoamiMessage,
+    InternalUnixUserTokenReadMessage, Oauth2OpenIdDiscoveryMessage, Oauth2OpenIdPublicKeyMessage,
+    SearchMessage, SshCaPublicKeyMessage, WhoamiMessage,
 };
 use crate::actors::v1_write::QueryServerWriteV1;
 use crate::actors::v1_write::{
-    AppendAttributeMessage, CreateMessage, DeleteMessage, IdmAccountPersonExtendMessage,
-    IdmAccountSetPasswordMessage, IdmAccountUnixExtendMessage, IdmAccountUnixSetCredMessage,
-    IdmGroupUnixExtendMessage, InternalCredentialSetMessage, InternalDeleteMessage,
-    InternalRegenerateRadiusMessage, InternalSshKeyCreateMessage, ModifyMessage,
-    PurgeAttributeMessage, RemoveAttributeValueMessage, ReviveRecycledMessage, SetAttributeMessage,
+    AppendAttributeMessage, CreateMessage, DeleteMessage, IdmAccountAppPasswordGenerateMessage,
+    IdmAccountAppPasswordRemoveMessage, IdmAccountCredentialResetIntentMessage,
+    IdmAccountPersonExtendMessage, IdmAccountSetPasswordMessage, IdmAccountSoftLockClearMessage,
+    IdmAccountUnixExtendMessage, IdmAccountUnixSetCredMessage, IdmCredentialResetBeginMessage,
+    IdmCredentialResetStepMessage, IdmGroupUnixExtendMessage,
+    IdmServiceAccountApiTokenDestroyMessage, IdmServiceAccountApiTokenGenerateMessage,
+    InternalCredentialSetMessage, InternalDeleteMessage, InternalRegenerateRadiusMessage,
+    InternalSshKeyCreateMessage, ModifyMessage, Oauth2AuthoriseMessage,
+    Oauth2AuthorisePermitMessage, Oauth2TokenExchangeMessage, PurgeAttributeMessage,
+    RemoveAttributeValueMessage, ReviveRecycledMessage, SetAttributeMessage,
 };
 use crate::config::TlsConfiguration;
+use crate::crypto::{ClientCertificate, JwsSigner};
 use crate::event::AuthResult;
 use crate::filter::{Filter, FilterInvalid};
 use crate::idm::AuthState;
 use crate::status::{StatusActor, StatusRequestEvent};
+use crate::utils::duration_from_epoch_now;
 use crate::value::PartialValue;
 
+use kanidm_proto::jws::JwsValidator;
+use kanidm_proto::oauth2::{
+    AccessTokenRequest, AuthorisationRequest, ConsentRequest, ErrorResponse, Jwk, Oauth2Error,
+};
 use kanidm_proto::v1::Entry as ProtoEntry;
 use kanidm_proto::v1::OperationError;
 use kanidm_proto::v1::{
-    AccountUnixExtend, AuthRequest, AuthResponse, AuthState as ProtoAuthState, CreateRequest,
-    DeleteRequest, GroupUnixExtend, ModifyRequest, SearchRequest, SetCredentialRequest,
-    SingleStringRequest, UserAuthToken,
+    AccountUnixExtend, ApiTokenGenerate, AppPasswordGenerate, AuthAuditQuery, AuthRequest,
+    AuthResponse, AuthState as ProtoAuthState, CreateRequest, CredentialResetIntentRequest,
+    CredentialResetRequest, DeleteRequest, GroupUnixExtend, ModifyRequest, SearchRequest,
+    SetCredentialRequest, SingleStringRequest, UserAuthToken,
 };
 
 use serde::Serialize;
@@ -33,13 +48,15 @@ use std::time::Duration;
 use uuid::Uuid;
 
 // Temporary
-use tide_rustls::TlsListener;
-// use openssl::ssl::{SslAcceptor, SslAcceptorBuilder};
-// use tokio::net::TcpListener;
-// use async_std::io;
 use async_std::task;
-// use std::net;
-// use std::str::FromStr;
+use openssl::ssl::{Ssl, SslAcceptor, SslAcceptorBuilder};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tide_rustls::TlsListener;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_openssl::SslStream;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -47,38 +64,58 @@ pub struct AppState {
     pub qe_w_ref: &'static QueryServerWriteV1,
     pub qe_r_ref: &'static QueryServerReadV1,
     // Store the token management parts.
-    pub fernet_handle: fernet::Fernet,
+    pub jws_signer: JwsSigner,
+    pub jws_validator: JwsValidator,
+    pub jwk: Jwk,
 }
 
 pub trait RequestExtensions {
     fn get_current_uat(&self) -> Option<UserAuthToken>;
 
+    fn get_current_trusted_uat(&self) -> Option<UserAuthToken>;
+
     fn get_url_param(&self, param: &str) -> Result<String, tide::Error>;
 }
 
+fn get_bearer_token(req: &tide::Request<AppState>) -> Option<&str> {
+    req.header(tide::http::headers::AUTHORIZATION)
+        .and_then(|hv| {
+            // Get the first header value.
+            hv.get(0)
+        })
+        .and_then(|h| {
+            // Turn it to a &str, and then check the prefix
+            h.as_str().strip_prefix("Bearer ")
+        })
+}
+
 impl RequestExtensions for tide::Request<AppState> {
     fn get_current_uat(&self) -> Option<UserAuthToken> {
-        // Contact the QS to get it to validate wtf is up.
-        let kref = &self.state().fernet_handle;
+        let vref = &self.state().jws_validator;
         // self.session().get::<UserAuthToken>("uat")
-        self.header(tide::http::headers::AUTHORIZATION)
-            .and_then(|hv| {
-                // Get the first header value.
-                hv.get(0)
-            })
-            .and_then(|h| {
-                // Turn it to a &str, and then check the prefix
-                h.as_str().strip_prefix("Bearer ")
-            })
-            .and_then(|ts| {
-                // Take the token str and attempt to decrypt
-                // Attempt to re-inflate a UAT from bytes.
-                let uat: Option<UserAuthToken> = kref
-                    .decrypt_with_ttl(ts, 3600)
-                    .ok()
-                    .and_then(|b| serde_json::from_slice(&b).ok());
+        get_bearer_token(self).and_then(|ts| {
+            // Take the token str and check the signature and expiry. The validator
+            // is the same one that clients can build from our public jwk.
+            let ct = duration_from_epoch_now();
+            vref.validate_uat(ts, ct).ok().map(|uat| {
+                // Service account api tokens track when they were last used.
+                self.state().qe_w_ref.handle_apitokenused(&uat);
                 uat
             })
+        })
+    }
+
+    // As get_current_uat, but also accepts tokens issued by a domain that we trust. This is
+    // only for the endpoints that build their event with from_ro_uat or from_rw_uat, as
+    // those check that a remote token can only act as its foreign principal.
+    fn get_current_trusted_uat(&self) -> Option<UserAuthToken> {
+        self.get_current_uat().or_else(|| {
+            get_bearer_token(self).and_then(|ts| {
+                self.state()
+                    .qe_r_ref
+                    .validate_trusted_uat(ts, duration_from_epoch_now())
+            })
+        })
     }
 
     fn get_url_param(&self, param: &str) -> Result<String, tide::Error> {
@@ -125,6 +162,40 @@ pub fn to_tide_response<T: Serialize>(
     })
 }
 
+// Oauth2 errors are returned in the format defined by RFC 6749 rather than as an
+// OperationError so that standard oauth2 clients can interpret them.
+pub fn to_oauth2_response<T: Serialize>(v: Result<T, Oauth2Error>, hvalue: String) -> tide::Result {
+    match v {
+        Ok(iv) => {
+            let mut res = tide::Response::new(200);
+            tide::Body::from_json(&iv).map(|b| {
+                res.set_body(b);
+                res
+            })
+        }
+        Err(e) => {
+            let sc = match &e {
+                Oauth2Error::AuthenticationRequired | Oauth2Error::InvalidClient => {
+                    tide::StatusCode::Unauthorized
+                }
+                Oauth2Error::AccessDenied => tide::StatusCode::Forbidden,
+                Oauth2Error::ServerError => tide::StatusCode::InternalServerError,
+                Oauth2Error::TemporarilyUnavailable => tide::StatusCode::ServiceUnavailable,
+                _ => tide::StatusCode::BadRequest,
+            };
+            let mut res = tide::Response::new(sc);
+            tide::Body::from_json(&ErrorResponse::from(e)).map(|b| {
+                res.set_body(b);
+                res
+            })
+        }
+    }
+    .map(|mut res| {
+        res.insert_header("X-KANIDM-OPID", hvalue);
+        res
+    })
+}
+
 macro_rules! new_eventid {
     () => {{
         let eventid = Uuid::new_v4();
@@ -137,7 +208,7 @@ macro_rules! new_eventid {
 
 // pub async fn create((req, session, state): (Json<CreateRequest>, Session, Data<AppState>),
 pub async fn create(mut req: tide::Request<AppState>) -> tide::Result {
-    let uat = req.get_current_uat();
+    let uat = req.get_current_trusted_uat();
     // parse the req to a CreateRequest
     let msg: CreateRequest = req.body_json().await?;
 
@@ -149,7 +220,7 @@ pub async fn create(mut req: tide::Request<AppState>) -> tide::Result {
 }
 
 pub async fn modify(mut req: tide::Request<AppState>) -> tide::Result {
-    let uat = req.get_current_uat();
+    let uat = req.get_current_trusted_uat();
     let msg: ModifyRequest = req.body_json().await?;
     let (eventid, hvalue) = new_eventid!();
     let m_obj = ModifyMessage::new(uat, msg, eventid);
@@ -158,7 +229,7 @@ pub async fn modify(mut req: tide::Request<AppState>) -> tide::Result {
 }
 
 pub async fn delete(mut req: tide::Request<AppState>) -> tide::Result {
-    let uat = req.get_current_uat();
+    let uat = req.get_current_trusted_uat();
     let msg: DeleteRequest = req.body_json().await?;
     let (eventid, hvalue) = new_eventid!();
     let m_obj = DeleteMessage::new(uat, msg, eventid);
@@ -167,7 +238,7 @@ pub async fn delete(mut req: tide::Request<AppState>) -> tide::Result {
 }
 
 pub async fn search(mut req: tide::Request<AppState>) -> tide::Result {
-    let uat = req.get_current_uat();
+    let uat = req.get_current_trusted_uat();
     let msg: SearchRequest = req.body_json().await?;
     let (eventid, hvalue) = new_eventid!();
     let m_obj = SearchMessage::new(uat, msg, eventid);
@@ -176,7 +247,7 @@ pub async fn search(mut req: tide::Request<AppState>) -> tide::Result {
 }
 
 pub async fn whoami(req: tide::Request<AppState>) -> tide::Result {
-    let uat = req.get_current_uat();
+    let uat = req.get_current_trusted_uat();
     let (eventid, hvalue) = new_eventid!();
     // New event, feed current auth data from the token to it.
     let m_obj = WhoamiMessage { uat, eventid };
@@ -192,7 +263,7 @@ pub async fn json_rest_event_get(
     filter: Filter<FilterInvalid>,
     attrs: Option<Vec<String>>,
 ) -> tide::Result {
-    let uat = req.get_current_uat();
+    let uat = req.get_current_trusted_uat();
 
     let (eventid, hvalue) = new_eventid!();
     let m_obj = InternalSearchMessage {
@@ -211,7 +282,7 @@ async fn json_rest_event_get_id(
     filter: Filter<FilterInvalid>,
     attrs: Option<Vec<String>>,
 ) -> tide::Result {
-    let uat = req.get_current_uat();
+    let uat = req.get_current_trusted_uat();
     let id = req.get_url_param("id")?;
 
     let filter = Filter::join_parts_and(filter, filter_all!(f_id(id.as_str())));
@@ -265,7 +336,7 @@ async fn json_rest_event_get_id_attr(
 ) -> tide::Result {
     let id = req.get_url_param("id")?;
     let attr = req.get_url_param("attr")?;
-    let uat = req.get_current_uat();
+    let uat = req.get_current_trusted_uat();
 
     let filter = Filter::join_parts_and(filter, filter_all!(f_id(id.as_str())));
     let (eventid, hvalue) = new_eventid!();
@@ -557,6 +628,67 @@ pub async fn account_put_id_credential_primary(req: tide::Request<AppState>) ->
     json_rest_event_credential_put(req, None).await
 }
 
+pub async fn account_post_id_credential_reset_intent(
+    mut req: tide::Request<AppState>,
+) -> tide::Result {
+    let uat = req.get_current_uat();
+    let id = req.get_url_param("id")?;
+    let obj: CredentialResetIntentRequest = req.body_json().await?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = IdmAccountCredentialResetIntentMessage {
+        uat,
+        uuid_or_name: id,
+        ttl: obj.ttl,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_idmaccountcredentialresetintent(m_obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
+// The reset endpoints do not require authentication, as the token is the credential.
+pub async fn credential_reset_begin(mut req: tide::Request<AppState>) -> tide::Result {
+    let obj: SingleStringRequest = req.body_json().await?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = IdmCredentialResetBeginMessage {
+        token: obj.value,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_idmcredentialresetbegin(m_obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
+pub async fn credential_reset_step(mut req: tide::Request<AppState>) -> tide::Result {
+    let sessionid = Uuid::parse_str(req.get_url_param("sessionid")?.as_str())
+        .map_err(|_| tide::Error::from_str(tide::StatusCode::BadRequest, "invalid sessionid"))?;
+    let obj: CredentialResetRequest = req.body_json().await?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = IdmCredentialResetStepMessage {
+        sessionid,
+        req: obj,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_idmcredentialresetstep(m_obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
 // Return a vec of str
 pub async fn account_get_id_ssh_pubkeys(req: tide::Request<AppState>) -> tide::Result {
     let uat = req.get_current_uat();
@@ -691,6 +823,122 @@ pub async fn account_get_id_radius_token(req: tide::Request<AppState>) -> tide::
     to_tide_response(res, hvalue)
 }
 
+pub async fn account_post_id_radius_auth(mut req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let id = req.get_url_param("id")?;
+    let obj: SingleStringRequest = req.body_json().await?;
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = IdmAccountRadiusAuthMessage {
+        uat,
+        uuid_or_name: id,
+        cred: obj.value,
+        eventid,
+    };
+    let res = req
+        .state()
+        .qe_r_ref
+        .handle_idmaccountradiusauth(m_obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
+pub async fn account_get_id_app_password(req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let id = req.get_url_param("id")?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let obj = IdmAccountAppPasswordListMessage {
+        uat,
+        uuid_or_name: id,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_r_ref
+        .handle_accountapppasswordlist(obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
+pub async fn account_post_id_app_password(mut req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let id = req.get_url_param("id")?;
+    let gen: AppPasswordGenerate = req.body_json().await?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let obj = IdmAccountAppPasswordGenerateMessage {
+        uat,
+        uuid_or_name: id,
+        gen,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_accountapppasswordgenerate(obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
+pub async fn account_delete_id_app_password(req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let id = req.get_url_param("id")?;
+    let label = req.get_url_param("label")?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let obj = IdmAccountAppPasswordRemoveMessage {
+        uat,
+        uuid_or_name: id,
+        label,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_accountapppasswordremove(obj)
+        .await
+        .map(|()| true);
+    to_tide_response(res, hvalue)
+}
+
+pub async fn account_get_id_lock(req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let id = req.get_url_param("id")?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let obj = IdmAccountSoftLockReadMessage {
+        uat,
+        uuid_or_name: id,
+        eventid,
+    };
+
+    let res = req.state().qe_r_ref.handle_accountsoftlockread(obj).await;
+    to_tide_response(res, hvalue)
+}
+
+pub async fn account_delete_id_lock(req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let id = req.get_url_param("id")?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let obj = IdmAccountSoftLockClearMessage {
+        uat,
+        uuid_or_name: id,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_accountsoftlockclear(obj)
+        .await
+        .map(|()| true);
+    to_tide_response(res, hvalue)
+}
+
 pub async fn account_post_id_person_extend(req: tide::Request<AppState>) -> tide::Result {
     let uat = req.get_current_uat();
     let id = req.get_url_param("id")?;
@@ -898,6 +1146,267 @@ pub async fn domain_id_put_attr(req: tide::Request<AppState>) -> tide::Result {
     json_rest_event_put_id_attr(req, filter).await
 }
 
+pub async fn oauth2_get(req: tide::Request<AppState>) -> tide::Result {
+    let filter = filter_all!(f_eq(
+        "class",
+        PartialValue::new_class("oauth2_resource_server")
+    ));
+    json_rest_event_get(req, filter, None).await
+}
+
+pub async fn oauth2_basic_post(req: tide::Request<AppState>) -> tide::Result {
+    let classes = vec![
+        "oauth2_resource_server".to_string(),
+        "oauth2_resource_server_basic".to_string(),
+        "object".to_string(),
+    ];
+    json_rest_event_post(req, classes).await
+}
+
+// Resource servers are identified by their oauth2_rs_name rather than name.
+fn oauth2_id(id: &str) -> Filter<FilterInvalid> {
+    filter_all!(f_and!([
+        f_eq("class", PartialValue::new_class("oauth2_resource_server")),
+        f_eq("oauth2_rs_name", PartialValue::new_iname(id))
+    ]))
+}
+
+pub async fn oauth2_id_get(req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let filter = oauth2_id(req.get_url_param("id")?.as_str());
+
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = InternalSearchMessage {
+        uat,
+        filter,
+        attrs: None,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_r_ref
+        .handle_internalsearch(m_obj)
+        .await
+        .map(|mut r| r.pop());
+    to_tide_response(res, hvalue)
+}
+
+pub async fn oauth2_id_delete(req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let filter = oauth2_id(req.get_url_param("id")?.as_str());
+
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = InternalDeleteMessage {
+        uat,
+        filter,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_internaldelete(m_obj)
+        .await
+        .map(|()| true);
+    to_tide_response(res, hvalue)
+}
+
+// == service account ==
+
+pub async fn service_account_get(req: tide::Request<AppState>) -> tide::Result {
+    let filter = filter_all!(f_eq("class", PartialValue::new_class("service_account")));
+    json_rest_event_get(req, filter, None).await
+}
+
+pub async fn service_account_post(req: tide::Request<AppState>) -> tide::Result {
+    let classes = vec![
+        "service_account".to_string(),
+        "account".to_string(),
+        "object".to_string(),
+    ];
+    json_rest_event_post(req, classes).await
+}
+
+pub async fn service_account_id_get(req: tide::Request<AppState>) -> tide::Result {
+    let filter = filter_all!(f_eq("class", PartialValue::new_class("service_account")));
+    json_rest_event_get_id(req, filter, None).await
+}
+
+pub async fn service_account_id_delete(req: tide::Request<AppState>) -> tide::Result {
+    let filter = filter_all!(f_eq("class", PartialValue::new_class("service_account")));
+    json_rest_event_delete_id(req, filter).await
+}
+
+pub async fn service_account_get_id_api_token(req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let id = req.get_url_param("id")?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let obj = IdmServiceAccountApiTokenListMessage {
+        uat,
+        uuid_or_name: id,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_r_ref
+        .handle_serviceaccountapitokenlist(obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
+pub async fn service_account_post_id_api_token(mut req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let id = req.get_url_param("id")?;
+    let gen: ApiTokenGenerate = req.body_json().await?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let obj = IdmServiceAccountApiTokenGenerateMessage {
+        uat,
+        uuid_or_name: id,
+        gen,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_serviceaccountapitokengenerate(obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
+pub async fn service_account_delete_id_api_token(req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let id = req.get_url_param("id")?;
+    let token_id = Uuid::parse_str(req.get_url_param("token_id")?.as_str())
+        .map_err(|_| tide::Error::from_str(tide::StatusCode::BadRequest, "invalid token_id"))?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let obj = IdmServiceAccountApiTokenDestroyMessage {
+        uat,
+        uuid_or_name: id,
+        token_id,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_serviceaccountapitokendestroy(obj)
+        .await
+        .map(|()| true);
+    to_tide_response(res, hvalue)
+}
+
+async fn oauth2_authorise(
+    req: tide::Request<AppState>,
+    auth_req: AuthorisationRequest,
+) -> tide::Result {
+    let uat = req.get_current_uat();
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = Oauth2AuthoriseMessage {
+        uat,
+        auth_req,
+        eventid,
+    };
+
+    let res = req.state().qe_w_ref.handle_oauth2_authorise(m_obj).await;
+    to_oauth2_response(res, hvalue)
+}
+
+pub async fn oauth2_authorise_get(req: tide::Request<AppState>) -> tide::Result {
+    let auth_req: AuthorisationRequest = req.query()?;
+    oauth2_authorise(req, auth_req).await
+}
+
+pub async fn oauth2_authorise_post(mut req: tide::Request<AppState>) -> tide::Result {
+    let auth_req: AuthorisationRequest = req.body_json().await?;
+    oauth2_authorise(req, auth_req).await
+}
+
+pub async fn oauth2_authorise_permit_post(mut req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let consent_req: ConsentRequest = req.body_json().await?;
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = Oauth2AuthorisePermitMessage {
+        uat,
+        consent_req,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_oauth2_authorise_permit(m_obj)
+        .await;
+    to_oauth2_response(res, hvalue)
+}
+
+pub async fn oauth2_token_post(mut req: tide::Request<AppState>) -> tide::Result {
+    let client_authz = req
+        .header(tide::http::headers::AUTHORIZATION)
+        .and_then(|hv| hv.get(0))
+        .map(|h| h.as_str().to_string());
+    let token_req: AccessTokenRequest = req.body_form().await?;
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = Oauth2TokenExchangeMessage {
+        client_authz,
+        token_req,
+        eventid,
+    };
+
+    let res = req
+        .state()
+        .qe_w_ref
+        .handle_oauth2_token_exchange(m_obj)
+        .await;
+    to_oauth2_response(res, hvalue)
+}
+
+pub async fn oauth2_openid_discovery_get(req: tide::Request<AppState>) -> tide::Result {
+    let client_id = req.get_url_param("client_id")?;
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = Oauth2OpenIdDiscoveryMessage { client_id, eventid };
+
+    let res = req
+        .state()
+        .qe_r_ref
+        .handle_oauth2_openid_discovery(m_obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
+pub async fn oauth2_openid_publickey_get(req: tide::Request<AppState>) -> tide::Result {
+    let client_id = req.get_url_param("client_id")?;
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = Oauth2OpenIdPublicKeyMessage { client_id, eventid };
+
+    let res = req
+        .state()
+        .qe_r_ref
+        .handle_oauth2_openid_publickey(m_obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
+pub async fn audit_auth_get(req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let query: AuthAuditQuery = req.query()?;
+
+    let (eventid, hvalue) = new_eventid!();
+    let obj = AuthAuditSearchMessage {
+        uat,
+        query,
+        eventid,
+    };
+
+    let res = req.state().qe_r_ref.handle_authauditsearch(obj).await;
+    to_tide_response(res, hvalue)
+}
+
 pub async fn recycle_bin_get(req: tide::Request<AppState>) -> tide::Result {
     let filter = filter_all!(f_pres("class"));
     let uat = req.get_current_uat();
@@ -984,7 +1493,17 @@ pub async fn auth(mut req: tide::Request<AppState>) -> tide::Result {
 
     let obj: AuthRequest = req.body_json().await?;
 
-    let auth_msg = AuthMessage::new(obj, maybe_sessionid, eventid);
+    // Failed authentications are rate limited by the address of the client. This is the
+    // peer of the connection, so a reverse proxy in front of the server is one source.
+    let source = req
+        .peer_addr()
+        .and_then(|addr| addr.parse::<std::net::SocketAddr>().ok())
+        .map(|addr| addr.ip());
+
+    // Only present when the listener verifies client certificates.
+    let client_cert = req.ext::<ClientCertificate>().cloned();
+
+    let auth_msg = AuthMessage::new(obj, maybe_sessionid, source, client_cert, eventid);
 
     // We probably need to know if we allocate the cookie, that this is a
     // new session, and in that case, anything *except* authrequest init is
@@ -1033,19 +1552,26 @@ pub async fn auth(mut req: tide::Request<AppState>) -> tide::Result {
                         .map(|_| ProtoAuthState::Continue(allowed))
                         .map_err(|_| OperationError::InvalidSessionState)
                 }
+                AuthState::PasswordExpired => {
+                    debug!("🧩 -> AuthState::PasswordExpired");
+                    let msession = req.session_mut();
+                    // The session continues so that the password can be changed.
+                    msession.remove("auth-session-id");
+                    msession
+                        .insert("auth-session-id", sessionid)
+                        .map(|_| ProtoAuthState::PasswordExpired)
+                        .map_err(|_| OperationError::InvalidSessionState)
+                }
                 AuthState::Success(uat) => {
                     debug!("🧩 -> AuthState::Success");
                     // Remove the auth-session-id
                     let msession = req.session_mut();
                     msession.remove("auth-session-id");
-                    // Create the string "Bearer <token>"
-                    let kref = &req.state().fernet_handle;
-                    serde_json::to_vec(&uat)
-                        .map(|data| {
-                            let tok = kref.encrypt(&data);
-                            ProtoAuthState::Success(tok)
-                        })
-                        .map_err(|_| OperationError::InvalidSessionState)
+                    // Sign the uat, the client uses this as "Bearer <token>"
+                    req.state()
+                        .jws_signer
+                        .sign(&uat)
+                        .map(ProtoAuthState::Success)
                 }
                 AuthState::Denied(_) => {
                     debug!("🧩 -> AuthState::Denied");
@@ -1076,6 +1602,48 @@ pub async fn idm_account_set_password(mut req: tide::Request<AppState>) -> tide:
     to_tide_response(res, hvalue)
 }
 
+pub async fn jwk_get(req: tide::Request<AppState>) -> tide::Result {
+    // The public key that user auth tokens are signed with, so that clients can
+    // verify them offline.
+    let (_, hvalue) = new_eventid!();
+    let jwk = req.state().jwk.clone();
+    to_tide_response(Ok(jwk), hvalue)
+}
+
+pub async fn ssh_ca_get(req: tide::Request<AppState>) -> tide::Result {
+    // Served as plain text, so that it can be written straight to the file sshd
+    // reads as TrustedUserCAKeys.
+    let (eventid, hvalue) = new_eventid!();
+    let obj = SshCaPublicKeyMessage { eventid };
+    match req.state().qe_r_ref.handle_sshcapublickey(obj).await {
+        Ok(key) => {
+            let mut res = tide::Response::new(200);
+            res.set_body(format!("{}\n", key));
+            res.set_content_type(tide::http::mime::PLAIN);
+            res.insert_header("X-KANIDM-OPID", hvalue);
+            Ok(res)
+        }
+        Err(e) => to_tide_response::<()>(Err(e), hvalue),
+    }
+}
+
+pub async fn idm_account_ssh_cert_post(mut req: tide::Request<AppState>) -> tide::Result {
+    let uat = req.get_current_uat();
+    let obj: SingleStringRequest = req.body_json().await?;
+    let (eventid, hvalue) = new_eventid!();
+    let m_obj = IdmAccountSshCertSignMessage {
+        uat,
+        publickey: obj.value,
+        eventid,
+    };
+    let res = req
+        .state()
+        .qe_r_ref
+        .handle_idmaccountsshcertsign(m_obj)
+        .await;
+    to_tide_response(res, hvalue)
+}
+
 // == Status
 
 pub async fn status(req: tide::Request<AppState>) -> tide::Result {
@@ -1092,125 +1660,157 @@ pub async fn status(req: tide::Request<AppState>) -> tide::Result {
     Ok(res)
 }
 
-/*
-// For openssl
-struct TlsListener {
-    address: String,
-    tls_params: &'static SslAcceptor,
-}
-
-impl TlsListener {
-    fn new(address: String, tls_params: &'static SslAcceptor) -> Self {
-        Self {
-            address,
-            tls_params,
+/// A TLS connection shared between the reader and writer halves that async-h1 needs.
+#[derive(Clone)]
+struct TlsConnection(Arc<Mutex<SslStream<TcpStream>>>);
+
+impl TlsConnection {
+    fn with_stream<T>(
+        &self,
+        f: impl FnOnce(Pin<&mut SslStream<TcpStream>>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        match self.0.lock() {
+            Ok(mut stream) => f(Pin::new(&mut *stream)),
+            Err(_) => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Other,
+                "tls connection poisoned",
+            ))),
         }
     }
 }
 
-impl std::fmt::Debug for TlsListener {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "TlsListener {{}}")
+impl futures::io::AsyncRead for TlsConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.with_stream(|stream| {
+            let mut read_buf = tokio::io::ReadBuf::new(buf);
+            tokio::io::AsyncRead::poll_read(stream, cx, &mut read_buf)
+                .map_ok(|_| read_buf.filled().len())
+        })
     }
 }
 
-impl std::fmt::Display for TlsListener {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "TlsListener {{}}")
+impl futures::io::AsyncWrite for TlsConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.with_stream(|stream| tokio::io::AsyncWrite::poll_write(stream, cx, buf))
     }
-}
 
-impl<State: Clone + Send + Sync + 'static> tide::listener::ToListener<State> for TlsListener {
-    type Listener = TlsListener;
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.with_stream(|stream| tokio::io::AsyncWrite::poll_flush(stream, cx))
+    }
 
-    fn to_listener(self) -> std::io::Result<Self::Listener> {
-        Ok(self)
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.with_stream(|stream| tokio::io::AsyncWrite::poll_shutdown(stream, cx))
     }
 }
 
-fn handle_client<State: Clone + Send + Sync + 'static>(
-    _app: tide::Server<State>,
-    _stream: tokio_openssl::SslStream<tokio::net::TcpStream>,
-    _local_addr: std::net::SocketAddr,
-    _peer_addr: std::net::SocketAddr,
+async fn client_cert_tls_connection(
+    app: tide::Server<AppState>,
+    mut tlsstream: SslStream<TcpStream>,
+    paddr: std::net::SocketAddr,
 ) {
-    tokio::spawn(async move {
-        let fut = async_h1::accept(stream, |mut req| async {
-            req.set_local_addr(Some(local_addr));
-            req.set_peer_addr(Some(peer_addr));
+    if let Err(e) = SslStream::accept(Pin::new(&mut tlsstream)).await {
+        error!("tls accept error, continuing -> {:?}", e);
+        return;
+    };
+    // The certificate was verified against the client ca during the handshake.
+    let client_cert = match tlsstream
+        .ssl()
+        .peer_certificate()
+        .map(|x509| ClientCertificate::from_x509(&x509))
+        .transpose()
+    {
+        Ok(cc) => cc,
+        Err(e) => {
+            error!("client certificate error, continuing -> {:?}", e);
+            return;
+        }
+    };
+    let local_addr = tlsstream.get_ref().local_addr().ok();
+
+    let conn = TlsConnection(Arc::new(Mutex::new(tlsstream)));
+    let res = async_h1::accept(conn, |mut req| {
+        let app = app.clone();
+        let client_cert = client_cert.clone();
+        async move {
+            req.set_local_addr(local_addr);
+            req.set_peer_addr(Some(paddr));
+            if let Some(cc) = client_cert {
+                req.ext_mut().insert(cc);
+            }
             app.respond(req).await
-        });
-
-        if let Err(error) = fut.await {
-            // Do nothing
-            // log::error!("async-h1 error", { error: error.to_string() });
         }
-    });
+    })
+    .await;
+    if let Err(e) = res {
+        debug!("https connection error -> {:?}", e);
+    }
 }
 
-#[async_trait::async_trait]
-impl<State: Clone + Send + Sync + 'static> tide::listener::Listener<State> for TlsListener {
-    async fn listen(&mut self, app: tide::Server<State>) -> io::Result<()> {
-        let addr = net::SocketAddr::from_str(&self.address).map_err(|e| {
-            eprintln!(
-                "Could not parse https server address {} -> {:?}",
-                self.address, e
-            );
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Could not parse https server address",
-            )
-        })?;
-
-        let mut listener = TcpListener::bind(&addr).await?;
-
-        let tls_params = self.tls_params;
-
-        loop {
-            match listener.accept().await {
-                Ok((tcpstream, paddr)) => {
-                    let iapp = app.clone();
-                    tokio::spawn(async move {
-                        let res = tokio_openssl::accept(tls_params, tcpstream).await;
-                        match res {
-                            Ok(tlsstream) => {
-                                handle_client(iapp, tlsstream, addr, paddr);
-                            }
-                            Err(e) => {
-                                error!("tcp handshake error, continuing -> {:?}", e);
-                            }
-                        };
-                    });
-                }
-                Err(e) => {
-                    error!("acceptor error, continuing -> {:?}", e);
-                }
+/// Serve https with openssl rather than rustls, as only openssl gives us the client
+/// certificate that was verified during the handshake.
+async fn client_cert_tls_acceptor(
+    listener: TcpListener,
+    tls_parms: SslAcceptor,
+    app: tide::Server<AppState>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((tcpstream, paddr)) => {
+                // From the parms we need to create an SslContext.
+                match Ssl::new(tls_parms.context())
+                    .and_then(|tls_obj| SslStream::new(tls_obj, tcpstream))
+                {
+                    Ok(tlsstream) => {
+                        tokio::spawn(client_cert_tls_connection(app.clone(), tlsstream, paddr));
+                    }
+                    Err(e) => {
+                        error!("tls setup error, continuing -> {:?}", e);
+                    }
+                };
+            }
+            Err(e) => {
+                error!("acceptor error, continuing -> {:?}", e);
             }
         }
     }
 }
-*/
 
 // TODO: Add request limits.
+#[allow(clippy::too_many_arguments)]
 pub fn create_https_server(
     address: String,
-    // opt_tls_params: Option<SslAcceptorBuilder>,
-    opt_tls_params: Option<&TlsConfiguration>,
+    opt_tls_params: Option<SslAcceptorBuilder>,
+    opt_tls_config: Option<&TlsConfiguration>,
     cookie_key: &[u8; 32],
+    jws_signer: JwsSigner,
     status_ref: &'static StatusActor,
     qe_w_ref: &'static QueryServerWriteV1,
     qe_r_ref: &'static QueryServerReadV1,
 ) -> Result<(), ()> {
-    // Create the in memory fernet key
-    let fernet_handle = fernet::Fernet::new(&fernet::Fernet::generate_key()).ok_or_else(|| {
-        error!("Failed to generate fernet key");
+    // Build the validator from our own public key, so that we check tokens the same
+    // way that a client would.
+    let jwk = jws_signer.public_key_as_jwk().map_err(|e| {
+        error!("Failed to get token signing public key -> {:?}", e);
+    })?;
+    let jws_validator = JwsValidator::from_jwk(&jwk).map_err(|e| {
+        error!("Failed to setup token validator -> {:?}", e);
     })?;
 
     let mut tserver = tide::Server::with_state(AppState {
         status_ref,
         qe_w_ref,
         qe_r_ref,
-        fernet_handle,
+        jws_signer,
+        jws_validator,
+        jwk,
     });
 
     // Add middleware?
@@ -1231,6 +1831,14 @@ pub fn create_https_server(
     raw_route.at("/search").post(search);
 
     tserver.at("/v1/auth").post(auth);
+    tserver.at("/v1/jwk").get(jwk_get);
+    tserver.at("/v1/ssh_ca").get(ssh_ca_get);
+
+    let mut credential_route = tserver.at("/v1/credential");
+    credential_route.at("/_reset").post(credential_reset_begin);
+    credential_route
+        .at("/_reset/:sessionid")
+        .post(credential_reset_step);
 
     let mut schema_route = tserver.at("/v1/schema");
     schema_route.at("/").get(schema_get);
@@ -1264,6 +1872,7 @@ pub fn create_https_server(
         .at("/_credential/primary/set_password")
         .post(idm_account_set_password);
     self_route.at("/_credential/:cid/_lock").get(do_nothing);
+    self_route.at("/_ssh_cert").post(idm_account_ssh_cert_post);
 
     self_route
         .at("/_radius")
@@ -1297,12 +1906,18 @@ pub fn create_https_server(
     account_route
         .at("/:id/_person/_extend")
         .post(account_post_id_person_extend);
-    account_route.at("/:id/_lock").get(do_nothing);
+    account_route
+        .at("/:id/_lock")
+        .get(account_get_id_lock)
+        .delete(account_delete_id_lock);
 
     account_route.at("/:id/_credential").get(do_nothing);
     account_route
         .at("/:id/_credential/primary")
         .put(account_put_id_credential_primary);
+    account_route
+        .at("/:id/_credential/_reset_intent")
+        .post(account_post_id_credential_reset_intent);
     account_route
         .at("/:id/_credential/:cid/_lock")
         .get(do_nothing);
@@ -1326,6 +1941,17 @@ pub fn create_https_server(
     account_route
         .at("/:id/_radius/_token")
         .get(account_get_id_radius_token);
+    account_route
+        .at("/:id/_radius/_auth")
+        .post(account_post_id_radius_auth);
+
+    account_route
+        .at("/:id/_app_password")
+        .get(account_get_id_app_password)
+        .post(account_post_id_app_password);
+    account_route
+        .at("/:id/_app_password/:label")
+        .delete(account_delete_id_app_password);
 
     account_route.at("/:id/_unix").post(account_post_id_unix);
     account_route
@@ -1364,6 +1990,49 @@ pub fn create_https_server(
         .get(domain_id_get_attr)
         .put(domain_id_put_attr);
 
+    tserver.at("/v1/audit/auth").get(audit_auth_get);
+
+    let mut oauth2_route = tserver.at("/v1/oauth2");
+    oauth2_route.at("/").get(oauth2_get);
+    oauth2_route.at("/_basic").post(oauth2_basic_post);
+    oauth2_route
+        .at("/:id")
+        .get(oauth2_id_get)
+        .delete(oauth2_id_delete);
+
+    let mut service_account_route = tserver.at("/v1/service_account");
+    service_account_route
+        .at("/")
+        .get(service_account_get)
+        .post(service_account_post);
+    service_account_route
+        .at("/:id")
+        .get(service_account_id_get)
+        .delete(service_account_id_delete);
+    service_account_route
+        .at("/:id/_api_token")
+        .get(service_account_get_id_api_token)
+        .post(service_account_post_id_api_token);
+    service_account_route
+        .at("/:id/_api_token/:token_id")
+        .delete(service_account_delete_id_api_token);
+
+    let mut oauth2_process_route = tserver.at("/oauth2");
+    oauth2_process_route
+        .at("/authorise")
+        .get(oauth2_authorise_get)
+        .post(oauth2_authorise_post);
+    oauth2_process_route
+        .at("/authorise/permit")
+        .post(oauth2_authorise_permit_post);
+    oauth2_process_route.at("/token").post(oauth2_token_post);
+    oauth2_process_route
+        .at("/openid/:client_id/.well-known/openid-configuration")
+        .get(oauth2_openid_discovery_get);
+    oauth2_process_route
+        .at("/openid/:client_id/public_key.jwk")
+        .get(oauth2_openid_publickey_get);
+
     let mut recycle_route = tserver.at("/v1/recycle_bin");
     recycle_route.at("/").get(recycle_bin_get);
     recycle_route.at("/:id").get(recycle_bin_id_get);
@@ -1377,8 +2046,17 @@ pub fn create_https_server(
     accessprof_route.at("/:id/_attr/:attr").get(do_nothing);
 
     // Create listener?
-    match opt_tls_params {
-        Some(tls_param) => {
+    match (opt_tls_params, opt_tls_config) {
+        (Some(tls_params), Some(tls_param)) if tls_param.client_ca.is_some() => {
+            let tls_parms = tls_params.build();
+            tokio::spawn(async move {
+                match TcpListener::bind(&address).await {
+                    Ok(listener) => client_cert_tls_acceptor(listener, tls_parms, tserver).await,
+                    Err(e) => error!("Failed to start server listener -> {:?}", e),
+                }
+            });
+        }
+        (_, Some(tls_param)) => {
             let tlsl = TlsListener::build()
                 .addrs(address)
                 .cert(&tls_param.chain)
@@ -1387,11 +2065,6 @@ pub fn create_https_server(
                 .map_err(|e| {
                     error!("Failed to build TLS Listener -> {:?}", e);
                 })?;
-            /*
-            let x = Box::new(tls_param.build());
-            let x_ref = Box::leak(x);
-            let tlsl = TlsListener::new(address, x_ref);
-            */
 
             tokio::spawn(async move {
                 if let Err(e) = tserver.listen(tlsl).await {
@@ -1399,7 +2072,7 @@ pub fn create_https_server(
                 }
             });
         }
-        None => {
+        (_, None) => {
             // Create without https
             tokio::spawn(async move {
                 if let Err(e) = tserver.listen(address).await {
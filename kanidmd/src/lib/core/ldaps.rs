@@ -1,4 +1,5 @@
-use crate::actors::v1_read::{LdapRequestMessage, QueryServerReadV1};
+use crate::actors::v1_read::{LdapCertBindMessage, LdapRequestMessage, QueryServerReadV1};
+use crate::crypto::ClientCertificate;
 use crate::ldap::{LdapBoundToken, LdapResponseState};
 use core::pin::Pin;
 use openssl::ssl::{Ssl, SslAcceptor, SslAcceptorBuilder};
@@ -6,19 +7,21 @@ use tokio_openssl::SslStream;
 
 use futures_util::sink::SinkExt;
 use futures_util::stream::StreamExt;
-// use ldap3_server::simple::*;
-// use ldap3_server::proto::LdapMsg;
+use ldap3_server::proto::{LdapExtendedResponse, LdapOp, LdapResult};
+use ldap3_server::simple::{LdapMsg, LdapResultCode};
 use ldap3_server::LdapCodec;
 // use std::convert::TryFrom;
 use std::marker::Unpin;
 use std::net;
 use std::str::FromStr;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
 // use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio_util::codec::{FramedRead, FramedWrite};
+use tokio_util::codec::Framed;
 use uuid::Uuid;
 
+const STARTTLS_OID: &str = "1.3.6.1.4.1.1466.20037";
+
 struct LdapSession {
     uat: Option<LdapBoundToken>,
 }
@@ -32,18 +35,89 @@ impl LdapSession {
     }
 }
 
-async fn client_process<W: AsyncWrite + Unpin, R: AsyncRead + Unpin>(
-    mut r: FramedRead<R, LdapCodec>,
-    mut w: FramedWrite<W, LdapCodec>,
-    _paddr: net::SocketAddr,
+// How the transport of a connection may change during the session.
+#[derive(Clone, Copy)]
+enum LdapTransport {
+    // LDAPS, or plaintext without any TLS configured.
+    Fixed,
+    // Plaintext that can be upgraded with StartTLS.
+    StartTls { bind_requires_tls: bool },
+}
+
+fn starttls_response(msgid: i32, code: LdapResultCode, message: &str) -> LdapMsg {
+    LdapMsg {
+        msgid,
+        op: LdapOp::ExtendedResponse(LdapExtendedResponse {
+            res: LdapResult {
+                code,
+                matcheddn: "".to_string(),
+                message: message.to_string(),
+                referral: Vec::new(),
+            },
+            name: Some(STARTTLS_OID.to_string()),
+            value: None,
+        }),
+        ctrl: Vec::new(),
+    }
+}
+
+// Process the requests of a connection. If the client requests StartTLS on a connection that
+// can be upgraded, the connection is returned so that TLS can be established on it.
+async fn client_process<S: AsyncRead + AsyncWrite + Unpin>(
+    mut framed: Framed<S, LdapCodec>,
+    paddr: net::SocketAddr,
+    client_cert: Option<ClientCertificate>,
+    transport: LdapTransport,
     qe_r_ref: &'static QueryServerReadV1,
-) {
+) -> Option<Framed<S, LdapCodec>> {
     // This is a connected client session. we need to associate some state to the
     // session
     let mut session = LdapSession::new();
+    // A verified client certificate binds the connection once, before any request. An
+    // explicit bind can still replace it.
+    if let Some(client_cert) = client_cert {
+        session.uat = qe_r_ref
+            .handle_ldapcertbind(LdapCertBindMessage {
+                eventid: Uuid::new_v4(),
+                source: Some(paddr.ip()),
+                client_cert,
+            })
+            .await;
+    }
     // Now that we have the session we begin an event loop to process input OR
     // we return.
-    while let Some(Ok(protomsg)) = r.next().await {
+    while let Some(Ok(protomsg)) = framed.next().await {
+        if let LdapOp::ExtendedRequest(ler) = &protomsg.op {
+            if ler.name == STARTTLS_OID {
+                match transport {
+                    LdapTransport::StartTls { .. } => {
+                        let rmsg = starttls_response(protomsg.msgid, LdapResultCode::Success, "");
+                        if framed.send(rmsg).await.is_err() {
+                            break;
+                        }
+                        // The client must wait for our response before it begins the
+                        // handshake, so nothing may follow the request in plaintext.
+                        if !framed.read_buffer().is_empty() {
+                            error!("data followed a starttls request, disconnecting");
+                            break;
+                        }
+                        return Some(framed);
+                    }
+                    LdapTransport::Fixed => {
+                        let rmsg = starttls_response(
+                            protomsg.msgid,
+                            LdapResultCode::OperationsError,
+                            "StartTLS is not available on this connection",
+                        );
+                        if framed.send(rmsg).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                }
+            }
+        }
+
         // Start the event
         let eventid = Uuid::new_v4();
         let uat = session.uat.clone();
@@ -52,31 +126,38 @@ async fn client_process<W: AsyncWrite + Unpin, R: AsyncRead + Unpin>(
                 eventid,
                 protomsg,
                 uat,
+                source: Some(paddr.ip()),
+                bind_requires_tls: matches!(
+                    transport,
+                    LdapTransport::StartTls {
+                        bind_requires_tls: true
+                    }
+                ),
             })
             .await;
 
         match qs_result {
-            Some(LdapResponseState::Unbind) => return,
+            Some(LdapResponseState::Unbind) => return None,
             Some(LdapResponseState::Disconnect(rmsg)) => {
-                if w.send(rmsg).await.is_err() {
+                if framed.send(rmsg).await.is_err() {
                     break;
                 }
                 break;
             }
             Some(LdapResponseState::Bind(uat, rmsg)) => {
                 session.uat = Some(uat);
-                if w.send(rmsg).await.is_err() {
+                if framed.send(rmsg).await.is_err() {
                     break;
                 }
             }
             Some(LdapResponseState::Respond(rmsg)) => {
-                if w.send(rmsg).await.is_err() {
+                if framed.send(rmsg).await.is_err() {
                     break;
                 }
             }
             Some(LdapResponseState::MultiPartResponse(v)) => {
                 for rmsg in v.into_iter() {
-                    if w.send(rmsg).await.is_err() {
+                    if framed.send(rmsg).await.is_err() {
                         break;
                     }
                 }
@@ -84,7 +165,7 @@ async fn client_process<W: AsyncWrite + Unpin, R: AsyncRead + Unpin>(
             Some(LdapResponseState::BindMultiPartResponse(uat, v)) => {
                 session.uat = Some(uat);
                 for rmsg in v.into_iter() {
-                    if w.send(rmsg).await.is_err() {
+                    if framed.send(rmsg).await.is_err() {
                         break;
                     }
                 }
@@ -95,6 +176,42 @@ async fn client_process<W: AsyncWrite + Unpin, R: AsyncRead + Unpin>(
             }
         };
     }
+    None
+}
+
+// Establish TLS on a connection, returning the stream and the verified client certificate.
+async fn tls_accept(
+    tcpstream: TcpStream,
+    tls_parms: &SslAcceptor,
+) -> Option<(SslStream<TcpStream>, Option<ClientCertificate>)> {
+    // From the parms we need to create an SslContext.
+    let mut tlsstream = match Ssl::new(tls_parms.context())
+        .and_then(|tls_obj| SslStream::new(tls_obj, tcpstream))
+    {
+        Ok(ta) => ta,
+        Err(e) => {
+            error!("tls setup error, continuing -> {:?}", e);
+            return None;
+        }
+    };
+    if let Err(e) = SslStream::accept(Pin::new(&mut tlsstream)).await {
+        error!("tls accept error, continuing -> {:?}", e);
+        return None;
+    };
+    // The certificate was verified during the handshake when a client ca
+    // is configured.
+    match tlsstream
+        .ssl()
+        .peer_certificate()
+        .map(|x509| ClientCertificate::from_x509(&x509))
+        .transpose()
+    {
+        Ok(cc) => Some((tlsstream, cc)),
+        Err(e) => {
+            error!("client certificate error, continuing -> {:?}", e);
+            None
+        }
+    }
 }
 
 async fn tls_acceptor(
@@ -105,24 +222,63 @@ async fn tls_acceptor(
     loop {
         match listener.accept().await {
             Ok((tcpstream, paddr)) => {
-                // From the parms we need to create an SslContext.
-                let mut tlsstream = match Ssl::new(tls_parms.context())
-                    .and_then(|tls_obj| SslStream::new(tls_obj, tcpstream))
-                {
-                    Ok(ta) => ta,
-                    Err(e) => {
-                        error!("tls setup error, continuing -> {:?}", e);
-                        continue;
-                    }
+                let (tlsstream, client_cert) = match tls_accept(tcpstream, &tls_parms).await {
+                    Some(r) => r,
+                    None => continue,
                 };
-                if let Err(e) = SslStream::accept(Pin::new(&mut tlsstream)).await {
-                    error!("tls accept error, continuing -> {:?}", e);
-                    continue;
-                };
-                let (r, w) = tokio::io::split(tlsstream);
-                let r = FramedRead::new(r, LdapCodec);
-                let w = FramedWrite::new(w, LdapCodec);
-                tokio::spawn(client_process(r, w, paddr, qe_r_ref));
+                let framed = Framed::new(tlsstream, LdapCodec);
+                tokio::spawn(client_process(
+                    framed,
+                    paddr,
+                    client_cert,
+                    LdapTransport::Fixed,
+                    qe_r_ref,
+                ));
+            }
+            Err(e) => {
+                error!("acceptor error, continuing -> {:?}", e);
+            }
+        }
+    }
+}
+
+async fn starttls_client(
+    tcpstream: TcpStream,
+    paddr: net::SocketAddr,
+    tls_parms: SslAcceptor,
+    bind_requires_tls: bool,
+    qe_r_ref: &'static QueryServerReadV1,
+) {
+    let framed = Framed::new(tcpstream, LdapCodec);
+    let transport = LdapTransport::StartTls { bind_requires_tls };
+    let tcpstream = match client_process(framed, paddr, None, transport, qe_r_ref).await {
+        Some(framed) => framed.into_inner(),
+        None => return,
+    };
+    // The session begins again once TLS is established, so any bind made in plaintext is
+    // discarded.
+    if let Some((tlsstream, client_cert)) = tls_accept(tcpstream, &tls_parms).await {
+        let framed = Framed::new(tlsstream, LdapCodec);
+        client_process(framed, paddr, client_cert, LdapTransport::Fixed, qe_r_ref).await;
+    }
+}
+
+async fn starttls_acceptor(
+    listener: TcpListener,
+    tls_parms: SslAcceptor,
+    bind_requires_tls: bool,
+    qe_r_ref: &'static QueryServerReadV1,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((tcpstream, paddr)) => {
+                tokio::spawn(starttls_client(
+                    tcpstream,
+                    paddr,
+                    tls_parms.clone(),
+                    bind_requires_tls,
+                    qe_r_ref,
+                ));
             }
             Err(e) => {
                 error!("acceptor error, continuing -> {:?}", e);
@@ -135,11 +291,15 @@ async fn acceptor(listener: TcpListener, qe_r_ref: &'static QueryServerReadV1) {
     loop {
         match listener.accept().await {
             Ok((tcpstream, paddr)) => {
-                let (r, w) = tokio::io::split(tcpstream);
-                let r = FramedRead::new(r, LdapCodec);
-                let w = FramedWrite::new(w, LdapCodec);
+                let framed = Framed::new(tcpstream, LdapCodec);
                 // Let it rip.
-                tokio::spawn(client_process(r, w, paddr, qe_r_ref));
+                tokio::spawn(client_process(
+                    framed,
+                    paddr,
+                    None,
+                    LdapTransport::Fixed,
+                    qe_r_ref,
+                ));
             }
             Err(e) => {
                 error!("acceptor error, continuing -> {:?}", e);
@@ -179,3 +339,36 @@ pub(crate) async fn create_ldap_server(
     info!("Created LDAP interface");
     Ok(())
 }
+
+pub(crate) async fn create_ldap_starttls_server(
+    address: &str,
+    tls_params: SslAcceptorBuilder,
+    bind_requires_tls: bool,
+    qe_r_ref: &'static QueryServerReadV1,
+) -> Result<(), ()> {
+    let addr = net::SocketAddr::from_str(address).map_err(|e| {
+        eprintln!(
+            "Could not parse ldap starttls server address {} -> {:?}",
+            address, e
+        );
+    })?;
+
+    let listener = TcpListener::bind(&addr).await.map_err(|e| {
+        eprintln!(
+            "Could not bind to ldap starttls server address {} -> {:?}",
+            address, e
+        );
+    })?;
+
+    info!("Starting LDAP StartTLS interface ldap://{} ...", address);
+    let tls_parms = tls_params.build();
+    tokio::spawn(starttls_acceptor(
+        listener,
+        tls_parms,
+        bind_requires_tls,
+        qe_r_ref,
+    ));
+
+    info!("Created LDAP StartTLS interface");
+    Ok(())
+}
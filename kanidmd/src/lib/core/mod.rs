@@ -4,6 +4,7 @@ use libc::umask;
 
 // use crossbeam::channel::unbounded;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::unbounded_channel as unbounded;
 
 use crate::config::Configuration;
@@ -15,12 +16,14 @@ use crate::actors::v1_write::QueryServerWriteV1;
 use crate::async_log;
 use crate::audit::AuditScope;
 use crate::be::{Backend, BackendTransaction, FsType};
-use crate::crypto::setup_tls;
+use crate::constants::PW_HASH_TIME_TARGET_MS;
+use crate::credential::policy::CryptoPolicy;
+use crate::crypto::{setup_tls, JwsSigner};
 use crate::idm::server::{IdmServer, IdmServerDelayed};
 use crate::interval::IntervalActor;
 use crate::ldap::LdapServer;
 use crate::schema::Schema;
-use crate::server::QueryServer;
+use crate::server::{QueryServer, QueryServerTransaction};
 use crate::status::StatusActor;
 use crate::utils::duration_from_epoch_now;
 
@@ -95,7 +98,13 @@ fn setup_qs_idms(
 
     // We generate a SINGLE idms only!
 
-    let (idms, idms_delayed) = IdmServer::new(audit, query_server.clone(), config.origin.clone())?;
+    let (idms, idms_delayed) = IdmServer::new(
+        audit,
+        query_server.clone(),
+        config.origin.clone(),
+        config.breach_corpus_path.as_deref(),
+        CryptoPolicy::time_target(Duration::from_millis(PW_HASH_TIME_TARGET_MS)),
+    )?;
 
     Ok((query_server, idms, idms_delayed))
 }
@@ -464,7 +473,7 @@ pub async fn create_server_core(config: Configuration) -> Result<(), ()> {
     let status_ref = StatusActor::start(log_tx.clone(), config.log_level);
 
     // Setup TLS (if any)
-    let _opt_tls_params = match setup_tls(&config) {
+    let opt_tls_params = match setup_tls(&config) {
         Ok(opt_tls_params) => opt_tls_params,
         Err(e) => {
             error!("Failed to configure TLS parameters -> {:?}", e);
@@ -528,6 +537,20 @@ pub async fn create_server_core(config: Configuration) -> Result<(), ()> {
         None => {}
     }
 
+    // Load the domain signing key, used to sign the user auth tokens we issue.
+    let jws_signer = match qs
+        .read()
+        .get_domain_es256_private_key(&mut audit)
+        .and_then(|der| JwsSigner::from_es256_der(&der))
+    {
+        Ok(s) => s,
+        Err(e) => {
+            audit.write_log();
+            error!("Unable to load domain token signing key -> {:?}", e);
+            return Err(());
+        }
+    };
+
     let ldap = match LdapServer::new(&mut audit, &idms) {
         Ok(l) => l,
         Err(e) => {
@@ -587,6 +610,28 @@ pub async fn create_server_core(config: Configuration) -> Result<(), ()> {
         }
     }
 
+    // A plaintext LDAP listener for clients that upgrade with StartTLS.
+    if let Some(la) = &config.ldapstarttlsaddress {
+        let ldap_tls_params = match setup_tls(&config) {
+            Ok(Some(t)) => t,
+            Ok(None) => {
+                error!("LDAP StartTLS requires tls_chain and tls_key to be configured");
+                return Err(());
+            }
+            Err(e) => {
+                error!("Failed to configure LDAP TLS parameters -> {:?}", e);
+                return Err(());
+            }
+        };
+        ldaps::create_ldap_starttls_server(
+            la.as_str(),
+            ldap_tls_params,
+            config.ldap_bind_requires_tls,
+            server_read_ref,
+        )
+        .await?;
+    }
+
     // TODO: Remove these when we go to auth bearer!
     // Copy the max size
     let _secure_cookies = config.secure_cookies;
@@ -595,9 +640,10 @@ pub async fn create_server_core(config: Configuration) -> Result<(), ()> {
 
     self::https::create_https_server(
         config.address,
-        // opt_tls_params,
+        opt_tls_params,
         config.tls_config.as_ref(),
         &cookie_key,
+        jws_signer,
         status_ref,
         server_write_ref,
         server_read_ref,
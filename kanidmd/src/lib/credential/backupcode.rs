@@ -0,0 +1,115 @@
+use crate::be::dbvalue::DbBackupCodeV1;
+use crate::utils::DistinctAlpha;
+use openssl::sha::Sha256;
+use rand::{thread_rng, Rng};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+// How many codes are issued at a time.
+pub const BACKUP_CODE_COUNT: usize = 8;
+// Three groups of four from a 55 character set is ~69 bits per code, so a fast hash
+// without a salt is enough to protect them at rest.
+const BACKUP_CODE_GROUPS: usize = 3;
+const BACKUP_CODE_GROUP_LEN: usize = 4;
+
+/// A set of single use codes that can stand in for the TOTP or webauthn factor of a
+/// credential when the device is lost. Only the hash of each unused code is kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupCodes {
+    code_set: BTreeSet<Vec<u8>>,
+}
+
+impl TryFrom<DbBackupCodeV1> for BackupCodes {
+    type Error = ();
+
+    fn try_from(value: DbBackupCodeV1) -> Result<Self, Self::Error> {
+        Ok(BackupCodes {
+            code_set: value.h.into_iter().collect(),
+        })
+    }
+}
+
+fn hash_code(code: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(code.trim().as_bytes());
+    hasher.finish().to_vec()
+}
+
+impl BackupCodes {
+    /// Generate a new set of codes, returning the cleartext codes to be shown to the
+    /// account holder once.
+    pub fn generate() -> (Self, Vec<String>) {
+        let mut trng = thread_rng();
+        let codes: Vec<String> = (0..BACKUP_CODE_COUNT)
+            .map(|_| {
+                (0..BACKUP_CODE_GROUPS)
+                    .map(|_| {
+                        (&mut trng)
+                            .sample_iter(&DistinctAlpha)
+                            .take(BACKUP_CODE_GROUP_LEN)
+                            .collect::<String>()
+                    })
+                    .collect::<Vec<_>>()
+                    .join("-")
+            })
+            .collect();
+        let code_set = codes.iter().map(|c| hash_code(c.as_str())).collect();
+        (BackupCodes { code_set }, codes)
+    }
+
+    pub fn verify(&self, code: &str) -> bool {
+        self.code_set.contains(&hash_code(code))
+    }
+
+    /// Remove a used code. Returns None if the code is not part of this set, such as
+    /// when it was already used.
+    pub fn remove(&self, code: &str) -> Option<Self> {
+        let mut code_set = self.code_set.clone();
+        if code_set.remove(&hash_code(code)) {
+            Some(BackupCodes { code_set })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code_set.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.code_set.len()
+    }
+
+    pub fn to_dbbackupcodev1(&self) -> DbBackupCodeV1 {
+        DbBackupCodeV1 {
+            h: self.code_set.iter().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::credential::backupcode::{BackupCodes, BACKUP_CODE_COUNT};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_credential_backupcode_single_use() {
+        let (codes, cleartext) = BackupCodes::generate();
+        assert!(codes.len() == BACKUP_CODE_COUNT);
+        assert!(cleartext.len() == BACKUP_CODE_COUNT);
+        assert!(cleartext.iter().all(|c| codes.verify(c.as_str())));
+        assert!(!codes.verify("aaaa-bbbb-cccc"));
+
+        let code = cleartext[0].as_str();
+        let codes = codes.remove(code).expect("code not present");
+        assert!(codes.len() == BACKUP_CODE_COUNT - 1);
+        assert!(!codes.verify(code));
+        assert!(codes.remove(code).is_none());
+        assert!(codes.verify(cleartext[1].as_str()));
+
+        // Survives a round trip to the db.
+        let codes = BackupCodes::try_from(codes.to_dbbackupcodev1()).unwrap();
+        assert!(!codes.verify(code));
+        assert!(codes.verify(cleartext[1].as_str()));
+    }
+}
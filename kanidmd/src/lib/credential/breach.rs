@@ -0,0 +1,161 @@
+//! A corpus of the SHA1 hashes of breached passwords, such as the dumps published by
+//! Have I Been Pwned. These are far too many to store as `badlist_password` values, so
+//! they are kept in a file that `kanidm_badlist_preprocess` generates.
+//!
+//! The file starts with a magic value, followed by an index of where the hashes starting
+//! with each two byte prefix begin, and then all the hashes in sorted order. A lookup only
+//! reads the bounds of its prefix and then searches within them, so the file is never
+//! loaded into memory. It's opened for each lookup, so it can be updated by replacing it
+//! while the server is running.
+
+use crate::audit::AuditScope;
+use kanidm_proto::v1::OperationError;
+use openssl::sha::sha1;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+// This must match kanidm_badlist_preprocess.
+pub const BREACH_CORPUS_MAGIC: &[u8; 8] = b"KANIBC01";
+const PREFIX_BUCKETS: u64 = 65536;
+// The magic, and the start of each bucket plus the end of the last one.
+const HEADER_LEN: u64 = 8 + (PREFIX_BUCKETS + 1) * 8;
+const HASH_LEN: u64 = 20;
+
+#[derive(Debug)]
+pub struct BreachCorpus {
+    path: PathBuf,
+}
+
+impl BreachCorpus {
+    /// Check that the corpus is readable, so that a bad path is found when the server
+    /// starts rather than when a password is changed.
+    pub fn open(au: &mut AuditScope, path: &Path) -> Result<Self, OperationError> {
+        let corpus = BreachCorpus {
+            path: path.to_path_buf(),
+        };
+        let count = corpus
+            .open_file(au)
+            .and_then(|f| corpus.read_u64(au, &f, 8 + PREFIX_BUCKETS * 8))?;
+        ladmin_info!(au, "Breach corpus {:?} contains {} hashes", path, count);
+        Ok(corpus)
+    }
+
+    fn open_file(&self, au: &mut AuditScope) -> Result<File, OperationError> {
+        let f = File::open(&self.path).map_err(|e| {
+            ladmin_error!(
+                au,
+                "Unable to open breach corpus {:?} -> {:?}",
+                self.path,
+                e
+            );
+            OperationError::FsError
+        })?;
+
+        let mut magic = [0; 8];
+        f.read_exact_at(&mut magic, 0).map_err(|e| {
+            ladmin_error!(
+                au,
+                "Unable to read breach corpus {:?} -> {:?}",
+                self.path,
+                e
+            );
+            OperationError::FsError
+        })?;
+        if magic != *BREACH_CORPUS_MAGIC {
+            ladmin_error!(au, "{:?} is not a breach corpus", self.path);
+            return Err(OperationError::InvalidState);
+        }
+
+        Ok(f)
+    }
+
+    fn read_u64(&self, au: &mut AuditScope, f: &File, offset: u64) -> Result<u64, OperationError> {
+        let mut buf = [0; 8];
+        f.read_exact_at(&mut buf, offset)
+            .map(|_| u64::from_le_bytes(buf))
+            .map_err(|e| {
+                ladmin_error!(
+                    au,
+                    "Unable to read breach corpus {:?} -> {:?}",
+                    self.path,
+                    e
+                );
+                OperationError::FsError
+            })
+    }
+
+    /// Is this password in the corpus?
+    pub fn contains(&self, au: &mut AuditScope, cleartext: &str) -> Result<bool, OperationError> {
+        let hash = sha1(cleartext.as_bytes());
+        let prefix = u64::from(u16::from_be_bytes([hash[0], hash[1]]));
+
+        let f = self.open_file(au)?;
+        let mut lo = self.read_u64(au, &f, 8 + prefix * 8)?;
+        let mut hi = self.read_u64(au, &f, 8 + (prefix + 1) * 8)?;
+
+        let mut candidate = [0; HASH_LEN as usize];
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            f.read_exact_at(&mut candidate, HEADER_LEN + mid * HASH_LEN)
+                .map_err(|e| {
+                    ladmin_error!(
+                        au,
+                        "Unable to read breach corpus {:?} -> {:?}",
+                        self.path,
+                        e
+                    );
+                    OperationError::FsError
+                })?;
+            match candidate[..].cmp(&hash[..]) {
+                std::cmp::Ordering::Equal => return Ok(true),
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BreachCorpus, BREACH_CORPUS_MAGIC, PREFIX_BUCKETS};
+    use crate::audit::AuditScope;
+    use openssl::sha::sha1;
+    use std::io::Write;
+
+    fn write_corpus(path: &std::path::Path, passwords: &[&str]) {
+        let mut hashes: Vec<[u8; 20]> = passwords.iter().map(|p| sha1(p.as_bytes())).collect();
+        hashes.sort_unstable();
+        let mut f = std::fs::File::create(path).unwrap();
+        f.write_all(BREACH_CORPUS_MAGIC).unwrap();
+        (0..=PREFIX_BUCKETS).for_each(|prefix| {
+            let start = hashes
+                .iter()
+                .filter(|h| u64::from(u16::from_be_bytes([h[0], h[1]])) < prefix)
+                .count() as u64;
+            f.write_all(&start.to_le_bytes()).unwrap();
+        });
+        hashes.iter().for_each(|h| f.write_all(h).unwrap());
+    }
+
+    #[test]
+    fn test_breach_corpus_contains() {
+        let mut au = AuditScope::new("test_breach_corpus_contains", uuid::Uuid::new_v4(), None);
+        let path = std::env::temp_dir().join(format!("kanidm_breach_{}", uuid::Uuid::new_v4()));
+        write_corpus(
+            &path,
+            &["password", "correct horse battery staple", "hunter2"],
+        );
+
+        let corpus = BreachCorpus::open(&mut au, &path).expect("Failed to open corpus");
+        assert!(corpus.contains(&mut au, "hunter2") == Ok(true));
+        assert!(corpus.contains(&mut au, "correct horse battery staple") == Ok(true));
+        assert!(corpus.contains(&mut au, "eeNg5ahkie6Ahng8yoo9") == Ok(false));
+
+        // Anything else is rejected.
+        std::fs::write(&path, b"password\n").unwrap();
+        assert!(corpus.contains(&mut au, "hunter2").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,9 +1,10 @@
 use crate::be::dbvalue::{DbCredTypeV1, DbCredV1, DbPasswordV1, DbWebauthnV1};
+use argon2::{ThreadMode, Variant, Version};
 use hashbrown::HashMap as Map;
 use kanidm_proto::v1::OperationError;
 use openssl::hash::MessageDigest;
 use openssl::pkcs5::pbkdf2_hmac;
-use openssl::sha::Sha512;
+use openssl::sha::{Sha1, Sha256, Sha512};
 use rand::prelude::*;
 use std::convert::TryFrom;
 use std::time::{Duration, Instant};
@@ -11,25 +12,40 @@ use uuid::Uuid;
 use webauthn_rs::proto::Credential as WebauthnCredential;
 use webauthn_rs::proto::{Counter, CredentialID};
 
+pub mod backupcode;
+pub mod breach;
 pub mod policy;
 pub mod softlock;
 pub mod totp;
 pub mod webauthn;
 
-use crate::credential::policy::CryptoPolicy;
+use crate::credential::backupcode::BackupCodes;
+use crate::credential::policy::{CredentialStrength, CryptoPolicy};
 use crate::credential::softlock::CredSoftLockPolicy;
 use crate::credential::totp::TOTP;
 
 // NIST 800-63.b salt should be 112 bits -> 14  8u8.
 // I choose tinfoil hat though ...
+#[cfg(test)]
 const PBKDF2_SALT_LEN: usize = 24;
 // 64 * u8 -> 512 bits of out.
+#[cfg(test)]
 const PBKDF2_KEY_LEN: usize = 64;
 const PBKDF2_IMPORT_MIN_LEN: usize = 32;
 
+// 128 bits of salt, and 256 bits of output as recommended by RFC 9106.
+const ARGON2_SALT_LEN: usize = 16;
+const ARGON2_KEY_LEN: u32 = 32;
+
 const DS_SSHA512_SALT_LEN: usize = 8;
 const DS_SSHA512_HASH_LEN: usize = 64;
 
+// OpenLDAP salted sha hashes allow any salt length.
+const SSHA_HASH_LEN: usize = 20;
+const SSHA256_HASH_LEN: usize = 32;
+// $2y$ + 2 digit cost + $ + 22 char salt + 31 char hash
+const BCRYPT_LEN: usize = 60;
+
 // These are in order of "relative" strength.
 /*
 #[derive(Clone, Debug)]
@@ -41,15 +57,29 @@ pub enum Policy {
 }
 */
 
-// Why PBKDF2? Rust's bcrypt has a number of hardcodings like max pw len of 72
-// I don't really feel like adding in so many restrictions, so I'll use
-// pbkdf2 in openssl because it doesn't have the same limits.
+// Why not bcrypt? Rust's bcrypt has a number of hardcodings like max pw len of 72
+// I don't really feel like adding in so many restrictions. New passwords use
+// argon2id as it is memory hard, and PBKDF2 is retained so that existing and
+// imported hashes can still be verified until they are upgraded.
 #[derive(Clone, Debug)]
 enum KDF {
     //     cost, salt,   hash
     PBKDF2(usize, Vec<u8>, Vec<u8>),
     //      salt     hash
     SSHA512(Vec<u8>, Vec<u8>),
+    //      m_cost, t_cost, p_cost, salt,  hash
+    ARGON2ID(u32, u32, u32, Vec<u8>, Vec<u8>),
+    //   salt     hash
+    SSHA(Vec<u8>, Vec<u8>),
+    //      salt     hash
+    SSHA256(Vec<u8>, Vec<u8>),
+    // crypt(3) md5, sha256 or sha512 in modular crypt format.
+    CRYPT(String),
+    // bcrypt in modular crypt format.
+    BCRYPT(String),
+    // Any argon2 variant as a PHC string. Unlike ARGON2ID these come from other
+    // systems, so the parameters are not ours.
+    ARGON2PHC(String),
 }
 
 #[derive(Clone, Debug)]
@@ -68,10 +98,38 @@ impl TryFrom<DbPasswordV1> for Password {
             DbPasswordV1::SSHA512(s, h) => Ok(Password {
                 material: KDF::SSHA512(s, h),
             }),
+            DbPasswordV1::ARGON2ID(m, t, p, s, h) => Ok(Password {
+                material: KDF::ARGON2ID(m, t, p, s, h),
+            }),
+            DbPasswordV1::SSHA(s, h) => Ok(Password {
+                material: KDF::SSHA(s, h),
+            }),
+            DbPasswordV1::SSHA256(s, h) => Ok(Password {
+                material: KDF::SSHA256(s, h),
+            }),
+            DbPasswordV1::CRYPT(c) => Ok(Password {
+                material: KDF::CRYPT(c),
+            }),
+            DbPasswordV1::BCRYPT(c) => Ok(Password {
+                material: KDF::BCRYPT(c),
+            }),
+            DbPasswordV1::ARGON2PHC(c) => Ok(Password {
+                material: KDF::ARGON2PHC(c),
+            }),
         }
     }
 }
 
+// Split a base64 encoded hash || salt as used by ldap servers.
+fn split_salted_hash(value: &str, hash_len: usize) -> Result<(Vec<u8>, Vec<u8>), ()> {
+    let sh = base64::decode(value).map_err(|_| ())?;
+    if sh.len() <= hash_len {
+        return Err(());
+    }
+    let (h, s) = sh.split_at(hash_len);
+    Ok((s.to_vec(), h.to_vec()))
+}
+
 impl TryFrom<&str> for Password {
     type Error = ();
 
@@ -105,13 +163,69 @@ impl TryFrom<&str> for Password {
 
         // Test 389ds formats
         if let Some(ds_ssha512) = value.strip_prefix("{SSHA512}") {
-            let sh = base64::decode(ds_ssha512).map_err(|_| ())?;
-            let (h, s) = sh.split_at(DS_SSHA512_HASH_LEN);
+            let (s, h) = split_salted_hash(ds_ssha512, DS_SSHA512_HASH_LEN)?;
             if s.len() != DS_SSHA512_SALT_LEN {
                 return Err(());
             }
             return Ok(Password {
-                material: KDF::SSHA512(s.to_vec(), h.to_vec()),
+                material: KDF::SSHA512(s, h),
+            });
+        }
+
+        // Test openldap formats
+        if let Some(ssha) = value.strip_prefix("{SSHA}") {
+            let (s, h) = split_salted_hash(ssha, SSHA_HASH_LEN)?;
+            return Ok(Password {
+                material: KDF::SSHA(s, h),
+            });
+        }
+
+        if let Some(ssha256) = value.strip_prefix("{SSHA256}") {
+            let (s, h) = split_salted_hash(ssha256, SSHA256_HASH_LEN)?;
+            return Ok(Password {
+                material: KDF::SSHA256(s, h),
+            });
+        }
+
+        // Openldap prefixes crypt and argon2 hashes, but other sources provide them bare.
+        let mcf = value
+            .strip_prefix("{CRYPT}")
+            .or_else(|| value.strip_prefix("{ARGON2}"))
+            .unwrap_or(value);
+
+        // $id$[rounds=N$]salt$hash
+        if mcf.starts_with("$1$") || mcf.starts_with("$5$") || mcf.starts_with("$6$") {
+            let parts: Vec<&str> = mcf.split('$').collect();
+            let valid = (parts.len() == 4 || parts.len() == 5)
+                && parts.last().map(|h| !h.is_empty()).unwrap_or(false);
+            if !valid {
+                return Err(());
+            }
+            return Ok(Password {
+                material: KDF::CRYPT(mcf.to_string()),
+            });
+        }
+
+        if mcf.starts_with("$2a$") || mcf.starts_with("$2b$") || mcf.starts_with("$2y$") {
+            if mcf.len() != BCRYPT_LEN {
+                return Err(());
+            }
+            return Ok(Password {
+                material: KDF::BCRYPT(mcf.to_string()),
+            });
+        }
+
+        // $argon2id$v=19$m=65536,t=2,p=1$salt$hash, where the version is optional.
+        if mcf.starts_with("$argon2id$")
+            || mcf.starts_with("$argon2i$")
+            || mcf.starts_with("$argon2d$")
+        {
+            let parts = mcf.split('$').count();
+            if parts != 5 && parts != 6 {
+                return Err(());
+            }
+            return Ok(Password {
+                material: KDF::ARGON2PHC(mcf.to_string()),
             });
         }
 
@@ -120,28 +234,52 @@ impl TryFrom<&str> for Password {
     }
 }
 
+fn argon2id_config(m_cost: u32, t_cost: u32, p_cost: u32) -> argon2::Config<'static> {
+    argon2::Config {
+        variant: Variant::Argon2id,
+        version: Version::Version13,
+        mem_cost: m_cost,
+        time_cost: t_cost,
+        lanes: p_cost,
+        thread_mode: ThreadMode::Sequential,
+        secret: &[],
+        ad: &[],
+        hash_length: ARGON2_KEY_LEN,
+    }
+}
+
 impl Password {
-    fn bench_pbkdf2(pbkdf2_cost: usize) -> Option<Duration> {
+    fn bench_argon2id(m_cost: u32, t_cost: u32, p_cost: u32) -> Option<Duration> {
         let mut rng = rand::thread_rng();
-        let salt: Vec<u8> = (0..PBKDF2_SALT_LEN).map(|_| rng.gen()).collect();
-        let input: Vec<u8> = (0..PBKDF2_SALT_LEN).map(|_| rng.gen()).collect();
-        // This is 512 bits of output
-        let mut key: Vec<u8> = (0..PBKDF2_KEY_LEN).map(|_| 0).collect();
+        let salt: Vec<u8> = (0..ARGON2_SALT_LEN).map(|_| rng.gen()).collect();
+        let input: Vec<u8> = (0..ARGON2_SALT_LEN).map(|_| rng.gen()).collect();
+        let config = argon2id_config(m_cost, t_cost, p_cost);
 
         let start = Instant::now();
-        let _ = pbkdf2_hmac(
-            input.as_slice(),
-            salt.as_slice(),
-            pbkdf2_cost,
-            MessageDigest::sha256(),
-            key.as_mut_slice(),
-        )
-        .ok()?;
+        let _ = argon2::hash_raw(input.as_slice(), salt.as_slice(), &config).ok()?;
         let end = Instant::now();
 
         end.checked_duration_since(start)
     }
 
+    fn new_argon2id(
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+        cleartext: &str,
+    ) -> Result<KDF, OperationError> {
+        let mut rng = rand::thread_rng();
+        let salt: Vec<u8> = (0..ARGON2_SALT_LEN).map(|_| rng.gen()).collect();
+        let config = argon2id_config(m_cost, t_cost, p_cost);
+
+        argon2::hash_raw(cleartext.as_bytes(), salt.as_slice(), &config)
+            .map(|key| KDF::ARGON2ID(m_cost, t_cost, p_cost, salt, key))
+            .map_err(|_| OperationError::CryptographyError)
+    }
+
+    // New passwords are always argon2id, but we need to be able to create pbkdf2
+    // hashes to test the upgrade path.
+    #[cfg(test)]
     fn new_pbkdf2(pbkdf2_cost: usize, cleartext: &str) -> Result<KDF, OperationError> {
         let mut rng = rand::thread_rng();
         let salt: Vec<u8> = (0..PBKDF2_SALT_LEN).map(|_| rng.gen()).collect();
@@ -163,7 +301,13 @@ impl Password {
     }
 
     pub fn new(policy: &CryptoPolicy, cleartext: &str) -> Result<Self, OperationError> {
-        Self::new_pbkdf2(policy.pbkdf2_cost, cleartext).map(|material| Password { material })
+        Self::new_argon2id(
+            policy.argon2_m_cost,
+            policy.argon2_t_cost,
+            policy.argon2_p_cost,
+            cleartext,
+        )
+        .map(|material| Password { material })
     }
 
     pub fn verify(&self, cleartext: &str) -> Result<bool, OperationError> {
@@ -194,6 +338,34 @@ impl Password {
                 let r = hasher.finish();
                 Ok(key == &(r.to_vec()))
             }
+            KDF::ARGON2ID(m_cost, t_cost, p_cost, salt, key) => {
+                // verify_raw takes the output length from the stored hash.
+                let config = argon2id_config(*m_cost, *t_cost, *p_cost);
+                argon2::verify_raw(
+                    cleartext.as_bytes(),
+                    salt.as_slice(),
+                    key.as_slice(),
+                    &config,
+                )
+                .map_err(|_| OperationError::CryptographyError)
+            }
+            KDF::SSHA(salt, key) => {
+                let mut hasher = Sha1::new();
+                hasher.update(cleartext.as_bytes());
+                hasher.update(&salt);
+                let r = hasher.finish();
+                Ok(key == &(r.to_vec()))
+            }
+            KDF::SSHA256(salt, key) => {
+                let mut hasher = Sha256::new();
+                hasher.update(cleartext.as_bytes());
+                hasher.update(&salt);
+                let r = hasher.finish();
+                Ok(key == &(r.to_vec()))
+            }
+            KDF::CRYPT(hash) | KDF::BCRYPT(hash) => Ok(pwhash::unix::verify(cleartext, hash)),
+            KDF::ARGON2PHC(phc) => argon2::verify_encoded(phc, cleartext.as_bytes())
+                .map_err(|_| OperationError::CryptographyError),
         }
     }
 
@@ -203,12 +375,26 @@ impl Password {
                 DbPasswordV1::PBKDF2(*cost, salt.clone(), hash.clone())
             }
             KDF::SSHA512(salt, hash) => DbPasswordV1::SSHA512(salt.clone(), hash.clone()),
+            KDF::ARGON2ID(m_cost, t_cost, p_cost, salt, hash) => {
+                DbPasswordV1::ARGON2ID(*m_cost, *t_cost, *p_cost, salt.clone(), hash.clone())
+            }
+            KDF::SSHA(salt, hash) => DbPasswordV1::SSHA(salt.clone(), hash.clone()),
+            KDF::SSHA256(salt, hash) => DbPasswordV1::SSHA256(salt.clone(), hash.clone()),
+            KDF::CRYPT(hash) => DbPasswordV1::CRYPT(hash.clone()),
+            KDF::BCRYPT(hash) => DbPasswordV1::BCRYPT(hash.clone()),
+            KDF::ARGON2PHC(phc) => DbPasswordV1::ARGON2PHC(phc.clone()),
         }
     }
 
-    pub fn requires_upgrade(&self) -> bool {
+    /// Any hash that is not argon2id, or that is weaker than the current policy, is
+    /// rehashed on the next successful authentication.
+    pub fn requires_upgrade(&self, policy: &CryptoPolicy) -> bool {
         match &self.material {
-            KDF::PBKDF2(_, _, _) => false,
+            KDF::ARGON2ID(m_cost, t_cost, p_cost, _, _) => {
+                *m_cost < policy.argon2_m_cost
+                    || *t_cost < policy.argon2_t_cost
+                    || *p_cost < policy.argon2_p_cost
+            }
             _ => true,
         }
     }
@@ -230,6 +416,14 @@ pub struct Credential {
     // policy: Policy,
     pub(crate) type_: CredentialType,
     pub(crate) claims: Vec<String>,
+    // Previous passwords of this credential, oldest first, which may not be reused.
+    pub(crate) password_history: Vec<Password>,
+    // When the password was last changed, as a duration since the unix epoch. This is
+    // not known for credentials that predate it, or were imported.
+    pub(crate) password_set: Option<Duration>,
+    // The attested authenticator model of each webauthn token, by label. This is kept
+    // here as the webauthn credential type has nowhere to hold it.
+    pub(crate) webauthn_model: Map<String, Uuid>,
     // Uuid of Credential, used by auth session to lock this specific credential
     // if required.
     pub(crate) uuid: Uuid,
@@ -243,7 +437,12 @@ pub enum CredentialType {
     Password(Password),
     GeneratedPassword(Password),
     Webauthn(Map<String, WebauthnCredential>),
-    PasswordMFA(Password, Option<TOTP>, Map<String, WebauthnCredential>),
+    PasswordMFA(
+        Password,
+        Map<String, TOTP>,
+        Map<String, WebauthnCredential>,
+        Option<BackupCodes>,
+    ),
     // PasswordWebauthn(Password, Map<String, WebauthnCredential>),
     // WebauthnVerified(Map<String, WebauthnCredential>),
     // PasswordWebauthnVerified(Password, Map<String, WebauthnCredential>),
@@ -259,8 +458,12 @@ impl TryFrom<DbCredV1> for Credential {
             password,
             webauthn,
             totp,
+            totps,
+            backup_code,
             claims,
             uuid,
+            history,
+            password_set,
         } = value;
 
         let v_password = match password {
@@ -268,11 +471,29 @@ impl TryFrom<DbCredV1> for Credential {
             None => None,
         };
 
-        let v_totp = match totp {
-            Some(dbt) => Some(TOTP::try_from(dbt)?),
+        // Credentials from before multiple totps were supported hold a single one.
+        let v_totp = totp
+            .into_iter()
+            .chain(totps.into_iter())
+            .map(|dbt| TOTP::try_from(dbt).map(|t| (t.label().to_string(), t)))
+            .collect::<Result<Map<_, _>, _>>()?;
+
+        let v_backup_code = match backup_code {
+            Some(dbb) => Some(BackupCodes::try_from(dbb)?),
             None => None,
         };
 
+        let password_history = history
+            .into_iter()
+            .map(Password::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let webauthn_model = webauthn
+            .iter()
+            .flatten()
+            .filter_map(|wc| wc.m.map(|m| (wc.l.clone(), m)))
+            .collect();
+
         let v_webauthn = match webauthn {
             Some(dbw) => Some(
                 dbw.into_iter()
@@ -297,7 +518,9 @@ impl TryFrom<DbCredV1> for Credential {
             DbCredTypeV1::GPw => v_password.map(CredentialType::GeneratedPassword),
             // In the future this could use .zip
             DbCredTypeV1::PwMfa => match (v_password, v_webauthn) {
-                (Some(pw), Some(wn)) => Some(CredentialType::PasswordMFA(pw, v_totp, wn)),
+                (Some(pw), Some(wn)) => {
+                    Some(CredentialType::PasswordMFA(pw, v_totp, wn, v_backup_code))
+                }
                 _ => None,
             },
             DbCredTypeV1::Wn => v_webauthn.map(CredentialType::Webauthn),
@@ -308,6 +531,9 @@ impl TryFrom<DbCredV1> for Credential {
         Ok(Credential {
             type_,
             claims,
+            password_history,
+            password_set: password_set.map(Duration::from_secs),
+            webauthn_model,
             uuid,
         })
     }
@@ -321,12 +547,19 @@ impl Credential {
         Password::new(policy, cleartext).map(Self::new_from_password)
     }
 
-    pub fn new_webauthn_only(label: String, cred: WebauthnCredential) -> Self {
+    pub fn new_webauthn_only(label: String, cred: WebauthnCredential, model: Option<Uuid>) -> Self {
         let mut webauthn_map = Map::new();
+        let mut webauthn_model = Map::new();
+        if let Some(m) = model {
+            webauthn_model.insert(label.clone(), m);
+        }
         webauthn_map.insert(label, cred);
         Credential {
             type_: CredentialType::Webauthn(webauthn_map),
             claims: Vec::new(),
+            password_history: Vec::new(),
+            password_set: None,
+            webauthn_model,
             uuid: Uuid::new_v4(),
         }
     }
@@ -339,18 +572,77 @@ impl Credential {
         Password::new(policy, cleartext).map(|pw| self.update_password(pw))
     }
 
+    /// Set a new password at time `ct`, moving the current password into the history.
+    /// Only the `retain` most recent previous passwords are kept.
+    pub fn set_password_with_history(
+        &self,
+        policy: &CryptoPolicy,
+        cleartext: &str,
+        retain: usize,
+        ct: Duration,
+    ) -> Result<Self, OperationError> {
+        let mut ncred = self.set_password(policy, cleartext)?.with_password_set(ct);
+        if let Ok(pw) = self.password_ref() {
+            ncred.password_history.push(pw.clone());
+        }
+        let excess = ncred.password_history.len().saturating_sub(retain);
+        ncred.password_history.drain(..excess);
+        Ok(ncred)
+    }
+
+    pub(crate) fn with_password_set(mut self, ct: Duration) -> Self {
+        self.password_set = Some(ct);
+        self
+    }
+
+    /// When the password of this credential expires given a maximum age. If the time
+    /// the password was set is not known it does not expire, as it can't be told apart
+    /// from one set moments ago.
+    pub(crate) fn password_expiry(&self, max_age: Duration) -> Option<Duration> {
+        self.password_ref()
+            .ok()
+            .and(self.password_set)
+            .map(|set| set + max_age)
+    }
+
+    /// Check if the cleartext is the current password, or any in the history.
+    pub fn password_in_history(&self, cleartext: &str) -> Result<bool, OperationError> {
+        for pw in self
+            .password_ref()
+            .ok()
+            .into_iter()
+            .chain(self.password_history.iter())
+        {
+            if pw.verify(cleartext)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub(crate) fn has_password_history(&self) -> bool {
+        !self.password_history.is_empty()
+    }
+
+    pub(crate) fn clear_password_history(&self) -> Self {
+        let mut ncred = self.clone();
+        ncred.password_history.clear();
+        ncred
+    }
+
     pub fn append_webauthn(
         &self,
         label: String,
         cred: WebauthnCredential,
+        model: Option<Uuid>,
     ) -> Result<Self, OperationError> {
         let type_ = match &self.type_ {
             CredentialType::Password(pw) | CredentialType::GeneratedPassword(pw) => {
                 let mut wan = Map::new();
-                wan.insert(label, cred);
-                CredentialType::PasswordMFA(pw.clone(), None, wan)
+                wan.insert(label.clone(), cred);
+                CredentialType::PasswordMFA(pw.clone(), Map::new(), wan, None)
             }
-            CredentialType::PasswordMFA(pw, totp, map) => {
+            CredentialType::PasswordMFA(pw, totp, map, backup_code) => {
                 let mut nmap = map.clone();
                 if nmap.insert(label.clone(), cred).is_some() {
                     return Err(OperationError::InvalidAttribute(format!(
@@ -358,7 +650,7 @@ impl Credential {
                         label
                     )));
                 }
-                CredentialType::PasswordMFA(pw.clone(), totp.clone(), nmap)
+                CredentialType::PasswordMFA(pw.clone(), totp.clone(), nmap, backup_code.clone())
             }
             CredentialType::Webauthn(map) => {
                 let mut nmap = map.clone();
@@ -372,10 +664,18 @@ impl Credential {
             }
         };
 
+        let mut webauthn_model = self.webauthn_model.clone();
+        if let Some(m) = model {
+            webauthn_model.insert(label, m);
+        }
+
         // Check stuff
         Ok(Credential {
             type_,
             claims: self.claims.clone(),
+            password_history: self.password_history.clone(),
+            password_set: self.password_set,
+            webauthn_model,
             uuid: self.uuid,
         })
     }
@@ -387,7 +687,7 @@ impl Credential {
                     "Webauthn is not present on this credential".to_string(),
                 ));
             }
-            CredentialType::PasswordMFA(pw, totp, map) => {
+            CredentialType::PasswordMFA(pw, totp, map, backup_code) => {
                 let mut nmap = map.clone();
                 if nmap.remove(label).is_none() {
                     return Err(OperationError::InvalidAttribute(format!(
@@ -395,14 +695,11 @@ impl Credential {
                         label
                     )));
                 }
-                if nmap.is_empty() {
-                    if totp.is_some() {
-                        CredentialType::PasswordMFA(pw.clone(), totp.clone(), nmap)
-                    } else {
-                        CredentialType::Password(pw.clone())
-                    }
+                if nmap.is_empty() && totp.is_empty() {
+                    // Backup codes can't stand in for a factor that no longer exists.
+                    CredentialType::Password(pw.clone())
                 } else {
-                    CredentialType::PasswordMFA(pw.clone(), totp.clone(), nmap)
+                    CredentialType::PasswordMFA(pw.clone(), totp.clone(), nmap, backup_code.clone())
                 }
             }
             CredentialType::Webauthn(map) => {
@@ -423,10 +720,16 @@ impl Credential {
             }
         };
 
+        let mut webauthn_model = self.webauthn_model.clone();
+        webauthn_model.remove(label);
+
         // Check stuff
         Ok(Credential {
             type_,
             claims: self.claims.clone(),
+            password_history: self.password_history.clone(),
+            password_set: self.password_set,
+            webauthn_model,
             uuid: self.uuid,
         })
     }
@@ -442,7 +745,7 @@ impl Credential {
                 // No action required
                 return Ok(None);
             }
-            CredentialType::PasswordMFA(_, _, map) | CredentialType::Webauthn(map) => map
+            CredentialType::PasswordMFA(_, _, map, _) | CredentialType::Webauthn(map) => map
                 .iter()
                 .fold(None, |acc, (k, v)| {
                     if acc.is_none() && &v.cred_id == cid && v.counter < counter {
@@ -475,14 +778,17 @@ impl Credential {
                 return Err(OperationError::InvalidState);
             }
             CredentialType::Webauthn(_) => CredentialType::Webauthn(map),
-            CredentialType::PasswordMFA(pw, totp, _) => {
-                CredentialType::PasswordMFA(pw.clone(), totp.clone(), map)
+            CredentialType::PasswordMFA(pw, totp, _, backup_code) => {
+                CredentialType::PasswordMFA(pw.clone(), totp.clone(), map, backup_code.clone())
             }
         };
 
         Ok(Some(Credential {
             type_,
             claims: self.claims.clone(),
+            password_history: self.password_history.clone(),
+            password_set: self.password_set,
+            webauthn_model: self.webauthn_model.clone(),
             uuid: self.uuid,
         }))
     }
@@ -492,15 +798,54 @@ impl Credential {
             CredentialType::Password(_) | CredentialType::GeneratedPassword(_) => Err(
                 OperationError::InvalidAccountState("non-webauthn cred type?".to_string()),
             ),
-            CredentialType::PasswordMFA(_, _, map) | CredentialType::Webauthn(map) => Ok(map),
+            CredentialType::PasswordMFA(_, _, map, _) | CredentialType::Webauthn(map) => Ok(map),
+        }
+    }
+
+    /// The strength of this credential. When a credential offers a choice of factors,
+    /// it is only as strong as the weakest of them. Backup codes are a shared secret
+    /// like TOTP, so rank the same.
+    pub(crate) fn strength(&self) -> CredentialStrength {
+        match &self.type_ {
+            CredentialType::Password(_) => CredentialStrength::Password,
+            CredentialType::GeneratedPassword(_) => CredentialStrength::GeneratedPassword,
+            CredentialType::Webauthn(wan) if wan.values().all(|c| c.verified) => {
+                CredentialStrength::WebauthnVerified
+            }
+            CredentialType::Webauthn(_) => CredentialStrength::Webauthn,
+            CredentialType::PasswordMFA(_, totp, wan, None)
+                if totp.is_empty() && wan.values().all(|c| c.verified) =>
+            {
+                CredentialStrength::PasswordWebauthnVerified
+            }
+            CredentialType::PasswordMFA(_, totp, _, None) if totp.is_empty() => {
+                CredentialStrength::PasswordWebauthn
+            }
+            CredentialType::PasswordMFA(_, _, _, _) => CredentialStrength::PasswordTotp,
+        }
+    }
+
+    /// If this credential can only be used with webauthn, and none of its tokens were
+    /// user verified at registration.
+    pub(crate) fn is_webauthn_unverified_only(&self) -> bool {
+        match &self.type_ {
+            CredentialType::Webauthn(wan) => !wan.values().any(|c| c.verified),
+            CredentialType::PasswordMFA(_, totp, wan, _) => {
+                totp.is_empty() && !wan.values().any(|c| c.verified)
+            }
+            _ => false,
         }
     }
 
+    pub fn webauthn_model(&self, label: &str) -> Option<&Uuid> {
+        self.webauthn_model.get(label)
+    }
+
     pub fn password_ref(&self) -> Result<&Password, OperationError> {
         match &self.type_ {
             CredentialType::Password(pw)
             | CredentialType::GeneratedPassword(pw)
-            | CredentialType::PasswordMFA(pw, _, _) => Ok(pw),
+            | CredentialType::PasswordMFA(pw, _, _, _) => Ok(pw),
             CredentialType::Webauthn(_) => Err(OperationError::InvalidAccountState(
                 "non-password cred type?".to_string(),
             )),
@@ -515,24 +860,38 @@ impl Credential {
     pub fn to_db_valuev1(&self) -> DbCredV1 {
         let claims = self.claims.clone();
         let uuid = self.uuid;
+        let history: Vec<DbPasswordV1> = self
+            .password_history
+            .iter()
+            .map(|pw| pw.to_dbpasswordv1())
+            .collect();
+        let password_set = self.password_set.map(|d| d.as_secs());
         match &self.type_ {
             CredentialType::Password(pw) => DbCredV1 {
                 type_: DbCredTypeV1::Pw,
                 password: Some(pw.to_dbpasswordv1()),
                 webauthn: None,
                 totp: None,
+                totps: Vec::new(),
+                backup_code: None,
                 claims,
                 uuid,
+                history,
+                password_set,
             },
             CredentialType::GeneratedPassword(pw) => DbCredV1 {
                 type_: DbCredTypeV1::GPw,
                 password: Some(pw.to_dbpasswordv1()),
                 webauthn: None,
                 totp: None,
+                totps: Vec::new(),
+                backup_code: None,
                 claims,
                 uuid,
+                history,
+                password_set,
             },
-            CredentialType::PasswordMFA(pw, totp, map) => DbCredV1 {
+            CredentialType::PasswordMFA(pw, totp, map, backup_code) => DbCredV1 {
                 type_: DbCredTypeV1::PwMfa,
                 password: Some(pw.to_dbpasswordv1()),
                 webauthn: Some(
@@ -543,12 +902,17 @@ impl Credential {
                             c: v.cred.clone(),
                             t: v.counter,
                             v: v.verified,
+                            m: self.webauthn_model.get(k).copied(),
                         })
                         .collect(),
                 ),
-                totp: totp.as_ref().map(|t| t.to_dbtotpv1()),
+                totp: None,
+                totps: totp.values().map(|t| t.to_dbtotpv1()).collect(),
+                backup_code: backup_code.as_ref().map(|b| b.to_dbbackupcodev1()),
                 claims,
                 uuid,
+                history,
+                password_set,
             },
             CredentialType::Webauthn(map) => DbCredV1 {
                 type_: DbCredTypeV1::Wn,
@@ -561,12 +925,17 @@ impl Credential {
                             c: v.cred.clone(),
                             t: v.counter,
                             v: v.verified,
+                            m: self.webauthn_model.get(k).copied(),
                         })
                         .collect(),
                 ),
                 totp: None,
+                totps: Vec::new(),
+                backup_code: None,
                 claims,
                 uuid,
+                history,
+                password_set,
             },
         }
     }
@@ -575,26 +944,36 @@ impl Credential {
         let type_ = match &self.type_ {
             CredentialType::Password(_) => CredentialType::Password(pw),
             CredentialType::GeneratedPassword(_) => CredentialType::GeneratedPassword(pw),
-            CredentialType::PasswordMFA(_, totp, wan) => {
-                CredentialType::PasswordMFA(pw, totp.clone(), wan.clone())
+            CredentialType::PasswordMFA(_, totp, wan, backup_code) => {
+                CredentialType::PasswordMFA(pw, totp.clone(), wan.clone(), backup_code.clone())
+            }
+            CredentialType::Webauthn(wan) => {
+                CredentialType::PasswordMFA(pw, Map::new(), wan.clone(), None)
             }
-            CredentialType::Webauthn(wan) => CredentialType::PasswordMFA(pw, None, wan.clone()),
         };
         Credential {
             type_,
             claims: self.claims.clone(),
+            password_history: self.password_history.clone(),
+            password_set: self.password_set,
+            webauthn_model: self.webauthn_model.clone(),
             uuid: self.uuid,
         }
     }
 
     // We don't make totp accessible from outside the crate for now.
+    // A totp with the same label as an existing one replaces it.
     pub(crate) fn update_totp(&self, totp: TOTP) -> Self {
         let type_ = match &self.type_ {
             CredentialType::Password(pw) | CredentialType::GeneratedPassword(pw) => {
-                CredentialType::PasswordMFA(pw.clone(), Some(totp), Map::new())
+                let mut nmap = Map::new();
+                nmap.insert(totp.label().to_string(), totp);
+                CredentialType::PasswordMFA(pw.clone(), nmap, Map::new(), None)
             }
-            CredentialType::PasswordMFA(pw, _, wan) => {
-                CredentialType::PasswordMFA(pw.clone(), Some(totp), wan.clone())
+            CredentialType::PasswordMFA(pw, map, wan, backup_code) => {
+                let mut nmap = map.clone();
+                nmap.insert(totp.label().to_string(), totp);
+                CredentialType::PasswordMFA(pw.clone(), nmap, wan.clone(), backup_code.clone())
             }
             CredentialType::Webauthn(wan) => {
                 debug_assert!(false);
@@ -604,25 +983,95 @@ impl Credential {
         Credential {
             type_,
             claims: self.claims.clone(),
+            password_history: self.password_history.clone(),
+            password_set: self.password_set,
+            webauthn_model: self.webauthn_model.clone(),
             uuid: self.uuid,
         }
     }
 
-    pub(crate) fn remove_totp(&self) -> Self {
+    pub(crate) fn remove_totp(&self, label: &str) -> Result<Self, OperationError> {
         let type_ = match &self.type_ {
-            CredentialType::PasswordMFA(pw, Some(_), wan) => {
-                if wan.is_empty() {
+            CredentialType::PasswordMFA(pw, map, wan, backup_code) => {
+                let mut nmap = map.clone();
+                if nmap.remove(label).is_none() {
+                    return Err(OperationError::InvalidAttribute(format!(
+                        "Removing TOTP with label '{:?}': does not exist",
+                        label
+                    )));
+                }
+                if nmap.is_empty() && wan.is_empty() {
+                    // Backup codes can't stand in for a factor that no longer exists.
                     CredentialType::Password(pw.clone())
                 } else {
-                    CredentialType::PasswordMFA(pw.clone(), None, wan.clone())
+                    CredentialType::PasswordMFA(pw.clone(), nmap, wan.clone(), backup_code.clone())
                 }
             }
-            _ => self.type_.clone(),
+            _ => {
+                return Err(OperationError::InvalidAttribute(
+                    "TOTP is not present on this credential".to_string(),
+                ));
+            }
         };
-        Credential {
+        Ok(Credential {
             type_,
             claims: self.claims.clone(),
+            password_history: self.password_history.clone(),
+            password_set: self.password_set,
+            webauthn_model: self.webauthn_model.clone(),
             uuid: self.uuid,
+        })
+    }
+
+    /// Replace the backup codes of this credential. They can only be used in place of
+    /// a TOTP or webauthn token, so one of those must already be registered.
+    pub(crate) fn update_backup_code(
+        &self,
+        backup_codes: BackupCodes,
+    ) -> Result<Self, OperationError> {
+        let type_ = match &self.type_ {
+            CredentialType::PasswordMFA(pw, totp, wan, _) => CredentialType::PasswordMFA(
+                pw.clone(),
+                totp.clone(),
+                wan.clone(),
+                Some(backup_codes),
+            ),
+            _ => {
+                return Err(OperationError::InvalidAccountState(
+                    "backup codes require a totp or webauthn token".to_string(),
+                ))
+            }
+        };
+        Ok(Credential {
+            type_,
+            claims: self.claims.clone(),
+            password_history: self.password_history.clone(),
+            password_set: self.password_set,
+            webauthn_model: self.webauthn_model.clone(),
+            uuid: self.uuid,
+        })
+    }
+
+    /// Consume a backup code. Returns None if the code is not valid for this
+    /// credential, such as when it has already been used.
+    pub(crate) fn remove_backup_code(&self, code: &str) -> Option<Self> {
+        match &self.type_ {
+            CredentialType::PasswordMFA(pw, totp, wan, Some(backup_codes)) => {
+                backup_codes.remove(code).map(|nbackup_codes| Credential {
+                    type_: CredentialType::PasswordMFA(
+                        pw.clone(),
+                        totp.clone(),
+                        wan.clone(),
+                        Some(nbackup_codes),
+                    ),
+                    claims: self.claims.clone(),
+                    password_history: self.password_history.clone(),
+                    password_set: self.password_set,
+                    webauthn_model: self.webauthn_model.clone(),
+                    uuid: self.uuid,
+                })
+            }
+            _ => None,
         }
     }
 
@@ -630,6 +1079,9 @@ impl Credential {
         Credential {
             type_: CredentialType::Password(pw),
             claims: Vec::new(),
+            password_history: Vec::new(),
+            password_set: None,
+            webauthn_model: Map::new(),
             uuid: Uuid::new_v4(),
         }
     }
@@ -639,9 +1091,10 @@ impl Credential {
             CredentialType::Password(_pw) | CredentialType::GeneratedPassword(_pw) => {
                 Some(CredSoftLockPolicy::Password)
             }
-            CredentialType::PasswordMFA(_pw, totp, wan) => {
-                if let Some(r_totp) = totp {
-                    Some(CredSoftLockPolicy::TOTP(r_totp.step))
+            CredentialType::PasswordMFA(_pw, totp, wan, _) => {
+                // With several totps, lock for long enough to cover the slowest.
+                if let Some(step) = totp.values().map(|t| t.step).max() {
+                    Some(CredSoftLockPolicy::TOTP(step))
                 } else if !wan.is_empty() {
                     Some(CredSoftLockPolicy::Webauthn)
                 } else {
@@ -652,13 +1105,21 @@ impl Credential {
         }
     }
 
-    /*
-    pub fn add_claim(&mut self) {
+    /// Limit what this credential may be used for. A credential with no claims is
+    /// unrestricted.
+    pub fn add_claim(&mut self, claim: &str) {
+        if !self.has_claim(claim) {
+            self.claims.push(claim.to_string());
+        }
     }
 
-    pub fn remove_claim(&mut self) {
+    pub fn remove_claim(&mut self, claim: &str) {
+        self.claims.retain(|c| c != claim);
+    }
+
+    pub fn has_claim(&self, claim: &str) -> bool {
+        self.claims.iter().any(|c| c == claim)
     }
-    */
 
     /*
     pub fn modify_password(&mut self) {
@@ -677,8 +1138,8 @@ impl CredentialType {
     fn is_valid(&self) -> bool {
         match self {
             CredentialType::Password(_) | CredentialType::GeneratedPassword(_) => true,
-            CredentialType::PasswordMFA(_, m_totp, webauthn) => {
-                m_totp.is_some() || !webauthn.is_empty()
+            CredentialType::PasswordMFA(_, m_totp, webauthn, _) => {
+                !m_totp.is_empty() || !webauthn.is_empty()
             }
             CredentialType::Webauthn(webauthn) => !webauthn.is_empty(),
         }
@@ -702,6 +1163,128 @@ mod tests {
         assert!(!c.verify_password("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").unwrap());
     }
 
+    #[test]
+    fn test_credential_claims() {
+        let p = CryptoPolicy::minimum();
+        let mut c = Credential::new_password_only(&p, "password").unwrap();
+        assert!(!c.has_claim("ldap"));
+        c.add_claim("ldap");
+        c.add_claim("ldap");
+        assert!(c.has_claim("ldap"));
+        assert!(c.claims.len() == 1);
+
+        // Claims must survive the round trip to the db.
+        let c = Credential::try_from(c.to_db_valuev1()).unwrap();
+        assert!(c.has_claim("ldap"));
+        assert!(!c.has_claim("unix"));
+
+        let mut c = c;
+        c.remove_claim("ldap");
+        assert!(!c.has_claim("ldap"));
+    }
+
+    #[test]
+    fn test_credential_password_history() {
+        let p = CryptoPolicy::minimum();
+        let c = Credential::new_password_only(&p, "password1").unwrap();
+        assert!(c.password_in_history("password1").unwrap());
+        assert!(!c.password_in_history("password2").unwrap());
+
+        let c = c
+            .set_password_with_history(&p, "password2", 2, Duration::from_secs(1))
+            .unwrap();
+        let c = c
+            .set_password_with_history(&p, "password3", 2, Duration::from_secs(2))
+            .unwrap();
+        assert!(c.verify_password("password3").unwrap());
+        assert!(c.password_in_history("password1").unwrap());
+        assert!(c.password_in_history("password2").unwrap());
+
+        // The oldest is dropped, and the history survives the db round trip.
+        let c = c
+            .set_password_with_history(&p, "password4", 2, Duration::from_secs(3))
+            .unwrap();
+        let c = Credential::try_from(c.to_db_valuev1()).unwrap();
+        assert!(!c.password_in_history("password1").unwrap());
+        assert!(c.password_in_history("password2").unwrap());
+        assert!(c.password_in_history("password3").unwrap());
+        assert!(c.password_in_history("password4").unwrap());
+
+        // A plain set, such as a rehash, leaves the history alone.
+        let c = c.set_password(&p, "password4").unwrap();
+        assert!(c.password_in_history("password3").unwrap());
+
+        let c = c.clear_password_history();
+        assert!(!c.has_password_history());
+        assert!(!c.password_in_history("password3").unwrap());
+    }
+
+    #[test]
+    fn test_credential_password_expiry() {
+        let p = CryptoPolicy::minimum();
+        let max_age = Duration::from_secs(60);
+        // Passwords from before the set time was recorded don't expire.
+        let c = Credential::new_password_only(&p, "password1").unwrap();
+        assert!(c.password_expiry(max_age).is_none());
+
+        let c = c
+            .set_password_with_history(&p, "password2", 2, Duration::from_secs(10))
+            .unwrap();
+        assert!(c.password_expiry(max_age) == Some(Duration::from_secs(70)));
+    }
+
+    #[test]
+    fn test_credential_backup_code() {
+        let p = CryptoPolicy::minimum();
+        let c = Credential::new_password_only(&p, "password").unwrap();
+
+        // Backup codes need a second factor to stand in for.
+        let (codes, cleartext) = BackupCodes::generate();
+        assert!(c.update_backup_code(codes.clone()).is_err());
+
+        let totp = TOTP::generate_secure("test_totp".to_string(), totp::TOTP_DEFAULT_STEP);
+        let c = c.update_totp(totp).update_backup_code(codes).unwrap();
+        let c = Credential::try_from(c.to_db_valuev1()).unwrap();
+
+        let code = cleartext[0].as_str();
+        let c = c.remove_backup_code(code).expect("code not present");
+        assert!(c.remove_backup_code(code).is_none());
+        assert!(c.remove_backup_code(cleartext[1].as_str()).is_some());
+
+        // Removing the only factor removes the codes with it.
+        let c = c.remove_totp("test_totp").unwrap();
+        assert!(matches!(c.type_, CredentialType::Password(_)));
+        assert!(c.remove_backup_code(cleartext[1].as_str()).is_none());
+    }
+
+    #[test]
+    fn test_credential_multiple_totp() {
+        let p = CryptoPolicy::minimum();
+        let c = Credential::new_password_only(&p, "password").unwrap();
+        assert!(c.remove_totp("phone").is_err());
+
+        let phone = TOTP::generate_secure("phone".to_string(), totp::TOTP_DEFAULT_STEP);
+        let token = TOTP::generate_secure("token".to_string(), 60);
+        let c = c.update_totp(phone).update_totp(token);
+        // Both are kept through the db, and the softlock covers the longest step.
+        let c = Credential::try_from(c.to_db_valuev1()).unwrap();
+        match &c.type_ {
+            CredentialType::PasswordMFA(_, totp, _, _) => assert!(totp.len() == 2),
+            _ => assert!(false),
+        };
+        assert!(matches!(
+            c.softlock_policy(),
+            Some(CredSoftLockPolicy::TOTP(60))
+        ));
+
+        // Removing one leaves the other in place.
+        assert!(c.remove_totp("laptop").is_err());
+        let c = c.remove_totp("token").unwrap();
+        assert!(matches!(c.type_, CredentialType::PasswordMFA(_, _, _, _)));
+        let c = c.remove_totp("phone").unwrap();
+        assert!(matches!(c.type_, CredentialType::Password(_)));
+    }
+
     #[test]
     fn test_password_from_invalid() {
         assert!(Password::try_from("password").is_err())
@@ -712,16 +1295,110 @@ mod tests {
         let im_pw = "pbkdf2_sha256$36000$xIEozuZVAoYm$uW1b35DUKyhvQAf1mBqMvoBDcqSD06juzyO/nmyV0+w=";
         let password = "eicieY7ahchaoCh0eeTa";
         let r = Password::try_from(im_pw).expect("Failed to parse");
+        assert!(r.requires_upgrade(&CryptoPolicy::minimum()));
         assert!(r.verify(password).unwrap_or(false));
     }
 
+    #[test]
+    fn test_password_from_openldap_ssha() {
+        let im_pw = "{SSHA}leROjkjxEnJ3xePiL3ptH4ic69GPGgPMVSCeQQ==";
+        let r = Password::try_from(im_pw).expect("Failed to parse");
+        assert!(r.requires_upgrade(&CryptoPolicy::minimum()));
+        assert!(r.verify("password").unwrap_or(false));
+        assert!(!r.verify("password1").unwrap_or(true));
+
+        let im_pw = "{SSHA256}gRbtTCnGisAXTgXDwxgJyHulbdtSvVSYn0oYVqIRpYWPGgPMVSCeQQ==";
+        let r = Password::try_from(im_pw).expect("Failed to parse");
+        assert!(r.requires_upgrade(&CryptoPolicy::minimum()));
+        assert!(r.verify("password").unwrap_or(false));
+        assert!(!r.verify("password1").unwrap_or(true));
+
+        // A hash with no salt is rejected rather than panicking.
+        assert!(Password::try_from("{SSHA}leROjkjxEnJ3xePiL3ptH4ic69E=").is_err());
+        assert!(Password::try_from("{SSHA512}cGFzc3dvcmQ=").is_err());
+    }
+
+    #[test]
+    fn test_password_from_crypt() {
+        let password = "password";
+        let hashes = [
+            "$1$eXam9le$03ICITdwWeqzNtyMxo9Ld1",
+            "$5$eXam9leSaltVal$AuQb/EkO1Xm9EtsCW5BY8iCbObvEpcWVA9.uVOt.oc8",
+            "{CRYPT}$6$eXam9leSaltVal$OQtztVddtoOsWswqiVQg/TUSRSfM6RgbCcn.vZSDqGbmQcNKUqIFW3Ol/ojGvCYWDb5SOYkYUCqkwOUDQCkcR.",
+            "{CRYPT}$6$rounds=10000$eXam9leSaltVal$fuNcoSy21Th21Z3y4Q82kfcA10g.NDZoo4EXx9PHLa6dv1Mv8S3.WYD7/bKd9mqbxuBCmfnAmIHBWpiBdBG3K0",
+        ];
+        for im_pw in hashes.iter() {
+            let r = Password::try_from(*im_pw).expect("Failed to parse");
+            assert!(r.requires_upgrade(&CryptoPolicy::minimum()));
+            assert!(r.verify(password).unwrap_or(false));
+            assert!(!r.verify("password1").unwrap_or(true));
+            // Must survive the round trip to the db.
+            let r = Password::try_from(r.to_dbpasswordv1()).expect("Failed to load");
+            assert!(r.verify(password).unwrap_or(false));
+        }
+
+        assert!(Password::try_from("$6$eXam9leSaltVal$").is_err());
+    }
+
+    #[test]
+    fn test_password_from_bcrypt() {
+        let im_pw = "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW";
+        let r = Password::try_from(im_pw).expect("Failed to parse");
+        assert!(r.requires_upgrade(&CryptoPolicy::minimum()));
+        assert!(r.verify("U*U").unwrap_or(false));
+        assert!(!r.verify("U*U*").unwrap_or(true));
+
+        assert!(Password::try_from("$2a$05$CCCCCCCCCCCCCCCCCCCCC").is_err());
+    }
+
+    #[test]
+    fn test_password_from_argon2_phc() {
+        let im_pw = "$argon2id$v=19$m=65536,t=2,p=1$c29tZXNhbHQ$CTFhFdXPJO1aFaMaO6Mm5c8y7cJHAph8ArZWb2GRPPc";
+        let r = Password::try_from(im_pw).expect("Failed to parse");
+        // We don't control the parameters of imported hashes, so they are upgraded.
+        assert!(r.requires_upgrade(&CryptoPolicy::minimum()));
+        assert!(r.verify("password").unwrap_or(false));
+        assert!(!r.verify("password1").unwrap_or(true));
+
+        let r =
+            Password::try_from(format!("{{ARGON2}}{}", im_pw).as_str()).expect("Failed to parse");
+        assert!(r.verify("password").unwrap_or(false));
+
+        assert!(Password::try_from("$argon2id$v=19$c29tZXNhbHQ").is_err());
+    }
+
+    #[test]
+    fn test_password_argon2id() {
+        let p = CryptoPolicy::minimum();
+        let r = Password::new(&p, "password").expect("Failed to hash");
+        assert!(!r.requires_upgrade(&CryptoPolicy::minimum()));
+        assert!(r.verify("password").unwrap_or(false));
+        assert!(!r.verify("password1").unwrap_or(true));
+
+        // The parameters are stored with the hash, so it must verify after the db round trip.
+        let r = Password::try_from(r.to_dbpasswordv1()).expect("Failed to load");
+        assert!(!r.requires_upgrade(&CryptoPolicy::minimum()));
+        assert!(r.verify("password").unwrap_or(false));
+
+        // A hash weaker than the current policy is upgraded.
+        assert!(r.requires_upgrade(&CryptoPolicy::default()));
+    }
+
+    #[test]
+    fn test_password_pbkdf2_requires_upgrade() {
+        let material = Password::new_pbkdf2(10000, "password").expect("Failed to hash");
+        let r = Password { material };
+        assert!(r.requires_upgrade(&CryptoPolicy::minimum()));
+        assert!(r.verify("password").unwrap_or(false));
+    }
+
     #[test]
     fn test_password_from_ds_ssha512() {
         let im_pw = "{SSHA512}JwrSUHkI7FTAfHRVR6KoFlSN0E3dmaQWARjZ+/UsShYlENOqDtFVU77HJLLrY2MuSp0jve52+pwtdVl2QUAHukQ0XUf5LDtM";
         let password = "password";
         let r = Password::try_from(im_pw).expect("Failed to parse");
         // Known weak, require upgrade.
-        assert!(r.requires_upgrade());
+        assert!(r.requires_upgrade(&CryptoPolicy::minimum()));
         assert!(r.verify(password).unwrap_or(false));
     }
 }
@@ -1,47 +1,215 @@
 use super::Password;
+use crate::constants::{PW_MIN_LENGTH, PW_MIN_SCORE};
+use crate::entry::{Entry, EntryCommitted, EntrySealed};
+use std::convert::TryFrom;
 use std::time::Duration;
 
-const PBKDF2_MIN_NIST_COST: u64 = 10000;
+// These are the minimum argon2id parameters recommended by OWASP, 15 MiB of memory
+// with two iterations and one degree of parallelism. Passwords hashed with weaker
+// parameters are rehashed when they are next used, so raising these upgrades them.
+const ARGON2_MIN_M_COST: u32 = 15 * 1024;
+const ARGON2_MIN_T_COST: u32 = 2;
+const ARGON2_P_COST: u32 = 1;
 
 #[derive(Debug)]
 pub struct CryptoPolicy {
-    pub(crate) pbkdf2_cost: usize,
+    pub(crate) argon2_m_cost: u32,
+    pub(crate) argon2_t_cost: u32,
+    pub(crate) argon2_p_cost: u32,
 }
 
 impl CryptoPolicy {
     #[cfg(test)]
     pub(crate) fn minimum() -> Self {
+        // Hashing at the real minimum is slow in debug builds, so tests use the
+        // smallest parameters argon2 will accept.
         CryptoPolicy {
-            pbkdf2_cost: PBKDF2_MIN_NIST_COST as usize,
+            argon2_m_cost: 8 * ARGON2_P_COST,
+            argon2_t_cost: 1,
+            argon2_p_cost: ARGON2_P_COST,
         }
     }
 
+    /// Scale the number of iterations so that hashing a password takes about `t` on this
+    /// machine, but never below the minimums. Stored hashes are only upgraded when they are
+    /// weaker than the policy, so a benchmark that varies between restarts does not rehash
+    /// passwords that already meet it.
     pub fn time_target(t: Duration) -> Self {
-        let r = match Password::bench_pbkdf2((PBKDF2_MIN_NIST_COST * 10) as usize) {
-            Some(bt) => {
-                let ubt = bt.as_nanos() as u64;
-
-                // Get the cost per thousand rounds
-                let per_thou = (PBKDF2_MIN_NIST_COST * 10) / 1000;
-                let t_per_thou = ubt / per_thou;
-                // eprintln!("{} / {}", ubt, per_thou);
-
-                // Now we need the attacker work in nanos
-                let attack_time = t.as_nanos() as u64;
-                let r = (attack_time / t_per_thou) * 1000;
-
-                // eprintln!("({} / {} ) * 1000", attack_time, t_per_thou);
-                // eprintln!("Maybe rounds -> {}", r);
-
-                if r < PBKDF2_MIN_NIST_COST {
-                    PBKDF2_MIN_NIST_COST as usize
-                } else {
-                    r as usize
+        // Memory cost is fixed, and the number of iterations is scaled to meet the target.
+        let t_cost =
+            match Password::bench_argon2id(ARGON2_MIN_M_COST, ARGON2_MIN_T_COST, ARGON2_P_COST) {
+                Some(bt) => {
+                    let ubt = bt.as_nanos() as u64;
+
+                    // Get the cost per iteration
+                    let t_per_iter = ubt / (ARGON2_MIN_T_COST as u64);
+
+                    // Now we need the attacker work in nanos
+                    let attack_time = t.as_nanos() as u64;
+                    let r = attack_time.checked_div(t_per_iter).unwrap_or(0);
+
+                    if r < ARGON2_MIN_T_COST as u64 {
+                        ARGON2_MIN_T_COST
+                    } else {
+                        u32::try_from(r).unwrap_or(u32::MAX)
+                    }
                 }
+                None => ARGON2_MIN_T_COST,
+            };
+
+        CryptoPolicy {
+            argon2_m_cost: ARGON2_MIN_M_COST,
+            argon2_t_cost: t_cost,
+            argon2_p_cost: ARGON2_P_COST,
+        }
+    }
+}
+
+impl Default for CryptoPolicy {
+    fn default() -> Self {
+        CryptoPolicy {
+            argon2_m_cost: ARGON2_MIN_M_COST,
+            argon2_t_cost: ARGON2_MIN_T_COST,
+            argon2_p_cost: ARGON2_P_COST,
+        }
+    }
+}
+
+/// The strength of a credential, weakest first. Groups may require a minimum strength of
+/// the credentials of their members, and when an account is a member of several such
+/// groups, the strongest requirement applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CredentialStrength {
+    Password,
+    GeneratedPassword,
+    Webauthn,
+    PasswordTotp,
+    PasswordWebauthn,
+    // Webauthn where the authenticator verified the user, such as with a pin or biometric.
+    WebauthnVerified,
+    PasswordWebauthnVerified,
+}
+
+impl CredentialStrength {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CredentialStrength::Password => "password",
+            CredentialStrength::GeneratedPassword => "generated_password",
+            CredentialStrength::Webauthn => "webauthn",
+            CredentialStrength::PasswordTotp => "password_totp",
+            CredentialStrength::PasswordWebauthn => "password_webauthn",
+            CredentialStrength::WebauthnVerified => "webauthn_verified",
+            CredentialStrength::PasswordWebauthnVerified => "password_webauthn_verified",
+        }
+    }
+
+    /// The reason given when authentication is denied because a credential is weaker
+    /// than this strength.
+    pub(crate) fn denied_msg(&self) -> &'static str {
+        match self {
+            CredentialStrength::Password => "credential policy requires a password or stronger",
+            CredentialStrength::GeneratedPassword => {
+                "credential policy requires a generated password or stronger"
+            }
+            CredentialStrength::Webauthn => "credential policy requires webauthn or stronger",
+            CredentialStrength::PasswordTotp => {
+                "credential policy requires a password with totp or stronger"
+            }
+            CredentialStrength::PasswordWebauthn => {
+                "credential policy requires a password with webauthn or stronger"
             }
-            None => PBKDF2_MIN_NIST_COST as usize,
-        };
+            CredentialStrength::WebauthnVerified => {
+                "credential policy requires user verified webauthn or stronger"
+            }
+            CredentialStrength::PasswordWebauthnVerified => {
+                "credential policy requires a password with user verified webauthn"
+            }
+        }
+    }
+}
+
+impl TryFrom<&str> for CredentialStrength {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "password" => Ok(CredentialStrength::Password),
+            "generated_password" => Ok(CredentialStrength::GeneratedPassword),
+            "webauthn" => Ok(CredentialStrength::Webauthn),
+            "password_totp" => Ok(CredentialStrength::PasswordTotp),
+            "password_webauthn" => Ok(CredentialStrength::PasswordWebauthn),
+            "webauthn_verified" => Ok(CredentialStrength::WebauthnVerified),
+            "password_webauthn_verified" => Ok(CredentialStrength::PasswordWebauthnVerified),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The requirements that new passwords must meet. These are set in the system config, and
+/// groups may override the minimums for their members.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordQualityPolicy {
+    pub(crate) min_length: usize,
+    // The minimum zxcvbn score, from 0 to 4.
+    pub(crate) min_score: u32,
+    // How many of lower case, upper case, digits and symbols must be present.
+    pub(crate) min_char_classes: u32,
+    // Lower case words that may not appear anywhere in the password.
+    pub(crate) badlist_words: Vec<String>,
+}
+
+impl Default for PasswordQualityPolicy {
+    fn default() -> Self {
+        PasswordQualityPolicy {
+            min_length: PW_MIN_LENGTH,
+            min_score: PW_MIN_SCORE,
+            min_char_classes: 0,
+            badlist_words: Vec::new(),
+        }
+    }
+}
+
+impl PasswordQualityPolicy {
+    pub(crate) fn from_system_config(e: &Entry<EntrySealed, EntryCommitted>) -> Self {
+        let d = Self::default();
+        PasswordQualityPolicy {
+            min_length: e
+                .get_ava_single_uint32("password_min_length")
+                .map(|v| v as usize)
+                .unwrap_or(d.min_length),
+            min_score: e
+                .get_ava_single_uint32("password_min_score")
+                .unwrap_or(d.min_score),
+            min_char_classes: e
+                .get_ava_single_uint32("password_min_char_classes")
+                .unwrap_or(d.min_char_classes),
+            badlist_words: e
+                .get_ava_as_str("password_badlist_words")
+                .map(|iter| iter.map(|s| s.to_string()).collect())
+                .unwrap_or(d.badlist_words),
+        }
+    }
+
+    /// The number of character classes present in a password.
+    pub(crate) fn char_classes(cleartext: &str) -> u32 {
+        let classes: [fn(char) -> bool; 4] = [
+            char::is_lowercase,
+            char::is_uppercase,
+            char::is_numeric,
+            |c| !c.is_alphanumeric(),
+        ];
+        classes
+            .iter()
+            .filter(|class| cleartext.chars().any(**class))
+            .count() as u32
+    }
 
-        CryptoPolicy { pbkdf2_cost: r }
+    /// The first banned word that the password contains, ignoring case.
+    pub(crate) fn find_badlist_word(&self, cleartext: &str) -> Option<&str> {
+        let lc_password = cleartext.to_lowercase();
+        self.badlist_words
+            .iter()
+            .map(|w| w.as_str())
+            .find(|w| lc_password.contains(w))
     }
 }
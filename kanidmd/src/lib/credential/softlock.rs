@@ -1,3 +1,4 @@
+use crate::be::dbvalue::{DbSoftLockPolicyV1, DbSoftLockV1};
 use std::time::Duration;
 
 /// Represents a temporary denial of the credential to authenticate. This is used
@@ -60,6 +61,30 @@ use std::time::Duration;
 
 const ONEDAY: u64 = 86400;
 
+/// The thresholds applied to password softlocks. These are set by the administrator
+/// in `system_config`, and default to a maximum delay of 10 seconds between attempts
+/// and 100 failures per day before the credential is locked until the day ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoftLockConfig {
+    /// The number of failures in a window after which the credential is locked until
+    /// the window resets.
+    pub max_failures: usize,
+    /// The longest delay in seconds that is applied between failed attempts.
+    pub max_delay: u64,
+    /// The length in seconds of the window in which failures are counted.
+    pub reset_window: u64,
+}
+
+impl Default for SoftLockConfig {
+    fn default() -> Self {
+        SoftLockConfig {
+            max_failures: 100,
+            max_delay: 10,
+            reset_window: ONEDAY,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum CredSoftLockPolicy {
     Password,
@@ -70,24 +95,32 @@ pub enum CredSoftLockPolicy {
 impl CredSoftLockPolicy {
     /// Determine the next lock state after a failure based on this credentials
     /// policy.
-    fn failure_next_state(&self, count: usize, ct: Duration) -> LockState {
+    fn failure_next_state(&self, count: usize, ct: Duration, config: &SoftLockConfig) -> LockState {
         match self {
             CredSoftLockPolicy::Password => {
-                let next_day_end = ct.as_secs() + ONEDAY;
-                let rem = next_day_end % ONEDAY;
-                let reset_at = Duration::from_secs(next_day_end - rem);
+                let window = config.reset_window.max(1);
+                let next_window_end = ct.as_secs() + window;
+                let rem = next_window_end % window;
+                let reset_at = Duration::from_secs(next_window_end - rem);
 
-                if count < 3 {
-                    LockState::Locked(count, reset_at, ct + Duration::from_secs(1))
+                if count >= config.max_failures {
+                    return LockState::Locked(count, reset_at, reset_at);
+                }
+
+                let delay = if count < 3 {
+                    1
                 } else if count < 9 {
-                    LockState::Locked(count, reset_at, ct + Duration::from_secs(3))
+                    3
                 } else if count < 25 {
-                    LockState::Locked(count, reset_at, ct + Duration::from_secs(5))
-                } else if count < 100 {
-                    LockState::Locked(count, reset_at, ct + Duration::from_secs(10))
+                    5
                 } else {
-                    LockState::Locked(count, reset_at, reset_at)
-                }
+                    config.max_delay
+                };
+                LockState::Locked(
+                    count,
+                    reset_at,
+                    ct + Duration::from_secs(delay.min(config.max_delay)),
+                )
             }
             CredSoftLockPolicy::TOTP(step) => {
                 // reset at is based on the next step ending.
@@ -177,25 +210,80 @@ impl CredSoftLock {
     }
 
     /// Document a failure of authentication at this time.
-    pub fn record_failure(&mut self, ct: Duration) {
+    pub fn record_failure(&mut self, ct: Duration, config: &SoftLockConfig) {
         let mut next_state = match self.state {
             LockState::Init => {
-                self.policy.failure_next_state(1, ct)
+                self.policy.failure_next_state(1, ct, config)
                 // LockState::Locked(1, reset_at, unlock_at)
             }
             LockState::Locked(count, _reset_at, _unlock_at) => {
                 // We should never reach this but just in case ...
-                self.policy.failure_next_state(count + 1, ct)
+                self.policy.failure_next_state(count + 1, ct, config)
                 // LockState::Locked(count + 1, reset_at, unlock_at)
             }
             LockState::Unlocked(count, _reset_at) => {
-                self.policy.failure_next_state(count + 1, ct)
+                self.policy.failure_next_state(count + 1, ct, config)
                 // LockState::Locked(count + 1, reset_at, unlock_at)
             }
         };
         std::mem::swap(&mut self.state, &mut next_state);
     }
 
+    /// The number of failures in this cycle, the time the credential may next be
+    /// used if it is locked, and the time the cycle resets. None if there have been
+    /// no failures.
+    pub fn status(&self) -> Option<(usize, Option<Duration>, Duration)> {
+        match self.state {
+            LockState::Init => None,
+            LockState::Locked(count, reset_at, unlock_at) => {
+                Some((count, Some(unlock_at), reset_at))
+            }
+            LockState::Unlocked(count, reset_at) => Some((count, None, reset_at)),
+        }
+    }
+
+    /// Is this softlock past its reset time, and so no longer worth keeping.
+    pub fn is_expired(&self, ct: Duration) -> bool {
+        match self.state {
+            LockState::Init => true,
+            LockState::Locked(_, reset_at, _) | LockState::Unlocked(_, reset_at) => ct > reset_at,
+        }
+    }
+
+    /// Convert the softlock to the form stored in the database so that it can be
+    /// restored when the server restarts.
+    pub fn to_dbsoftlock_v1(&self) -> Option<DbSoftLockV1> {
+        let p = match self.policy {
+            CredSoftLockPolicy::Password => DbSoftLockPolicyV1::Password,
+            CredSoftLockPolicy::TOTP(step) => DbSoftLockPolicyV1::TOTP(step),
+            CredSoftLockPolicy::Webauthn => DbSoftLockPolicyV1::Webauthn,
+        };
+        match self.state {
+            LockState::Init => None,
+            LockState::Locked(c, r, u) => Some(DbSoftLockV1 { p, c, r, u }),
+            // The unlock time has already passed, so any past time restores as unlocked.
+            LockState::Unlocked(c, r) => Some(DbSoftLockV1 {
+                p,
+                c,
+                r,
+                u: Duration::from_secs(0),
+            }),
+        }
+    }
+
+    pub fn from_dbsoftlock_v1(dbs: DbSoftLockV1) -> Self {
+        let policy = match dbs.p {
+            DbSoftLockPolicyV1::Password => CredSoftLockPolicy::Password,
+            DbSoftLockPolicyV1::TOTP(step) => CredSoftLockPolicy::TOTP(step),
+            DbSoftLockPolicyV1::Webauthn => CredSoftLockPolicy::Webauthn,
+        };
+        // An unlock time in the past is moved to unlocked by the next time step.
+        CredSoftLock {
+            state: LockState::Locked(dbs.c, dbs.r, dbs.u),
+            policy,
+        }
+    }
+
     #[cfg(test)]
     pub fn is_state_init(&self) -> bool {
         match self.state {
@@ -236,6 +324,7 @@ mod tests {
         // Check that given the set of inputs, correct decisions about
         // locking are made, and the states can be moved through.
         // ==> Check the init state.
+        let config = SoftLockConfig::default();
         let mut slock = CredSoftLock::new(CredSoftLockPolicy::Password);
         assert!(slock.is_state_init());
         assert!(slock.is_valid());
@@ -243,7 +332,7 @@ mod tests {
         let ct = Duration::from_secs(10);
         // Generate a failure
         // ==> trans to locked
-        slock.record_failure(ct);
+        slock.record_failure(ct, &config);
         assert!(
             slock.peek_state()
                 == &LockState::Locked(1, Duration::from_secs(ONEDAY), Duration::from_secs(10 + 1))
@@ -259,7 +348,7 @@ mod tests {
         assert!(slock.is_valid());
         // Now trigger a failure now, we move back to locked.
         // ==> trans fail unlock -> lock
-        slock.record_failure(ct2);
+        slock.record_failure(ct2, &config);
         assert!(
             slock.peek_state()
                 == &LockState::Locked(2, Duration::from_secs(ONEDAY), Duration::from_secs(10 + 3))
@@ -287,29 +376,30 @@ mod tests {
     #[test]
     fn test_credential_softlock_policy_password() {
         let policy = CredSoftLockPolicy::Password;
+        let config = SoftLockConfig::default();
 
         assert!(
-            policy.failure_next_state(1, Duration::from_secs(0))
+            policy.failure_next_state(1, Duration::from_secs(0), &config)
                 == LockState::Locked(1, Duration::from_secs(ONEDAY), Duration::from_secs(1))
         );
 
         assert!(
-            policy.failure_next_state(8, Duration::from_secs(0))
+            policy.failure_next_state(8, Duration::from_secs(0), &config)
                 == LockState::Locked(8, Duration::from_secs(ONEDAY), Duration::from_secs(3))
         );
 
         assert!(
-            policy.failure_next_state(24, Duration::from_secs(0))
+            policy.failure_next_state(24, Duration::from_secs(0), &config)
                 == LockState::Locked(24, Duration::from_secs(ONEDAY), Duration::from_secs(5))
         );
 
         assert!(
-            policy.failure_next_state(99, Duration::from_secs(0))
+            policy.failure_next_state(99, Duration::from_secs(0), &config)
                 == LockState::Locked(99, Duration::from_secs(ONEDAY), Duration::from_secs(10))
         );
 
         assert!(
-            policy.failure_next_state(100, Duration::from_secs(0))
+            policy.failure_next_state(100, Duration::from_secs(0), &config)
                 == LockState::Locked(
                     100,
                     Duration::from_secs(ONEDAY),
@@ -321,9 +411,10 @@ mod tests {
     #[test]
     fn test_credential_softlock_policy_totp() {
         let policy = CredSoftLockPolicy::TOTP(TOTP_DEFAULT_STEP);
+        let config = SoftLockConfig::default();
 
         assert!(
-            policy.failure_next_state(1, Duration::from_secs(10))
+            policy.failure_next_state(1, Duration::from_secs(10), &config)
                 == LockState::Locked(
                     1,
                     Duration::from_secs(TOTP_DEFAULT_STEP),
@@ -332,7 +423,7 @@ mod tests {
         );
 
         assert!(
-            policy.failure_next_state(2, Duration::from_secs(10))
+            policy.failure_next_state(2, Duration::from_secs(10), &config)
                 == LockState::Locked(
                     2,
                     Duration::from_secs(TOTP_DEFAULT_STEP),
@@ -341,7 +432,7 @@ mod tests {
         );
 
         assert!(
-            policy.failure_next_state(3, Duration::from_secs(10))
+            policy.failure_next_state(3, Duration::from_secs(10), &config)
                 == LockState::Locked(
                     3,
                     Duration::from_secs(TOTP_DEFAULT_STEP),
@@ -353,16 +444,62 @@ mod tests {
     #[test]
     fn test_credential_softlock_policy_webauthn() {
         let policy = CredSoftLockPolicy::Webauthn;
+        let config = SoftLockConfig::default();
 
         assert!(
-            policy.failure_next_state(1, Duration::from_secs(0))
+            policy.failure_next_state(1, Duration::from_secs(0), &config)
                 == LockState::Locked(1, Duration::from_secs(1), Duration::from_secs(1))
         );
 
         // No matter how many failures, webauthn always only delays by 1 second.
         assert!(
-            policy.failure_next_state(1000, Duration::from_secs(0))
+            policy.failure_next_state(1000, Duration::from_secs(0), &config)
                 == LockState::Locked(1000, Duration::from_secs(1), Duration::from_secs(1))
         );
     }
+
+    #[test]
+    fn test_credential_softlock_policy_password_config() {
+        let policy = CredSoftLockPolicy::Password;
+        let config = SoftLockConfig {
+            max_failures: 5,
+            max_delay: 2,
+            reset_window: 3600,
+        };
+
+        // Delays are capped by the configured maximum.
+        assert!(
+            policy.failure_next_state(4, Duration::from_secs(10), &config)
+                == LockState::Locked(4, Duration::from_secs(3600), Duration::from_secs(12))
+        );
+
+        // Past the maximum failures we lock until the end of the window.
+        assert!(
+            policy.failure_next_state(5, Duration::from_secs(10), &config)
+                == LockState::Locked(5, Duration::from_secs(3600), Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn test_credential_softlock_db_roundtrip() {
+        let config = SoftLockConfig::default();
+        let mut slock = CredSoftLock::new(CredSoftLockPolicy::Password);
+        assert!(slock.to_dbsoftlock_v1().is_none());
+
+        let ct = Duration::from_secs(10);
+        slock.record_failure(ct, &config);
+        let dbs = slock.to_dbsoftlock_v1().expect("No softlock state");
+        let mut restored = CredSoftLock::from_dbsoftlock_v1(dbs);
+        assert!(restored.peek_state() == slock.peek_state());
+        assert!(!restored.is_valid());
+
+        // Once unlocked, the restored state is still unlocked.
+        slock.apply_time_step(ct + Duration::from_secs(2));
+        assert!(slock.is_valid());
+        let dbs = slock.to_dbsoftlock_v1().expect("No softlock state");
+        restored = CredSoftLock::from_dbsoftlock_v1(dbs);
+        restored.apply_time_step(ct + Duration::from_secs(2));
+        assert!(restored.peek_state() == slock.peek_state());
+        assert!(restored.status() == Some((1, None, Duration::from_secs(ONEDAY))));
+    }
 }
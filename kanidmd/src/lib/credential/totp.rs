@@ -1,4 +1,5 @@
 use crate::be::dbvalue::{DbTotpAlgoV1, DbTotpV1};
+use kanidm_proto::v1::OperationError;
 use openssl::hash::MessageDigest;
 use openssl::pkey::PKey;
 use openssl::sign::Signer;
@@ -13,6 +14,9 @@ use kanidm_proto::v1::TOTPSecret as ProtoTOTP;
 // This is 64 bits of entropy, as the examples in https://tools.ietf.org/html/rfc6238 show.
 const SECRET_SIZE_BYTES: usize = 8;
 pub const TOTP_DEFAULT_STEP: u64 = 30;
+// Imported secrets must be at least 64 bits, and steps at most ten minutes.
+const IMPORT_MIN_SECRET_BYTES: usize = 8;
+const IMPORT_MAX_STEP: u64 = 600;
 
 #[derive(Debug, PartialEq)]
 pub enum TOTPError {
@@ -57,6 +61,40 @@ impl TOTPAlgo {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TOTPDigits {
+    Six,
+    Eight,
+}
+
+impl TryFrom<u8> for TOTPDigits {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            6 => Ok(TOTPDigits::Six),
+            8 => Ok(TOTPDigits::Eight),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TOTPDigits {
+    fn as_u8(self) -> u8 {
+        match self {
+            TOTPDigits::Six => 6,
+            TOTPDigits::Eight => 8,
+        }
+    }
+
+    fn modulus(self) -> u32 {
+        match self {
+            TOTPDigits::Six => 1_000_000,
+            TOTPDigits::Eight => 100_000_000,
+        }
+    }
+}
+
 /// https://tools.ietf.org/html/rfc6238 which relies on https://tools.ietf.org/html/rfc4226
 #[derive(Debug, Clone)]
 pub struct TOTP {
@@ -64,6 +102,7 @@ pub struct TOTP {
     secret: Vec<u8>,
     pub(crate) step: u64,
     algo: TOTPAlgo,
+    digits: TOTPDigits,
 }
 
 impl TryFrom<DbTotpV1> for TOTP {
@@ -80,6 +119,7 @@ impl TryFrom<DbTotpV1> for TOTP {
             secret: value.k,
             step: value.s,
             algo,
+            digits: TOTPDigits::try_from(value.d)?,
         })
     }
 }
@@ -95,6 +135,7 @@ impl From<ProtoTOTP> for TOTP {
                 ProtoTOTPAlgo::Sha512 => TOTPAlgo::Sha512,
             },
             step: value.step,
+            digits: TOTPDigits::try_from(value.digits).unwrap_or(TOTPDigits::Six),
         }
     }
 }
@@ -106,9 +147,45 @@ impl TOTP {
             secret,
             step,
             algo,
+            digits: TOTPDigits::Six,
         }
     }
 
+    /// Create a token from a secret that was issued by another system, checking that
+    /// it is safe to accept.
+    pub fn from_import(label: String, value: ProtoTOTP) -> Result<Self, OperationError> {
+        if value.secret.len() < IMPORT_MIN_SECRET_BYTES {
+            return Err(OperationError::InvalidAttribute(format!(
+                "totp secret must be at least {} bytes",
+                IMPORT_MIN_SECRET_BYTES
+            )));
+        }
+        if value.step == 0 || value.step > IMPORT_MAX_STEP {
+            return Err(OperationError::InvalidAttribute(format!(
+                "totp step must be between 1 and {} seconds",
+                IMPORT_MAX_STEP
+            )));
+        }
+        let digits = TOTPDigits::try_from(value.digits).map_err(|_| {
+            OperationError::InvalidAttribute("totp digits must be 6 or 8".to_string())
+        })?;
+        Ok(TOTP {
+            label,
+            secret: value.secret,
+            step: value.step,
+            algo: match value.algo {
+                ProtoTOTPAlgo::Sha1 => TOTPAlgo::Sha1,
+                ProtoTOTPAlgo::Sha256 => TOTPAlgo::Sha256,
+                ProtoTOTPAlgo::Sha512 => TOTPAlgo::Sha512,
+            },
+            digits,
+        })
+    }
+
+    pub fn label(&self) -> &str {
+        self.label.as_str()
+    }
+
     // Create a new token with secure key and algo.
     pub fn generate_secure(label: String, step: u64) -> Self {
         let mut rng = rand::thread_rng();
@@ -119,6 +196,7 @@ impl TOTP {
             secret,
             step,
             algo,
+            digits: TOTPDigits::Six,
         }
     }
 
@@ -132,6 +210,7 @@ impl TOTP {
                 TOTPAlgo::Sha256 => DbTotpAlgoV1::S256,
                 TOTPAlgo::Sha512 => DbTotpAlgoV1::S512,
             },
+            d: self.digits.as_u8(),
         }
     }
 
@@ -148,7 +227,7 @@ impl TOTP {
             .map_err(|_| TOTPError::HmacError)?;
 
         let otp = u32::from_be_bytes(bytes);
-        Ok((otp & 0x7fff_ffff) % 1_000_000)
+        Ok((otp & 0x7fff_ffff) % self.digits.modulus())
     }
 
     pub fn do_totp_duration_from_epoch(&self, time: &Duration) -> Result<u32, TOTPError> {
@@ -190,6 +269,7 @@ impl TOTP {
                 TOTPAlgo::Sha256 => ProtoTOTPAlgo::Sha256,
                 TOTPAlgo::Sha512 => ProtoTOTPAlgo::Sha512,
             },
+            digits: self.digits.as_u8(),
         }
     }
 }
@@ -197,6 +277,9 @@ impl TOTP {
 #[cfg(test)]
 mod tests {
     use crate::credential::totp::{TOTPAlgo, TOTPError, TOTP, TOTP_DEFAULT_STEP};
+    use kanidm_proto::v1::TOTPAlgo as ProtoTOTPAlgo;
+    use kanidm_proto::v1::TOTPSecret as ProtoTOTP;
+    use std::convert::TryFrom;
     use std::time::Duration;
 
     #[test]
@@ -273,4 +356,37 @@ mod tests {
             Ok(952181),
         );
     }
+
+    #[test]
+    fn totp_import_eight_digits() {
+        // The 8 digit sha1 vectors from appendix B of rfc6238.
+        let proto = ProtoTOTP {
+            accountname: "william".to_string(),
+            issuer: "blackhats".to_string(),
+            secret: b"12345678901234567890".to_vec(),
+            algo: ProtoTOTPAlgo::Sha1,
+            step: TOTP_DEFAULT_STEP,
+            digits: 8,
+        };
+        let otp = TOTP::from_import("hardware".to_string(), proto.clone()).unwrap();
+        assert!(otp.label() == "hardware");
+        assert!(otp.do_totp_duration_from_epoch(&Duration::from_secs(59)) == Ok(94287082));
+        assert!(otp.do_totp_duration_from_epoch(&Duration::from_secs(1111111109)) == Ok(7081804));
+        assert!(otp.verify(94287082, &Duration::from_secs(59)));
+
+        // Survives a round trip to the db.
+        let otp = TOTP::try_from(otp.to_dbtotpv1()).unwrap();
+        assert!(otp.do_totp_duration_from_epoch(&Duration::from_secs(59)) == Ok(94287082));
+
+        // Unsafe or unsupported parameters are refused.
+        let mut bad = proto.clone();
+        bad.digits = 7;
+        assert!(TOTP::from_import("hardware".to_string(), bad).is_err());
+        let mut bad = proto.clone();
+        bad.secret = vec![0x00, 0xaa, 0xbb, 0xcc];
+        assert!(TOTP::from_import("hardware".to_string(), bad).is_err());
+        let mut bad = proto;
+        bad.step = 0;
+        assert!(TOTP::from_import("hardware".to_string(), bad).is_err());
+    }
 }
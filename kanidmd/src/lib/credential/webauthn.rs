@@ -1,5 +1,21 @@
+use crate::audit::AuditScope;
+use kanidm_proto::v1::OperationError;
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509StoreContext, X509};
+use serde_cbor::Value as CborValue;
+use std::collections::BTreeSet;
+use uuid::Uuid;
+use webauthn_rs::proto::{RegisterPublicKeyCredential, UserVerificationPolicy};
 use webauthn_rs::WebauthnConfig;
 
+// The attested credential data of authData follows the rp id hash (32), the flags (1)
+// and the signature counter (4). https://www.w3.org/TR/webauthn-2/#sctn-authenticator-data
+const AUTHDATA_FLAGS_OFFSET: usize = 32;
+const AUTHDATA_AAGUID_OFFSET: usize = 37;
+const AUTHDATA_AAGUID_LEN: usize = 16;
+const AUTHDATA_FLAG_AT: u8 = 0x40;
+
 pub struct WebauthnDomainConfig {
     pub rp_name: String,
     pub origin: String,
@@ -19,3 +35,204 @@ impl WebauthnConfig for WebauthnDomainConfig {
         self.rp_id.clone()
     }
 }
+
+/// The requirements the system configuration places on webauthn authenticators. Unlike the
+/// domain config these may change at any time, so they are read for each registration
+/// and authentication.
+#[derive(Debug, Default)]
+pub struct WebauthnPolicy {
+    // Require user verification (pin or biometric) at registration and authentication.
+    pub require_verification: bool,
+    // If not empty, registration requires an attestation that chains to one of these.
+    pub attestation_ca: Vec<X509>,
+    // If not empty, registration requires an authenticator model in this set, attested by
+    // a certificate.
+    pub attestation_aaguid: BTreeSet<Uuid>,
+}
+
+impl WebauthnPolicy {
+    pub fn user_verification(&self) -> UserVerificationPolicy {
+        if self.require_verification {
+            UserVerificationPolicy::Required
+        } else {
+            UserVerificationPolicy::Discouraged
+        }
+    }
+
+    /// Check the attestation of a registration that webauthn has already verified against
+    /// the allow lists, returning the aaguid of the authenticator model if a certificate
+    /// attested it.
+    pub fn check_attestation(
+        &self,
+        au: &mut AuditScope,
+        rpkc: &RegisterPublicKeyCredential,
+    ) -> Result<Option<Uuid>, OperationError> {
+        self.check_attestation_object(au, rpkc.response.attestation_object.0.as_slice())
+    }
+
+    fn check_attestation_object(
+        &self,
+        au: &mut AuditScope,
+        data: &[u8],
+    ) -> Result<Option<Uuid>, OperationError> {
+        let (aaguid, x5c) = parse_attestation(data).map_err(|e| {
+            lsecurity!(au, "Unable to parse webauthn attestation -> {}", e);
+            OperationError::Webauthn
+        })?;
+
+        // With self or none attestation, the aaguid is only a claim of the authenticator,
+        // so it can't be trusted or recorded as the model.
+        let aaguid = if x5c.is_empty() { None } else { aaguid };
+
+        if !self.attestation_aaguid.is_empty() {
+            match aaguid {
+                Some(a) if self.attestation_aaguid.contains(&a) => {}
+                _ => {
+                    lsecurity!(au, "Authenticator model {:?} is not allowed", aaguid);
+                    return Err(OperationError::InvalidAttribute(
+                        "authenticator model is not allowed by system policy".to_string(),
+                    ));
+                }
+            }
+        }
+
+        if !self.attestation_ca.is_empty() && !self.chains_to_ca(x5c).unwrap_or(false) {
+            lsecurity!(au, "Authenticator attestation is not from a trusted ca");
+            return Err(OperationError::InvalidAttribute(
+                "authenticator attestation is not trusted by system policy".to_string(),
+            ));
+        }
+
+        Ok(aaguid)
+    }
+
+    fn chains_to_ca(&self, x5c: Vec<X509>) -> Result<bool, openssl::error::ErrorStack> {
+        let mut x5c = x5c.into_iter();
+        // Self or none attestation has no certificate to check.
+        let leaf = match x5c.next() {
+            Some(l) => l,
+            None => return Ok(false),
+        };
+        let mut chain = Stack::new()?;
+        for c in x5c {
+            chain.push(c)?;
+        }
+        let mut store = X509StoreBuilder::new()?;
+        for ca in self.attestation_ca.iter() {
+            store.add_cert(ca.clone())?;
+        }
+        let store = store.build();
+        let mut ctx = X509StoreContext::new()?;
+        ctx.init(&store, &leaf, &chain, |c| c.verify_cert())
+    }
+}
+
+fn cbor_map_get<'a>(map: &'a CborValue, key: &str) -> Option<&'a CborValue> {
+    match map {
+        CborValue::Map(m) => m.get(&CborValue::Text(key.to_string())),
+        _ => None,
+    }
+}
+
+/// Extract the aaguid and attestation certificates from a cbor attestation object.
+fn parse_attestation(data: &[u8]) -> Result<(Option<Uuid>, Vec<X509>), String> {
+    let obj: CborValue = serde_cbor::from_slice(data).map_err(|e| e.to_string())?;
+
+    let aaguid = match cbor_map_get(&obj, "authData") {
+        Some(CborValue::Bytes(auth_data)) => {
+            let at = auth_data
+                .get(AUTHDATA_FLAGS_OFFSET)
+                .map(|flags| flags & AUTHDATA_FLAG_AT != 0)
+                .unwrap_or(false);
+            auth_data
+                .get(AUTHDATA_AAGUID_OFFSET..AUTHDATA_AAGUID_OFFSET + AUTHDATA_AAGUID_LEN)
+                .filter(|_| at)
+                .and_then(|b| Uuid::from_slice(b).ok())
+                // Authenticators that don't attest their model send all zeros.
+                .filter(|u| !u.is_nil())
+        }
+        _ => return Err("attestation object has no authData".to_string()),
+    };
+
+    let x5c = match cbor_map_get(&obj, "attStmt").and_then(|s| cbor_map_get(s, "x5c")) {
+        Some(CborValue::Array(certs)) => certs
+            .iter()
+            .map(|c| match c {
+                CborValue::Bytes(der) => X509::from_der(der).map_err(|e| e.to_string()),
+                _ => Err("invalid x5c certificate".to_string()),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => Vec::new(),
+    };
+
+    Ok((aaguid, x5c))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::AuditScope;
+    use crate::credential::webauthn::{parse_attestation, WebauthnPolicy};
+    use kanidm_proto::v1::OperationError;
+    use serde_cbor::Value as CborValue;
+    use std::collections::BTreeMap;
+    use uuid::Uuid;
+
+    fn attestation_object(flags: u8, aaguid: &Uuid) -> Vec<u8> {
+        let mut auth_data = vec![0; 32];
+        auth_data.push(flags);
+        auth_data.extend_from_slice(&[0, 0, 0, 1]);
+        auth_data.extend_from_slice(aaguid.as_bytes());
+        auth_data.extend_from_slice(&[0, 0]);
+
+        let mut obj = BTreeMap::new();
+        obj.insert(
+            CborValue::Text("fmt".to_string()),
+            CborValue::Text("none".to_string()),
+        );
+        obj.insert(
+            CborValue::Text("attStmt".to_string()),
+            CborValue::Map(BTreeMap::new()),
+        );
+        obj.insert(
+            CborValue::Text("authData".to_string()),
+            CborValue::Bytes(auth_data),
+        );
+        serde_cbor::to_vec(&CborValue::Map(obj)).unwrap()
+    }
+
+    #[test]
+    fn test_webauthn_parse_attestation_aaguid() {
+        let aaguid = Uuid::parse_str("cb69481e-8ff7-4039-93ec-0a2729a154a8").unwrap();
+        let (r, x5c) = parse_attestation(&attestation_object(0x45, &aaguid)).unwrap();
+        assert!(r == Some(aaguid));
+        assert!(x5c.is_empty());
+
+        // No attested credential data, or an unattested model.
+        let (r, _) = parse_attestation(&attestation_object(0x05, &aaguid)).unwrap();
+        assert!(r.is_none());
+        let (r, _) = parse_attestation(&attestation_object(0x45, &Uuid::nil())).unwrap();
+        assert!(r.is_none());
+
+        assert!(parse_attestation(&[0x00]).is_err());
+    }
+
+    #[test]
+    fn test_webauthn_self_attested_aaguid_refused() {
+        let mut au = AuditScope::new("test_webauthn_self_attested", Uuid::new_v4(), None);
+        let aaguid = Uuid::parse_str("cb69481e-8ff7-4039-93ec-0a2729a154a8").unwrap();
+        let data = attestation_object(0x45, &aaguid);
+
+        // Without an allow list the registration is accepted, but the claimed model
+        // isn't recorded.
+        let policy = WebauthnPolicy::default();
+        assert!(policy.check_attestation_object(&mut au, &data) == Ok(None));
+
+        // An allowed model must be attested by a certificate, not only claimed.
+        let mut policy = WebauthnPolicy::default();
+        policy.attestation_aaguid.insert(aaguid);
+        assert!(matches!(
+            policy.check_attestation_object(&mut au, &data),
+            Err(OperationError::InvalidAttribute(_))
+        ));
+    }
+}
@@ -1,6 +1,21 @@
 use crate::config::Configuration;
+use kanidm_proto::jws::JwsValidator;
+use kanidm_proto::oauth2::Jwk;
+use kanidm_proto::v1::OperationError;
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, PointConversionForm};
+use openssl::ecdsa::EcdsaSig;
 use openssl::error::ErrorStack;
-use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::Private;
+use openssl::rand::rand_bytes;
+use openssl::sha::sha256;
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode};
+use openssl::x509::{X509Name, X509Ref};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
 
 pub fn setup_tls(config: &Configuration) -> Result<Option<SslAcceptorBuilder>, ErrorStack> {
     match &config.tls_config {
@@ -9,8 +24,495 @@ pub fn setup_tls(config: &Configuration) -> Result<Option<SslAcceptorBuilder>, E
             ssl_builder.set_certificate_chain_file(&tls_config.chain)?;
             ssl_builder.set_private_key_file(&tls_config.key, SslFiletype::PEM)?;
             ssl_builder.check_private_key()?;
+            if let Some(client_ca) = &tls_config.client_ca {
+                // A client certificate is requested but not required, as most clients
+                // authenticate in other ways. One that is presented must verify.
+                ssl_builder.set_ca_file(client_ca)?;
+                ssl_builder.set_client_ca_list(X509Name::load_client_ca_file(client_ca)?);
+                ssl_builder.set_verify(SslVerifyMode::PEER);
+                // Required for sessions to resume once the peer is verified.
+                ssl_builder.set_session_id_context(b"kanidm")?;
+            }
             Ok(Some(ssl_builder))
         }
         None => Ok(None),
     }
 }
+
+/// A client certificate of a connection, which the TLS layer has verified against the
+/// configured client CA. Accounts are bound to certificates by their fingerprint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// The sha256 fingerprint of the certificate, in lowercase hex.
+    pub fingerprint: String,
+    /// The common name of the subject, for logging.
+    pub subject: Option<String>,
+}
+
+impl ClientCertificate {
+    pub fn from_x509(cert: &X509Ref) -> Result<Self, OperationError> {
+        let fingerprint = cert
+            .digest(MessageDigest::sha256())
+            .map_err(|_| OperationError::CryptographyError)?
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let subject = cert
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|cn| cn.data().as_utf8().ok())
+            .map(|cn| cn.to_string());
+        Ok(ClientCertificate {
+            fingerprint,
+            subject,
+        })
+    }
+}
+
+/// An ES256 (ECDSA P-256 with SHA-256) signer for compact JWS, as used by the
+/// user auth token, oauth2 and openid connect token issuance. The private key is
+/// stored as DER on the domain_info entry.
+#[derive(Clone)]
+pub(crate) struct JwsSigner {
+    key: EcKey<Private>,
+    kid: String,
+}
+
+impl JwsSigner {
+    pub fn generate_es256_der() -> Result<Vec<u8>, OperationError> {
+        EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)
+            .and_then(|group| EcKey::generate(&group))
+            .and_then(|key| key.private_key_to_der())
+            .map_err(|_| OperationError::CryptographyError)
+    }
+
+    pub fn from_es256_der(der: &[u8]) -> Result<Self, OperationError> {
+        let key =
+            EcKey::private_key_from_der(der).map_err(|_| OperationError::CryptographyError)?;
+        key.check_key()
+            .map_err(|_| OperationError::CryptographyError)?;
+
+        // The kid is derived from the public key so that it is stable across restarts.
+        let mut ctx = BigNumContext::new().map_err(|_| OperationError::CryptographyError)?;
+        let pk_bytes = key
+            .public_key()
+            .to_bytes(key.group(), PointConversionForm::UNCOMPRESSED, &mut ctx)
+            .map_err(|_| OperationError::CryptographyError)?;
+        let kid = base64::encode_config(&sha256(&pk_bytes)[..16], base64::URL_SAFE_NO_PAD);
+
+        Ok(JwsSigner { key, kid })
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, OperationError> {
+        let header = serde_json::json!({
+            "alg": "ES256",
+            "typ": "JWT",
+            "kid": self.kid,
+        });
+        let header = serde_json::to_vec(&header).map_err(|_| OperationError::SerdeJsonError)?;
+        let payload = serde_json::to_vec(claims).map_err(|_| OperationError::SerdeJsonError)?;
+
+        let signing_input = format!(
+            "{}.{}",
+            base64::encode_config(&header, base64::URL_SAFE_NO_PAD),
+            base64::encode_config(&payload, base64::URL_SAFE_NO_PAD)
+        );
+
+        let digest = sha256(signing_input.as_bytes());
+        // JWS requires the raw r || s form, not DER.
+        let sig = EcdsaSig::sign(&digest, &self.key)
+            .and_then(|sig| {
+                let mut r = sig.r().to_vec_padded(32)?;
+                let mut s = sig.s().to_vec_padded(32)?;
+                r.append(&mut s);
+                Ok(r)
+            })
+            .map_err(|_| OperationError::CryptographyError)?;
+
+        Ok(format!(
+            "{}.{}",
+            signing_input,
+            base64::encode_config(&sig, base64::URL_SAFE_NO_PAD)
+        ))
+    }
+
+    /// Check a token that was signed by this key, and return the claims. Time related
+    /// claims are not checked, as they differ per token type.
+    pub fn verify<T: DeserializeOwned>(&self, jws: &str) -> Result<T, OperationError> {
+        self.public_key_as_jwk()
+            .and_then(|jwk| {
+                JwsValidator::from_jwk(&jwk).map_err(|_| OperationError::CryptographyError)
+            })
+            .and_then(|validator| {
+                validator
+                    .validate(jws)
+                    .map_err(|_| OperationError::InvalidRequestState)
+            })
+    }
+
+    pub fn public_key_as_jwk(&self) -> Result<Jwk, OperationError> {
+        let mut ctx = BigNumContext::new().map_err(|_| OperationError::CryptographyError)?;
+        let mut x = BigNum::new().map_err(|_| OperationError::CryptographyError)?;
+        let mut y = BigNum::new().map_err(|_| OperationError::CryptographyError)?;
+        self.key
+            .public_key()
+            .affine_coordinates_gfp(self.key.group(), &mut x, &mut y, &mut ctx)
+            .map_err(|_| OperationError::CryptographyError)?;
+
+        let x = x
+            .to_vec_padded(32)
+            .map_err(|_| OperationError::CryptographyError)?;
+        let y = y
+            .to_vec_padded(32)
+            .map_err(|_| OperationError::CryptographyError)?;
+
+        Ok(Jwk {
+            kty: "EC".to_string(),
+            crv: "P-256".to_string(),
+            x: base64::encode_config(&x, base64::URL_SAFE_NO_PAD),
+            y: base64::encode_config(&y, base64::URL_SAFE_NO_PAD),
+            alg: "ES256".to_string(),
+            use_: "sig".to_string(),
+            kid: self.kid.clone(),
+        })
+    }
+}
+
+const SSH_CA_KEY_TYPE: &str = "ecdsa-sha2-nistp256";
+const SSH_CERT_SUFFIX: &str = "-cert-v01@openssh.com";
+const SSH_CERT_TYPE_USER: u32 = 1;
+// The permissions that ssh-keygen gives user certificates, in the order the protocol requires.
+const SSH_CERT_EXTENSIONS: [&str; 5] = [
+    "permit-X11-forwarding",
+    "permit-agent-forwarding",
+    "permit-port-forwarding",
+    "permit-pty",
+    "permit-user-rc",
+];
+
+fn ssh_put_string(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn ssh_put_mpint(buf: &mut Vec<u8>, data: &[u8]) {
+    let start = data.iter().position(|b| *b != 0).unwrap_or(data.len());
+    let data = &data[start..];
+    // A set high bit would make the integer negative.
+    if data.first().map(|b| b & 0x80 != 0).unwrap_or(false) {
+        buf.extend_from_slice(&(data.len() as u32 + 1).to_be_bytes());
+        buf.push(0);
+        buf.extend_from_slice(data);
+    } else {
+        ssh_put_string(buf, data);
+    }
+}
+
+fn ssh_get_string(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (len, rest) = data.split_at(4);
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    if rest.len() < len {
+        None
+    } else {
+        Some(rest.split_at(len))
+    }
+}
+
+/// Decode an OpenSSH public key, as found in authorized_keys, into its type and wire
+/// format blob. The comment is ignored.
+pub(crate) fn ssh_public_key_blob(publickey: &str) -> Option<(String, Vec<u8>)> {
+    let mut parts = publickey.split_whitespace();
+    let key_type = parts.next()?;
+    let blob = parts.next().and_then(|b| base64::decode(b).ok())?;
+    // The blob repeats the type, which must agree.
+    match ssh_get_string(&blob) {
+        Some((t, _)) if t == key_type.as_bytes() => Some((key_type.to_string(), blob)),
+        _ => None,
+    }
+}
+
+/// The certificate authority that signs OpenSSH user certificates. Like the token signer
+/// it's an ECDSA P-256 key, stored as DER on the domain_info entry.
+pub(crate) struct SshCaSigner {
+    key: EcKey<Private>,
+}
+
+impl SshCaSigner {
+    pub fn generate_der() -> Result<Vec<u8>, OperationError> {
+        JwsSigner::generate_es256_der()
+    }
+
+    pub fn from_der(der: &[u8]) -> Result<Self, OperationError> {
+        let key =
+            EcKey::private_key_from_der(der).map_err(|_| OperationError::CryptographyError)?;
+        key.check_key()
+            .map_err(|_| OperationError::CryptographyError)?;
+        Ok(SshCaSigner { key })
+    }
+
+    fn public_key_blob(&self) -> Result<Vec<u8>, OperationError> {
+        let mut ctx = BigNumContext::new().map_err(|_| OperationError::CryptographyError)?;
+        let point = self
+            .key
+            .public_key()
+            .to_bytes(
+                self.key.group(),
+                PointConversionForm::UNCOMPRESSED,
+                &mut ctx,
+            )
+            .map_err(|_| OperationError::CryptographyError)?;
+
+        let mut blob = Vec::new();
+        ssh_put_string(&mut blob, SSH_CA_KEY_TYPE.as_bytes());
+        ssh_put_string(&mut blob, b"nistp256");
+        ssh_put_string(&mut blob, &point);
+        Ok(blob)
+    }
+
+    /// The public key in authorized_keys format, for TrustedUserCAKeys.
+    pub fn public_key_openssh(&self) -> Result<String, OperationError> {
+        self.public_key_blob()
+            .map(|blob| format!("{} {} kanidm", SSH_CA_KEY_TYPE, base64::encode(&blob)))
+    }
+
+    /// Sign a user certificate for the public key. The certificate is returned in the
+    /// format of a -cert.pub file, with the key id as the comment.
+    pub fn sign_user_key(
+        &self,
+        publickey: &str,
+        key_id: &str,
+        principals: &[String],
+        valid_after: Duration,
+        valid_before: Duration,
+    ) -> Result<String, OperationError> {
+        let (key_type, key_blob) = ssh_public_key_blob(publickey)
+            .filter(|(key_type, _)| !key_type.ends_with(SSH_CERT_SUFFIX))
+            .ok_or_else(|| OperationError::InvalidAttribute("ssh_publickey".to_string()))?;
+        // The public fields follow the type, and are the same in the certificate.
+        let key_fields = ssh_get_string(&key_blob)
+            .map(|(_, fields)| fields)
+            .ok_or(OperationError::InvalidState)?;
+        let cert_type = format!("{}{}", key_type, SSH_CERT_SUFFIX);
+
+        let mut nonce = [0; 32];
+        let mut serial = [0; 8];
+        rand_bytes(&mut nonce)
+            .and_then(|_| rand_bytes(&mut serial))
+            .map_err(|_| OperationError::CryptographyError)?;
+
+        let mut packed_principals = Vec::new();
+        principals
+            .iter()
+            .for_each(|p| ssh_put_string(&mut packed_principals, p.as_bytes()));
+        let mut extensions = Vec::new();
+        SSH_CERT_EXTENSIONS.iter().for_each(|ext| {
+            ssh_put_string(&mut extensions, ext.as_bytes());
+            ssh_put_string(&mut extensions, &[]);
+        });
+
+        let mut cert = Vec::new();
+        ssh_put_string(&mut cert, cert_type.as_bytes());
+        ssh_put_string(&mut cert, &nonce);
+        cert.extend_from_slice(key_fields);
+        cert.extend_from_slice(&serial);
+        cert.extend_from_slice(&SSH_CERT_TYPE_USER.to_be_bytes());
+        ssh_put_string(&mut cert, key_id.as_bytes());
+        ssh_put_string(&mut cert, &packed_principals);
+        cert.extend_from_slice(&valid_after.as_secs().to_be_bytes());
+        cert.extend_from_slice(&valid_before.as_secs().to_be_bytes());
+        // No critical options, and the reserved field is empty.
+        ssh_put_string(&mut cert, &[]);
+        ssh_put_string(&mut cert, &extensions);
+        ssh_put_string(&mut cert, &[]);
+        ssh_put_string(&mut cert, &self.public_key_blob()?);
+
+        let digest = sha256(&cert);
+        let sig =
+            EcdsaSig::sign(&digest, &self.key).map_err(|_| OperationError::CryptographyError)?;
+        let mut sig_rs = Vec::new();
+        ssh_put_mpint(&mut sig_rs, &sig.r().to_vec());
+        ssh_put_mpint(&mut sig_rs, &sig.s().to_vec());
+        let mut sig_blob = Vec::new();
+        ssh_put_string(&mut sig_blob, SSH_CA_KEY_TYPE.as_bytes());
+        ssh_put_string(&mut sig_blob, &sig_rs);
+        ssh_put_string(&mut cert, &sig_blob);
+
+        Ok(format!(
+            "{} {} {}",
+            cert_type,
+            base64::encode(&cert),
+            key_id
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::{ssh_get_string, ClientCertificate, JwsSigner, SshCaSigner};
+    use kanidm_proto::jws::JwsValidator;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::ecdsa::EcdsaSig;
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::sha::sha256;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+    use std::time::Duration;
+
+    #[test]
+    fn test_client_certificate_from_x509() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let pkey = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "host.example.com")
+            .unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let cert = builder.build();
+
+        let cc = ClientCertificate::from_x509(&cert).expect("failed to read certificate");
+        assert!(cc.subject.as_deref() == Some("host.example.com"));
+        assert!(cc.fingerprint.len() == 64);
+        assert!(cc.fingerprint == cc.fingerprint.to_lowercase());
+        // The fingerprint is of the der certificate.
+        let der = cert.to_der().unwrap();
+        let expect: String = sha256(&der).iter().map(|b| format!("{:02x}", b)).collect();
+        assert!(cc.fingerprint == expect);
+    }
+
+    #[test]
+    fn test_jws_es256_sign_verify() {
+        let der = JwsSigner::generate_es256_der().expect("failed to generate key");
+        let signer = JwsSigner::from_es256_der(&der).expect("failed to load key");
+
+        let jws = signer
+            .sign(&serde_json::json!({ "sub": "test" }))
+            .expect("failed to sign");
+        let parts: Vec<&str> = jws.split('.').collect();
+        assert!(parts.len() == 3);
+
+        // Rebuild the public key from the jwk, and check the signature.
+        let jwk = signer.public_key_as_jwk().expect("failed to get jwk");
+        let x = base64::decode_config(&jwk.x, base64::URL_SAFE_NO_PAD).unwrap();
+        let y = base64::decode_config(&jwk.y, base64::URL_SAFE_NO_PAD).unwrap();
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let pkey = EcKey::from_public_key_affine_coordinates(
+            &group,
+            &BigNum::from_slice(&x).unwrap(),
+            &BigNum::from_slice(&y).unwrap(),
+        )
+        .unwrap();
+
+        let sig = base64::decode_config(parts[2], base64::URL_SAFE_NO_PAD).unwrap();
+        assert!(sig.len() == 64);
+        let sig = EcdsaSig::from_private_components(
+            BigNum::from_slice(&sig[..32]).unwrap(),
+            BigNum::from_slice(&sig[32..]).unwrap(),
+        )
+        .unwrap();
+
+        let digest = sha256(format!("{}.{}", parts[0], parts[1]).as_bytes());
+        assert!(sig.verify(&digest, &pkey).unwrap());
+
+        // The kid must be stable for the same key.
+        let signer2 = JwsSigner::from_es256_der(&der).expect("failed to load key");
+        assert!(signer2.public_key_as_jwk().unwrap() == jwk);
+
+        // And the client side validator must accept it.
+        let validator = JwsValidator::from_jwk(&jwk).expect("failed to build validator");
+        let claims: serde_json::Value = validator.validate(&jws).expect("failed to validate");
+        assert!(claims["sub"] == "test");
+
+        // The signer can check its own tokens, but not those of another key.
+        let claims: serde_json::Value = signer.verify(&jws).expect("failed to verify");
+        assert!(claims["sub"] == "test");
+        let other = JwsSigner::generate_es256_der()
+            .and_then(|der| JwsSigner::from_es256_der(&der))
+            .expect("failed to load key");
+        assert!(other.verify::<serde_json::Value>(&jws).is_err());
+    }
+
+    #[test]
+    fn test_ssh_ca_sign_user_key() {
+        let der = SshCaSigner::generate_der().expect("failed to generate key");
+        let signer = SshCaSigner::from_der(&der).expect("failed to load key");
+        let ca = signer
+            .public_key_openssh()
+            .expect("failed to get public key");
+        assert!(ca.starts_with("ecdsa-sha2-nistp256 "));
+
+        let user_key = concat!(
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAeGW1P6Pc2rPq0XqbRaDKBcXZUPRklo0L1EyR30CwoP",
+            " william@amethyst"
+        );
+        let principals = vec!["william".to_string(), "william@example.com".to_string()];
+        let cert = signer
+            .sign_user_key(
+                user_key,
+                "william@example.com",
+                &principals,
+                Duration::from_secs(100),
+                Duration::from_secs(200),
+            )
+            .expect("failed to sign");
+
+        let parsed = sshkeys::Certificate::from_string(&cert).expect("invalid certificate");
+        assert!(parsed.key_id == "william@example.com");
+        assert!(parsed.valid_principals == principals);
+        assert!(parsed.valid_after == 100);
+        assert!(parsed.valid_before == 200);
+
+        // The signature is the last field, over everything before it.
+        let blob = base64::decode(cert.split(' ').nth(1).unwrap()).unwrap();
+        let ca_blob = base64::decode(ca.split(' ').nth(1).unwrap()).unwrap();
+        let sig_pos = blob
+            .windows(ca_blob.len())
+            .position(|w| w == ca_blob.as_slice())
+            .unwrap()
+            + ca_blob.len();
+        let (signed, sig_blob) = blob.split_at(sig_pos);
+        let (sig_blob, rest) = ssh_get_string(sig_blob).unwrap();
+        assert!(rest.is_empty());
+        let (sig_type, sig_rs) = ssh_get_string(sig_blob).unwrap();
+        assert!(sig_type == b"ecdsa-sha2-nistp256");
+        let (sig_rs, _) = ssh_get_string(sig_rs).unwrap();
+        let (r, sig_rs) = ssh_get_string(sig_rs).unwrap();
+        let (s, _) = ssh_get_string(sig_rs).unwrap();
+        let sig = EcdsaSig::from_private_components(
+            BigNum::from_slice(r).unwrap(),
+            BigNum::from_slice(s).unwrap(),
+        )
+        .unwrap();
+        let key = EcKey::private_key_from_der(&der).unwrap();
+        assert!(sig.verify(&sha256(signed), &key).unwrap());
+
+        // A certificate can't be signed again.
+        assert!(signer
+            .sign_user_key(
+                &cert,
+                "x",
+                &principals,
+                Duration::from_secs(100),
+                Duration::from_secs(200)
+            )
+            .is_err());
+    }
+}
@@ -36,7 +36,7 @@ use crate::schema::{SchemaAttribute, SchemaClass, SchemaTransaction};
 use crate::server::{
     QueryServerReadTransaction, QueryServerTransaction, QueryServerWriteTransaction,
 };
-use crate::value::{IndexType, SyntaxType};
+use crate::value::{ApiTokenMeta, IndexType, SyntaxType};
 use crate::value::{PartialValue, Value};
 use kanidm_proto::v1::Entry as ProtoEntry;
 use kanidm_proto::v1::Filter as ProtoFilter;
@@ -1588,6 +1588,11 @@ impl<VALID, STATE> Entry<VALID, STATE> {
             .and_then(|a| a.get_radius_secret())
     }
 
+    #[inline(always)]
+    pub fn get_ava_single_uuid(&self, attr: &str) -> Option<&Uuid> {
+        self.get_ava_single(attr).and_then(|a| a.to_uuid())
+    }
+
     #[inline(always)]
     pub fn get_ava_single_datetime(&self, attr: &str) -> Option<OffsetDateTime> {
         self.get_ava_single(attr).and_then(|a| a.to_datetime())
@@ -1598,6 +1603,43 @@ impl<VALID, STATE> Entry<VALID, STATE> {
         self.get_ava_single(attr).and_then(|v| v.to_str())
     }
 
+    #[inline(always)]
+    pub fn get_ava_single_private_binary(&self, attr: &str) -> Option<&[u8]> {
+        self.get_ava_single(attr)
+            .and_then(|v| v.to_private_binary())
+            .map(|b| b.as_slice())
+    }
+
+    #[inline(always)]
+    pub fn get_ava_as_oauthscopemaps(
+        &self,
+        attr: &str,
+    ) -> Option<impl Iterator<Item = (&Uuid, &BTreeSet<String>)>> {
+        self.attrs
+            .get(attr)
+            .map(|vs| vs.iter().filter_map(|v| v.to_oauthscopemap()))
+    }
+
+    #[inline(always)]
+    pub fn get_ava_as_credentials(
+        &self,
+        attr: &str,
+    ) -> Option<impl Iterator<Item = (&str, &Credential)>> {
+        self.attrs
+            .get(attr)
+            .map(|vs| vs.iter().filter_map(|v| v.to_tagged_credential()))
+    }
+
+    #[inline(always)]
+    pub fn get_ava_as_apitokens(
+        &self,
+        attr: &str,
+    ) -> Option<impl Iterator<Item = (&Uuid, &ApiTokenMeta)>> {
+        self.attrs
+            .get(attr)
+            .map(|vs| vs.iter().filter_map(|v| v.to_apitoken()))
+    }
+
     #[inline(always)]
     pub fn get_ava_single_protofilter(&self, attr: &str) -> Option<&ProtoFilter> {
         self.get_ava_single(attr)
@@ -1,7 +1,9 @@
 use crate::audit::AuditScope;
+use crate::crypto::ClientCertificate;
 use crate::entry::{Entry, EntryCommitted, EntryInit, EntryNew, EntryReduced, EntrySealed};
 use crate::filter::{Filter, FilterInvalid, FilterValid};
 use crate::idm::AuthState;
+use crate::ldap::LdapBoundToken;
 use crate::schema::SchemaTransaction;
 use crate::value::PartialValue;
 use kanidm_proto::v1::Entry as ProtoEntry;
@@ -14,6 +16,7 @@ use crate::modify::{ModifyInvalid, ModifyList, ModifyValid};
 use crate::server::{
     QueryServerReadTransaction, QueryServerTransaction, QueryServerWriteTransaction,
 };
+use crate::trust::spn_realm;
 use kanidm_proto::v1::OperationError;
 
 use crate::actors::v1_read::{
@@ -26,8 +29,11 @@ use crate::actors::v1_write::{CreateMessage, DeleteMessage, ModifyMessage};
 use ldap3_server::simple::LdapFilter;
 use smartstring::alias::String as AttrString;
 use std::collections::BTreeSet;
+use std::net::IpAddr;
 use std::time::Duration;
 use uuid::Uuid;
+#[cfg(test)]
+use webauthn_rs::proto::PublicKeyCredential;
 
 #[derive(Debug)]
 pub struct SearchResult {
@@ -119,6 +125,18 @@ impl EventLimits {
         }
     }
 
+    // The limits that our own tokens carry, for identities that didn't get their limits
+    // from one of our tokens. The limits in a token from a trusted domain were set by that
+    // domain, and LDAP binds don't have a token at all.
+    pub fn account_default() -> Self {
+        EventLimits {
+            unindexed_allow: false,
+            search_max_results: 128,
+            search_max_filter_test: 256,
+            filter_max_elements: 32,
+        }
+    }
+
     // From a userauthtoken
     pub fn from_uat(uat: &UserAuthToken) -> Self {
         EventLimits {
@@ -155,6 +173,62 @@ impl std::fmt::Display for Event {
     }
 }
 
+// If this uat is a service account api token, check it has not been revoked. The
+// signature and expiry of the token were already checked when it was received.
+pub(crate) fn check_uat_api_token(
+    audit: &mut AuditScope,
+    e: &Entry<EntrySealed, EntryCommitted>,
+    uat: &UserAuthToken,
+) -> Result<(), OperationError> {
+    match &uat.api_token_id {
+        Some(tid) => {
+            let pv = PartialValue::new_apitoken_s(tid).ok_or_else(|| {
+                ladmin_error!(audit, "invalid uat api token id");
+                OperationError::InvalidUuid
+            })?;
+            if e.attribute_value_pres("api_token_session", &pv) {
+                Ok(())
+            } else {
+                lsecurity!(audit, "api token {} has been revoked", tid);
+                Err(OperationError::NotAuthenticated)
+            }
+        }
+        None => Ok(()),
+    }
+}
+
+// Tokens issued by a trusted domain are only valid for the foreign principal that shares the
+// uuid and spn of the remote account. Our own tokens always carry an spn in our domain, so
+// a remote token can not be used to act as a local entry. Returns the limits to apply.
+pub(crate) fn check_uat_trust(
+    audit: &mut AuditScope,
+    e: &Entry<EntrySealed, EntryCommitted>,
+    uat: &UserAuthToken,
+) -> Result<EventLimits, OperationError> {
+    let e_spn = e
+        .get_ava_single("spn")
+        .map(|v| v.to_proto_string_clone())
+        .unwrap_or_default();
+
+    if e.attribute_value_pres("class", &PartialValue::new_class("foreign_principal")) {
+        if e_spn == uat.spn {
+            return Ok(EventLimits::account_default());
+        }
+    } else if spn_realm(uat.spn.as_str()).is_some()
+        && spn_realm(uat.spn.as_str()) == spn_realm(e_spn.as_str())
+    {
+        return Ok(EventLimits::from_uat(uat));
+    }
+
+    lsecurity!(
+        audit,
+        "uat for {} does not match the entry {}",
+        uat.spn,
+        e.get_uuid2spn().to_proto_string_clone()
+    );
+    Err(OperationError::NotAuthenticated)
+}
+
 impl Event {
     pub fn from_ro_uat(
         audit: &mut AuditScope,
@@ -172,13 +246,13 @@ impl Event {
             ladmin_error!(audit, "from_ro_uat failed {:?}", e);
             e
         })?;
+        check_uat_api_token(audit, &e, uat)?;
+        let limits = check_uat_trust(audit, &e, uat)?;
         // TODO #64: Now apply claims from the uat into the Entry
         // to allow filtering.
 
         // TODO #59: If the account is expiredy, do not allow the event
         // to proceed
-
-        let limits = EventLimits::from_uat(uat);
         Ok(Event {
             origin: EventOrigin::User(e),
             limits,
@@ -192,6 +266,10 @@ impl Event {
     ) -> Result<Self, OperationError> {
         ltrace!(audit, "from_rw_uat -> {:?}", uat);
         let uat = uat.ok_or(OperationError::NotAuthenticated)?;
+        if uat.read_only {
+            lsecurity!(audit, "from_rw_uat denied, uat is read only");
+            return Err(OperationError::AccessDenied);
+        }
         let u = Uuid::parse_str(uat.uuid.as_str()).map_err(|_| {
             ladmin_error!(audit, "from_rw_uat invalid uat uuid");
             OperationError::InvalidUuid
@@ -201,19 +279,53 @@ impl Event {
             ladmin_error!(audit, "from_rw_uat failed {:?}", e);
             e
         })?;
+        check_uat_api_token(audit, &e, uat)?;
+        let limits = check_uat_trust(audit, &e, uat)?;
         // TODO #64: Now apply claims from the uat into the Entry
         // to allow filtering.
 
         // TODO #59: If the account is expiredy, do not allow the event
         // to proceed
-
-        let limits = EventLimits::from_uat(uat);
         Ok(Event {
             origin: EventOrigin::User(e),
             limits,
         })
     }
 
+    /// The account an LDAP connection is bound as. LDAP searches are made with the
+    /// anonymous effective uat of the connection, but writes are made as the bound account.
+    pub fn from_rw_ldap(
+        audit: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        lbt: &LdapBoundToken,
+    ) -> Result<Self, OperationError> {
+        ltrace!(audit, "from_rw_ldap -> {:?}", lbt.spn);
+        if !lbt.policy_met {
+            lsecurity!(
+                audit,
+                "from_rw_ldap denied, the bind of {} did not meet its credential policy",
+                lbt.spn
+            );
+            return Err(OperationError::AccessDenied);
+        }
+        if lbt.via_app_password {
+            lsecurity!(
+                audit,
+                "from_rw_ldap denied, {} is bound with an app password",
+                lbt.spn
+            );
+            return Err(OperationError::AccessDenied);
+        }
+        let e = qs.internal_search_uuid(audit, &lbt.uuid).map_err(|e| {
+            ladmin_error!(audit, "from_rw_ldap failed {:?}", e);
+            e
+        })?;
+        Ok(Event {
+            origin: EventOrigin::User(e),
+            limits: EventLimits::account_default(),
+        })
+    }
+
     pub fn from_internal() -> Self {
         Event {
             origin: EventOrigin::Internal,
@@ -547,6 +659,19 @@ impl CreateEvent {
         }
     }
 
+    pub fn from_ldap_parts(
+        audit: &mut AuditScope,
+        lbt: &LdapBoundToken,
+        pe: &ProtoEntry,
+        qs: &QueryServerWriteTransaction,
+    ) -> Result<Self, OperationError> {
+        let entry = Entry::from_proto_entry(audit, pe, qs)?;
+        Ok(CreateEvent {
+            event: Event::from_rw_ldap(audit, qs, lbt)?,
+            entries: vec![entry],
+        })
+    }
+
     // Is this an internal only function?
     #[cfg(test)]
     pub unsafe fn new_impersonate_entry_ser(
@@ -646,6 +771,24 @@ impl DeleteEvent {
         })
     }
 
+    pub fn from_ldap_parts(
+        audit: &mut AuditScope,
+        lbt: &LdapBoundToken,
+        target_uuid: Uuid,
+        qs: &QueryServerWriteTransaction,
+    ) -> Result<Self, OperationError> {
+        let event = Event::from_rw_ldap(audit, qs, lbt)?;
+        let filter_orig = filter_all!(f_eq("uuid", PartialValue::new_uuid(target_uuid)))
+            .validate(qs.get_schema())
+            .map_err(OperationError::SchemaViolation)?;
+        let filter = filter_orig.clone().into_ignore_hidden();
+        Ok(DeleteEvent {
+            event,
+            filter,
+            filter_orig,
+        })
+    }
+
     #[cfg(test)]
     pub unsafe fn new_impersonate_entry(
         e: Entry<EntrySealed, EntryCommitted>,
@@ -749,6 +892,32 @@ impl ModifyEvent {
         })
     }
 
+    pub fn from_ldap_parts(
+        audit: &mut AuditScope,
+        lbt: &LdapBoundToken,
+        target_uuid: Uuid,
+        proto_ml: &ProtoModifyList,
+        qs: &QueryServerWriteTransaction,
+    ) -> Result<Self, OperationError> {
+        let f = filter_all!(f_eq("uuid", PartialValue::new_uuid(target_uuid)));
+        let m = ModifyList::from(audit, &proto_ml, qs)?;
+        let event = Event::from_rw_ldap(audit, qs, lbt)?;
+        let filter_orig = f
+            .validate(qs.get_schema())
+            .map_err(OperationError::SchemaViolation)?;
+        let filter = filter_orig.clone().into_ignore_hidden();
+        let modlist = m
+            .validate(qs.get_schema())
+            .map_err(OperationError::SchemaViolation)?;
+
+        Ok(ModifyEvent {
+            event,
+            filter,
+            filter_orig,
+            modlist,
+        })
+    }
+
     pub fn from_internal_parts(
         audit: &mut AuditScope,
         uat: Option<&UserAuthToken>,
@@ -893,6 +1062,7 @@ pub struct AuthEventStepMech {
 #[derive(Debug)]
 pub enum AuthEventStep {
     Init(AuthEventStepInit),
+    InitDiscoverable,
     Begin(AuthEventStepMech),
     Cred(AuthEventStepCred),
 }
@@ -909,6 +1079,15 @@ impl AuthEventStep {
                     Ok(AuthEventStep::Init(AuthEventStepInit { name, appid: None }))
                 }
             }
+            AuthStep::InitDiscoverable => {
+                if sid.is_some() {
+                    Err(OperationError::InvalidAuthState(
+                        "session id present in init".to_string(),
+                    ))
+                } else {
+                    Ok(AuthEventStep::InitDiscoverable)
+                }
+            }
             AuthStep::Begin(mech) => match sid {
                 Some(ssid) => Ok(AuthEventStep::Begin(AuthEventStepMech {
                     sessionid: ssid,
@@ -966,12 +1145,40 @@ impl AuthEventStep {
             cred: AuthCredential::Password(pw.to_string()),
         })
     }
+
+    #[cfg(test)]
+    pub fn cred_step_password_change(sid: Uuid, pw: &str) -> Self {
+        AuthEventStep::Cred(AuthEventStepCred {
+            sessionid: sid,
+            cred: AuthCredential::PasswordChange(pw.to_string()),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn cred_step_webauthn(sid: Uuid, resp: PublicKeyCredential) -> Self {
+        AuthEventStep::Cred(AuthEventStepCred {
+            sessionid: sid,
+            cred: AuthCredential::Webauthn(resp),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn cred_step_client_certificate(sid: Uuid) -> Self {
+        AuthEventStep::Cred(AuthEventStepCred {
+            sessionid: sid,
+            cred: AuthCredential::ClientCertificate,
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct AuthEvent {
     pub event: Option<Event>,
     pub step: AuthEventStep,
+    // The address of the client, which authentication failures are counted against.
+    pub source: Option<IpAddr>,
+    // The verified client certificate of the connection, if any.
+    pub client_cert: Option<ClientCertificate>,
     // pub sessionid: Option<Uuid>,
 }
 
@@ -980,6 +1187,8 @@ impl AuthEvent {
         Ok(AuthEvent {
             event: None,
             step: AuthEventStep::from_authstep(msg.req.step, msg.sessionid)?,
+            source: msg.source,
+            client_cert: msg.client_cert,
         })
     }
 
@@ -988,6 +1197,8 @@ impl AuthEvent {
         AuthEvent {
             event: None,
             step: AuthEventStep::anonymous_init(),
+            source: None,
+            client_cert: None,
         }
     }
 
@@ -996,6 +1207,18 @@ impl AuthEvent {
         AuthEvent {
             event: None,
             step: AuthEventStep::named_init(name),
+            source: None,
+            client_cert: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn discoverable_init() -> Self {
+        AuthEvent {
+            event: None,
+            step: AuthEventStep::InitDiscoverable,
+            source: None,
+            client_cert: None,
         }
     }
 
@@ -1004,6 +1227,8 @@ impl AuthEvent {
         AuthEvent {
             event: None,
             step: AuthEventStep::begin_mech(sessionid, mech),
+            source: None,
+            client_cert: None,
         }
     }
 
@@ -1012,6 +1237,8 @@ impl AuthEvent {
         AuthEvent {
             event: None,
             step: AuthEventStep::cred_step_anonymous(sid),
+            source: None,
+            client_cert: None,
         }
     }
 
@@ -1020,8 +1247,52 @@ impl AuthEvent {
         AuthEvent {
             event: None,
             step: AuthEventStep::cred_step_password(sid, pw),
+            source: None,
+            client_cert: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn cred_step_password_change(sid: Uuid, pw: &str) -> Self {
+        AuthEvent {
+            event: None,
+            step: AuthEventStep::cred_step_password_change(sid, pw),
+            source: None,
+            client_cert: None,
         }
     }
+
+    #[cfg(test)]
+    pub fn cred_step_webauthn(sid: Uuid, resp: PublicKeyCredential) -> Self {
+        AuthEvent {
+            event: None,
+            step: AuthEventStep::cred_step_webauthn(sid, resp),
+            source: None,
+            client_cert: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn cred_step_client_certificate(sid: Uuid) -> Self {
+        AuthEvent {
+            event: None,
+            step: AuthEventStep::cred_step_client_certificate(sid),
+            source: None,
+            client_cert: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_source(mut self, source: IpAddr) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_client_certificate(mut self, client_cert: ClientCertificate) -> Self {
+        self.client_cert = Some(client_cert);
+        self
+    }
 }
 
 // Probably should be a struct with the session id present.
@@ -1,25 +1,34 @@
 use crate::entry::{Entry, EntryCommitted, EntrySealed};
 use kanidm_proto::v1::OperationError;
 
-use kanidm_proto::v1::UserAuthToken;
+use kanidm_proto::jws::TokenPurpose;
+use kanidm_proto::v1::AppPassword as ProtoAppPassword;
+use kanidm_proto::v1::{AppPasswordScope, UserAuthToken};
 
 use crate::audit::AuditScope;
-use crate::constants::UUID_ANONYMOUS;
-use crate::credential::policy::CryptoPolicy;
+use crate::constants::{APP_PASSWORD_MAX, UAT_EXPIRY, UUID_ANONYMOUS};
+use crate::credential::backupcode::BackupCodes;
+use crate::credential::policy::{CredentialStrength, CryptoPolicy};
 use crate::credential::totp::TOTP;
 use crate::credential::{softlock::CredSoftLockPolicy, Credential};
+use crate::crypto::ClientCertificate;
 use crate::idm::claim::Claim;
 use crate::idm::group::Group;
-use crate::modify::{ModifyInvalid, ModifyList};
+use crate::modify::{Modify, ModifyInvalid, ModifyList};
 use crate::server::{QueryServerReadTransaction, QueryServerWriteTransaction};
 use crate::value::{PartialValue, Value};
 
+use std::collections::BTreeMap;
 use std::time::Duration;
 use time::OffsetDateTime;
 use uuid::Uuid;
 use webauthn_rs::proto::Credential as WebauthnCredential;
 use webauthn_rs::proto::{Counter, CredentialID};
 
+// How long after a recorded login another login of the same mechanism is recorded, so
+// that frequent logins don't each write to the account.
+const LAST_LOGIN_GRANULARITY: i64 = 300;
+
 lazy_static! {
     static ref PVCLASS_ACCOUNT: PartialValue = PartialValue::new_class("account");
 }
@@ -52,6 +61,11 @@ macro_rules! try_from_entry {
             .get_ava_single_credential("primary_credential")
             .map(|v| v.clone());
 
+        let app_passwords = $value
+            .get_ava_as_credentials("app_password")
+            .map(|i| i.map(|(t, c)| (t.to_string(), c.clone())).collect())
+            .unwrap_or_else(BTreeMap::new);
+
         let spn = $value
             .get_ava_single("spn")
             .map(|s| {
@@ -66,6 +80,17 @@ macro_rules! try_from_entry {
 
         let expire = $value.get_ava_single_datetime("account_expire");
 
+        let last_login = $value.get_ava_single_datetime("last_login");
+
+        let last_login_mechanism = $value
+            .get_ava_single_str("last_login_mechanism")
+            .map(|s| s.to_string());
+
+        let x509_fingerprints = $value
+            .get_ava_as_str("x509_fingerprint")
+            .map(|i| i.map(|s| s.to_string()).collect())
+            .unwrap_or_else(Vec::new);
+
         // Resolved by the caller
         let groups = $groups;
 
@@ -77,8 +102,12 @@ macro_rules! try_from_entry {
             displayname,
             groups,
             primary,
+            app_passwords,
             valid_from,
             expire,
+            last_login,
+            last_login_mechanism,
+            x509_fingerprints,
             spn,
         })
     }};
@@ -97,10 +126,14 @@ pub(crate) struct Account {
     pub uuid: Uuid,
     pub groups: Vec<Group>,
     pub primary: Option<Credential>,
+    // Application passwords, by their label. Each is limited by a claim to one scope.
+    pub app_passwords: BTreeMap<String, Credential>,
     pub valid_from: Option<OffsetDateTime>,
     pub expire: Option<OffsetDateTime>,
-    // primary: Credential
-    // app_creds: Vec<Credential>
+    pub last_login: Option<OffsetDateTime>,
+    pub last_login_mechanism: Option<String>,
+    // The fingerprints of the client certificates bound to this account.
+    pub x509_fingerprints: Vec<String>,
     // account expiry? (as opposed to cred expiry)
     pub spn: String,
     // TODO #256: When you add mail, you should update the check to zxcvbn
@@ -137,13 +170,18 @@ impl Account {
     }
 
     // Could this actually take a claims list and application instead?
-    pub(crate) fn to_userauthtoken(&self, claims: &[Claim]) -> Option<UserAuthToken> {
+    pub(crate) fn to_userauthtoken(&self, claims: &[Claim], ct: Duration) -> Option<UserAuthToken> {
         // This could consume self?
         // The cred handler provided is what authenticated this user, so we can use it to
         // process what the proper claims should be.
         // Get the claims from the cred_h
 
+        // The token is signed and may be verified offline, so it must carry its expiry.
+        let exp = (ct.as_secs() + UAT_EXPIRY) as i64;
+
         Some(UserAuthToken {
+            exp,
+            purpose: TokenPurpose::UserAuth,
             name: self.name.clone(),
             spn: self.spn.clone(),
             displayname: self.name.clone(),
@@ -156,9 +194,94 @@ impl Account {
             lim_rmax: 128,
             lim_pmax: 256,
             lim_fmax: 32,
+            api_token_id: None,
+            read_only: false,
+            password_expiry: self.password_expiry().map(|d| d.as_secs() as i64),
         })
     }
 
+    /// The maximum password age that applies to this account through its groups.
+    pub(crate) fn password_max_age(&self) -> Option<Duration> {
+        Group::password_max_age(&self.groups)
+    }
+
+    /// When the password of the primary credential expires, if a maximum password age
+    /// applies to this account.
+    pub(crate) fn password_expiry(&self) -> Option<Duration> {
+        self.password_max_age()
+            .and_then(|max_age| self.primary.as_ref()?.password_expiry(max_age))
+    }
+
+    pub(crate) fn is_password_expired(&self, ct: Duration) -> bool {
+        self.password_expiry().map(|exp| exp <= ct).unwrap_or(false)
+    }
+
+    /// The weakest credential this account may authenticate with, as required by the
+    /// policies of its groups.
+    pub(crate) fn credential_type_minimum(&self) -> Option<CredentialStrength> {
+        Group::credential_type_minimum(&self.groups)
+    }
+
+    /// Check that replacing the primary credential with `ncred` does not weaken it below
+    /// the minimum required by group policy. Changes that don't weaken the credential are
+    /// always allowed, so that members can work their way up to the minimum.
+    pub(crate) fn check_credential_policy(&self, ncred: &Credential) -> Result<(), OperationError> {
+        let nstrength = ncred.strength();
+        match self.credential_type_minimum() {
+            Some(min)
+                if nstrength < min
+                    && self
+                        .primary
+                        .as_ref()
+                        .map(|c| nstrength < c.strength())
+                        .unwrap_or(false) =>
+            {
+                Err(OperationError::CredentialTooWeak(min.as_str().to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn gen_primary_mod(
+        &self,
+        ncred: Credential,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        self.check_credential_policy(&ncred)?;
+        let vcred = Value::new_credential("primary", ncred);
+        Ok(ModifyList::new_purge_and_set("primary_credential", vcred))
+    }
+
+    /// The modification recording a successful login, unless a login of the same
+    /// mechanism was recorded recently. Logins are delayed actions, so they may be
+    /// processed out of order, and the record never moves backwards.
+    pub(crate) fn last_login_mod(
+        &self,
+        mechanism: &str,
+        ct: Duration,
+    ) -> Option<ModifyList<ModifyInvalid>> {
+        let cot = OffsetDateTime::unix_epoch() + ct;
+
+        let stale = match self.last_login {
+            Some(ll) if ll >= cot => false,
+            Some(ll) => {
+                (cot - ll).whole_seconds() >= LAST_LOGIN_GRANULARITY
+                    || self.last_login_mechanism.as_deref() != Some(mechanism)
+            }
+            None => true,
+        };
+
+        if stale {
+            Some(ModifyList::new_list(vec![
+                Modify::Purged("last_login".into()),
+                Modify::Present("last_login".into(), Value::new_datetime_epoch(ct)),
+                Modify::Purged("last_login_mechanism".into()),
+                Modify::Present("last_login_mechanism".into(), Value::new_utf8s(mechanism)),
+            ]))
+        } else {
+            None
+        }
+    }
+
     pub fn is_within_valid_time(&self, ct: Duration) -> bool {
         let cot = OffsetDateTime::unix_epoch() + ct;
 
@@ -197,11 +320,24 @@ impl Account {
         self.uuid == *UUID_ANONYMOUS
     }
 
+    /// Check that a client certificate is bound to this account. Fingerprints are
+    /// stored lowercased, as is that of the certificate.
+    pub fn is_bound_certificate(&self, cert: &ClientCertificate) -> bool {
+        self.x509_fingerprints
+            .iter()
+            .any(|fp| fp.as_str() == cert.fingerprint.as_str())
+    }
+
+    /// Generate the modification to set the password of the primary credential. When
+    /// `change` is set, this is a new password set at that time, and the current password
+    /// is retained in the history along with at most that many previous passwords. Otherwise
+    /// this is a rehash of the same password, and the history and set time are left as is.
     pub(crate) fn gen_password_mod(
         &self,
         cleartext: &str,
         appid: &Option<String>,
         crypto_policy: &CryptoPolicy,
+        change: Option<(Duration, usize)>,
     ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
         // What should this look like? Probablf an appid + stuff -> modify?
         // then the caller has to apply the modify under the requests event
@@ -213,13 +349,24 @@ impl Account {
                 match &self.primary {
                     // Change the cred
                     Some(primary) => {
-                        let ncred = primary.set_password(crypto_policy, cleartext)?;
-                        let vcred = Value::new_credential("primary", ncred);
-                        Ok(ModifyList::new_purge_and_set("primary_credential", vcred))
+                        let ncred = match change {
+                            Some((ct, retain)) => primary.set_password_with_history(
+                                crypto_policy,
+                                cleartext,
+                                retain,
+                                ct,
+                            )?,
+                            None => primary.set_password(crypto_policy, cleartext)?,
+                        };
+                        self.gen_primary_mod(ncred)
                     }
                     // Make a new credential instead
                     None => {
                         let ncred = Credential::new_password_only(crypto_policy, cleartext)?;
+                        let ncred = match change {
+                            Some((ct, _)) => ncred.with_password_set(ct),
+                            None => ncred,
+                        };
                         let vcred = Value::new_credential("primary", ncred);
                         Ok(ModifyList::new_purge_and_set("primary_credential", vcred))
                     }
@@ -236,8 +383,7 @@ impl Account {
             // Change the cred
             Some(primary) => {
                 let ncred = primary.update_totp(token);
-                let vcred = Value::new_credential("primary", ncred);
-                Ok(ModifyList::new_purge_and_set("primary_credential", vcred))
+                self.gen_primary_mod(ncred)
             }
             None => {
                 // No credential exists, we can't supplementy it.
@@ -246,13 +392,15 @@ impl Account {
         }
     }
 
-    pub(crate) fn gen_totp_remove_mod(&self) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+    pub(crate) fn gen_totp_remove_mod(
+        &self,
+        label: &str,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
         match &self.primary {
             // Change the cred
             Some(primary) => {
-                let ncred = primary.remove_totp();
-                let vcred = Value::new_credential("primary", ncred);
-                Ok(ModifyList::new_purge_and_set("primary_credential", vcred))
+                let ncred = primary.remove_totp(label)?;
+                self.gen_primary_mod(ncred)
             }
             None => {
                 // No credential exists, we can't remove what is not real.
@@ -261,17 +409,43 @@ impl Account {
         }
     }
 
+    pub(crate) fn gen_backup_code_mod(
+        &self,
+        backup_codes: BackupCodes,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        match &self.primary {
+            Some(primary) => {
+                let ncred = primary.update_backup_code(backup_codes)?;
+                self.gen_primary_mod(ncred)
+            }
+            None => Err(OperationError::InvalidState),
+        }
+    }
+
+    pub(crate) fn gen_backup_code_remove_mod(
+        &self,
+        code: &str,
+    ) -> Option<ModifyList<ModifyInvalid>> {
+        self.primary
+            .as_ref()
+            .and_then(|primary| primary.remove_backup_code(code))
+            .map(|ncred| {
+                let vcred = Value::new_credential("primary", ncred);
+                ModifyList::new_purge_and_set("primary_credential", vcred)
+            })
+    }
+
     pub(crate) fn gen_webauthn_mod(
         &self,
         label: String,
         cred: WebauthnCredential,
+        model: Option<Uuid>,
     ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
         let ncred = match &self.primary {
-            Some(primary) => primary.append_webauthn(label, cred)?,
-            None => Credential::new_webauthn_only(label, cred),
+            Some(primary) => primary.append_webauthn(label, cred, model)?,
+            None => Credential::new_webauthn_only(label, cred, model),
         };
-        let vcred = Value::new_credential("primary", ncred);
-        Ok(ModifyList::new_purge_and_set("primary_credential", vcred))
+        self.gen_primary_mod(ncred)
     }
 
     pub(crate) fn gen_webauthn_remove_mod(
@@ -282,8 +456,7 @@ impl Account {
             // Change the cred
             Some(primary) => {
                 let ncred = primary.remove_webauthn(label)?;
-                let vcred = Value::new_credential("primary", ncred);
-                Ok(ModifyList::new_purge_and_set("primary_credential", vcred))
+                self.gen_primary_mod(ncred)
             }
             None => {
                 // No credential exists, we can't remove what is not real.
@@ -331,6 +504,69 @@ impl Account {
         }
     }
 
+    /// Check a password against the app passwords of the account that were created for
+    /// this scope.
+    pub(crate) fn verify_app_password(
+        &self,
+        cleartext: &str,
+        scope: AppPasswordScope,
+    ) -> Result<bool, OperationError> {
+        for cred in self
+            .app_passwords
+            .values()
+            .filter(|cred| cred.has_claim(scope.as_str()))
+        {
+            if cred.password_ref().and_then(|pw| pw.verify(cleartext))? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub(crate) fn gen_app_password_mod(
+        &self,
+        label: &str,
+        scope: AppPasswordScope,
+        cleartext: &str,
+        crypto_policy: &CryptoPolicy,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        if label.trim().is_empty() {
+            return Err(OperationError::InvalidAttribute(
+                "An app password requires a label".to_string(),
+            ));
+        }
+        // Credential tags are case insensitive.
+        if self.app_passwords.contains_key(&label.to_lowercase()) {
+            return Err(OperationError::InvalidAttribute(
+                "An app password with this label already exists".to_string(),
+            ));
+        }
+        if self.app_passwords.len() >= APP_PASSWORD_MAX {
+            return Err(OperationError::InvalidAttribute(format!(
+                "An account may not have more than {} app passwords",
+                APP_PASSWORD_MAX
+            )));
+        }
+        let mut ncred = Credential::new_password_only(crypto_policy, cleartext)?;
+        ncred.add_claim(scope.as_str());
+        let vcred = Value::new_credential(label, ncred);
+        Ok(ModifyList::new_append("app_password", vcred))
+    }
+
+    pub(crate) fn gen_app_password_remove_mod(
+        &self,
+        label: &str,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        if self.app_passwords.contains_key(&label.to_lowercase()) {
+            Ok(ModifyList::new_remove(
+                "app_password",
+                PartialValue::new_credential_tag(label),
+            ))
+        } else {
+            Err(OperationError::NoMatchingEntries)
+        }
+    }
+
     pub(crate) fn regenerate_radius_secret_mod(
         &self,
         cleartext: &str,
@@ -340,6 +576,27 @@ impl Account {
     }
 }
 
+/// Describe the app passwords of an account without revealing their material.
+pub(crate) fn app_passwords_to_proto<'a>(
+    app_passwords: impl Iterator<Item = (&'a str, &'a Credential)>,
+) -> Vec<ProtoAppPassword> {
+    app_passwords
+        .filter_map(|(label, cred)| {
+            [
+                AppPasswordScope::Ldap,
+                AppPasswordScope::Unix,
+                AppPasswordScope::Radius,
+            ]
+            .iter()
+            .find(|scope| cred.has_claim(scope.as_str()))
+            .map(|scope| ProtoAppPassword {
+                label: label.to_string(),
+                scope: *scope,
+            })
+        })
+        .collect()
+}
+
 // Need to also add a "to UserAuthToken" ...
 
 // Need tests for conversion and the cred validations
@@ -0,0 +1,175 @@
+use crate::be::dbvalue::DbAuthRecordV1;
+use kanidm_proto::v1::AuthAuditRecord;
+use std::net::IpAddr;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The most records a single search of the audit store may return.
+pub const AUTH_AUDIT_SEARCH_LIMIT: u32 = 1000;
+
+/// Authentication attempts are kept in a store so that they can be searched without log
+/// aggregation. The store is bounded both by the age of the records and by their number,
+/// whichever removes more, so that a burst of failures can not grow it without limit.
+///
+/// These are set by the administrator in `system_config`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthAuditConfig {
+    /// How long records are kept.
+    pub retention: Duration,
+    /// The greatest number of records that are kept.
+    pub max_records: u32,
+}
+
+impl Default for AuthAuditConfig {
+    fn default() -> Self {
+        AuthAuditConfig {
+            retention: Duration::from_secs(30 * 86400),
+            max_records: 100_000,
+        }
+    }
+}
+
+/// How an authentication attempt reached the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthProtocol {
+    Https,
+    Ldap,
+    Unix,
+    Radius,
+}
+
+impl AuthProtocol {
+    fn to_str(self) -> &'static str {
+        match self {
+            AuthProtocol::Https => "https",
+            AuthProtocol::Ldap => "ldap",
+            AuthProtocol::Unix => "unix",
+            AuthProtocol::Radius => "radius",
+        }
+    }
+}
+
+/// An authentication attempt as it's being built up, which is completed into the record
+/// to store once its result is known.
+#[derive(Debug, Clone)]
+pub struct AuthRecord {
+    ct: Duration,
+    protocol: AuthProtocol,
+    source: Option<IpAddr>,
+    name: Option<String>,
+    uuid: Option<Uuid>,
+    mechanism: Option<&'static str>,
+    softlocked: bool,
+}
+
+impl AuthRecord {
+    pub fn new(protocol: AuthProtocol, source: Option<IpAddr>, ct: Duration) -> Self {
+        AuthRecord {
+            ct,
+            protocol,
+            source,
+            name: None,
+            uuid: None,
+            mechanism: None,
+            softlocked: false,
+        }
+    }
+
+    pub fn account(mut self, name: &str, uuid: Uuid) -> Self {
+        self.name = Some(name.to_string());
+        self.uuid = Some(uuid);
+        self
+    }
+
+    /// The name an attempt was made for, when it did not resolve to an account.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_lowercase());
+        self
+    }
+
+    pub fn mechanism(mut self, mechanism: Option<&'static str>) -> Self {
+        self.mechanism = mechanism;
+        self
+    }
+
+    pub fn softlocked(mut self, softlocked: bool) -> Self {
+        self.softlocked = softlocked;
+        self
+    }
+
+    pub fn success(self) -> DbAuthRecordV1 {
+        self.into_dbauthrecord_v1(true, None)
+    }
+
+    pub fn failure(self, reason: &str) -> DbAuthRecordV1 {
+        self.into_dbauthrecord_v1(false, Some(reason.to_string()))
+    }
+
+    fn into_dbauthrecord_v1(self, success: bool, reason: Option<String>) -> DbAuthRecordV1 {
+        DbAuthRecordV1 {
+            t: self.ct,
+            n: self.name,
+            u: self.uuid,
+            s: success,
+            m: self.mechanism.map(str::to_string),
+            p: self.protocol.to_str().to_string(),
+            a: self.source,
+            l: self.softlocked,
+            r: reason,
+        }
+    }
+}
+
+pub fn to_proto_authauditrecord(record: DbAuthRecordV1) -> AuthAuditRecord {
+    AuthAuditRecord {
+        time: (time::OffsetDateTime::unix_epoch() + record.t).format(time::Format::Rfc3339),
+        account: record.n,
+        account_uuid: record.u.map(|u| u.to_hyphenated_ref().to_string()),
+        success: record.s,
+        mechanism: record.m,
+        protocol: record.p,
+        source: record.a.map(|a| a.to_string()),
+        softlocked: record.l,
+        reason: record.r,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::idm::authaudit::{to_proto_authauditrecord, AuthProtocol, AuthRecord};
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_auth_record_to_proto() {
+        let uuid = Uuid::new_v4();
+        let source: IpAddr = "192.0.2.1".parse().unwrap();
+        let record = AuthRecord::new(AuthProtocol::Ldap, Some(source), Duration::from_secs(60))
+            .account("alice", uuid)
+            .mechanism(Some("password"))
+            .softlocked(true)
+            .failure("invalid password");
+
+        let proto = to_proto_authauditrecord(record);
+        assert!(
+            time::OffsetDateTime::parse(&proto.time, time::Format::Rfc3339).unwrap()
+                == time::OffsetDateTime::unix_epoch() + Duration::from_secs(60)
+        );
+        assert!(proto.account.as_deref() == Some("alice"));
+        assert!(proto.account_uuid == Some(uuid.to_hyphenated_ref().to_string()));
+        assert!(!proto.success);
+        assert!(proto.protocol == "ldap");
+        assert!(proto.source.as_deref() == Some("192.0.2.1"));
+        assert!(proto.softlocked);
+        assert!(proto.reason.as_deref() == Some("invalid password"));
+
+        // An unresolved name is kept as it would be resolved.
+        let record = AuthRecord::new(AuthProtocol::Https, None, Duration::from_secs(60))
+            .name("Alice")
+            .success();
+        assert!(record.n.as_deref() == Some("alice"));
+        assert!(record.u.is_none());
+        assert!(record.r.is_none());
+    }
+}
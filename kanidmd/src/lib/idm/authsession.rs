@@ -5,16 +5,23 @@ use crate::idm::AuthState;
 use kanidm_proto::v1::OperationError;
 use kanidm_proto::v1::{AuthAllowed, AuthCredential, AuthMech};
 
-use crate::credential::{totp::TOTP, Credential, CredentialType, Password};
-
-use crate::idm::delayed::{DelayedAction, PasswordUpgrade, WebauthnCounterIncrement};
+use crate::credential::policy::CryptoPolicy;
+use crate::credential::{
+    backupcode::BackupCodes, totp::TOTP, Credential, CredentialType, Password,
+};
+
+use crate::idm::delayed::{
+    BackupCodeRemoval, DelayedAction, PasswordUpgrade, WebauthnCounterIncrement,
+};
 // use crossbeam::channel::Sender;
 use tokio::sync::mpsc::UnboundedSender as Sender;
 
-use crate::credential::webauthn::WebauthnDomainConfig;
+use crate::credential::webauthn::{WebauthnDomainConfig, WebauthnPolicy};
+use crate::crypto::ClientCertificate;
+use hashbrown::HashMap as Map;
 use std::time::Duration;
 use uuid::Uuid;
-// use webauthn_rs::proto::Credential as WebauthnCredential;
+use webauthn_rs::proto::Credential as WebauthnCredential;
 use webauthn_rs::proto::RequestChallengeResponse;
 use webauthn_rs::{AuthenticationState, Webauthn};
 
@@ -26,6 +33,9 @@ use webauthn_rs::{AuthenticationState, Webauthn};
 const BAD_PASSWORD_MSG: &str = "incorrect password";
 const BAD_TOTP_MSG: &str = "incorrect totp";
 const BAD_WEBAUTHN_MSG: &str = "invalid webauthn authentication";
+const BAD_BACKUPCODE_MSG: &str = "invalid backup code";
+const BAD_CERTIFICATE_MSG: &str = "client certificate was not presented";
+const WEBAUTHN_UNVERIFIED_MSG: &str = "webauthn token is not user verified as required by policy";
 const BAD_AUTH_TYPE_MSG: &str = "invalid authentication method in this context";
 const BAD_CREDENTIALS: &str = "invalid credential message";
 const ACCOUNT_EXPIRED: &str = "account expired";
@@ -47,8 +57,9 @@ enum CredVerifyState {
 struct CredMfa {
     pw: Password,
     pw_state: CredVerifyState,
-    totp: Option<TOTP>,
+    totp: Vec<TOTP>,
     wan: Option<(RequestChallengeResponse, AuthenticationState)>,
+    backup_code: Option<BackupCodes>,
     mfa_state: CredVerifyState,
 }
 
@@ -66,24 +77,40 @@ enum CredHandler {
     Password(Password),
     PasswordMFA(Box<CredMfa>),
     Webauthn(CredWebauthn),
+    // The fingerprint of the client certificate the session began with.
+    ClientCertificate(String),
     // Webauthn + Password
 }
 
+// When the policy requires user verification only offer tokens that were verified
+// at registration. A challenge for only verified tokens requires verification.
+fn allowed_wan(
+    wan: &Map<String, WebauthnCredential>,
+    webauthn_policy: &WebauthnPolicy,
+) -> Vec<WebauthnCredential> {
+    wan.values()
+        .filter(|c| c.verified || !webauthn_policy.require_verification)
+        .cloned()
+        .collect()
+}
+
 impl CredHandler {
     // Is there a nicer implementation of this?
     fn try_from(
         au: &mut AuditScope,
         c: &Credential,
         webauthn: &Webauthn<WebauthnDomainConfig>,
+        webauthn_policy: &WebauthnPolicy,
     ) -> Result<Self, ()> {
         match &c.type_ {
             CredentialType::Password(pw) | CredentialType::GeneratedPassword(pw) => {
                 Ok(CredHandler::Password(pw.clone()))
             }
-            CredentialType::PasswordMFA(pw, maybe_totp, maybe_wan) => {
+            CredentialType::PasswordMFA(pw, maybe_totp, maybe_wan, maybe_backup_code) => {
+                let maybe_wan = allowed_wan(maybe_wan, webauthn_policy);
                 let wan = if !maybe_wan.is_empty() {
                     webauthn
-                        .generate_challenge_authenticate(maybe_wan.values().cloned().collect())
+                        .generate_challenge_authenticate(maybe_wan)
                         .map(Some)
                         .map_err(|e| {
                             lsecurity!(
@@ -99,13 +126,14 @@ impl CredHandler {
                 let cmfa = Box::new(CredMfa {
                     pw: pw.clone(),
                     pw_state: CredVerifyState::Init,
-                    totp: maybe_totp.clone(),
+                    totp: maybe_totp.values().cloned().collect(),
                     wan,
+                    backup_code: maybe_backup_code.clone().filter(|b| !b.is_empty()),
                     mfa_state: CredVerifyState::Init,
                 });
 
                 // Paranoia. Should NEVER occur.
-                if cmfa.totp.is_none() && cmfa.wan.is_none() {
+                if cmfa.totp.is_empty() && cmfa.wan.is_none() {
                     lsecurity_critical!(
                         au,
                         "Unable to create CredHandler::PasswordMFA - totp and webauthn are both not present. Credentials MAY be corrupt!"
@@ -116,7 +144,7 @@ impl CredHandler {
                 Ok(CredHandler::PasswordMFA(cmfa))
             }
             CredentialType::Webauthn(wan) => webauthn
-                .generate_challenge_authenticate(wan.values().cloned().collect())
+                .generate_challenge_authenticate(allowed_wan(wan, webauthn_policy))
                 .map(|(chal, wan_state)| {
                     CredHandler::Webauthn(CredWebauthn {
                         chal,
@@ -137,14 +165,25 @@ impl CredHandler {
 }
 
 impl CredHandler {
+    fn last_login_mechanism(&self) -> Option<&'static str> {
+        match self {
+            CredHandler::Anonymous => None,
+            CredHandler::Password(_) => Some("password"),
+            CredHandler::PasswordMFA(_) => Some("passwordmfa"),
+            CredHandler::Webauthn(_) => Some("webauthn"),
+            CredHandler::ClientCertificate(_) => Some("x509"),
+        }
+    }
+
     fn maybe_pw_upgrade(
         au: &mut AuditScope,
         pw: &Password,
         who: Uuid,
         cleartext: &str,
         async_tx: &Sender<DelayedAction>,
+        crypto_policy: &CryptoPolicy,
     ) {
-        if pw.requires_upgrade() {
+        if pw.requires_upgrade(crypto_policy) {
             if let Err(_e) = async_tx.send(DelayedAction::PwUpgrade(PasswordUpgrade {
                 target_uuid: who,
                 existing_password: cleartext.to_string(),
@@ -178,12 +217,20 @@ impl CredHandler {
         pw: &mut Password,
         who: Uuid,
         async_tx: &Sender<DelayedAction>,
+        crypto_policy: &CryptoPolicy,
     ) -> CredState {
         match cred {
             AuthCredential::Password(cleartext) => {
                 if pw.verify(cleartext.as_str()).unwrap_or(false) {
                     lsecurity!(au, "Handler::Password -> Result::Success");
-                    Self::maybe_pw_upgrade(au, pw, who, cleartext.as_str(), async_tx);
+                    Self::maybe_pw_upgrade(
+                        au,
+                        pw,
+                        who,
+                        cleartext.as_str(),
+                        async_tx,
+                        crypto_policy,
+                    );
                     CredState::Success(Vec::new())
                 } else {
                     lsecurity!(
@@ -204,6 +251,7 @@ impl CredHandler {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn validate_password_mfa(
         au: &mut AuditScope,
         cred: &AuthCredential,
@@ -212,11 +260,12 @@ impl CredHandler {
         webauthn: &Webauthn<WebauthnDomainConfig>,
         who: Uuid,
         async_tx: &Sender<DelayedAction>,
+        crypto_policy: &CryptoPolicy,
     ) -> CredState {
         match (&pw_mfa.mfa_state, &pw_mfa.pw_state) {
             (CredVerifyState::Init, CredVerifyState::Init) => {
                 // MFA first
-                match (cred, pw_mfa.totp.as_ref(), pw_mfa.wan.as_ref()) {
+                match (cred, pw_mfa.totp.is_empty(), pw_mfa.wan.as_ref()) {
                     (AuthCredential::Webauthn(resp), _, Some((_, wan_state))) => {
                         webauthn.authenticate_credential(&resp, wan_state.clone())
                                 .map(|r| {
@@ -242,8 +291,38 @@ impl CredHandler {
                                     CredState::Denied(BAD_WEBAUTHN_MSG)
                                 })
                     }
-                    (AuthCredential::TOTP(totp_chal), Some(totp), _) => {
-                        if totp.verify(*totp_chal, ts) {
+                    (AuthCredential::BackupCode(code), _, _) => {
+                        match pw_mfa.backup_code.as_ref() {
+                            Some(backup_codes) if backup_codes.verify(code.as_str()) => {
+                                pw_mfa.mfa_state = CredVerifyState::Success;
+                                // The code is single use, so it must be removed now.
+                                if let Err(_e) = async_tx.send(DelayedAction::BackupCodeRemoval(
+                                    BackupCodeRemoval {
+                                        target_uuid: who,
+                                        code_to_remove: code.clone(),
+                                    },
+                                )) {
+                                    ladmin_warning!(au, "unable to queue delayed backup code removal, continuing ... ");
+                                };
+                                lsecurity!(
+                                    au,
+                                    "Handler::PasswordMFA -> Result::Continue - BackupCode OK, password -"
+                                );
+                                CredState::Continue(vec![AuthAllowed::Password])
+                            }
+                            _ => {
+                                pw_mfa.mfa_state = CredVerifyState::Fail;
+                                lsecurity!(
+                                    au,
+                                    "Handler::PasswordMFA -> Result::Denied - BackupCode Fail, password -"
+                                );
+                                CredState::Denied(BAD_BACKUPCODE_MSG)
+                            }
+                        }
+                    }
+                    (AuthCredential::TOTP(totp_chal), false, _) => {
+                        // Any of the registered totps may be used.
+                        if pw_mfa.totp.iter().any(|totp| totp.verify(*totp_chal, ts)) {
                             pw_mfa.mfa_state = CredVerifyState::Success;
                             lsecurity!(
                                 au,
@@ -284,6 +363,7 @@ impl CredHandler {
                                 who,
                                 cleartext.as_str(),
                                 async_tx,
+                                crypto_policy,
                             );
                             CredState::Success(Vec::new())
                         } else {
@@ -367,6 +447,7 @@ impl CredHandler {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn validate(
         &mut self,
         au: &mut AuditScope,
@@ -374,19 +455,35 @@ impl CredHandler {
         ts: &Duration,
         who: Uuid,
         async_tx: &Sender<DelayedAction>,
+        crypto_policy: &CryptoPolicy,
         webauthn: &Webauthn<WebauthnDomainConfig>,
     ) -> CredState {
         match self {
             CredHandler::Anonymous => Self::validate_anonymous(au, cred),
             CredHandler::Password(ref mut pw) => {
-                Self::validate_password(au, cred, pw, who, async_tx)
-            }
-            CredHandler::PasswordMFA(ref mut pw_mfa) => {
-                Self::validate_password_mfa(au, cred, ts, pw_mfa, webauthn, who, async_tx)
+                Self::validate_password(au, cred, pw, who, async_tx, crypto_policy)
             }
+            CredHandler::PasswordMFA(ref mut pw_mfa) => Self::validate_password_mfa(
+                au,
+                cred,
+                ts,
+                pw_mfa,
+                webauthn,
+                who,
+                async_tx,
+                crypto_policy,
+            ),
             CredHandler::Webauthn(ref mut wan_cred) => {
                 Self::validate_webauthn(au, cred, wan_cred, webauthn, who, async_tx)
             }
+            // The certificate is checked by AuthSession::validate_client_certificate.
+            CredHandler::ClientCertificate(_) => {
+                lsecurity!(
+                    au,
+                    "Handler::ClientCertificate -> Result::Denied - invalid cred type for handler"
+                );
+                CredState::Denied(BAD_AUTH_TYPE_MSG)
+            }
         }
     }
 
@@ -397,7 +494,10 @@ impl CredHandler {
             CredHandler::PasswordMFA(ref pw_mfa) => pw_mfa
                 .totp
                 .iter()
+                // Only offer totp once, however many are registered.
+                .take(1)
                 .map(|_| AuthAllowed::TOTP)
+                .chain(pw_mfa.backup_code.iter().map(|_| AuthAllowed::BackupCode))
                 .chain(
                     pw_mfa
                         .wan
@@ -406,6 +506,7 @@ impl CredHandler {
                 )
                 .collect(),
             CredHandler::Webauthn(webauthn) => vec![AuthAllowed::Webauthn(webauthn.chal.clone())],
+            CredHandler::ClientCertificate(_) => vec![AuthAllowed::ClientCertificate],
         }
     }
 
@@ -414,7 +515,8 @@ impl CredHandler {
             (CredHandler::Anonymous, AuthMech::Anonymous)
             | (CredHandler::Password(_), AuthMech::Password)
             | (CredHandler::PasswordMFA(_), AuthMech::PasswordMFA)
-            | (CredHandler::Webauthn(_), AuthMech::Webauthn) => true,
+            | (CredHandler::Webauthn(_), AuthMech::Webauthn)
+            | (CredHandler::ClientCertificate(_), AuthMech::ClientCertificate) => true,
             (_, _) => false,
         }
     }
@@ -425,6 +527,7 @@ impl CredHandler {
             CredHandler::Password(_) => AuthMech::Password,
             CredHandler::PasswordMFA(_) => AuthMech::PasswordMFA,
             CredHandler::Webauthn(_) => AuthMech::Webauthn,
+            CredHandler::ClientCertificate(_) => AuthMech::ClientCertificate,
         }
     }
 }
@@ -434,12 +537,18 @@ impl CredHandler {
 /// and we go to the init state, sending back the list of what can proceed.
 /// The client then sends a "begin" with the chosen mech that moves to
 /// "InProgress", "Success" or "Denied". From there the CredHandler
-/// is interacted with until we move to either "Success" or "Denied".
+/// is interacted with until we move to either "Success" or "Denied". If the
+/// credentials were valid but the password has expired, we move to
+/// "PasswordExpired" until a new password is written, and then "PasswordChanged".
 enum AuthSessionState {
     Init(Vec<CredHandler>),
     // Stop! Don't make this a vec - make the credhandler able to hold multiple
     // internal copies of it's type and check against them all.
     InProgress(CredHandler),
+    // The claims to issue once the password is changed.
+    PasswordExpired(Vec<Claim>),
+    // The new password has been committed, so the session can complete.
+    PasswordChanged(Vec<Claim>),
     Success,
     Denied(&'static str),
 }
@@ -453,6 +562,132 @@ impl AuthSessionState {
     }
 }
 
+/// Check that the account and its primary credential can be used to authenticate,
+/// returning the credential, or the reason the account must be denied.
+fn usable_primary<'a>(
+    au: &mut AuditScope,
+    account: &'a Account,
+    webauthn_policy: &WebauthnPolicy,
+    ct: Duration,
+) -> Result<&'a Credential, &'static str> {
+    if !account.is_within_valid_time(ct) {
+        lsecurity!(au, "account expired");
+        return Err(ACCOUNT_EXPIRED);
+    }
+
+    // Now we see if they have one ...
+    let cred = account.primary.as_ref().ok_or_else(|| {
+        lsecurity!(au, "account has no primary credentials");
+        "invalid credential state"
+    })?;
+
+    match account.credential_type_minimum() {
+        // A group of this account requires a stronger credential, so this
+        // can never succeed. Tell them why rather than let them try.
+        Some(min) if cred.strength() < min => {
+            lsecurity!(
+                au,
+                "credential {} is weaker than {} required by group policy",
+                cred.strength().as_str(),
+                min.as_str()
+            );
+            Err(min.denied_msg())
+        }
+        // None of the tokens that could be used can satisfy the policy.
+        _ if webauthn_policy.require_verification && cred.is_webauthn_unverified_only() => {
+            lsecurity!(au, "no webauthn token is user verified, denying");
+            Err(WEBAUTHN_UNVERIFIED_MSG)
+        }
+        _ => Ok(cred),
+    }
+}
+
+/// Check that the client certificate of the connection is bound to the account and can
+/// be used to authenticate it. Group credential policy can't weigh a certificate, so it
+/// is not offered to accounts that a policy applies to.
+fn usable_certificate(
+    au: &mut AuditScope,
+    account: &Account,
+    client_cert: Option<&ClientCertificate>,
+    ct: Duration,
+) -> Option<CredHandler> {
+    let cert = client_cert?;
+    if !account.is_bound_certificate(cert) {
+        lsecurity!(
+            au,
+            "client certificate {} ({:?}) is not bound to the account",
+            cert.fingerprint,
+            cert.subject
+        );
+        None
+    } else if !account.is_within_valid_time(ct) {
+        lsecurity!(au, "account expired");
+        None
+    } else if account.credential_type_minimum().is_some() {
+        lsecurity!(
+            au,
+            "client certificate can not satisfy the credential policy of the account"
+        );
+        None
+    } else {
+        Some(CredHandler::ClientCertificate(cert.fingerprint.clone()))
+    }
+}
+
+/// A webauthn authentication that began without an account name. The challenge allows
+/// any credential, and the account is found from the discoverable credential that
+/// responds, at which point this becomes an AuthSession for that account.
+#[derive(Clone)]
+pub(crate) struct DiscoverableAuthSession {
+    wan: CredWebauthn,
+    started: bool,
+}
+
+impl DiscoverableAuthSession {
+    pub fn new(
+        au: &mut AuditScope,
+        webauthn: &Webauthn<WebauthnDomainConfig>,
+    ) -> Result<(Self, AuthState), OperationError> {
+        let (chal, wan_state) = webauthn
+            .generate_challenge_authenticate(Vec::new())
+            .map_err(|e| {
+                lsecurity!(
+                    au,
+                    "Unable to create webauthn authentication challenge -> {:?}",
+                    e
+                );
+                OperationError::Webauthn
+            })?;
+        let session = DiscoverableAuthSession {
+            wan: CredWebauthn {
+                chal,
+                wan_state,
+                state: CredVerifyState::Init,
+            },
+            started: false,
+        };
+        Ok((
+            session,
+            AuthState::Choose(vec![AuthMech::WebauthnDiscoverable]),
+        ))
+    }
+
+    pub fn start_session(&mut self, mech: &AuthMech) -> Result<AuthState, OperationError> {
+        if self.started {
+            Err(OperationError::InvalidAuthState(
+                "session already finalised!".to_string(),
+            ))
+        } else if mech == &AuthMech::WebauthnDiscoverable {
+            self.started = true;
+            Ok(AuthState::Continue(vec![AuthAllowed::Webauthn(
+                self.wan.chal.clone(),
+            )]))
+        } else {
+            Ok(AuthState::Denied(BAD_CREDENTIALS.to_string()))
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct AuthSession {
     // Do we store a copy of the entry?
@@ -464,6 +699,8 @@ pub(crate) struct AuthSession {
     //
     // This handler will then handle the mfa and stepping up through to generate the auth states
     state: AuthSessionState,
+    // The mechanism recorded as the accounts last login once this session succeeds.
+    mechanism: Option<&'static str>,
 }
 
 impl AuthSession {
@@ -471,43 +708,46 @@ impl AuthSession {
         au: &mut AuditScope,
         account: Account,
         _appid: &Option<String>,
+        client_cert: Option<&ClientCertificate>,
         webauthn: &Webauthn<WebauthnDomainConfig>,
+        webauthn_policy: &WebauthnPolicy,
         ct: Duration,
     ) -> (Option<Self>, AuthState) {
         // During this setup, determine the credential handler that we'll be using
         // for this session. This is currently based on presentation of an application
         // id.
-        let state = if account.is_within_valid_time(ct) {
+        let state = if account.is_anonymous() && account.is_within_valid_time(ct) {
             // We want the primary handler - this is where we make a decision
             // based on the anonymous ... in theory this could be cleaner
             // and interact with the account more?
-            if account.is_anonymous() {
-                AuthSessionState::Init(vec![CredHandler::Anonymous])
-            } else {
-                // Now we see if they have one ...
-                match &account.primary {
-                    Some(cred) => {
-                        // TODO: Make it possible to have multiple creds.
-                        // Probably means new authsession has to be failable
-                        CredHandler::try_from(au, cred, webauthn)
-                            .map(|ch| AuthSessionState::Init(vec![ch]))
-                            .unwrap_or_else(|_| {
-                                lsecurity_critical!(
-                                    au,
-                                    "corrupt credentials, unable to start credhandler"
-                                );
-                                AuthSessionState::Denied("invalid credential state")
-                            })
-                    }
-                    None => {
-                        lsecurity!(au, "account has no primary credentials");
-                        AuthSessionState::Denied("invalid credential state")
-                    }
+            AuthSessionState::Init(vec![CredHandler::Anonymous])
+        } else {
+            // A bound certificate is offered beside the primary credential, or alone so
+            // that service accounts need no other credential.
+            let cert_handler = usable_certificate(au, &account, client_cert, ct);
+            match usable_primary(au, &account, webauthn_policy, ct) {
+                Ok(cred) => {
+                    // TODO: Make it possible to have multiple creds.
+                    // Probably means new authsession has to be failable
+                    CredHandler::try_from(au, cred, webauthn, webauthn_policy)
+                        .map(|ch| {
+                            AuthSessionState::Init(
+                                std::iter::once(ch).chain(cert_handler).collect(),
+                            )
+                        })
+                        .unwrap_or_else(|_| {
+                            lsecurity_critical!(
+                                au,
+                                "corrupt credentials, unable to start credhandler"
+                            );
+                            AuthSessionState::Denied("invalid credential state")
+                        })
                 }
+                Err(reason) => match cert_handler {
+                    Some(ch) => AuthSessionState::Init(vec![ch]),
+                    None => AuthSessionState::Denied(reason),
+                },
             }
-        } else {
-            lsecurity!(au, "account expired");
-            AuthSessionState::Denied(ACCOUNT_EXPIRED)
         };
 
         // if credhandler == deny, finish = true.
@@ -516,7 +756,11 @@ impl AuthSession {
             (None, AuthState::Denied(reason.to_string()))
         } else {
             // We can proceed
-            let auth_session = AuthSession { account, state };
+            let auth_session = AuthSession {
+                account,
+                state,
+                mechanism: None,
+            };
             // Get the set of mechanisms that can proceed. This is tied
             // to the session so that it can mutate state and have progression
             // of what's next, or ordering.
@@ -527,10 +771,49 @@ impl AuthSession {
         }
     }
 
+    /// Bind a discoverable session to the account its credential belongs to. Only a
+    /// webauthn only credential can be used, as any other requires more than the token.
+    pub fn new_discoverable(
+        au: &mut AuditScope,
+        account: Account,
+        discoverable: DiscoverableAuthSession,
+        webauthn_policy: &WebauthnPolicy,
+        ct: Duration,
+    ) -> Result<Self, &'static str> {
+        if !discoverable.started {
+            lsecurity!(au, "discoverable session has not begun");
+            return Err(BAD_AUTH_TYPE_MSG);
+        }
+
+        let wan = match &usable_primary(au, &account, webauthn_policy, ct)?.type_ {
+            CredentialType::Webauthn(wan) => allowed_wan(wan, webauthn_policy),
+            _ => {
+                lsecurity!(
+                    au,
+                    "credential is not webauthn only, can not be discoverable"
+                );
+                return Err(BAD_AUTH_TYPE_MSG);
+            }
+        };
+
+        let mut wan_cred = discoverable.wan;
+        wan_cred.wan_state.set_allowed_credentials(wan);
+        Ok(AuthSession {
+            account,
+            state: AuthSessionState::InProgress(CredHandler::Webauthn(wan_cred)),
+            mechanism: Some("webauthn"),
+        })
+    }
+
     pub fn get_account(&self) -> &Account {
         &self.account
     }
 
+    /// The mechanism the session proceeded with, if any. Anonymous is not recorded.
+    pub fn last_login_mechanism(&self) -> Option<&'static str> {
+        self.mechanism
+    }
+
     pub fn start_session(
         &mut self,
         _au: &mut AuditScope,
@@ -546,7 +829,9 @@ impl AuthSession {
         let (next_state, response) = match &mut self.state {
             AuthSessionState::Success
             | AuthSessionState::Denied(_)
-            | AuthSessionState::InProgress(_) => (
+            | AuthSessionState::InProgress(_)
+            | AuthSessionState::PasswordExpired(_)
+            | AuthSessionState::PasswordChanged(_) => (
                 None,
                 Err(OperationError::InvalidAuthState(
                     "session already finalised!".to_string(),
@@ -571,6 +856,7 @@ impl AuthSession {
                             )),
                         )
                     } else {
+                        self.mechanism = allowed_handler.last_login_mechanism();
                         (
                             Some(AuthSessionState::InProgress(allowed_handler)),
                             Ok(AuthState::Continue(allowed)),
@@ -599,6 +885,7 @@ impl AuthSession {
         cred: &AuthCredential,
         time: &Duration,
         async_tx: &Sender<DelayedAction>,
+        crypto_policy: &CryptoPolicy,
         webauthn: &Webauthn<WebauthnDomainConfig>,
     ) -> Result<AuthState, OperationError> {
         let (next_state, response) = match &mut self.state {
@@ -607,16 +894,36 @@ impl AuthSession {
                     "session already finalised!".to_string(),
                 ));
             }
+            AuthSessionState::PasswordExpired(_) | AuthSessionState::PasswordChanged(_) => {
+                return Err(OperationError::InvalidAuthState(
+                    "password expired, it must be changed".to_string(),
+                ));
+            }
             AuthSessionState::InProgress(ref mut handler) => {
-                match handler.validate(au, cred, time, self.account.uuid, async_tx, webauthn) {
+                match handler.validate(
+                    au,
+                    cred,
+                    time,
+                    self.account.uuid,
+                    async_tx,
+                    crypto_policy,
+                    webauthn,
+                ) {
+                    CredState::Success(claims) if self.account.is_password_expired(*time) => {
+                        lsecurity!(au, "Successful cred handling, but the password has expired");
+                        (
+                            Some(AuthSessionState::PasswordExpired(claims)),
+                            Ok(AuthState::PasswordExpired),
+                        )
+                    }
                     CredState::Success(claims) => {
                         lsecurity!(au, "Successful cred handling");
                         let uat = self
                             .account
-                            .to_userauthtoken(&claims)
+                            .to_userauthtoken(&claims, *time)
                             .ok_or(OperationError::InvalidState)?;
 
-                        // Now encrypt and prepare the token for return to the client.
+                        // The token is signed by the caller before return to the client.
                         (Some(AuthSessionState::Success), Ok(AuthState::Success(uat)))
                     }
                     CredState::Continue(allowed) => {
@@ -653,6 +960,95 @@ impl AuthSession {
         response
     }
 
+    pub fn is_password_expired(&self) -> bool {
+        matches!(self.state, AuthSessionState::PasswordExpired(_))
+    }
+
+    /// Record that the new password of the account was committed, so that the session
+    /// can be completed.
+    pub(crate) fn expired_password_changed(&mut self) {
+        if let AuthSessionState::PasswordExpired(claims) = &self.state {
+            self.state = AuthSessionState::PasswordChanged(claims.clone());
+        }
+    }
+
+    /// Complete a session once its expired password has been replaced.
+    pub fn complete_expired_password_change(
+        &mut self,
+        au: &mut AuditScope,
+        time: &Duration,
+    ) -> Result<AuthState, OperationError> {
+        let claims = match &self.state {
+            AuthSessionState::PasswordChanged(claims) => claims,
+            AuthSessionState::PasswordExpired(_) => {
+                return Err(OperationError::InvalidAuthState(
+                    "the new password has not been set".to_string(),
+                ));
+            }
+            _ => {
+                return Err(OperationError::InvalidAuthState(
+                    "password change is not required".to_string(),
+                ));
+            }
+        };
+
+        let mut uat = self
+            .account
+            .to_userauthtoken(claims, *time)
+            .ok_or(OperationError::InvalidState)?;
+        // The token must reflect the new password, not the one that expired.
+        uat.password_expiry = self
+            .account
+            .password_max_age()
+            .map(|max_age| (*time + max_age).as_secs() as i64);
+
+        lsecurity!(au, "Expired password changed");
+        self.state = AuthSessionState::Success;
+        Ok(AuthState::Success(uat))
+    }
+
+    /// Complete a session that proceeded with a client certificate. The certificate was
+    /// verified by the TLS layer, so it only has to be the one the session began with.
+    pub fn validate_client_certificate(
+        &mut self,
+        au: &mut AuditScope,
+        client_cert: Option<&ClientCertificate>,
+        time: &Duration,
+    ) -> Result<AuthState, OperationError> {
+        let denied = match &self.state {
+            AuthSessionState::InProgress(CredHandler::ClientCertificate(fingerprint)) => {
+                if client_cert.map(|cert| &cert.fingerprint) == Some(fingerprint) {
+                    None
+                } else {
+                    Some(BAD_CERTIFICATE_MSG)
+                }
+            }
+            AuthSessionState::InProgress(_) => Some(BAD_AUTH_TYPE_MSG),
+            _ => {
+                return Err(OperationError::InvalidAuthState(
+                    "session already finalised!".to_string(),
+                ));
+            }
+        };
+
+        if let Some(reason) = denied {
+            lsecurity!(
+                au,
+                "Handler::ClientCertificate -> Result::Denied - {}",
+                reason
+            );
+            return self.end_session(reason);
+        }
+
+        lsecurity!(au, "Handler::ClientCertificate -> Result::Success");
+        let uat = self
+            .account
+            .to_userauthtoken(&[], *time)
+            .ok_or(OperationError::InvalidState)?;
+        self.state = AuthSessionState::Success;
+        Ok(AuthState::Success(uat))
+    }
+
     pub fn end_session(&mut self, reason: &'static str) -> Result<AuthState, OperationError> {
         let mut next_state = AuthSessionState::Denied(reason);
         std::mem::swap(&mut self.state, &mut next_state);
@@ -663,7 +1059,9 @@ impl AuthSession {
         match &self.state {
             AuthSessionState::Success
             | AuthSessionState::Denied(_)
-            | AuthSessionState::InProgress(_) => Vec::new(),
+            | AuthSessionState::InProgress(_)
+            | AuthSessionState::PasswordExpired(_)
+            | AuthSessionState::PasswordChanged(_) => Vec::new(),
             AuthSessionState::Init(handlers) => {
                 // Iterate over the handlers into what mechs they are
                 // and filter to unique?
@@ -677,12 +1075,16 @@ impl AuthSession {
 mod tests {
     use crate::audit::AuditScope;
     use crate::constants::{JSON_ADMIN_V1, JSON_ANONYMOUS_V1};
-    use crate::credential::policy::CryptoPolicy;
+    use crate::credential::backupcode::BackupCodes;
+    use crate::credential::policy::{CredentialStrength, CryptoPolicy};
     use crate::credential::totp::{TOTP, TOTP_DEFAULT_STEP};
-    use crate::credential::webauthn::WebauthnDomainConfig;
+    use crate::credential::webauthn::{WebauthnDomainConfig, WebauthnPolicy};
     use crate::credential::Credential;
+    use crate::crypto::ClientCertificate;
     use crate::idm::authsession::{
-        AuthSession, BAD_AUTH_TYPE_MSG, BAD_PASSWORD_MSG, BAD_TOTP_MSG, BAD_WEBAUTHN_MSG,
+        AuthSession, DiscoverableAuthSession, BAD_AUTH_TYPE_MSG, BAD_BACKUPCODE_MSG,
+        BAD_CERTIFICATE_MSG, BAD_PASSWORD_MSG, BAD_TOTP_MSG, BAD_WEBAUTHN_MSG,
+        WEBAUTHN_UNVERIFIED_MSG,
     };
     use crate::idm::delayed::DelayedAction;
     use crate::idm::AuthState;
@@ -718,7 +1120,9 @@ mod tests {
             &mut audit,
             anon_account,
             &None,
+            None,
             &webauthn,
+            &WebauthnPolicy::default(),
             duration_from_epoch_now(),
         );
 
@@ -765,7 +1169,9 @@ mod tests {
             &mut audit,
             anon_account,
             &Some("NonExistantAppID".to_string()),
+            None,
             &webauthn,
+            &WebauthnPolicy::default(),
             duration_from_epoch_now(),
         );
 
@@ -789,7 +1195,9 @@ mod tests {
                 $audit,
                 $account.clone(),
                 &None,
+                None,
                 $webauthn,
+                &WebauthnPolicy::default(),
                 duration_from_epoch_now(),
             );
             let mut session = session.unwrap();
@@ -850,6 +1258,7 @@ mod tests {
             &attempt,
             &Duration::from_secs(0),
             &async_tx,
+            &CryptoPolicy::minimum(),
             &webauthn,
         ) {
             Ok(AuthState::Denied(_)) => {}
@@ -866,6 +1275,7 @@ mod tests {
             &attempt,
             &Duration::from_secs(0),
             &async_tx,
+            &CryptoPolicy::minimum(),
             &webauthn,
         ) {
             Ok(AuthState::Success(_)) => {}
@@ -888,7 +1298,9 @@ mod tests {
                 $audit,
                 $account.clone(),
                 &None,
+                None,
                 $webauthn,
+                &WebauthnPolicy::default(),
                 duration_from_epoch_now(),
             );
             let mut session = session.expect("Session was unable to be created.");
@@ -978,6 +1390,7 @@ mod tests {
                 &AuthCredential::Anonymous,
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_AUTH_TYPE_MSG),
@@ -996,6 +1409,7 @@ mod tests {
                 &AuthCredential::Password(pw_bad.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_AUTH_TYPE_MSG),
@@ -1011,6 +1425,7 @@ mod tests {
                 &AuthCredential::TOTP(totp_bad),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_TOTP_MSG),
@@ -1028,6 +1443,7 @@ mod tests {
                 &AuthCredential::TOTP(totp_good),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
@@ -1038,6 +1454,7 @@ mod tests {
                 &AuthCredential::Password(pw_bad.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
@@ -1055,6 +1472,7 @@ mod tests {
                 &AuthCredential::TOTP(totp_good),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
@@ -1065,6 +1483,7 @@ mod tests {
                 &AuthCredential::Password(pw_good.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Success(_)) => {}
@@ -1072,6 +1491,148 @@ mod tests {
             };
         }
 
+        // add a second totp, either can now be used.
+        let totp_second = TOTP::generate_secure("second_totp".to_string(), TOTP_DEFAULT_STEP);
+        let totp_second_good = totp_second
+            .do_totp_duration_from_epoch(&ts)
+            .expect("failed to perform totp.");
+        account.primary = account.primary.map(|c| c.update_totp(totp_second));
+        for chal in [totp_good, totp_second_good].iter() {
+            let (mut session, _) = start_password_mfa_session!(&mut audit, account, &webauthn);
+
+            match session.validate_creds(
+                &mut audit,
+                &AuthCredential::TOTP(*chal),
+                &ts,
+                &async_tx,
+                &CryptoPolicy::minimum(),
+                &webauthn,
+            ) {
+                Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
+                _ => panic!(),
+            };
+        }
+
+        drop(async_tx);
+        assert!(async_rx.blocking_recv().is_none());
+        audit.write_log();
+    }
+
+    #[test]
+    fn test_idm_authsession_backup_code_password_mech() {
+        let mut audit = AuditScope::new(
+            "test_idm_authsession_backup_code_password_mech",
+            uuid::Uuid::new_v4(),
+            None,
+        );
+        let webauthn = create_webauthn();
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+        let ts = Duration::from_secs(12345);
+
+        let totp = TOTP::generate_secure("test_totp".to_string(), TOTP_DEFAULT_STEP);
+        let (backup_codes, codes) = BackupCodes::generate();
+
+        let pw_good = "test_password";
+        let pw_bad = "bad_password";
+
+        let p = CryptoPolicy::minimum();
+        let cred = Credential::new_password_only(&p, pw_good)
+            .unwrap()
+            .update_totp(totp)
+            .update_backup_code(backup_codes)
+            .unwrap();
+        account.primary = Some(cred);
+
+        let (async_tx, mut async_rx) = unbounded();
+
+        // check send bad backup code, should fail immediate
+        {
+            let (mut session, _) = start_password_mfa_session!(&mut audit, account, &webauthn);
+
+            match session.validate_creds(
+                &mut audit,
+                &AuthCredential::BackupCode("aaaa-bbbb-cccc".to_string()),
+                &ts,
+                &async_tx,
+                &CryptoPolicy::minimum(),
+                &webauthn,
+            ) {
+                Ok(AuthState::Denied(msg)) => assert!(msg == BAD_BACKUPCODE_MSG),
+                _ => panic!(),
+            };
+        }
+
+        // check send good backup code, should continue
+        //      then bad pw, fail pw
+        {
+            let (mut session, _) = start_password_mfa_session!(&mut audit, account, &webauthn);
+
+            match session.validate_creds(
+                &mut audit,
+                &AuthCredential::BackupCode(codes[0].clone()),
+                &ts,
+                &async_tx,
+                &CryptoPolicy::minimum(),
+                &webauthn,
+            ) {
+                Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
+                _ => panic!(),
+            };
+            match session.validate_creds(
+                &mut audit,
+                &AuthCredential::Password(pw_bad.to_string()),
+                &ts,
+                &async_tx,
+                &CryptoPolicy::minimum(),
+                &webauthn,
+            ) {
+                Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
+                _ => panic!(),
+            };
+            // The code is consumed even though the password was wrong.
+            match async_rx.blocking_recv() {
+                Some(DelayedAction::BackupCodeRemoval(bcr)) => {
+                    assert!(bcr.code_to_remove == codes[0])
+                }
+                _ => assert!(false),
+            }
+        }
+
+        // check send good backup code, should continue
+        //      then good pw, success
+        {
+            let (mut session, _) = start_password_mfa_session!(&mut audit, account, &webauthn);
+
+            match session.validate_creds(
+                &mut audit,
+                &AuthCredential::BackupCode(codes[1].clone()),
+                &ts,
+                &async_tx,
+                &CryptoPolicy::minimum(),
+                &webauthn,
+            ) {
+                Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
+                _ => panic!(),
+            };
+            match session.validate_creds(
+                &mut audit,
+                &AuthCredential::Password(pw_good.to_string()),
+                &ts,
+                &async_tx,
+                &CryptoPolicy::minimum(),
+                &webauthn,
+            ) {
+                Ok(AuthState::Success(_)) => {}
+                _ => panic!(),
+            };
+            match async_rx.blocking_recv() {
+                Some(DelayedAction::BackupCodeRemoval(bcr)) => {
+                    assert!(bcr.code_to_remove == codes[1])
+                }
+                _ => assert!(false),
+            }
+        }
+
         drop(async_tx);
         assert!(async_rx.blocking_recv().is_none());
         audit.write_log();
@@ -1087,7 +1648,9 @@ mod tests {
                 $audit,
                 $account.clone(),
                 &None,
+                None,
                 $webauthn,
+                &WebauthnPolicy::default(),
                 duration_from_epoch_now(),
             );
             let mut session = session.unwrap();
@@ -1165,7 +1728,7 @@ mod tests {
         let (webauthn, mut wa, wan_cred) = setup_webauthn(account.name.as_str());
 
         // Now create the credential for the account.
-        let cred = Credential::new_webauthn_only("soft".to_string(), wan_cred);
+        let cred = Credential::new_webauthn_only("soft".to_string(), wan_cred, None);
         account.primary = Some(cred);
 
         // now check correct mech was offered.
@@ -1180,6 +1743,7 @@ mod tests {
                 &AuthCredential::Anonymous,
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_AUTH_TYPE_MSG),
@@ -1200,6 +1764,7 @@ mod tests {
                 &AuthCredential::Webauthn(resp),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Success(_)) => {}
@@ -1228,6 +1793,7 @@ mod tests {
                 &AuthCredential::Webauthn(resp),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_WEBAUTHN_MSG),
@@ -1272,6 +1838,7 @@ mod tests {
                 &AuthCredential::Webauthn(resp),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_WEBAUTHN_MSG),
@@ -1284,6 +1851,119 @@ mod tests {
         audit.write_log();
     }
 
+    #[test]
+    fn test_idm_authsession_webauthn_verification_required() {
+        let mut audit = AuditScope::new(
+            "test_idm_authsession_webauthn_verification_required",
+            uuid::Uuid::new_v4(),
+            None,
+        );
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+
+        // The soft token is u2f, so it can never verify the user.
+        let (webauthn, _wa, wan_cred) = setup_webauthn(account.name.as_str());
+        assert!(!wan_cred.verified);
+        let cred = Credential::new_webauthn_only("soft".to_string(), wan_cred, None);
+        assert!(cred.strength() == CredentialStrength::Webauthn);
+        account.primary = Some(cred);
+
+        let webauthn_policy = WebauthnPolicy {
+            require_verification: true,
+            ..Default::default()
+        };
+        let (session, state) = AuthSession::new(
+            &mut audit,
+            account,
+            &None,
+            None,
+            &webauthn,
+            &webauthn_policy,
+            duration_from_epoch_now(),
+        );
+        assert!(session.is_none());
+        match state {
+            AuthState::Denied(msg) => assert!(msg == WEBAUTHN_UNVERIFIED_MSG),
+            _ => panic!(),
+        };
+
+        audit.write_log();
+    }
+
+    #[test]
+    fn test_idm_authsession_webauthn_discoverable() {
+        let mut audit = AuditScope::new(
+            "test_idm_authsession_webauthn_discoverable",
+            uuid::Uuid::new_v4(),
+            None,
+        );
+        let ts = duration_from_epoch_now();
+        let webauthn_policy = WebauthnPolicy::default();
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+        let (webauthn, _wa, wan_cred) = setup_webauthn(account.name.as_str());
+
+        // Only the discoverable mech can begin, and only once.
+        let (mut discoverable, state) =
+            DiscoverableAuthSession::new(&mut audit, &webauthn).expect("Failed to begin");
+        match state {
+            AuthState::Choose(mechs) => assert!(mechs == vec![AuthMech::WebauthnDiscoverable]),
+            _ => panic!(),
+        };
+        match discoverable.clone().start_session(&AuthMech::Webauthn) {
+            Ok(AuthState::Denied(_)) => {}
+            _ => panic!(),
+        };
+
+        // It can't be bound before it has begun.
+        account.primary = Some(Credential::new_webauthn_only(
+            "soft".to_string(),
+            wan_cred.clone(),
+            None,
+        ));
+        match AuthSession::new_discoverable(
+            &mut audit,
+            account.clone(),
+            discoverable.clone(),
+            &webauthn_policy,
+            ts,
+        ) {
+            Err(msg) => assert!(msg == BAD_AUTH_TYPE_MSG),
+            _ => panic!(),
+        };
+
+        match discoverable.start_session(&AuthMech::WebauthnDiscoverable) {
+            Ok(AuthState::Continue(allowed)) => assert!(allowed.len() == 1),
+            _ => panic!(),
+        };
+        assert!(discoverable
+            .start_session(&AuthMech::WebauthnDiscoverable)
+            .is_err());
+
+        assert!(AuthSession::new_discoverable(
+            &mut audit,
+            account.clone(),
+            discoverable.clone(),
+            &webauthn_policy,
+            ts,
+        )
+        .is_ok());
+
+        // A token that is only a second factor can't authenticate alone.
+        let p = CryptoPolicy::minimum();
+        account.primary = Some(
+            Credential::new_password_only(&p, "test_password")
+                .unwrap()
+                .append_webauthn("soft".to_string(), wan_cred, None)
+                .unwrap(),
+        );
+        match AuthSession::new_discoverable(&mut audit, account, discoverable, &webauthn_policy, ts)
+        {
+            Err(msg) => assert!(msg == BAD_AUTH_TYPE_MSG),
+            _ => panic!(),
+        };
+
+        audit.write_log();
+    }
+
     #[test]
     fn test_idm_authsession_webauthn_password_mech() {
         let mut audit = AuditScope::new(
@@ -1304,7 +1984,7 @@ mod tests {
         let p = CryptoPolicy::minimum();
         let cred = Credential::new_password_only(&p, pw_good)
             .unwrap()
-            .append_webauthn("soft".to_string(), wan_cred)
+            .append_webauthn("soft".to_string(), wan_cred, None)
             .unwrap();
 
         account.primary = Some(cred);
@@ -1318,6 +1998,7 @@ mod tests {
                 &AuthCredential::Password(pw_bad.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_AUTH_TYPE_MSG),
@@ -1334,6 +2015,7 @@ mod tests {
                 &AuthCredential::TOTP(0),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_AUTH_TYPE_MSG),
@@ -1360,6 +2042,7 @@ mod tests {
                 &AuthCredential::Webauthn(resp),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_WEBAUTHN_MSG),
@@ -1381,6 +2064,7 @@ mod tests {
                 &AuthCredential::Webauthn(resp),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
@@ -1391,6 +2075,7 @@ mod tests {
                 &AuthCredential::Password(pw_bad.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
@@ -1418,6 +2103,7 @@ mod tests {
                 &AuthCredential::Webauthn(resp),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
@@ -1428,6 +2114,7 @@ mod tests {
                 &AuthCredential::Password(pw_good.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Success(_)) => {}
@@ -1476,7 +2163,7 @@ mod tests {
         let p = CryptoPolicy::minimum();
         let cred = Credential::new_password_only(&p, pw_good)
             .unwrap()
-            .append_webauthn("soft".to_string(), wan_cred)
+            .append_webauthn("soft".to_string(), wan_cred, None)
             .unwrap()
             .update_totp(totp);
 
@@ -1491,6 +2178,7 @@ mod tests {
                 &AuthCredential::Password(pw_bad.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_AUTH_TYPE_MSG),
@@ -1507,6 +2195,7 @@ mod tests {
                 &AuthCredential::TOTP(totp_bad),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_TOTP_MSG),
@@ -1531,6 +2220,7 @@ mod tests {
                 &AuthCredential::Webauthn(resp),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_WEBAUTHN_MSG),
@@ -1552,6 +2242,7 @@ mod tests {
                 &AuthCredential::Webauthn(resp),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
@@ -1562,6 +2253,7 @@ mod tests {
                 &AuthCredential::Password(pw_bad.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
@@ -1584,6 +2276,7 @@ mod tests {
                 &AuthCredential::TOTP(totp_good),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
@@ -1594,6 +2287,7 @@ mod tests {
                 &AuthCredential::Password(pw_bad.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
@@ -1610,6 +2304,7 @@ mod tests {
                 &AuthCredential::TOTP(totp_good),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
@@ -1620,6 +2315,7 @@ mod tests {
                 &AuthCredential::Password(pw_good.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Success(_)) => {}
@@ -1641,6 +2337,7 @@ mod tests {
                 &AuthCredential::Webauthn(resp),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
@@ -1651,6 +2348,7 @@ mod tests {
                 &AuthCredential::Password(pw_good.to_string()),
                 &ts,
                 &async_tx,
+                &CryptoPolicy::minimum(),
                 &webauthn,
             ) {
                 Ok(AuthState::Success(_)) => {}
@@ -1668,4 +2366,72 @@ mod tests {
         assert!(async_rx.blocking_recv().is_none());
         audit.write_log();
     }
+
+    #[test]
+    fn test_idm_authsession_client_certificate() {
+        let mut audit = AuditScope::new(
+            "test_idm_authsession_client_certificate",
+            uuid::Uuid::new_v4(),
+            None,
+        );
+        let webauthn = create_webauthn();
+        let ts = duration_from_epoch_now();
+        let bound = ClientCertificate {
+            fingerprint: "00ff".repeat(16),
+            subject: Some("admin".to_string()),
+        };
+        let other = ClientCertificate {
+            fingerprint: "ff00".repeat(16),
+            subject: Some("admin".to_string()),
+        };
+        // The admin has no primary credential, so only the certificate can be offered.
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+        account.x509_fingerprints = vec![bound.fingerprint.clone()];
+
+        // An unbound certificate isn't offered.
+        let (session, state) = AuthSession::new(
+            &mut audit,
+            account.clone(),
+            &None,
+            Some(&other),
+            &webauthn,
+            &WebauthnPolicy::default(),
+            ts,
+        );
+        assert!(session.is_none());
+        assert!(matches!(state, AuthState::Denied(_)));
+
+        let (session, state) = AuthSession::new(
+            &mut audit,
+            account.clone(),
+            &None,
+            Some(&bound),
+            &webauthn,
+            &WebauthnPolicy::default(),
+            ts,
+        );
+        match state {
+            AuthState::Choose(mechs) => assert!(mechs == vec![AuthMech::ClientCertificate]),
+            _ => panic!(),
+        };
+        let mut session = session.expect("Missing auth session?");
+        match session.start_session(&mut audit, &AuthMech::ClientCertificate) {
+            Ok(AuthState::Continue(allowed)) => {
+                assert!(allowed == vec![AuthAllowed::ClientCertificate])
+            }
+            _ => panic!(),
+        };
+        // The certificate must still be the one presented.
+        let mut denied = session.clone();
+        match denied.validate_client_certificate(&mut audit, Some(&other), &ts) {
+            Ok(AuthState::Denied(msg)) => assert!(msg == BAD_CERTIFICATE_MSG),
+            _ => panic!(),
+        };
+        match session.validate_client_certificate(&mut audit, Some(&bound), &ts) {
+            Ok(AuthState::Success(uat)) => assert!(uat.name == "admin"),
+            _ => panic!(),
+        };
+
+        audit.write_log();
+    }
 }
@@ -1,6 +1,6 @@
 use kanidm_proto::v1::Claim as ProtoClaim;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Claim {
     // For now, empty. Later we'll flesh this out to uuid + name?
 }
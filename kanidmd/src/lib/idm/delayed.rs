@@ -1,3 +1,5 @@
+use crate::be::dbvalue::DbSoftLockV1;
+use std::time::Duration;
 use uuid::Uuid;
 use webauthn_rs::proto::{Counter, CredentialID};
 
@@ -5,6 +7,9 @@ pub(crate) enum DelayedAction {
     PwUpgrade(PasswordUpgrade),
     UnixPwUpgrade(UnixPasswordUpgrade),
     WebauthnCounterIncrement(WebauthnCounterIncrement),
+    ApiTokenUsed(ApiTokenUsed),
+    BackupCodeRemoval(BackupCodeRemoval),
+    LastLogin(LastLogin),
 }
 
 pub(crate) struct PasswordUpgrade {
@@ -23,3 +28,28 @@ pub(crate) struct WebauthnCounterIncrement {
     pub counter: Counter,
     pub cid: CredentialID,
 }
+
+pub(crate) struct ApiTokenUsed {
+    pub target_uuid: Uuid,
+    pub token_id: Uuid,
+    pub ct: Duration,
+}
+
+pub(crate) struct BackupCodeRemoval {
+    pub target_uuid: Uuid,
+    pub code_to_remove: String,
+}
+
+pub(crate) struct LastLogin {
+    pub target_uuid: Uuid,
+    // How the account authenticated, such as "password" or "ldap".
+    pub mechanism: &'static str,
+    pub ct: Duration,
+}
+
+// Softlock updates are queued separately to the other delayed actions, as a burst of
+// failures to one credential only needs the latest state to be written.
+pub(crate) struct SoftLockUpdate {
+    pub cred_uuid: Uuid,
+    pub state: Option<DbSoftLockV1>,
+}
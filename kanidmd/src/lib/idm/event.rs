@@ -1,11 +1,19 @@
 use crate::actors::v1_write::IdmAccountSetPasswordMessage;
 use crate::audit::AuditScope;
+use crate::be::AuthRecordFilter;
 use crate::event::Event;
+use crate::idm::authaudit::AUTH_AUDIT_SEARCH_LIMIT;
 use crate::server::{QueryServerReadTransaction, QueryServerWriteTransaction};
 
+use std::net::IpAddr;
+use std::time::Duration;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
-use kanidm_proto::v1::{OperationError, UserAuthToken};
+use kanidm_proto::v1::{
+    ApiTokenGenerate, ApiTokenPurpose, AppPasswordScope, AuthAuditQuery, OperationError,
+    SetCredentialRequest, TOTPSecret, UserAuthToken,
+};
 use webauthn_rs::proto::RegisterPublicKeyCredential;
 
 pub struct PasswordChangeEvent {
@@ -170,6 +178,48 @@ impl RadiusAuthTokenEvent {
     }
 }
 
+pub struct RadiusAuthEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub cleartext: String,
+}
+
+impl std::fmt::Debug for RadiusAuthEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RadiusAuthEvent")
+            .field("event", &self.event)
+            .field("target", &self.target)
+            .finish()
+    }
+}
+
+impl RadiusAuthEvent {
+    #[cfg(test)]
+    pub fn new_internal(target: &Uuid, cleartext: &str) -> Self {
+        RadiusAuthEvent {
+            event: Event::from_internal(),
+            target: *target,
+            cleartext: cleartext.to_string(),
+        }
+    }
+
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerReadTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+        cleartext: String,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+
+        Ok(RadiusAuthEvent {
+            event: e,
+            target,
+            cleartext,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct UnixUserTokenEvent {
     pub event: Event,
@@ -344,9 +394,85 @@ impl VerifyTOTPEvent {
 pub struct RemoveTOTPEvent {
     pub event: Event,
     pub target: Uuid,
+    pub label: String,
 }
 
 impl RemoveTOTPEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+        label: String,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(RemoveTOTPEvent {
+            event: e,
+            target,
+            label,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, label: String) -> Self {
+        let e = Event::from_internal();
+
+        RemoveTOTPEvent {
+            event: e,
+            target,
+            label,
+        }
+    }
+}
+
+// Not Debug, as it carries the totp secret.
+pub struct ImportTOTPEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub label: String,
+    pub secret: TOTPSecret,
+}
+
+impl ImportTOTPEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+        label: String,
+        secret: TOTPSecret,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(ImportTOTPEvent {
+            event: e,
+            target,
+            label,
+            secret,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, label: String, secret: TOTPSecret) -> Self {
+        let e = Event::from_internal();
+
+        ImportTOTPEvent {
+            event: e,
+            target,
+            label,
+            secret,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GenerateBackupCodeEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl GenerateBackupCodeEvent {
     pub fn from_parts(
         audit: &mut AuditScope,
         qs: &QueryServerWriteTransaction,
@@ -355,14 +481,14 @@ impl RemoveTOTPEvent {
     ) -> Result<Self, OperationError> {
         let e = Event::from_rw_uat(audit, qs, uat)?;
 
-        Ok(RemoveTOTPEvent { event: e, target })
+        Ok(GenerateBackupCodeEvent { event: e, target })
     }
 
     #[cfg(test)]
     pub fn new_internal(target: Uuid) -> Self {
         let e = Event::from_internal();
 
-        RemoveTOTPEvent { event: e, target }
+        GenerateBackupCodeEvent { event: e, target }
     }
 }
 
@@ -476,10 +602,444 @@ impl RemoveWebauthnEvent {
     }
 }
 
+#[derive(Debug)]
+pub struct GenerateApiTokenEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub label: String,
+    pub expiry: Option<OffsetDateTime>,
+    pub read_only: bool,
+}
+
+impl GenerateApiTokenEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+        gen: ApiTokenGenerate,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        let expiry = gen
+            .expiry
+            .as_deref()
+            .map(|s| {
+                OffsetDateTime::parse(s, time::Format::Rfc3339)
+                    .map(|odt| odt.to_offset(time::UtcOffset::UTC))
+                    .map_err(|_| {
+                        lrequest_error!(audit, "Invalid api token expiry -> {}", s);
+                        OperationError::InvalidAttribute("expiry".to_string())
+                    })
+            })
+            .transpose()?;
+
+        Ok(GenerateApiTokenEvent {
+            event: e,
+            target,
+            label: gen.label,
+            expiry,
+            read_only: gen.purpose == ApiTokenPurpose::ReadOnly,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(
+        target: Uuid,
+        label: &str,
+        expiry: Option<OffsetDateTime>,
+        read_only: bool,
+    ) -> Self {
+        let e = Event::from_internal();
+
+        GenerateApiTokenEvent {
+            event: e,
+            target,
+            label: label.to_string(),
+            expiry,
+            read_only,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DestroyApiTokenEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub token_id: Uuid,
+}
+
+impl DestroyApiTokenEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+        token_id: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(DestroyApiTokenEvent {
+            event: e,
+            target,
+            token_id,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, token_id: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        DestroyApiTokenEvent {
+            event: e,
+            target,
+            token_id,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ListApiTokenEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl ListApiTokenEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerReadTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+
+        Ok(ListApiTokenEvent { event: e, target })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        ListApiTokenEvent { event: e, target }
+    }
+}
+
+#[derive(Debug)]
+pub struct GenerateAppPasswordEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub label: String,
+    pub scope: AppPasswordScope,
+}
+
+impl GenerateAppPasswordEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+        label: String,
+        scope: AppPasswordScope,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(GenerateAppPasswordEvent {
+            event: e,
+            target,
+            label,
+            scope,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, label: &str, scope: AppPasswordScope) -> Self {
+        let e = Event::from_internal();
+
+        GenerateAppPasswordEvent {
+            event: e,
+            target,
+            label: label.to_string(),
+            scope,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RemoveAppPasswordEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub label: String,
+}
+
+impl RemoveAppPasswordEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+        label: String,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(RemoveAppPasswordEvent {
+            event: e,
+            target,
+            label,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, label: &str) -> Self {
+        let e = Event::from_internal();
+
+        RemoveAppPasswordEvent {
+            event: e,
+            target,
+            label: label.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ListAppPasswordEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl ListAppPasswordEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerReadTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+
+        Ok(ListAppPasswordEvent { event: e, target })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        ListAppPasswordEvent { event: e, target }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReadSoftLockEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl ReadSoftLockEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerReadTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+
+        Ok(ReadSoftLockEvent { event: e, target })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        ReadSoftLockEvent { event: e, target }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuthAuditSearchEvent {
+    pub event: Event,
+    pub filter: AuthRecordFilter,
+}
+
+impl AuthAuditSearchEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerReadTransaction,
+        uat: Option<&UserAuthToken>,
+        query: AuthAuditQuery,
+        ct: Duration,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+
+        let mut parse_time = |attr: &str, s: Option<&str>| {
+            s.map(|s| {
+                OffsetDateTime::parse(s, time::Format::Rfc3339)
+                    .map(|odt| Duration::from_secs(odt.unix_timestamp().max(0) as u64))
+                    .map_err(|_| {
+                        lrequest_error!(audit, "Invalid auth audit {} -> {}", attr, s);
+                        OperationError::InvalidAttribute(attr.to_string())
+                    })
+            })
+            .transpose()
+        };
+        let since = parse_time("since", query.since.as_deref())?;
+        let until = parse_time("until", query.until.as_deref())?;
+
+        Ok(AuthAuditSearchEvent {
+            event: e,
+            filter: AuthRecordFilter {
+                account: query.account.map(|a| a.to_lowercase()),
+                success: query.success,
+                since: since.unwrap_or_else(|| Duration::from_secs(0)),
+                until: until.unwrap_or(ct),
+                limit: query
+                    .limit
+                    .unwrap_or(AUTH_AUDIT_SEARCH_LIMIT)
+                    .min(AUTH_AUDIT_SEARCH_LIMIT),
+            },
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_impersonate(e: Event, filter: AuthRecordFilter) -> Self {
+        AuthAuditSearchEvent { event: e, filter }
+    }
+}
+
+#[derive(Debug)]
+pub struct SshCertSignEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub publickey: String,
+    // The certificate expires with the token of the session that requested it.
+    pub valid_before: Duration,
+}
+
+impl SshCertSignEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerReadTransaction,
+        uat: Option<&UserAuthToken>,
+        publickey: String,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+        let target = *e.get_uuid().ok_or(OperationError::InvalidState)?;
+        let valid_before = uat
+            .map(|uat| Duration::from_secs(uat.exp.max(0) as u64))
+            .ok_or(OperationError::NotAuthenticated)?;
+
+        Ok(SshCertSignEvent {
+            event: e,
+            target,
+            publickey,
+            valid_before,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, publickey: &str, valid_before: Duration) -> Self {
+        SshCertSignEvent {
+            event: Event::from_internal(),
+            target,
+            publickey: publickey.to_string(),
+            valid_before,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ClearSoftLockEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl ClearSoftLockEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(ClearSoftLockEvent { event: e, target })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        ClearSoftLockEvent { event: e, target }
+    }
+}
+
+#[derive(Debug)]
+pub struct CredentialResetIntentEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub ttl: Option<u64>,
+}
+
+impl CredentialResetIntentEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        uat: Option<&UserAuthToken>,
+        target: Uuid,
+        ttl: Option<u64>,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(CredentialResetIntentEvent {
+            event: e,
+            target,
+            ttl,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, ttl: Option<u64>) -> Self {
+        let e = Event::from_internal();
+
+        CredentialResetIntentEvent {
+            event: e,
+            target,
+            ttl,
+        }
+    }
+}
+
+// The holder of a reset token is not authenticated, so these carry no event. The
+// token itself is the proof that they may change the credentials of the account.
+pub struct CredentialResetBeginEvent {
+    pub token: String,
+}
+
+impl CredentialResetBeginEvent {
+    pub fn new(token: String) -> Self {
+        CredentialResetBeginEvent { token }
+    }
+}
+
+pub struct CredentialResetStepEvent {
+    pub sessionid: Uuid,
+    pub token: String,
+    pub sac: SetCredentialRequest,
+}
+
+impl CredentialResetStepEvent {
+    pub fn new(sessionid: Uuid, token: String, sac: SetCredentialRequest) -> Self {
+        CredentialResetStepEvent {
+            sessionid,
+            token,
+            sac,
+        }
+    }
+}
+
 pub struct LdapAuthEvent {
     // pub event: Event,
     pub target: Uuid,
     pub cleartext: String,
+    // The client address, recorded in the audit store.
+    pub source: Option<IpAddr>,
 }
 
 impl LdapAuthEvent {
@@ -500,6 +1060,7 @@ impl LdapAuthEvent {
         // uat: Option<UserAuthToken>,
         target: Uuid,
         cleartext: String,
+        source: Option<IpAddr>,
     ) -> Result<Self, OperationError> {
         // let e = Event::from_ro_uat(audit, qs, uat)?;
 
@@ -507,6 +1068,7 @@ impl LdapAuthEvent {
             // event: e,
             target,
             cleartext,
+            source,
         })
     }
 }
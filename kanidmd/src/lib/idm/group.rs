@@ -1,4 +1,5 @@
 use crate::audit::AuditScope;
+use crate::credential::policy::{CredentialStrength, PasswordQualityPolicy};
 use crate::entry::{Entry, EntryCommitted, EntryReduced, EntrySealed};
 use crate::server::{
     QueryServerReadTransaction, QueryServerTransaction, QueryServerWriteTransaction,
@@ -7,6 +8,8 @@ use crate::value::PartialValue;
 use kanidm_proto::v1::Group as ProtoGroup;
 use kanidm_proto::v1::OperationError;
 
+use std::convert::TryFrom;
+use std::time::Duration;
 use uuid::Uuid;
 
 lazy_static! {
@@ -17,7 +20,15 @@ lazy_static! {
 pub struct Group {
     name: String,
     uuid: Uuid,
-    // We'll probably add policy and claims later to this
+    // The maximum age in seconds of the passwords of members.
+    password_max_age: Option<u32>,
+    // The weakest credential that members may authenticate with.
+    credential_type_minimum: Option<CredentialStrength>,
+    // Overrides of the system password quality policy for members.
+    password_min_length: Option<u32>,
+    password_min_score: Option<u32>,
+    password_min_char_classes: Option<u32>,
+    // We'll probably add claims later to this
 }
 
 macro_rules! try_from_account_e {
@@ -31,7 +42,15 @@ macro_rules! try_from_account_e {
 
         let uuid = *$value.get_uuid();
 
-        let upg = Group { name, uuid };
+        let upg = Group {
+            name,
+            uuid,
+            password_max_age: None,
+            credential_type_minimum: None,
+            password_min_length: None,
+            password_min_score: None,
+            password_min_char_classes: None,
+        };
 
         let mut groups: Vec<Group> = match $value.get_ava_as_refuuid("memberof") {
             Some(riter) => {
@@ -107,7 +126,60 @@ impl Group {
 
         let uuid = *value.get_uuid();
 
-        Ok(Group { name, uuid })
+        let password_max_age = value.get_ava_single_uint32("password_max_age");
+
+        // Values are checked when written, so anything else can't be present.
+        let credential_type_minimum = value
+            .get_ava_single_str("credential_type_minimum")
+            .and_then(|s| CredentialStrength::try_from(s).ok());
+
+        Ok(Group {
+            name,
+            uuid,
+            password_max_age,
+            credential_type_minimum,
+            password_min_length: value.get_ava_single_uint32("password_min_length"),
+            password_min_score: value.get_ava_single_uint32("password_min_score"),
+            password_min_char_classes: value.get_ava_single_uint32("password_min_char_classes"),
+        })
+    }
+
+    /// The strictest maximum password age of a set of groups, if any of them have one.
+    pub fn password_max_age(groups: &[Self]) -> Option<Duration> {
+        groups
+            .iter()
+            .filter_map(|g| g.password_max_age)
+            .min()
+            .map(|secs| Duration::from_secs(secs as u64))
+    }
+
+    /// The weakest credential that a member of all of these groups may authenticate with.
+    pub fn credential_type_minimum(groups: &[Self]) -> Option<CredentialStrength> {
+        groups
+            .iter()
+            .filter_map(|g| g.credential_type_minimum)
+            .max()
+    }
+
+    /// The password quality policy of a member of these groups. Groups may only raise the
+    /// minimums of the system policy, so the strictest of the system policy and the group
+    /// overrides applies.
+    pub fn password_quality(
+        groups: &[Self],
+        system: PasswordQualityPolicy,
+    ) -> PasswordQualityPolicy {
+        let strictest =
+            |f: fn(&Self) -> Option<u32>, sys: u32| groups.iter().filter_map(f).fold(sys, u32::max);
+        PasswordQualityPolicy {
+            min_length: groups
+                .iter()
+                .filter_map(|g| g.password_min_length)
+                .map(|v| v as usize)
+                .fold(system.min_length, usize::max),
+            min_score: strictest(|g| g.password_min_score, system.min_score),
+            min_char_classes: strictest(|g| g.password_min_char_classes, system.min_char_classes),
+            badlist_words: system.badlist_words,
+        }
     }
 
     pub fn to_proto(&self) -> ProtoGroup {
@@ -1,8 +1,9 @@
 use crate::audit::AuditScope;
 use crate::credential::totp::{TOTP, TOTP_DEFAULT_STEP};
-use crate::credential::webauthn::WebauthnDomainConfig;
+use crate::credential::webauthn::{WebauthnDomainConfig, WebauthnPolicy};
 use crate::event::EventOriginId;
 use crate::idm::account::Account;
+use kanidm_proto::jws::TokenPurpose;
 use kanidm_proto::v1::TOTPSecret;
 use kanidm_proto::v1::{OperationError, SetCredentialResponse};
 use std::mem;
@@ -14,9 +15,29 @@ use webauthn_rs::proto::{CreationChallengeResponse, RegisterPublicKeyCredential}
 use webauthn_rs::RegistrationState as WebauthnRegistrationState;
 use webauthn_rs::{proto::UserVerificationPolicy, Webauthn};
 
+/// The claims of a signed credential reset token. The intent id must match the one
+/// stored on the account for the token to be redeemed, which makes the token single use.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CredResetIntentClaims {
+    pub purpose: TokenPurpose,
+    pub intent_id: Uuid,
+    pub target: Uuid,
+    pub exp: i64,
+}
+
+/// A redeemed credential reset token. While this exists the holder of the token may
+/// set the credentials of the target account, using the same mfa registration flows
+/// as an authenticated account.
+#[derive(Clone)]
+pub(crate) struct CredResetSession {
+    pub intent_id: Uuid,
+    pub target: Uuid,
+}
+
 pub(crate) enum MfaRegCred {
     TOTP(TOTP),
-    Webauthn(String, WebauthnCredential),
+    // The label, the credential and the attested authenticator model.
+    Webauthn(String, WebauthnCredential, Option<Uuid>),
 }
 
 pub(crate) enum MfaRegNext {
@@ -123,10 +144,11 @@ impl MfaRegSession {
         account: Account,
         label: String,
         webauthn: &Webauthn<WebauthnDomainConfig>,
+        webauthn_policy: &WebauthnPolicy,
     ) -> Result<(Self, MfaRegNext), OperationError> {
         // Setup the registration.
         let (chal, reg_state) = webauthn
-            .generate_challenge_register(&account.name, Some(UserVerificationPolicy::Discouraged))
+            .generate_challenge_register(&account.name, Some(webauthn_policy.user_verification()))
             .map_err(|e| {
                 ladmin_error!(au, "Unable to generate webauthn challenge -> {:?}", e);
                 OperationError::Webauthn
@@ -149,6 +171,7 @@ impl MfaRegSession {
         target: &Uuid,
         chal: &RegisterPublicKeyCredential,
         webauthn: &Webauthn<WebauthnDomainConfig>,
+        webauthn_policy: &WebauthnPolicy,
     ) -> Result<(MfaRegNext, Option<MfaRegCred>), OperationError> {
         if &self.origin != origin || target != &self.account.uuid {
             // Verify that the same event source is the one continuing this attempt
@@ -160,13 +183,20 @@ impl MfaRegSession {
         mem::swap(&mut self.state, &mut nstate);
 
         match nstate {
-            MfaRegState::WebauthnInit(label, reg_state) => webauthn
-                .register_credential(chal, reg_state, |_| Ok(false))
-                .map_err(|e| {
-                    ladmin_error!(au, "Unable to register webauthn credential -> {:?}", e);
-                    OperationError::Webauthn
-                })
-                .map(|cred| (MfaRegNext::Success, Some(MfaRegCred::Webauthn(label, cred)))),
+            MfaRegState::WebauthnInit(label, reg_state) => {
+                let cred = webauthn
+                    .register_credential(chal, reg_state, |_| Ok(false))
+                    .map_err(|e| {
+                        ladmin_error!(au, "Unable to register webauthn credential -> {:?}", e);
+                        OperationError::Webauthn
+                    })?;
+                // The attestation is now known to be genuine, so check it is allowed.
+                let model = webauthn_policy.check_attestation(au, chal)?;
+                Ok((
+                    MfaRegNext::Success,
+                    Some(MfaRegCred::Webauthn(label, cred, model)),
+                ))
+            }
             _ => Err(OperationError::InvalidRequestState),
         }
     }
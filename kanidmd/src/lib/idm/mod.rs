@@ -1,12 +1,16 @@
 pub(crate) mod account;
+pub(crate) mod authaudit;
 pub(crate) mod authsession;
 pub(crate) mod claim;
 pub(crate) mod delayed;
 pub(crate) mod event;
 pub(crate) mod group;
 pub(crate) mod mfareg;
+pub(crate) mod oauth2;
 pub(crate) mod radius;
+pub(crate) mod ratelimit;
 pub(crate) mod server;
+pub(crate) mod serviceaccount;
 pub(crate) mod unix;
 // mod identity;
 
@@ -18,4 +22,5 @@ pub enum AuthState {
     Continue(Vec<AuthAllowed>),
     Denied(String),
     Success(UserAuthToken),
+    PasswordExpired,
 }
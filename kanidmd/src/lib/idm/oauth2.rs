@@ -0,0 +1,604 @@
+//! Oauth2 resource server configurations and the in-progress authorisation sessions
+//! that are issued to user agents and resource servers during an authorisation code
+//! grant.
+
+use crate::audit::AuditScope;
+use crate::entry::{Entry, EntryCommitted, EntrySealed};
+use crate::server::QueryServerTransaction;
+use crate::value::PartialValue;
+use kanidm_proto::oauth2::Oauth2Error;
+
+use openssl::sha::sha256;
+use std::collections::BTreeSet;
+use url::Url;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref PVCLASS_OAUTH2_RS: PartialValue = PartialValue::new_class("oauth2_resource_server");
+}
+
+/// The scope that requests an OpenID Connect id_token is issued. This is always
+/// permitted in addition to the resource server's implicit scopes.
+pub(crate) const OAUTH2_SCOPE_OPENID: &str = "openid";
+
+#[derive(Debug, Clone)]
+pub(crate) struct Oauth2RS {
+    pub name: String,
+    pub displayname: String,
+    pub uuid: Uuid,
+    pub origin: Url,
+    pub redirect_uris: Vec<Url>,
+    pub implicit_scopes: BTreeSet<String>,
+    pub basic_secret: Option<String>,
+}
+
+impl Oauth2RS {
+    pub fn try_from_entry(value: &Entry<EntrySealed, EntryCommitted>) -> Result<Self, Oauth2Error> {
+        if !value.attribute_value_pres("class", &PVCLASS_OAUTH2_RS) {
+            return Err(Oauth2Error::InvalidClient);
+        }
+
+        let name = value
+            .get_ava_single_str("oauth2_rs_name")
+            .map(|s| s.to_string())
+            .ok_or(Oauth2Error::ServerError)?;
+
+        let displayname = value
+            .get_ava_single_str("displayname")
+            .map(|s| s.to_string())
+            .ok_or(Oauth2Error::ServerError)?;
+
+        let origin = value
+            .get_ava_single_str("oauth2_rs_origin")
+            .and_then(|s| Url::parse(s).ok())
+            .ok_or(Oauth2Error::ServerError)?;
+
+        let redirect_uris = value
+            .get_ava_as_str("oauth2_rs_redirect_uri")
+            .map(|i| i.filter_map(|s| Url::parse(s).ok()).collect())
+            .unwrap_or_else(Vec::new);
+
+        let implicit_scopes = value
+            .get_ava_as_str("oauth2_rs_implicit_scopes")
+            .map(|i| i.map(|s| s.to_string()).collect())
+            .unwrap_or_else(BTreeSet::new);
+
+        let basic_secret = value
+            .get_ava_single_str("oauth2_rs_basic_secret")
+            .map(|s| s.to_string());
+
+        Ok(Oauth2RS {
+            name,
+            displayname,
+            uuid: *value.get_uuid(),
+            origin,
+            redirect_uris,
+            implicit_scopes,
+            basic_secret,
+        })
+    }
+
+    /// The redirect uri must exactly match one the resource server registered, else
+    /// an attacker could redirect the code to themself, or to another page of the
+    /// resource server that leaks it.
+    pub fn check_redirect_uri(&self, redirect_uri: &Url) -> bool {
+        self.redirect_uris.iter().any(|u| u == redirect_uri)
+    }
+
+    pub fn permitted_scopes(&self) -> BTreeSet<String> {
+        let mut scopes = self.implicit_scopes.clone();
+        scopes.insert(OAUTH2_SCOPE_OPENID.to_string());
+        scopes
+    }
+
+    pub fn check_basic_secret(&self, secret: &str) -> bool {
+        match &self.basic_secret {
+            Some(s) => {
+                s.len() == secret.len() && openssl::memcmp::eq(s.as_bytes(), secret.as_bytes())
+            }
+            None => false,
+        }
+    }
+}
+
+/// Find the resource server by its client_id (oauth2_rs_name).
+pub(crate) fn oauth2_rs_from_client_id<T: QueryServerTransaction>(
+    au: &mut AuditScope,
+    qs: &T,
+    client_id: &str,
+) -> Result<Oauth2RS, Oauth2Error> {
+    let f = filter!(f_and!([
+        f_eq("class", PVCLASS_OAUTH2_RS.clone()),
+        f_eq("oauth2_rs_name", PartialValue::new_iname(client_id))
+    ]));
+    let mut entries = qs.internal_search(au, f).map_err(|e| {
+        ladmin_error!(au, "Failed to search oauth2 resource servers -> {:?}", e);
+        Oauth2Error::ServerError
+    })?;
+    if entries.len() != 1 {
+        lsecurity!(au, "Unknown oauth2 client_id -> {:?}", client_id);
+        return Err(Oauth2Error::InvalidClient);
+    }
+    entries
+        .pop()
+        .ok_or(Oauth2Error::InvalidClient)
+        .and_then(|e| Oauth2RS::try_from_entry(&e))
+}
+
+/// The issuer of tokens for a resource server. Each resource server has a distinct
+/// issuer so that the discovery document can be located by the client_id.
+pub(crate) fn oauth2_issuer(origin: &Url, client_id: &str) -> String {
+    format!(
+        "{}/oauth2/openid/{}",
+        origin.as_str().trim_end_matches('/'),
+        client_id
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Oauth2SessionState {
+    // The user must consent before a code can be issued.
+    ConsentRequested,
+    // A code has been issued to the user agent and may be exchanged once by the
+    // resource server.
+    CodeIssued,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Oauth2Session {
+    pub state: Oauth2SessionState,
+    secret: String,
+    pub account_uuid: Uuid,
+    pub rs_uuid: Uuid,
+    pub scopes: BTreeSet<String>,
+    pub client_state: String,
+    pub redirect_uri: Url,
+    code_challenge: String,
+    pub nonce: Option<String>,
+}
+
+impl Oauth2Session {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        state: Oauth2SessionState,
+        secret: String,
+        account_uuid: Uuid,
+        rs_uuid: Uuid,
+        scopes: BTreeSet<String>,
+        client_state: String,
+        redirect_uri: Url,
+        code_challenge: String,
+        nonce: Option<String>,
+    ) -> Self {
+        Oauth2Session {
+            state,
+            secret,
+            account_uuid,
+            rs_uuid,
+            scopes,
+            client_state,
+            redirect_uri,
+            code_challenge,
+            nonce,
+        }
+    }
+
+    /// Move a session to the code issued state, regenerating the secret so that
+    /// the consent token can not be reused as a code.
+    pub fn issue_code(&mut self, secret: String) {
+        self.state = Oauth2SessionState::CodeIssued;
+        self.secret = secret;
+    }
+
+    /// Consent tokens and codes are the session id and a random secret.
+    pub fn to_token(&self, sessionid: &Uuid) -> String {
+        format!("{}.{}", sessionid, self.secret)
+    }
+
+    pub fn check_secret(&self, secret: &str) -> bool {
+        self.secret.len() == secret.len()
+            && openssl::memcmp::eq(self.secret.as_bytes(), secret.as_bytes())
+    }
+
+    /// Check the verifier against the S256 challenge as per RFC 7636 4.6.
+    pub fn check_pkce(&self, code_verifier: &str) -> bool {
+        let hash = sha256(code_verifier.as_bytes());
+        let challenge = base64::encode_config(&hash, base64::URL_SAFE_NO_PAD);
+        challenge == self.code_challenge
+    }
+
+    pub fn scope_string(&self) -> String {
+        self.scopes
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Split a consent token or code into the session id and secret.
+pub(crate) fn parse_token(token: &str) -> Result<(Uuid, &str), Oauth2Error> {
+    let mut iter = token.splitn(2, '.');
+    let sessionid = iter
+        .next()
+        .and_then(|u| Uuid::parse_str(u).ok())
+        .ok_or(Oauth2Error::InvalidRequest)?;
+    let secret = iter.next().ok_or(Oauth2Error::InvalidRequest)?;
+    Ok((sessionid, secret))
+}
+
+/// Decode the client_id and secret from a http basic authorisation header value.
+pub(crate) fn parse_basic_authz(client_authz: &str) -> Result<(String, String), Oauth2Error> {
+    let b64 = client_authz
+        .strip_prefix("Basic ")
+        .ok_or(Oauth2Error::InvalidClient)?;
+    let authz = base64::decode(b64)
+        .ok()
+        .and_then(|b| String::from_utf8(b).ok())
+        .ok_or(Oauth2Error::InvalidClient)?;
+    let mut iter = authz.splitn(2, ':');
+    let client_id = iter.next().ok_or(Oauth2Error::InvalidClient)?;
+    let secret = iter.next().ok_or(Oauth2Error::InvalidClient)?;
+    Ok((client_id.to_string(), secret.to_string()))
+}
+
+pub(crate) fn parse_scopes(scope: &str) -> BTreeSet<String> {
+    scope.split_whitespace().map(|s| s.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::AuditScope;
+    use crate::constants::UUID_ADMIN;
+    use crate::entry::{Entry, EntryInit, EntryNew};
+    use crate::event::CreateEvent;
+    use crate::idm::account::Account;
+    use crate::idm::server::IdmServer;
+    use crate::server::QueryServerTransaction;
+    use crate::value::Value;
+    use kanidm_proto::jws::TokenPurpose;
+    use kanidm_proto::oauth2::*;
+    use kanidm_proto::v1::UserAuthToken;
+
+    use openssl::sha::sha256;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    const TEST_CURRENT_TIME: u64 = 6000;
+    const TEST_CODE_VERIFIER: &str = "Ahng3ieCh1tuchieR8mieXieghe1ahy3ahf3eetae";
+
+    fn setup_oauth2_resource_server(
+        idms: &IdmServer,
+        au: &mut AuditScope,
+        ct: Duration,
+    ) -> (String, UserAuthToken) {
+        let mut idms_prox_write = idms.proxy_write(ct);
+
+        let uuid = Uuid::new_v4();
+        let e: Entry<EntryInit, EntryNew> = entry_init!(
+            ("class", Value::new_class("object")),
+            ("class", Value::new_class("oauth2_resource_server")),
+            ("class", Value::new_class("oauth2_resource_server_basic")),
+            ("uuid", Value::new_uuid(uuid)),
+            ("oauth2_rs_name", Value::new_iname("test_resource_server")),
+            ("displayname", Value::new_utf8s("test_resource_server")),
+            (
+                "oauth2_rs_origin",
+                Value::new_utf8s("https://demo.example.com")
+            ),
+            (
+                "oauth2_rs_redirect_uri",
+                Value::new_utf8s("https://demo.example.com/oauth2/result")
+            ),
+            ("oauth2_rs_implicit_scopes", Value::new_iutf8("read"))
+        );
+        let ce = CreateEvent::new_internal(vec![e]);
+        assert!(idms_prox_write.qs_write.create(au, &ce).is_ok());
+
+        let secret = idms_prox_write
+            .qs_write
+            .internal_search_uuid(au, &uuid)
+            .ok()
+            .and_then(|e| {
+                e.get_ava_single_str("oauth2_rs_basic_secret")
+                    .map(|s| s.to_string())
+            })
+            .expect("No oauth2_rs_basic_secret found");
+
+        let account = idms_prox_write
+            .qs_write
+            .internal_search_uuid(au, &UUID_ADMIN)
+            .and_then(|e| Account::try_from_entry_rw(au, &e, &mut idms_prox_write.qs_write))
+            .expect("Failed to retrieve admin account");
+        let uat = account
+            .to_userauthtoken(&[], ct)
+            .expect("Unable to create uat");
+
+        idms_prox_write.commit(au).expect("Failed to commit");
+        (secret, uat)
+    }
+
+    fn test_auth_req(scope: &str) -> AuthorisationRequest {
+        let code_challenge = base64::encode_config(
+            &sha256(TEST_CODE_VERIFIER.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        );
+        AuthorisationRequest {
+            response_type: "code".to_string(),
+            client_id: "test_resource_server".to_string(),
+            state: "123".to_string(),
+            redirect_uri: "https://demo.example.com/oauth2/result".to_string(),
+            scope: scope.to_string(),
+            code_challenge,
+            code_challenge_method: CodeChallengeMethod::S256,
+            nonce: Some("abcdef".to_string()),
+        }
+    }
+
+    fn code_from_redirect(redirect_uri: &str) -> String {
+        url::Url::parse(redirect_uri)
+            .expect("Invalid redirect uri")
+            .query_pairs()
+            .find(|(k, _)| k == "code")
+            .map(|(_, v)| v.to_string())
+            .expect("No code in redirect")
+    }
+
+    fn decode_claims<T: serde::de::DeserializeOwned>(jws: &str) -> T {
+        let payload = jws.split('.').nth(1).expect("Invalid jws");
+        let bytes =
+            base64::decode_config(payload, base64::URL_SAFE_NO_PAD).expect("Invalid payload");
+        serde_json::from_slice(&bytes).expect("Invalid claims")
+    }
+
+    #[test]
+    fn test_idm_oauth2_basic_function() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       _idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let (secret, uat) = setup_oauth2_resource_server(idms, au, ct);
+
+            // First authorisation requires consent.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let consent_token = match idms_prox_write
+                .check_oauth2_authorisation(au, Some(&uat), &test_auth_req("openid read"), ct)
+                .expect("Failed to perform oauth2 authorisation request")
+            {
+                AuthorisationResponse::ConsentRequested {
+                    client_name,
+                    scopes,
+                    consent_token,
+                } => {
+                    assert!(client_name == "test_resource_server");
+                    assert!(scopes == vec!["openid".to_string(), "read".to_string()]);
+                    consent_token
+                }
+                _ => panic!("Consent should be requested"),
+            };
+
+            // Consent, this gives us the code.
+            let redirect_uri = match idms_prox_write
+                .check_oauth2_authorise_permit(au, Some(&uat), &consent_token, ct)
+                .expect("Failed to permit oauth2 authorisation")
+            {
+                AuthorisationResponse::Permitted { redirect_uri } => redirect_uri,
+                _ => panic!("Should be permitted"),
+            };
+            assert!(redirect_uri.starts_with("https://demo.example.com/oauth2/result?"));
+            let code = code_from_redirect(&redirect_uri);
+
+            // The consent token can not be replayed.
+            assert!(
+                idms_prox_write
+                    .check_oauth2_authorise_permit(au, Some(&uat), &consent_token, ct)
+                    .unwrap_err()
+                    == Oauth2Error::InvalidRequest
+            );
+
+            // Exchange the code.
+            let token_req = AccessTokenRequest {
+                grant_type: "authorization_code".to_string(),
+                code: Some(code.clone()),
+                redirect_uri: Some("https://demo.example.com/oauth2/result".to_string()),
+                code_verifier: Some(TEST_CODE_VERIFIER.to_string()),
+                client_id: Some("test_resource_server".to_string()),
+                client_secret: Some(secret.clone()),
+                ..Default::default()
+            };
+            let token = idms_prox_write
+                .check_oauth2_token_exchange(au, None, &token_req, ct)
+                .expect("Failed to exchange code");
+            assert!(token.scope == Some("openid read".to_string()));
+
+            let claims: AccessTokenClaims = decode_claims(&token.access_token);
+            assert!(claims.purpose == TokenPurpose::Oauth2Access);
+            assert!(claims.sub == uat.uuid);
+            assert!(claims.aud == "test_resource_server");
+            assert!(claims.iss == "https://idm.example.com/oauth2/openid/test_resource_server");
+
+            let oidc: OidcToken = decode_claims(token.id_token.as_deref().expect("No id_token"));
+            assert!(oidc.purpose == TokenPurpose::Oauth2Id);
+            assert!(oidc.nonce == Some("abcdef".to_string()));
+            assert!(oidc.preferred_username == Some("admin@example.com".to_string()));
+            assert!(oidc.groups.contains(&"system_admins".to_string()));
+
+            // Codes are single use.
+            assert!(
+                idms_prox_write
+                    .check_oauth2_token_exchange(au, None, &token_req, ct)
+                    .unwrap_err()
+                    == Oauth2Error::InvalidGrant
+            );
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // Consent was recorded, so the next authorisation is immediately permitted.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            match idms_prox_write
+                .check_oauth2_authorisation(au, Some(&uat), &test_auth_req("openid"), ct)
+                .expect("Failed to perform oauth2 authorisation request")
+            {
+                AuthorisationResponse::Permitted { .. } => {}
+                _ => panic!("Should be permitted"),
+            };
+            assert!(idms_prox_write.commit(au).is_ok());
+        })
+    }
+
+    #[test]
+    fn test_idm_oauth2_invalid_requests() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       _idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let (secret, uat) = setup_oauth2_resource_server(idms, au, ct);
+            let mut idms_prox_write = idms.proxy_write(ct);
+
+            // Not authenticated.
+            assert!(
+                idms_prox_write
+                    .check_oauth2_authorisation(au, None, &test_auth_req("read"), ct)
+                    .unwrap_err()
+                    == Oauth2Error::AuthenticationRequired
+            );
+
+            // Redirect to a different origin.
+            let mut auth_req = test_auth_req("read");
+            auth_req.redirect_uri = "https://evil.example.com/oauth2/result".to_string();
+            assert!(
+                idms_prox_write
+                    .check_oauth2_authorisation(au, Some(&uat), &auth_req, ct)
+                    .unwrap_err()
+                    == Oauth2Error::InvalidRequest
+            );
+
+            // Redirect to a path of the origin that wasn't registered.
+            let mut auth_req = test_auth_req("read");
+            auth_req.redirect_uri = "https://demo.example.com/other".to_string();
+            assert!(
+                idms_prox_write
+                    .check_oauth2_authorisation(au, Some(&uat), &auth_req, ct)
+                    .unwrap_err()
+                    == Oauth2Error::InvalidRequest
+            );
+
+            // Scope that isn't permitted.
+            assert!(
+                idms_prox_write
+                    .check_oauth2_authorisation(au, Some(&uat), &test_auth_req("write"), ct)
+                    .unwrap_err()
+                    == Oauth2Error::InvalidScope
+            );
+
+            // A token from a trusted domain can not claim the uuid of a local account.
+            let mut foreign_uat = uat.clone();
+            foreign_uat.spn = "admin@remote.example.com".to_string();
+            assert!(
+                idms_prox_write
+                    .check_oauth2_authorisation(au, Some(&foreign_uat), &test_auth_req("read"), ct)
+                    .unwrap_err()
+                    == Oauth2Error::AccessDenied
+            );
+
+            // Read only tokens and revoked api tokens can not authorise a resource server.
+            let mut ro_uat = uat.clone();
+            ro_uat.read_only = true;
+            assert!(
+                idms_prox_write
+                    .check_oauth2_authorisation(au, Some(&ro_uat), &test_auth_req("read"), ct)
+                    .unwrap_err()
+                    == Oauth2Error::AccessDenied
+            );
+            let mut revoked_uat = uat.clone();
+            revoked_uat.api_token_id = Some(Uuid::new_v4().to_string());
+            assert!(
+                idms_prox_write
+                    .check_oauth2_authorisation(au, Some(&revoked_uat), &test_auth_req("read"), ct)
+                    .unwrap_err()
+                    == Oauth2Error::AccessDenied
+            );
+
+            // Unknown client.
+            let mut auth_req = test_auth_req("read");
+            auth_req.client_id = "nonexistant".to_string();
+            assert!(
+                idms_prox_write
+                    .check_oauth2_authorisation(au, Some(&uat), &auth_req, ct)
+                    .unwrap_err()
+                    == Oauth2Error::InvalidClient
+            );
+
+            // Client credentials with a bad secret.
+            let authz = format!("Basic {}", base64::encode("test_resource_server:badsecret"));
+            let token_req = AccessTokenRequest {
+                grant_type: "client_credentials".to_string(),
+                ..Default::default()
+            };
+            assert!(
+                idms_prox_write
+                    .check_oauth2_token_exchange(au, Some(&authz), &token_req, ct)
+                    .unwrap_err()
+                    == Oauth2Error::InvalidClient
+            );
+
+            // And with the correct one.
+            let authz = format!(
+                "Basic {}",
+                base64::encode(format!("test_resource_server:{}", secret))
+            );
+            let token = idms_prox_write
+                .check_oauth2_token_exchange(au, Some(&authz), &token_req, ct)
+                .expect("Failed to perform client credentials grant");
+            assert!(token.id_token.is_none());
+            assert!(token.scope == Some("read".to_string()));
+
+            // A code with the wrong pkce verifier is rejected.
+            let code = match idms_prox_write
+                .check_oauth2_authorisation(au, Some(&uat), &test_auth_req("read"), ct)
+                .expect("Failed to perform oauth2 authorisation request")
+            {
+                AuthorisationResponse::ConsentRequested { consent_token, .. } => {
+                    match idms_prox_write
+                        .check_oauth2_authorise_permit(au, Some(&uat), &consent_token, ct)
+                        .expect("Failed to permit")
+                    {
+                        AuthorisationResponse::Permitted { redirect_uri } => {
+                            code_from_redirect(&redirect_uri)
+                        }
+                        _ => panic!("Should be permitted"),
+                    }
+                }
+                _ => panic!("Consent should be requested"),
+            };
+            let mut token_req = AccessTokenRequest {
+                grant_type: "authorization_code".to_string(),
+                code: Some(code),
+                redirect_uri: Some("https://demo.example.com/oauth2/result".to_string()),
+                code_verifier: Some("wrong".to_string()),
+                ..Default::default()
+            };
+            assert!(
+                idms_prox_write
+                    .check_oauth2_token_exchange(au, Some(&authz), &token_req, ct)
+                    .unwrap_err()
+                    == Oauth2Error::InvalidGrant
+            );
+            // The exchange is committed even though it failed, as the handler does.
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // The code was consumed by the failed exchange, so the correct verifier
+            // can not be tried next.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            token_req.code_verifier = Some(TEST_CODE_VERIFIER.to_string());
+            assert!(
+                idms_prox_write
+                    .check_oauth2_token_exchange(au, Some(&authz), &token_req, ct)
+                    .unwrap_err()
+                    == Oauth2Error::InvalidGrant
+            );
+            assert!(idms_prox_write.commit(au).is_ok());
+        })
+    }
+}
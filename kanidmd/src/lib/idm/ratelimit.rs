@@ -0,0 +1,234 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// Each failure past half of a threshold adds this to the delay of the next attempt.
+const DELAY_STEP_MS: u64 = 250;
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Softlocks protect each credential, but a client that tries one password against many
+/// accounts never trips them. To detect this, authentication failures are also counted by
+/// the address of the client, and by the subnet of that address. Once a source has failed
+/// half of its threshold in a window, each attempt is delayed, and once it reaches the
+/// threshold it's blocked until the window ends.
+///
+/// The thresholds are set by the administrator in `system_config`. A threshold of 0
+/// disables the limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// The number of failures from one address in a window before it's blocked.
+    pub address_max_failures: u32,
+    /// The number of failures from one subnet (/24 for ipv4, /64 for ipv6) in a window
+    /// before it's blocked.
+    pub subnet_max_failures: u32,
+    /// The length in seconds of the window in which failures are counted.
+    pub window: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            address_max_failures: 30,
+            subnet_max_failures: 150,
+            window: 600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    Address(IpAddr),
+    Subnet(IpAddr),
+}
+
+impl RateLimitKey {
+    /// The address and the subnet that a failure from this address counts towards.
+    pub fn from_addr(addr: IpAddr) -> [Self; 2] {
+        let subnet = match addr {
+            IpAddr::V4(a) => {
+                let o = a.octets();
+                IpAddr::from([o[0], o[1], o[2], 0])
+            }
+            IpAddr::V6(a) => {
+                let s = a.segments();
+                IpAddr::from([s[0], s[1], s[2], s[3], 0, 0, 0, 0])
+            }
+        };
+        [RateLimitKey::Address(addr), RateLimitKey::Subnet(subnet)]
+    }
+
+    fn max_failures(&self, config: &RateLimitConfig) -> u32 {
+        match self {
+            RateLimitKey::Address(_) => config.address_max_failures,
+            RateLimitKey::Subnet(_) => config.subnet_max_failures,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceLimit {
+    Allow,
+    Delay(Duration),
+    Block,
+}
+
+/// The failures of one source in the current window.
+#[derive(Debug, Clone)]
+pub struct SourceFailures {
+    window_start: Duration,
+    failures: u32,
+}
+
+impl SourceFailures {
+    pub fn new(ct: Duration) -> Self {
+        SourceFailures {
+            window_start: ct,
+            failures: 0,
+        }
+    }
+
+    pub fn is_expired(&self, ct: Duration, config: &RateLimitConfig) -> bool {
+        ct >= self.window_start + Duration::from_secs(config.window)
+    }
+
+    fn current(&self, ct: Duration, config: &RateLimitConfig) -> u32 {
+        if self.is_expired(ct, config) {
+            0
+        } else {
+            self.failures
+        }
+    }
+
+    /// Record a failure, and return whether this is the failure that blocks the source.
+    pub fn record_failure(
+        &mut self,
+        key: &RateLimitKey,
+        ct: Duration,
+        config: &RateLimitConfig,
+    ) -> bool {
+        if self.is_expired(ct, config) {
+            *self = SourceFailures::new(ct);
+        }
+        self.failures = self.failures.saturating_add(1);
+        let max = key.max_failures(config);
+        max > 0 && self.failures == max
+    }
+
+    pub fn limit(&self, key: &RateLimitKey, ct: Duration, config: &RateLimitConfig) -> SourceLimit {
+        let max = key.max_failures(config);
+        let failures = self.current(ct, config);
+        if max == 0 || failures < max / 2 {
+            SourceLimit::Allow
+        } else if failures >= max {
+            SourceLimit::Block
+        } else {
+            let steps = u64::from(failures - max / 2 + 1);
+            SourceLimit::Delay(std::cmp::min(
+                Duration::from_millis(DELAY_STEP_MS * steps),
+                MAX_DELAY,
+            ))
+        }
+    }
+}
+
+impl SourceLimit {
+    /// The most restrictive of two limits.
+    pub fn strictest(self, other: SourceLimit) -> SourceLimit {
+        match (self, other) {
+            (SourceLimit::Block, _) | (_, SourceLimit::Block) => SourceLimit::Block,
+            (SourceLimit::Delay(a), SourceLimit::Delay(b)) => {
+                SourceLimit::Delay(std::cmp::max(a, b))
+            }
+            (SourceLimit::Delay(d), _) | (_, SourceLimit::Delay(d)) => SourceLimit::Delay(d),
+            (SourceLimit::Allow, SourceLimit::Allow) => SourceLimit::Allow,
+        }
+    }
+}
+
+/// Counts of rate limiting since the server started, which are reported periodically.
+#[derive(Debug, Default)]
+pub struct RateLimitMetrics {
+    failures: AtomicU64,
+    delayed: AtomicU64,
+    blocked: AtomicU64,
+}
+
+impl RateLimitMetrics {
+    pub fn record(&self, limit: &SourceLimit) {
+        match limit {
+            SourceLimit::Allow => {}
+            SourceLimit::Delay(_) => {
+                self.delayed.fetch_add(1, Ordering::Relaxed);
+            }
+            SourceLimit::Block => {
+                self.blocked.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of failures, delayed attempts and blocked attempts.
+    pub fn get(&self) -> (u64, u64, u64) {
+        (
+            self.failures.load(Ordering::Relaxed),
+            self.delayed.load(Ordering::Relaxed),
+            self.blocked.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::idm::ratelimit::*;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_ratelimit_subnets() {
+        let v4: IpAddr = "192.0.2.17".parse().unwrap();
+        assert!(
+            RateLimitKey::from_addr(v4)[1] == RateLimitKey::Subnet("192.0.2.0".parse().unwrap())
+        );
+        let v6: IpAddr = "2001:db8:1:2:3:4:5:6".parse().unwrap();
+        assert!(
+            RateLimitKey::from_addr(v6)[1]
+                == RateLimitKey::Subnet("2001:db8:1:2::".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_ratelimit_source_failures() {
+        let config = RateLimitConfig {
+            address_max_failures: 4,
+            subnet_max_failures: 0,
+            window: 60,
+        };
+        let [addr, subnet] = RateLimitKey::from_addr("192.0.2.17".parse().unwrap());
+        let ct = Duration::from_secs(1000);
+        let mut sf = SourceFailures::new(ct);
+        assert!(sf.limit(&addr, ct, &config) == SourceLimit::Allow);
+
+        assert!(!sf.record_failure(&addr, ct, &config));
+        assert!(sf.limit(&addr, ct, &config) == SourceLimit::Allow);
+        // Past half of the threshold, attempts are delayed.
+        assert!(!sf.record_failure(&addr, ct, &config));
+        assert!(sf.limit(&addr, ct, &config) == SourceLimit::Delay(Duration::from_millis(250)));
+        assert!(!sf.record_failure(&addr, ct, &config));
+        assert!(sf.limit(&addr, ct, &config) == SourceLimit::Delay(Duration::from_millis(500)));
+        // Then blocked, only reported once.
+        assert!(sf.record_failure(&addr, ct, &config));
+        assert!(sf.limit(&addr, ct, &config) == SourceLimit::Block);
+        assert!(!sf.record_failure(&addr, ct, &config));
+        // A threshold of 0 is disabled.
+        assert!(sf.limit(&subnet, ct, &config) == SourceLimit::Allow);
+
+        // The block ends with the window.
+        let ct = ct + Duration::from_secs(60);
+        assert!(sf.limit(&addr, ct, &config) == SourceLimit::Allow);
+        assert!(!sf.record_failure(&addr, ct, &config));
+        assert!(sf.limit(&addr, ct, &config) == SourceLimit::Allow);
+    }
+}
@@ -1,38 +1,90 @@
 use crate::audit::AuditScope;
-use crate::constants::{AUTH_SESSION_TIMEOUT, MFAREG_SESSION_TIMEOUT, PW_MIN_LENGTH};
-use crate::constants::{UUID_ANONYMOUS, UUID_SYSTEM_CONFIG};
-use crate::credential::policy::CryptoPolicy;
-use crate::credential::softlock::CredSoftLock;
-use crate::credential::webauthn::WebauthnDomainConfig;
-use crate::event::{AuthEvent, AuthEventStep, AuthResult};
-use crate::idm::account::Account;
-use crate::idm::authsession::AuthSession;
+use crate::constants::{AUTH_SESSION_TIMEOUT, MFAREG_SESSION_TIMEOUT};
+use crate::constants::{
+    CREDRESET_INTENT_DEFAULT_TTL, CREDRESET_INTENT_MAX_TTL, CREDRESET_SESSION_TIMEOUT,
+};
+use crate::constants::{OAUTH2_ACCESS_TOKEN_EXPIRY, OAUTH2_SESSION_TIMEOUT};
+use crate::constants::{
+    UUID_ADMIN, UUID_ANONYMOUS, UUID_IDM_ADMIN, UUID_IDM_AUTH_AUDIT_READ_PRIV, UUID_SYSTEM_CONFIG,
+};
+use crate::credential::backupcode::BackupCodes;
+use crate::credential::breach::BreachCorpus;
+use crate::credential::policy::{CredentialStrength, CryptoPolicy, PasswordQualityPolicy};
+use crate::credential::softlock::{CredSoftLock, CredSoftLockPolicy, SoftLockConfig};
+use crate::credential::totp::TOTP;
+use crate::credential::webauthn::{WebauthnDomainConfig, WebauthnPolicy};
+use crate::credential::Credential;
+use crate::crypto::{ssh_public_key_blob, ClientCertificate, JwsSigner, SshCaSigner};
+use crate::entry::{Entry, EntryCommitted, EntrySealed};
+use crate::event::{
+    check_uat_api_token, check_uat_trust, AuthEvent, AuthEventStep, AuthEventStepCred,
+    AuthEventStepMech, AuthResult, Event, EventOrigin,
+};
+use crate::filter::f_eq;
+use crate::idm::account::{app_passwords_to_proto, Account};
+use crate::idm::authaudit::{to_proto_authauditrecord, AuthAuditConfig, AuthProtocol, AuthRecord};
+use crate::idm::authsession::{AuthSession, DiscoverableAuthSession};
 use crate::idm::event::{
-    GeneratePasswordEvent, GenerateTOTPEvent, LdapAuthEvent, PasswordChangeEvent,
-    RadiusAuthTokenEvent, RegenerateRadiusSecretEvent, RemoveTOTPEvent, RemoveWebauthnEvent,
-    UnixGroupTokenEvent, UnixPasswordChangeEvent, UnixUserAuthEvent, UnixUserTokenEvent,
-    VerifyTOTPEvent, WebauthnDoRegisterEvent, WebauthnInitRegisterEvent,
+    AuthAuditSearchEvent, ClearSoftLockEvent, CredentialResetBeginEvent,
+    CredentialResetIntentEvent, CredentialResetStepEvent, DestroyApiTokenEvent,
+    GenerateApiTokenEvent, GenerateAppPasswordEvent, GenerateBackupCodeEvent,
+    GeneratePasswordEvent, GenerateTOTPEvent, ImportTOTPEvent, LdapAuthEvent, ListApiTokenEvent,
+    ListAppPasswordEvent, PasswordChangeEvent, RadiusAuthEvent, RadiusAuthTokenEvent,
+    ReadSoftLockEvent, RegenerateRadiusSecretEvent, RemoveAppPasswordEvent, RemoveTOTPEvent,
+    RemoveWebauthnEvent, SshCertSignEvent, UnixGroupTokenEvent, UnixPasswordChangeEvent,
+    UnixUserAuthEvent, UnixUserTokenEvent, VerifyTOTPEvent, WebauthnDoRegisterEvent,
+    WebauthnInitRegisterEvent,
+};
+use crate::idm::group::Group;
+use crate::idm::mfareg::{
+    CredResetIntentClaims, CredResetSession, MfaRegCred, MfaRegNext, MfaRegSession,
+};
+use crate::idm::oauth2::{
+    oauth2_issuer, oauth2_rs_from_client_id, parse_basic_authz, parse_scopes, parse_token,
+    Oauth2RS, Oauth2Session, Oauth2SessionState, OAUTH2_SCOPE_OPENID,
 };
-use crate::idm::mfareg::{MfaRegCred, MfaRegNext, MfaRegSession};
 use crate::idm::radius::RadiusAccount;
+use crate::idm::ratelimit::{
+    RateLimitConfig, RateLimitKey, RateLimitMetrics, SourceFailures, SourceLimit,
+};
+use crate::idm::serviceaccount::ServiceAccount;
 use crate::idm::unix::{UnixGroup, UnixUserAccount};
 use crate::idm::AuthState;
 use crate::ldap::LdapBoundToken;
+use crate::modify::{Modify, ModifyList};
 use crate::server::QueryServerReadTransaction;
 use crate::server::{QueryServer, QueryServerTransaction, QueryServerWriteTransaction};
-use crate::utils::{password_from_random, readable_password_from_random, uuid_from_duration, SID};
-use crate::value::PartialValue;
+use crate::utils::{
+    duration_from_epoch_now, password_from_random, readable_password_from_random,
+    uuid_from_duration, SID,
+};
+use crate::value::{ApiTokenMeta, PartialValue, Value};
 
 use crate::actors::v1_write::QueryServerWriteV1;
+use crate::be::dbvalue::{DbAuthRecordV1, DbSoftLockV1};
 use crate::idm::delayed::{
-    DelayedAction, PasswordUpgrade, UnixPasswordUpgrade, WebauthnCounterIncrement,
+    ApiTokenUsed, DelayedAction, LastLogin, PasswordUpgrade, SoftLockUpdate, UnixPasswordUpgrade,
+    WebauthnCounterIncrement,
 };
 
+use kanidm_proto::jws::TokenPurpose;
+use kanidm_proto::oauth2::{
+    AccessTokenClaims, AccessTokenRequest, AccessTokenResponse, AuthorisationRequest,
+    AuthorisationResponse, JwkKeySet, Oauth2Error, OidcDiscoveryResponse, OidcToken,
+};
+use kanidm_proto::v1::ApiToken;
+use kanidm_proto::v1::AppPassword;
+use kanidm_proto::v1::AppPasswordScope;
+use kanidm_proto::v1::AuthAuditRecord;
+use kanidm_proto::v1::AuthCredential;
+use kanidm_proto::v1::CredentialSoftLock;
 use kanidm_proto::v1::OperationError;
 use kanidm_proto::v1::RadiusAuthToken;
 use kanidm_proto::v1::SetCredentialResponse;
 use kanidm_proto::v1::UnixGroupToken;
 use kanidm_proto::v1::UnixUserToken;
+use kanidm_proto::v1::UserAuthToken;
+use kanidm_proto::v1::{CredentialResetIntent, CredentialResetSession, SetCredentialRequest};
 
 use tokio::sync::mpsc::{
     unbounded_channel as unbounded, UnboundedReceiver as Receiver, UnboundedSender as Sender,
@@ -47,14 +99,27 @@ use core::task::{Context, Poll};
 use futures::task as futures_task;
 
 use concread::bptree::{BptreeMap, BptreeMapWriteTxn};
-use concread::hashmap::HashMap;
+use concread::hashmap::{HashMap, HashMapWriteTxn};
+use futures::FutureExt;
+use openssl::x509::X509;
 use rand::prelude::*;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
 use std::time::Duration;
 use url::Url;
 use uuid::Uuid;
 
 use webauthn_rs::Webauthn;
 
+const UNKNOWN_DISCOVERABLE_MSG: &str = "unknown discoverable webauthn credential";
+const RATE_LIMITED_MSG: &str = "Too many failed authentications, try again later";
+const INVALID_TIME_MSG: &str = "Account is not within valid time period";
+// Seconds that ssh certificates are valid before they're issued.
+const SSH_CERT_CLOCK_SKEW: u64 = 300;
+const BAD_CERTIFICATE_MSG: &str = "client certificate is not bound to an account";
+const CERTIFICATE_POLICY_MSG: &str = "client certificate does not meet the credential policy";
+
 pub struct IdmServer {
     // There is a good reason to keep this single thread - it
     // means that limits to sessions can be easily applied and checked to
@@ -62,11 +127,26 @@ pub struct IdmServer {
     // in memory caches related to locking.
     session_ticket: Semaphore,
     sessions: BptreeMap<Uuid, AuthSession>,
+    // Sessions that have not yet been bound to an account by a discoverable credential.
+    discoverable_sessions: BptreeMap<Uuid, DiscoverableAuthSession>,
     // Do we need a softlock ticket?
     softlock_ticket: Semaphore,
     softlocks: HashMap<Uuid, CredSoftLock>,
+    // Changes to softlocks, to be written to the database so they survive a restart.
+    softlock_tx: Sender<SoftLockUpdate>,
+    // Authentication failures by client address and subnet, to limit bruteforcing
+    // across many accounts.
+    auth_source_ticket: Semaphore,
+    auth_sources: HashMap<RateLimitKey, SourceFailures>,
+    auth_source_metrics: RateLimitMetrics,
+    // Authentication attempts, to be written to the audit store.
+    audit_tx: Sender<DbAuthRecordV1>,
     // Keep a set of inprogress mfa registrations
     mfareg_sessions: BptreeMap<Uuid, MfaRegSession>,
+    // Redeemed credential reset tokens, that are still within their window
+    credreset_sessions: BptreeMap<Uuid, CredResetSession>,
+    // Inprogress oauth2 consent requests and issued authorisation codes
+    oauth2_sessions: BptreeMap<Uuid, Oauth2Session>,
     // Need a reference to the query server.
     qs: QueryServer,
     // The configured crypto policy for the IDM server. Later this could be transactional
@@ -75,6 +155,10 @@ pub struct IdmServer {
     async_tx: Sender<DelayedAction>,
     // Our webauthn verifier/config
     webauthn: Webauthn<WebauthnDomainConfig>,
+    // The origin we are served from, used to build oauth2 issuer and endpoint urls.
+    origin: Url,
+    // Breached passwords that may not be set, if a corpus is configured.
+    breach_corpus: Option<BreachCorpus>,
 }
 
 pub struct IdmServerWriteTransaction<'a> {
@@ -85,21 +169,32 @@ pub struct IdmServerWriteTransaction<'a> {
     // sessions: BptreeMapWriteTxn<'a, Uuid, AuthSession>,
     session_ticket: &'a Semaphore,
     sessions: &'a BptreeMap<Uuid, AuthSession>,
+    discoverable_sessions: &'a BptreeMap<Uuid, DiscoverableAuthSession>,
 
     softlock_ticket: &'a Semaphore,
     softlocks: &'a HashMap<Uuid, CredSoftLock>,
+    softlock_tx: Sender<SoftLockUpdate>,
+    auth_source_ticket: &'a Semaphore,
+    auth_sources: &'a HashMap<RateLimitKey, SourceFailures>,
+    auth_source_metrics: &'a RateLimitMetrics,
+    audit_tx: Sender<DbAuthRecordV1>,
     pub qs_read: QueryServerReadTransaction<'a>,
     // thread/server id
     sid: SID,
     // For flagging eventual actions.
     async_tx: Sender<DelayedAction>,
+    // Passwords hashed with weaker parameters than this are upgraded on use.
+    crypto_policy: &'a CryptoPolicy,
     webauthn: &'a Webauthn<WebauthnDomainConfig>,
+    breach_corpus: Option<&'a BreachCorpus>,
 }
 
 pub struct IdmServerProxyReadTransaction<'a> {
     // This contains read-only methods, like getting users, groups
     // and other structured content.
     pub qs_read: QueryServerReadTransaction<'a>,
+    softlocks: &'a HashMap<Uuid, CredSoftLock>,
+    origin: &'a Url,
 }
 
 pub struct IdmServerProxyWriteTransaction<'a> {
@@ -108,13 +203,23 @@ pub struct IdmServerProxyWriteTransaction<'a> {
     pub qs_write: QueryServerWriteTransaction<'a>,
     // Associate to an event origin ID, which has a TS and a UUID instead
     mfareg_sessions: BptreeMapWriteTxn<'a, Uuid, MfaRegSession>,
+    credreset_sessions: BptreeMapWriteTxn<'a, Uuid, CredResetSession>,
+    oauth2_sessions: BptreeMapWriteTxn<'a, Uuid, Oauth2Session>,
+    softlocks: &'a HashMap<Uuid, CredSoftLock>,
+    softlock_tx: Sender<SoftLockUpdate>,
+    // Credentials whose softlocks are cleared once this transaction commits.
+    softlock_clear: Vec<Uuid>,
     sid: SID,
     crypto_policy: &'a CryptoPolicy,
     webauthn: &'a Webauthn<WebauthnDomainConfig>,
+    origin: &'a Url,
+    breach_corpus: Option<&'a BreachCorpus>,
 }
 
 pub struct IdmServerDelayed {
     async_rx: Receiver<DelayedAction>,
+    softlock_rx: Receiver<SoftLockUpdate>,
+    audit_rx: Receiver<DbAuthRecordV1>,
 }
 
 impl IdmServer {
@@ -123,16 +228,12 @@ impl IdmServer {
         au: &mut AuditScope,
         qs: QueryServer,
         origin: String,
+        breach_corpus_path: Option<&str>,
+        crypto_policy: CryptoPolicy,
     ) -> Result<(IdmServer, IdmServerDelayed), OperationError> {
-        // This is calculated back from:
-        //  500 auths / thread -> 0.002 sec per op
-        //      we can then spend up to ~0.001s hashing
-        //      that means an attacker could possibly have
-        //      1000 attempts/sec on a compromised pw.
-        // overtime, we could increase this as auth parallelism
-        // improves.
-        let crypto_policy = CryptoPolicy::time_target(Duration::from_millis(1));
         let (async_tx, async_rx) = unbounded();
+        let (softlock_tx, softlock_rx) = unbounded();
+        let (audit_tx, audit_rx) = unbounded();
 
         // Get the domain name, as the relying party id.
         let rp_id = {
@@ -141,7 +242,7 @@ impl IdmServer {
         };
 
         // Check that it gels with our origin.
-        Url::parse(origin.as_str())
+        let origin_url = Url::parse(origin.as_str())
             .map_err(|_e| {
                 ladmin_error!(au, "Unable to parse origin URL - refusing to start. You must correct the value for origin. {:?}", origin);
                 OperationError::InvalidState
@@ -158,6 +259,7 @@ impl IdmServer {
                     ladmin_error!(au, "Effective domain is not a descendent of server domain name (rp_id). You must change origin or domain name to be consistent. ed: {:?} - rp_id: {:?}", origin, rp_id);
                     OperationError::InvalidState
                 })
+                .map(|_| url)
             })?;
 
         // Now clone to rp_name.
@@ -169,19 +271,52 @@ impl IdmServer {
             rp_id,
         });
 
+        let breach_corpus = breach_corpus_path
+            .map(|p| BreachCorpus::open(au, Path::new(p)))
+            .transpose()?;
+
+        // Restore the softlocks that were in effect when the server stopped, so that a
+        // restart does not reset the protection against bruteforcing.
+        let softlocks = HashMap::new();
+        {
+            let ct = duration_from_epoch_now();
+            let qs_write = task::block_on(qs.write_async(ct));
+            qs_write.purge_softlocks(au, &ct)?;
+            let mut softlock_write = softlocks.write();
+            for (cred_uuid, dbs) in qs_write.get_softlocks(au)? {
+                softlock_write.insert(cred_uuid, CredSoftLock::from_dbsoftlock_v1(dbs));
+            }
+            softlock_write.commit();
+            qs_write.commit(au)?;
+        }
+
         Ok((
             IdmServer {
                 session_ticket: Semaphore::new(1),
                 sessions: BptreeMap::new(),
+                discoverable_sessions: BptreeMap::new(),
                 softlock_ticket: Semaphore::new(1),
-                softlocks: HashMap::new(),
+                softlocks,
+                softlock_tx,
+                auth_source_ticket: Semaphore::new(1),
+                auth_sources: HashMap::new(),
+                auth_source_metrics: RateLimitMetrics::default(),
+                audit_tx,
                 mfareg_sessions: BptreeMap::new(),
+                credreset_sessions: BptreeMap::new(),
+                oauth2_sessions: BptreeMap::new(),
                 qs,
                 crypto_policy,
                 async_tx,
                 webauthn,
+                origin: origin_url,
+                breach_corpus,
+            },
+            IdmServerDelayed {
+                async_rx,
+                softlock_rx,
+                audit_rx,
             },
-            IdmServerDelayed { async_rx },
         ))
     }
 
@@ -203,12 +338,20 @@ impl IdmServer {
             // sessions: self.sessions.write(),
             session_ticket: &self.session_ticket,
             sessions: &self.sessions,
+            discoverable_sessions: &self.discoverable_sessions,
             softlock_ticket: &self.softlock_ticket,
             softlocks: &self.softlocks,
+            softlock_tx: self.softlock_tx.clone(),
+            auth_source_ticket: &self.auth_source_ticket,
+            auth_sources: &self.auth_sources,
+            auth_source_metrics: &self.auth_source_metrics,
+            audit_tx: self.audit_tx.clone(),
             qs_read,
             sid,
             async_tx: self.async_tx.clone(),
+            crypto_policy: &self.crypto_policy,
             webauthn: &self.webauthn,
+            breach_corpus: self.breach_corpus.as_ref(),
         }
     }
 
@@ -220,6 +363,8 @@ impl IdmServer {
     pub async fn proxy_read_async(&self) -> IdmServerProxyReadTransaction<'_> {
         IdmServerProxyReadTransaction {
             qs_read: self.qs.read_async().await,
+            softlocks: &self.softlocks,
+            origin: &self.origin,
         }
     }
 
@@ -236,10 +381,17 @@ impl IdmServer {
 
         IdmServerProxyWriteTransaction {
             mfareg_sessions: self.mfareg_sessions.write(),
+            credreset_sessions: self.credreset_sessions.write(),
+            oauth2_sessions: self.oauth2_sessions.write(),
+            softlocks: &self.softlocks,
+            softlock_tx: self.softlock_tx.clone(),
+            softlock_clear: Vec::new(),
             qs_write,
             sid,
             crypto_policy: &self.crypto_policy,
             webauthn: &self.webauthn,
+            origin: &self.origin,
+            breach_corpus: self.breach_corpus.as_ref(),
         }
     }
 
@@ -255,6 +407,375 @@ impl IdmServer {
             .and_then(|_| pw.commit(au))
             .map(|()| true)
     }
+
+    #[cfg(test)]
+    pub(crate) async fn softlock_update(
+        &self,
+        au: &mut AuditScope,
+        ts: Duration,
+        slu: SoftLockUpdate,
+    ) -> Result<(), OperationError> {
+        let mut pw = self.proxy_write_async(ts).await;
+        let mut updates = BTreeMap::new();
+        updates.insert(slu.cred_uuid, slu.state);
+        pw.process_softlockupdate(au, updates, ts)
+            .and_then(|_| pw.commit(au))
+    }
+
+    /// Record that an api token was used. This is done as a delayed action so that
+    /// read only requests made with the token do not need a write transaction.
+    pub(crate) fn note_api_token_use(
+        &self,
+        au: &mut AuditScope,
+        uat: &UserAuthToken,
+        ct: Duration,
+    ) {
+        let ids = uat.api_token_id.as_deref().and_then(|token_id| {
+            Uuid::parse_str(token_id)
+                .ok()
+                .zip(Uuid::parse_str(uat.uuid.as_str()).ok())
+        });
+
+        if let Some((token_id, target_uuid)) = ids {
+            if self
+                .async_tx
+                .send(DelayedAction::ApiTokenUsed(ApiTokenUsed {
+                    target_uuid,
+                    token_id,
+                    ct,
+                }))
+                .is_err()
+            {
+                ladmin_error!(au, "unable to queue api token use, continuing ... ");
+            }
+        }
+    }
+
+    /// Replace the expired password of an auth session. The password is committed before
+    /// the session is marked as changed, so that auth only completes the session and issues
+    /// a uat once the change has been written. Any other step is left to auth.
+    pub async fn change_expired_password(
+        &self,
+        au: &mut AuditScope,
+        ae: &AuthEvent,
+        ct: Duration,
+    ) -> Result<(), OperationError> {
+        let (sessionid, cleartext) = match &ae.step {
+            AuthEventStep::Cred(AuthEventStepCred {
+                sessionid,
+                cred: AuthCredential::PasswordChange(cleartext),
+            }) => (sessionid, cleartext.as_str()),
+            _ => return Ok(()),
+        };
+
+        let target = match self.sessions.read().get(sessionid) {
+            Some(auth_session) if auth_session.is_password_expired() => {
+                auth_session.get_account().uuid
+            }
+            // Auth will report why the session can't proceed.
+            _ => return Ok(()),
+        };
+
+        let mut idms_prox_write = self.proxy_write_async(ct).await;
+        idms_prox_write
+            .set_expired_password(au, &target, cleartext, ct)
+            .and_then(|_| idms_prox_write.commit(au))?;
+
+        let _session_ticket = self.session_ticket.acquire().await;
+        let mut session_write = self.sessions.write();
+        if let Some(auth_session) = session_write.get_mut(sessionid) {
+            auth_session.expired_password_changed();
+        }
+        session_write.commit();
+        Ok(())
+    }
+}
+
+/// Read the softlock thresholds from the system configuration. Any that are unset or zero
+/// use the defaults.
+fn softlock_config<T: QueryServerTransaction>(
+    au: &mut AuditScope,
+    qs: &T,
+) -> Result<SoftLockConfig, OperationError> {
+    let config_entry = qs
+        .internal_search_uuid(au, &UUID_SYSTEM_CONFIG)
+        .map_err(|e| {
+            ladmin_error!(au, "Failed to retrieve system configuration {:?}", e);
+            e
+        })?;
+    let get = |attr: &str| config_entry.get_ava_single_uint32(attr).filter(|v| *v > 0);
+    let defaults = SoftLockConfig::default();
+
+    Ok(SoftLockConfig {
+        max_failures: get("softlock_max_failures")
+            .map(|v| v as usize)
+            .unwrap_or(defaults.max_failures),
+        max_delay: get("softlock_max_delay")
+            .map(u64::from)
+            .unwrap_or(defaults.max_delay),
+        reset_window: get("softlock_reset_window")
+            .map(u64::from)
+            .unwrap_or(defaults.reset_window),
+    })
+}
+
+/// Read the authentication rate limits from the system configuration. Any that are unset
+/// use the defaults, but unlike the softlocks a threshold may be set to 0 to disable it,
+/// such as when every client is behind the same proxy.
+fn ratelimit_config<T: QueryServerTransaction>(
+    au: &mut AuditScope,
+    qs: &T,
+) -> Result<RateLimitConfig, OperationError> {
+    let config_entry = qs
+        .internal_search_uuid(au, &UUID_SYSTEM_CONFIG)
+        .map_err(|e| {
+            ladmin_error!(au, "Failed to retrieve system configuration {:?}", e);
+            e
+        })?;
+    let defaults = RateLimitConfig::default();
+
+    Ok(RateLimitConfig {
+        address_max_failures: config_entry
+            .get_ava_single_uint32("auth_ratelimit_address_failures")
+            .unwrap_or(defaults.address_max_failures),
+        subnet_max_failures: config_entry
+            .get_ava_single_uint32("auth_ratelimit_subnet_failures")
+            .unwrap_or(defaults.subnet_max_failures),
+        window: config_entry
+            .get_ava_single_uint32("auth_ratelimit_window")
+            .filter(|v| *v > 0)
+            .map(u64::from)
+            .unwrap_or(defaults.window),
+    })
+}
+
+/// Read the bounds of the authentication audit store from the system configuration. A
+/// value of 0 is treated as unset, as an audit store that keeps nothing is not useful.
+fn auth_audit_config<T: QueryServerTransaction>(
+    au: &mut AuditScope,
+    qs: &T,
+) -> Result<AuthAuditConfig, OperationError> {
+    let config_entry = qs
+        .internal_search_uuid(au, &UUID_SYSTEM_CONFIG)
+        .map_err(|e| {
+            ladmin_error!(au, "Failed to retrieve system configuration {:?}", e);
+            e
+        })?;
+    let defaults = AuthAuditConfig::default();
+
+    Ok(AuthAuditConfig {
+        retention: config_entry
+            .get_ava_single_uint32("auth_audit_retention_days")
+            .filter(|v| *v > 0)
+            .map(|v| Duration::from_secs(u64::from(v) * 86400))
+            .unwrap_or(defaults.retention),
+        max_records: config_entry
+            .get_ava_single_uint32("auth_audit_max_records")
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.max_records),
+    })
+}
+
+fn webauthn_policy<T: QueryServerTransaction>(
+    au: &mut AuditScope,
+    qs: &T,
+) -> Result<WebauthnPolicy, OperationError> {
+    let config_entry = qs
+        .internal_search_uuid(au, &UUID_SYSTEM_CONFIG)
+        .map_err(|e| {
+            ladmin_error!(au, "Failed to retrieve system configuration {:?}", e);
+            e
+        })?;
+
+    // A CA that can't be parsed must not silently widen what is trusted, so refuse
+    // to continue rather than skipping it.
+    let attestation_ca = config_entry
+        .get_ava_as_str("webauthn_attestation_ca")
+        .map(|iter| {
+            iter.map(|pem| {
+                X509::from_pem(pem.as_bytes()).map_err(|e| {
+                    ladmin_error!(au, "Invalid webauthn attestation ca -> {:?}", e);
+                    OperationError::InvalidState
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?
+        .unwrap_or_else(Vec::new);
+
+    let attestation_aaguid = config_entry
+        .get_ava("webauthn_attestation_aaguid")
+        .map(|iter| iter.filter_map(|v| v.to_uuid().copied()).collect())
+        .unwrap_or_default();
+
+    Ok(WebauthnPolicy {
+        require_verification: config_entry
+            .get_ava_single_bool("webauthn_user_verification")
+            .unwrap_or(false),
+        attestation_ca,
+        attestation_aaguid,
+    })
+}
+
+/// Find the account that a discoverable webauthn credential belongs to. The credential
+/// id is looked up in the index, and the user handle held by the authenticator must
+/// name the same account.
+fn discoverable_account(
+    au: &mut AuditScope,
+    qs: &mut QueryServerReadTransaction,
+    cred: &AuthCredential,
+) -> Result<Option<Account>, OperationError> {
+    let resp = match cred {
+        AuthCredential::Webauthn(resp) => resp,
+        _ => return Ok(None),
+    };
+
+    let cid = base64::encode_config(&resp.raw_id.0, base64::URL_SAFE_NO_PAD);
+    let mut entries = qs.internal_search(
+        au,
+        filter!(f_eq(
+            "webauthn_credential_id",
+            PartialValue::new_utf8s(cid.as_str())
+        )),
+    )?;
+    let entry = match entries.pop() {
+        Some(e) if entries.is_empty() => e,
+        _ => {
+            lsecurity!(au, "No single account holds webauthn credential {}", cid);
+            return Ok(None);
+        }
+    };
+
+    // Tokens are registered with the account name as the user handle.
+    let handle_uuid = resp.response.user_handle.as_ref().and_then(|h| {
+        match std::str::from_utf8(h.0.as_slice()) {
+            Ok(name) => qs.name_to_uuid(au, name).ok(),
+            Err(_) => Uuid::from_slice(h.0.as_slice()).ok(),
+        }
+    });
+    if handle_uuid.as_ref() != Some(entry.get_uuid()) {
+        lsecurity!(
+            au,
+            "Webauthn user handle does not name the account {} holding the credential",
+            entry.get_uuid()
+        );
+        return Ok(None);
+    }
+
+    Account::try_from_entry_ro(au, &entry, qs).map(Some)
+}
+
+/// Record an authentication failure of a credential, creating its softlock if the
+/// credential type supports one, and queue the new state to be persisted.
+fn record_softlock_failure(
+    au: &mut AuditScope,
+    softlock_write: &mut HashMapWriteTxn<Uuid, CredSoftLock>,
+    softlock_tx: &Sender<SoftLockUpdate>,
+    cred_uuid: Uuid,
+    policy: Option<CredSoftLockPolicy>,
+    config: &SoftLockConfig,
+    ct: Duration,
+) {
+    let state = if let Some(slock) = softlock_write.get_mut(&cred_uuid) {
+        // Update it.
+        slock.record_failure(ct, config);
+        slock.to_dbsoftlock_v1()
+    } else if let Some(policy) = policy {
+        // Create if not exist, and the cred type supports softlocking.
+        let mut slock = CredSoftLock::new(policy);
+        slock.record_failure(ct, config);
+        let state = slock.to_dbsoftlock_v1();
+        softlock_write.insert(cred_uuid, slock);
+        state
+    } else {
+        return;
+    };
+
+    if softlock_tx
+        .send(SoftLockUpdate { cred_uuid, state })
+        .is_err()
+    {
+        ladmin_error!(
+            au,
+            "CRITICAL: unable to queue softlock update, continuing ... "
+        );
+    }
+}
+
+/// Queue an authentication attempt to be written to the audit store.
+fn queue_auth_record(
+    au: &mut AuditScope,
+    audit_tx: &Sender<DbAuthRecordV1>,
+    record: DbAuthRecordV1,
+) {
+    ltrace!(au, "auth record -> {:?}", record);
+    if audit_tx.send(record).is_err() {
+        ladmin_warning!(au, "unable to queue auth audit record, continuing ... ");
+    }
+}
+
+/// If the credential is softlocked, such as after the failure that was just recorded.
+fn is_softlocked(
+    softlock_write: &HashMapWriteTxn<Uuid, CredSoftLock>,
+    cred_uuid: Option<Uuid>,
+) -> bool {
+    cred_uuid
+        .and_then(|cu| softlock_write.get(&cu))
+        .map(|slock| !slock.is_valid())
+        .unwrap_or(false)
+}
+
+/// The reason a unix or ldap bind that reached the credential failed.
+fn bind_failure(is_valid: bool) -> &'static str {
+    if is_valid {
+        "Invalid credentials"
+    } else {
+        "Account is temporarily locked"
+    }
+}
+
+/// Queue the record of a successful authentication. Sessions without a mechanism, such
+/// as anonymous, are not recorded.
+fn queue_last_login(
+    au: &mut AuditScope,
+    async_tx: &Sender<DelayedAction>,
+    target_uuid: Uuid,
+    mechanism: Option<&'static str>,
+    ct: Duration,
+) {
+    if let Some(mechanism) = mechanism {
+        if async_tx
+            .send(DelayedAction::LastLogin(LastLogin {
+                target_uuid,
+                mechanism,
+                ct,
+            }))
+            .is_err()
+        {
+            ladmin_warning!(au, "unable to queue last login update, continuing ... ");
+        }
+    }
+}
+
+/// The credentials of an account that can be softlocked, with the name they are
+/// displayed as. Application passwords share the softlock of the unix credential,
+/// which is the account uuid when there is no unix password.
+fn softlock_credentials(entry: &Entry<EntrySealed, EntryCommitted>) -> Vec<(&'static str, Uuid)> {
+    let primary = entry
+        .get_ava_single_credential("primary_credential")
+        .map(|cred| ("primary", cred.uuid));
+    let unix = entry
+        .get_ava_single_credential("unix_password")
+        .map(|cred| cred.uuid)
+        .or_else(|| {
+            entry
+                .get_ava_as_credentials("app_password")
+                .and_then(|mut creds| creds.next())
+                .map(|_| *entry.get_uuid())
+        })
+        .map(|cred_uuid| ("unix", cred_uuid));
+
+    primary.into_iter().chain(unix).collect()
 }
 
 impl IdmServerDelayed {
@@ -279,16 +800,175 @@ impl IdmServerDelayed {
         }
     }
 
+    // Successful authentications queue a last login, which tests that are not about
+    // last logins discard here. Any other queued action is a test failure.
+    #[cfg(test)]
+    pub(crate) fn skip_last_login(&mut self) {
+        let waker = futures_task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        while let Poll::Ready(Some(da)) = self.async_rx.poll_recv(&mut cx) {
+            if !matches!(da, DelayedAction::LastLogin(_)) {
+                panic!("Task queue has an action other than a last login");
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn try_recv_softlock(&mut self) -> Result<SoftLockUpdate, OperationError> {
+        let waker = futures_task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        match self.softlock_rx.poll_recv(&mut cx) {
+            Poll::Pending => Err(OperationError::InvalidState),
+            Poll::Ready(None) => Err(OperationError::QueueDisconnected),
+            Poll::Ready(Some(m)) => Ok(m),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn try_recv_auth_record(&mut self) -> Result<DbAuthRecordV1, OperationError> {
+        let waker = futures_task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        match self.audit_rx.poll_recv(&mut cx) {
+            Poll::Pending => Err(OperationError::InvalidState),
+            Poll::Ready(None) => Err(OperationError::QueueDisconnected),
+            Poll::Ready(Some(m)) => Ok(m),
+        }
+    }
+
     pub(crate) async fn process_all(&mut self, server: &'static QueryServerWriteV1) {
         loop {
-            match self.async_rx.recv().await {
-                // process it.
-                Some(da) => server.handle_delayedaction(da).await,
-                // Channel has closed
-                None => return,
+            let slu = tokio::select! {
+                da = self.async_rx.recv() => match da {
+                    // process it.
+                    Some(da) => {
+                        server.handle_delayedaction(da).await;
+                        continue;
+                    }
+                    // Channel has closed
+                    None => return,
+                },
+                ar = self.audit_rx.recv() => match ar {
+                    // Write everything that is queued in one transaction.
+                    Some(ar) => {
+                        let mut records = vec![ar];
+                        while let Some(Some(ar)) = self.audit_rx.recv().now_or_never() {
+                            records.push(ar);
+                        }
+                        server.handle_authrecords(records).await;
+                        continue;
+                    }
+                    None => return,
+                },
+                slu = self.softlock_rx.recv() => match slu {
+                    Some(slu) => slu,
+                    None => return,
+                },
+            };
+
+            // Only the latest state of each credential matters, so take everything that
+            // is queued and write it in one transaction.
+            let mut updates = BTreeMap::new();
+            updates.insert(slu.cred_uuid, slu.state);
+            while let Some(Some(slu)) = self.softlock_rx.recv().now_or_never() {
+                updates.insert(slu.cred_uuid, slu.state);
+            }
+            server.handle_softlockupdate(updates).await;
+        }
+    }
+}
+
+// This is shared by the proxy write and auth transactions, as an expired password may be
+// changed during authentication.
+fn check_password_quality<T: QueryServerTransaction>(
+    au: &mut AuditScope,
+    qs: &T,
+    cleartext: &str,
+    related_inputs: &[&str],
+    existing: Option<&Credential>,
+    groups: &[Group],
+    breach_corpus: Option<&BreachCorpus>,
+) -> Result<(), OperationError> {
+    // The quality requirements are set on the system config, and the groups of the account
+    // may override them. Mfa requirements are a separate group credential policy.
+    let config_entry = qs
+        .internal_search_uuid(au, &UUID_SYSTEM_CONFIG)
+        .map_err(|e| {
+            ladmin_error!(au, "Failed to retrieve system configuration {:?}", e);
+            e
+        })?;
+    let policy = Group::password_quality(
+        groups,
+        PasswordQualityPolicy::from_system_config(&config_entry),
+    );
+    ltrace!(au, "password quality policy -> {:?}", policy);
+
+    // is the password long enough?
+    if cleartext.len() < policy.min_length {
+        return Err(OperationError::PasswordTooShort(policy.min_length));
+    }
+
+    if PasswordQualityPolicy::char_classes(cleartext) < policy.min_char_classes {
+        return Err(OperationError::PasswordTooFewCharClasses(
+            policy.min_char_classes,
+        ));
+    }
+
+    if let Some(word) = policy.find_badlist_word(cleartext) {
+        lsecurity!(au, "Password contains badlisted word {}, rejecting", word);
+        return Err(OperationError::PasswordBadListed);
+    }
+
+    // does the password pass zxcvbn? The badlisted words are also given, so that
+    // variations of them are scored as weak.
+    let inputs: Vec<&str> = related_inputs
+        .iter()
+        .copied()
+        .chain(policy.badlist_words.iter().map(|w| w.as_str()))
+        .collect();
+    let entropy = zxcvbn::zxcvbn(cleartext, inputs.as_slice()).map_err(|e| {
+        ladmin_error!(au, "zxcvbn check failure (password empty?) {:?}", e);
+        OperationError::PasswordEmpty
+    })?;
+
+    if u32::from(entropy.score()) < policy.min_score {
+        // The password is too weak as per:
+        // https://docs.rs/zxcvbn/2.0.0/zxcvbn/struct.Entropy.html
+        // zxcvbn only gives feedback for scores below 3, so a higher minimum may
+        // reject a password without it.
+        let feedback = entropy.feedback().clone();
+        lsecurity!(au, "pw feedback -> {:?}", feedback);
+        return Err(OperationError::PasswordTooWeak(feedback));
+    }
+
+    // check a password badlist to eliminate more content
+    // we check the password as "lower case" to help eliminate possibilities
+    let lc_password = PartialValue::new_iutf8(cleartext);
+    if config_entry.attribute_value_pres("badlist_password", &lc_password) {
+        lsecurity!(au, "Password found in badlist, rejecting");
+        return Err(OperationError::PasswordBadListed);
+    }
+
+    if let Some(corpus) = breach_corpus {
+        if corpus.contains(au, cleartext)? {
+            lsecurity!(au, "Password found in breach corpus, rejecting");
+            return Err(OperationError::PasswordBreached);
+        }
+    }
+
+    // The history only applies once it's enabled, so that it can be turned off again.
+    let history_len = config_entry
+        .get_ava_single_uint32("password_history_length")
+        .unwrap_or(0);
+    if history_len > 0 {
+        if let Some(cred) = existing {
+            if cred.password_in_history(cleartext)? {
+                lsecurity!(au, "Password found in history, rejecting");
+                return Err(OperationError::PasswordInHistory);
             }
         }
     }
+
+    Ok(())
 }
 
 impl<'a> IdmServerWriteTransaction<'a> {
@@ -308,6 +988,105 @@ impl<'a> IdmServerWriteTransaction<'a> {
         session_write.split_off_lt(&split_at);
         // expired will now be dropped, and can't be used by future sessions.
         session_write.commit();
+        let mut discoverable_write = self.discoverable_sessions.write();
+        discoverable_write.split_off_lt(&split_at);
+        discoverable_write.commit();
+    }
+
+    /// The limit on an authentication attempt from this source. Attempts that have no
+    /// source, such as those made internally, are never limited.
+    pub fn auth_source_limit(
+        &self,
+        au: &mut AuditScope,
+        source: Option<IpAddr>,
+        ct: Duration,
+    ) -> Result<SourceLimit, OperationError> {
+        let addr = match source {
+            Some(addr) => addr,
+            None => return Ok(SourceLimit::Allow),
+        };
+        let config = ratelimit_config(au, &self.qs_read)?;
+        let sources_read = self.auth_sources.read();
+        let limit = RateLimitKey::from_addr(addr)
+            .iter()
+            .fold(SourceLimit::Allow, |limit, key| {
+                match sources_read.get(key) {
+                    Some(failures) => limit.strictest(failures.limit(key, ct, &config)),
+                    None => limit,
+                }
+            });
+        self.auth_source_metrics.record(&limit);
+        ltrace!(au, "auth source {:?} limit -> {:?}", addr, limit);
+        Ok(limit)
+    }
+
+    /// Count a failed authentication against the address and subnet of the source.
+    pub async fn auth_source_failure(
+        &self,
+        au: &mut AuditScope,
+        source: Option<IpAddr>,
+        ct: Duration,
+    ) -> Result<(), OperationError> {
+        let addr = match source {
+            Some(addr) => addr,
+            None => return Ok(()),
+        };
+        let config = ratelimit_config(au, &self.qs_read)?;
+        self.auth_source_metrics.record_failure();
+
+        let _auth_source_ticket = self.auth_source_ticket.acquire().await;
+        let mut sources_write = self.auth_sources.write();
+        for key in RateLimitKey::from_addr(addr).iter() {
+            if !sources_write.contains_key(key) {
+                sources_write.insert(*key, SourceFailures::new(ct));
+            }
+            let blocked = sources_write
+                .get_mut(key)
+                .map(|failures| failures.record_failure(key, ct, &config))
+                .unwrap_or(false);
+            if blocked {
+                lsecurity_critical!(
+                    au,
+                    "Possible bruteforce, blocking authentication from {:?} for {} seconds",
+                    key,
+                    config.window
+                );
+            }
+        }
+        sources_write.commit();
+        Ok(())
+    }
+
+    /// Remove the sources whose window has ended, and report the rate limiting metrics.
+    pub async fn purge_auth_sources(
+        &self,
+        au: &mut AuditScope,
+        ct: Duration,
+    ) -> Result<(), OperationError> {
+        let config = ratelimit_config(au, &self.qs_read)?;
+        let _auth_source_ticket = self.auth_source_ticket.acquire().await;
+        let mut sources_write = self.auth_sources.write();
+        let expired: Vec<RateLimitKey> = sources_write
+            .iter()
+            .filter(|(_, failures)| failures.is_expired(ct, &config))
+            .map(|(key, _)| *key)
+            .collect();
+        expired.iter().for_each(|key| {
+            sources_write.remove(key);
+        });
+        let tracked = sources_write.len();
+        sources_write.commit();
+
+        let (failures, delayed, blocked) = self.auth_source_metrics.get();
+        ladmin_info!(
+            au,
+            "Authentication rate limit metrics: failures {} delayed {} blocked {} tracked sources {}",
+            failures,
+            delayed,
+            blocked,
+            tracked
+        );
+        Ok(())
     }
 
     pub async fn auth(
@@ -317,6 +1096,114 @@ impl<'a> IdmServerWriteTransaction<'a> {
         ct: Duration,
     ) -> Result<AuthResult, OperationError> {
         ltrace!(au, "Received -> {:?}", ae);
+
+        // Sources that have failed too often are refused before anything is checked, so
+        // that they learn nothing about the accounts they are trying.
+        let limit = self.auth_source_limit(au, ae.source, ct)?;
+        if limit == SourceLimit::Block {
+            lsecurity!(au, "Authentication from {:?} is rate limited", ae.source);
+            let sessionid = match &ae.step {
+                AuthEventStep::Begin(mech) => mech.sessionid,
+                AuthEventStep::Cred(creds) => creds.sessionid,
+                AuthEventStep::Init(_) | AuthEventStep::InitDiscoverable => {
+                    uuid_from_duration(ct, self.sid)
+                }
+            };
+            let record = self.auth_record(au, ae, ct).failure(RATE_LIMITED_MSG);
+            queue_auth_record(au, &self.audit_tx, record);
+            return Ok(AuthResult {
+                sessionid,
+                state: AuthState::Denied(RATE_LIMITED_MSG.to_string()),
+                delay: None,
+            });
+        }
+
+        let r = self.auth_step(au, ae, ct).await;
+        let failed = match &r {
+            Ok(ar) => matches!(ar.state, AuthState::Denied(_)),
+            Err(_) => true,
+        };
+        if failed {
+            self.auth_source_failure(au, ae.source, ct).await?;
+        }
+
+        // Only the end of an attempt is recorded. Errors are not, as they are an invalid
+        // session state, other than an unknown account when the session begins.
+        let record = match &r {
+            Ok(AuthResult {
+                state: AuthState::Success(_),
+                ..
+            }) => Some(self.auth_record(au, ae, ct).success()),
+            Ok(AuthResult {
+                state: AuthState::Denied(reason),
+                ..
+            }) => Some(self.auth_record(au, ae, ct).failure(reason.as_str())),
+            Err(e) if matches!(ae.step, AuthEventStep::Init(_)) => {
+                Some(self.auth_record(au, ae, ct).failure(&format!("{:?}", e)))
+            }
+            _ => None,
+        };
+        if let Some(record) = record {
+            queue_auth_record(au, &self.audit_tx, record);
+        }
+
+        r.map(|mut ar| {
+            if let SourceLimit::Delay(delay) = limit {
+                ar.delay = Some(delay);
+            }
+            ar
+        })
+    }
+
+    /// Begin the audit record of an authentication, naming the account it's for. Once a
+    /// session exists its account is used, otherwise the name is resolved as the session
+    /// would, so that attempts are recorded under the account name however it was given.
+    fn auth_record(&mut self, au: &mut AuditScope, ae: &AuthEvent, ct: Duration) -> AuthRecord {
+        let record = AuthRecord::new(AuthProtocol::Https, ae.source, ct);
+        let (account, mechanism) = match &ae.step {
+            AuthEventStep::Init(init) => {
+                let account = self
+                    .qs_read
+                    .name_to_uuid(au, init.name.as_str())
+                    .and_then(|euuid| self.qs_read.internal_search_uuid(au, &euuid))
+                    .and_then(|entry| Account::try_from_entry_ro(au, &entry, &mut self.qs_read));
+                match account {
+                    Ok(account) => (account, None),
+                    Err(_) => return record.name(init.name.as_str()),
+                }
+            }
+            AuthEventStep::InitDiscoverable => return record,
+            AuthEventStep::Begin(AuthEventStepMech { sessionid, .. })
+            | AuthEventStep::Cred(AuthEventStepCred { sessionid, .. }) => {
+                let session_read = self.sessions.read();
+                match session_read.get(sessionid) {
+                    Some(auth_session) => (
+                        auth_session.get_account().clone(),
+                        auth_session.last_login_mechanism(),
+                    ),
+                    None => return record,
+                }
+            }
+        };
+
+        let softlocked = self
+            .softlocks
+            .read()
+            .get(&account.primary_cred_uuid())
+            .map(|slock| !slock.is_valid())
+            .unwrap_or(false);
+        record
+            .account(account.name.as_str(), account.uuid)
+            .mechanism(mechanism)
+            .softlocked(softlocked)
+    }
+
+    async fn auth_step(
+        &mut self,
+        au: &mut AuditScope,
+        ae: &AuthEvent,
+        ct: Duration,
+    ) -> Result<AuthResult, OperationError> {
         // Match on the auth event, to see what we need to do.
 
         match &ae.step {
@@ -379,8 +1266,18 @@ impl<'a> IdmServerWriteTransaction<'a> {
                     r
                 };
 
+                let webauthn_policy = webauthn_policy(au, &self.qs_read)?;
+
                 let (auth_session, state) = if is_valid {
-                    AuthSession::new(au, account, &init.appid, self.webauthn, ct)
+                    AuthSession::new(
+                        au,
+                        account,
+                        &init.appid,
+                        ae.client_cert.as_ref(),
+                        self.webauthn,
+                        &webauthn_policy,
+                        ct,
+                    )
                 } else {
                     // it's softlocked, don't even bother.
                     lsecurity!(au, "Account is softlocked.");
@@ -425,11 +1322,47 @@ impl<'a> IdmServerWriteTransaction<'a> {
                     delay,
                 })
             } // AuthEventStep::Init
+            AuthEventStep::InitDiscoverable => {
+                let sessionid = uuid_from_duration(ct, self.sid);
+                lsecurity!(au, "Initiating Discoverable Authentication Session");
+
+                let (discoverable, state) = DiscoverableAuthSession::new(au, self.webauthn)?;
+
+                let _session_ticket = self.session_ticket.acquire().await;
+                let mut discoverable_write = self.discoverable_sessions.write();
+                if discoverable_write.contains_key(&sessionid) {
+                    return Err(OperationError::InvalidSessionState);
+                }
+                discoverable_write.insert(sessionid, discoverable);
+                discoverable_write.commit();
+
+                Ok(AuthResult {
+                    sessionid,
+                    state,
+                    delay: None,
+                })
+            } // AuthEventStep::InitDiscoverable
             AuthEventStep::Begin(mech) => {
                 // lperf_segment!(au, "idm::server::auth<Begin>", || {
                 let _session_ticket = self.session_ticket.acquire().await;
                 let _softlock_ticket = self.softlock_ticket.acquire().await;
 
+                // A discoverable session has no account, and so no softlock, until the
+                // credential is provided.
+                let mut discoverable_write = self.discoverable_sessions.write();
+                if let Some(discoverable) = discoverable_write.get_mut(&mech.sessionid) {
+                    let r = discoverable.start_session(&mech.mech);
+                    if let Ok(AuthState::Denied(_)) = &r {
+                        discoverable_write.remove(&mech.sessionid);
+                    }
+                    discoverable_write.commit();
+                    return r.map(|state| AuthResult {
+                        sessionid: mech.sessionid,
+                        state,
+                        delay: None,
+                    });
+                }
+
                 let mut session_write = self.sessions.write();
                 // Do we have a session?
                 let auth_session = session_write
@@ -477,10 +1410,43 @@ impl<'a> IdmServerWriteTransaction<'a> {
             } // End AuthEventStep::Mech
             AuthEventStep::Cred(creds) => {
                 // lperf_segment!(au, "idm::server::auth<Creds>", || {
+                let softlock_config = softlock_config(au, &self.qs_read)?;
                 let _session_ticket = self.session_ticket.acquire().await;
                 let _softlock_ticket = self.softlock_ticket.acquire().await;
 
                 let mut session_write = self.sessions.write();
+
+                // Bind a discoverable session to the account its credential names. From
+                // here it is checked like any other session.
+                let mut discoverable_write = self.discoverable_sessions.write();
+                if let Some(discoverable) = discoverable_write.remove(&creds.sessionid) {
+                    discoverable_write.commit();
+                    let webauthn_policy = webauthn_policy(au, &self.qs_read)?;
+                    let bound = discoverable_account(au, &mut self.qs_read, &creds.cred)?
+                        .ok_or(UNKNOWN_DISCOVERABLE_MSG)
+                        .and_then(|account| {
+                            AuthSession::new_discoverable(
+                                au,
+                                account,
+                                discoverable,
+                                &webauthn_policy,
+                                ct,
+                            )
+                        });
+                    match bound {
+                        Ok(auth_session) => {
+                            session_write.insert(creds.sessionid, auth_session);
+                        }
+                        Err(reason) => {
+                            lsecurity!(au, "Discoverable authentication denied: {}", reason);
+                            return Ok(AuthResult {
+                                sessionid: creds.sessionid,
+                                state: AuthState::Denied(reason.to_string()),
+                                delay: None,
+                            });
+                        }
+                    }
+                }
                 // Do we have a session?
                 let auth_session = session_write
                     // Why is the session missing?
@@ -506,37 +1472,57 @@ impl<'a> IdmServerWriteTransaction<'a> {
                     })
                     .unwrap_or(true);
 
-                let r = if is_valid {
-                    // Process the credentials here as required.
+                let r = if !is_valid {
+                    // Fail the session
+                    auth_session.end_session("Account is temporarily locked")
+                } else if let AuthCredential::ClientCertificate = &creds.cred {
+                    // The certificate was verified by the TLS layer, so there is no
+                    // secret to count against the softlock.
+                    auth_session.validate_client_certificate(au, ae.client_cert.as_ref(), &ct)
+                } else if let AuthCredential::PasswordChange(_) = &creds.cred {
+                    // The new password was checked and committed by change_expired_password
+                    // before this step, so the session only has to complete.
+                    auth_session.complete_expired_password_change(au, &ct)
+                } else {
+                    // Process the credentials here as required.
                     // Basically throw them at the auth_session and see what
                     // falls out.
                     auth_session
-                        .validate_creds(au, &creds.cred, &ct, &self.async_tx, self.webauthn)
+                        .validate_creds(
+                            au,
+                            &creds.cred,
+                            &ct,
+                            &self.async_tx,
+                            self.crypto_policy,
+                            self.webauthn,
+                        )
                         .map(|aus| {
                             // Inspect the result:
                             // if it was a failure, we need to inc the softlock.
                             if let AuthState::Denied(_) = &aus {
-                                if let Some(slock) = softlock_write.get_mut(&cred_uuid) {
-                                    // Update it.
-                                    slock.record_failure(ct);
-                                } else {
-                                    // Create if not exist, and the cred type supports softlocking.
-                                    if let Some(policy) =
-                                        auth_session.get_account().primary_cred_softlock_policy()
-                                    {
-                                        let mut slock = CredSoftLock::new(policy);
-                                        slock.record_failure(ct);
-                                        softlock_write.insert(cred_uuid, slock);
-                                    }
-                                }
+                                record_softlock_failure(
+                                    au,
+                                    &mut softlock_write,
+                                    &self.softlock_tx,
+                                    cred_uuid,
+                                    auth_session.get_account().primary_cred_softlock_policy(),
+                                    &softlock_config,
+                                    ct,
+                                );
                             };
                             aus
                         })
-                } else {
-                    // Fail the session
-                    auth_session.end_session("Account is temporarily locked")
                 }
                 .map(|aus| {
+                    if let AuthState::Success(_) = &aus {
+                        queue_last_login(
+                            au,
+                            &self.async_tx,
+                            auth_session.get_account().uuid,
+                            auth_session.last_login_mechanism(),
+                            ct,
+                        );
+                    }
                     // TODO: Change this william!
                     // For now ...
                     let delay = None;
@@ -572,11 +1558,17 @@ impl<'a> IdmServerWriteTransaction<'a> {
                 e
             })?;
 
+        let record = AuthRecord::new(AuthProtocol::Unix, None, ct)
+            .account(account.name.as_str(), account.uuid)
+            .mechanism(Some("password"));
+
         if !account.is_within_valid_time(ct) {
             lsecurity!(au, "Account is not within valid time period");
+            queue_auth_record(au, &self.audit_tx, record.failure(INVALID_TIME_MSG));
             return Ok(None);
         }
 
+        let softlock_config = softlock_config(au, &self.qs_read)?;
         let _softlock_ticket = self.softlock_ticket.acquire().await;
         let mut softlock_write = self.softlocks.write();
 
@@ -602,20 +1594,30 @@ impl<'a> IdmServerWriteTransaction<'a> {
         let res = if is_valid {
             // Account is unlocked, can proceed.
             account
-                .verify_unix_credential(au, uae.cleartext.as_str(), &self.async_tx, ct)
+                .verify_unix_credential(
+                    au,
+                    uae.cleartext.as_str(),
+                    AppPasswordScope::Unix,
+                    &self.async_tx,
+                    self.crypto_policy,
+                    ct,
+                )
                 .map(|res| {
                     if res.is_none() {
-                        if let Some(cu) = cred_uuid.as_ref() {
+                        if let Some(cu) = cred_uuid {
                             // Update the cred failure.
-                            if let Some(slock) = softlock_write.get_mut(cu) {
-                                // Update it.
-                                slock.record_failure(ct);
-                            } else if let Some(policy) = account.unix_cred_softlock_policy() {
-                                let mut slock = CredSoftLock::new(policy);
-                                slock.record_failure(ct);
-                                softlock_write.insert(*cu, slock);
-                            };
+                            record_softlock_failure(
+                                au,
+                                &mut softlock_write,
+                                &self.softlock_tx,
+                                cu,
+                                account.unix_cred_softlock_policy(),
+                                &softlock_config,
+                                ct,
+                            );
                         }
+                    } else {
+                        queue_last_login(au, &self.async_tx, account.uuid, Some("unix"), ct);
                     };
                     res
                 })
@@ -625,6 +1627,119 @@ impl<'a> IdmServerWriteTransaction<'a> {
             Ok(None)
         };
 
+        let record = record.softlocked(is_softlocked(&softlock_write, cred_uuid));
+        match &res {
+            Ok(Some(_)) => queue_auth_record(au, &self.audit_tx, record.success()),
+            Ok(None) => {
+                queue_auth_record(au, &self.audit_tx, record.failure(bind_failure(is_valid)))
+            }
+            Err(_) => {}
+        }
+
+        softlock_write.commit();
+        res
+    }
+
+    /// Authenticate a RADIUS user with an app password of the radius scope. This is for
+    /// RADIUS servers, so the event must be able to read the account's RADIUS credential.
+    pub async fn auth_radius(
+        &mut self,
+        au: &mut AuditScope,
+        rae: &RadiusAuthEvent,
+        ct: Duration,
+    ) -> Result<Option<RadiusAuthToken>, OperationError> {
+        let radius_account = self
+            .qs_read
+            .impersonate_search_ext_uuid(au, &rae.target, &rae.event)
+            .and_then(|account_entry| {
+                RadiusAccount::try_from_entry_reduced(au, &account_entry, &mut self.qs_read)
+            })
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to start auth radius -> {:?}", e);
+                e
+            })?;
+
+        // The app passwords are not readable by the radius server, so check them
+        // against the full entry.
+        let account_entry = self
+            .qs_read
+            .internal_search_uuid(au, &rae.target)
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to start auth radius -> {:?}", e);
+                e
+            })?;
+        // App passwords share the softlock of the unix credential.
+        let cred_uuid = softlock_credentials(&account_entry)
+            .into_iter()
+            .find(|(name, _)| *name == "unix")
+            .map(|(_, cred_uuid)| cred_uuid);
+        let account = Account::try_from_entry_ro(au, &account_entry, &mut self.qs_read)?;
+
+        let record = AuthRecord::new(AuthProtocol::Radius, None, ct)
+            .account(account.name.as_str(), account.uuid)
+            .mechanism(Some("app_password"));
+
+        if !account.is_within_valid_time(ct) {
+            lsecurity!(au, "Account is not within valid time period");
+            queue_auth_record(au, &self.audit_tx, record.failure(INVALID_TIME_MSG));
+            return Ok(None);
+        }
+
+        let softlock_config = softlock_config(au, &self.qs_read)?;
+        let _softlock_ticket = self.softlock_ticket.acquire().await;
+        let mut softlock_write = self.softlocks.write();
+
+        let is_valid = if let Some(cu) = cred_uuid.as_ref() {
+            softlock_write
+                .get_mut(cu)
+                .map(|slock| {
+                    slock.apply_time_step(ct);
+                    slock.is_valid()
+                })
+                .unwrap_or(true)
+        } else {
+            // No app passwords, it'll fail in verify ...
+            true
+        };
+
+        let res = if is_valid {
+            account
+                .verify_app_password(rae.cleartext.as_str(), AppPasswordScope::Radius)
+                .and_then(|verified| {
+                    if verified {
+                        lsecurity!(au, "Successful radius cred handling (app password)");
+                        queue_last_login(au, &self.async_tx, account.uuid, Some("radius"), ct);
+                        radius_account.to_radiusauthtoken(ct).map(Some)
+                    } else {
+                        lsecurity!(au, "Failed radius cred handling (denied)");
+                        if let Some(cu) = cred_uuid {
+                            record_softlock_failure(
+                                au,
+                                &mut softlock_write,
+                                &self.softlock_tx,
+                                cu,
+                                Some(CredSoftLockPolicy::Password),
+                                &softlock_config,
+                                ct,
+                            );
+                        }
+                        Ok(None)
+                    }
+                })
+        } else {
+            lsecurity!(au, "Account is softlocked.");
+            Ok(None)
+        };
+
+        let record = record.softlocked(is_softlocked(&softlock_write, cred_uuid));
+        match &res {
+            Ok(Some(_)) => queue_auth_record(au, &self.audit_tx, record.success()),
+            Ok(None) => {
+                queue_auth_record(au, &self.audit_tx, record.failure(bind_failure(is_valid)))
+            }
+            Err(_) => {}
+        }
+
         softlock_write.commit();
         res
     }
@@ -656,23 +1771,31 @@ impl<'a> IdmServerWriteTransaction<'a> {
             Ok(Some(LdapBoundToken {
                 uuid: *UUID_ANONYMOUS,
                 effective_uat: account
-                    .to_userauthtoken(&[])
+                    .to_userauthtoken(&[], ct)
                     .ok_or(OperationError::InvalidState)
                     .map_err(|e| {
                         ladmin_error!(au, "Unable to generate effective_uat -> {:?}", e);
                         e
                     })?,
                 spn: account.spn,
+                policy_met: true,
+                via_app_password: false,
             }))
         } else {
             let account =
                 UnixUserAccount::try_from_entry_ro(au, &account_entry, &mut self.qs_read)?;
 
+            let record = AuthRecord::new(AuthProtocol::Ldap, lae.source, ct)
+                .account(account.name.as_str(), account.uuid)
+                .mechanism(Some("password"));
+
             if !account.is_within_valid_time(ct) {
                 lsecurity!(au, "Account is not within valid time period");
+                queue_auth_record(au, &self.audit_tx, record.failure(INVALID_TIME_MSG));
                 return Ok(None);
             }
 
+            let softlock_config = softlock_config(au, &self.qs_read)?;
             let _softlock_ticket = self.softlock_ticket.acquire().await;
             let mut softlock_write = self.softlocks.write();
 
@@ -695,10 +1818,14 @@ impl<'a> IdmServerWriteTransaction<'a> {
             };
 
             let res = if is_valid {
-                if account
-                    .verify_unix_credential(au, lae.cleartext.as_str(), &self.async_tx, ct)?
-                    .is_some()
-                {
+                if let Some((_, via_app_password)) = account.verify_unix_credential_method(
+                    au,
+                    lae.cleartext.as_str(),
+                    AppPasswordScope::Ldap,
+                    &self.async_tx,
+                    self.crypto_policy,
+                    ct,
+                )? {
                     // Get the anon uat
                     let anon_entry = self
                         .qs_read
@@ -713,30 +1840,41 @@ impl<'a> IdmServerWriteTransaction<'a> {
                         })?;
                     let anon_account =
                         Account::try_from_entry_ro(au, &anon_entry, &mut self.qs_read)?;
+                    // A password can only satisfy a group policy that requires no more
+                    // than a password, the same as for any other authentication.
+                    let policy_met =
+                        Account::try_from_entry_ro(au, &account_entry, &mut self.qs_read)?
+                            .credential_type_minimum()
+                            .map(|min| min <= CredentialStrength::Password)
+                            .unwrap_or(true);
+                    queue_last_login(au, &self.async_tx, account.uuid, Some("ldap"), ct);
 
                     Ok(Some(LdapBoundToken {
                         spn: account.spn,
                         uuid: account.uuid,
                         effective_uat: anon_account
-                            .to_userauthtoken(&[])
+                            .to_userauthtoken(&[], ct)
                             .ok_or(OperationError::InvalidState)
                             .map_err(|e| {
                                 ladmin_error!(au, "Unable to generate effective_uat -> {:?}", e);
                                 e
                             })?,
+                        policy_met,
+                        via_app_password,
                     }))
                 } else {
                     // PW failure, update softlock.
-                    if let Some(cu) = cred_uuid.as_ref() {
+                    if let Some(cu) = cred_uuid {
                         // Update the cred failure.
-                        if let Some(slock) = softlock_write.get_mut(cu) {
-                            // Update it.
-                            slock.record_failure(ct);
-                        } else if let Some(policy) = account.unix_cred_softlock_policy() {
-                            let mut slock = CredSoftLock::new(policy);
-                            slock.record_failure(ct);
-                            softlock_write.insert(*cu, slock);
-                        };
+                        record_softlock_failure(
+                            au,
+                            &mut softlock_write,
+                            &self.softlock_tx,
+                            cu,
+                            account.unix_cred_softlock_policy(),
+                            &softlock_config,
+                            ct,
+                        );
                     };
                     Ok(None)
                 }
@@ -746,11 +1884,101 @@ impl<'a> IdmServerWriteTransaction<'a> {
                 Ok(None)
             };
 
+            let record = record.softlocked(is_softlocked(&softlock_write, cred_uuid));
+            match &res {
+                Ok(Some(_)) => queue_auth_record(au, &self.audit_tx, record.success()),
+                Ok(None) => {
+                    queue_auth_record(au, &self.audit_tx, record.failure(bind_failure(is_valid)))
+                }
+                Err(_) => {}
+            }
+
             softlock_write.commit();
             res
         }
     }
 
+    /// Bind an LDAP connection as the account its verified client certificate is bound
+    /// to, as a SASL EXTERNAL bind would. The effective token is anonymous, as it is for
+    /// password binds.
+    pub fn auth_ldap_certificate(
+        &mut self,
+        au: &mut AuditScope,
+        cert: &ClientCertificate,
+        source: Option<IpAddr>,
+        ct: Duration,
+    ) -> Result<Option<LdapBoundToken>, OperationError> {
+        let record = AuthRecord::new(AuthProtocol::Ldap, source, ct).mechanism(Some("x509"));
+
+        let mut entries = self.qs_read.internal_search(
+            au,
+            filter!(f_eq(
+                "x509_fingerprint",
+                PartialValue::new_iutf8(cert.fingerprint.as_str())
+            )),
+        )?;
+        let account_entry = match entries.pop() {
+            Some(e) if entries.is_empty() => e,
+            _ => {
+                lsecurity!(
+                    au,
+                    "No single account is bound to client certificate {} ({:?})",
+                    cert.fingerprint,
+                    cert.subject
+                );
+                let record = match &cert.subject {
+                    Some(subject) => record.name(subject.as_str()),
+                    None => record,
+                };
+                queue_auth_record(au, &self.audit_tx, record.failure(BAD_CERTIFICATE_MSG));
+                return Ok(None);
+            }
+        };
+        let account = Account::try_from_entry_ro(au, &account_entry, &mut self.qs_read)?;
+        let record = record.account(account.name.as_str(), account.uuid);
+
+        if !account.is_within_valid_time(ct) {
+            lsecurity!(au, "Account is not within valid time period");
+            queue_auth_record(au, &self.audit_tx, record.failure(INVALID_TIME_MSG));
+            return Ok(None);
+        }
+        if account.credential_type_minimum().is_some() {
+            lsecurity!(
+                au,
+                "client certificate can not satisfy the credential policy of the account"
+            );
+            queue_auth_record(au, &self.audit_tx, record.failure(CERTIFICATE_POLICY_MSG));
+            return Ok(None);
+        }
+
+        let anon_entry = self
+            .qs_read
+            .internal_search_uuid(au, &UUID_ANONYMOUS)
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to find effective uat for auth ldap -> {:?}", e);
+                e
+            })?;
+        let anon_account = Account::try_from_entry_ro(au, &anon_entry, &mut self.qs_read)?;
+        let effective_uat = anon_account
+            .to_userauthtoken(&[], ct)
+            .ok_or(OperationError::InvalidState)
+            .map_err(|e| {
+                ladmin_error!(au, "Unable to generate effective_uat -> {:?}", e);
+                e
+            })?;
+
+        lsecurity!(au, "✅ LDAP certificate bind success {}", account.spn);
+        queue_last_login(au, &self.async_tx, account.uuid, Some("ldap"), ct);
+        queue_auth_record(au, &self.audit_tx, record.success());
+        Ok(Some(LdapBoundToken {
+            spn: account.spn,
+            uuid: account.uuid,
+            effective_uat,
+            policy_met: true,
+            via_app_password: false,
+        }))
+    }
+
     pub fn commit(self, _au: &mut AuditScope) -> Result<(), OperationError> {
         /*
         lperf_trace_segment!(au, "idm::server::IdmServerWriteTransaction::commit", || {
@@ -762,6 +1990,71 @@ impl<'a> IdmServerWriteTransaction<'a> {
 }
 
 impl<'a> IdmServerProxyReadTransaction<'a> {
+    pub fn oauth2_openid_discovery(
+        &self,
+        au: &mut AuditScope,
+        client_id: &str,
+    ) -> Result<OidcDiscoveryResponse, OperationError> {
+        let rs = oauth2_rs_from_client_id(au, &self.qs_read, client_id)
+            .map_err(|_| OperationError::NoMatchingEntries)?;
+
+        let origin = self.origin.as_str().trim_end_matches('/');
+        let issuer = oauth2_issuer(self.origin, &rs.name);
+        let jwks_uri = format!("{}/public_key.jwk", issuer);
+
+        let mut scopes_supported: Vec<String> = rs.permitted_scopes().into_iter().collect();
+        scopes_supported.sort();
+
+        Ok(OidcDiscoveryResponse {
+            issuer,
+            authorization_endpoint: format!("{}/oauth2/authorise", origin),
+            token_endpoint: format!("{}/oauth2/token", origin),
+            jwks_uri,
+            response_types_supported: vec!["code".to_string()],
+            grant_types_supported: vec![
+                "authorization_code".to_string(),
+                "client_credentials".to_string(),
+            ],
+            subject_types_supported: vec!["public".to_string()],
+            id_token_signing_alg_values_supported: vec!["ES256".to_string()],
+            token_endpoint_auth_methods_supported: vec![
+                "client_secret_basic".to_string(),
+                "client_secret_post".to_string(),
+            ],
+            code_challenge_methods_supported: vec!["S256".to_string()],
+            scopes_supported,
+            claims_supported: vec![
+                "iss".to_string(),
+                "sub".to_string(),
+                "aud".to_string(),
+                "iat".to_string(),
+                "exp".to_string(),
+                "nonce".to_string(),
+                "name".to_string(),
+                "preferred_username".to_string(),
+                "groups".to_string(),
+            ],
+        })
+    }
+
+    pub fn oauth2_openid_publickey(
+        &self,
+        au: &mut AuditScope,
+        client_id: &str,
+    ) -> Result<JwkKeySet, OperationError> {
+        // Only serve keys for a resource server that exists.
+        let _rs = oauth2_rs_from_client_id(au, &self.qs_read, client_id)
+            .map_err(|_| OperationError::NoMatchingEntries)?;
+
+        let jwk = self
+            .qs_read
+            .get_domain_es256_private_key(au)
+            .and_then(|der| JwsSigner::from_es256_der(&der))
+            .and_then(|signer| signer.public_key_as_jwk())?;
+
+        Ok(JwkKeySet { keys: vec![jwk] })
+    }
+
     pub fn get_radiusauthtoken(
         &mut self,
         au: &mut AuditScope,
@@ -802,6 +2095,178 @@ impl<'a> IdmServerProxyReadTransaction<'a> {
         account.to_unixusertoken(ct)
     }
 
+    pub fn service_account_list_api_token(
+        &mut self,
+        au: &mut AuditScope,
+        lte: &ListApiTokenEvent,
+    ) -> Result<Vec<ApiToken>, OperationError> {
+        self.qs_read
+            .impersonate_search_ext_uuid(au, &lte.target, &lte.event)
+            .and_then(|e| ServiceAccount::try_from_entry_reduced(&e))
+            .map(|sa| sa.to_api_tokens())
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to list api tokens {:?}", e);
+                e
+            })
+    }
+
+    /// Sign an OpenSSH user certificate for one of the ssh public keys of the account. The
+    /// key must be registered on the account, so that removing it stops it being certified.
+    pub fn account_sign_ssh_key(
+        &mut self,
+        au: &mut AuditScope,
+        ssce: &SshCertSignEvent,
+        ct: Duration,
+    ) -> Result<String, OperationError> {
+        let account_entry = self.qs_read.internal_search_uuid(au, &ssce.target)?;
+        let account = Account::try_from_entry_ro(au, &account_entry, &mut self.qs_read)?;
+        if !account.is_within_valid_time(ct) {
+            lsecurity!(au, "Account is not within valid time period");
+            return Err(OperationError::InvalidAccountState(
+                INVALID_TIME_MSG.to_string(),
+            ));
+        }
+
+        let presented = ssh_public_key_blob(ssce.publickey.as_str());
+        let registered = presented.is_some()
+            && account_entry
+                .get_ava_iter_sshpubkeys("ssh_publickey")
+                .map(|mut keys| keys.any(|k| ssh_public_key_blob(k) == presented))
+                .unwrap_or(false);
+        if !registered {
+            lrequest_error!(au, "ssh public key is not registered to the account");
+            return Err(OperationError::InvalidAttribute(
+                "ssh_publickey".to_string(),
+            ));
+        }
+
+        // Posix accounts may also log in as the names of their groups.
+        let mut principals = vec![account.name.clone(), account.spn.clone()];
+        if account_entry.attribute_value_pres("class", &PartialValue::new_class("posixaccount")) {
+            let unix_account =
+                UnixUserAccount::try_from_entry_ro(au, &account_entry, &mut self.qs_read)?;
+            principals.extend(unix_account.groups.into_iter().map(|g| g.name));
+        }
+        principals.sort_unstable();
+        principals.dedup();
+
+        let signer = self
+            .qs_read
+            .get_domain_ssh_ca_private_key(au)
+            .and_then(|der| SshCaSigner::from_der(&der))?;
+        // Allow for the clocks of hosts that are behind ours.
+        let valid_after = ct
+            .checked_sub(Duration::from_secs(SSH_CERT_CLOCK_SKEW))
+            .unwrap_or(ct);
+        let cert = signer.sign_user_key(
+            ssce.publickey.as_str(),
+            account.spn.as_str(),
+            &principals,
+            valid_after,
+            ssce.valid_before,
+        )?;
+        lsecurity!(
+            au,
+            "Signed ssh certificate for {} with principals {:?}",
+            account.spn,
+            principals
+        );
+        Ok(cert)
+    }
+
+    pub fn ssh_ca_public_key(&self, au: &mut AuditScope) -> Result<String, OperationError> {
+        self.qs_read
+            .get_domain_ssh_ca_private_key(au)
+            .and_then(|der| SshCaSigner::from_der(&der))
+            .and_then(|signer| signer.public_key_openssh())
+    }
+
+    pub fn account_list_app_password(
+        &mut self,
+        au: &mut AuditScope,
+        lape: &ListAppPasswordEvent,
+    ) -> Result<Vec<AppPassword>, OperationError> {
+        self.qs_read
+            .impersonate_search_ext_uuid(au, &lape.target, &lape.event)
+            .map(|e| {
+                e.get_ava_as_credentials("app_password")
+                    .map(app_passwords_to_proto)
+                    .unwrap_or_else(Vec::new)
+            })
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to list app passwords {:?}", e);
+                e
+            })
+    }
+
+    pub fn account_softlock_status(
+        &mut self,
+        au: &mut AuditScope,
+        rsle: &ReadSoftLockEvent,
+        ct: Duration,
+    ) -> Result<Vec<CredentialSoftLock>, OperationError> {
+        // The credentials themselves are not readable, so check the account can be seen
+        // before looking them up internally.
+        self.qs_read
+            .impersonate_search_ext_uuid(au, &rsle.target, &rsle.event)
+            .and_then(|_| self.qs_read.internal_search_uuid(au, &rsle.target))
+            .map(|entry| {
+                let softlock_read = self.softlocks.read();
+                softlock_credentials(&entry)
+                    .into_iter()
+                    .filter_map(|(credential, cred_uuid)| {
+                        let slock = softlock_read.get(&cred_uuid)?;
+                        if slock.is_expired(ct) {
+                            return None;
+                        }
+                        let (failures, unlock_at, reset_at) = slock.status()?;
+                        let to_rfc3339 = |d: Duration| {
+                            (time::OffsetDateTime::unix_epoch() + d).format(time::Format::Rfc3339)
+                        };
+                        Some(CredentialSoftLock {
+                            credential: credential.to_string(),
+                            failures: failures as u32,
+                            unlock_at: unlock_at.filter(|u| *u > ct).map(to_rfc3339),
+                            reset_at: to_rfc3339(reset_at),
+                        })
+                    })
+                    .collect()
+            })
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to read softlocks {:?}", e);
+                e
+            })
+    }
+
+    /// Search the authentication audit store. The store is not made of entries, so access
+    /// to it is by membership of its group rather than by an access profile.
+    pub fn search_auth_records(
+        &mut self,
+        au: &mut AuditScope,
+        aase: &AuthAuditSearchEvent,
+    ) -> Result<Vec<AuthAuditRecord>, OperationError> {
+        let allowed = match &aase.event.origin {
+            EventOrigin::Internal => true,
+            EventOrigin::User(e) => e.attribute_value_pres(
+                "memberof",
+                &PartialValue::new_refer_r(&UUID_IDM_AUTH_AUDIT_READ_PRIV),
+            ),
+        };
+        if !allowed {
+            lsecurity!(au, "Auth audit search denied for {:?}", aase.event);
+            return Err(OperationError::AccessDenied);
+        }
+
+        ltrace!(au, "Auth audit search -> {:?}", aase.filter);
+        self.qs_read
+            .get_auth_records(au, &aase.filter)
+            .map(|records| records.into_iter().map(to_proto_authauditrecord).collect())
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to search auth records {:?}", e);
+                e
+            })
+    }
+
     pub fn get_unixgrouptoken(
         &mut self,
         au: &mut AuditScope,
@@ -829,102 +2294,425 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
         // expired will now be dropped, and can't be used by future sessions.
     }
 
-    fn check_password_quality(
-        &mut self,
-        au: &mut AuditScope,
-        cleartext: &str,
-        related_inputs: &[&str],
-    ) -> Result<(), OperationError> {
-        // password strength and badlisting is always global, rather than per-pw-policy.
-        // pw-policy as check on the account is about requirements for mfa for example.
-        //
-
-        // is the password at least 10 char?
-        if cleartext.len() < PW_MIN_LENGTH {
-            return Err(OperationError::PasswordTooShort(PW_MIN_LENGTH));
-        }
+    pub fn expire_credreset_sessions(&mut self, ct: Duration) {
+        let expire = ct - Duration::from_secs(CREDRESET_SESSION_TIMEOUT);
+        let split_at = uuid_from_duration(expire, self.sid);
+        self.credreset_sessions.split_off_lt(&split_at);
+    }
 
-        // does the password pass zxcvbn?
+    pub fn expire_oauth2_sessions(&mut self, ct: Duration) {
+        let expire = ct - Duration::from_secs(OAUTH2_SESSION_TIMEOUT);
+        let split_at = uuid_from_duration(expire, self.sid);
+        self.oauth2_sessions.split_off_lt(&split_at);
+    }
 
-        let entropy = zxcvbn::zxcvbn(cleartext, related_inputs).map_err(|e| {
-            ladmin_error!(au, "zxcvbn check failure (password empty?) {:?}", e);
-            OperationError::PasswordEmpty
+    fn oauth2_uuid_to_account(
+        &mut self,
+        au: &mut AuditScope,
+        target: &Uuid,
+        ct: Duration,
+    ) -> Result<Account, Oauth2Error> {
+        let account = self.target_to_account(au, target).map_err(|e| {
+            ladmin_error!(au, "Failed to resolve oauth2 account {:?}", e);
+            Oauth2Error::AccessDenied
         })?;
 
-        // check account pwpolicy (for 3 or 4)? Do we need pw strength beyond this
-        // or should we be enforcing mfa instead
-        if entropy.score() < 3 {
-            // The password is too week as per:
-            // https://docs.rs/zxcvbn/2.0.0/zxcvbn/struct.Entropy.html
-            let feedback: zxcvbn::feedback::Feedback = entropy
-                .feedback()
-                .as_ref()
-                .ok_or(OperationError::InvalidState)
-                .map(|v| v.clone())
-                .map_err(|e| {
-                    lsecurity!(au, "zxcvbn returned no feedback when score < 3");
-                    e
-                })?;
-
-            lsecurity!(au, "pw feedback -> {:?}", feedback);
-
-            // return Err(OperationError::PasswordTooWeak(feedback))
-            return Err(OperationError::PasswordTooWeak);
-        }
-
-        // check a password badlist to eliminate more content
-        // we check the password as "lower case" to help eliminate possibilities
-        let lc_password = PartialValue::new_iutf8(cleartext);
-        let badlist_entry = self
-            .qs_write
-            .internal_search_uuid(au, &UUID_SYSTEM_CONFIG)
-            .map_err(|e| {
-                ladmin_error!(au, "Failed to retrieve system configuration {:?}", e);
-                e
-            })?;
-        if badlist_entry.attribute_value_pres("badlist_password", &lc_password) {
-            lsecurity!(au, "Password found in badlist, rejecting");
-            Err(OperationError::PasswordBadListed)
+        if account.is_within_valid_time(ct) {
+            Ok(account)
         } else {
-            Ok(())
+            lsecurity!(
+                au,
+                "Account is not valid, denying oauth2 -> {:?}",
+                account.uuid
+            );
+            Err(Oauth2Error::AccessDenied)
         }
     }
 
-    fn target_to_account(
+    // The uat is checked the same way as when a write event is built from it, so that a
+    // token issued by a trusted domain can not claim the uuid of one of our own accounts,
+    // and revoked or read only api tokens can not authorise a resource server.
+    fn oauth2_uat_to_account(
         &mut self,
         au: &mut AuditScope,
-        target: &Uuid,
-    ) -> Result<Account, OperationError> {
-        // Get the account
-        let account = self
+        uat: &UserAuthToken,
+        ct: Duration,
+    ) -> Result<Account, Oauth2Error> {
+        if uat.read_only {
+            lsecurity!(au, "oauth2 authorisation denied, uat is read only");
+            return Err(Oauth2Error::AccessDenied);
+        }
+        let target =
+            Uuid::parse_str(uat.uuid.as_str()).map_err(|_| Oauth2Error::AuthenticationRequired)?;
+        let entry = self
             .qs_write
-            .internal_search_uuid(au, target)
-            .and_then(|account_entry| {
-                Account::try_from_entry_rw(au, &account_entry, &mut self.qs_write)
-            })
+            .internal_search_uuid(au, &target)
             .map_err(|e| {
-                ladmin_error!(au, "Failed to search account {:?}", e);
-                e
+                ladmin_error!(au, "Failed to resolve oauth2 uat {:?}", e);
+                Oauth2Error::AccessDenied
             })?;
-        // Ask if tis all good - this step checks pwpolicy and such
+        check_uat_api_token(au, &entry, uat).map_err(|_| Oauth2Error::AccessDenied)?;
+        check_uat_trust(au, &entry, uat).map_err(|_| Oauth2Error::AccessDenied)?;
+        self.oauth2_uuid_to_account(au, &target, ct)
+    }
 
-        // Deny the change if the account is anonymous!
-        if account.is_anonymous() {
-            Err(OperationError::SystemProtectedObject)
-        } else {
-            Ok(account)
-        }
+    fn oauth2_code_redirect(sessionid: &Uuid, session: &Oauth2Session) -> String {
+        let mut redirect_uri = session.redirect_uri.clone();
+        redirect_uri
+            .query_pairs_mut()
+            .append_pair("code", &session.to_token(sessionid))
+            .append_pair("state", &session.client_state);
+        redirect_uri.to_string()
     }
 
-    pub fn set_account_password(
+    pub fn check_oauth2_authorisation(
         &mut self,
         au: &mut AuditScope,
-        pce: &PasswordChangeEvent,
-    ) -> Result<(), OperationError> {
-        let account = self.target_to_account(au, &pce.target)?;
-        // Ask if tis all good - this step checks pwpolicy and such
+        uat: Option<&UserAuthToken>,
+        auth_req: &AuthorisationRequest,
+        ct: Duration,
+    ) -> Result<AuthorisationResponse, Oauth2Error> {
+        if auth_req.response_type != "code" {
+            return Err(Oauth2Error::UnsupportedResponseType);
+        }
 
-        // Question: Is it a security issue to reveal pw policy checks BEFORE permission is
+        let rs: Oauth2RS = oauth2_rs_from_client_id(au, &self.qs_write, &auth_req.client_id)?;
+
+        let redirect_uri =
+            Url::parse(&auth_req.redirect_uri).map_err(|_| Oauth2Error::InvalidRequest)?;
+        if !rs.check_redirect_uri(&redirect_uri) {
+            lsecurity!(
+                au,
+                "Invalid oauth2 redirect_uri for {} -> {:?}",
+                rs.name,
+                auth_req.redirect_uri
+            );
+            return Err(Oauth2Error::InvalidRequest);
+        }
+
+        // We require PKCE for all clients.
+        if auth_req.code_challenge.is_empty() {
+            return Err(Oauth2Error::InvalidRequest);
+        }
+
+        let scopes = parse_scopes(&auth_req.scope);
+        if scopes.is_empty() || !scopes.is_subset(&rs.permitted_scopes()) {
+            return Err(Oauth2Error::InvalidScope);
+        }
+
+        let uat = uat.ok_or(Oauth2Error::AuthenticationRequired)?;
+        let account = self.oauth2_uat_to_account(au, uat, ct)?;
+
+        // Has the account previously consented to these scopes?
+        let consented = self
+            .qs_write
+            .internal_search_uuid(au, &account.uuid)
+            .map_err(|_| Oauth2Error::ServerError)?
+            .get_ava_as_oauthscopemaps("oauth2_consent_scope_map")
+            .and_then(|mut i| {
+                i.find(|(u, _)| **u == rs.uuid)
+                    .map(|(_, consented)| scopes.is_subset(consented))
+            })
+            .unwrap_or(false);
+
+        let sessionid = uuid_from_duration(ct, self.sid);
+        let mut session = Oauth2Session::new(
+            Oauth2SessionState::ConsentRequested,
+            password_from_random(),
+            account.uuid,
+            rs.uuid,
+            scopes,
+            auth_req.state.clone(),
+            redirect_uri,
+            auth_req.code_challenge.clone(),
+            auth_req.nonce.clone(),
+        );
+
+        let resp = if consented {
+            session.issue_code(password_from_random());
+            AuthorisationResponse::Permitted {
+                redirect_uri: Self::oauth2_code_redirect(&sessionid, &session),
+            }
+        } else {
+            AuthorisationResponse::ConsentRequested {
+                client_name: rs.displayname,
+                scopes: session.scopes.iter().cloned().collect(),
+                consent_token: session.to_token(&sessionid),
+            }
+        };
+
+        ltrace!(au, "Start oauth2 session -> {:?}", sessionid);
+        self.oauth2_sessions.insert(sessionid, session);
+        Ok(resp)
+    }
+
+    pub fn check_oauth2_authorise_permit(
+        &mut self,
+        au: &mut AuditScope,
+        uat: Option<&UserAuthToken>,
+        consent_token: &str,
+        ct: Duration,
+    ) -> Result<AuthorisationResponse, Oauth2Error> {
+        let uat = uat.ok_or(Oauth2Error::AuthenticationRequired)?;
+        let (sessionid, secret) = parse_token(consent_token)?;
+
+        let mut session = self
+            .oauth2_sessions
+            .get(&sessionid)
+            .filter(|s| s.state == Oauth2SessionState::ConsentRequested && s.check_secret(secret))
+            .cloned()
+            .ok_or_else(|| {
+                lsecurity!(au, "Invalid oauth2 consent token -> {:?}", sessionid);
+                Oauth2Error::InvalidRequest
+            })?;
+
+        // Only the account that started the session may consent.
+        if Uuid::parse_str(uat.uuid.as_str()).ok() != Some(session.account_uuid) {
+            lsecurity!(
+                au,
+                "Oauth2 consent by a different account -> {:?}",
+                uat.uuid
+            );
+            return Err(Oauth2Error::AccessDenied);
+        }
+        let account = self.oauth2_uat_to_account(au, uat, ct)?;
+
+        // Record the consent so that future authorisations proceed immediately.
+        let modlist = ModifyList::new_list(vec![
+            Modify::Removed(
+                "oauth2_consent_scope_map".into(),
+                PartialValue::new_oauthscopemap(session.rs_uuid),
+            ),
+            Modify::Present(
+                "oauth2_consent_scope_map".into(),
+                Value::new_oauthscopemap(session.rs_uuid, session.scopes.clone()),
+            ),
+        ]);
+
+        self.qs_write
+            .internal_modify(
+                au,
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                &modlist,
+            )
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to record oauth2 consent {:?}", e);
+                Oauth2Error::ServerError
+            })?;
+
+        session.issue_code(password_from_random());
+        let redirect_uri = Self::oauth2_code_redirect(&sessionid, &session);
+        self.oauth2_sessions.insert(sessionid, session);
+
+        Ok(AuthorisationResponse::Permitted { redirect_uri })
+    }
+
+    pub fn check_oauth2_token_exchange(
+        &mut self,
+        au: &mut AuditScope,
+        client_authz: Option<&str>,
+        token_req: &AccessTokenRequest,
+        ct: Duration,
+    ) -> Result<AccessTokenResponse, Oauth2Error> {
+        // Authenticate the resource server, either by http basic or the request body.
+        let (client_id, client_secret) = match client_authz {
+            Some(authz) => parse_basic_authz(authz)?,
+            None => match (&token_req.client_id, &token_req.client_secret) {
+                (Some(id), Some(secret)) => (id.clone(), secret.clone()),
+                _ => return Err(Oauth2Error::InvalidClient),
+            },
+        };
+
+        let rs = oauth2_rs_from_client_id(au, &self.qs_write, &client_id)?;
+        if !rs.check_basic_secret(&client_secret) {
+            lsecurity!(au, "Invalid oauth2 client_secret for {}", rs.name);
+            return Err(Oauth2Error::InvalidClient);
+        }
+
+        let signer = self
+            .qs_write
+            .get_domain_es256_private_key(au)
+            .and_then(|der| JwsSigner::from_es256_der(&der))
+            .map_err(|e| {
+                ladmin_error!(au, "Unable to load oauth2 signing key {:?}", e);
+                Oauth2Error::ServerError
+            })?;
+
+        let iss = oauth2_issuer(self.origin, &rs.name);
+        let iat = ct.as_secs() as i64;
+        let exp = iat + OAUTH2_ACCESS_TOKEN_EXPIRY as i64;
+
+        match token_req.grant_type.as_str() {
+            "authorization_code" => {
+                let code = token_req
+                    .code
+                    .as_deref()
+                    .ok_or(Oauth2Error::InvalidRequest)?;
+                let (sessionid, secret) = parse_token(code)?;
+
+                // Codes are single use, so the session is removed before anything else is
+                // checked. The caller must commit this even if the exchange fails.
+                let session = self
+                    .oauth2_sessions
+                    .get(&sessionid)
+                    .filter(|s| {
+                        s.state == Oauth2SessionState::CodeIssued
+                            && s.rs_uuid == rs.uuid
+                            && s.check_secret(secret)
+                    })
+                    .cloned()
+                    .ok_or_else(|| {
+                        lsecurity!(au, "Invalid oauth2 code -> {:?}", sessionid);
+                        Oauth2Error::InvalidGrant
+                    })?;
+                self.oauth2_sessions.remove(&sessionid);
+
+                let redirect_uri = token_req
+                    .redirect_uri
+                    .as_deref()
+                    .and_then(|u| Url::parse(u).ok())
+                    .ok_or(Oauth2Error::InvalidRequest)?;
+                if redirect_uri != session.redirect_uri {
+                    return Err(Oauth2Error::InvalidGrant);
+                }
+
+                let code_verifier = token_req
+                    .code_verifier
+                    .as_deref()
+                    .ok_or(Oauth2Error::InvalidRequest)?;
+                if !session.check_pkce(code_verifier) {
+                    lsecurity!(au, "Invalid oauth2 pkce verifier -> {:?}", sessionid);
+                    return Err(Oauth2Error::InvalidGrant);
+                }
+
+                // The account may have been locked or expired since the code was issued.
+                let account = self.oauth2_uuid_to_account(au, &session.account_uuid, ct)?;
+                let sub = account.uuid.to_hyphenated_ref().to_string();
+                let scope = session.scope_string();
+
+                let access_token = signer
+                    .sign(&AccessTokenClaims {
+                        purpose: TokenPurpose::Oauth2Access,
+                        iss: iss.clone(),
+                        sub: sub.clone(),
+                        aud: rs.name.clone(),
+                        iat,
+                        exp,
+                        scope: scope.clone(),
+                    })
+                    .map_err(|_| Oauth2Error::ServerError)?;
+
+                let id_token = if session.scopes.contains(OAUTH2_SCOPE_OPENID) {
+                    let oidc = OidcToken {
+                        purpose: TokenPurpose::Oauth2Id,
+                        iss,
+                        sub,
+                        aud: rs.name.clone(),
+                        iat,
+                        exp,
+                        nonce: session.nonce.clone(),
+                        name: Some(account.displayname.clone()),
+                        preferred_username: Some(account.spn.clone()),
+                        groups: account.groups.iter().map(|g| g.to_proto().name).collect(),
+                    };
+                    Some(signer.sign(&oidc).map_err(|_| Oauth2Error::ServerError)?)
+                } else {
+                    None
+                };
+
+                Ok(AccessTokenResponse {
+                    access_token,
+                    token_type: "bearer".to_string(),
+                    expires_in: OAUTH2_ACCESS_TOKEN_EXPIRY,
+                    scope: Some(scope),
+                    id_token,
+                })
+            }
+            "client_credentials" => {
+                // The resource server acts on its own behalf, so only the implicit scopes
+                // may be granted.
+                let scopes = token_req
+                    .scope
+                    .as_deref()
+                    .map(parse_scopes)
+                    .unwrap_or_else(|| rs.implicit_scopes.clone());
+                if !scopes.is_subset(&rs.implicit_scopes) {
+                    return Err(Oauth2Error::InvalidScope);
+                }
+                let scope = scopes.into_iter().collect::<Vec<_>>().join(" ");
+
+                let access_token = signer
+                    .sign(&AccessTokenClaims {
+                        purpose: TokenPurpose::Oauth2Access,
+                        iss,
+                        sub: rs.uuid.to_hyphenated_ref().to_string(),
+                        aud: rs.name.clone(),
+                        iat,
+                        exp,
+                        scope: scope.clone(),
+                    })
+                    .map_err(|_| Oauth2Error::ServerError)?;
+
+                Ok(AccessTokenResponse {
+                    access_token,
+                    token_type: "bearer".to_string(),
+                    expires_in: OAUTH2_ACCESS_TOKEN_EXPIRY,
+                    scope: Some(scope),
+                    id_token: None,
+                })
+            }
+            _ => Err(Oauth2Error::UnsupportedGrantType),
+        }
+    }
+
+    fn password_history_length(&mut self, au: &mut AuditScope) -> Result<usize, OperationError> {
+        self.qs_write
+            .internal_search_uuid(au, &UUID_SYSTEM_CONFIG)
+            .map(|e| {
+                e.get_ava_single_uint32("password_history_length")
+                    .unwrap_or(0) as usize
+            })
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to retrieve system configuration {:?}", e);
+                e
+            })
+    }
+
+    fn target_to_account(
+        &mut self,
+        au: &mut AuditScope,
+        target: &Uuid,
+    ) -> Result<Account, OperationError> {
+        // Get the account
+        let account = self
+            .qs_write
+            .internal_search_uuid(au, target)
+            .and_then(|account_entry| {
+                Account::try_from_entry_rw(au, &account_entry, &mut self.qs_write)
+            })
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to search account {:?}", e);
+                e
+            })?;
+        // Ask if tis all good - this step checks pwpolicy and such
+
+        // Deny the change if the account is anonymous!
+        if account.is_anonymous() {
+            Err(OperationError::SystemProtectedObject)
+        } else {
+            Ok(account)
+        }
+    }
+
+    pub fn set_account_password(
+        &mut self,
+        au: &mut AuditScope,
+        pce: &PasswordChangeEvent,
+    ) -> Result<(), OperationError> {
+        let account = self.target_to_account(au, &pce.target)?;
+        // Ask if tis all good - this step checks pwpolicy and such
+
+        // Question: Is it a security issue to reveal pw policy checks BEFORE permission is
         // determined over the credential modification?
         //
         // I don't think so - because we should only be showing how STRONG the pw is ...
@@ -936,15 +2724,31 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
             account.spn.as_str(),
         ];
 
-        self.check_password_quality(au, pce.cleartext.as_str(), related_inputs.as_slice())
-            .map_err(|e| {
-                lrequest_error!(au, "check_password_quality -> {:?}", e);
-                e
-            })?;
+        check_password_quality(
+            au,
+            &self.qs_write,
+            pce.cleartext.as_str(),
+            related_inputs.as_slice(),
+            account.primary.as_ref(),
+            &account.groups,
+            self.breach_corpus,
+        )
+        .map_err(|e| {
+            lrequest_error!(au, "check_password_quality -> {:?}", e);
+            e
+        })?;
+
+        // The current password is one of the history, so we retain one less.
+        let retain = self.password_history_length(au)?.saturating_sub(1);
 
         // it returns a modify
         let modlist = account
-            .gen_password_mod(pce.cleartext.as_str(), &pce.appid, self.crypto_policy)
+            .gen_password_mod(
+                pce.cleartext.as_str(),
+                &pce.appid,
+                self.crypto_policy,
+                Some((self.qs_write.get_curtime(), retain)),
+            )
             .map_err(|e| {
                 ladmin_error!(au, "Failed to generate password mod {:?}", e);
                 e
@@ -975,13 +2779,17 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
         au: &mut AuditScope,
         pce: &UnixPasswordChangeEvent,
     ) -> Result<(), OperationError> {
-        // Get the account
-        let account = self
+        // Get the account, and all of its groups for the password quality policy.
+        let (account, groups) = self
             .qs_write
             .internal_search_uuid(au, &pce.target)
             .and_then(|account_entry| {
                 // Assert the account is unix and valid.
-                UnixUserAccount::try_from_entry_rw(au, &account_entry, &mut self.qs_write)
+                let account =
+                    UnixUserAccount::try_from_entry_rw(au, &account_entry, &mut self.qs_write)?;
+                let groups =
+                    Group::try_from_account_entry_rw(au, &account_entry, &mut self.qs_write)?;
+                Ok((account, groups))
             })
             .map_err(|e| {
                 ladmin_error!(au, "Failed to start set unix account password {:?}", e);
@@ -1001,15 +2809,29 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
             account.spn.as_str(),
         ];
 
-        self.check_password_quality(au, pce.cleartext.as_str(), related_inputs.as_slice())
-            .map_err(|e| {
-                ladmin_error!(au, "Failed to checked password quality {:?}", e);
-                e
-            })?;
+        check_password_quality(
+            au,
+            &self.qs_write,
+            pce.cleartext.as_str(),
+            related_inputs.as_slice(),
+            account.cred_ref(),
+            &groups,
+            self.breach_corpus,
+        )
+        .map_err(|e| {
+            ladmin_error!(au, "Failed to checked password quality {:?}", e);
+            e
+        })?;
+
+        let retain = self.password_history_length(au)?.saturating_sub(1);
 
         // it returns a modify
         let modlist = account
-            .gen_password_mod(pce.cleartext.as_str(), self.crypto_policy)
+            .gen_password_mod(
+                pce.cleartext.as_str(),
+                self.crypto_policy,
+                Some((self.qs_write.get_curtime(), retain)),
+            )
             .map_err(|e| {
                 ladmin_error!(au, "Unable to generate password change modlist {:?}", e);
                 e
@@ -1066,9 +2888,17 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
         // check a password badlist - even if generated, we still don't want to
         // reuse something that has been disclosed.
 
+        // Record the replaced password, so it can't be set again later.
+        let retain = self.password_history_length(au)?.saturating_sub(1);
+
         // it returns a modify
         let modlist = account
-            .gen_password_mod(cleartext.as_str(), &gpe.appid, self.crypto_policy)
+            .gen_password_mod(
+                cleartext.as_str(),
+                &gpe.appid,
+                self.crypto_policy,
+                Some((self.qs_write.get_curtime(), retain)),
+            )
             .map_err(|e| {
                 ladmin_error!(au, "Unable to generate password mod {:?}", e);
                 e
@@ -1134,138 +2964,646 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
             .map(|_| cleartext)
     }
 
-    pub fn reg_account_webauthn_init(
+    pub fn generate_account_app_password(
         &mut self,
         au: &mut AuditScope,
-        wre: &WebauthnInitRegisterEvent,
-        ct: Duration,
-    ) -> Result<SetCredentialResponse, OperationError> {
-        let account = self.target_to_account(au, &wre.target)?;
-        let sessionid = uuid_from_duration(ct, self.sid);
-
-        let origin = (&wre.event.origin).into();
-        let label = wre.label.clone();
-
-        let (session, next) =
-            MfaRegSession::webauthn_new(au, origin, account, label, self.webauthn)?;
-
-        let next = next.to_proto(sessionid);
-
-        // Add session to tree
-        self.mfareg_sessions.insert(sessionid, session);
-        ltrace!(au, "Start mfa reg session -> {:?}", sessionid);
-        Ok(next)
-    }
+        gape: &GenerateAppPasswordEvent,
+    ) -> Result<String, OperationError> {
+        let account = self.target_to_account(au, &gape.target)?;
 
-    pub fn reg_account_webauthn_complete(
-        &mut self,
-        au: &mut AuditScope,
-        wre: &WebauthnDoRegisterEvent,
-    ) -> Result<SetCredentialResponse, OperationError> {
-        let sessionid = wre.session;
-        let origin = (&wre.event.origin).into();
-        let webauthn = self.webauthn;
+        // App passwords are generated so that they are not reused from elsewhere.
+        let cleartext = readable_password_from_random();
 
-        // Regardless of the outcome, we purge this session, so we get it
-        // from the tree instead of a mut pointer.
-        let mut session = self
-            .mfareg_sessions
-            .remove(&sessionid)
-            .ok_or(OperationError::InvalidState)
+        let modlist = account
+            .gen_app_password_mod(
+                gape.label.as_str(),
+                gape.scope,
+                cleartext.as_str(),
+                self.crypto_policy,
+            )
             .map_err(|e| {
-                ladmin_error!(au, "Failed to register webauthn -> {:?}", e);
+                lrequest_error!(au, "Unable to generate app password mod {:?}", e);
                 e
             })?;
+        ltrace!(au, "processing change {:?}", modlist);
 
-        let (next, wan_cred) = session
-            .webauthn_step(au, &origin, &wre.target, &wre.chal, webauthn)
+        self.qs_write
+            .impersonate_modify(
+                au,
+                // Filter as executed
+                &filter!(f_eq("uuid", PartialValue::new_uuidr(&gape.target))),
+                // Filter as intended (acp)
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&gape.target))),
+                &modlist,
+                // Provide the event to impersonate
+                &gape.event,
+            )
             .map_err(|e| {
-                ladmin_error!(au, "Failed to register webauthn -> {:?}", e);
-                OperationError::Webauthn
-            })?;
-
-        if let (MfaRegNext::Success, Some(MfaRegCred::Webauthn(label, cred))) = (&next, wan_cred) {
-            // Persist the credential
-            let modlist = session.account.gen_webauthn_mod(label, cred).map_err(|e| {
-                ladmin_error!(au, "Failed to gen webauthn mod {:?}", e);
+                lrequest_error!(au, "error -> {:?}", e);
                 e
-            })?;
-            // Perform the mod
-            self.qs_write
-                .impersonate_modify(
-                    au,
-                    // Filter as executed
-                    &filter!(f_eq("uuid", PartialValue::new_uuidr(&session.account.uuid))),
-                    // Filter as intended (acp)
-                    &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&session.account.uuid))),
-                    &modlist,
-                    &wre.event,
-                )
-                .map_err(|e| {
-                    ladmin_error!(au, "reg_account_webauthn_complete {:?}", e);
-                    e
-                })?;
-        }
-
-        let next = next.to_proto(sessionid);
-        Ok(next)
+            })
+            .map(|_| cleartext)
     }
 
-    pub fn remove_account_webauthn(
+    pub fn remove_account_app_password(
         &mut self,
         au: &mut AuditScope,
-        rwe: &RemoveWebauthnEvent,
-    ) -> Result<SetCredentialResponse, OperationError> {
-        ltrace!(
-            au,
-            "Attempting to remove webauthn {:?} -> {:?}",
-            rwe.label,
-            rwe.target
-        );
+        rape: &RemoveAppPasswordEvent,
+    ) -> Result<(), OperationError> {
+        let account = self.target_to_account(au, &rape.target)?;
 
-        let account = self.target_to_account(au, &rwe.target)?;
         let modlist = account
-            .gen_webauthn_remove_mod(rwe.label.as_str())
+            .gen_app_password_remove_mod(rape.label.as_str())
             .map_err(|e| {
-                ladmin_error!(au, "Failed to gen webauthn remove mod {:?}", e);
+                lrequest_error!(au, "Unable to generate app password remove mod {:?}", e);
                 e
             })?;
-        // Perform the mod
+        ltrace!(au, "processing change {:?}", modlist);
+
         self.qs_write
             .impersonate_modify(
                 au,
                 // Filter as executed
-                &filter!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                &filter!(f_eq("uuid", PartialValue::new_uuidr(&rape.target))),
                 // Filter as intended (acp)
-                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&rape.target))),
                 &modlist,
-                &rwe.event,
+                // Provide the event to impersonate
+                &rape.event,
             )
             .map_err(|e| {
-                ladmin_error!(au, "remove_account_webauthn {:?}", e);
+                lrequest_error!(au, "error -> {:?}", e);
                 e
             })
-            .map(|_| SetCredentialResponse::Success)
     }
 
-    pub fn generate_account_totp(
+    pub fn clear_account_softlocks(
         &mut self,
         au: &mut AuditScope,
-        gte: &GenerateTOTPEvent,
-        ct: Duration,
-    ) -> Result<SetCredentialResponse, OperationError> {
-        let account = self.target_to_account(au, &gte.target)?;
-        let sessionid = uuid_from_duration(ct, self.sid);
-
-        let origin = (&gte.event.origin).into();
-        let label = gte.label.clone();
-        let (session, next) = MfaRegSession::totp_new(origin, account, label).map_err(|e| {
-            ladmin_error!(au, "Unable to start totp MfaRegSession {:?}", e);
-            e
-        })?;
-
-        let next = next.to_proto(sessionid);
-
+        csle: &ClearSoftLockEvent,
+    ) -> Result<(), OperationError> {
+        // Nothing on the entry changes, but clearing a softlock is only allowed to those
+        // who may change the credentials of the account.
+        self.qs_write
+            .impersonate_modify_allowed(
+                au,
+                // Filter as executed
+                &filter!(f_eq("uuid", PartialValue::new_uuidr(&csle.target))),
+                // Filter as intended (acp)
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&csle.target))),
+                &ModifyList::new_purge("primary_credential"),
+                // Provide the event to impersonate
+                &csle.event,
+            )
+            .map_err(|e| {
+                lrequest_error!(au, "error -> {:?}", e);
+                e
+            })?;
+
+        let entry = self.qs_write.internal_search_uuid(au, &csle.target)?;
+        let creds = softlock_credentials(&entry);
+        lsecurity!(
+            au,
+            "Clearing softlocks of {:?} for {} by {:?}",
+            creds,
+            csle.target,
+            csle.event.get_uuid()
+        );
+        self.softlock_clear
+            .extend(creds.into_iter().map(|(_, cred_uuid)| cred_uuid));
+        Ok(())
+    }
+
+    fn target_to_service_account(
+        &mut self,
+        au: &mut AuditScope,
+        target: &Uuid,
+    ) -> Result<ServiceAccount, OperationError> {
+        self.qs_write
+            .internal_search_uuid(au, target)
+            .and_then(|e| ServiceAccount::try_from_entry_ro(&e))
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to search service account {:?}", e);
+                e
+            })
+    }
+
+    pub fn service_account_generate_api_token(
+        &mut self,
+        au: &mut AuditScope,
+        gte: &GenerateApiTokenEvent,
+        ct: Duration,
+    ) -> Result<String, OperationError> {
+        let service_account = self.target_to_service_account(au, &gte.target)?;
+
+        let cot = time::OffsetDateTime::unix_epoch() + ct;
+        if gte.expiry.map(|exp| exp <= cot).unwrap_or(false) {
+            lrequest_error!(au, "Api token expiry is in the past");
+            return Err(OperationError::InvalidAttribute("expiry".to_string()));
+        }
+
+        let token_id = Uuid::new_v4();
+        let modlist = service_account.generate_api_token_mod(
+            token_id,
+            ApiTokenMeta {
+                label: gte.label.clone(),
+                expiry: gte.expiry,
+                read_only: gte.read_only,
+                last_used: None,
+            },
+        );
+        ltrace!(au, "processing change {:?}", modlist);
+
+        self.qs_write
+            .impersonate_modify(
+                au,
+                // Filter as executed
+                &filter!(f_eq("uuid", PartialValue::new_uuidr(&gte.target))),
+                // Filter as intended (acp)
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&gte.target))),
+                &modlist,
+                // Provide the event to impersonate
+                &gte.event,
+            )
+            .map_err(|e| {
+                lrequest_error!(au, "error -> {:?}", e);
+                e
+            })?;
+
+        // The token is issued as a signed uat for the service account, bound to this
+        // token id so that it can be revoked.
+        let account = self
+            .qs_write
+            .internal_search_uuid(au, &gte.target)
+            .and_then(|e| Account::try_from_entry_rw(au, &e, &mut self.qs_write))?;
+
+        let mut uat = account.to_userauthtoken(&[], ct).ok_or_else(|| {
+            ladmin_error!(au, "Unable to create api token uat");
+            OperationError::InvalidState
+        })?;
+        uat.exp = gte
+            .expiry
+            .map(|exp| exp.unix_timestamp())
+            .unwrap_or(i64::MAX);
+        uat.api_token_id = Some(token_id.to_hyphenated_ref().to_string());
+        uat.read_only = gte.read_only;
+
+        self.qs_write
+            .get_domain_es256_private_key(au)
+            .and_then(|der| JwsSigner::from_es256_der(&der))
+            .and_then(|signer| signer.sign(&uat))
+    }
+
+    pub fn service_account_destroy_api_token(
+        &mut self,
+        au: &mut AuditScope,
+        dte: &DestroyApiTokenEvent,
+    ) -> Result<(), OperationError> {
+        let service_account = self.target_to_service_account(au, &dte.target)?;
+        let modlist = service_account.destroy_api_token_mod(au, &dte.token_id)?;
+        ltrace!(au, "processing change {:?}", modlist);
+
+        self.qs_write
+            .impersonate_modify(
+                au,
+                // Filter as executed
+                &filter!(f_eq("uuid", PartialValue::new_uuidr(&dte.target))),
+                // Filter as intended (acp)
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&dte.target))),
+                &modlist,
+                // Provide the event to impersonate
+                &dte.event,
+            )
+            .map_err(|e| {
+                lrequest_error!(au, "error -> {:?}", e);
+                e
+            })
+    }
+
+    pub fn credential_reset_intent(
+        &mut self,
+        au: &mut AuditScope,
+        crie: &CredentialResetIntentEvent,
+        ct: Duration,
+    ) -> Result<CredentialResetIntent, OperationError> {
+        let account = self.target_to_account(au, &crie.target)?;
+
+        let ttl = crie.ttl.unwrap_or(CREDRESET_INTENT_DEFAULT_TTL);
+        if ttl == 0 || ttl > CREDRESET_INTENT_MAX_TTL {
+            lrequest_error!(au, "Credential reset token ttl {} is out of range", ttl);
+            return Err(OperationError::InvalidRequestState);
+        }
+
+        let intent_id = Uuid::new_v4();
+        let expiry = time::OffsetDateTime::unix_epoch() + ct + Duration::from_secs(ttl);
+        let claims = CredResetIntentClaims {
+            purpose: TokenPurpose::CredentialReset,
+            intent_id,
+            target: account.uuid,
+            exp: expiry.unix_timestamp(),
+        };
+
+        let token = self
+            .qs_write
+            .get_domain_es256_private_key(au)
+            .and_then(|der| JwsSigner::from_es256_der(&der))
+            .and_then(|signer| signer.sign(&claims))
+            .map_err(|e| {
+                ladmin_error!(au, "Unable to sign credential reset token -> {:?}", e);
+                e
+            })?;
+
+        // Only one token may be outstanding, so issuing a new one replaces the last.
+        let modlist =
+            ModifyList::new_purge_and_set("credential_reset_intent", Value::new_uuid(intent_id));
+        self.qs_write
+            .impersonate_modify(
+                au,
+                // Filter as executed
+                &filter!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                // Filter as intended (acp)
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                &modlist,
+                // Provide the event to impersonate
+                &crie.event,
+            )
+            .map_err(|e| {
+                lrequest_error!(au, "error -> {:?}", e);
+                e
+            })?;
+
+        lsecurity!(
+            au,
+            "Issued credential reset token {} for {} by {:?}",
+            intent_id,
+            account.spn,
+            crie.event.get_uuid()
+        );
+
+        Ok(CredentialResetIntent {
+            token,
+            expiry: expiry.format(time::Format::Rfc3339),
+        })
+    }
+
+    fn verify_credential_reset_token(
+        &self,
+        au: &mut AuditScope,
+        token: &str,
+        ct: Duration,
+    ) -> Result<CredResetIntentClaims, OperationError> {
+        let claims: CredResetIntentClaims = self
+            .qs_write
+            .get_domain_es256_private_key(au)
+            .and_then(|der| JwsSigner::from_es256_der(&der))
+            .and_then(|signer| signer.verify(token))
+            .map_err(|e| {
+                lsecurity!(au, "Invalid credential reset token -> {:?}", e);
+                e
+            })?;
+
+        if claims.purpose != TokenPurpose::CredentialReset {
+            lsecurity!(au, "Token is not a credential reset token");
+            return Err(OperationError::InvalidRequestState);
+        }
+
+        if claims.exp <= ct.as_secs() as i64 {
+            lsecurity!(
+                au,
+                "Credential reset token {} has expired",
+                claims.intent_id
+            );
+            return Err(OperationError::InvalidRequestState);
+        }
+        Ok(claims)
+    }
+
+    pub fn credential_reset_begin(
+        &mut self,
+        au: &mut AuditScope,
+        crbe: &CredentialResetBeginEvent,
+        ct: Duration,
+    ) -> Result<CredentialResetSession, OperationError> {
+        let claims = self.verify_credential_reset_token(au, crbe.token.as_str(), ct)?;
+
+        // The token is only valid while it is the outstanding intent of the account.
+        let entry = self.qs_write.internal_search_uuid(au, &claims.target)?;
+        if entry.get_ava_single_uuid("credential_reset_intent") != Some(&claims.intent_id) {
+            lsecurity!(
+                au,
+                "Credential reset token {} was already redeemed or replaced",
+                claims.intent_id
+            );
+            return Err(OperationError::InvalidRequestState);
+        }
+        let account = self.target_to_account(au, &claims.target)?;
+
+        // Consume the intent so that the token can not be redeemed again.
+        self.qs_write
+            .internal_modify(
+                au,
+                &filter!(f_eq("uuid", PartialValue::new_uuidr(&claims.target))),
+                &ModifyList::new_purge("credential_reset_intent"),
+            )
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to consume credential reset intent -> {:?}", e);
+                e
+            })?;
+
+        let sessionid = uuid_from_duration(ct, self.sid);
+        self.credreset_sessions.insert(
+            sessionid,
+            CredResetSession {
+                intent_id: claims.intent_id,
+                target: claims.target,
+            },
+        );
+
+        lsecurity!(
+            au,
+            "Redeemed credential reset token {} for {} -> session {}",
+            claims.intent_id,
+            account.spn,
+            sessionid
+        );
+
+        Ok(CredentialResetSession {
+            sessionid,
+            spn: account.spn,
+        })
+    }
+
+    pub fn credential_reset_step(
+        &mut self,
+        au: &mut AuditScope,
+        crse: &CredentialResetStepEvent,
+        ct: Duration,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        let claims = self.verify_credential_reset_token(au, crse.token.as_str(), ct)?;
+
+        // The session must have been started by this token.
+        let session = self
+            .credreset_sessions
+            .get(&crse.sessionid)
+            .cloned()
+            .filter(|s| s.intent_id == claims.intent_id && s.target == claims.target)
+            .ok_or(OperationError::InvalidRequestState)
+            .map_err(|e| {
+                lsecurity!(
+                    au,
+                    "No credential reset session {} for token {}",
+                    crse.sessionid,
+                    claims.intent_id
+                );
+                e
+            })?;
+        let target = session.target;
+
+        lsecurity!(
+            au,
+            "Credential reset session {} updating credentials of {}",
+            crse.sessionid,
+            target
+        );
+
+        // The holder of the token is not an authenticated identity, so the changes are
+        // made internally, limited to the credentials of the target account.
+        match &crse.sac {
+            SetCredentialRequest::Password(cleartext) => {
+                let pce = PasswordChangeEvent::new_internal(&target, cleartext.as_str(), None);
+                self.set_account_password(au, &pce)
+                    .map(|_| SetCredentialResponse::Success)
+            }
+            SetCredentialRequest::GeneratePassword => {
+                let gpe = GeneratePasswordEvent {
+                    event: Event::from_internal(),
+                    target,
+                    appid: None,
+                };
+                self.generate_account_password(au, &gpe)
+                    .map(SetCredentialResponse::Token)
+            }
+            SetCredentialRequest::TOTPGenerate(label) => {
+                let gte = GenerateTOTPEvent {
+                    event: Event::from_internal(),
+                    target,
+                    label: label.clone(),
+                };
+                self.generate_account_totp(au, &gte, ct)
+            }
+            SetCredentialRequest::TOTPVerify(session, chal) => {
+                let vte = VerifyTOTPEvent {
+                    event: Event::from_internal(),
+                    target,
+                    session: *session,
+                    chal: *chal,
+                };
+                self.verify_account_totp(au, &vte, ct)
+            }
+            SetCredentialRequest::TOTPRemove(label) => {
+                let rte = RemoveTOTPEvent {
+                    event: Event::from_internal(),
+                    target,
+                    label: label.clone(),
+                };
+                self.remove_account_totp(au, &rte)
+            }
+            SetCredentialRequest::TOTPImport(label, secret) => {
+                let ite = ImportTOTPEvent {
+                    event: Event::from_internal(),
+                    target,
+                    label: label.clone(),
+                    secret: secret.clone(),
+                };
+                self.import_account_totp(au, &ite)
+            }
+            SetCredentialRequest::WebauthnBegin(label) => {
+                let wre = WebauthnInitRegisterEvent {
+                    event: Event::from_internal(),
+                    target,
+                    label: label.clone(),
+                };
+                self.reg_account_webauthn_init(au, &wre, ct)
+            }
+            SetCredentialRequest::WebauthnRegister(session, chal) => {
+                let wre = WebauthnDoRegisterEvent {
+                    event: Event::from_internal(),
+                    target,
+                    session: *session,
+                    chal: chal.clone(),
+                };
+                self.reg_account_webauthn_complete(au, &wre)
+            }
+            SetCredentialRequest::WebauthnRemove(label) => {
+                let rwe = RemoveWebauthnEvent {
+                    event: Event::from_internal(),
+                    target,
+                    label: label.clone(),
+                };
+                self.remove_account_webauthn(au, &rwe)
+            }
+            SetCredentialRequest::BackupCodeGenerate => {
+                let gbe = GenerateBackupCodeEvent {
+                    event: Event::from_internal(),
+                    target,
+                };
+                self.generate_backup_codes(au, &gbe)
+            }
+        }
+    }
+
+    pub fn reg_account_webauthn_init(
+        &mut self,
+        au: &mut AuditScope,
+        wre: &WebauthnInitRegisterEvent,
+        ct: Duration,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        let account = self.target_to_account(au, &wre.target)?;
+        let sessionid = uuid_from_duration(ct, self.sid);
+
+        let origin = (&wre.event.origin).into();
+        let label = wre.label.clone();
+
+        let webauthn_policy = webauthn_policy(au, &self.qs_write)?;
+        let (session, next) = MfaRegSession::webauthn_new(
+            au,
+            origin,
+            account,
+            label,
+            self.webauthn,
+            &webauthn_policy,
+        )?;
+
+        let next = next.to_proto(sessionid);
+
+        // Add session to tree
+        self.mfareg_sessions.insert(sessionid, session);
+        ltrace!(au, "Start mfa reg session -> {:?}", sessionid);
+        Ok(next)
+    }
+
+    pub fn reg_account_webauthn_complete(
+        &mut self,
+        au: &mut AuditScope,
+        wre: &WebauthnDoRegisterEvent,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        let sessionid = wre.session;
+        let origin = (&wre.event.origin).into();
+        let webauthn = self.webauthn;
+
+        // Regardless of the outcome, we purge this session, so we get it
+        // from the tree instead of a mut pointer.
+        let mut session = self
+            .mfareg_sessions
+            .remove(&sessionid)
+            .ok_or(OperationError::InvalidState)
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to register webauthn -> {:?}", e);
+                e
+            })?;
+
+        let webauthn_policy = webauthn_policy(au, &self.qs_write)?;
+        let (next, wan_cred) = session
+            .webauthn_step(
+                au,
+                &origin,
+                &wre.target,
+                &wre.chal,
+                webauthn,
+                &webauthn_policy,
+            )
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to register webauthn -> {:?}", e);
+                e
+            })?;
+
+        if let (MfaRegNext::Success, Some(MfaRegCred::Webauthn(label, cred, model))) =
+            (&next, wan_cred)
+        {
+            // Persist the credential
+            let modlist = session
+                .account
+                .gen_webauthn_mod(label, cred, model)
+                .map_err(|e| {
+                    ladmin_error!(au, "Failed to gen webauthn mod {:?}", e);
+                    e
+                })?;
+            // Perform the mod
+            self.qs_write
+                .impersonate_modify(
+                    au,
+                    // Filter as executed
+                    &filter!(f_eq("uuid", PartialValue::new_uuidr(&session.account.uuid))),
+                    // Filter as intended (acp)
+                    &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&session.account.uuid))),
+                    &modlist,
+                    &wre.event,
+                )
+                .map_err(|e| {
+                    ladmin_error!(au, "reg_account_webauthn_complete {:?}", e);
+                    e
+                })?;
+        }
+
+        let next = next.to_proto(sessionid);
+        Ok(next)
+    }
+
+    pub fn remove_account_webauthn(
+        &mut self,
+        au: &mut AuditScope,
+        rwe: &RemoveWebauthnEvent,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        ltrace!(
+            au,
+            "Attempting to remove webauthn {:?} -> {:?}",
+            rwe.label,
+            rwe.target
+        );
+
+        let account = self.target_to_account(au, &rwe.target)?;
+        let modlist = account
+            .gen_webauthn_remove_mod(rwe.label.as_str())
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to gen webauthn remove mod {:?}", e);
+                e
+            })?;
+        // Perform the mod
+        self.qs_write
+            .impersonate_modify(
+                au,
+                // Filter as executed
+                &filter!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                // Filter as intended (acp)
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                &modlist,
+                &rwe.event,
+            )
+            .map_err(|e| {
+                ladmin_error!(au, "remove_account_webauthn {:?}", e);
+                e
+            })
+            .map(|_| SetCredentialResponse::Success)
+    }
+
+    pub fn generate_account_totp(
+        &mut self,
+        au: &mut AuditScope,
+        gte: &GenerateTOTPEvent,
+        ct: Duration,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        let account = self.target_to_account(au, &gte.target)?;
+        let sessionid = uuid_from_duration(ct, self.sid);
+
+        let origin = (&gte.event.origin).into();
+        let label = gte.label.clone();
+        let (session, next) = MfaRegSession::totp_new(origin, account, label).map_err(|e| {
+            ladmin_error!(au, "Unable to start totp MfaRegSession {:?}", e);
+            e
+        })?;
+
+        let next = next.to_proto(sessionid);
+
         // Add session to tree
         self.mfareg_sessions.insert(sessionid, session);
         ltrace!(au, "Start mfa reg session -> {:?}", sessionid);
@@ -1338,7 +3676,7 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
         ltrace!(au, "Attempting to remove totp -> {:?}", rte.target);
 
         let account = self.target_to_account(au, &rte.target)?;
-        let modlist = account.gen_totp_remove_mod().map_err(|e| {
+        let modlist = account.gen_totp_remove_mod(&rte.label).map_err(|e| {
             ladmin_error!(au, "Failed to gen totp remove mod {:?}", e);
             e
         })?;
@@ -1360,6 +3698,74 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
             .map(|_| SetCredentialResponse::Success)
     }
 
+    pub fn import_account_totp(
+        &mut self,
+        au: &mut AuditScope,
+        ite: &ImportTOTPEvent,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        ltrace!(au, "Attempting to import totp -> {:?}", ite.target);
+
+        let account = self.target_to_account(au, &ite.target)?;
+        // Unlike a generated totp there is no verification step, as the secret is
+        // already in use by the existing device.
+        let token = TOTP::from_import(ite.label.clone(), ite.secret.clone()).map_err(|e| {
+            ladmin_error!(au, "Refusing to import totp {:?}", e);
+            e
+        })?;
+        let modlist = account.gen_totp_mod(token).map_err(|e| {
+            ladmin_error!(au, "Failed to gen totp mod {:?}", e);
+            e
+        })?;
+        // Perform the mod
+        self.qs_write
+            .impersonate_modify(
+                au,
+                // Filter as executed
+                &filter!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                // Filter as intended (acp)
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                &modlist,
+                &ite.event,
+            )
+            .map_err(|e| {
+                ladmin_error!(au, "import_account_totp {:?}", e);
+                e
+            })
+            .map(|_| SetCredentialResponse::Success)
+    }
+
+    pub fn generate_backup_codes(
+        &mut self,
+        au: &mut AuditScope,
+        gbe: &GenerateBackupCodeEvent,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        let account = self.target_to_account(au, &gbe.target)?;
+
+        // Any existing codes are replaced, so codes that may have been seen by
+        // someone else can be revoked by generating a new set.
+        let (backup_codes, codes) = BackupCodes::generate();
+        let modlist = account.gen_backup_code_mod(backup_codes).map_err(|e| {
+            ladmin_error!(au, "Failed to gen backup code mod {:?}", e);
+            e
+        })?;
+        // Perform the mod
+        self.qs_write
+            .impersonate_modify(
+                au,
+                // Filter as executed
+                &filter!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                // Filter as intended (acp)
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&account.uuid))),
+                &modlist,
+                &gbe.event,
+            )
+            .map_err(|e| {
+                ladmin_error!(au, "generate_backup_codes {:?}", e);
+                e
+            })
+            .map(|_| SetCredentialResponse::BackupCodes(codes))
+    }
+
     // -- delayed action processing --
     fn process_pwupgrade(
         &mut self,
@@ -1379,6 +3785,7 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
                     pwu.existing_password.as_str(),
                     &pwu.appid,
                     self.crypto_policy,
+                    None,
                 )
                 .map_err(|e| {
                     ladmin_error!(au, "Unable to generate password mod {:?}", e);
@@ -1387,79 +3794,301 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
 
             self.qs_write.internal_modify(
                 au,
-                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&pwu.target_uuid))),
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&pwu.target_uuid))),
+                &modlist,
+            )
+        } else {
+            // No action needed, it's probably been changed/updated already.
+            Ok(())
+        }
+    }
+
+    fn process_unixpwupgrade(
+        &mut self,
+        au: &mut AuditScope,
+        pwu: &UnixPasswordUpgrade,
+    ) -> Result<(), OperationError> {
+        let account = self
+            .qs_write
+            .internal_search_uuid(au, &pwu.target_uuid)
+            .and_then(|account_entry| {
+                UnixUserAccount::try_from_entry_rw(au, &account_entry, &mut self.qs_write)
+            })
+            .map_err(|e| {
+                ladmin_error!(au, "Failed to start unix pw upgrade -> {:?}", e);
+                e
+            })?;
+
+        let same = account.check_existing_pw(pwu.existing_password.as_str())?;
+
+        if same {
+            let modlist = account
+                .gen_password_mod(pwu.existing_password.as_str(), self.crypto_policy, None)
+                .map_err(|e| {
+                    ladmin_error!(au, "Unable to generate password mod {:?}", e);
+                    e
+                })?;
+
+            self.qs_write.internal_modify(
+                au,
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&pwu.target_uuid))),
+                &modlist,
+            )
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn process_webauthncounterinc(
+        &mut self,
+        au: &mut AuditScope,
+        wci: &WebauthnCounterIncrement,
+    ) -> Result<(), OperationError> {
+        let account = self.target_to_account(au, &wci.target_uuid)?;
+
+        // Generate an optional mod and then attempt to apply it.
+        let opt_modlist = account
+            .gen_webauthn_counter_mod(&wci.cid, wci.counter)
+            .map_err(|e| {
+                ladmin_error!(au, "Unable to generate webauthn counter mod {:?}", e);
+                e
+            })?;
+
+        if let Some(modlist) = opt_modlist {
+            self.qs_write.internal_modify(
+                au,
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&wci.target_uuid))),
+                &modlist,
+            )
+        } else {
+            // No mod needed.
+            ltrace!(au, "No modification required");
+            Ok(())
+        }
+    }
+
+    pub(crate) fn process_apitokenused(
+        &mut self,
+        au: &mut AuditScope,
+        atu: &ApiTokenUsed,
+    ) -> Result<(), OperationError> {
+        let service_account = self.target_to_service_account(au, &atu.target_uuid)?;
+
+        if let Some(modlist) = service_account.api_token_used_mod(&atu.token_id, atu.ct) {
+            self.qs_write.internal_modify(
+                au,
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&atu.target_uuid))),
+                &modlist,
+            )
+        } else {
+            // No mod needed.
+            ltrace!(au, "No modification required");
+            Ok(())
+        }
+    }
+
+    pub(crate) fn process_lastlogin(
+        &mut self,
+        au: &mut AuditScope,
+        ll: &LastLogin,
+    ) -> Result<(), OperationError> {
+        let account = self.target_to_account(au, &ll.target_uuid)?;
+
+        if let Some(modlist) = account.last_login_mod(ll.mechanism, ll.ct) {
+            self.qs_write.internal_modify(
+                au,
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&ll.target_uuid))),
                 &modlist,
             )
         } else {
-            // No action needed, it's probably been changed/updated already.
+            ltrace!(au, "No modification required");
             Ok(())
         }
     }
 
-    fn process_unixpwupgrade(
+    /// Expire the accounts that have not authenticated within the inactivity period set
+    /// on the system configuration, other than members of the exclusion group and the
+    /// builtin administrators. Accounts that have never authenticated have no record to
+    /// judge them by, so they are left alone.
+    pub fn expire_inactive_accounts(
         &mut self,
         au: &mut AuditScope,
-        pwu: &UnixPasswordUpgrade,
-    ) -> Result<(), OperationError> {
-        let account = self
+        ct: Duration,
+    ) -> Result<usize, OperationError> {
+        let config_entry = self
             .qs_write
-            .internal_search_uuid(au, &pwu.target_uuid)
-            .and_then(|account_entry| {
-                UnixUserAccount::try_from_entry_rw(au, &account_entry, &mut self.qs_write)
-            })
+            .internal_search_uuid(au, &UUID_SYSTEM_CONFIG)
             .map_err(|e| {
-                ladmin_error!(au, "Failed to start unix pw upgrade -> {:?}", e);
+                ladmin_error!(au, "Failed to retrieve system configuration {:?}", e);
                 e
             })?;
 
-        let same = account.check_existing_pw(pwu.existing_password.as_str())?;
+        let days = match config_entry
+            .get_ava_single_uint32("account_inactivity_days")
+            .filter(|d| *d > 0)
+        {
+            Some(days) => days,
+            None => return Ok(0),
+        };
+        let cot = time::OffsetDateTime::unix_epoch() + ct;
+        let cutoff = cot - Duration::from_secs(u64::from(days) * 86400);
 
-        if same {
-            let modlist = account
-                .gen_password_mod(pwu.existing_password.as_str(), self.crypto_policy)
-                .map_err(|e| {
-                    ladmin_error!(au, "Unable to generate password mod {:?}", e);
-                    e
-                })?;
+        let mut excluded = vec![
+            f_eq("uuid", PartialValue::new_uuidr(&UUID_ADMIN)),
+            f_eq("uuid", PartialValue::new_uuidr(&UUID_IDM_ADMIN)),
+        ];
+        if let Some(group) = config_entry
+            .get_ava_as_refuuid("account_inactivity_exclude_group")
+            .and_then(|mut groups| groups.next())
+        {
+            excluded.push(f_eq("memberof", PartialValue::new_refer_r(group)));
+        }
 
-            self.qs_write.internal_modify(
+        let inactive: Vec<_> = self
+            .qs_write
+            .internal_search(
                 au,
-                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&pwu.target_uuid))),
-                &modlist,
-            )
-        } else {
-            Ok(())
+                filter!(f_and!([
+                    f_eq("class", PartialValue::new_class("account")),
+                    f_pres("last_login"),
+                    f_andnot(f_or(excluded))
+                ])),
+            )?
+            .into_iter()
+            .filter(|entry| {
+                entry
+                    .get_ava_single_datetime("last_login")
+                    .map(|ll| ll < cutoff)
+                    .unwrap_or(false)
+                    && entry
+                        .get_ava_single_datetime("account_expire")
+                        .map(|exp| exp > cot)
+                        .unwrap_or(true)
+            })
+            .collect();
+
+        if inactive.is_empty() {
+            return Ok(0);
+        }
+
+        for entry in inactive.iter() {
+            lsecurity!(
+                au,
+                "Expiring account {} after {} days of inactivity",
+                entry.get_ava_single_str("name").unwrap_or("-"),
+                days
+            );
         }
+
+        self.qs_write
+            .internal_modify(
+                au,
+                &filter_all!(f_or(
+                    inactive
+                        .iter()
+                        .map(|entry| f_eq("uuid", PartialValue::new_uuidr(entry.get_uuid())))
+                        .collect()
+                )),
+                &ModifyList::new_purge_and_set("account_expire", Value::new_datetime_epoch(ct)),
+            )
+            .map(|_| inactive.len())
     }
 
-    pub(crate) fn process_webauthncounterinc(
+    /// Replace the expired password of an account. The quality of the new password is
+    /// checked as it is for any other password change.
+    pub(crate) fn set_expired_password(
         &mut self,
         au: &mut AuditScope,
-        wci: &WebauthnCounterIncrement,
+        target: &Uuid,
+        cleartext: &str,
+        ct: Duration,
     ) -> Result<(), OperationError> {
-        let account = self.target_to_account(au, &wci.target_uuid)?;
+        let account = self.target_to_account(au, target)?;
 
-        // Generate an optional mod and then attempt to apply it.
-        let opt_modlist = account
-            .gen_webauthn_counter_mod(&wci.cid, wci.counter)
+        let related_inputs = [
+            account.name.as_str(),
+            account.displayname.as_str(),
+            account.spn.as_str(),
+        ];
+        check_password_quality(
+            au,
+            &self.qs_write,
+            cleartext,
+            &related_inputs,
+            account.primary.as_ref(),
+            &account.groups,
+            self.breach_corpus,
+        )
+        .map_err(|e| {
+            lrequest_error!(au, "check_password_quality -> {:?}", e);
+            e
+        })?;
+
+        let retain = self.password_history_length(au)?.saturating_sub(1);
+
+        let modlist = account
+            .gen_password_mod(cleartext, &None, self.crypto_policy, Some((ct, retain)))
             .map_err(|e| {
-                ladmin_error!(au, "Unable to generate webauthn counter mod {:?}", e);
+                ladmin_error!(au, "Unable to generate password mod {:?}", e);
                 e
             })?;
 
-        if let Some(modlist) = opt_modlist {
+        self.qs_write.internal_modify(
+            au,
+            &filter_all!(f_eq("uuid", PartialValue::new_uuidr(target))),
+            &modlist,
+        )
+    }
+
+    pub(crate) fn process_backupcoderemoval(
+        &mut self,
+        au: &mut AuditScope,
+        bcr: &BackupCodeRemoval,
+    ) -> Result<(), OperationError> {
+        let account = self.target_to_account(au, &bcr.target_uuid)?;
+
+        if let Some(modlist) = account.gen_backup_code_remove_mod(bcr.code_to_remove.as_str()) {
             self.qs_write.internal_modify(
                 au,
-                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&wci.target_uuid))),
+                &filter_all!(f_eq("uuid", PartialValue::new_uuidr(&bcr.target_uuid))),
                 &modlist,
             )
         } else {
-            // No mod needed.
+            // The codes were regenerated or removed since this was used.
             ltrace!(au, "No modification required");
             Ok(())
         }
     }
 
+    pub(crate) fn process_softlockupdate(
+        &mut self,
+        au: &mut AuditScope,
+        updates: BTreeMap<Uuid, Option<DbSoftLockV1>>,
+        ct: Duration,
+    ) -> Result<(), OperationError> {
+        updates
+            .into_iter()
+            .try_for_each(|(cred_uuid, state)| {
+                self.qs_write.write_softlock(au, &cred_uuid, state.as_ref())
+            })
+            .and_then(|_| self.qs_write.purge_softlocks(au, &ct))
+    }
+
+    /// Write authentication attempts to the audit store, and trim it to its bounds.
+    pub(crate) fn process_authrecords(
+        &mut self,
+        au: &mut AuditScope,
+        records: &[DbAuthRecordV1],
+        ct: Duration,
+    ) -> Result<(), OperationError> {
+        let config = auth_audit_config(au, &self.qs_write)?;
+        let before = ct.checked_sub(config.retention).unwrap_or_default();
+        self.qs_write.write_auth_records(au, records).and_then(|_| {
+            self.qs_write
+                .purge_auth_records(au, &before, config.max_records)
+        })
+    }
+
     pub(crate) fn process_delayedaction(
         &mut self,
         au: &mut AuditScope,
@@ -1471,13 +4100,41 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
             DelayedAction::WebauthnCounterIncrement(wci) => {
                 self.process_webauthncounterinc(au, &wci)
             }
+            DelayedAction::ApiTokenUsed(atu) => self.process_apitokenused(au, &atu),
+            DelayedAction::BackupCodeRemoval(bcr) => self.process_backupcoderemoval(au, &bcr),
+            DelayedAction::LastLogin(ll) => self.process_lastlogin(au, &ll),
         }
     }
 
     pub fn commit(self, au: &mut AuditScope) -> Result<(), OperationError> {
         lperf_trace_segment!(au, "idm::server::IdmServerWriteTransaction::commit", || {
             self.mfareg_sessions.commit();
-            self.qs_write.commit(au)
+            self.credreset_sessions.commit();
+            self.oauth2_sessions.commit();
+            self.qs_write.commit(au)?;
+
+            if !self.softlock_clear.is_empty() {
+                let mut softlock_write = self.softlocks.write();
+                for cred_uuid in self.softlock_clear {
+                    softlock_write.remove(&cred_uuid);
+                    // Removal from the database is queued behind any earlier failures.
+                    if self
+                        .softlock_tx
+                        .send(SoftLockUpdate {
+                            cred_uuid,
+                            state: None,
+                        })
+                        .is_err()
+                    {
+                        ladmin_error!(
+                            au,
+                            "CRITICAL: unable to queue softlock update, continuing ... "
+                        );
+                    }
+                }
+                softlock_write.commit();
+            }
+            Ok(())
         })
     }
 }
@@ -1486,36 +4143,51 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
 
 #[cfg(test)]
 mod tests {
+    use crate::be::AuthRecordFilter;
     use crate::constants::{
-        AUTH_SESSION_TIMEOUT, MFAREG_SESSION_TIMEOUT, UUID_ADMIN, UUID_ANONYMOUS,
+        APP_PASSWORD_MAX, AUTH_SESSION_TIMEOUT, CREDRESET_INTENT_DEFAULT_TTL,
+        MFAREG_SESSION_TIMEOUT, UUID_ADMIN, UUID_ANONYMOUS, UUID_SYSTEM_CONFIG,
     };
     use crate::credential::policy::CryptoPolicy;
     use crate::credential::totp::TOTP;
-    use crate::credential::{Credential, Password};
+    use crate::credential::{Credential, CredentialType, Password};
+    use crate::crypto::{ClientCertificate, JwsSigner};
     use crate::entry::{Entry, EntryInit, EntryNew};
-    use crate::event::{AuthEvent, AuthResult, CreateEvent, ModifyEvent};
-    use crate::idm::delayed::{DelayedAction, WebauthnCounterIncrement};
+    use crate::event::{AuthEvent, AuthResult, CreateEvent, Event, ModifyEvent};
+    use crate::idm::authaudit::AUTH_AUDIT_SEARCH_LIMIT;
+    use crate::idm::delayed::{BackupCodeRemoval, DelayedAction, WebauthnCounterIncrement};
     use crate::idm::event::{
-        GenerateTOTPEvent, PasswordChangeEvent, RadiusAuthTokenEvent, RegenerateRadiusSecretEvent,
-        RemoveTOTPEvent, RemoveWebauthnEvent, UnixGroupTokenEvent, UnixPasswordChangeEvent,
-        UnixUserAuthEvent, UnixUserTokenEvent, VerifyTOTPEvent, WebauthnDoRegisterEvent,
-        WebauthnInitRegisterEvent,
+        AuthAuditSearchEvent, ClearSoftLockEvent, CredentialResetBeginEvent,
+        CredentialResetIntentEvent, CredentialResetStepEvent, DestroyApiTokenEvent,
+        GenerateApiTokenEvent, GenerateAppPasswordEvent, GenerateBackupCodeEvent,
+        GenerateTOTPEvent, ImportTOTPEvent, LdapAuthEvent, ListApiTokenEvent, ListAppPasswordEvent,
+        PasswordChangeEvent, RadiusAuthEvent, RadiusAuthTokenEvent, ReadSoftLockEvent,
+        RegenerateRadiusSecretEvent, RemoveAppPasswordEvent, RemoveTOTPEvent, RemoveWebauthnEvent,
+        SshCertSignEvent, UnixGroupTokenEvent, UnixPasswordChangeEvent, UnixUserAuthEvent,
+        UnixUserTokenEvent, VerifyTOTPEvent, WebauthnDoRegisterEvent, WebauthnInitRegisterEvent,
     };
+    use crate::idm::mfareg::CredResetIntentClaims;
     use crate::idm::AuthState;
     use crate::modify::{Modify, ModifyList};
     use crate::value::{PartialValue, Value};
+    use kanidm_proto::jws::{JwsValidator, TokenPurpose};
+    use kanidm_proto::v1::AppPasswordScope;
     use kanidm_proto::v1::OperationError;
-    use kanidm_proto::v1::SetCredentialResponse;
     use kanidm_proto::v1::{AuthAllowed, AuthMech};
+    use kanidm_proto::v1::{SetCredentialRequest, SetCredentialResponse, TOTPSecret};
 
     use crate::audit::AuditScope;
-    use crate::idm::server::IdmServer;
+    use crate::idm::ratelimit::{RateLimitConfig, RateLimitKey};
+    use crate::idm::server::{
+        IdmServer, IdmServerProxyWriteTransaction, RATE_LIMITED_MSG, UNKNOWN_DISCOVERABLE_MSG,
+    };
     // , IdmServerDelayed;
-    use crate::server::QueryServer;
+    use crate::server::{QueryServer, QueryServerTransaction};
     use crate::utils::duration_from_epoch_now;
     use async_std::task;
     use smartstring::alias::String as AttrString;
     use std::convert::TryFrom;
+    use std::net::IpAddr;
     use std::time::Duration;
     use uuid::Uuid;
     use webauthn_authenticator_rs::{softtok::U2FSoft, WebauthnAuthenticator};
@@ -1830,10 +4502,11 @@ mod tests {
     fn test_idm_simple_password_auth() {
         run_idm_test!(|qs: &QueryServer,
                        idms: &IdmServer,
-                       _idms_delayed: &IdmServerDelayed,
+                       idms_delayed: &mut IdmServerDelayed,
                        au: &mut AuditScope| {
             init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
             check_admin_password(idms, au, TEST_PASSWORD);
+            idms_delayed.skip_last_login();
         })
     }
 
@@ -1841,7 +4514,7 @@ mod tests {
     fn test_idm_simple_password_spn_auth() {
         run_idm_test!(|qs: &QueryServer,
                        idms: &IdmServer,
-                       _idms_delayed: &IdmServerDelayed,
+                       idms_delayed: &mut IdmServerDelayed,
                        au: &mut AuditScope| {
             init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
 
@@ -1888,75 +4561,494 @@ mod tests {
                 }
             };
 
-            idms_write.commit(au).expect("Must not fail");
+            idms_write.commit(au).expect("Must not fail");
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_simple_password_invalid() {
+        run_idm_test!(|qs: &QueryServer,
+                       idms: &IdmServer,
+                       _idms_delayed: &IdmServerDelayed,
+                       au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let sid = init_admin_authsession_sid(
+                idms,
+                au,
+                Duration::from_secs(TEST_CURRENT_TIME),
+                "admin",
+            );
+            let mut idms_write = idms.write();
+            let anon_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD_INC);
+
+            // Expect success
+            let r2 = task::block_on(idms_write.auth(
+                au,
+                &anon_step,
+                Duration::from_secs(TEST_CURRENT_TIME),
+            ));
+            debug!("r2 ==> {:?}", r2);
+
+            match r2 {
+                Ok(ar) => {
+                    let AuthResult {
+                        sessionid: _,
+                        state,
+                        delay,
+                    } = ar;
+                    debug_assert!(delay.is_none());
+                    match state {
+                        AuthState::Denied(_reason) => {
+                            // Check the uat.
+                        }
+                        _ => {
+                            error!("A critical error has occured! We have a non-denied result!");
+                            panic!();
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("A critical error has occured! {:?}", e);
+                    // Should not occur!
+                    panic!();
+                }
+            };
+
+            idms_write.commit(au).expect("Must not fail");
+        })
+    }
+
+    #[test]
+    fn test_idm_auth_audit_records() {
+        run_idm_test!(|qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+
+            // A success, then a failure, then a name that does not exist.
+            check_admin_password(idms, au, TEST_PASSWORD);
+
+            let sid = init_admin_authsession_sid(idms, au, ct, "admin");
+            let mut idms_write = idms.write();
+            let anon_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD_INC);
+            let r2 = task::block_on(idms_write.auth(au, &anon_step, ct));
+            assert!(matches!(
+                r2,
+                Ok(AuthResult {
+                    state: AuthState::Denied(_),
+                    ..
+                })
+            ));
+            idms_write.commit(au).expect("Must not fail");
+
+            let mut idms_write = idms.write();
+            let r3 = task::block_on(idms_write.auth(au, &AuthEvent::named_init("Nobody"), ct));
+            assert!(r3.is_err());
+            idms_write.commit(au).expect("Must not fail");
+
+            let records: Vec<_> = (0..3)
+                .map(|_| {
+                    idms_delayed
+                        .try_recv_auth_record()
+                        .expect("Auth record not queued")
+                })
+                .collect();
+            assert!(idms_delayed.try_recv_auth_record().is_err());
+
+            let mut idms_prox_write = idms.proxy_write(ct);
+            assert!(idms_prox_write
+                .process_authrecords(au, records.as_slice(), ct)
+                .is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let filter = |account: &str, success: Option<bool>| AuthRecordFilter {
+                account: Some(account.to_string()),
+                success,
+                since: Duration::from_secs(0),
+                until: ct,
+                limit: AUTH_AUDIT_SEARCH_LIMIT,
+            };
+
+            let mut idms_prox_read = idms.proxy_read();
+            let aase = AuthAuditSearchEvent::new_impersonate(
+                Event::from_internal(),
+                filter("admin", None),
+            );
+            let r = idms_prox_read
+                .search_auth_records(au, &aase)
+                .expect("Failed to search auth records");
+            // Newest first.
+            assert!(r.len() == 2);
+            assert!(!r[0].success);
+            assert!(r[0].mechanism.as_deref() == Some("password"));
+            assert!(r[0].protocol == "https");
+            assert!(r[1].success);
+
+            let aase = AuthAuditSearchEvent::new_impersonate(
+                Event::from_internal(),
+                filter("nobody", Some(false)),
+            );
+            let r = idms_prox_read
+                .search_auth_records(au, &aase)
+                .expect("Failed to search auth records");
+            assert!(r.len() == 1);
+            assert!(r[0].account_uuid.is_none());
+
+            // Admin is a member of the read group through system_admins, anonymous is not.
+            let admin = idms_prox_read
+                .qs_read
+                .internal_search_uuid(au, &UUID_ADMIN)
+                .expect("Can't access admin entry.");
+            let aase = AuthAuditSearchEvent::new_impersonate(
+                Event::from_impersonate_entry(admin.as_ref().clone()),
+                filter("admin", Some(true)),
+            );
+            let r = idms_prox_read.search_auth_records(au, &aase);
+            assert!(matches!(r, Ok(r) if r.len() == 1));
+
+            let anon = idms_prox_read
+                .qs_read
+                .internal_search_uuid(au, &UUID_ANONYMOUS)
+                .expect("Can't access anonymous entry.");
+            let aase = AuthAuditSearchEvent::new_impersonate(
+                Event::from_impersonate_entry(anon.as_ref().clone()),
+                filter("admin", None),
+            );
+            let r = idms_prox_read.search_auth_records(au, &aase);
+            assert!(matches!(r, Err(OperationError::AccessDenied)));
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_client_certificate_auth() {
+        run_idm_test!(|qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let cert = ClientCertificate {
+                fingerprint: "0a1b".repeat(16),
+                subject: Some("admin".to_string()),
+            };
+            let unbound = ClientCertificate {
+                fingerprint: "b1a0".repeat(16),
+                subject: Some("nobody".to_string()),
+            };
+
+            let qs_write = qs.write(ct);
+            let me_inv_m = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iname("admin"))),
+                    ModifyList::new_list(vec![Modify::Present(
+                        AttrString::from("x509_fingerprint"),
+                        Value::new_iutf8(cert.fingerprint.as_str()),
+                    )]),
+                )
+            };
+            assert!(qs_write.modify(au, &me_inv_m).is_ok());
+            assert!(qs_write.commit(au).is_ok());
+
+            // The certificate is offered beside the password when it's presented.
+            let mut idms_write = idms.write();
+            let admin_init = AuthEvent::named_init("admin").with_client_certificate(cert.clone());
+            let sid = match task::block_on(idms_write.auth(au, &admin_init, ct)) {
+                Ok(AuthResult {
+                    sessionid,
+                    state: AuthState::Choose(mechs),
+                    ..
+                }) => {
+                    assert!(mechs.contains(&AuthMech::Password));
+                    assert!(mechs.contains(&AuthMech::ClientCertificate));
+                    sessionid
+                }
+                _ => panic!(),
+            };
+            let admin_begin = AuthEvent::begin_mech(sid, AuthMech::ClientCertificate);
+            let r = task::block_on(idms_write.auth(au, &admin_begin, ct));
+            assert!(matches!(
+                r,
+                Ok(AuthResult {
+                    state: AuthState::Continue(_),
+                    ..
+                })
+            ));
+            let admin_step =
+                AuthEvent::cred_step_client_certificate(sid).with_client_certificate(cert.clone());
+            let r = task::block_on(idms_write.auth(au, &admin_step, ct));
+            assert!(matches!(
+                r,
+                Ok(AuthResult {
+                    state: AuthState::Success(_),
+                    ..
+                })
+            ));
+
+            // Without the certificate, it's not offered.
+            let r = task::block_on(idms_write.auth(au, &AuthEvent::named_init("admin"), ct));
+            assert!(matches!(
+                r,
+                Ok(AuthResult {
+                    state: AuthState::Choose(mechs),
+                    ..
+                }) if mechs == vec![AuthMech::Password]
+            ));
+
+            // LDAP binds implicitly as the bound account.
+            let lbt = idms_write
+                .auth_ldap_certificate(au, &cert, None, ct)
+                .expect("Failed to bind ldap")
+                .expect("No ldap bound token");
+            assert!(lbt.uuid == *UUID_ADMIN);
+            assert!(idms_write
+                .auth_ldap_certificate(au, &unbound, None, ct)
+                .expect("Failed to bind ldap")
+                .is_none());
+            idms_write.commit(au).expect("Must not fail");
+
+            let records: Vec<_> = (0..3)
+                .map(|_| {
+                    idms_delayed
+                        .try_recv_auth_record()
+                        .expect("Auth record not queued")
+                })
+                .collect();
+            assert!(idms_delayed.try_recv_auth_record().is_err());
+            assert!(records[0].s && records[0].m.as_deref() == Some("x509"));
+            assert!(records[1].s && records[1].p == "ldap");
+            assert!(!records[2].s && records[2].u.is_none());
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_simple_password_reset() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       _idms_delayed: &IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+        })
+    }
+
+    #[test]
+    fn test_idm_password_history() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       _idms_delayed: &IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            // Block reuse of the current and previous password.
+            let me_hist = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("uuid", PartialValue::new_uuidr(&UUID_SYSTEM_CONFIG))),
+                    ModifyList::new_purge_and_set("password_history_length", Value::new_uint32(2)),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_hist).is_ok());
+
+            let pce_a = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+            let pce_b = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC, None);
+            let pce_c =
+                PasswordChangeEvent::new_internal(&UUID_ADMIN, "eeNg5ahkie6Ahng8yoo9ohqu", None);
+
+            assert!(idms_prox_write.set_account_password(au, &pce_a).is_ok());
+            assert!(
+                idms_prox_write.set_account_password(au, &pce_a)
+                    == Err(OperationError::PasswordInHistory)
+            );
+            assert!(idms_prox_write.set_account_password(au, &pce_b).is_ok());
+            assert!(
+                idms_prox_write.set_account_password(au, &pce_a)
+                    == Err(OperationError::PasswordInHistory)
+            );
+            // Once it falls out of the history it can be used again.
+            assert!(idms_prox_write.set_account_password(au, &pce_c).is_ok());
+            assert!(idms_prox_write.set_account_password(au, &pce_a).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
         })
     }
 
     #[test]
-    fn test_idm_simple_password_invalid() {
-        run_idm_test!(|qs: &QueryServer,
+    fn test_idm_password_max_age() {
+        run_idm_test!(|_qs: &QueryServer,
                        idms: &IdmServer,
-                       _idms_delayed: &IdmServerDelayed,
+                       idms_delayed: &mut IdmServerDelayed,
                        au: &mut AuditScope| {
-            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
-            let sid = init_admin_authsession_sid(
-                idms,
-                au,
-                Duration::from_secs(TEST_CURRENT_TIME),
-                "admin",
+            // The set time of the password comes from the transaction, so this must be
+            // after the server setup.
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            // Passwords of members of this group must be changed after a minute.
+            let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "group"],
+                    "name": ["testgroup"],
+                    "uuid": ["c57d0e4d-3c4b-4d4e-8d8e-b0f2f8d0e8a1"],
+                    "description": ["testgroup"],
+                    "password_max_age": ["60"],
+                    "member": ["00000000-0000-0000-0000-000000000000"]
+                }
+            }"#,
             );
+            let ce = CreateEvent::new_internal(vec![e]);
+            assert!(idms_prox_write.qs_write.create(au, &ce).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // Within the max age, the expiry is reported in the token.
+            let sid = init_admin_authsession_sid(idms, au, ct + Duration::from_secs(30), "admin");
             let mut idms_write = idms.write();
-            let anon_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD_INC);
+            let step = AuthEvent::cred_step_password(sid, TEST_PASSWORD);
+            match task::block_on(idms_write.auth(au, &step, ct + Duration::from_secs(30))) {
+                Ok(AuthResult {
+                    state: AuthState::Success(uat),
+                    ..
+                }) => assert!(uat.password_expiry == Some((ct.as_secs() + 60) as i64)),
+                _ => panic!(),
+            };
+            idms_write.commit(au).expect("Must not fail");
 
-            // Expect success
-            let r2 = task::block_on(idms_write.auth(
-                au,
-                &anon_step,
-                Duration::from_secs(TEST_CURRENT_TIME),
-            ));
-            debug!("r2 ==> {:?}", r2);
+            // Past it, the only step allowed is to change the password.
+            let ct = ct + Duration::from_secs(61);
+            let sid = init_admin_authsession_sid(idms, au, ct, "admin");
+            let mut idms_write = idms.write();
+            let step = AuthEvent::cred_step_password(sid, TEST_PASSWORD);
+            match task::block_on(idms_write.auth(au, &step, ct)) {
+                Ok(AuthResult {
+                    state: AuthState::PasswordExpired,
+                    ..
+                }) => {}
+                _ => panic!(),
+            };
+            assert!(task::block_on(idms_write.auth(au, &step, ct)).is_err());
+            idms_write.commit(au).expect("Must not fail");
 
-            match r2 {
-                Ok(ar) => {
-                    let AuthResult {
-                        sessionid: _,
-                        state,
-                        delay,
-                    } = ar;
-                    debug_assert!(delay.is_none());
-                    match state {
-                        AuthState::Denied(_reason) => {
-                            // Check the uat.
-                        }
-                        _ => {
-                            error!("A critical error has occured! We have a non-denied result!");
-                            panic!();
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("A critical error has occured! {:?}", e);
-                    // Should not occur!
-                    panic!();
-                }
+            // The new password must still meet the quality requirements.
+            let step = AuthEvent::cred_step_password_change(sid, "password");
+            assert!(task::block_on(idms.change_expired_password(au, &step, ct)).is_err());
+            // The session can't complete until the new password is written.
+            let step = AuthEvent::cred_step_password_change(sid, TEST_PASSWORD_INC);
+            let mut idms_write = idms.write();
+            assert!(task::block_on(idms_write.auth(au, &step, ct)).is_err());
+            idms_write.commit(au).expect("Must not fail");
+
+            assert!(task::block_on(idms.change_expired_password(au, &step, ct)).is_ok());
+            let mut idms_write = idms.write();
+            match task::block_on(idms_write.auth(au, &step, ct)) {
+                Ok(AuthResult {
+                    state: AuthState::Success(uat),
+                    ..
+                }) => assert!(uat.password_expiry == Some((ct.as_secs() + 60) as i64)),
+                _ => panic!(),
             };
+            idms_write.commit(au).expect("Must not fail");
 
+            // The change was written before the session completed, so the new password works.
+            let sid = init_admin_authsession_sid(idms, au, ct, "admin");
+            let mut idms_write = idms.write();
+            let step = AuthEvent::cred_step_password(sid, TEST_PASSWORD_INC);
+            match task::block_on(idms_write.auth(au, &step, ct)) {
+                Ok(AuthResult {
+                    state: AuthState::Success(_),
+                    ..
+                }) => {}
+                _ => panic!(),
+            };
             idms_write.commit(au).expect("Must not fail");
+            idms_delayed.skip_last_login();
         })
     }
 
     #[test]
-    fn test_idm_simple_password_reset() {
+    fn test_idm_credential_policy() {
         run_idm_test!(|_qs: &QueryServer,
                        idms: &IdmServer,
                        _idms_delayed: &IdmServerDelayed,
                        au: &mut AuditScope| {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = idms.proxy_write(ct);
             let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
-
-            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
             assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            // Members of this group must use at least a password and totp.
+            let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "group"],
+                    "name": ["testgroup"],
+                    "uuid": ["9d2b6e43-7d1f-4c53-a1b0-5f3e2a8c4d17"],
+                    "description": ["testgroup"],
+                    "credential_type_minimum": ["password_totp"],
+                    "member": ["00000000-0000-0000-0000-000000000000"]
+                }
+            }"#,
+            );
+            let ce = CreateEvent::new_internal(vec![e]);
+            assert!(idms_prox_write.qs_write.create(au, &ce).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // A password alone is too weak, so the session is denied at the start.
+            let mut idms_write = idms.write();
+            let admin_init = AuthEvent::named_init("admin");
+            match task::block_on(idms_write.auth(au, &admin_init, ct)) {
+                Ok(AuthResult {
+                    state: AuthState::Denied(reason),
+                    ..
+                }) => {
+                    assert!(reason == "credential policy requires a password with totp or stronger")
+                }
+                _ => panic!(),
+            };
+            idms_write.commit(au).expect("Must not fail");
+
+            // Strengthening the credential is allowed.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let gte = GenerateTOTPEvent::new_internal(*UUID_ADMIN);
+            let (sesid, tok) = match idms_prox_write.generate_account_totp(au, &gte, ct) {
+                Ok(SetCredentialResponse::TOTPCheck(id, tok)) => (id, tok),
+                _ => panic!("invalid state!"),
+            };
+            let r_tok: TOTP = tok.into();
+            let chal = r_tok
+                .do_totp_duration_from_epoch(&ct)
+                .expect("Failed to do totp?");
+            let vte = VerifyTOTPEvent::new_internal(*UUID_ADMIN, sesid, chal);
+            match idms_prox_write.verify_account_totp(au, &vte, ct) {
+                Ok(SetCredentialResponse::Success) => {}
+                _ => panic!(),
+            };
+
+            // But it can't be weakened again, and changing the password keeps the totp.
+            let rte = RemoveTOTPEvent::new_internal(*UUID_ADMIN, "internal_token".to_string());
+            match idms_prox_write.remove_account_totp(au, &rte) {
+                Err(OperationError::CredentialTooWeak(min)) => assert!(min == "password_totp"),
+                _ => panic!(),
+            };
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC, None);
             assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
             assert!(idms_prox_write.commit(au).is_ok());
+
+            // Now the session can proceed.
+            let mut idms_write = idms.write();
+            match task::block_on(idms_write.auth(au, &admin_init, ct)) {
+                Ok(AuthResult {
+                    state: AuthState::Choose(_),
+                    ..
+                }) => {}
+                _ => panic!(),
+            };
+            idms_write.commit(au).expect("Must not fail");
         })
     }
 
@@ -2084,6 +5176,91 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_idm_password_quality_policy() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       _idms_delayed: &IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            let me_policy = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("uuid", PartialValue::new_uuidr(&UUID_SYSTEM_CONFIG))),
+                    ModifyList::new_list(vec![
+                        Modify::Present("password_min_length".to_string(), Value::new_uint32(16)),
+                        Modify::Present(
+                            "password_min_char_classes".to_string(),
+                            Value::new_uint32(3),
+                        ),
+                        Modify::Present(
+                            "password_badlist_words".to_string(),
+                            Value::new_iutf8("example"),
+                        ),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_policy).is_ok());
+
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, "eeNg5ahkie6Ah", None);
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordTooShort(16))
+            );
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordTooFewCharClasses(3))
+            );
+            let pce =
+                PasswordChangeEvent::new_internal(&UUID_ADMIN, "eeNg5ahkie6AhExampleoo9", None);
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordBadListed)
+            );
+            // Weak passwords are explained.
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, "Password12345678", None);
+            match idms_prox_write.set_account_password(au, &pce) {
+                Err(OperationError::PasswordTooWeak(Some(_feedback))) => {}
+                _ => panic!(),
+            }
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, "eeNg5ahkie6Ahng8yoo9", None);
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+
+            // A group of admin can raise the minimums, but not relax them.
+            let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "group"],
+                    "name": ["testgroup"],
+                    "uuid": ["5c3e1a7b-2f64-4d0e-9b8a-7e1d6c4f2a90"],
+                    "description": ["testgroup"],
+                    "password_min_length": ["24"],
+                    "password_min_char_classes": ["1"],
+                    "member": ["00000000-0000-0000-0000-000000000000"]
+                }
+            }"#,
+            );
+            let ce = CreateEvent::new_internal(vec![e]);
+            assert!(idms_prox_write.qs_write.create(au, &ce).is_ok());
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, "eeNg5ahkie6Ahng8yoo9", None);
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordTooShort(24))
+            );
+            let pce =
+                PasswordChangeEvent::new_internal(&UUID_ADMIN, "eeneiphahkiexaiquaingoh1", None);
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordTooFewCharClasses(3))
+            );
+            let pce =
+                PasswordChangeEvent::new_internal(&UUID_ADMIN, "eeNg5ahkie6Ahng8yoo9Ohv3", None);
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+
+            assert!(idms_prox_write.commit(au).is_ok());
+        })
+    }
+
     #[test]
     fn test_idm_unixusertoken() {
         run_idm_test!(|_qs: &QueryServer,
@@ -2167,7 +5344,7 @@ mod tests {
     fn test_idm_simple_unix_password_reset() {
         run_idm_test!(|_qs: &QueryServer,
                        idms: &IdmServer,
-                       _idms_delayed: &IdmServerDelayed,
+                       idms_delayed: &mut IdmServerDelayed,
                        au: &mut AuditScope| {
             let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
             // make the admin a valid posix account
@@ -2240,6 +5417,7 @@ mod tests {
                 _ => assert!(false),
             };
             assert!(idms_write.commit(au).is_ok());
+            idms_delayed.skip_last_login();
         })
     }
 
@@ -2247,7 +5425,7 @@ mod tests {
     fn test_idm_totp_registration() {
         run_idm_test!(|_qs: &QueryServer,
                        idms: &IdmServer,
-                       _idms_delayed: &IdmServerDelayed,
+                       idms_delayed: &mut IdmServerDelayed,
                        au: &mut AuditScope| {
             let ct = duration_from_epoch_now();
             let expire = Duration::from_secs(ct.as_secs() + MFAREG_SESSION_TIMEOUT + 2);
@@ -2328,58 +5506,203 @@ mod tests {
             // attempt the verify
             let vte3 = VerifyTOTPEvent::new_internal(UUID_ANONYMOUS.clone(), sesid, chal);
 
-            match idms_prox_write.verify_account_totp(au, &vte3, ct.clone()) {
-                Err(e) => assert!(e == OperationError::InvalidRequestState),
-                _ => panic!(),
-            };
+            match idms_prox_write.verify_account_totp(au, &vte3, ct.clone()) {
+                Err(e) => assert!(e == OperationError::InvalidRequestState),
+                _ => panic!(),
+            };
+
+            // == reg, verify w_ incorrect totp (fail)
+            let res = idms_prox_write
+                .generate_account_totp(au, &gte1, ct.clone())
+                .unwrap();
+            let (_sesid, _tok) = match res {
+                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
+                _ => panic!("invalid state!"),
+            };
+
+            // We can reuse the OTP/Vte2 from before, since we want the invalid otp.
+            match idms_prox_write.verify_account_totp(au, &vte2, ct.clone()) {
+                // On failure we get back another attempt to setup the token.
+                Ok(SetCredentialResponse::TOTPCheck(_id, _tok)) => {}
+                _ => panic!(),
+            };
+            idms_prox_write.expire_mfareg_sessions(expire.clone());
+
+            // Turn the pts into an otp
+            // == reg, verify w_ correct totp (success)
+            let res = idms_prox_write
+                .generate_account_totp(au, &gte1, ct.clone())
+                .unwrap();
+            let (sesid, tok) = match res {
+                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
+                _ => panic!("invalid state!"),
+            };
+            // We can't reuse the OTP/Vte from before, since the token seed changes
+            let r_tok: TOTP = tok.into();
+            let chal = r_tok
+                .do_totp_duration_from_epoch(&ct)
+                .expect("Failed to do totp?");
+            // attempt the verify
+            let vte3 = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, chal);
+
+            match idms_prox_write.verify_account_totp(au, &vte3, ct.clone()) {
+                Ok(SetCredentialResponse::Success) => {}
+                _ => panic!(),
+            };
+            idms_prox_write.expire_mfareg_sessions(expire.clone());
+
+            // Test removing the TOTP and then authing with password only.
+            let rte =
+                RemoveTOTPEvent::new_internal(UUID_ADMIN.clone(), "internal_token".to_string());
+            idms_prox_write.remove_account_totp(au, &rte).unwrap();
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            check_admin_password(idms, au, TEST_PASSWORD);
+            // All done!
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_totp_import() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+
+            // A token moved from another system, with 8 digits and a 60 second step.
+            let secret = TOTPSecret::from_uri(
+                "otpauth://totp/Example:william?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Example&algorithm=SHA1&digits=8&period=60",
+            )
+            .unwrap();
+            let ite = ImportTOTPEvent::new_internal(
+                UUID_ADMIN.clone(),
+                "hardware".to_string(),
+                secret.clone(),
+            );
+            match idms_prox_write.import_account_totp(au, &ite) {
+                Ok(SetCredentialResponse::Success) => {}
+                _ => panic!(),
+            };
+
+            // Weak secrets are refused.
+            let mut weak = secret.clone();
+            weak.secret.truncate(4);
+            let ite = ImportTOTPEvent::new_internal(UUID_ADMIN.clone(), "weak".to_string(), weak);
+            match idms_prox_write.import_account_totp(au, &ite) {
+                Err(OperationError::InvalidAttribute(_)) => {}
+                _ => panic!(),
+            };
+
+            // A second device can be held alongside the first.
+            let ite =
+                ImportTOTPEvent::new_internal(UUID_ADMIN.clone(), "phone".to_string(), secret);
+            assert!(idms_prox_write.import_account_totp(au, &ite).is_ok());
+
+            let account = idms_prox_write
+                .target_to_account(au, &UUID_ADMIN)
+                .expect("account must exist");
+            match account.primary.map(|c| c.type_) {
+                Some(CredentialType::PasswordMFA(_, totp, _, _)) => {
+                    assert!(totp.len() == 2);
+                    assert!(totp.contains_key("hardware"));
+                    assert!(totp.contains_key("phone"));
+                }
+                _ => panic!(),
+            };
+
+            // Each is removed by its label.
+            let rte = RemoveTOTPEvent::new_internal(UUID_ADMIN.clone(), "laptop".to_string());
+            assert!(idms_prox_write.remove_account_totp(au, &rte).is_err());
+            let rte = RemoveTOTPEvent::new_internal(UUID_ADMIN.clone(), "hardware".to_string());
+            assert!(idms_prox_write.remove_account_totp(au, &rte).is_ok());
+            let rte = RemoveTOTPEvent::new_internal(UUID_ADMIN.clone(), "phone".to_string());
+            assert!(idms_prox_write.remove_account_totp(au, &rte).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            check_admin_password(idms, au, TEST_PASSWORD);
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_backup_code_lifecycle() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
 
-            // == reg, verify w_ incorrect totp (fail)
-            let res = idms_prox_write
-                .generate_account_totp(au, &gte1, ct.clone())
-                .unwrap();
-            let (_sesid, _tok) = match res {
-                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
-                _ => panic!("invalid state!"),
-            };
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
 
-            // We can reuse the OTP/Vte2 from before, since we want the invalid otp.
-            match idms_prox_write.verify_account_totp(au, &vte2, ct.clone()) {
-                // On failure we get back another attempt to setup the token.
-                Ok(SetCredentialResponse::TOTPCheck(_id, _tok)) => {}
+            // Backup codes can't be generated without totp or webauthn.
+            let gbe = GenerateBackupCodeEvent::new_internal(UUID_ADMIN.clone());
+            match idms_prox_write.generate_backup_codes(au, &gbe) {
+                Err(OperationError::InvalidAccountState(_)) => {}
                 _ => panic!(),
             };
-            idms_prox_write.expire_mfareg_sessions(expire.clone());
 
-            // Turn the pts into an otp
-            // == reg, verify w_ correct totp (success)
-            let res = idms_prox_write
-                .generate_account_totp(au, &gte1, ct.clone())
-                .unwrap();
-            let (sesid, tok) = match res {
-                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
+            // Register a totp.
+            let gte = GenerateTOTPEvent::new_internal(UUID_ADMIN.clone());
+            let (sesid, tok) = match idms_prox_write.generate_account_totp(au, &gte, ct.clone()) {
+                Ok(SetCredentialResponse::TOTPCheck(id, tok)) => (id, tok),
                 _ => panic!("invalid state!"),
             };
-            // We can't reuse the OTP/Vte from before, since the token seed changes
             let r_tok: TOTP = tok.into();
             let chal = r_tok
                 .do_totp_duration_from_epoch(&ct)
                 .expect("Failed to do totp?");
-            // attempt the verify
-            let vte3 = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, chal);
-
-            match idms_prox_write.verify_account_totp(au, &vte3, ct.clone()) {
+            let vte = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, chal);
+            match idms_prox_write.verify_account_totp(au, &vte, ct.clone()) {
                 Ok(SetCredentialResponse::Success) => {}
                 _ => panic!(),
             };
-            idms_prox_write.expire_mfareg_sessions(expire.clone());
 
-            // Test removing the TOTP and then authing with password only.
-            let rte = RemoveTOTPEvent::new_internal(UUID_ADMIN.clone());
-            idms_prox_write.remove_account_totp(au, &rte).unwrap();
+            let codes = match idms_prox_write.generate_backup_codes(au, &gbe) {
+                Ok(SetCredentialResponse::BackupCodes(codes)) => codes,
+                _ => panic!(),
+            };
+            assert!(codes.len() == 8);
             assert!(idms_prox_write.commit(au).is_ok());
 
-            check_admin_password(idms, au, TEST_PASSWORD);
-            // All done!
+            // Using a code consumes it.
+            idms_delayed.is_empty_or_panic();
+            let da = DelayedAction::BackupCodeRemoval(BackupCodeRemoval {
+                target_uuid: UUID_ADMIN.clone(),
+                code_to_remove: codes[0].clone(),
+            });
+            let r = task::block_on(idms.delayed_action(au, duration_from_epoch_now(), da));
+            assert!(Ok(true) == r);
+
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let cred = idms_prox_write
+                .target_to_account(au, &UUID_ADMIN)
+                .expect("account must exist")
+                .primary
+                .expect("Must exist.");
+            assert!(cred.remove_backup_code(codes[0].as_str()).is_none());
+            assert!(cred.remove_backup_code(codes[1].as_str()).is_some());
+
+            // Regenerating replaces all remaining codes.
+            let ncodes = match idms_prox_write.generate_backup_codes(au, &gbe) {
+                Ok(SetCredentialResponse::BackupCodes(codes)) => codes,
+                _ => panic!(),
+            };
+            let cred = idms_prox_write
+                .target_to_account(au, &UUID_ADMIN)
+                .expect("account must exist")
+                .primary
+                .expect("Must exist.");
+            assert!(cred.remove_backup_code(codes[1].as_str()).is_none());
+            assert!(cred.remove_backup_code(ncodes[0].as_str()).is_some());
+            assert!(idms_prox_write.commit(au).is_ok());
         })
     }
 
@@ -2418,11 +5741,41 @@ mod tests {
             assert!(Ok(true) == r);
             // Check the admin pw still matches
             check_admin_password(idms, au, "password");
-            // No delayed action was queued.
+            // No upgrade was queued, only the last logins.
+            idms_delayed.skip_last_login();
             idms_delayed.is_empty_or_panic();
         })
     }
 
+    #[test]
+    fn test_idm_password_upgrade_crypto_policy() {
+        run_idm_test!(|qs: &QueryServer,
+                       _idms: &IdmServer,
+                       _idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            // The password is hashed with the minimum policy of the tests.
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            // A server with the stronger default policy upgrades it on login.
+            let (idms_strong, mut idms_strong_delayed) = IdmServer::new(
+                au,
+                qs.clone(),
+                "https://idm.example.com".to_string(),
+                None,
+                CryptoPolicy::default(),
+            )
+            .expect("Failed to setup idms");
+            check_admin_password(&idms_strong, au, TEST_PASSWORD);
+            let da = idms_strong_delayed.try_recv().expect("invalid");
+            assert!(matches!(da, DelayedAction::PwUpgrade(_)));
+            let r = task::block_on(idms_strong.delayed_action(au, duration_from_epoch_now(), da));
+            assert!(Ok(true) == r);
+            // The upgraded hash meets the policy, so it isn't upgraded again.
+            check_admin_password(&idms_strong, au, TEST_PASSWORD);
+            idms_strong_delayed.skip_last_login();
+            idms_strong_delayed.is_empty_or_panic();
+        })
+    }
+
     #[test]
     fn test_idm_unix_password_upgrade() {
         run_idm_test!(|_qs: &QueryServer,
@@ -2484,7 +5837,8 @@ mod tests {
                 _ => assert!(false),
             };
             idms_write.commit(au).expect("Must not fail");
-            // No delayed action was queued.
+            // No upgrade was queued, only the last logins.
+            idms_delayed.skip_last_login();
             idms_delayed.is_empty_or_panic();
         })
     }
@@ -2698,7 +6052,7 @@ mod tests {
     fn test_idm_account_softlocking() {
         run_idm_test!(|qs: &QueryServer,
                        idms: &IdmServer,
-                       _idms_delayed: &mut IdmServerDelayed,
+                       idms_delayed: &mut IdmServerDelayed,
                        au: &mut AuditScope| {
             init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
 
@@ -2829,6 +6183,224 @@ mod tests {
             // Auth invalid, softlock present, count == 1
             // Auth invalid after reset at, count == 0 and then to count == 1
             // Tested in the softlock state machine.
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_auth_source_ratelimit() {
+        run_idm_test!(|qs: &QueryServer,
+                       idms: &IdmServer,
+                       _idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+
+            // Block an address after two failures, and don't limit subnets.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let me_rl = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("uuid", PartialValue::new_uuidr(&UUID_SYSTEM_CONFIG))),
+                    ModifyList::new_list(vec![
+                        Modify::Purged(AttrString::from("auth_ratelimit_address_failures")),
+                        Modify::Present(
+                            AttrString::from("auth_ratelimit_address_failures"),
+                            Value::new_uint32(2),
+                        ),
+                        Modify::Purged(AttrString::from("auth_ratelimit_subnet_failures")),
+                        Modify::Present(
+                            AttrString::from("auth_ratelimit_subnet_failures"),
+                            Value::new_uint32(0),
+                        ),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_rl).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let attacker: IpAddr = "192.0.2.17".parse().unwrap();
+            let neighbour: IpAddr = "192.0.2.18".parse().unwrap();
+
+            let mut idms_write = idms.write();
+            // An unknown account is a failure too, so accounts can't be enumerated.
+            let unknown_init = AuthEvent::named_init("nosuchaccount").with_source(attacker);
+            assert!(task::block_on(idms_write.auth(au, &unknown_init, ct)).is_err());
+
+            // Past half the threshold, attempts are delayed.
+            let admin_init = AuthEvent::named_init("admin").with_source(attacker);
+            match task::block_on(idms_write.auth(au, &admin_init, ct + Duration::from_secs(1))) {
+                Ok(AuthResult {
+                    state: AuthState::Choose(_),
+                    delay,
+                    ..
+                }) => assert!(delay == Some(Duration::from_millis(250))),
+                _ => panic!(),
+            };
+
+            // Then blocked, even with the correct account.
+            assert!(task::block_on(idms_write.auth(
+                au,
+                &unknown_init,
+                ct + Duration::from_secs(2)
+            ))
+            .is_err());
+            match task::block_on(idms_write.auth(au, &admin_init, ct + Duration::from_secs(3))) {
+                Ok(AuthResult {
+                    state: AuthState::Denied(reason),
+                    ..
+                }) => assert!(reason == RATE_LIMITED_MSG),
+                _ => panic!(),
+            };
+
+            // Other addresses in the subnet are not affected, as the subnet limit is disabled,
+            // and neither are internal authentications with no source.
+            let neighbour_init = AuthEvent::named_init("admin").with_source(neighbour);
+            let internal_init = AuthEvent::named_init("admin");
+            for (i, ae) in [neighbour_init, internal_init].iter().enumerate() {
+                match task::block_on(idms_write.auth(
+                    au,
+                    ae,
+                    ct + Duration::from_secs(4 + i as u64),
+                )) {
+                    Ok(AuthResult {
+                        state: AuthState::Choose(_),
+                        delay: None,
+                        ..
+                    }) => {}
+                    _ => panic!(),
+                };
+            }
+
+            // The block ends with the window, and the source is purged.
+            let ct = ct + Duration::from_secs(RateLimitConfig::default().window);
+            assert!(task::block_on(idms_write.purge_auth_sources(au, ct)).is_ok());
+            assert!(idms
+                .auth_sources
+                .read()
+                .get(&RateLimitKey::Address(attacker))
+                .is_none());
+            match task::block_on(idms_write.auth(au, &admin_init, ct)) {
+                Ok(AuthResult {
+                    state: AuthState::Choose(_),
+                    delay: None,
+                    ..
+                }) => {}
+                _ => panic!(),
+            };
+            idms_write.commit(au).expect("Must not fail");
+        })
+    }
+
+    #[test]
+    fn test_idm_last_login_and_inactivity() {
+        run_idm_test!(|qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+
+            // A login is recorded by a delayed action.
+            check_admin_password(idms, au, TEST_PASSWORD);
+            let da = idms_delayed.try_recv().expect("No last login");
+            assert!(Ok(true) == task::block_on(idms.delayed_action(au, ct, da)));
+            let admin = qs
+                .read()
+                .internal_search_uuid(au, &UUID_ADMIN)
+                .expect("Failed to read admin");
+            assert!(
+                admin.get_ava_single_datetime("last_login")
+                    == Some(time::OffsetDateTime::unix_epoch() + ct)
+            );
+            assert!(admin.get_ava_single_str("last_login_mechanism") == Some("password"));
+
+            // A second login soon after is not written.
+            check_admin_password(idms, au, TEST_PASSWORD);
+            let da = idms_delayed.try_recv().expect("No last login");
+            let mut idms_prox_write = idms.proxy_write(ct);
+            assert!(idms_prox_write.process_delayedaction(au, da).is_ok());
+            assert!(idms_prox_write
+                .target_to_account(au, &UUID_ADMIN)
+                .expect("account must exist")
+                .last_login_mod("password", ct + Duration::from_secs(1))
+                .is_none());
+
+            // Two accounts last used long ago, one of which is excluded.
+            let long_ago = Value::new_datetime_epoch(ct - Duration::from_secs(40 * 86400));
+            let inactive_uuid = Uuid::new_v4();
+            let excluded_uuid = Uuid::new_v4();
+            let group_uuid = Uuid::new_v4();
+            let e_inactive: Entry<EntryInit, EntryNew> = entry_init!(
+                ("class", Value::new_class("object")),
+                ("class", Value::new_class("account")),
+                ("name", Value::new_iname("inactive")),
+                ("uuid", Value::new_uuid(inactive_uuid)),
+                ("displayname", Value::new_utf8s("inactive")),
+                ("last_login", long_ago.clone())
+            );
+            let e_excluded: Entry<EntryInit, EntryNew> = entry_init!(
+                ("class", Value::new_class("object")),
+                ("class", Value::new_class("account")),
+                ("name", Value::new_iname("excluded")),
+                ("uuid", Value::new_uuid(excluded_uuid)),
+                ("displayname", Value::new_utf8s("excluded")),
+                ("last_login", long_ago)
+            );
+            let e_group: Entry<EntryInit, EntryNew> = entry_init!(
+                ("class", Value::new_class("object")),
+                ("class", Value::new_class("group")),
+                ("name", Value::new_iname("inactivity_exempt")),
+                ("uuid", Value::new_uuid(group_uuid)),
+                ("member", Value::new_refer(excluded_uuid))
+            );
+            let ce = CreateEvent::new_internal(vec![e_inactive, e_excluded, e_group]);
+            assert!(idms_prox_write.qs_write.create(au, &ce).is_ok());
+
+            // Nothing is expired until the policy is enabled.
+            assert!(idms_prox_write.expire_inactive_accounts(au, ct) == Ok(0));
+
+            let me_policy = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("uuid", PartialValue::new_uuidr(&UUID_SYSTEM_CONFIG))),
+                    ModifyList::new_list(vec![
+                        Modify::Present(
+                            AttrString::from("account_inactivity_days"),
+                            Value::new_uint32(30),
+                        ),
+                        Modify::Present(
+                            AttrString::from("account_inactivity_exclude_group"),
+                            Value::new_refer(group_uuid),
+                        ),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_policy).is_ok());
+
+            // The admin, though also inactive by then, is never expired.
+            let later = ct + Duration::from_secs(31 * 86400);
+            assert!(idms_prox_write.expire_inactive_accounts(au, later) == Ok(1));
+            let expire = |idms_prox_write: &IdmServerProxyWriteTransaction,
+                          au: &mut AuditScope,
+                          uuid: &Uuid| {
+                idms_prox_write
+                    .qs_write
+                    .internal_search_uuid(au, uuid)
+                    .expect("Failed to read account")
+                    .get_ava_single_datetime("account_expire")
+            };
+            assert!(
+                expire(&idms_prox_write, au, &inactive_uuid)
+                    == Some(time::OffsetDateTime::unix_epoch() + later)
+            );
+            assert!(expire(&idms_prox_write, au, &excluded_uuid).is_none());
+            assert!(expire(&idms_prox_write, au, &UUID_ADMIN).is_none());
+
+            // An account that is already expired is left as it is.
+            assert!(
+                idms_prox_write.expire_inactive_accounts(au, later + Duration::from_secs(1))
+                    == Ok(0)
+            );
+            assert!(idms_prox_write.commit(au).is_ok());
         })
     }
 
@@ -2930,69 +6502,237 @@ mod tests {
     }
 
     #[test]
-    fn test_idm_account_unix_softlocking() {
-        run_idm_test!(|qs: &QueryServer,
+    fn test_idm_account_unix_softlocking() {
+        run_idm_test!(|qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            // make the admin a valid posix account
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            let me_posix = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iname("admin"))),
+                    ModifyList::new_list(vec![
+                        Modify::Present(
+                            AttrString::from("class"),
+                            Value::new_class("posixaccount"),
+                        ),
+                        Modify::Present(AttrString::from("gidnumber"), Value::new_uint32(2001)),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_posix).is_ok());
+
+            let pce = UnixPasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+            assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let mut idms_write = idms.write();
+            let uuae_good = UnixUserAuthEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+            let uuae_bad = UnixUserAuthEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC);
+
+            let a2 = task::block_on(idms_write.auth_unix(
+                au,
+                &uuae_bad,
+                Duration::from_secs(TEST_CURRENT_TIME),
+            ));
+            match a2 {
+                Ok(None) => {}
+                _ => assert!(false),
+            };
+
+            // Now if we immediately auth again, should fail at same time due to SL
+            let a1 = task::block_on(idms_write.auth_unix(
+                au,
+                &uuae_good,
+                Duration::from_secs(TEST_CURRENT_TIME),
+            ));
+            match a1 {
+                Ok(None) => {}
+                _ => assert!(false),
+            };
+
+            // And then later, works because of SL lifting.
+            let a1 = task::block_on(idms_write.auth_unix(
+                au,
+                &uuae_good,
+                Duration::from_secs(TEST_CURRENT_TIME + 2),
+            ));
+            match a1 {
+                Ok(Some(_tok)) => {}
+                _ => assert!(false),
+            };
+
+            assert!(idms_write.commit(au).is_ok());
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_account_softlock_persist_and_clear() {
+        run_idm_test!(|qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = duration_from_epoch_now();
+
+            // Lock until the window resets after a single failure.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let me_sl = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("uuid", PartialValue::new_uuidr(&UUID_SYSTEM_CONFIG))),
+                    ModifyList::new_purge_and_set("softlock_max_failures", Value::new_uint32(1)),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_sl).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let sid = init_admin_authsession_sid(idms, au, ct, "admin");
+            let mut idms_write = idms.write();
+            let anon_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD_INC);
+            match task::block_on(idms_write.auth(au, &anon_step, ct)) {
+                Ok(AuthResult {
+                    state: AuthState::Denied(_),
+                    ..
+                }) => {}
+                _ => panic!(),
+            };
+            idms_write.commit(au).expect("Must not fail");
+
+            let status = |idms: &IdmServer, au: &mut AuditScope| {
+                let mut idms_prox_read = idms.proxy_read();
+                idms_prox_read
+                    .account_softlock_status(au, &ReadSoftLockEvent::new_internal(*UUID_ADMIN), ct)
+                    .expect("Failed to read softlocks")
+            };
+
+            let sl = status(idms, au);
+            assert!(sl.len() == 1);
+            assert!(sl[0].credential == "primary");
+            assert!(sl[0].failures == 1);
+            assert!(sl[0].unlock_at.as_ref() == Some(&sl[0].reset_at));
+
+            // The lock is written to the database, and restored by a new server.
+            let slu = idms_delayed
+                .try_recv_softlock()
+                .expect("No softlock update");
+            assert!(task::block_on(idms.softlock_update(au, ct, slu)).is_ok());
+            let (idms_restart, _idms_restart_delayed) = IdmServer::new(
+                au,
+                qs.clone(),
+                "https://idm.example.com".to_string(),
+                None,
+                CryptoPolicy::minimum(),
+            )
+            .expect("Failed to restart idms");
+            let sl = status(&idms_restart, au);
+            assert!(sl.len() == 1);
+            assert!(sl[0].failures == 1);
+
+            // Clearing the lock allows authentication again.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            assert!(idms_prox_write
+                .clear_account_softlocks(au, &ClearSoftLockEvent::new_internal(*UUID_ADMIN))
+                .is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+            assert!(status(idms, au).is_empty());
+
+            let sid = init_admin_authsession_sid(idms, au, ct, "admin");
+            let mut idms_write = idms.write();
+            let anon_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD);
+            match task::block_on(idms_write.auth(au, &anon_step, ct)) {
+                Ok(AuthResult {
+                    state: AuthState::Success(_),
+                    ..
+                }) => {}
+                _ => panic!(),
+            };
+            idms_write.commit(au).expect("Must not fail");
+
+            // And the cleared lock is removed from the database.
+            let slu = idms_delayed
+                .try_recv_softlock()
+                .expect("No softlock update");
+            assert!(slu.state.is_none());
+            assert!(task::block_on(idms.softlock_update(au, ct, slu)).is_ok());
+            let (idms_restart, _idms_restart_delayed) = IdmServer::new(
+                au,
+                qs.clone(),
+                "https://idm.example.com".to_string(),
+                None,
+                CryptoPolicy::minimum(),
+            )
+            .expect("Failed to restart idms");
+            assert!(status(&idms_restart, au).is_empty());
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_webauthn_registration_attestation_policy() {
+        run_idm_test!(|_qs: &QueryServer,
                        idms: &IdmServer,
                        _idms_delayed: &mut IdmServerDelayed,
                        au: &mut AuditScope| {
-            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
-            // make the admin a valid posix account
-            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
-            let me_posix = unsafe {
+            let ct = duration_from_epoch_now();
+            let aaguid = Uuid::parse_str("cb69481e-8ff7-4039-93ec-0a2729a154a8").unwrap();
+
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let me_wp = unsafe {
                 ModifyEvent::new_internal_invalid(
-                    filter!(f_eq("name", PartialValue::new_iname("admin"))),
-                    ModifyList::new_list(vec![
-                        Modify::Present(
-                            AttrString::from("class"),
-                            Value::new_class("posixaccount"),
-                        ),
-                        Modify::Present(AttrString::from("gidnumber"), Value::new_uint32(2001)),
-                    ]),
+                    filter!(f_eq("uuid", PartialValue::new_uuidr(&UUID_SYSTEM_CONFIG))),
+                    ModifyList::new_list(vec![Modify::Present(
+                        AttrString::from("webauthn_attestation_aaguid"),
+                        Value::new_uuid(aaguid),
+                    )]),
                 )
             };
-            assert!(idms_prox_write.qs_write.modify(au, &me_posix).is_ok());
-
-            let pce = UnixPasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
-            assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
-            assert!(idms_prox_write.commit(au).is_ok());
+            assert!(idms_prox_write.qs_write.modify(au, &me_wp).is_ok());
 
-            let mut idms_write = idms.write();
-            let uuae_good = UnixUserAuthEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
-            let uuae_bad = UnixUserAuthEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC);
+            let policy = super::webauthn_policy(au, &idms_prox_write.qs_write)
+                .expect("Failed to read webauthn policy");
+            assert!(!policy.require_verification);
+            assert!(policy.attestation_ca.is_empty());
+            assert!(policy.attestation_aaguid.contains(&aaguid));
 
-            let a2 = task::block_on(idms_write.auth_unix(
-                au,
-                &uuae_bad,
-                Duration::from_secs(TEST_CURRENT_TIME),
-            ));
-            match a2 {
-                Ok(None) => {}
-                _ => assert!(false),
+            // The soft token is a u2f device, so has no model to allow.
+            let mut wa_softtok = WebauthnAuthenticator::new(U2FSoft::new());
+            let wrei = WebauthnInitRegisterEvent::new_internal(
+                UUID_ADMIN.clone(),
+                "softtoken".to_string(),
+            );
+            let (sessionid, ccr) = match idms_prox_write.reg_account_webauthn_init(au, &wrei, ct) {
+                Ok(SetCredentialResponse::WebauthnCreateChallenge(sessionid, ccr)) => {
+                    (sessionid, ccr)
+                }
+                _ => {
+                    panic!();
+                }
             };
 
-            // Now if we immediately auth again, should fail at same time due to SL
-            let a1 = task::block_on(idms_write.auth_unix(
-                au,
-                &uuae_good,
-                Duration::from_secs(TEST_CURRENT_TIME),
-            ));
-            match a1 {
-                Ok(None) => {}
-                _ => assert!(false),
-            };
+            let rego = wa_softtok
+                .do_registration("https://idm.example.com", ccr)
+                .expect("Failed to register to softtoken");
+            let wdre = WebauthnDoRegisterEvent::new_internal(UUID_ADMIN.clone(), sessionid, rego);
 
-            // And then later, works because of SL lifting.
-            let a1 = task::block_on(idms_write.auth_unix(
-                au,
-                &uuae_good,
-                Duration::from_secs(TEST_CURRENT_TIME + 2),
-            ));
-            match a1 {
-                Ok(Some(_tok)) => {}
-                _ => assert!(false),
+            match idms_prox_write.reg_account_webauthn_complete(au, &wdre) {
+                Err(OperationError::InvalidAttribute(_)) => {}
+                _ => {
+                    panic!();
+                }
             };
 
-            assert!(idms_write.commit(au).is_ok());
+            let account = idms_prox_write
+                .target_to_account(au, &UUID_ADMIN)
+                .expect("account must exist");
+            assert!(account
+                .primary
+                .as_ref()
+                .and_then(|cred| cred.webauthn_ref().ok())
+                .is_none());
+            assert!(idms_prox_write.commit(au).is_ok());
         })
     }
 
@@ -3089,6 +6829,573 @@ mod tests {
 
             check_admin_password(idms, au, TEST_PASSWORD);
             // All done!
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_webauthn_discoverable_auth() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let mut wa_softtok = WebauthnAuthenticator::new(U2FSoft::new());
+
+            let wrei = WebauthnInitRegisterEvent::new_internal(
+                UUID_ADMIN.clone(),
+                "softtoken".to_string(),
+            );
+            let (sessionid, ccr) = match idms_prox_write.reg_account_webauthn_init(au, &wrei, ct) {
+                Ok(SetCredentialResponse::WebauthnCreateChallenge(sessionid, ccr)) => {
+                    (sessionid, ccr)
+                }
+                _ => {
+                    panic!();
+                }
+            };
+            let rego = wa_softtok
+                .do_registration("https://idm.example.com", ccr)
+                .expect("Failed to register to softtoken");
+            let wdre = WebauthnDoRegisterEvent::new_internal(UUID_ADMIN.clone(), sessionid, rego);
+            match idms_prox_write.reg_account_webauthn_complete(au, &wdre) {
+                Ok(SetCredentialResponse::Success) => {}
+                _ => {
+                    panic!();
+                }
+            };
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // The token id is indexed on the account.
+            let mut idms_prox_read = idms.proxy_read();
+            let account = idms_prox_read
+                .qs_read
+                .internal_search_uuid(au, &UUID_ADMIN)
+                .expect("account must exist");
+            let cred = account
+                .get_ava_single_credential("primary_credential")
+                .expect("Must exist.");
+            let cid = cred
+                .webauthn_ref()
+                .expect("must have webauthn")
+                .values()
+                .next()
+                .map(|c| base64::encode_config(&c.cred_id, base64::URL_SAFE_NO_PAD))
+                .expect("must have a webauthn credential");
+            assert!(account.attribute_equality(
+                "webauthn_credential_id",
+                &PartialValue::new_utf8s(cid.as_str())
+            ));
+            drop(idms_prox_read);
+
+            // Get a response from the token to a challenge for the admin account.
+            let mut idms_write = idms.write();
+            let r = task::block_on(idms_write.auth(au, &AuthEvent::named_init("admin"), ct))
+                .expect("Failed to init");
+            let r = task::block_on(idms_write.auth(
+                au,
+                &AuthEvent::begin_mech(r.sessionid, AuthMech::Webauthn),
+                ct,
+            ))
+            .expect("Failed to begin");
+            let resp = match r.state {
+                AuthState::Continue(mut allowed) => match allowed.pop() {
+                    Some(AuthAllowed::Webauthn(chal)) => wa_softtok
+                        .do_authentication("https://idm.example.com", chal)
+                        .expect("failed to use softtoken to authenticate"),
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            };
+
+            // Start a discoverable session.
+            let r = task::block_on(idms_write.auth(au, &AuthEvent::discoverable_init(), ct))
+                .expect("Failed to init discoverable");
+            let sid = r.sessionid;
+            match r.state {
+                AuthState::Choose(mechs) => {
+                    assert!(mechs == vec![AuthMech::WebauthnDiscoverable]);
+                }
+                _ => panic!(),
+            };
+            match task::block_on(idms_write.auth(
+                au,
+                &AuthEvent::begin_mech(sid, AuthMech::WebauthnDiscoverable),
+                ct,
+            )) {
+                Ok(AuthResult {
+                    state: AuthState::Continue(allowed),
+                    ..
+                }) => assert!(allowed.len() == 1),
+                _ => panic!(),
+            };
+
+            // The u2f soft token holds no user handle, so it does not name the account.
+            match task::block_on(idms_write.auth(
+                au,
+                &AuthEvent::cred_step_webauthn(sid, resp.clone()),
+                ct,
+            )) {
+                Ok(AuthResult {
+                    state: AuthState::Denied(reason),
+                    ..
+                }) => assert!(reason == UNKNOWN_DISCOVERABLE_MSG),
+                _ => panic!(),
+            };
+            // And the session is gone.
+            assert!(task::block_on(idms_write.auth(
+                au,
+                &AuthEvent::cred_step_webauthn(sid, resp),
+                ct
+            ))
+            .is_err());
+
+            // Only the discoverable mech can begin a discoverable session.
+            let r = task::block_on(idms_write.auth(au, &AuthEvent::discoverable_init(), ct))
+                .expect("Failed to init discoverable");
+            match task::block_on(idms_write.auth(
+                au,
+                &AuthEvent::begin_mech(r.sessionid, AuthMech::Password),
+                ct,
+            )) {
+                Ok(AuthResult {
+                    state: AuthState::Denied(_),
+                    ..
+                }) => {}
+                _ => panic!(),
+            };
+            idms_write.commit(au).expect("Must not fail");
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_service_account_api_token_lifecycle() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       _idms_delayed: &IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let sa_uuid = Uuid::new_v4();
+            let e: Entry<EntryInit, EntryNew> = entry_init!(
+                ("class", Value::new_class("object")),
+                ("class", Value::new_class("account")),
+                ("class", Value::new_class("service_account")),
+                ("name", Value::new_iname("test_service")),
+                ("uuid", Value::new_uuid(sa_uuid)),
+                ("displayname", Value::new_utf8s("test_service"))
+            );
+
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let ce = CreateEvent::new_internal(vec![e]);
+            assert!(idms_prox_write.qs_write.create(au, &ce).is_ok());
+
+            // An expiry in the past is rejected.
+            let gte = GenerateApiTokenEvent::new_internal(
+                sa_uuid,
+                "expired",
+                Some(time::OffsetDateTime::unix_epoch() + ct - Duration::from_secs(1)),
+                false,
+            );
+            assert!(idms_prox_write
+                .service_account_generate_api_token(au, &gte, ct)
+                .is_err());
+
+            let gte = GenerateApiTokenEvent::new_internal(sa_uuid, "test", None, true);
+            let token = idms_prox_write
+                .service_account_generate_api_token(au, &gte, ct)
+                .expect("Failed to generate api token");
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // The token is a signed uat bound to the token id.
+            let mut idms_prox_read = idms.proxy_read();
+            let validator = idms_prox_read
+                .qs_read
+                .get_domain_es256_private_key(au)
+                .and_then(|der| JwsSigner::from_es256_der(&der))
+                .and_then(|signer| signer.public_key_as_jwk())
+                .map(|jwk| JwsValidator::from_jwk(&jwk).expect("Invalid jwk"))
+                .expect("Failed to get validator");
+            let uat = validator
+                .validate_uat(token.as_str(), ct)
+                .expect("Failed to validate api token");
+            assert!(uat.read_only);
+            assert!(uat.exp == i64::MAX);
+
+            let lte = ListApiTokenEvent::new_internal(sa_uuid);
+            let tokens = idms_prox_read
+                .service_account_list_api_token(au, &lte)
+                .expect("Failed to list api tokens");
+            assert!(tokens.len() == 1);
+            assert!(Some(tokens[0].token_id.clone()) == uat.api_token_id);
+            drop(idms_prox_read);
+
+            // Once destroyed, it is no longer listed.
+            let token_id = tokens[0]
+                .token_id
+                .parse()
+                .expect("Failed to parse token id");
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let dte = DestroyApiTokenEvent::new_internal(sa_uuid, token_id);
+            assert!(idms_prox_write
+                .service_account_destroy_api_token(au, &dte)
+                .is_ok());
+            // Destroying it again fails.
+            assert!(idms_prox_write
+                .service_account_destroy_api_token(au, &dte)
+                .is_err());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let mut idms_prox_read = idms.proxy_read();
+            let tokens = idms_prox_read
+                .service_account_list_api_token(au, &lte)
+                .expect("Failed to list api tokens");
+            assert!(tokens.is_empty());
+        })
+    }
+
+    #[test]
+    fn test_idm_app_password_scope() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let mut idms_prox_write = idms.proxy_write(ct);
+            // make the admin a valid posix account
+            let me_posix = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iname("admin"))),
+                    ModifyList::new_list(vec![
+                        Modify::Present(
+                            AttrString::from("class"),
+                            Value::new_class("posixaccount"),
+                        ),
+                        Modify::Present(AttrString::from("gidnumber"), Value::new_uint32(2001)),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_posix).is_ok());
+
+            let gape =
+                GenerateAppPasswordEvent::new_internal(*UUID_ADMIN, "mail", AppPasswordScope::Ldap);
+            let ldap_pw = idms_prox_write
+                .generate_account_app_password(au, &gape)
+                .expect("Failed to generate app password");
+            // Labels are unique regardless of case.
+            let gape =
+                GenerateAppPasswordEvent::new_internal(*UUID_ADMIN, "MAIL", AppPasswordScope::Unix);
+            assert!(idms_prox_write
+                .generate_account_app_password(au, &gape)
+                .is_err());
+            let gape = GenerateAppPasswordEvent::new_internal(
+                *UUID_ADMIN,
+                "laptop",
+                AppPasswordScope::Unix,
+            );
+            let unix_pw = idms_prox_write
+                .generate_account_app_password(au, &gape)
+                .expect("Failed to generate app password");
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let mut idms_prox_read = idms.proxy_read();
+            let lape = ListAppPasswordEvent::new_internal(*UUID_ADMIN);
+            let app_pws = idms_prox_read
+                .account_list_app_password(au, &lape)
+                .expect("Failed to list app passwords");
+            assert!(app_pws.len() == 2);
+            assert!(app_pws
+                .iter()
+                .any(|a| a.label == "laptop" && a.scope == AppPasswordScope::Unix));
+            drop(idms_prox_read);
+
+            // Each password is only accepted within its scope.
+            let mut idms_write = idms.write();
+            let uuae = UnixUserAuthEvent::new_internal(&UUID_ADMIN, unix_pw.as_str());
+            let a1 = task::block_on(idms_write.auth_unix(au, &uuae, ct));
+            assert!(matches!(a1, Ok(Some(_))));
+            let uuae = UnixUserAuthEvent::new_internal(&UUID_ADMIN, ldap_pw.as_str());
+            let a2 = task::block_on(idms_write.auth_unix(au, &uuae, ct));
+            assert!(matches!(a2, Ok(None)));
+
+            let lae = LdapAuthEvent {
+                target: *UUID_ADMIN,
+                cleartext: ldap_pw.clone(),
+                source: None,
+            };
+            // Step past the softlock from the failure above.
+            let ct = ct + Duration::from_secs(2);
+            let a3 = task::block_on(idms_write.auth_ldap(au, &lae, ct));
+            assert!(matches!(a3, Ok(Some(_))));
+            let lae = LdapAuthEvent {
+                target: *UUID_ADMIN,
+                cleartext: unix_pw.clone(),
+                source: None,
+            };
+            let a4 = task::block_on(idms_write.auth_ldap(au, &lae, ct));
+            assert!(matches!(a4, Ok(None)));
+            assert!(idms_write.commit(au).is_ok());
+
+            // Once removed, the password no longer works.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let rape = RemoveAppPasswordEvent::new_internal(*UUID_ADMIN, "Mail");
+            assert!(idms_prox_write
+                .remove_account_app_password(au, &rape)
+                .is_ok());
+            assert!(idms_prox_write
+                .remove_account_app_password(au, &rape)
+                .is_err());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let ct = ct + Duration::from_secs(2);
+            let mut idms_write = idms.write();
+            let lae = LdapAuthEvent {
+                target: *UUID_ADMIN,
+                cleartext: ldap_pw,
+                source: None,
+            };
+            let a5 = task::block_on(idms_write.auth_ldap(au, &lae, ct));
+            assert!(matches!(a5, Ok(None)));
+            assert!(idms_write.commit(au).is_ok());
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_app_password_radius() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let rrse = RegenerateRadiusSecretEvent::new_internal(*UUID_ADMIN);
+            let radius_secret = idms_prox_write
+                .regenerate_radius_secret(au, &rrse)
+                .expect("Failed to reset radius credential");
+            let gape = GenerateAppPasswordEvent::new_internal(
+                *UUID_ADMIN,
+                "phone",
+                AppPasswordScope::Radius,
+            );
+            let radius_pw = idms_prox_write
+                .generate_account_app_password(au, &gape)
+                .expect("Failed to generate app password");
+            let gape =
+                GenerateAppPasswordEvent::new_internal(*UUID_ADMIN, "mail", AppPasswordScope::Ldap);
+            let ldap_pw = idms_prox_write
+                .generate_account_app_password(au, &gape)
+                .expect("Failed to generate app password");
+            // There is a limit to how many app passwords an account can have.
+            for i in 2..APP_PASSWORD_MAX {
+                let gape = GenerateAppPasswordEvent::new_internal(
+                    *UUID_ADMIN,
+                    format!("device_{}", i).as_str(),
+                    AppPasswordScope::Radius,
+                );
+                assert!(idms_prox_write
+                    .generate_account_app_password(au, &gape)
+                    .is_ok());
+            }
+            let gape = GenerateAppPasswordEvent::new_internal(
+                *UUID_ADMIN,
+                "one_too_many",
+                AppPasswordScope::Radius,
+            );
+            assert!(idms_prox_write
+                .generate_account_app_password(au, &gape)
+                .is_err());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // Only a radius app password is accepted, not the radius secret itself
+            // or an app password of another scope.
+            let mut idms_write = idms.write();
+            let rae = RadiusAuthEvent::new_internal(&UUID_ADMIN, radius_pw.as_str());
+            let a1 = task::block_on(idms_write.auth_radius(au, &rae, ct));
+            match a1 {
+                Ok(Some(tok)) => assert!(tok.secret == radius_secret),
+                _ => panic!("Failed to authenticate with radius app password"),
+            }
+            let rae = RadiusAuthEvent::new_internal(&UUID_ADMIN, ldap_pw.as_str());
+            let a2 = task::block_on(idms_write.auth_radius(au, &rae, ct));
+            assert!(matches!(a2, Ok(None)));
+            let ct = ct + Duration::from_secs(2);
+            let rae = RadiusAuthEvent::new_internal(&UUID_ADMIN, radius_secret.as_str());
+            let a3 = task::block_on(idms_write.auth_radius(au, &rae, ct));
+            assert!(matches!(a3, Ok(None)));
+            assert!(idms_write.commit(au).is_ok());
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_credential_reset_intent() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let mut idms_prox_write = idms.proxy_write(ct);
+
+            // The ttl must be within bounds.
+            let crie = CredentialResetIntentEvent::new_internal(*UUID_ADMIN, Some(0));
+            assert!(idms_prox_write
+                .credential_reset_intent(au, &crie, ct)
+                .is_err());
+
+            // Issuing a second intent replaces the first.
+            let crie = CredentialResetIntentEvent::new_internal(*UUID_ADMIN, None);
+            let replaced = idms_prox_write
+                .credential_reset_intent(au, &crie, ct)
+                .expect("Failed to issue reset intent");
+            let intent = idms_prox_write
+                .credential_reset_intent(au, &crie, ct)
+                .expect("Failed to issue reset intent");
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let crbe = CredentialResetBeginEvent::new(replaced.token);
+            assert!(idms_prox_write
+                .credential_reset_begin(au, &crbe, ct)
+                .is_err());
+            let crbe = CredentialResetBeginEvent::new("not a token".to_string());
+            assert!(idms_prox_write
+                .credential_reset_begin(au, &crbe, ct)
+                .is_err());
+
+            let crbe = CredentialResetBeginEvent::new(intent.token.clone());
+            let session = idms_prox_write
+                .credential_reset_begin(au, &crbe, ct)
+                .expect("Failed to begin reset");
+            assert!(session.spn == "admin@example.com");
+            // The token is single use.
+            assert!(idms_prox_write
+                .credential_reset_begin(au, &crbe, ct)
+                .is_err());
+
+            // A session that was not started by this token is rejected.
+            let crse = CredentialResetStepEvent::new(
+                Uuid::new_v4(),
+                intent.token.clone(),
+                SetCredentialRequest::Password(TEST_PASSWORD.to_string()),
+            );
+            assert!(idms_prox_write
+                .credential_reset_step(au, &crse, ct)
+                .is_err());
+
+            let crse = CredentialResetStepEvent::new(
+                session.sessionid,
+                intent.token.clone(),
+                SetCredentialRequest::Password(TEST_PASSWORD.to_string()),
+            );
+            let r = idms_prox_write.credential_reset_step(au, &crse, ct);
+            assert!(matches!(r, Ok(SetCredentialResponse::Success)));
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            check_admin_password(idms, au, TEST_PASSWORD);
+
+            // Once the token has expired, the session can no longer be used.
+            let ct_exp = ct + Duration::from_secs(CREDRESET_INTENT_DEFAULT_TTL + 1);
+            let mut idms_prox_write = idms.proxy_write(ct_exp);
+            let crse = CredentialResetStepEvent::new(
+                session.sessionid,
+                intent.token,
+                SetCredentialRequest::Password(TEST_PASSWORD_INC.to_string()),
+            );
+            assert!(idms_prox_write
+                .credential_reset_step(au, &crse, ct_exp)
+                .is_err());
+
+            // Only tokens issued for a credential reset can be redeemed, even when they are
+            // signed by the domain key.
+            let crie = CredentialResetIntentEvent::new_internal(*UUID_ADMIN, None);
+            let intent = idms_prox_write
+                .credential_reset_intent(au, &crie, ct_exp)
+                .expect("Failed to issue reset intent");
+            let signer = idms_prox_write
+                .qs_write
+                .get_domain_es256_private_key(au)
+                .and_then(|der| JwsSigner::from_es256_der(&der))
+                .expect("Failed to get signer");
+            let mut claims: CredResetIntentClaims = signer
+                .verify(intent.token.as_str())
+                .expect("Failed to verify reset token");
+            claims.purpose = TokenPurpose::UserAuth;
+            let crbe = CredentialResetBeginEvent::new(
+                signer.sign(&claims).expect("Failed to sign claims"),
+            );
+            assert!(idms_prox_write
+                .credential_reset_begin(au, &crbe, ct_exp)
+                .is_err());
+            let crbe = CredentialResetBeginEvent::new(intent.token);
+            assert!(idms_prox_write
+                .credential_reset_begin(au, &crbe, ct_exp)
+                .is_ok());
+
+            // And an expired token can not be redeemed.
+            let crie = CredentialResetIntentEvent::new_internal(*UUID_ADMIN, Some(60));
+            let intent = idms_prox_write
+                .credential_reset_intent(au, &crie, ct_exp)
+                .expect("Failed to issue reset intent");
+            let crbe = CredentialResetBeginEvent::new(intent.token);
+            let ct_exp = ct_exp + Duration::from_secs(61);
+            assert!(idms_prox_write
+                .credential_reset_begin(au, &crbe, ct_exp)
+                .is_err());
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_idm_account_sign_ssh_key() {
+        run_idm_test!(|qs: &QueryServer,
+                       idms: &IdmServer,
+                       _idms_delayed: &IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let pubkey = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAeGW1P6Pc2rPq0XqbRaDKBcXZUPRklo0L1EyR30CwoP william@amethyst";
+            let other = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHEp5UX3RSGvaVB0IvSm0EHYJB2hi1Tw0LfpW/8fFrgb user@host";
+            let valid_before = ct + Duration::from_secs(3600);
+
+            let qs_write = qs.write(ct);
+            let me_inv_m = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iname("admin"))),
+                    ModifyList::new_list(vec![Modify::Present(
+                        AttrString::from("ssh_publickey"),
+                        Value::new_sshkey_str("laptop", pubkey),
+                    )]),
+                )
+            };
+            assert!(qs_write.modify(au, &me_inv_m).is_ok());
+            assert!(qs_write.commit(au).is_ok());
+
+            let mut idms_prox_read = idms.proxy_read();
+            // The certificate is valid for the names of the account, and ends with the session.
+            let ssce = SshCertSignEvent::new_internal(UUID_ADMIN.clone(), pubkey, valid_before);
+            let cert = idms_prox_read
+                .account_sign_ssh_key(au, &ssce, ct)
+                .expect("Failed to sign ssh key");
+            let parsed = sshkeys::Certificate::from_string(&cert).expect("invalid certificate");
+            assert!(parsed.key_id == "admin@example.com");
+            assert!(
+                parsed.valid_principals
+                    == vec!["admin".to_string(), "admin@example.com".to_string()]
+            );
+            assert!(parsed.valid_before == valid_before.as_secs());
+            assert!(parsed.valid_after < ct.as_secs());
+
+            // It's signed by the published ca key.
+            let ca = idms_prox_read
+                .ssh_ca_public_key(au)
+                .expect("Failed to get ssh ca key");
+            let ca = sshkeys::PublicKey::from_string(&ca).expect("invalid ca key");
+            assert!(parsed.signature_key.fingerprint().hash == ca.fingerprint().hash);
+
+            // Keys that aren't registered to the account are refused.
+            let ssce = SshCertSignEvent::new_internal(UUID_ADMIN.clone(), other, valid_before);
+            assert!(idms_prox_read.account_sign_ssh_key(au, &ssce, ct).is_err());
         })
     }
 }
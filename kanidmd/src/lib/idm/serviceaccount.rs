@@ -0,0 +1,209 @@
+use crate::audit::AuditScope;
+use crate::entry::{Entry, EntryCommitted, EntryReduced, EntrySealed};
+use crate::modify::{Modify, ModifyInvalid, ModifyList};
+use crate::value::{ApiTokenMeta, PartialValue, Value};
+use kanidm_proto::v1::OperationError;
+use kanidm_proto::v1::{ApiToken, ApiTokenPurpose};
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+// Recording every use of a token would make every request a write, so the last
+// used time is only updated at this granularity.
+const API_TOKEN_LAST_USED_GRANULARITY: i64 = 300;
+
+lazy_static! {
+    static ref PVCLASS_ACCOUNT: PartialValue = PartialValue::new_class("account");
+    static ref PVCLASS_SERVICE_ACCOUNT: PartialValue = PartialValue::new_class("service_account");
+}
+
+macro_rules! try_from_entry {
+    ($value:expr) => {{
+        if !$value.attribute_value_pres("class", &PVCLASS_ACCOUNT) {
+            return Err(OperationError::InvalidAccountState(
+                "Missing class: account".to_string(),
+            ));
+        }
+
+        if !$value.attribute_value_pres("class", &PVCLASS_SERVICE_ACCOUNT) {
+            return Err(OperationError::InvalidAccountState(
+                "Missing class: service_account".to_string(),
+            ));
+        }
+
+        let name = $value
+            .get_ava_single_str("name")
+            .map(|s| s.to_string())
+            .ok_or_else(|| {
+                OperationError::InvalidAccountState("Missing attribute: name".to_string())
+            })?;
+
+        let uuid = *$value.get_uuid();
+
+        let api_tokens = $value
+            .get_ava_as_apitokens("api_token_session")
+            .map(|i| i.map(|(u, m)| (*u, m.clone())).collect())
+            .unwrap_or_else(BTreeMap::new);
+
+        Ok(ServiceAccount {
+            name,
+            uuid,
+            api_tokens,
+        })
+    }};
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ServiceAccount {
+    pub name: String,
+    pub uuid: Uuid,
+    pub api_tokens: BTreeMap<Uuid, ApiTokenMeta>,
+}
+
+impl ServiceAccount {
+    pub(crate) fn try_from_entry_ro(
+        value: &Entry<EntrySealed, EntryCommitted>,
+    ) -> Result<Self, OperationError> {
+        try_from_entry!(value)
+    }
+
+    pub(crate) fn try_from_entry_reduced(
+        value: &Entry<EntryReduced, EntryCommitted>,
+    ) -> Result<Self, OperationError> {
+        try_from_entry!(value)
+    }
+
+    pub(crate) fn to_api_tokens(&self) -> Vec<ApiToken> {
+        self.api_tokens
+            .iter()
+            .map(|(u, m)| ApiToken {
+                token_id: u.to_hyphenated_ref().to_string(),
+                label: m.label.clone(),
+                expiry: m.expiry.map(|odt| odt.format(time::Format::Rfc3339)),
+                purpose: if m.read_only {
+                    ApiTokenPurpose::ReadOnly
+                } else {
+                    ApiTokenPurpose::ReadWrite
+                },
+                last_used: m.last_used.map(|odt| odt.format(time::Format::Rfc3339)),
+            })
+            .collect()
+    }
+
+    pub(crate) fn generate_api_token_mod(
+        &self,
+        token_id: Uuid,
+        meta: ApiTokenMeta,
+    ) -> ModifyList<ModifyInvalid> {
+        ModifyList::new_append("api_token_session", Value::new_apitoken(token_id, meta))
+    }
+
+    pub(crate) fn destroy_api_token_mod(
+        &self,
+        au: &mut AuditScope,
+        token_id: &Uuid,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        if self.api_tokens.contains_key(token_id) {
+            Ok(ModifyList::new_remove(
+                "api_token_session",
+                PartialValue::new_apitoken(*token_id),
+            ))
+        } else {
+            lrequest_error!(au, "api token {} does not exist", token_id);
+            Err(OperationError::NoMatchingEntries)
+        }
+    }
+
+    /// Update the last used time of a token. Returns None if the token no longer
+    /// exists, or was used too recently to need an update.
+    pub(crate) fn api_token_used_mod(
+        &self,
+        token_id: &Uuid,
+        ct: Duration,
+    ) -> Option<ModifyList<ModifyInvalid>> {
+        let meta = self.api_tokens.get(token_id)?;
+        let cot = OffsetDateTime::unix_epoch() + ct;
+
+        let stale = meta
+            .last_used
+            .map(|lu| (cot - lu).whole_seconds() >= API_TOKEN_LAST_USED_GRANULARITY)
+            .unwrap_or(true);
+
+        if stale {
+            let mut meta = meta.clone();
+            meta.last_used = Some(cot);
+            Some(ModifyList::new_list(vec![
+                Modify::Removed(
+                    "api_token_session".into(),
+                    PartialValue::new_apitoken(*token_id),
+                ),
+                Modify::Present(
+                    "api_token_session".into(),
+                    Value::new_apitoken(*token_id, meta),
+                ),
+            ]))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::idm::serviceaccount::ServiceAccount;
+    use crate::value::{ApiTokenMeta, Value};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_idm_serviceaccount_last_used_granularity() {
+        let token_id = Uuid::new_v4();
+        let meta = ApiTokenMeta {
+            label: "test".to_string(),
+            expiry: None,
+            read_only: false,
+            last_used: None,
+        };
+
+        let mut e = unsafe {
+            entry_init!(
+                ("class", Value::new_class("object")),
+                ("class", Value::new_class("account")),
+                ("class", Value::new_class("service_account")),
+                ("name", Value::new_iname("test_service")),
+                ("uuid", Value::new_uuid(Uuid::new_v4())),
+                (
+                    "api_token_session",
+                    Value::new_apitoken(token_id, meta.clone())
+                )
+            )
+            .into_invalid_new()
+        };
+        let sa = ServiceAccount::try_from_entry_ro(unsafe { &e.clone().into_sealed_committed() })
+            .expect("Failed to build service account");
+
+        // Never used, so it must be updated.
+        let ct = Duration::from_secs(1000);
+        assert!(sa.api_token_used_mod(&token_id, ct).is_some());
+        // An unknown token is ignored.
+        assert!(sa.api_token_used_mod(&Uuid::new_v4(), ct).is_none());
+
+        // Recently used, so no update is needed.
+        let mut used = meta;
+        used.last_used = Some(time::OffsetDateTime::unix_epoch() + ct);
+        e.set_ava(
+            "api_token_session",
+            btreeset![Value::new_apitoken(token_id, used)],
+        );
+        let sa = ServiceAccount::try_from_entry_ro(unsafe { &e.into_sealed_committed() })
+            .expect("Failed to build service account");
+        assert!(sa
+            .api_token_used_mod(&token_id, ct + Duration::from_secs(10))
+            .is_none());
+        assert!(sa
+            .api_token_used_mod(&token_id, ct + Duration::from_secs(600))
+            .is_some());
+    }
+}
@@ -11,9 +11,10 @@ use crate::server::{
 };
 use crate::value::{PartialValue, Value};
 use kanidm_proto::v1::OperationError;
-use kanidm_proto::v1::{UnixGroupToken, UnixUserToken};
+use kanidm_proto::v1::{AppPasswordScope, UnixGroupToken, UnixUserToken};
 
 use crate::idm::delayed::{DelayedAction, UnixPasswordUpgrade};
+use crate::idm::group::Group;
 
 // use crossbeam::channel::Sender;
 use std::time::Duration;
@@ -33,8 +34,11 @@ pub(crate) struct UnixUserAccount {
     pub sshkeys: Vec<String>,
     pub groups: Vec<UnixGroup>,
     cred: Option<Credential>,
+    app_passwords: Vec<Credential>,
     pub valid_from: Option<OffsetDateTime>,
     pub expire: Option<OffsetDateTime>,
+    // The maximum password age from all groups, not only the posix ones.
+    password_max_age: Option<Duration>,
 }
 
 lazy_static! {
@@ -45,7 +49,7 @@ lazy_static! {
 }
 
 macro_rules! try_from_entry {
-    ($value:expr, $groups:expr) => {{
+    ($value:expr, $groups:expr, $password_max_age:expr) => {{
         if !$value.attribute_value_pres("class", &PVCLASS_ACCOUNT) {
             return Err(OperationError::InvalidAccountState(
                 "Missing class: account".to_string(),
@@ -98,6 +102,11 @@ macro_rules! try_from_entry {
             .get_ava_single_credential("unix_password")
             .map(|v| v.clone());
 
+        let app_passwords = $value
+            .get_ava_as_credentials("app_password")
+            .map(|i| i.map(|(_, c)| c.clone()).collect())
+            .unwrap_or_else(Vec::new);
+
         let valid_from = $value.get_ava_single_datetime("account_valid_from");
 
         let expire = $value.get_ava_single_datetime("account_expire");
@@ -112,8 +121,10 @@ macro_rules! try_from_entry {
             sshkeys,
             groups: $groups,
             cred,
+            app_passwords,
             valid_from,
             expire,
+            password_max_age: $password_max_age,
         })
     }};
 }
@@ -125,7 +136,9 @@ impl UnixUserAccount {
         qs: &mut QueryServerWriteTransaction,
     ) -> Result<Self, OperationError> {
         let groups = UnixGroup::try_from_account_entry_rw(au, value, qs)?;
-        try_from_entry!(value, groups)
+        let password_max_age =
+            Group::password_max_age(&Group::try_from_account_entry_rw(au, value, qs)?);
+        try_from_entry!(value, groups, password_max_age)
     }
 
     pub(crate) fn try_from_entry_ro(
@@ -134,7 +147,9 @@ impl UnixUserAccount {
         qs: &mut QueryServerReadTransaction,
     ) -> Result<Self, OperationError> {
         let groups = UnixGroup::try_from_account_entry_ro(au, value, qs)?;
-        try_from_entry!(value, groups)
+        let password_max_age =
+            Group::password_max_age(&Group::try_from_account_entry_ro(au, value, qs)?);
+        try_from_entry!(value, groups, password_max_age)
     }
 
     /*
@@ -162,15 +177,33 @@ impl UnixUserAccount {
             groups,
             sshkeys: self.sshkeys.clone(),
             valid: self.is_within_valid_time(ct),
+            password_expiry: self.password_expiry().map(|d| d.as_secs() as i64),
         })
     }
 
+    /// When the unix password expires, if a maximum password age applies to this account.
+    pub(crate) fn password_expiry(&self) -> Option<Duration> {
+        self.password_max_age
+            .and_then(|max_age| self.cred.as_ref()?.password_expiry(max_age))
+    }
+
+    // If the account only has app passwords, they share a softlock keyed on the account.
     pub fn unix_cred_uuid(&self) -> Option<Uuid> {
-        self.cred.as_ref().map(|c| c.uuid)
+        self.cred.as_ref().map(|c| c.uuid).or_else(|| {
+            if self.app_passwords.is_empty() {
+                None
+            } else {
+                Some(self.uuid)
+            }
+        })
     }
 
     pub fn unix_cred_softlock_policy(&self) -> Option<CredSoftLockPolicy> {
-        self.cred.as_ref().and_then(|cred| cred.softlock_policy())
+        match &self.cred {
+            Some(cred) => cred.softlock_policy(),
+            None if !self.app_passwords.is_empty() => Some(CredSoftLockPolicy::Password),
+            None => None,
+        }
     }
 
     pub fn is_anonymous(&self) -> bool {
@@ -181,8 +214,19 @@ impl UnixUserAccount {
         &self,
         cleartext: &str,
         crypto_policy: &CryptoPolicy,
+        change: Option<(Duration, usize)>,
     ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
-        let ncred = Credential::new_password_only(crypto_policy, cleartext)?;
+        // Keep the existing credential so that the password history carries over.
+        let ncred = match (&self.cred, change) {
+            (Some(cred), Some((ct, retain))) => {
+                cred.set_password_with_history(crypto_policy, cleartext, retain, ct)?
+            }
+            (Some(cred), None) => cred.set_password(crypto_policy, cleartext)?,
+            (None, Some((ct, _))) => {
+                Credential::new_password_only(crypto_policy, cleartext)?.with_password_set(ct)
+            }
+            (None, None) => Credential::new_password_only(crypto_policy, cleartext)?,
+        };
         let vcred = Value::new_credential("unix", ncred);
         Ok(ModifyList::new_purge_and_set("unix_password", vcred))
     }
@@ -208,13 +252,32 @@ impl UnixUserAccount {
         vmin && vmax
     }
 
+    /// Verify a password for the unix and ldap interfaces. The unix password is valid for
+    /// any scope, but an app password is only valid for the scope it was created for.
     pub(crate) fn verify_unix_credential(
         &self,
         au: &mut AuditScope,
         cleartext: &str,
+        scope: AppPasswordScope,
         async_tx: &Sender<DelayedAction>,
+        crypto_policy: &CryptoPolicy,
         ct: Duration,
     ) -> Result<Option<UnixUserToken>, OperationError> {
+        self.verify_unix_credential_method(au, cleartext, scope, async_tx, crypto_policy, ct)
+            .map(|r| r.map(|(tok, _)| tok))
+    }
+
+    /// As `verify_unix_credential`, but also reports if the password that was accepted
+    /// was an app password rather than the unix password.
+    pub(crate) fn verify_unix_credential_method(
+        &self,
+        au: &mut AuditScope,
+        cleartext: &str,
+        scope: AppPasswordScope,
+        async_tx: &Sender<DelayedAction>,
+        crypto_policy: &CryptoPolicy,
+        ct: Duration,
+    ) -> Result<Option<(UnixUserToken, bool)>, OperationError> {
         // Is the cred locked?
         // NOW checked by the caller!
 
@@ -225,43 +288,65 @@ impl UnixUserAccount {
         }
         */
 
-        // is the cred some or none?
-        match &self.cred {
-            Some(cred) => {
-                cred.password_ref().and_then(|pw| {
-                    if pw.verify(cleartext)? {
-                        lsecurity!(au, "Successful unix cred handling");
-                        if pw.requires_upgrade() {
-                            async_tx
-                                .send(DelayedAction::UnixPwUpgrade(UnixPasswordUpgrade {
-                                    target_uuid: self.uuid,
-                                    existing_password: cleartext.to_string(),
-                                }))
-                                .map_err(|_| {
-                                    ladmin_error!(
-                                        au,
-                                        "failed to queue delayed action - unix password upgrade"
-                                    );
-                                    OperationError::InvalidState
-                                })?;
-                        }
-
-                        // Technically this means we check the times twice, but that doesn't
-                        // seem like a big deal when we want to short cut return on invalid.
-                        Some(self.to_unixusertoken(ct)).transpose()
-                    } else {
-                        // Failed to auth
-                        lsecurity!(au, "Failed unix cred handling (denied)");
-                        Ok(None)
-                    }
-                })
-            }
+        if self.cred.is_none() && self.app_passwords.is_empty() {
             // They don't have a unix cred, fail the auth.
-            None => {
-                lsecurity!(au, "Failed unix cred handling (no cred present)");
-                Ok(None)
+            lsecurity!(au, "Failed unix cred handling (no cred present)");
+            return Ok(None);
+        }
+
+        if let Some(cred) = &self.cred {
+            let pw = cred.password_ref()?;
+            if pw.verify(cleartext)? {
+                // There is no way to change the password through pam, so the user
+                // must change it with the cli before they can log in again.
+                if self.password_expiry().map(|exp| exp <= ct).unwrap_or(false) {
+                    lsecurity!(au, "Failed unix cred handling (password expired)");
+                    return Ok(None);
+                }
+                lsecurity!(au, "Successful unix cred handling");
+                if pw.requires_upgrade(crypto_policy) {
+                    async_tx
+                        .send(DelayedAction::UnixPwUpgrade(UnixPasswordUpgrade {
+                            target_uuid: self.uuid,
+                            existing_password: cleartext.to_string(),
+                        }))
+                        .map_err(|_| {
+                            ladmin_error!(
+                                au,
+                                "failed to queue delayed action - unix password upgrade"
+                            );
+                            OperationError::InvalidState
+                        })?;
+                }
+
+                // Technically this means we check the times twice, but that doesn't
+                // seem like a big deal when we want to short cut return on invalid.
+                return self.to_unixusertoken(ct).map(|tok| Some((tok, false)));
+            }
+        }
+
+        for cred in self
+            .app_passwords
+            .iter()
+            .filter(|cred| cred.has_claim(scope.as_str()))
+        {
+            if cred.password_ref().and_then(|pw| pw.verify(cleartext))? {
+                lsecurity!(
+                    au,
+                    "Successful unix cred handling (app password, scope {})",
+                    scope.as_str()
+                );
+                return self.to_unixusertoken(ct).map(|tok| Some((tok, true)));
             }
         }
+
+        // Failed to auth
+        lsecurity!(au, "Failed unix cred handling (denied)");
+        Ok(None)
+    }
+
+    pub(crate) fn cred_ref(&self) -> Option<&Credential> {
+        self.cred.as_ref()
     }
 
     pub(crate) fn check_existing_pw(&self, cleartext: &str) -> Result<bool, OperationError> {
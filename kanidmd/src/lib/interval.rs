@@ -18,6 +18,8 @@ impl IntervalActor {
                 server
                     .handle_purgerecycledevent(PurgeRecycledEvent::new())
                     .await;
+                server.handle_purgeauthsources().await;
+                server.handle_expireinactiveaccounts().await;
             }
         });
     }
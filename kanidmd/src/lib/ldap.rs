@@ -1,17 +1,30 @@
 use crate::audit::AuditScope;
 use crate::constants::{STR_UUID_DOMAIN_INFO, UUID_ANONYMOUS, UUID_DOMAIN_INFO};
-use crate::event::SearchEvent;
+use crate::crypto::ClientCertificate;
+use crate::event::{CreateEvent, DeleteEvent, ModifyEvent, SearchEvent};
 use crate::idm::event::LdapAuthEvent;
+use crate::idm::ratelimit::SourceLimit;
 use crate::idm::server::IdmServer;
-use crate::server::QueryServerTransaction;
+use crate::schema::SchemaTransaction;
+use crate::server::{QueryServerTransaction, QueryServerWriteTransaction};
+use crate::value::SyntaxType;
 use async_std::task;
-use kanidm_proto::v1::{OperationError, UserAuthToken};
+use kanidm_proto::v1::Entry as ProtoEntry;
+use kanidm_proto::v1::{
+    Modify as ProtoModify, ModifyList as ProtoModifyList, OperationError, PluginError, SchemaError,
+    UserAuthToken,
+};
+use ldap3_server::proto::{
+    LdapAddRequest, LdapModifyDNRequest, LdapModifyRequest, LdapModifyType, LdapOp, LdapResult,
+};
 use ldap3_server::simple::*;
 use regex::Regex;
 use smartstring::alias::String as AttrString;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
 use std::iter;
-use std::time::SystemTime;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
 // Clippy doesn't like Bind here. But proto needs unboxed ldapmsg,
@@ -32,6 +45,77 @@ pub struct LdapBoundToken {
     pub uuid: Uuid,
     // For now, always anonymous
     pub effective_uat: UserAuthToken,
+    // If the bind satisfied the credential policy of the account. Writes are refused when
+    // it did not, as they are made as the account rather than the effective uat.
+    pub policy_met: bool,
+    // If the bind used an app password. App passwords are scoped to a single service, so
+    // they can not be used to change the account.
+    pub via_app_password: bool,
+}
+
+/// The write operations of ldap. These aren't part of the simple server operations, so
+/// they are taken from the message before it's converted to one.
+pub enum LdapWriteOp {
+    Add(LdapAddRequest),
+    Modify(LdapModifyRequest),
+    Delete(String),
+    ModifyDn(LdapModifyDNRequest),
+}
+
+pub struct LdapWriteRequest {
+    pub msgid: i32,
+    pub op: LdapWriteOp,
+}
+
+impl LdapWriteRequest {
+    fn dn(&self) -> &str {
+        match &self.op {
+            LdapWriteOp::Add(ar) => ar.dn.as_str(),
+            LdapWriteOp::Modify(mr) => mr.dn.as_str(),
+            LdapWriteOp::Delete(dn) => dn.as_str(),
+            LdapWriteOp::ModifyDn(mdr) => mdr.dn.as_str(),
+        }
+    }
+
+    pub fn gen_error(&self, code: LdapResultCode, message: String) -> LdapMsg {
+        let res = LdapResult {
+            code,
+            matcheddn: "".to_string(),
+            message,
+            referral: Vec::new(),
+        };
+        LdapMsg {
+            msgid: self.msgid,
+            op: match self.op {
+                LdapWriteOp::Add(_) => LdapOp::AddResponse(res),
+                LdapWriteOp::Modify(_) => LdapOp::ModifyResponse(res),
+                LdapWriteOp::Delete(_) => LdapOp::DelResponse(res),
+                LdapWriteOp::ModifyDn(_) => LdapOp::ModifyDNResponse(res),
+            },
+            ctrl: Vec::new(),
+        }
+    }
+
+    pub fn gen_success(&self) -> LdapMsg {
+        self.gen_error(LdapResultCode::Success, "".to_string())
+    }
+}
+
+impl TryFrom<LdapMsg> for LdapWriteRequest {
+    // Any other message is handed back so it can be converted to a server operation.
+    type Error = LdapMsg;
+
+    fn try_from(msg: LdapMsg) -> Result<Self, Self::Error> {
+        let LdapMsg { msgid, op, ctrl } = msg;
+        let op = match op {
+            LdapOp::AddRequest(ar) => LdapWriteOp::Add(ar),
+            LdapOp::ModifyRequest(mr) => LdapWriteOp::Modify(mr),
+            LdapOp::DelRequest(dn) => LdapWriteOp::Delete(dn),
+            LdapOp::ModifyDNRequest(mdr) => LdapWriteOp::ModifyDn(mdr),
+            op => return Err(LdapMsg { msgid, op, ctrl }),
+        };
+        Ok(LdapWriteRequest { msgid, op })
+    }
 }
 
 pub struct LdapServer {
@@ -84,7 +168,10 @@ impl LdapServer {
                 },
                 LdapPartialAttribute {
                     atype: "supportedExtension".to_string(),
-                    vals: vec!["1.3.6.1.4.1.4203.1.11.3".to_string()],
+                    vals: vec![
+                        "1.3.6.1.4.1.4203.1.11.3".to_string(),
+                        "1.3.6.1.4.1.1466.20037".to_string(),
+                    ],
                 },
                 LdapPartialAttribute {
                     atype: "defaultnamingcontext".to_string(),
@@ -293,8 +380,70 @@ impl LdapServer {
         &self,
         au: &mut AuditScope,
         idms: &IdmServer,
+        source: Option<IpAddr>,
+        dn: &str,
+        pw: &str,
+    ) -> Result<Option<LdapBoundToken>, OperationError> {
+        let ct = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|e| {
+                ladmin_error!(au, "Clock Error -> {:?}", e);
+                OperationError::InvalidState
+            })?;
+
+        // Binds are rate limited by source in the same way as other authentications.
+        let limit = idms.write_async().await.auth_source_limit(au, source, ct)?;
+        match limit {
+            SourceLimit::Block => {
+                lsecurity!(au, "❌ LDAP Bind from {:?} is rate limited", source);
+                return Ok(None);
+            }
+            SourceLimit::Delay(delay) => task::sleep(delay).await,
+            SourceLimit::Allow => {}
+        }
+
+        let r = self.bind(au, idms, source, dn, pw, ct).await;
+        if !matches!(r, Ok(Some(_))) {
+            idms.write_async()
+                .await
+                .auth_source_failure(au, source, ct)
+                .await?;
+        }
+        r
+    }
+
+    /// Bind a connection that presented a verified client certificate as the account that
+    /// certificate is bound to, as a SASL EXTERNAL bind would. This is done once when the
+    /// connection is established, and the result is kept for the life of the connection.
+    pub async fn do_certificate_bind(
+        &self,
+        au: &mut AuditScope,
+        idms: &IdmServer,
+        source: Option<IpAddr>,
+        cert: &ClientCertificate,
+    ) -> Result<Option<LdapBoundToken>, OperationError> {
+        let ct = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|e| {
+                ladmin_error!(au, "Clock Error -> {:?}", e);
+                OperationError::InvalidState
+            })?;
+
+        lsecurity!(au, "Attempt LDAP certificate bind for {}", cert.fingerprint);
+        let mut idm_write = idms.write_async().await;
+        idm_write
+            .auth_ldap_certificate(au, cert, source, ct)
+            .and_then(|r| idm_write.commit(au).map(|_| r))
+    }
+
+    async fn bind(
+        &self,
+        au: &mut AuditScope,
+        idms: &IdmServer,
+        source: Option<IpAddr>,
         dn: &str,
         pw: &str,
+        ct: Duration,
     ) -> Result<Option<LdapBoundToken>, OperationError> {
         lsecurity!(
             au,
@@ -338,14 +487,7 @@ impl LdapServer {
                 })?
         };
 
-        let ct = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map_err(|e| {
-                ladmin_error!(au, "Clock Error -> {:?}", e);
-                OperationError::InvalidState
-            })?;
-
-        let lae = LdapAuthEvent::from_parts(au, target_uuid, pw.to_string())?;
+        let lae = LdapAuthEvent::from_parts(au, target_uuid, pw.to_string(), source)?;
         idm_write.auth_ldap(au, &lae, ct).await.and_then(|r| {
             idm_write.commit(au).map(|_| {
                 if r.is_some() {
@@ -358,17 +500,258 @@ impl LdapServer {
         })
     }
 
+    /// Resolve a dn in our basedn to the attribute and value of its rdn.
+    fn dn_to_rdn(
+        &self,
+        au: &mut AuditScope,
+        dn: &str,
+    ) -> Result<(AttrString, String), OperationError> {
+        self.dnre
+            .captures(dn)
+            .and_then(|caps| match (caps.name("attr"), caps.name("val")) {
+                (Some(a), Some(v)) => {
+                    Some((ldap_attr_filter_map(a.as_str()), v.as_str().to_string()))
+                }
+                _ => None,
+            })
+            .ok_or_else(|| {
+                lrequest_error!(au, "LDAP write failure - invalid dn {}", dn);
+                OperationError::InvalidRequestState
+            })
+    }
+
+    /// Resolve the entry a dn refers to. As with a bind dn, the rdn value is resolved as a
+    /// name, so `name`, `spn` and `uuid` rdns are all accepted.
+    fn dn_to_uuid(
+        &self,
+        au: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        dn: &str,
+    ) -> Result<Uuid, OperationError> {
+        let (_, val) = self.dn_to_rdn(au, dn)?;
+        qs.name_to_uuid(au, val.as_str()).map_err(|e| {
+            lrequest_error!(
+                au,
+                "LDAP write failure - unable to resolve dn {} {:?}",
+                dn,
+                e
+            );
+            e
+        })
+    }
+
+    /// The reverse of the value and attribute maps we apply to search results. References are
+    /// presented as the dn of the entry they refer to, which we reduce to its rdn value so
+    /// that it can be resolved as a name.
+    fn ldap_value_map(&self, qs: &QueryServerWriteTransaction, attr: &str, value: &str) -> String {
+        let is_ref = qs
+            .get_schema()
+            .get_attributes()
+            .get(attr)
+            .map(|a| a.syntax == SyntaxType::REFERENCE_UUID)
+            .unwrap_or(false);
+        if is_ref {
+            if let Some(v) = self.dnre.captures(value).and_then(|caps| caps.name("val")) {
+                return v.as_str().to_string();
+            }
+        }
+        value.to_string()
+    }
+
+    fn ldap_modlist(
+        &self,
+        qs: &QueryServerWriteTransaction,
+        mr: &LdapModifyRequest,
+    ) -> ProtoModifyList {
+        let mods = mr
+            .changes
+            .iter()
+            .flat_map(|c| {
+                let attr = ldap_attr_filter_map(c.modification.atype.as_str());
+                let vals: Vec<_> = c
+                    .modification
+                    .vals
+                    .iter()
+                    .map(|v| self.ldap_value_map(qs, attr.as_str(), v.as_str()))
+                    .collect();
+                let attr = attr.to_string();
+                match c.operation {
+                    LdapModifyType::Add => vals
+                        .into_iter()
+                        .map(|v| ProtoModify::Present(attr.clone(), v))
+                        .collect(),
+                    // Deleting an attribute without values removes all of them.
+                    LdapModifyType::Delete if vals.is_empty() => vec![ProtoModify::Purged(attr)],
+                    LdapModifyType::Delete => vals
+                        .into_iter()
+                        .map(|v| ProtoModify::Removed(attr.clone(), v))
+                        .collect(),
+                    LdapModifyType::Replace => iter::once(ProtoModify::Purged(attr.clone()))
+                        .chain(
+                            vals.into_iter()
+                                .map(|v| ProtoModify::Present(attr.clone(), v)),
+                        )
+                        .collect::<Vec<_>>(),
+                }
+            })
+            .collect();
+        ProtoModifyList::new_list(mods)
+    }
+
+    async fn do_write(
+        &self,
+        au: &mut AuditScope,
+        idms: &IdmServer,
+        wr: &LdapWriteRequest,
+        uat: &LdapBoundToken,
+    ) -> Result<(), OperationError> {
+        ladmin_info!(au, "Attempt LDAP write of {} for {}", wr.dn(), uat.spn);
+        let ct = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|e| {
+                ladmin_error!(au, "Clock Error -> {:?}", e);
+                OperationError::InvalidState
+            })?;
+
+        let idms_prox_write = idms.proxy_write_async(ct).await;
+        let qs_write = &idms_prox_write.qs_write;
+        lperf_segment!(au, "ldap::do_write<core>", || {
+            match &wr.op {
+                LdapWriteOp::Add(ar) => {
+                    let (rdn_attr, rdn_val) = self.dn_to_rdn(au, ar.dn.as_str())?;
+                    let mut attrs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+                    ar.attributes.iter().for_each(|a| {
+                        let attr = ldap_attr_filter_map(a.atype.as_str());
+                        let vals = a
+                            .vals
+                            .iter()
+                            .map(|v| self.ldap_value_map(qs_write, attr.as_str(), v.as_str()));
+                        attrs.entry(attr.to_string()).or_default().extend(vals);
+                    });
+                    // The rdn is part of the entry, even if it wasn't repeated in the attributes.
+                    let rdn_vals = attrs.entry(rdn_attr.to_string()).or_default();
+                    if !rdn_vals.contains(&rdn_val) {
+                        rdn_vals.push(rdn_val);
+                    }
+                    let ce =
+                        CreateEvent::from_ldap_parts(au, uat, &ProtoEntry { attrs }, qs_write)?;
+                    qs_write.create(au, &ce)
+                }
+                LdapWriteOp::Modify(mr) => {
+                    let target_uuid = self.dn_to_uuid(au, qs_write, mr.dn.as_str())?;
+                    let modlist = self.ldap_modlist(qs_write, mr);
+                    let me =
+                        ModifyEvent::from_ldap_parts(au, uat, target_uuid, &modlist, qs_write)?;
+                    qs_write.modify(au, &me)
+                }
+                LdapWriteOp::Delete(dn) => {
+                    let target_uuid = self.dn_to_uuid(au, qs_write, dn.as_str())?;
+                    let de = DeleteEvent::from_ldap_parts(au, uat, target_uuid, qs_write)?;
+                    qs_write.delete(au, &de)
+                }
+                LdapWriteOp::ModifyDn(mdr) => {
+                    // Our tree is flat, so an entry can only be renamed, never moved.
+                    if mdr
+                        .new_superior
+                        .as_ref()
+                        .map(|sup| sup != &self.basedn)
+                        .unwrap_or(false)
+                    {
+                        lrequest_error!(au, "LDAP write failure - entries can not be moved");
+                        return Err(OperationError::InvalidRequestState);
+                    }
+                    let target_uuid = self.dn_to_uuid(au, qs_write, mdr.dn.as_str())?;
+                    // The rdn of the entry is derived from its name, which is single valued,
+                    // so the old rdn is always removed.
+                    let mut rdn = mdr.newrdn.splitn(2, '=');
+                    let name = match (rdn.next().map(ldap_attr_filter_map), rdn.next()) {
+                        (Some(a), Some(v)) if a == "name" => v.to_string(),
+                        _ => {
+                            lrequest_error!(au, "LDAP write failure - invalid rdn {}", mdr.newrdn);
+                            return Err(OperationError::InvalidAttribute(
+                                "entries can only be renamed with a name rdn".to_string(),
+                            ));
+                        }
+                    };
+                    let modlist = ProtoModifyList::new_list(vec![
+                        ProtoModify::Purged("name".to_string()),
+                        ProtoModify::Present("name".to_string(), name),
+                    ]);
+                    let me =
+                        ModifyEvent::from_ldap_parts(au, uat, target_uuid, &modlist, qs_write)?;
+                    qs_write.modify(au, &me)
+                }
+            }
+        })?;
+
+        idms_prox_write.commit(au).map(|_| {
+            ladmin_info!(au, "LDAP write success {}", wr.dn());
+        })
+    }
+
+    pub async fn do_write_op(
+        &self,
+        au: &mut AuditScope,
+        idms: &IdmServer,
+        wr: LdapWriteRequest,
+        uat: Option<LdapBoundToken>,
+        source: Option<IpAddr>,
+    ) -> Result<LdapResponseState, OperationError> {
+        match uat {
+            Some(u) => Ok(match self.do_write(au, idms, &wr, &u).await {
+                Ok(()) => LdapResponseState::Respond(wr.gen_success()),
+                Err(e) => {
+                    let (rc, msg) = operationerr_to_ldapresultcode(e);
+                    LdapResponseState::Respond(wr.gen_error(rc, msg))
+                }
+            }),
+            None => {
+                // As with search, a write can occur without a bind, so bind first.
+                let lbt = match self.do_bind(au, idms, source, "", "").await {
+                    Ok(Some(lbt)) => lbt,
+                    Ok(None) => {
+                        return Ok(LdapResponseState::Respond(
+                            wr.gen_error(LdapResultCode::InvalidCredentials, "".to_string()),
+                        ))
+                    }
+                    Err(e) => {
+                        let (rc, msg) = operationerr_to_ldapresultcode(e);
+                        return Ok(LdapResponseState::Respond(wr.gen_error(rc, msg)));
+                    }
+                };
+                Ok(match self.do_write(au, idms, &wr, &lbt).await {
+                    Ok(()) => LdapResponseState::Bind(lbt, wr.gen_success()),
+                    Err(e) => {
+                        let (rc, msg) = operationerr_to_ldapresultcode(e);
+                        LdapResponseState::Bind(lbt, wr.gen_error(rc, msg))
+                    }
+                })
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn do_op(
         &self,
         au: &mut AuditScope,
         idms: &IdmServer,
         server_op: ServerOps,
         uat: Option<LdapBoundToken>,
+        source: Option<IpAddr>,
+        bind_requires_tls: bool,
         eventid: &Uuid,
     ) -> Result<LdapResponseState, OperationError> {
         match server_op {
+            // Credentials must not be sent before StartTLS, but anonymous binds are allowed.
+            ServerOps::SimpleBind(sbr) if bind_requires_tls && !sbr.pw.is_empty() => {
+                lsecurity!(au, "❌ LDAP Bind refused for {}, TLS is required", sbr.dn);
+                Ok(LdapResponseState::Respond(sbr.gen_error(
+                    LdapResultCode::ConfidentialityRequired,
+                    "StartTLS is required before binding with credentials".to_string(),
+                )))
+            }
             ServerOps::SimpleBind(sbr) => self
-                .do_bind(au, idms, sbr.dn.as_str(), sbr.pw.as_str())
+                .do_bind(au, idms, source, sbr.dn.as_str(), sbr.pw.as_str())
                 .await
                 .map(|r| match r {
                     Some(lbt) => LdapResponseState::Bind(lbt, sbr.gen_success()),
@@ -389,7 +772,7 @@ impl LdapServer {
                     }),
                 None => {
                     // Search can occur without a bind, so bind first.
-                    let lbt = match self.do_bind(au, idms, "", "").await {
+                    let lbt = match self.do_bind(au, idms, source, "", "").await {
                         Ok(Some(lbt)) => lbt,
                         Ok(None) => {
                             return Ok(LdapResponseState::Respond(
@@ -447,9 +830,29 @@ fn operationerr_to_ldapresultcode(e: OperationError) -> (LdapResultCode, String)
         OperationError::InvalidAttributeName(s) | OperationError::InvalidAttribute(s) => {
             (LdapResultCode::InvalidAttributeSyntax, s)
         }
+        OperationError::NotAuthenticated | OperationError::AccessDenied => {
+            (LdapResultCode::InsufficentAccessRights, "".to_string())
+        }
+        OperationError::NoMatchingEntries => (LdapResultCode::NoSuchObject, "".to_string()),
+        OperationError::SchemaViolation(SchemaError::InvalidAttribute(s))
+        | OperationError::SchemaViolation(SchemaError::PhantomAttribute(s)) => {
+            (LdapResultCode::UndefinedAttributeType, s)
+        }
+        OperationError::SchemaViolation(SchemaError::InvalidAttributeSyntax(s)) => {
+            (LdapResultCode::InvalidAttributeSyntax, s)
+        }
+        OperationError::SchemaViolation(se @ SchemaError::NoClassFound)
+        | OperationError::SchemaViolation(se @ SchemaError::InvalidClass(_))
+        | OperationError::SchemaViolation(se @ SchemaError::MissingMustAttribute(_)) => {
+            (LdapResultCode::ObjectClassViolation, format!("{:?}", se))
+        }
         OperationError::SchemaViolation(se) => {
             (LdapResultCode::UnwillingToPerform, format!("{:?}", se))
         }
+        OperationError::Plugin(PluginError::AttrUnique(s)) => {
+            (LdapResultCode::EntryAlreadyExists, s)
+        }
+        OperationError::Plugin(pe) => (LdapResultCode::ConstraintViolation, format!("{:?}", pe)),
         e => (LdapResultCode::Other, format!("{:?}", e)),
     }
 }
@@ -477,18 +880,25 @@ pub(crate) fn ldap_attr_entry_map(attr: &str) -> String {
 #[cfg(test)]
 mod tests {
     use crate::constants::{STR_UUID_ADMIN, UUID_ADMIN, UUID_ANONYMOUS};
-    use crate::event::ModifyEvent;
-    use crate::idm::event::UnixPasswordChangeEvent;
+    use crate::entry::{Entry, EntryInit, EntryNew};
+    use crate::event::{CreateEvent, ModifyEvent};
+    use crate::idm::event::{GenerateAppPasswordEvent, UnixPasswordChangeEvent};
     use crate::modify::{Modify, ModifyList};
+    use crate::server::QueryServerTransaction;
     use crate::value::{PartialValue, Value};
-    // use kanidm_proto::v1::OperationError;
     // use crate::audit::AuditScope;
     // use crate::idm::server::IdmServer;
     // use crate::server::QueryServer;
     // use crate::utils::duration_from_epoch_now;
     // use uuid::Uuid;
-    use crate::ldap::LdapServer;
+    use crate::ldap::{LdapResponseState, LdapServer, LdapWriteOp, LdapWriteRequest};
     use async_std::task;
+    use kanidm_proto::v1::{AppPasswordScope, OperationError};
+    use ldap3_server::proto::{
+        LdapAddRequest, LdapAttribute, LdapModify, LdapModifyDNRequest, LdapModifyRequest,
+        LdapModifyType, LdapPartialAttribute,
+    };
+    use ldap3_server::simple::{ServerOps, SimpleBindRequest};
     use smartstring::alias::String as AttrString;
 
     const TEST_PASSWORD: &'static str = "ntaoeuntnaoeuhraohuercahu😍";
@@ -497,7 +907,7 @@ mod tests {
     fn test_ldap_simple_bind() {
         run_idm_test!(|_qs: &QueryServer,
                        idms: &IdmServer,
-                       _idms_delayed: &IdmServerDelayed,
+                       idms_delayed: &mut IdmServerDelayed,
                        au: &mut AuditScope| {
             let ldaps = LdapServer::new(au, idms).expect("failed to start ldap");
 
@@ -522,31 +932,33 @@ mod tests {
             assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
             assert!(idms_prox_write.commit(au).is_ok());
 
-            let anon_t = task::block_on(ldaps.do_bind(au, idms, "", ""))
+            let anon_t = task::block_on(ldaps.do_bind(au, idms, None, "", ""))
                 .unwrap()
                 .unwrap();
             assert!(anon_t.uuid == *UUID_ANONYMOUS);
-            assert!(task::block_on(ldaps.do_bind(au, idms, "", "test"))
+            assert!(task::block_on(ldaps.do_bind(au, idms, None, "", "test"))
                 .unwrap()
                 .is_none());
 
             // Now test the admin and various DN's
-            let admin_t = task::block_on(ldaps.do_bind(au, idms, "admin", TEST_PASSWORD))
+            let admin_t = task::block_on(ldaps.do_bind(au, idms, None, "admin", TEST_PASSWORD))
                 .unwrap()
                 .unwrap();
             assert!(admin_t.uuid == *UUID_ADMIN);
             let admin_t =
-                task::block_on(ldaps.do_bind(au, idms, "admin@example.com", TEST_PASSWORD))
+                task::block_on(ldaps.do_bind(au, idms, None, "admin@example.com", TEST_PASSWORD))
                     .unwrap()
                     .unwrap();
             assert!(admin_t.uuid == *UUID_ADMIN);
-            let admin_t = task::block_on(ldaps.do_bind(au, idms, STR_UUID_ADMIN, TEST_PASSWORD))
-                .unwrap()
-                .unwrap();
+            let admin_t =
+                task::block_on(ldaps.do_bind(au, idms, None, STR_UUID_ADMIN, TEST_PASSWORD))
+                    .unwrap()
+                    .unwrap();
             assert!(admin_t.uuid == *UUID_ADMIN);
             let admin_t = task::block_on(ldaps.do_bind(
                 au,
                 idms,
+                None,
                 "name=admin,dc=example,dc=com",
                 TEST_PASSWORD,
             ))
@@ -556,6 +968,7 @@ mod tests {
             let admin_t = task::block_on(ldaps.do_bind(
                 au,
                 idms,
+                None,
                 "spn=admin@example.com,dc=example,dc=com",
                 TEST_PASSWORD,
             ))
@@ -565,6 +978,7 @@ mod tests {
             let admin_t = task::block_on(ldaps.do_bind(
                 au,
                 idms,
+                None,
                 format!("uuid={},dc=example,dc=com", STR_UUID_ADMIN).as_str(),
                 TEST_PASSWORD,
             ))
@@ -572,18 +986,25 @@ mod tests {
             .unwrap();
             assert!(admin_t.uuid == *UUID_ADMIN);
 
-            let admin_t = task::block_on(ldaps.do_bind(au, idms, "name=admin", TEST_PASSWORD))
-                .unwrap()
-                .unwrap();
-            assert!(admin_t.uuid == *UUID_ADMIN);
             let admin_t =
-                task::block_on(ldaps.do_bind(au, idms, "spn=admin@example.com", TEST_PASSWORD))
+                task::block_on(ldaps.do_bind(au, idms, None, "name=admin", TEST_PASSWORD))
                     .unwrap()
                     .unwrap();
             assert!(admin_t.uuid == *UUID_ADMIN);
             let admin_t = task::block_on(ldaps.do_bind(
                 au,
                 idms,
+                None,
+                "spn=admin@example.com",
+                TEST_PASSWORD,
+            ))
+            .unwrap()
+            .unwrap();
+            assert!(admin_t.uuid == *UUID_ADMIN);
+            let admin_t = task::block_on(ldaps.do_bind(
+                au,
+                idms,
+                None,
                 format!("uuid={}", STR_UUID_ADMIN).as_str(),
                 TEST_PASSWORD,
             ))
@@ -591,14 +1012,20 @@ mod tests {
             .unwrap();
             assert!(admin_t.uuid == *UUID_ADMIN);
 
-            let admin_t =
-                task::block_on(ldaps.do_bind(au, idms, "admin,dc=example,dc=com", TEST_PASSWORD))
-                    .unwrap()
-                    .unwrap();
+            let admin_t = task::block_on(ldaps.do_bind(
+                au,
+                idms,
+                None,
+                "admin,dc=example,dc=com",
+                TEST_PASSWORD,
+            ))
+            .unwrap()
+            .unwrap();
             assert!(admin_t.uuid == *UUID_ADMIN);
             let admin_t = task::block_on(ldaps.do_bind(
                 au,
                 idms,
+                None,
                 "admin@example.com,dc=example,dc=com",
                 TEST_PASSWORD,
             ))
@@ -608,6 +1035,7 @@ mod tests {
             let admin_t = task::block_on(ldaps.do_bind(
                 au,
                 idms,
+                None,
                 format!("{},dc=example,dc=com", STR_UUID_ADMIN).as_str(),
                 TEST_PASSWORD,
             ))
@@ -616,14 +1044,17 @@ mod tests {
             assert!(admin_t.uuid == *UUID_ADMIN);
 
             // Bad password, check last to prevent softlocking of the admin account.
-            assert!(task::block_on(ldaps.do_bind(au, idms, "admin", "test"))
-                .unwrap()
-                .is_none());
+            assert!(
+                task::block_on(ldaps.do_bind(au, idms, None, "admin", "test"))
+                    .unwrap()
+                    .is_none()
+            );
 
             // Non-existant and invalid DNs
             assert!(task::block_on(ldaps.do_bind(
                 au,
                 idms,
+                None,
                 "spn=admin@example.com,dc=clownshoes,dc=example,dc=com",
                 TEST_PASSWORD
             ))
@@ -631,20 +1062,340 @@ mod tests {
             assert!(task::block_on(ldaps.do_bind(
                 au,
                 idms,
+                None,
                 "spn=claire@example.com,dc=example,dc=com",
                 TEST_PASSWORD
             ))
             .is_err());
+            assert!(task::block_on(ldaps.do_bind(
+                au,
+                idms,
+                None,
+                ",dc=example,dc=com",
+                TEST_PASSWORD
+            ))
+            .is_err());
+            assert!(task::block_on(ldaps.do_bind(
+                au,
+                idms,
+                None,
+                "dc=example,dc=com",
+                TEST_PASSWORD
+            ))
+            .is_err());
+
+            assert!(task::block_on(ldaps.do_bind(au, idms, None, "claire", "test")).is_err());
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_ldap_bind_requires_tls() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       _idms_delayed: &IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ldaps = LdapServer::new(au, idms).expect("failed to start ldap");
+            let eventid = uuid::Uuid::new_v4();
+
+            // Binds with credentials are refused before StartTLS, without checking them.
+            let sbr = SimpleBindRequest {
+                msgid: 1,
+                dn: "admin".to_string(),
+                pw: TEST_PASSWORD.to_string(),
+            };
+            let r = task::block_on(ldaps.do_op(
+                au,
+                idms,
+                ServerOps::SimpleBind(sbr),
+                None,
+                None,
+                true,
+                &eventid,
+            ));
+            assert!(matches!(r, Ok(LdapResponseState::Respond(_))));
+
+            // Anonymous binds are still allowed.
+            let sbr = SimpleBindRequest {
+                msgid: 2,
+                dn: "".to_string(),
+                pw: "".to_string(),
+            };
+            let r = task::block_on(ldaps.do_op(
+                au,
+                idms,
+                ServerOps::SimpleBind(sbr),
+                None,
+                None,
+                true,
+                &eventid,
+            ));
+            assert!(matches!(r, Ok(LdapResponseState::Bind(_, _))));
+        })
+    }
+
+    #[test]
+    fn test_ldap_write() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ldaps = LdapServer::new(au, idms).expect("failed to start ldap");
+
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            // make the admin a valid posix account
+            let me_posix = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iname("admin"))),
+                    ModifyList::new_list(vec![
+                        Modify::Present(
+                            AttrString::from("class"),
+                            Value::new_class("posixaccount"),
+                        ),
+                        Modify::Present(AttrString::from("gidnumber"), Value::new_uint32(2001)),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_posix).is_ok());
+            let pce = UnixPasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+            assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let anon_t = task::block_on(ldaps.do_bind(au, idms, None, "", ""))
+                .unwrap()
+                .unwrap();
+            let admin_t = task::block_on(ldaps.do_bind(au, idms, None, "admin", TEST_PASSWORD))
+                .unwrap()
+                .unwrap();
+
+            let add = LdapWriteRequest {
+                msgid: 1,
+                op: LdapWriteOp::Add(LdapAddRequest {
+                    dn: "name=ldap_group,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapAttribute {
+                            atype: "objectClass".to_string(),
+                            vals: vec!["group".to_string()],
+                        },
+                        LdapAttribute {
+                            atype: "member".to_string(),
+                            vals: vec!["spn=admin@example.com,dc=example,dc=com".to_string()],
+                        },
+                    ],
+                }),
+            };
+            // Writes are made as the bound account, so anonymous can't create the group.
+            assert!(
+                task::block_on(ldaps.do_write(au, idms, &add, &anon_t))
+                    == Err(OperationError::AccessDenied)
+            );
+            assert!(task::block_on(ldaps.do_write(au, idms, &add, &admin_t)).is_ok());
+
+            let group = {
+                let idms_prox_read = idms.proxy_read();
+                let group_uuid = idms_prox_read
+                    .qs_read
+                    .name_to_uuid(au, "ldap_group")
+                    .expect("group was not created");
+                idms_prox_read
+                    .qs_read
+                    .internal_search_uuid(au, &group_uuid)
+                    .expect("group was not created")
+            };
+            // The member dn was resolved to the admin account.
+            assert!(group.attribute_value_pres("member", &PartialValue::new_refer(*UUID_ADMIN)));
+
+            let modify = LdapWriteRequest {
+                msgid: 2,
+                op: LdapWriteOp::Modify(LdapModifyRequest {
+                    dn: "name=ldap_group,dc=example,dc=com".to_string(),
+                    changes: vec![
+                        LdapModify {
+                            operation: LdapModifyType::Replace,
+                            modification: LdapPartialAttribute {
+                                atype: "description".to_string(),
+                                vals: vec!["A group made over ldap".to_string()],
+                            },
+                        },
+                        LdapModify {
+                            operation: LdapModifyType::Delete,
+                            modification: LdapPartialAttribute {
+                                atype: "member".to_string(),
+                                vals: Vec::new(),
+                            },
+                        },
+                    ],
+                }),
+            };
+            assert!(task::block_on(ldaps.do_write(au, idms, &modify, &admin_t)).is_ok());
+
+            let rename = LdapWriteRequest {
+                msgid: 3,
+                op: LdapWriteOp::ModifyDn(LdapModifyDNRequest {
+                    dn: format!("uuid={},dc=example,dc=com", group.get_uuid()),
+                    newrdn: "name=ldap_group_renamed".to_string(),
+                    deleteoldrdn: true,
+                    new_superior: None,
+                }),
+            };
+            assert!(task::block_on(ldaps.do_write(au, idms, &rename, &admin_t)).is_ok());
+
+            {
+                let idms_prox_read = idms.proxy_read();
+                let group = idms_prox_read
+                    .qs_read
+                    .internal_search_uuid(au, group.get_uuid())
+                    .expect("group was not found");
+                assert!(group.get_ava_single_str("name") == Some("ldap_group_renamed"));
+                assert!(group.get_ava_single_str("description") == Some("A group made over ldap"));
+                assert!(!group.attribute_pres("member"));
+            }
+
+            // The old name is gone.
+            let delete = LdapWriteRequest {
+                msgid: 4,
+                op: LdapWriteOp::Delete("name=ldap_group,dc=example,dc=com".to_string()),
+            };
             assert!(
-                task::block_on(ldaps.do_bind(au, idms, ",dc=example,dc=com", TEST_PASSWORD))
-                    .is_err()
+                task::block_on(ldaps.do_write(au, idms, &delete, &admin_t))
+                    == Err(OperationError::NoMatchingEntries)
+            );
+            let delete = LdapWriteRequest {
+                msgid: 5,
+                op: LdapWriteOp::Delete("name=ldap_group_renamed,dc=example,dc=com".to_string()),
+            };
+            assert!(task::block_on(ldaps.do_write(au, idms, &delete, &admin_t)).is_ok());
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_ldap_write_credential_policy() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ldaps = LdapServer::new(au, idms).expect("failed to start ldap");
+
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            let me_posix = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iname("admin"))),
+                    ModifyList::new_list(vec![
+                        Modify::Present(
+                            AttrString::from("class"),
+                            Value::new_class("posixaccount"),
+                        ),
+                        Modify::Present(AttrString::from("gidnumber"), Value::new_uint32(2001)),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_posix).is_ok());
+            let pce = UnixPasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+            assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
+            // Members of this group must use at least a password and totp.
+            let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "group"],
+                    "name": ["mfa_group"],
+                    "uuid": ["2f8a3c1e-5b7d-4e9a-8c6f-1d3b5a7e9c20"],
+                    "description": ["mfa_group"],
+                    "credential_type_minimum": ["password_totp"],
+                    "member": ["00000000-0000-0000-0000-000000000000"]
+                }
+            }"#,
             );
+            let ce = CreateEvent::new_internal(vec![e]);
+            assert!(idms_prox_write.qs_write.create(au, &ce).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // The password bind is still allowed, but it can't meet the policy.
+            let admin_t = task::block_on(ldaps.do_bind(au, idms, None, "admin", TEST_PASSWORD))
+                .unwrap()
+                .unwrap();
+            assert!(!admin_t.policy_met);
+
+            let add = LdapWriteRequest {
+                msgid: 1,
+                op: LdapWriteOp::Add(LdapAddRequest {
+                    dn: "name=ldap_group,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapAttribute {
+                        atype: "objectClass".to_string(),
+                        vals: vec!["group".to_string()],
+                    }],
+                }),
+            };
             assert!(
-                task::block_on(ldaps.do_bind(au, idms, "dc=example,dc=com", TEST_PASSWORD))
-                    .is_err()
+                task::block_on(ldaps.do_write(au, idms, &add, &admin_t))
+                    == Err(OperationError::AccessDenied)
             );
+            idms_delayed.skip_last_login();
+        })
+    }
+
+    #[test]
+    fn test_ldap_write_app_password() {
+        run_idm_test!(|_qs: &QueryServer,
+                       idms: &IdmServer,
+                       idms_delayed: &mut IdmServerDelayed,
+                       au: &mut AuditScope| {
+            let ldaps = LdapServer::new(au, idms).expect("failed to start ldap");
 
-            assert!(task::block_on(ldaps.do_bind(au, idms, "claire", "test")).is_err());
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            let me_posix = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iname("admin"))),
+                    ModifyList::new_list(vec![
+                        Modify::Present(
+                            AttrString::from("class"),
+                            Value::new_class("posixaccount"),
+                        ),
+                        Modify::Present(AttrString::from("gidnumber"), Value::new_uint32(2001)),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_posix).is_ok());
+            let pce = UnixPasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+            assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
+            let gape =
+                GenerateAppPasswordEvent::new_internal(*UUID_ADMIN, "mail", AppPasswordScope::Ldap);
+            let app_pw = idms_prox_write
+                .generate_account_app_password(au, &gape)
+                .expect("Failed to generate app password");
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let modify = LdapWriteRequest {
+                msgid: 1,
+                op: LdapWriteOp::Modify(LdapModifyRequest {
+                    dn: "name=admin,dc=example,dc=com".to_string(),
+                    changes: vec![LdapModify {
+                        operation: LdapModifyType::Add,
+                        modification: LdapPartialAttribute {
+                            atype: "ssh_publickey".to_string(),
+                            vals: vec!["ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAeGW1P6Pc2rPq0XqbRaDKBcXZUPRklo0L1EyR30CwoP william@amethyst".to_string()],
+                        },
+                    }],
+                }),
+            };
+
+            // The app password can bind, but not change the account.
+            let app_t = task::block_on(ldaps.do_bind(au, idms, None, "admin", app_pw.as_str()))
+                .unwrap()
+                .unwrap();
+            assert!(app_t.via_app_password);
+            assert!(
+                task::block_on(ldaps.do_write(au, idms, &modify, &app_t))
+                    == Err(OperationError::AccessDenied)
+            );
+
+            // The unix password is allowed to.
+            let admin_t = task::block_on(ldaps.do_bind(au, idms, None, "admin", TEST_PASSWORD))
+                .unwrap()
+                .unwrap();
+            assert!(!admin_t.via_app_password);
+            assert!(task::block_on(ldaps.do_write(au, idms, &modify, &admin_t)).is_ok());
+            idms_delayed.skip_last_login();
         })
     }
 }
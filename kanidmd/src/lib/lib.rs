@@ -48,6 +48,7 @@ mod repl;
 mod schema;
 mod server;
 mod status;
+mod trust;
 
 pub mod config;
 pub mod core;
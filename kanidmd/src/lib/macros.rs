@@ -117,6 +117,7 @@ macro_rules! run_idm_test {
     ($test_fn:expr) => {{
         use crate::audit::AuditScope;
         use crate::be::{Backend, FsType};
+        use crate::credential::policy::CryptoPolicy;
         use crate::idm::server::{IdmServer, IdmServerDelayed};
         use crate::schema::Schema;
         use crate::server::QueryServer;
@@ -149,6 +150,8 @@ macro_rules! run_idm_test {
             &mut audit,
             test_server.clone(),
             "https://idm.example.com".to_string(),
+            None,
+            CryptoPolicy::minimum(),
         )
         .expect("Failed to setup idms");
 
@@ -0,0 +1,310 @@
+// Ensure that the credential policy of a group names a credential type we know about, so
+// that a typo can't silently leave the members of a group without the intended policy.
+// Webauthn attestation CAs in the system config are likewise checked to be valid PEM, as
+// an unreadable CA would otherwise prevent all webauthn registrations. The password
+// quality minimums are bounded, as a minimum that can't be met would prevent all password
+// changes. Certificate fingerprints are normalised to the lowercase hex without separators
+// that is computed from a client certificate, as `openssl x509 -fingerprint` prints them
+// with colons and they would otherwise never match.
+
+use crate::plugins::Plugin;
+
+use crate::audit::AuditScope;
+use crate::credential::policy::CredentialStrength;
+use crate::entry::{Entry, EntryCommitted, EntryInvalid, EntryNew};
+use crate::event::{CreateEvent, ModifyEvent};
+use crate::server::QueryServerWriteTransaction;
+use crate::value::Value;
+
+use kanidm_proto::v1::OperationError;
+use openssl::x509::X509;
+use std::convert::TryFrom;
+
+pub struct CredentialPolicy {}
+
+fn check_credential_type_minimum<T: Clone>(
+    au: &mut AuditScope,
+    e: &Entry<EntryInvalid, T>,
+) -> Result<(), OperationError> {
+    match e.get_ava_single_str("credential_type_minimum") {
+        Some(v) => CredentialStrength::try_from(v).map(|_| ()).map_err(|_| {
+            ladmin_error!(au, "Invalid credential_type_minimum {}", v);
+            OperationError::InvalidAttribute(format!("credential_type_minimum {} is unknown", v))
+        }),
+        None => Ok(()),
+    }
+}
+
+fn check_webauthn_attestation_ca<T: Clone>(
+    au: &mut AuditScope,
+    e: &Entry<EntryInvalid, T>,
+) -> Result<(), OperationError> {
+    match e.get_ava_as_str("webauthn_attestation_ca") {
+        Some(mut iter) => iter.try_for_each(|pem| {
+            X509::from_pem(pem.as_bytes()).map(|_| ()).map_err(|e| {
+                ladmin_error!(au, "Invalid webauthn_attestation_ca -> {:?}", e);
+                OperationError::InvalidAttribute(
+                    "webauthn_attestation_ca is not a valid PEM certificate".to_string(),
+                )
+            })
+        }),
+        None => Ok(()),
+    }
+}
+
+fn check_password_quality_bounds<T: Clone>(
+    au: &mut AuditScope,
+    e: &Entry<EntryInvalid, T>,
+) -> Result<(), OperationError> {
+    // zxcvbn scores and the character classes both range from 0 to 4.
+    ["password_min_score", "password_min_char_classes"]
+        .iter()
+        .try_for_each(|attr| match e.get_ava_single_uint32(attr) {
+            Some(v) if v > 4 => {
+                ladmin_error!(au, "Invalid {} {}", attr, v);
+                Err(OperationError::InvalidAttribute(format!(
+                    "{} must be between 0 and 4",
+                    attr
+                )))
+            }
+            _ => Ok(()),
+        })
+}
+
+fn normalise_x509_fingerprint<T: Clone>(
+    au: &mut AuditScope,
+    e: &mut Entry<EntryInvalid, T>,
+) -> Result<(), OperationError> {
+    let fingerprints = match e.get_ava_as_str("x509_fingerprint") {
+        Some(iter) => iter
+            .map(|fp| fp.replace(':', "").to_lowercase())
+            .collect::<Vec<_>>(),
+        None => return Ok(()),
+    };
+    let valid = fingerprints
+        .iter()
+        .all(|fp| fp.len() == 64 && fp.chars().all(|c| c.is_ascii_hexdigit()));
+    if !valid {
+        ladmin_error!(au, "Invalid x509_fingerprint {:?}", fingerprints);
+        return Err(OperationError::InvalidAttribute(
+            "x509_fingerprint must be a sha256 fingerprint in hex".to_string(),
+        ));
+    }
+    e.set_ava(
+        "x509_fingerprint",
+        fingerprints.iter().map(|fp| Value::new_iutf8(fp)).collect(),
+    );
+    Ok(())
+}
+
+fn check_entry<T: Clone>(
+    au: &mut AuditScope,
+    e: &Entry<EntryInvalid, T>,
+) -> Result<(), OperationError> {
+    check_credential_type_minimum(au, e)?;
+    check_webauthn_attestation_ca(au, e)?;
+    check_password_quality_bounds(au, e)
+}
+
+impl Plugin for CredentialPolicy {
+    fn id() -> &'static str {
+        "plugin_credential_policy"
+    }
+
+    fn pre_create_transform(
+        au: &mut AuditScope,
+        _qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryNew>>,
+        _ce: &CreateEvent,
+    ) -> Result<(), OperationError> {
+        cand.iter_mut().try_for_each(|e| {
+            check_entry(au, e)?;
+            normalise_x509_fingerprint(au, e)
+        })
+    }
+
+    fn pre_modify(
+        au: &mut AuditScope,
+        _qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryCommitted>>,
+        _me: &ModifyEvent,
+    ) -> Result<(), OperationError> {
+        cand.iter_mut().try_for_each(|e| {
+            check_entry(au, e)?;
+            normalise_x509_fingerprint(au, e)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entry::{Entry, EntryInit, EntryNew};
+    use crate::server::{QueryServerTransaction, QueryServerWriteTransaction};
+    use crate::value::{PartialValue, Value};
+    use kanidm_proto::v1::OperationError;
+
+    #[test]
+    fn test_credential_policy_create_invalid() {
+        let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "attrs": {
+                "class": ["group"],
+                "name": ["testgroup"],
+                "uuid": ["b3e5a6ea-4a9c-4b4f-a1a8-3a0c5e4b2f11"],
+                "credential_type_minimum": ["passwrd_totp"]
+            }
+        }"#,
+        );
+
+        let create = vec![e];
+        let preload = Vec::new();
+
+        run_create_test!(
+            Err(OperationError::InvalidAttribute(
+                "credential_type_minimum passwrd_totp is unknown".to_string()
+            )),
+            preload,
+            create,
+            None,
+            |_, _| {}
+        );
+    }
+
+    #[test]
+    fn test_credential_policy_modify() {
+        let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "attrs": {
+                "class": ["group"],
+                "name": ["testgroup"],
+                "uuid": ["b3e5a6ea-4a9c-4b4f-a1a8-3a0c5e4b2f11"]
+            }
+        }"#,
+        );
+
+        let preload = vec![e.clone()];
+        run_modify_test!(
+            Ok(()),
+            preload,
+            filter!(f_eq("name", PartialValue::new_iname("testgroup"))),
+            modlist!([m_pres(
+                "credential_type_minimum",
+                &Value::new_iutf8("password_totp")
+            )]),
+            None,
+            |_, _: &QueryServerWriteTransaction| {}
+        );
+
+        let preload = vec![e];
+        run_modify_test!(
+            Err(OperationError::InvalidAttribute(
+                "credential_type_minimum totp is unknown".to_string()
+            )),
+            preload,
+            filter!(f_eq("name", PartialValue::new_iname("testgroup"))),
+            modlist!([m_pres("credential_type_minimum", &Value::new_iutf8("totp"))]),
+            None,
+            |_, _: &QueryServerWriteTransaction| {}
+        );
+    }
+
+    #[test]
+    fn test_credential_policy_webauthn_attestation_ca() {
+        let preload = Vec::new();
+        run_modify_test!(
+            Err(OperationError::InvalidAttribute(
+                "webauthn_attestation_ca is not a valid PEM certificate".to_string()
+            )),
+            preload,
+            filter!(f_eq(
+                "uuid",
+                PartialValue::new_uuidr(&crate::constants::UUID_SYSTEM_CONFIG)
+            )),
+            modlist!([m_pres(
+                "webauthn_attestation_ca",
+                &Value::new_utf8s(
+                    "-----BEGIN CERTIFICATE-----\nnot a cert\n-----END CERTIFICATE-----"
+                )
+            )]),
+            None,
+            |_, _: &QueryServerWriteTransaction| {}
+        );
+    }
+
+    #[test]
+    fn test_credential_policy_password_quality_bounds() {
+        let preload = Vec::new();
+        run_modify_test!(
+            Err(OperationError::InvalidAttribute(
+                "password_min_score must be between 0 and 4".to_string()
+            )),
+            preload,
+            filter!(f_eq(
+                "uuid",
+                PartialValue::new_uuidr(&crate::constants::UUID_SYSTEM_CONFIG)
+            )),
+            modlist!([m_pres("password_min_score", &Value::new_uint32(5))]),
+            None,
+            |_, _: &QueryServerWriteTransaction| {}
+        );
+    }
+
+    #[test]
+    fn test_credential_policy_x509_fingerprint() {
+        let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "attrs": {
+                "class": ["account", "person"],
+                "name": ["testperson"],
+                "displayname": ["testperson"],
+                "uuid": ["c5e1a9f4-2b3d-4e6f-8a7b-9c0d1e2f3a4b"]
+            }
+        }"#,
+        );
+
+        // The colon separated form printed by openssl is stored as plain hex.
+        let preload = vec![e.clone()];
+        run_modify_test!(
+            Ok(()),
+            preload,
+            filter!(f_eq("name", PartialValue::new_iname("testperson"))),
+            modlist!([m_pres(
+                "x509_fingerprint",
+                &Value::new_iutf8(
+                    "AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89"
+                )
+            )]),
+            None,
+            |au: &mut AuditScope, qs: &QueryServerWriteTransaction| {
+                let cands = qs
+                    .internal_search(
+                        au,
+                        filter!(f_eq("name", PartialValue::new_iname("testperson"))),
+                    )
+                    .expect("Internal search failure");
+                let e = cands.first().expect("No cand");
+                assert!(e.attribute_value_pres(
+                    "x509_fingerprint",
+                    &PartialValue::new_iutf8(
+                        "abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789"
+                    )
+                ));
+            }
+        );
+
+        let preload = vec![e];
+        run_modify_test!(
+            Err(OperationError::InvalidAttribute(
+                "x509_fingerprint must be a sha256 fingerprint in hex".to_string()
+            )),
+            preload,
+            filter!(f_eq("name", PartialValue::new_iname("testperson"))),
+            modlist!([m_pres(
+                "x509_fingerprint",
+                &Value::new_iutf8("not a fingerprint")
+            )]),
+            None,
+            |_, _: &QueryServerWriteTransaction| {}
+        );
+    }
+}
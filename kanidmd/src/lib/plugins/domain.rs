@@ -4,12 +4,16 @@
 // The primary point of this is to generate a unique domain UUID on startup
 // which is importart for management of the replication topo and trust
 // relationships.
+//
+// We also generate the domain's ES256 signing key here, which is used to sign
+// oauth2 and openid connect tokens, and the key of the ssh certificate authority.
 use crate::plugins::Plugin;
 
 use crate::audit::AuditScope;
 use crate::constants::UUID_DOMAIN_INFO;
-use crate::entry::{Entry, EntryInvalid, EntryNew};
-use crate::event::CreateEvent;
+use crate::crypto::{JwsSigner, SshCaSigner};
+use crate::entry::{Entry, EntryCommitted, EntryInvalid, EntryNew};
+use crate::event::{CreateEvent, ModifyEvent};
 use crate::server::QueryServerWriteTransaction;
 use crate::value::{PartialValue, Value};
 use kanidm_proto::v1::OperationError;
@@ -21,6 +25,38 @@ lazy_static! {
 
 pub struct Domain {}
 
+// Generate the keys of the domain that are missing.
+fn generate_domain_keys<STATE: Clone>(
+    au: &mut AuditScope,
+    e: &mut Entry<EntryInvalid, STATE>,
+) -> Result<(), OperationError> {
+    if !e.attribute_pres("es256_private_key_der") {
+        let der = JwsSigner::generate_es256_der().map_err(|e| {
+            ladmin_error!(au, "Unable to generate ES256 JwsSigner private key");
+            e
+        })?;
+        let v = Value::new_private_binary(&der);
+        e.set_ava("es256_private_key_der", btreeset![v]);
+        ltrace!(
+            au,
+            "plugin_domain: Applying es256_private_key_der transform"
+        );
+    }
+    if !e.attribute_pres("ssh_ca_private_key_der") {
+        let der = SshCaSigner::generate_der().map_err(|e| {
+            ladmin_error!(au, "Unable to generate ssh ca private key");
+            e
+        })?;
+        let v = Value::new_private_binary(&der);
+        e.set_ava("ssh_ca_private_key_der", btreeset![v]);
+        ltrace!(
+            au,
+            "plugin_domain: Applying ssh_ca_private_key_der transform"
+        );
+    }
+    Ok(())
+}
+
 impl Plugin for Domain {
     fn id() -> &'static str {
         "plugin_domain"
@@ -33,7 +69,7 @@ impl Plugin for Domain {
         _ce: &CreateEvent,
     ) -> Result<(), OperationError> {
         ltrace!(au, "Entering plugin_domain pre_create_transform");
-        cand.iter_mut().for_each(|e| {
+        cand.iter_mut().try_for_each(|e| {
             if e.attribute_value_pres("class", &PVCLASS_DOMAIN_INFO)
                 && e.attribute_value_pres("uuid", &PVUUID_DOMAIN_INFO)
             {
@@ -47,12 +83,32 @@ impl Plugin for Domain {
                     e.set_ava("domain_name", btreeset![n]);
                     ltrace!(au, "plugin_domain: Applying domain_name transform");
                 }
+                generate_domain_keys(au, e)?;
                 ltrace!(au, "{:?}", e);
             }
-        });
+            Ok(())
+        })?;
         ltrace!(au, "Ending plugin_domain pre_create_transform");
         Ok(())
     }
+
+    fn pre_modify(
+        au: &mut AuditScope,
+        _qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryCommitted>>,
+        _me: &ModifyEvent,
+    ) -> Result<(), OperationError> {
+        // Domains that existed before oauth2 or the ssh ca was added will not have
+        // their keys, so we generate them as part of the migration of the domain_info entry.
+        cand.iter_mut().try_for_each(|e| {
+            if e.attribute_value_pres("class", &PVCLASS_DOMAIN_INFO)
+                && e.attribute_value_pres("uuid", &PVUUID_DOMAIN_INFO)
+            {
+                generate_domain_keys(au, e)?;
+            }
+            Ok(())
+        })
+    }
 }
 
 #[cfg(test)]
@@ -73,6 +129,9 @@ mod tests {
             let u_dom = server_txn.get_domain_uuid();
 
             assert!(e_dom.attribute_value_pres("domain_uuid", &PartialValue::new_uuid(u_dom)));
+            // The signing keys must have been generated too.
+            assert!(e_dom.attribute_pres("es256_private_key_der"));
+            assert!(e_dom.attribute_pres("ssh_ca_private_key_der"));
         })
     }
 }
@@ -9,15 +9,21 @@ mod macros;
 
 mod attrunique;
 mod base;
+mod credential_policy;
 mod domain;
 mod failure;
 mod gidnumber;
 mod memberof;
+mod oauth2;
+mod password_history;
 mod password_import;
 mod protected;
 mod recycle;
 mod refint;
+mod service_account;
 mod spn;
+mod trust;
+mod webauthn_index;
 
 trait Plugin {
     fn id() -> &'static str;
@@ -276,7 +282,36 @@ impl Plugins {
                     run_pre_create_transform_plugin!(au, qs, cand, ce, gidnumber::GidNumber)
                 })
                 .and_then(|_| run_pre_create_transform_plugin!(au, qs, cand, ce, domain::Domain))
+                .and_then(|_| {
+                    run_pre_create_transform_plugin!(
+                        au,
+                        qs,
+                        cand,
+                        ce,
+                        credential_policy::CredentialPolicy
+                    )
+                })
+                .and_then(|_| run_pre_create_transform_plugin!(au, qs, cand, ce, oauth2::Oauth2))
+                .and_then(|_| {
+                    run_pre_create_transform_plugin!(
+                        au,
+                        qs,
+                        cand,
+                        ce,
+                        service_account::ServiceAccount
+                    )
+                })
+                .and_then(|_| run_pre_create_transform_plugin!(au, qs, cand, ce, trust::Trust))
                 .and_then(|_| run_pre_create_transform_plugin!(au, qs, cand, ce, spn::Spn))
+                .and_then(|_| {
+                    run_pre_create_transform_plugin!(
+                        au,
+                        qs,
+                        cand,
+                        ce,
+                        webauthn_index::WebauthnIndex
+                    )
+                })
                 .and_then(|_| {
                     // Should always be last
                     run_pre_create_transform_plugin!(au, qs, cand, ce, attrunique::AttrUnique)
@@ -334,7 +369,19 @@ impl Plugins {
                     run_pre_modify_plugin!(au, qs, cand, me, password_import::PasswordImport)
                 })
                 .and_then(|_| run_pre_modify_plugin!(au, qs, cand, me, gidnumber::GidNumber))
+                .and_then(|_| run_pre_modify_plugin!(au, qs, cand, me, domain::Domain))
+                .and_then(|_| {
+                    run_pre_modify_plugin!(au, qs, cand, me, credential_policy::CredentialPolicy)
+                })
+                .and_then(|_| run_pre_modify_plugin!(au, qs, cand, me, oauth2::Oauth2))
+                .and_then(|_| {
+                    run_pre_modify_plugin!(au, qs, cand, me, service_account::ServiceAccount)
+                })
+                .and_then(|_| run_pre_modify_plugin!(au, qs, cand, me, trust::Trust))
                 .and_then(|_| run_pre_modify_plugin!(au, qs, cand, me, spn::Spn))
+                .and_then(|_| {
+                    run_pre_modify_plugin!(au, qs, cand, me, webauthn_index::WebauthnIndex)
+                })
                 // attr unique should always be last
                 .and_then(|_| run_pre_modify_plugin!(au, qs, cand, me, attrunique::AttrUnique))
         })
@@ -378,7 +425,14 @@ impl Plugins {
             cand,
             de,
             protected::Protected
-        ))
+        )
+        .and_then(|_| run_pre_delete_plugin!(
+            au,
+            qs,
+            cand,
+            de,
+            password_history::PasswordHistory
+        )))
     }
 
     pub fn run_post_delete(
@@ -400,6 +454,13 @@ impl Plugins {
             cand,
             de,
             memberof::MemberOf
+        ))
+        .and_then(|_| run_post_delete_plugin!(
+            au,
+            qs,
+            cand,
+            de,
+            oauth2::Oauth2
         )))
     }
 
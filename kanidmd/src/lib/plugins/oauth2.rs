@@ -0,0 +1,178 @@
+// Manage the lifecycle of oauth2 resource servers. This generates the basic secret of
+// a resource server if one is not provided, and when a resource server is deleted, removes
+// all consent that accounts have granted to it so that a new resource server can not inherit
+// the consent of a previous one.
+use crate::plugins::Plugin;
+
+use crate::audit::AuditScope;
+use crate::entry::{Entry, EntryCommitted, EntryInvalid, EntryNew, EntrySealed};
+use crate::event::{CreateEvent, DeleteEvent, ModifyEvent};
+use crate::modify::{m_remove, ModifyList};
+use crate::server::QueryServerWriteTransaction;
+use crate::utils::password_from_random;
+use crate::value::{PartialValue, Value};
+use kanidm_proto::v1::OperationError;
+
+lazy_static! {
+    static ref PVCLASS_OAUTH2_RS: PartialValue = PartialValue::new_class("oauth2_resource_server");
+    static ref PVCLASS_OAUTH2_BASIC: PartialValue =
+        PartialValue::new_class("oauth2_resource_server_basic");
+}
+
+pub struct Oauth2 {}
+
+fn apply_oauth2_secret<T: Clone>(au: &mut AuditScope, e: &mut Entry<EntryInvalid, T>) {
+    if e.attribute_value_pres("class", &PVCLASS_OAUTH2_BASIC)
+        && !e.attribute_pres("oauth2_rs_basic_secret")
+    {
+        ltrace!(au, "plugin_oauth2: Generating oauth2_rs_basic_secret");
+        let v = Value::new_utf8(password_from_random());
+        e.set_ava("oauth2_rs_basic_secret", btreeset![v]);
+    }
+}
+
+impl Plugin for Oauth2 {
+    fn id() -> &'static str {
+        "plugin_oauth2"
+    }
+
+    fn pre_create_transform(
+        au: &mut AuditScope,
+        _qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryNew>>,
+        _ce: &CreateEvent,
+    ) -> Result<(), OperationError> {
+        cand.iter_mut().for_each(|e| apply_oauth2_secret(au, e));
+        Ok(())
+    }
+
+    fn pre_modify(
+        au: &mut AuditScope,
+        _qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryCommitted>>,
+        _me: &ModifyEvent,
+    ) -> Result<(), OperationError> {
+        // If the secret was purged, this regenerates it, allowing it to be reset.
+        cand.iter_mut().for_each(|e| apply_oauth2_secret(au, e));
+        Ok(())
+    }
+
+    fn post_delete(
+        au: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        cand: &[Entry<EntrySealed, EntryCommitted>],
+        _de: &DeleteEvent,
+    ) -> Result<(), OperationError> {
+        let removed: Vec<PartialValue> = cand
+            .iter()
+            .filter(|e| e.attribute_value_pres("class", &PVCLASS_OAUTH2_RS))
+            .map(|e| PartialValue::new_oauthscopemap(*e.get_uuid()))
+            .collect();
+
+        if removed.is_empty() {
+            return Ok(());
+        }
+
+        removed.iter().try_for_each(|pv| {
+            let filt = filter_all!(f_eq("oauth2_consent_scope_map", pv.clone()));
+            let modlist = ModifyList::new_list(vec![m_remove("oauth2_consent_scope_map", pv)]);
+            match qs.internal_modify(au, &filt, &modlist) {
+                // No account had consented to this resource server.
+                Ok(()) | Err(OperationError::NoMatchingEntries) => Ok(()),
+                Err(e) => {
+                    ladmin_error!(au, "Failed to remove oauth2 consent -> {:?}", e);
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entry::{Entry, EntryInit, EntryNew};
+    use crate::server::{QueryServerTransaction, QueryServerWriteTransaction};
+    use crate::value::{PartialValue, Value};
+    use std::collections::BTreeSet;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_oauth2_basic_secret_generate() {
+        let uuid = Uuid::new_v4();
+        let e: Entry<EntryInit, EntryNew> = entry_init!(
+            ("class", Value::new_class("object")),
+            ("class", Value::new_class("oauth2_resource_server")),
+            ("class", Value::new_class("oauth2_resource_server_basic")),
+            ("uuid", Value::new_uuid(uuid)),
+            ("oauth2_rs_name", Value::new_iname("test_resource_server")),
+            ("displayname", Value::new_utf8s("test_resource_server")),
+            (
+                "oauth2_rs_origin",
+                Value::new_utf8s("https://demo.example.com")
+            )
+        );
+
+        let create = vec![e];
+        let preload = Vec::new();
+
+        run_create_test!(
+            Ok(()),
+            preload,
+            create,
+            None,
+            |au: &mut AuditScope, qs: &QueryServerWriteTransaction| {
+                let e = qs
+                    .internal_search_uuid(au, &uuid)
+                    .expect("failed to get oauth2 config");
+                assert!(e.attribute_pres("oauth2_rs_basic_secret"));
+            }
+        );
+    }
+
+    #[test]
+    fn test_oauth2_delete_removes_consent() {
+        let rs_uuid = Uuid::new_v4();
+        let acct_uuid = Uuid::new_v4();
+
+        let e_rs: Entry<EntryInit, EntryNew> = entry_init!(
+            ("class", Value::new_class("object")),
+            ("class", Value::new_class("oauth2_resource_server")),
+            ("uuid", Value::new_uuid(rs_uuid)),
+            ("oauth2_rs_name", Value::new_iname("test_resource_server")),
+            ("displayname", Value::new_utf8s("test_resource_server")),
+            (
+                "oauth2_rs_origin",
+                Value::new_utf8s("https://demo.example.com")
+            )
+        );
+
+        let mut scopes = BTreeSet::new();
+        scopes.insert("openid".to_string());
+        let e_acct: Entry<EntryInit, EntryNew> = entry_init!(
+            ("class", Value::new_class("object")),
+            ("class", Value::new_class("account")),
+            ("uuid", Value::new_uuid(acct_uuid)),
+            ("name", Value::new_iname("testaccount")),
+            ("displayname", Value::new_utf8s("testaccount")),
+            (
+                "oauth2_consent_scope_map",
+                Value::new_oauthscopemap(rs_uuid, scopes)
+            )
+        );
+
+        let preload = vec![e_rs, e_acct];
+
+        run_delete_test!(
+            Ok(()),
+            preload,
+            filter!(f_eq("uuid", PartialValue::new_uuid(rs_uuid))),
+            None,
+            |au: &mut AuditScope, qs: &QueryServerWriteTransaction| {
+                let e = qs
+                    .internal_search_uuid(au, &acct_uuid)
+                    .expect("failed to get account");
+                assert!(!e.attribute_pres("oauth2_consent_scope_map"));
+            }
+        );
+    }
+}
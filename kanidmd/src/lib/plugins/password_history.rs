@@ -0,0 +1,106 @@
+// Remove the password history of credentials when an account is deleted. The history
+// is only needed to prevent reuse while the account is live, and if the account is
+// revived the user is no worse off than a fresh account.
+use crate::audit::AuditScope;
+use crate::entry::{Entry, EntryCommitted, EntryInvalid};
+use crate::event::DeleteEvent;
+use crate::plugins::Plugin;
+use crate::server::QueryServerWriteTransaction;
+use crate::value::Value;
+use kanidm_proto::v1::OperationError;
+
+// The credential attributes that may carry a history, and the tag of their credential.
+const HISTORY_ATTRS: [(&str, &str); 2] =
+    [("primary_credential", "primary"), ("unix_password", "unix")];
+
+pub struct PasswordHistory {}
+
+impl Plugin for PasswordHistory {
+    fn id() -> &'static str {
+        "plugin_password_history"
+    }
+
+    fn pre_delete(
+        au: &mut AuditScope,
+        _qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryCommitted>>,
+        _de: &DeleteEvent,
+    ) -> Result<(), OperationError> {
+        cand.iter_mut().for_each(|e| {
+            HISTORY_ATTRS.iter().for_each(|(attr, tag)| {
+                let ncred = e
+                    .get_ava_single_credential(attr)
+                    .filter(|c| c.has_password_history())
+                    .map(|c| c.clear_password_history());
+
+                if let Some(ncred) = ncred {
+                    ltrace!(au, "Removing password history from {}", attr);
+                    e.set_ava(attr, btreeset![Value::new_credential(tag, ncred)]);
+                }
+            })
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::credential::policy::CryptoPolicy;
+    use crate::credential::Credential;
+    use crate::entry::{Entry, EntryInit, EntryNew};
+    use crate::server::{QueryServerTransaction, QueryServerWriteTransaction};
+    use crate::value::{PartialValue, Value};
+    use std::time::Duration;
+
+    #[test]
+    fn test_delete_password_history() {
+        let mut ea: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "attrs": {
+                "class": ["account", "person"],
+                "name": ["testperson"],
+                "description": ["testperson"],
+                "displayname": ["testperson"],
+                "uuid": ["d2b496bd-8493-47b7-8142-f568b5cf47ee"]
+            }
+        }"#,
+        );
+
+        let p = CryptoPolicy::minimum();
+        let c = Credential::new_password_only(&p, "password1")
+            .unwrap()
+            .set_password_with_history(&p, "password2", 4, Duration::from_secs(1))
+            .unwrap();
+        assert!(c.has_password_history());
+        ea.add_ava("primary_credential", Value::new_credential("primary", c));
+
+        let preload = vec![ea];
+
+        run_delete_test!(
+            Ok(()),
+            preload,
+            filter!(f_eq("name", PartialValue::new_iname("testperson"))),
+            None,
+            |au: &mut AuditScope, qs: &QueryServerWriteTransaction| {
+                let e = qs
+                    .internal_search(
+                        au,
+                        filter_all!(f_eq(
+                            "uuid",
+                            PartialValue::new_uuids("d2b496bd-8493-47b7-8142-f568b5cf47ee")
+                                .unwrap()
+                        )),
+                    )
+                    .expect("failed to search")
+                    .pop()
+                    .expect("failed to get entry");
+                let c = e
+                    .get_ava_single_credential("primary_credential")
+                    .expect("failed to get primary cred.");
+                // The credential itself remains in case the account is revived.
+                assert!(c.verify_password("password2").unwrap());
+                assert!(!c.has_password_history());
+            }
+        );
+    }
+}
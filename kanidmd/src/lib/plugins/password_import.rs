@@ -270,8 +270,8 @@ mod tests {
                     .get_ava_single_credential("primary_credential")
                     .expect("failed to get primary cred.");
                 match &c.type_ {
-                    CredentialType::PasswordMFA(_pw, totp, webauthn) => {
-                        assert!(totp.is_some());
+                    CredentialType::PasswordMFA(_pw, totp, webauthn, _) => {
+                        assert!(!totp.is_empty());
                         assert!(webauthn.is_empty());
                     }
                     _ => assert!(false),
@@ -27,6 +27,7 @@ lazy_static! {
         // Allow modification of some domain info types for local configuration.
         m.insert("domain_ssid");
         m.insert("badlist_password");
+        m.insert("password_history_length");
         m
     };
     static ref PVCLASS_SYSTEM: PartialValue = PartialValue::new_class("system");
@@ -0,0 +1,172 @@
+// Service accounts authenticate only with api tokens, so they must never hold a
+// password credential, app password or credential reset token. This is checked after
+// password_import, so that an import can not be used to sneak a credential onto a
+// service account.
+use crate::audit::AuditScope;
+use crate::entry::{Entry, EntryCommitted, EntryInvalid, EntryNew};
+use crate::event::{CreateEvent, ModifyEvent};
+use crate::plugins::Plugin;
+use crate::server::QueryServerWriteTransaction;
+use crate::value::PartialValue;
+use kanidm_proto::v1::{OperationError, PluginError};
+
+lazy_static! {
+    static ref PVCLASS_SERVICE_ACCOUNT: PartialValue = PartialValue::new_class("service_account");
+    static ref PVCLASS_PERSON: PartialValue = PartialValue::new_class("person");
+}
+
+pub struct ServiceAccount {}
+
+fn check_service_account<T>(
+    au: &mut AuditScope,
+    e: &Entry<EntryInvalid, T>,
+) -> Result<(), OperationError> {
+    if !e.attribute_value_pres("class", &PVCLASS_SERVICE_ACCOUNT) {
+        return Ok(());
+    }
+
+    if e.attribute_value_pres("class", &PVCLASS_PERSON) {
+        lrequest_error!(au, "A service account can not be a person");
+        return Err(OperationError::Plugin(PluginError::ServiceAccount(
+            "a service account can not be a person".to_string(),
+        )));
+    }
+
+    if e.attribute_pres("primary_credential") {
+        lrequest_error!(au, "A service account can not have a primary credential");
+        return Err(OperationError::Plugin(PluginError::ServiceAccount(
+            "a service account can not have a primary credential".to_string(),
+        )));
+    }
+
+    if e.attribute_pres("app_password") {
+        lrequest_error!(au, "A service account can not have an app password");
+        return Err(OperationError::Plugin(PluginError::ServiceAccount(
+            "a service account can not have an app password".to_string(),
+        )));
+    }
+
+    if e.attribute_pres("credential_reset_intent") {
+        lrequest_error!(au, "A service account can not have its credentials reset");
+        return Err(OperationError::Plugin(PluginError::ServiceAccount(
+            "a service account can not have its credentials reset".to_string(),
+        )));
+    }
+
+    Ok(())
+}
+
+impl Plugin for ServiceAccount {
+    fn id() -> &'static str {
+        "plugin_service_account"
+    }
+
+    fn pre_create_transform(
+        au: &mut AuditScope,
+        _qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryNew>>,
+        _ce: &CreateEvent,
+    ) -> Result<(), OperationError> {
+        cand.iter().try_for_each(|e| check_service_account(au, e))
+    }
+
+    fn pre_modify(
+        au: &mut AuditScope,
+        _qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryCommitted>>,
+        _me: &ModifyEvent,
+    ) -> Result<(), OperationError> {
+        cand.iter().try_for_each(|e| check_service_account(au, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entry::{Entry, EntryInit, EntryNew};
+    use crate::modify::{Modify, ModifyList};
+    use crate::value::{PartialValue, Value};
+    use kanidm_proto::v1::{OperationError, PluginError};
+    use smartstring::alias::String as AttrString;
+
+    const IMPORT_HASH: &'static str =
+        "pbkdf2_sha256$36000$xIEozuZVAoYm$uW1b35DUKyhvQAf1mBqMvoBDcqSD06juzyO/nmyV0+w=";
+
+    #[test]
+    fn test_service_account_create() {
+        let preload: Vec<Entry<EntryInit, EntryNew>> = Vec::new();
+
+        let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "attrs": {
+                "class": ["account", "service_account"],
+                "name": ["testservice"],
+                "displayname": ["testservice"],
+                "uuid": ["d2b496bd-8493-47b7-8142-f568b5cf47ee"]
+            }
+        }"#,
+        );
+
+        let create = vec![e];
+
+        run_create_test!(Ok(()), preload, create, None, |_, _| {});
+    }
+
+    #[test]
+    fn test_service_account_create_deny_credential() {
+        let preload: Vec<Entry<EntryInit, EntryNew>> = Vec::new();
+
+        let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "attrs": {
+                "class": ["account", "service_account"],
+                "name": ["testservice"],
+                "displayname": ["testservice"],
+                "uuid": ["d2b496bd-8493-47b7-8142-f568b5cf47ee"],
+                "password_import": ["pbkdf2_sha256$36000$xIEozuZVAoYm$uW1b35DUKyhvQAf1mBqMvoBDcqSD06juzyO/nmyV0+w="]
+            }
+        }"#,
+        );
+
+        let create = vec![e];
+
+        run_create_test!(
+            Err(OperationError::Plugin(PluginError::ServiceAccount(
+                "a service account can not have a primary credential".to_string()
+            ))),
+            preload,
+            create,
+            None,
+            |_, _| {}
+        );
+    }
+
+    #[test]
+    fn test_service_account_modify_deny_credential() {
+        let ea: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "attrs": {
+                "class": ["account", "service_account"],
+                "name": ["testservice"],
+                "displayname": ["testservice"],
+                "uuid": ["d2b496bd-8493-47b7-8142-f568b5cf47ee"]
+            }
+        }"#,
+        );
+
+        let preload = vec![ea];
+
+        run_modify_test!(
+            Err(OperationError::Plugin(PluginError::ServiceAccount(
+                "a service account can not have a primary credential".to_string()
+            ))),
+            preload,
+            filter!(f_eq("name", PartialValue::new_iname("testservice"))),
+            ModifyList::new_list(vec![Modify::Present(
+                AttrString::from("password_import"),
+                Value::from(IMPORT_HASH)
+            )]),
+            None,
+            |_, _| {}
+        );
+    }
+}
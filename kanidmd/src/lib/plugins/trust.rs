@@ -0,0 +1,204 @@
+// Domain trusts and the foreign principals of trusted domains. Each trust key must be a
+// valid ES256 JWK so that the trust can verify tokens once it's loaded, and a foreign
+// principal must belong to a domain that we trust. The spn of a foreign principal is
+// supplied by the admin, so it can not also be an account or group, which would have
+// the spn plugin overwrite it with one in our own domain.
+use crate::audit::AuditScope;
+use crate::entry::{Entry, EntryCommitted, EntryInvalid, EntryNew};
+use crate::event::{CreateEvent, ModifyEvent};
+use crate::plugins::Plugin;
+use crate::server::{QueryServerTransaction, QueryServerWriteTransaction};
+use crate::trust::{spn_realm, trust_key_validator};
+use crate::value::PartialValue;
+use kanidm_proto::v1::{OperationError, PluginError};
+
+lazy_static! {
+    static ref CLASS_DOMAIN_TRUST: PartialValue = PartialValue::new_class("domain_trust");
+    static ref CLASS_FOREIGN_PRINCIPAL: PartialValue = PartialValue::new_class("foreign_principal");
+    static ref CLASS_ACCOUNT: PartialValue = PartialValue::new_class("account");
+    static ref CLASS_GROUP: PartialValue = PartialValue::new_class("group");
+}
+
+pub struct Trust {}
+
+fn check_trust<T>(
+    au: &mut AuditScope,
+    qs: &QueryServerWriteTransaction,
+    e: &Entry<EntryInvalid, T>,
+) -> Result<(), OperationError> {
+    if e.attribute_value_pres("class", &CLASS_DOMAIN_TRUST) {
+        let valid = e
+            .get_ava_as_str("trust_key")
+            .map(|mut keys| keys.all(|k| trust_key_validator(k).is_some()))
+            .unwrap_or(false);
+        if !valid {
+            lrequest_error!(au, "A domain trust has an invalid trust key");
+            return Err(OperationError::Plugin(PluginError::Trust(
+                "trust keys must be ES256 public keys in JWK format".to_string(),
+            )));
+        }
+    }
+
+    if e.attribute_value_pres("class", &CLASS_FOREIGN_PRINCIPAL) {
+        if e.attribute_value_pres("class", &CLASS_ACCOUNT)
+            || e.attribute_value_pres("class", &CLASS_GROUP)
+        {
+            lrequest_error!(au, "A foreign principal can not be an account or group");
+            return Err(OperationError::Plugin(PluginError::Trust(
+                "a foreign principal can not be an account or group".to_string(),
+            )));
+        }
+
+        let spn = e
+            .get_ava_single("spn")
+            .map(|v| v.to_proto_string_clone())
+            .unwrap_or_default();
+        let trusted = match spn_realm(spn.as_str()) {
+            Some(realm) => qs.internal_exists(
+                au,
+                filter!(f_and!([
+                    f_eq("class", CLASS_DOMAIN_TRUST.clone()),
+                    f_eq("domain_name", PartialValue::new_iname(realm))
+                ])),
+            )?,
+            None => false,
+        };
+        if !trusted {
+            lrequest_error!(au, "Foreign principal {} is not in a trusted domain", spn);
+            return Err(OperationError::Plugin(PluginError::Trust(format!(
+                "{} is not in a trusted domain",
+                spn
+            ))));
+        }
+    }
+
+    Ok(())
+}
+
+impl Plugin for Trust {
+    fn id() -> &'static str {
+        "plugin_trust"
+    }
+
+    fn pre_create_transform(
+        au: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryNew>>,
+        _ce: &CreateEvent,
+    ) -> Result<(), OperationError> {
+        cand.iter().try_for_each(|e| check_trust(au, qs, e))
+    }
+
+    fn pre_modify(
+        au: &mut AuditScope,
+        qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryCommitted>>,
+        _me: &ModifyEvent,
+    ) -> Result<(), OperationError> {
+        cand.iter().try_for_each(|e| check_trust(au, qs, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::JwsSigner;
+    use crate::entry::{Entry, EntryInit, EntryNew};
+    use crate::value::Value;
+    use kanidm_proto::v1::{OperationError, PluginError};
+
+    fn trust_entry(key: &str) -> Entry<EntryInit, EntryNew> {
+        let mut e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "attrs": {
+                "class": ["object", "domain_trust"],
+                "name": ["remote_trust"],
+                "domain_name": ["remote.example.com"],
+                "uuid": ["a0a60c35-7c0f-4b56-9b3e-4f7e3a0b8c11"]
+            }
+        }"#,
+        );
+        e.add_ava("trust_key", Value::new_utf8s(key));
+        e
+    }
+
+    fn foreign_principal(spn: &str) -> Entry<EntryInit, EntryNew> {
+        let mut e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "attrs": {
+                "class": ["object", "foreign_principal"],
+                "uuid": ["4d21d04a-dc0e-42eb-b850-34dd180b107f"]
+            }
+        }"#,
+        );
+        e.add_ava("spn", Value::new_spn_parse(spn).expect("Invalid spn"));
+        e
+    }
+
+    fn test_key() -> String {
+        let jwk = JwsSigner::generate_es256_der()
+            .and_then(|der| JwsSigner::from_es256_der(&der))
+            .and_then(|signer| signer.public_key_as_jwk())
+            .expect("Failed to create jwk");
+        serde_json::to_string(&jwk).expect("Failed to serialise jwk")
+    }
+
+    #[test]
+    fn test_trust_create() {
+        let preload = vec![trust_entry(test_key().as_str())];
+        let create = vec![foreign_principal("claire@remote.example.com")];
+
+        run_create_test!(Ok(()), preload, create, None, |_, _| {});
+    }
+
+    #[test]
+    fn test_trust_create_deny_invalid_key() {
+        let preload: Vec<Entry<EntryInit, EntryNew>> = Vec::new();
+        let create = vec![trust_entry("not a key")];
+
+        run_create_test!(
+            Err(OperationError::Plugin(PluginError::Trust(
+                "trust keys must be ES256 public keys in JWK format".to_string()
+            ))),
+            preload,
+            create,
+            None,
+            |_, _| {}
+        );
+    }
+
+    #[test]
+    fn test_trust_create_deny_untrusted_principal() {
+        let preload = vec![trust_entry(test_key().as_str())];
+        let create = vec![foreign_principal("claire@other.example.com")];
+
+        run_create_test!(
+            Err(OperationError::Plugin(PluginError::Trust(
+                "claire@other.example.com is not in a trusted domain".to_string()
+            ))),
+            preload,
+            create,
+            None,
+            |_, _| {}
+        );
+    }
+
+    #[test]
+    fn test_trust_create_deny_foreign_account() {
+        let preload = vec![trust_entry(test_key().as_str())];
+        let mut e = foreign_principal("claire@remote.example.com");
+        e.add_ava("class", Value::new_class("account"));
+        e.add_ava("name", Value::new_iname("claire"));
+        e.add_ava("displayname", Value::new_utf8s("Claire"));
+        let create = vec![e];
+
+        run_create_test!(
+            Err(OperationError::Plugin(PluginError::Trust(
+                "a foreign principal can not be an account or group".to_string()
+            ))),
+            preload,
+            create,
+            None,
+            |_, _| {}
+        );
+    }
+}
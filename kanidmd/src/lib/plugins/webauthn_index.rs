@@ -0,0 +1,122 @@
+// Maintain the ids of the webauthn tokens of an account's primary credential as an
+// indexed attribute. This lets a discoverable credential, which arrives without an
+// account name, be mapped back to its account. The attribute is always derived from
+// the credential, so it can't be set to point at another account.
+use crate::plugins::Plugin;
+
+use crate::audit::AuditScope;
+use crate::entry::{Entry, EntryCommitted, EntryInvalid, EntryNew};
+use crate::event::{CreateEvent, ModifyEvent};
+use crate::server::QueryServerWriteTransaction;
+use crate::value::Value;
+use kanidm_proto::v1::OperationError;
+use std::collections::BTreeSet;
+
+pub struct WebauthnIndex {}
+
+fn index_credential_ids<T: Clone>(au: &mut AuditScope, e: &mut Entry<EntryInvalid, T>) {
+    let cids: BTreeSet<Value> = e
+        .get_ava_single_credential("primary_credential")
+        .and_then(|cred| cred.webauthn_ref().ok())
+        .map(|wan| {
+            wan.values()
+                .map(|wcred| {
+                    Value::new_utf8(base64::encode_config(
+                        &wcred.cred_id,
+                        base64::URL_SAFE_NO_PAD,
+                    ))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    ltrace!(
+        au,
+        "plugin_webauthn_index: set {} credential ids",
+        cids.len()
+    );
+    if cids.is_empty() {
+        e.purge_ava("webauthn_credential_id");
+    } else {
+        e.set_ava("webauthn_credential_id", cids);
+    }
+}
+
+impl Plugin for WebauthnIndex {
+    fn id() -> &'static str {
+        "plugin_webauthn_index"
+    }
+
+    fn pre_create_transform(
+        au: &mut AuditScope,
+        _qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryNew>>,
+        _ce: &CreateEvent,
+    ) -> Result<(), OperationError> {
+        cand.iter_mut().for_each(|e| index_credential_ids(au, e));
+        Ok(())
+    }
+
+    fn pre_modify(
+        au: &mut AuditScope,
+        _qs: &QueryServerWriteTransaction,
+        cand: &mut Vec<Entry<EntryInvalid, EntryCommitted>>,
+        _me: &ModifyEvent,
+    ) -> Result<(), OperationError> {
+        cand.iter_mut().for_each(|e| index_credential_ids(au, e));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::AuditScope;
+    use crate::entry::{Entry, EntryInit, EntryNew};
+    use crate::server::{QueryServerTransaction, QueryServerWriteTransaction};
+    use crate::value::PartialValue;
+
+    #[test]
+    fn test_webauthn_index_not_settable() {
+        let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "attrs": {
+                "class": ["account", "person"],
+                "name": ["testperson"],
+                "description": ["testperson"],
+                "displayname": ["testperson"],
+                "uuid": ["d2b496bd-8493-47b7-8142-f568b5cf47ee"],
+                "webauthn_credential_id": ["Y3JlZGVudGlhbA"]
+            }
+        }"#,
+        );
+
+        let create = vec![e];
+        let preload = Vec::new();
+
+        // The account has no webauthn tokens, so the id can't be claimed.
+        run_create_test!(
+            Ok(()),
+            preload,
+            create,
+            None,
+            |au: &mut AuditScope, qs: &QueryServerWriteTransaction| {
+                let e = qs
+                    .internal_search_uuid(
+                        au,
+                        &uuid::Uuid::parse_str("d2b496bd-8493-47b7-8142-f568b5cf47ee").unwrap(),
+                    )
+                    .expect("failed to get entry");
+                assert!(!e.attribute_pres("webauthn_credential_id"));
+                assert!(!qs
+                    .internal_exists(
+                        au,
+                        filter!(f_eq(
+                            "webauthn_credential_id",
+                            PartialValue::new_utf8s("Y3JlZGVudGlhbA")
+                        ))
+                    )
+                    .expect("failed to search"));
+            }
+        );
+    }
+}
@@ -202,6 +202,9 @@ impl SchemaAttribute {
             SyntaxType::CID => v.is_cid(),
             SyntaxType::NSUNIQUEID => v.is_nsuniqueid(),
             SyntaxType::DATETIME => v.is_datetime(),
+            SyntaxType::PRIVATE_BINARY => v.is_private_binary(),
+            SyntaxType::OAUTH_SCOPE_MAP => v.is_oauthscopemap(),
+            SyntaxType::API_TOKEN => v.is_apitoken(),
         };
         if r {
             Ok(())
@@ -386,6 +389,33 @@ impl SchemaAttribute {
                     }
                 })
             }),
+            SyntaxType::PRIVATE_BINARY => ava.iter().fold(Ok(()), |acc, v| {
+                acc.and_then(|_| {
+                    if v.is_private_binary() {
+                        Ok(())
+                    } else {
+                        Err(SchemaError::InvalidAttributeSyntax(a.to_string()))
+                    }
+                })
+            }),
+            SyntaxType::OAUTH_SCOPE_MAP => ava.iter().fold(Ok(()), |acc, v| {
+                acc.and_then(|_| {
+                    if v.is_oauthscopemap() {
+                        Ok(())
+                    } else {
+                        Err(SchemaError::InvalidAttributeSyntax(a.to_string()))
+                    }
+                })
+            }),
+            SyntaxType::API_TOKEN => ava.iter().fold(Ok(()), |acc, v| {
+                acc.and_then(|_| {
+                    if v.is_apitoken() {
+                        Ok(())
+                    } else {
+                        Err(SchemaError::InvalidAttributeSyntax(a.to_string()))
+                    }
+                })
+            }),
         }
     }
 }
@@ -13,7 +13,10 @@ use tokio::sync::{Semaphore, SemaphorePermit};
 use uuid::Uuid;
 
 use crate::audit::AuditScope;
-use crate::be::{Backend, BackendReadTransaction, BackendTransaction, BackendWriteTransaction};
+use crate::be::dbvalue::{DbAuthRecordV1, DbSoftLockV1};
+use crate::be::{
+    AuthRecordFilter, Backend, BackendReadTransaction, BackendTransaction, BackendWriteTransaction,
+};
 
 use crate::access::{
     AccessControlCreate, AccessControlDelete, AccessControlModify, AccessControlSearch,
@@ -37,8 +40,9 @@ use crate::schema::{
     Schema, SchemaAttribute, SchemaClass, SchemaReadTransaction, SchemaTransaction,
     SchemaWriteTransaction,
 };
+use crate::trust::{TrustedDomain, TrustedDomains, TrustedDomainsWriteTransaction};
 use crate::value::{PartialValue, SyntaxType, Value};
-use kanidm_proto::v1::{ConsistencyError, OperationError, SchemaError};
+use kanidm_proto::v1::{ConsistencyError, OperationError, SchemaError, UserAuthToken};
 use smartstring::alias::String as AttrString;
 
 type EntrySealedCommitted = Entry<EntrySealed, EntryCommitted>;
@@ -56,6 +60,7 @@ lazy_static! {
     static ref PVCLASS_ACC: PartialValue = PartialValue::new_class("access_control_create");
     static ref PVCLASS_ACP: PartialValue = PartialValue::new_class("access_control_profile");
     static ref PVACP_ENABLE_FALSE: PartialValue = PartialValue::new_bool(false);
+    static ref PVCLASS_DOMAIN_TRUST: PartialValue = PartialValue::new_class("domain_trust");
 }
 
 #[derive(Clone)]
@@ -65,6 +70,7 @@ pub struct QueryServer {
     be: Backend,
     schema: Arc<Schema>,
     accesscontrols: Arc<AccessControls>,
+    trusts: Arc<TrustedDomains>,
     db_tickets: Arc<Semaphore>,
     write_ticket: Arc<Semaphore>,
 }
@@ -85,11 +91,13 @@ pub struct QueryServerWriteTransaction<'a> {
     be_txn: BackendWriteTransaction<'a>,
     schema: SchemaWriteTransaction<'a>,
     accesscontrols: AccessControlsWriteTransaction<'a>,
+    trusts: TrustedDomainsWriteTransaction<'a>,
     // We store a set of flags that indicate we need a reload of
-    // schema or acp, which is tested by checking the classes of the
+    // schema, acp or trusts, which is tested by checking the classes of the
     // changing content.
     changed_schema: Cell<bool>,
     changed_acp: Cell<bool>,
+    changed_trust: Cell<bool>,
     _db_ticket: SemaphorePermit<'a>,
     _write_ticket: SemaphorePermit<'a>,
 }
@@ -515,6 +523,9 @@ pub trait QueryServerTransaction {
                     SyntaxType::NSUNIQUEID => Ok(Value::new_nsuniqueid_s(value)),
                     SyntaxType::DATETIME => Value::new_datetime_s(value)
                         .ok_or_else(|| OperationError::InvalidAttribute("Invalid DateTime (rfc3339) syntax".to_string())),
+                    SyntaxType::PRIVATE_BINARY => Err(OperationError::InvalidAttribute("Private binary values can not be supplied through modification".to_string())),
+                    SyntaxType::OAUTH_SCOPE_MAP => Err(OperationError::InvalidAttribute("Oauth2 scope maps can not be supplied through modification - please use the IDM api".to_string())),
+                    SyntaxType::API_TOKEN => Err(OperationError::InvalidAttribute("Api tokens can not be supplied through modification - please use the IDM api".to_string())),
                 }
             }
             None => {
@@ -606,6 +617,22 @@ pub trait QueryServerTransaction {
                             "Invalid DateTime (rfc3339) syntax".to_string(),
                         )
                     }),
+                    SyntaxType::PRIVATE_BINARY => Ok(PartialValue::new_private_binary()),
+                    SyntaxType::OAUTH_SCOPE_MAP => PartialValue::new_oauthscopemap_s(value)
+                        .or_else(|| {
+                            let un = self
+                                .name_to_uuid(audit, value)
+                                .unwrap_or_else(|_| *UUID_DOES_NOT_EXIST);
+                            Some(PartialValue::new_oauthscopemap(un))
+                        })
+                        .ok_or_else(|| {
+                            OperationError::InvalidAttribute(
+                                "Invalid Oauth2 scope map syntax".to_string(),
+                            )
+                        }),
+                    SyntaxType::API_TOKEN => PartialValue::new_apitoken_s(value).ok_or_else(|| {
+                        OperationError::InvalidAttribute("Invalid api token syntax".to_string())
+                    }),
                 }
             }
             None => {
@@ -669,6 +696,49 @@ pub trait QueryServerTransaction {
                 e
             })
     }
+
+    // Get the domain's es256 signing key, used to sign oauth2 tokens.
+    fn get_domain_es256_private_key(
+        &self,
+        audit: &mut AuditScope,
+    ) -> Result<Vec<u8>, OperationError> {
+        self.internal_search_uuid(audit, &UUID_DOMAIN_INFO)
+            .and_then(|e| {
+                e.get_ava_single_private_binary("es256_private_key_der")
+                    .map(|s| s.to_vec())
+                    .ok_or(OperationError::InvalidEntryState)
+            })
+            .map_err(|e| {
+                ladmin_error!(audit, "Error getting domain es256 key -> {:?}", e);
+                e
+            })
+    }
+
+    fn get_domain_ssh_ca_private_key(
+        &self,
+        audit: &mut AuditScope,
+    ) -> Result<Vec<u8>, OperationError> {
+        self.internal_search_uuid(audit, &UUID_DOMAIN_INFO)
+            .and_then(|e| {
+                e.get_ava_single_private_binary("ssh_ca_private_key_der")
+                    .map(|s| s.to_vec())
+                    .ok_or(OperationError::InvalidEntryState)
+            })
+            .map_err(|e| {
+                ladmin_error!(audit, "Error getting domain ssh ca key -> {:?}", e);
+                e
+            })
+    }
+
+    // The authentication audit store is outside of the entries, so access to it must be
+    // checked by the caller.
+    fn get_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        filter: &AuthRecordFilter,
+    ) -> Result<Vec<DbAuthRecordV1>, OperationError> {
+        self.get_be_txn().get_auth_records(audit, filter)
+    }
 }
 
 // Actually conduct a search request
@@ -779,11 +849,18 @@ impl QueryServer {
             be,
             schema: Arc::new(schema),
             accesscontrols: Arc::new(AccessControls::new()),
+            trusts: Arc::new(TrustedDomains::new()),
             db_tickets: Arc::new(Semaphore::new(pool_size)),
             write_ticket: Arc::new(Semaphore::new(1)),
         }
     }
 
+    /// Validate a user auth token that was issued by a domain we trust. This only needs
+    /// the in memory trust keys, so it can be called as requests are received.
+    pub fn validate_trusted_uat(&self, jws: &str, ct: Duration) -> Option<UserAuthToken> {
+        self.trusts.validate_uat(jws, ct)
+    }
+
     #[cfg(test)]
     pub fn read(&self) -> QueryServerReadTransaction {
         task::block_on(self.read_async())
@@ -849,8 +926,10 @@ impl QueryServer {
             be_txn,
             schema: schema_write,
             accesscontrols: self.accesscontrols.write(),
+            trusts: self.trusts.write(),
             changed_schema: Cell::new(false),
             changed_acp: Cell::new(false),
+            changed_trust: Cell::new(false),
             _db_ticket: db_ticket,
             _write_ticket: write_ticket,
         }
@@ -926,6 +1005,14 @@ impl QueryServer {
             migrate_txn.migrate_2_to_3(audit)?;
         }
 
+        if system_info_version < 4 {
+            migrate_txn.migrate_3_to_4(audit)?;
+        }
+
+        if system_info_version < 5 {
+            migrate_txn.migrate_4_to_5(audit)?;
+        }
+
         migrate_txn.commit(audit)?;
         // Migrations complete. Init idm will now set the version as needed.
 
@@ -1058,11 +1145,18 @@ impl<'a> QueryServerWriteTransaction<'a> {
                         e.attribute_value_pres("class", &PVCLASS_ACP)
                     }
                 }));
+            if commit_cand
+                .iter()
+                .any(|e| e.attribute_value_pres("class", &PVCLASS_DOMAIN_TRUST))
+            {
+                self.changed_trust.set(true);
+            }
             ltrace!(
                 au,
-                "Schema reload: {:?}, ACP reload: {:?}",
+                "Schema reload: {:?}, ACP reload: {:?}, Trust reload: {:?}",
                 self.changed_schema,
-                self.changed_acp
+                self.changed_acp,
+                self.changed_trust
             );
 
             // We are complete, finalise logging and return
@@ -1193,11 +1287,18 @@ impl<'a> QueryServerWriteTransaction<'a> {
                         e.attribute_value_pres("class", &PVCLASS_ACP)
                     }
                 }));
+            if del_cand
+                .iter()
+                .any(|e| e.attribute_value_pres("class", &PVCLASS_DOMAIN_TRUST))
+            {
+                self.changed_trust.set(true);
+            }
             ltrace!(
                 au,
-                "Schema reload: {:?}, ACP reload: {:?}",
+                "Schema reload: {:?}, ACP reload: {:?}, Trust reload: {:?}",
                 self.changed_schema,
-                self.changed_acp
+                self.changed_acp,
+                self.changed_trust
             );
 
             // Send result
@@ -1541,11 +1642,19 @@ impl<'a> QueryServerWriteTransaction<'a> {
                             }
                         },
                     ));
+            if norm_cand
+                .iter()
+                .chain(pre_candidates.iter())
+                .any(|e| e.attribute_value_pres("class", &PVCLASS_DOMAIN_TRUST))
+            {
+                self.changed_trust.set(true);
+            }
             ltrace!(
                 au,
-                "Schema reload: {:?}, ACP reload: {:?}",
+                "Schema reload: {:?}, ACP reload: {:?}, Trust reload: {:?}",
                 self.changed_schema,
-                self.changed_acp
+                self.changed_acp,
+                self.changed_trust
             );
 
             // return
@@ -1668,11 +1777,19 @@ impl<'a> QueryServerWriteTransaction<'a> {
                             }
                         },
                     ));
+            if norm_cand
+                .iter()
+                .chain(pre_candidates.iter())
+                .any(|e| e.attribute_value_pres("class", &PVCLASS_DOMAIN_TRUST))
+            {
+                self.changed_trust.set(true);
+            }
             ltrace!(
                 au,
-                "Schema reload: {:?}, ACP reload: {:?}",
+                "Schema reload: {:?}, ACP reload: {:?}, Trust reload: {:?}",
                 self.changed_schema,
-                self.changed_acp
+                self.changed_acp,
+                self.changed_trust
             );
 
             ltrace!(au, "Modify operation success");
@@ -1753,6 +1870,83 @@ impl<'a> QueryServerWriteTransaction<'a> {
         })
     }
 
+    /// Migrate 3 to 4 indexes the webauthn credential ids of existing accounts, so that
+    /// their tokens can be used as discoverable credentials.
+    pub fn migrate_3_to_4(&self, au: &mut AuditScope) -> Result<(), OperationError> {
+        lperf_segment!(au, "server::migrate_3_to_4", || {
+            ladmin_warning!(au, "starting 3 to 4 migration.");
+            // The webauthn index plugin derives the ids again as each entry is modified.
+            self.internal_modify(
+                au,
+                &filter!(f_pres("primary_credential")),
+                &ModifyList::new_purge("webauthn_credential_id"),
+            )
+            .map_err(|e| {
+                ladmin_error!(au, "migrate_3_to_4 modification failure -> {:?}", e);
+                e
+            })
+        })
+    }
+
+    /// Migrate 4 to 5 records the migration as the time that existing passwords were set,
+    /// so that a maximum password age counts from the upgrade.
+    pub fn migrate_4_to_5(&self, au: &mut AuditScope) -> Result<(), OperationError> {
+        lperf_segment!(au, "server::migrate_4_to_5", || {
+            ladmin_warning!(au, "starting 4 to 5 migration.");
+            let filt = filter_all!(f_or!([
+                f_pres("primary_credential"),
+                f_pres("unix_password")
+            ]));
+
+            let pre_candidates = self.internal_search(au, filt).map_err(|e| {
+                ladmin_error!(au, "migrate_4_to_5 internal search failure -> {:?}", e);
+                e
+            })?;
+
+            if pre_candidates.is_empty() {
+                ladmin_info!(au, "migrate_4_to_5 no entries to migrate, complete");
+                return Ok(());
+            }
+
+            let ct = self.cid.ts;
+            let mut candidates: Vec<Entry<EntryInvalid, EntryCommitted>> = pre_candidates
+                .iter()
+                .map(|er| er.clone().invalidate(self.cid.clone()))
+                .collect();
+
+            candidates.iter_mut().for_each(|er| {
+                for attr in &["primary_credential", "unix_password"] {
+                    let opt_creds: Option<BTreeSet<_>> = er
+                        .pop_ava(attr)
+                        .map(|vs| vs.into_iter().map(|v| v.migrate_password_set(ct)).collect());
+                    if let Some(v) = opt_creds {
+                        er.set_ava(attr, v)
+                    };
+                }
+            });
+
+            let res: Result<Vec<Entry<EntrySealed, EntryCommitted>>, SchemaError> = candidates
+                .into_iter()
+                .map(|e| e.validate(&self.schema).map(|e| e.seal()))
+                .collect();
+
+            let norm_cand: Vec<Entry<_, _>> = match res {
+                Ok(v) => v,
+                Err(e) => {
+                    ladmin_error!(au, "migrate_4_to_5 schema error -> {:?}", e);
+                    return Err(OperationError::SchemaViolation(e));
+                }
+            };
+
+            self.be_txn
+                .modify(au, &pre_candidates, &norm_cand)
+                .map_err(|e| {
+                    ladmin_error!(au, "migrate_4_to_5 modification failure -> {:?}", e);
+                    e
+                })
+        })
+    }
+
     // These are where searches and other actions are actually implemented. This
     // is the "internal" version, where we define the event as being internal
     // only, allowing certain plugin by passes etc.
@@ -1833,6 +2027,56 @@ impl<'a> QueryServerWriteTransaction<'a> {
         self.impersonate_modify_valid(audit, f_valid, f_intent_valid, m_valid, event)
     }
 
+    /// Check that the event is allowed to apply this modification to the entries the
+    /// filter selects, without applying it. This authorises operations that change
+    /// state held outside of the entry, such as clearing a credential softlock.
+    pub fn impersonate_modify_allowed(
+        &self,
+        audit: &mut AuditScope,
+        filter: &Filter<FilterInvalid>,
+        filter_intent: &Filter<FilterInvalid>,
+        modlist: &ModifyList<ModifyInvalid>,
+        event: &Event,
+    ) -> Result<(), OperationError> {
+        let f_valid = filter.validate(self.get_schema()).map_err(|e| {
+            ladmin_error!(audit, "filter Schema Invalid {:?}", e);
+            OperationError::SchemaViolation(e)
+        })?;
+        let f_intent_valid = filter_intent.validate(self.get_schema()).map_err(|e| {
+            ladmin_error!(audit, "f_intent Schema Invalid {:?}", e);
+            OperationError::SchemaViolation(e)
+        })?;
+        let m_valid = modlist.validate(self.get_schema()).map_err(|e| {
+            ladmin_error!(audit, "modlist Schema Invalid {:?}", e);
+            OperationError::SchemaViolation(e)
+        })?;
+        let me = ModifyEvent::new_impersonate(event, f_valid, f_intent_valid, m_valid);
+
+        let pre_candidates = self.impersonate_search_valid(
+            audit,
+            me.filter.clone(),
+            me.filter_orig.clone(),
+            &me.event,
+        )?;
+        if pre_candidates.is_empty() {
+            lrequest_error!(audit, "modify check: no candidates match filter");
+            return Err(OperationError::NoMatchingEntries);
+        }
+
+        let access = self.get_accesscontrols();
+        let op_allow = access
+            .modify_allow_operation(audit, &me, &pre_candidates)
+            .map_err(|e| {
+                ladmin_error!(audit, "Unable to check modify access {:?}", e);
+                e
+            })?;
+        if op_allow {
+            Ok(())
+        } else {
+            Err(OperationError::AccessDenied)
+        }
+    }
+
     // internal server operation types.
     // These just wrap the fn create/search etc, but they allow
     // creating the needed create event with the correct internal flags
@@ -2030,6 +2274,42 @@ impl<'a> QueryServerWriteTransaction<'a> {
             JSON_SCHEMA_ATTR_UNIX_PASSWORD,
             JSON_SCHEMA_ATTR_ACCOUNT_EXPIRE,
             JSON_SCHEMA_ATTR_ACCOUNT_VALID_FROM,
+            JSON_SCHEMA_ATTR_ES256_PRIVATE_KEY_DER,
+            JSON_SCHEMA_ATTR_OAUTH2_RS_NAME,
+            JSON_SCHEMA_ATTR_OAUTH2_RS_ORIGIN,
+            JSON_SCHEMA_ATTR_OAUTH2_RS_REDIRECT_URI,
+            JSON_SCHEMA_ATTR_OAUTH2_RS_IMPLICIT_SCOPES,
+            JSON_SCHEMA_ATTR_OAUTH2_RS_BASIC_SECRET,
+            JSON_SCHEMA_ATTR_OAUTH2_CONSENT_SCOPE_MAP,
+            JSON_SCHEMA_ATTR_API_TOKEN_SESSION,
+            JSON_SCHEMA_ATTR_APP_PASSWORD,
+            JSON_SCHEMA_ATTR_CREDENTIAL_RESET_INTENT,
+            JSON_SCHEMA_ATTR_PASSWORD_HISTORY_LENGTH,
+            JSON_SCHEMA_ATTR_PASSWORD_MAX_AGE,
+            JSON_SCHEMA_ATTR_CREDENTIAL_TYPE_MINIMUM,
+            JSON_SCHEMA_ATTR_SOFTLOCK_MAX_FAILURES,
+            JSON_SCHEMA_ATTR_SOFTLOCK_MAX_DELAY,
+            JSON_SCHEMA_ATTR_SOFTLOCK_RESET_WINDOW,
+            JSON_SCHEMA_ATTR_WEBAUTHN_USER_VERIFICATION,
+            JSON_SCHEMA_ATTR_WEBAUTHN_ATTESTATION_CA,
+            JSON_SCHEMA_ATTR_WEBAUTHN_ATTESTATION_AAGUID,
+            JSON_SCHEMA_ATTR_WEBAUTHN_CREDENTIAL_ID,
+            JSON_SCHEMA_ATTR_PASSWORD_MIN_LENGTH,
+            JSON_SCHEMA_ATTR_PASSWORD_MIN_SCORE,
+            JSON_SCHEMA_ATTR_PASSWORD_MIN_CHAR_CLASSES,
+            JSON_SCHEMA_ATTR_PASSWORD_BADLIST_WORDS,
+            JSON_SCHEMA_ATTR_AUTH_RATELIMIT_ADDRESS_FAILURES,
+            JSON_SCHEMA_ATTR_AUTH_RATELIMIT_SUBNET_FAILURES,
+            JSON_SCHEMA_ATTR_AUTH_RATELIMIT_WINDOW,
+            JSON_SCHEMA_ATTR_LAST_LOGIN,
+            JSON_SCHEMA_ATTR_LAST_LOGIN_MECHANISM,
+            JSON_SCHEMA_ATTR_X509_FINGERPRINT,
+            JSON_SCHEMA_ATTR_ACCOUNT_INACTIVITY_DAYS,
+            JSON_SCHEMA_ATTR_ACCOUNT_INACTIVITY_EXCLUDE_GROUP,
+            JSON_SCHEMA_ATTR_AUTH_AUDIT_RETENTION_DAYS,
+            JSON_SCHEMA_ATTR_AUTH_AUDIT_MAX_RECORDS,
+            JSON_SCHEMA_ATTR_SSH_CA_PRIVATE_KEY_DER,
+            JSON_SCHEMA_ATTR_TRUST_KEY,
             JSON_SCHEMA_CLASS_PERSON,
             JSON_SCHEMA_CLASS_GROUP,
             JSON_SCHEMA_CLASS_ACCOUNT,
@@ -2037,6 +2317,11 @@ impl<'a> QueryServerWriteTransaction<'a> {
             JSON_SCHEMA_CLASS_POSIXACCOUNT,
             JSON_SCHEMA_CLASS_POSIXGROUP,
             JSON_SCHEMA_CLASS_SYSTEM_CONFIG,
+            JSON_SCHEMA_CLASS_OAUTH2_RS,
+            JSON_SCHEMA_CLASS_OAUTH2_RS_BASIC,
+            JSON_SCHEMA_CLASS_SERVICE_ACCOUNT,
+            JSON_SCHEMA_CLASS_DOMAIN_TRUST,
+            JSON_SCHEMA_CLASS_FOREIGN_PRINCIPAL,
             JSON_SCHEMA_ATTR_NSUNIQUEID,
         ];
 
@@ -2072,6 +2357,9 @@ impl<'a> QueryServerWriteTransaction<'a> {
             return res;
         }
 
+        // Load the existing trusts when this transaction commits.
+        self.changed_trust.set(true);
+
         // The domain info now exists, we should be able to do these migrations as they will
         // cause SPN regenerations to occur
 
@@ -2123,6 +2411,8 @@ impl<'a> QueryServerWriteTransaction<'a> {
             JSON_IDM_HP_GROUP_MANAGE_PRIV_V1,
             JSON_IDM_HP_GROUP_WRITE_PRIV_V1,
             JSON_IDM_ACP_MANAGE_PRIV_V1,
+            JSON_IDM_HP_OAUTH2_MANAGE_PRIV_V1,
+            JSON_IDM_AUTH_AUDIT_READ_PRIV_V1,
             JSON_DOMAIN_ADMINS,
             JSON_IDM_HIGH_PRIVILEGE_V1,
             // Built in access controls.
@@ -2155,6 +2445,8 @@ impl<'a> QueryServerWriteTransaction<'a> {
             JSON_IDM_ACP_GROUP_UNIX_EXTEND_PRIV_V1,
             JSON_IDM_ACP_PEOPLE_ACCOUNT_PASSWORD_IMPORT_PRIV_V1,
             JSON_IDM_ACP_PEOPLE_EXTEND_PRIV_V1,
+            JSON_IDM_ACP_HP_OAUTH2_MANAGE_PRIV_V1,
+            JSON_IDM_ACP_DOMAIN_TRUST_MANAGE_PRIV_V1,
         ];
 
         let res: Result<(), _> = idm_entries
@@ -2239,6 +2531,21 @@ impl<'a> QueryServerWriteTransaction<'a> {
         })
     }
 
+    fn reload_trusts(&mut self, audit: &mut AuditScope) -> Result<(), OperationError> {
+        ltrace!(audit, "Trust reload started ...");
+        let filt = filter!(f_eq("class", PVCLASS_DOMAIN_TRUST.clone()));
+        let res = self.internal_search(audit, filt).map_err(|e| {
+            ladmin_error!(audit, "reload trusts internal search failed {:?}", e);
+            e
+        })?;
+        let trusts: Result<Vec<_>, _> = res
+            .iter()
+            .map(|e| TrustedDomain::try_from_entry(audit, e))
+            .collect();
+        self.trusts.update(trusts?);
+        Ok(())
+    }
+
     fn reload_accesscontrols(&mut self, audit: &mut AuditScope) -> Result<(), OperationError> {
         // supply entries to the writable access controls to reload from.
         // This has to be done in FOUR passes - one for each type!
@@ -2377,6 +2684,11 @@ impl<'a> QueryServerWriteTransaction<'a> {
         self.d_uuid
     }
 
+    /// The time this transaction was started at.
+    pub(crate) fn get_curtime(&self) -> Duration {
+        self.cid.ts
+    }
+
     /// Initiate a domain rename process. This is generally an internal function but it's
     /// exposed to the cli for admins to be able to initiate the process.
     pub fn domain_rename(
@@ -2405,6 +2717,47 @@ impl<'a> QueryServerWriteTransaction<'a> {
         self.be_txn.upgrade_reindex(audit, v)
     }
 
+    pub(crate) fn write_softlock(
+        &self,
+        audit: &mut AuditScope,
+        cred_uuid: &Uuid,
+        k: Option<&DbSoftLockV1>,
+    ) -> Result<(), OperationError> {
+        self.be_txn.write_softlock(audit, cred_uuid, k)
+    }
+
+    pub(crate) fn purge_softlocks(
+        &self,
+        audit: &mut AuditScope,
+        ct: &Duration,
+    ) -> Result<(), OperationError> {
+        self.be_txn.purge_softlocks(audit, ct)
+    }
+
+    pub(crate) fn get_softlocks(
+        &self,
+        audit: &mut AuditScope,
+    ) -> Result<Vec<(Uuid, DbSoftLockV1)>, OperationError> {
+        self.be_txn.get_softlocks(audit)
+    }
+
+    pub(crate) fn write_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        records: &[DbAuthRecordV1],
+    ) -> Result<(), OperationError> {
+        self.be_txn.write_auth_records(audit, records)
+    }
+
+    pub(crate) fn purge_auth_records(
+        &self,
+        audit: &mut AuditScope,
+        before: &Duration,
+        max_records: u32,
+    ) -> Result<(), OperationError> {
+        self.be_txn.purge_auth_records(audit, before, max_records)
+    }
+
     pub fn commit(mut self, audit: &mut AuditScope) -> Result<(), OperationError> {
         // This could be faster if we cache the set of classes changed
         // in an operation so we can check if we need to do the reload or not
@@ -2420,6 +2773,9 @@ impl<'a> QueryServerWriteTransaction<'a> {
         if self.changed_schema.get() || self.changed_acp.get() {
             self.reload_accesscontrols(audit)?;
         }
+        if self.changed_trust.get() {
+            self.reload_trusts(audit)?;
+        }
 
         // Now destructure the transaction ready to reset it.
         let QueryServerWriteTransaction {
@@ -2427,6 +2783,7 @@ impl<'a> QueryServerWriteTransaction<'a> {
             be_txn,
             schema,
             accesscontrols,
+            trusts,
             cid,
             ..
         } = self;
@@ -2443,7 +2800,9 @@ impl<'a> QueryServerWriteTransaction<'a> {
             // because both are consistent.
             schema
                 .commit()
-                .and_then(|_| accesscontrols.commit().and_then(|_| be_txn.commit(audit)))
+                .and_then(|_| accesscontrols.commit())
+                .and_then(|_| trusts.commit())
+                .and_then(|_| be_txn.commit(audit))
         } else {
             Err(OperationError::ConsistencyError(r))
         }
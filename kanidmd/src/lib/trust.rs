@@ -0,0 +1,197 @@
+//! Trusts of other kanidm domains. A trusted domain signs the user auth tokens of its
+//! accounts with its own keys, and we accept those tokens for the foreign principals
+//! that represent its accounts in this domain. The keys of each trust are held in memory
+//! so that tokens can be checked as they are received, without a database transaction.
+
+use concread::cowcell::*;
+use kanidm_proto::jws::JwsValidator;
+use kanidm_proto::oauth2::Jwk;
+use kanidm_proto::v1::{OperationError, UserAuthToken};
+use std::time::Duration;
+
+use crate::audit::AuditScope;
+use crate::entry::{Entry, EntryCommitted, EntrySealed};
+
+/// The realm of an spn, IE `example.com` for `name@example.com`.
+pub(crate) fn spn_realm(spn: &str) -> Option<&str> {
+    spn.rfind('@').map(|i| &spn[i + 1..])
+}
+
+/// Parse a `trust_key` value, which is an ES256 public key in JWK format.
+pub(crate) fn trust_key_validator(key: &str) -> Option<JwsValidator> {
+    serde_json::from_str::<Jwk>(key)
+        .ok()
+        .and_then(|jwk| JwsValidator::from_jwk(&jwk).ok())
+}
+
+#[derive(Clone)]
+pub(crate) struct TrustedDomain {
+    domain_name: String,
+    validators: Vec<JwsValidator>,
+}
+
+impl TrustedDomain {
+    pub(crate) fn try_from_entry(
+        au: &mut AuditScope,
+        value: &Entry<EntrySealed, EntryCommitted>,
+    ) -> Result<Self, OperationError> {
+        let domain_name = value
+            .get_ava_single_str("domain_name")
+            .map(str::to_string)
+            .ok_or_else(|| {
+                ladmin_error!(au, "Domain trust missing domain_name");
+                OperationError::InvalidEntryState
+            })?;
+        // Keys are checked as they are written, so any that don't parse here are skipped.
+        let validators = value
+            .get_ava_as_str("trust_key")
+            .map(|keys| keys.filter_map(trust_key_validator).collect())
+            .unwrap_or_else(Vec::new);
+
+        Ok(TrustedDomain {
+            domain_name,
+            validators,
+        })
+    }
+
+    fn validate_uat(&self, jws: &str, ct: Duration) -> Option<UserAuthToken> {
+        self.validators
+            .iter()
+            .find_map(|v| v.validate_uat(jws, ct).ok())
+            // A trusted domain may only vouch for its own accounts.
+            .filter(|uat| spn_realm(uat.spn.as_str()) == Some(self.domain_name.as_str()))
+    }
+}
+
+#[derive(Clone)]
+struct TrustedDomainsInner {
+    domains: Vec<TrustedDomain>,
+}
+
+pub struct TrustedDomains {
+    inner: CowCell<TrustedDomainsInner>,
+}
+
+pub struct TrustedDomainsWriteTransaction<'a> {
+    inner: CowCellWriteTxn<'a, TrustedDomainsInner>,
+}
+
+impl TrustedDomains {
+    pub fn new() -> Self {
+        TrustedDomains {
+            inner: CowCell::new(TrustedDomainsInner {
+                domains: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn write(&self) -> TrustedDomainsWriteTransaction {
+        TrustedDomainsWriteTransaction {
+            inner: self.inner.write(),
+        }
+    }
+
+    /// Validate a user auth token that was issued by one of the trusted domains. The
+    /// token still has to be mapped to a foreign principal before it grants any access.
+    pub fn validate_uat(&self, jws: &str, ct: Duration) -> Option<UserAuthToken> {
+        self.inner
+            .read()
+            .domains
+            .iter()
+            .find_map(|td| td.validate_uat(jws, ct))
+    }
+}
+
+impl<'a> TrustedDomainsWriteTransaction<'a> {
+    pub(crate) fn update(&mut self, domains: Vec<TrustedDomain>) {
+        self.inner.domains = domains;
+    }
+
+    pub fn commit(self) -> Result<(), OperationError> {
+        self.inner.commit();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::JwsSigner;
+    use crate::trust::{spn_realm, TrustedDomain, TrustedDomains};
+    use kanidm_proto::jws::{JwsValidator, TokenPurpose};
+    use kanidm_proto::v1::UserAuthToken;
+    use std::time::Duration;
+
+    fn test_uat(spn: &str) -> UserAuthToken {
+        UserAuthToken {
+            exp: 1000,
+            purpose: TokenPurpose::UserAuth,
+            name: "claire".to_string(),
+            spn: spn.to_string(),
+            displayname: "Claire".to_string(),
+            uuid: "4d21d04a-dc0e-42eb-b850-34dd180b107f".to_string(),
+            groups: Vec::new(),
+            claims: Vec::new(),
+            lim_uidx: false,
+            lim_rmax: 128,
+            lim_pmax: 128,
+            lim_fmax: 128,
+            api_token_id: None,
+            read_only: false,
+            password_expiry: None,
+        }
+    }
+
+    #[test]
+    fn test_spn_realm() {
+        assert!(spn_realm("claire@example.com") == Some("example.com"));
+        assert!(spn_realm("claire").is_none());
+    }
+
+    #[test]
+    fn test_trusted_domain_validate_uat() {
+        let remote = JwsSigner::generate_es256_der()
+            .and_then(|der| JwsSigner::from_es256_der(&der))
+            .expect("Failed to create signer");
+        let other = JwsSigner::generate_es256_der()
+            .and_then(|der| JwsSigner::from_es256_der(&der))
+            .expect("Failed to create signer");
+        let jwk = remote.public_key_as_jwk().expect("Failed to get jwk");
+
+        let trusts = TrustedDomains::new();
+        let mut trusts_write = trusts.write();
+        trusts_write.update(vec![TrustedDomain {
+            domain_name: "remote.example.com".to_string(),
+            validators: vec![JwsValidator::from_jwk(&jwk).expect("Failed to build validator")],
+        }]);
+        assert!(trusts_write.commit().is_ok());
+
+        let ct = Duration::from_secs(10);
+        let token = remote
+            .sign(&test_uat("claire@remote.example.com"))
+            .expect("Failed to sign");
+        let uat = trusts
+            .validate_uat(&token, ct)
+            .expect("Token was not trusted");
+        assert!(uat.spn == "claire@remote.example.com");
+
+        // Expired tokens are not accepted.
+        assert!(trusts
+            .validate_uat(&token, Duration::from_secs(1000))
+            .is_none());
+        // The trusted domain can't vouch for accounts of other domains.
+        let token = remote
+            .sign(&test_uat("admin@example.com"))
+            .expect("Failed to sign");
+        assert!(trusts.validate_uat(&token, ct).is_none());
+        // Nor are other tokens signed by the trusted key.
+        let mut uat = test_uat("claire@remote.example.com");
+        uat.purpose = TokenPurpose::Oauth2Access;
+        let token = remote.sign(&uat).expect("Failed to sign");
+        assert!(trusts.validate_uat(&token, ct).is_none());
+        // Nor are tokens signed by keys we don't trust.
+        let token = other
+            .sign(&test_uat("claire@remote.example.com"))
+            .expect("Failed to sign");
+        assert!(trusts.validate_uat(&token, ct).is_none());
+    }
+}
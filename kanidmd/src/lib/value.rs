@@ -1,9 +1,13 @@
-use crate::be::dbvalue::{DbCidV1, DbValueCredV1, DbValueTaggedStringV1, DbValueV1};
+use crate::be::dbvalue::{
+    DbCidV1, DbValueApiTokenV1, DbValueCredV1, DbValueOauthScopeMapV1, DbValueTaggedStringV1,
+    DbValueV1,
+};
 use crate::credential::Credential;
 use crate::repl::cid::Cid;
 use kanidm_proto::v1::Filter as ProtoFilter;
 
 use std::borrow::Borrow;
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
@@ -127,6 +131,9 @@ pub enum SyntaxType {
     CID,
     NSUNIQUEID,
     DATETIME,
+    PRIVATE_BINARY,
+    OAUTH_SCOPE_MAP,
+    API_TOKEN,
 }
 
 impl TryFrom<&str> for SyntaxType {
@@ -152,6 +159,9 @@ impl TryFrom<&str> for SyntaxType {
             "CID" => Ok(SyntaxType::CID),
             "NSUNIQUEID" => Ok(SyntaxType::NSUNIQUEID),
             "DATETIME" => Ok(SyntaxType::DATETIME),
+            "PRIVATE_BINARY" => Ok(SyntaxType::PRIVATE_BINARY),
+            "OAUTH_SCOPE_MAP" => Ok(SyntaxType::OAUTH_SCOPE_MAP),
+            "API_TOKEN" => Ok(SyntaxType::API_TOKEN),
             _ => Err(()),
         }
     }
@@ -179,6 +189,9 @@ impl TryFrom<usize> for SyntaxType {
             14 => Ok(SyntaxType::UTF8STRING_INAME),
             15 => Ok(SyntaxType::NSUNIQUEID),
             16 => Ok(SyntaxType::DATETIME),
+            17 => Ok(SyntaxType::PRIVATE_BINARY),
+            18 => Ok(SyntaxType::OAUTH_SCOPE_MAP),
+            19 => Ok(SyntaxType::API_TOKEN),
             _ => Err(()),
         }
     }
@@ -204,6 +217,9 @@ impl SyntaxType {
             SyntaxType::UTF8STRING_INAME => 14,
             SyntaxType::NSUNIQUEID => 15,
             SyntaxType::DATETIME => 16,
+            SyntaxType::PRIVATE_BINARY => 17,
+            SyntaxType::OAUTH_SCOPE_MAP => 18,
+            SyntaxType::API_TOKEN => 19,
         }
     }
 }
@@ -231,16 +247,32 @@ impl fmt::Display for SyntaxType {
                 SyntaxType::CID => "CID",
                 SyntaxType::NSUNIQUEID => "NSUNIQUEID",
                 SyntaxType::DATETIME => "DATETIME",
+                SyntaxType::PRIVATE_BINARY => "PRIVATE_BINARY",
+                SyntaxType::OAUTH_SCOPE_MAP => "OAUTH_SCOPE_MAP",
+                SyntaxType::API_TOKEN => "API_TOKEN",
             }
         )
     }
 }
 
+/// The metadata of a service account api token. The token itself is a signed jws
+/// that is never stored, this is what allows it to be listed, checked and revoked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiTokenMeta {
+    pub label: String,
+    pub expiry: Option<OffsetDateTime>,
+    pub read_only: bool,
+    pub last_used: Option<OffsetDateTime>,
+}
+
 #[derive(Clone)]
 pub enum DataValue {
     Cred(Credential),
     SshKey(String),
     RadiusCred(String),
+    PrivateBinary(Vec<u8>),
+    OauthScopeMap(BTreeSet<String>),
+    ApiToken(ApiTokenMeta),
 }
 
 impl std::fmt::Debug for DataValue {
@@ -249,6 +281,9 @@ impl std::fmt::Debug for DataValue {
             DataValue::Cred(_) => write!(f, "DataValue::Cred(_)"),
             DataValue::SshKey(_) => write!(f, "DataValue::SshKey(_)"),
             DataValue::RadiusCred(_) => write!(f, "DataValue::RadiusCred(_)"),
+            DataValue::PrivateBinary(_) => write!(f, "DataValue::PrivateBinary(_)"),
+            DataValue::OauthScopeMap(m) => write!(f, "DataValue::OauthScopeMap({:?})", m),
+            DataValue::ApiToken(m) => write!(f, "DataValue::ApiToken({:?})", m),
         }
     }
 }
@@ -275,6 +310,12 @@ pub enum PartialValue {
     Cid(Cid),
     Nsuniqueid(String),
     DateTime(OffsetDateTime),
+    // Private binary is never displayed or indexed, so there is no tag.
+    PrivateBinary,
+    // The uuid of the resource server this scope map applies to.
+    OauthScopeMap(Uuid),
+    // The id of the api token.
+    ApiToken(Uuid),
 }
 
 impl PartialValue {
@@ -555,6 +596,47 @@ impl PartialValue {
         }
     }
 
+    pub fn new_private_binary() -> Self {
+        PartialValue::PrivateBinary
+    }
+
+    pub fn is_private_binary(&self) -> bool {
+        match self {
+            PartialValue::PrivateBinary => true,
+            _ => false,
+        }
+    }
+
+    pub fn new_oauthscopemap(u: Uuid) -> Self {
+        PartialValue::OauthScopeMap(u)
+    }
+
+    pub fn new_oauthscopemap_s(us: &str) -> Option<Self> {
+        Uuid::parse_str(us).ok().map(PartialValue::OauthScopeMap)
+    }
+
+    pub fn is_oauthscopemap(&self) -> bool {
+        match self {
+            PartialValue::OauthScopeMap(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn new_apitoken(u: Uuid) -> Self {
+        PartialValue::ApiToken(u)
+    }
+
+    pub fn new_apitoken_s(us: &str) -> Option<Self> {
+        Uuid::parse_str(us).ok().map(PartialValue::ApiToken)
+    }
+
+    pub fn is_apitoken(&self) -> bool {
+        match self {
+            PartialValue::ApiToken(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn to_str(&self) -> Option<&str> {
         match self {
             PartialValue::Utf8(s) => Some(s.as_str()),
@@ -608,6 +690,10 @@ impl PartialValue {
                 debug_assert!(odt.offset() == time::UtcOffset::UTC);
                 odt.format(time::Format::Rfc3339)
             }
+            // This will never match as we never index private binary! See generate_idx_eq_keys
+            PartialValue::PrivateBinary => "_".to_string(),
+            PartialValue::OauthScopeMap(u) => u.to_hyphenated_ref().to_string(),
+            PartialValue::ApiToken(u) => u.to_hyphenated_ref().to_string(),
         }
     }
 
@@ -946,6 +1032,19 @@ impl Value {
         }
     }
 
+    pub fn to_tagged_credential(&self) -> Option<(&str, &Credential)> {
+        match &self.pv {
+            PartialValue::Cred(tag) => match &self.data {
+                Some(dv) => match dv.as_ref() {
+                    DataValue::Cred(c) => Some((tag.as_str(), &c)),
+                    _ => None,
+                },
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
     pub fn new_radius_str(cleartext: &str) -> Self {
         Value {
             pv: PartialValue::new_radius_string(),
@@ -1096,6 +1195,78 @@ impl Value {
         self.pv.is_datetime()
     }
 
+    pub fn new_private_binary(der: &[u8]) -> Self {
+        Value {
+            pv: PartialValue::new_private_binary(),
+            data: Some(Box::new(DataValue::PrivateBinary(der.to_vec()))),
+        }
+    }
+
+    pub fn is_private_binary(&self) -> bool {
+        self.pv.is_private_binary()
+    }
+
+    pub fn to_private_binary(&self) -> Option<&Vec<u8>> {
+        match &self.pv {
+            PartialValue::PrivateBinary => match &self.data {
+                Some(dv) => match dv.as_ref() {
+                    DataValue::PrivateBinary(c) => Some(&c),
+                    _ => None,
+                },
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn new_oauthscopemap(u: Uuid, m: BTreeSet<String>) -> Self {
+        Value {
+            pv: PartialValue::new_oauthscopemap(u),
+            data: Some(Box::new(DataValue::OauthScopeMap(m))),
+        }
+    }
+
+    pub fn is_oauthscopemap(&self) -> bool {
+        self.pv.is_oauthscopemap()
+    }
+
+    pub fn to_oauthscopemap(&self) -> Option<(&Uuid, &BTreeSet<String>)> {
+        match &self.pv {
+            PartialValue::OauthScopeMap(u) => match &self.data {
+                Some(dv) => match dv.as_ref() {
+                    DataValue::OauthScopeMap(m) => Some((u, m)),
+                    _ => None,
+                },
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn new_apitoken(u: Uuid, m: ApiTokenMeta) -> Self {
+        Value {
+            pv: PartialValue::new_apitoken(u),
+            data: Some(Box::new(DataValue::ApiToken(m))),
+        }
+    }
+
+    pub fn is_apitoken(&self) -> bool {
+        self.pv.is_apitoken()
+    }
+
+    pub fn to_apitoken(&self) -> Option<(&Uuid, &ApiTokenMeta)> {
+        match &self.pv {
+            PartialValue::ApiToken(u) => match &self.data {
+                Some(dv) => match dv.as_ref() {
+                    DataValue::ApiToken(m) => Some((u, m)),
+                    _ => None,
+                },
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
     pub fn contains(&self, s: &PartialValue) -> bool {
         self.pv.contains(s)
     }
@@ -1191,6 +1362,34 @@ impl Value {
             DbValueV1::DT(s) => PartialValue::new_datetime_s(&s)
                 .ok_or(())
                 .map(|pv| Value { pv, data: None }),
+            DbValueV1::PB(b) => Ok(Value {
+                pv: PartialValue::PrivateBinary,
+                data: Some(Box::new(DataValue::PrivateBinary(b))),
+            }),
+            DbValueV1::OS(osm) => Ok(Value {
+                pv: PartialValue::OauthScopeMap(osm.u),
+                data: Some(Box::new(DataValue::OauthScopeMap(
+                    osm.m.into_iter().collect(),
+                ))),
+            }),
+            DbValueV1::AT(at) => {
+                let parse = |s: &str| {
+                    OffsetDateTime::parse(s, time::Format::Rfc3339)
+                        .map(|odt| odt.to_offset(time::UtcOffset::UTC))
+                        .map_err(|_| ())
+                };
+                let expiry = at.e.as_deref().map(parse).transpose()?;
+                let last_used = at.lu.as_deref().map(parse).transpose()?;
+                Ok(Value {
+                    pv: PartialValue::ApiToken(at.u),
+                    data: Some(Box::new(DataValue::ApiToken(ApiTokenMeta {
+                        label: at.l,
+                        expiry,
+                        read_only: at.r,
+                        last_used,
+                    }))),
+                })
+            }
         }
     }
 
@@ -1262,6 +1461,42 @@ impl Value {
                 debug_assert!(odt.offset() == time::UtcOffset::UTC);
                 DbValueV1::DT(odt.format(time::Format::Rfc3339))
             }
+            PartialValue::PrivateBinary => {
+                let pb = match &self.data {
+                    Some(v) => match v.as_ref() {
+                        DataValue::PrivateBinary(b) => b.clone(),
+                        _ => unreachable!(),
+                    },
+                    None => unreachable!(),
+                };
+                DbValueV1::PB(pb)
+            }
+            PartialValue::OauthScopeMap(u) => {
+                let m = match &self.data {
+                    Some(v) => match v.as_ref() {
+                        DataValue::OauthScopeMap(m) => m.iter().cloned().collect(),
+                        _ => unreachable!(),
+                    },
+                    None => unreachable!(),
+                };
+                DbValueV1::OS(DbValueOauthScopeMapV1 { u: *u, m })
+            }
+            PartialValue::ApiToken(u) => {
+                let m = match &self.data {
+                    Some(v) => match v.as_ref() {
+                        DataValue::ApiToken(m) => m,
+                        _ => unreachable!(),
+                    },
+                    None => unreachable!(),
+                };
+                DbValueV1::AT(DbValueApiTokenV1 {
+                    u: *u,
+                    l: m.label.clone(),
+                    e: m.expiry.map(|odt| odt.format(time::Format::Rfc3339)),
+                    r: m.read_only,
+                    lu: m.last_used.map(|odt| odt.format(time::Format::Rfc3339)),
+                })
+            }
         }
     }
 
@@ -1343,6 +1578,20 @@ impl Value {
         }
     }
 
+    /// Record `ct` as the time the password of a credential was set, if it isn't known.
+    pub(crate) fn migrate_password_set(self, ct: Duration) -> Self {
+        let migrated = match self.to_tagged_credential() {
+            Some((tag, cred)) if cred.password_set.is_none() && cred.password_ref().is_ok() => {
+                Some(Value::new_credential(
+                    tag,
+                    cred.clone().with_password_set(ct),
+                ))
+            }
+            _ => None,
+        };
+        migrated.unwrap_or(self)
+    }
+
     pub(crate) fn to_proto_string_clone(&self) -> String {
         match &self.pv {
             PartialValue::Utf8(s)
@@ -1394,6 +1643,29 @@ impl Value {
                 debug_assert!(odt.offset() == time::UtcOffset::UTC);
                 odt.format(time::Format::Rfc3339)
             }
+            // Private binary is never disclosed.
+            PartialValue::PrivateBinary => "private_binary".to_string(),
+            PartialValue::OauthScopeMap(u) => match &self.data {
+                Some(v) => match v.as_ref() {
+                    DataValue::OauthScopeMap(m) => {
+                        format!("{}: {}", u, m.iter().cloned().collect::<Vec<_>>().join(" "))
+                    }
+                    _ => format!("{}: corrupted value tag", u),
+                },
+                None => format!("{}: corrupted value", u),
+            },
+            PartialValue::ApiToken(u) => match &self.data {
+                Some(v) => match v.as_ref() {
+                    DataValue::ApiToken(m) => format!(
+                        "{}: {} {}",
+                        u,
+                        m.label,
+                        if m.read_only { "ro" } else { "rw" }
+                    ),
+                    _ => format!("{}: corrupted value tag", u),
+                },
+                None => format!("{}: corrupted value", u),
+            },
         }
     }
 
@@ -1435,6 +1707,27 @@ impl Value {
             },
             PartialValue::Nsuniqueid(s) => NSUNIQUEID_RE.is_match(s),
             PartialValue::DateTime(odt) => odt.offset() == time::UtcOffset::UTC,
+            PartialValue::PrivateBinary => match &self.data {
+                Some(v) => match v.as_ref() {
+                    DataValue::PrivateBinary(_) => true,
+                    _ => false,
+                },
+                None => false,
+            },
+            PartialValue::OauthScopeMap(_) => match &self.data {
+                Some(v) => match v.as_ref() {
+                    DataValue::OauthScopeMap(_) => true,
+                    _ => false,
+                },
+                None => false,
+            },
+            PartialValue::ApiToken(_) => match &self.data {
+                Some(v) => match v.as_ref() {
+                    DataValue::ApiToken(_) => true,
+                    _ => false,
+                },
+                None => false,
+            },
             _ => true,
         }
     }
@@ -1467,6 +1760,9 @@ impl Value {
                 debug_assert!(odt.offset() == time::UtcOffset::UTC);
                 vec![odt.format(time::Format::Rfc3339)]
             }
+            PartialValue::PrivateBinary => vec![],
+            PartialValue::OauthScopeMap(u) => vec![u.to_hyphenated_ref().to_string()],
+            PartialValue::ApiToken(u) => vec![u.to_hyphenated_ref().to_string()],
         }
     }
 }
@@ -37,13 +37,17 @@ include!("./opt.rs");
 struct ServerConfig {
     pub bindaddress: Option<String>,
     pub ldapbindaddress: Option<String>,
+    pub ldapstarttlsbindaddress: Option<String>,
+    pub ldap_bind_requires_tls: Option<bool>,
     // pub threads: Option<usize>,
     pub db_path: String,
     pub db_fs_type: Option<String>,
     pub tls_chain: Option<String>,
     pub tls_key: Option<String>,
+    pub tls_client_ca: Option<String>,
     pub log_level: Option<String>,
     pub origin: String,
+    pub breach_corpus_path: Option<String>,
 }
 
 impl ServerConfig {
@@ -204,10 +208,15 @@ async fn main() {
     config.update_log_level(ll);
     config.update_db_path(&sconfig.db_path.as_str());
     config.update_db_fs_type(&sconfig.db_fs_type);
-    config.update_tls(&sconfig.tls_chain, &sconfig.tls_key);
+    config.update_tls(&sconfig.tls_chain, &sconfig.tls_key, &sconfig.tls_client_ca);
     config.update_bind(&sconfig.bindaddress);
     config.update_ldapbind(&sconfig.ldapbindaddress);
+    config.update_ldapstarttlsbind(
+        &sconfig.ldapstarttlsbindaddress,
+        sconfig.ldap_bind_requires_tls,
+    );
     config.update_origin(&sconfig.origin.as_str());
+    config.update_breach_corpus_path(&sconfig.breach_corpus_path);
 
     // Apply any cli overrides, normally debug level.
     if let Some(dll) = opt.commonopt().debug.as_ref() {